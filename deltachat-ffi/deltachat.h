@@ -293,10 +293,10 @@ char*           dc_get_blobdir               (const dc_context_t* context);
  *                    changes require restarting IO by calling dc_stop_io() and then dc_start_io().
  * - `sentbox_watch`= 1=watch `Sent`-folder for changes (default),
  *                    0=do not watch the `Sent`-folder,
- *                    changes require restarting IO by calling dc_stop_io() and then dc_start_io().
+ *                    changes restart IO if it is running.
  * - `mvbox_watch`  = 1=watch `DeltaChat`-folder for changes (default),
  *                    0=do not watch the `DeltaChat`-folder,
- *                    changes require restarting IO by calling dc_stop_io() and then dc_start_io().
+ *                    changes restart IO if it is running.
  * - `mvbox_move`   = 1=heuristically detect chat-messages
  *                    and move them to the `DeltaChat`-folder,
  *                    0=do not move chat-messages
@@ -317,15 +317,53 @@ char*           dc_get_blobdir               (const dc_context_t* context);
  *                    0=do not save mime headers (default)
  * - `delete_device_after` = 0=do not delete messages from device automatically (default),
  *                    >=1=seconds, after which messages are deleted automatically from the device.
- *                    Messages in the "saved messages" chat (see dc_chat_is_self_talk()) are skipped.
- *                    Messages are deleted whether they were seen or not, the UI should clearly point that out.
+ *                    Messages in the "saved messages" chat (see dc_chat_is_self_talk()) and unseen messages are skipped.
+ *                    Old messages are deleted in the background while IO is running, see #DC_EVENT_DEVICE_CLEANUP_DONE.
+ *                    Messages deleted from the device are not downloaded again;
+ *                    they stay on the server unless `delete_server_after` is set as well.
+ *                    Without `delete_server_after`, they may be downloaded again
+ *                    if they are moved to another folder more than 90 days after the deletion.
  *                    See also dc_estimate_deletion_cnt().
  * - `delete_server_after` = 0=do not delete messages from server automatically (default),
  *                    1=delete messages directly after receiving from server, mvbox is skipped.
  *                    >1=seconds, after which messages are deleted automatically from the server, mvbox is used as defined.
  *                    "Saved messages" are deleted from the server as well as
  *                    emails matching the `show_emails` settings above, the UI should clearly point that out.
+ *                    When the value is lowered, known messages already on the server are deleted
+ *                    in the background, see #DC_EVENT_SERVER_CLEANUP_PROGRESS.
  *                    See also dc_estimate_deletion_cnt().
+ * - `delete_on_server_when_deleting_locally` = 0=deleting a chat keeps its messages on the server (default),
+ *                    1=deleting a chat or messages locally also deletes them from the server.
+ * - `trash_grace_period` = 0=the content of messages deleted by dc_delete_msgs() is removed immediately (default),
+ *                    >0=seconds the deleted messages are kept and can still be restored;
+ *                    they are deleted from the server only after this period.
+ * - `download_limit` = 0=download messages completely (default),
+ *                    >0=size in bytes, larger messages are only downloaded partially,
+ *                    the full message can be downloaded using dc_download_full_msg().
+ * - `max_message_size` = 0=split only attachments exceeding the upper limit of the core (default),
+ *                    >0=size in bytes of the largest message accepted by the provider,
+ *                    larger attachments are sent in several parts and reassembled by the receiver;
+ *                    until all parts arrived, dc_msg_get_download_state() returns DC_DOWNLOAD_IN_PROGRESS
+ *                    and dc_download_full_msg() requests the missing parts again.
+ * - `socks5_enabled` = 0=connect directly (default),
+ *                    1=connect to IMAP and SMTP servers and do autoconfig lookups through the SOCKS5 proxy
+ *                    given by the following options; host names are resolved by the proxy.
+ *                    Changes take effect on the next connection attempt, no restart is needed.
+ * - `socks5_host` = host name or IP address of the SOCKS5 proxy, required if `socks5_enabled` is set.
+ * - `socks5_port` = port of the SOCKS5 proxy, 1080 by default.
+ * - `socks5_user` = user name for the SOCKS5 proxy, leave empty if the proxy requires no authentication.
+ * - `socks5_password` = password for the SOCKS5 proxy.
+ * - `connect_timeout_secs` = seconds allowed for establishing an IMAP or SMTP connection
+ *                    up to the server greeting, 20 by default, 90 if `socks5_port` is a Tor port (9050 or 9150).
+ * - `io_timeout_secs` = seconds allowed for a single IMAP or SMTP command, 30 by default, 120 with Tor.
+ * - `max_retries` = number of quick reconnection attempts after a connection failed, 1 by default, 3 with Tor;
+ *                    further attempts are made once a minute.
+ * - `retry_base_delay` = seconds to wait before the first reconnection attempt, doubled for each further attempt,
+ *                    2 by default, 5 with Tor.
+ *                    The effective values of these four options are shown by dc_get_info();
+ *                    out-of-range values are clamped, changes take effect on the next connection attempt.
+ * - `cache_size` = maximum number of chats and of contacts each kept in memory,
+ *                    0=disable the caches, default is 2000.
  * - `media_quality` = DC_MEDIA_QUALITY_BALANCED (0) =
  *                    good outgoing images/videos/voice quality at reasonable sizes (default)
  *                    DC_MEDIA_QUALITY_WORSE (1)
@@ -334,6 +372,15 @@ char*           dc_get_blobdir               (const dc_context_t* context);
  *                    The library uses the `media_quality` setting to use different defaults
  *                    for recoding images sent with type #DC_MSG_IMAGE.
  *                    If needed, recoding other file types is up to the UI.
+ * - `notification_privacy` = DC_NOTIFICATION_PRIVACY_FULL (0) =
+ *                    notifications show chat name, sender name and message text (default)
+ *                    DC_NOTIFICATION_PRIVACY_NAME_ONLY (1) =
+ *                    notifications show chat name and sender name only
+ *                    DC_NOTIFICATION_PRIVACY_COUNT_ONLY (2) =
+ *                    notifications show the number of new messages only.
+ * - `badge_no_unarchive` = 1=fresh messages in chats with the visibility
+ *                    #DC_CHAT_VISIBILITY_NO_UNARCHIVE are returned by dc_get_fresh_msgs(),
+ *                    0=these messages are not returned (default).
  * - `webrtc_instance` = webrtc instance to use for videochats in the form
  *                    `[basicwebrtc:|jitsi:]https://example.com/subdir#roomname=$ROOM`
  *                    if the url is prefixed by `basicwebrtc`, the server is assumed to be of the type
@@ -381,6 +428,84 @@ int             dc_set_config                (dc_context_t* context, const char*
 char*           dc_get_config                (dc_context_t* context, const char* key);
 
 
+/**
+ * Set a UI configuration option.
+ *
+ * UI configuration options can be used by the UI to store state per account,
+ * e.g. the last selected chat.
+ * In contrast to files created by the UI, they are included in backups.
+ *
+ * The options are stored separately from the options set by dc_set_config(),
+ * the core never reads or changes them.
+ * To avoid clashes between different UIs, keys should be prefixed, e.g. `desktop.last_chat`.
+ *
+ * Each value is limited to 64 KB, all keys and values together are limited to 1 MB;
+ * if a value exceeds a limit, nothing is changed and 0 is returned.
+ *
+ * @memberof dc_context_t
+ * @param context The context object.
+ * @param key The option to change.
+ * @param value The value to save for "key", NULL removes the option.
+ * @return 0=failure, 1=success
+ */
+int             dc_set_ui_config             (dc_context_t* context, const char* key, const char* value);
+
+
+/**
+ * Get a UI configuration option set by dc_set_ui_config().
+ *
+ * @memberof dc_context_t
+ * @param context The context object.
+ * @param key The option to get.
+ * @return The value of the option, NULL if the option is not set.
+ *     Returned strings must be released using dc_str_unref().
+ */
+char*           dc_get_ui_config             (dc_context_t* context, const char* key);
+
+
+/**
+ * Set a large configuration value, e.g. theme data.
+ *
+ * The value is stored in a file in the blob directory
+ * and is not limited by dc_set_config_value_limit().
+ * Options that can be set with dc_set_config() cannot be set this way.
+ *
+ * @memberof dc_context_t
+ * @param context The context object.
+ * @param key The option to change.
+ * @param value The value to save for "key", NULL removes the option.
+ * @return 0=failure, 1=success
+ */
+int             dc_set_config_blob           (dc_context_t* context, const char* key, const char* value);
+
+
+/**
+ * Get a configuration value set by dc_set_config_blob().
+ *
+ * @memberof dc_context_t
+ * @param context The context object.
+ * @param key The option to get.
+ * @return The value of the option, NULL if the option is not set.
+ *     Returned strings must be released using dc_str_unref().
+ */
+char*           dc_get_config_blob           (dc_context_t* context, const char* key);
+
+
+/**
+ * Set the maximum size of configuration values in bytes, 64 KB by default.
+ *
+ * Setting a larger value with dc_set_config() fails,
+ * large values should be stored with dc_set_config_blob() instead.
+ * The limit is stored in the database and does not affect values stored before.
+ *
+ * @memberof dc_context_t
+ * @param context The context object.
+ * @param limit The maximum size of a value in bytes.
+ * @return 0=failure, 1=success
+ */
+int             dc_set_config_value_limit    (dc_context_t* context, size_t limit);
+
+
 /**
  * Set stock string translation.
  *
@@ -429,6 +554,31 @@ int             dc_set_config_from_qr   (dc_context_t* context, const char* qr);
 char*           dc_get_info                  (const dc_context_t* context);
 
 
+/**
+ * Get counters of the context, e.g. for a debug info screen.
+ *
+ * The counters are returned as a JSON object with the following fields:
+ *
+ * - `messages`: number of messages in chats that are not blocked
+ * - `deaddrop_messages`: number of messages in contact requests
+ * - `chats`: number of chats that are not blocked
+ * - `contacts`: number of contacts
+ * - `blobs`: number of files in the blob directory
+ * - `blob_bytes`: total size of the files in the blob directory
+ * - `messages_per_day`: array with the number of messages of each of the last 30 days,
+ *   oldest first, the last entry is today
+ *
+ * Counting is slow for big accounts, so the counters are cached for a minute
+ * and changes not made by the core may show up only after this time.
+ *
+ * @memberof dc_context_t
+ * @param context The context object.
+ * @return JSON object, must be released using dc_str_unref() after usage.
+ *     Empty string on errors.
+ */
+char*           dc_get_stats_json            (dc_context_t* context);
+
+
 /**
  * Get url that can be used to initiate an OAuth2 authorisation.
  *
@@ -557,6 +707,16 @@ void            dc_start_io     (dc_context_t* context);
  */
 void            dc_stop_io(dc_context_t* context);
 
+/**
+ * Check if job, IMAP and SMTP tasks are running,
+ * i.e. dc_start_io() was called and dc_stop_io() was not called since.
+ *
+ * @memberof dc_context_t
+ * @param context The context object as created by dc_context_new().
+ * @return 1=IO is running, 0=IO is not running.
+ */
+int             dc_is_io_running(const dc_context_t* context);
+
 /**
  * This function should be called when there is a hint
  * that the network is available again,
@@ -582,6 +742,63 @@ void            dc_stop_io(dc_context_t* context);
 void            dc_maybe_network             (dc_context_t* context);
 
 
+/**
+ * Drop the chats and contacts the library keeps in memory.
+ *
+ * This function should be called when the operating system reports low memory,
+ * e.g. from `onTrimMemory()` on Android or `didReceiveMemoryWarning()` on iOS.
+ * The caches are filled again as chats and contacts are loaded,
+ * their size can be limited by the config-option `cache_size`.
+ *
+ * If the context was created by the dc_accounts_t account manager,
+ * use dc_accounts_clear_caches() instead of this function.
+ *
+ * @memberof dc_context_t
+ * @param context The context as created by dc_context_new().
+ */
+void            dc_clear_caches              (dc_context_t* context);
+
+
+/**
+ * Perform database and blob directory maintenance for at most the given time.
+ *
+ * The maintenance is done in small steps; the progress is saved
+ * and the next call continues where this one stopped.
+ * The function always makes some progress, even if the budget is 0,
+ * so it may take a bit longer than the budget.
+ * It is meant to be called from short background tasks,
+ * e.g. Android WorkManager jobs or iOS background app refresh.
+ *
+ * If the context was created by the dc_accounts_t account manager,
+ * use dc_accounts_perform_maintenance() instead of this function.
+ *
+ * @memberof dc_context_t
+ * @param context The context as created by dc_context_new().
+ * @param budget_ms The time in milliseconds the maintenance may take.
+ * @return 1=a maintenance cycle was completed, 0=more steps are pending or there was an error.
+ */
+int             dc_perform_maintenance       (dc_context_t* context, int budget_ms);
+
+
+/**
+ * Reconcile the local messages with the folders on the server.
+ *
+ * The messages on the server are matched to the local messages by their Message-ID;
+ * local messages keep their state, only their server UIDs are updated.
+ * Messages not known locally are downloaded.
+ * This happens automatically when the server changes the UIDVALIDITY of a folder,
+ * calling the function is useful eg. if the user reports that a folder looks wrong.
+ *
+ * The reconciliation runs in the background while IO is running,
+ * #DC_EVENT_FOLDERS_RESYNCED is emitted when it is done.
+ *
+ * @memberof dc_context_t
+ * @param context The context as created by dc_context_new().
+ * @return 1=reconciliation scheduled, 0=error, eg. the context is not configured.
+ */
+int             dc_resync_folders            (dc_context_t* context);
+
+
 
 /**
  * Save a keypair as the default keys for the user.
@@ -836,6 +1053,45 @@ uint32_t        dc_send_msg                  (dc_context_t* context, uint32_t ch
 uint32_t        dc_send_msg_sync                  (dc_context_t* context, uint32_t chat_id, dc_msg_t* msg);
 
 
+/**
+ * Send a message defined by a dc_msg_t object to a chat at a later time.
+ *
+ * The message is added to the chat in the state #DC_STATE_OUT_SCHEDULED
+ * and sent as with dc_send_msg() once the given time has come.
+ * If the device is offline at that time, the message is sent as soon as possible afterwards.
+ * Scheduled messages are kept across restarts
+ * and can be cancelled using dc_cancel_scheduled_msg() before they are sent.
+ *
+ * Sends the event #DC_EVENT_MSGS_CHANGED on succcess.
+ *
+ * @memberof dc_context_t
+ * @param context The context object as returned from dc_context_new().
+ * @param chat_id Chat ID to send the message to.
+ * @param msg Message object to send to the chat defined by the chat ID.
+ *     On succcess, msg_id of the object is set up,
+ *     The function does not take ownership of the object,
+ *     so you have to free it using dc_msg_unref() as usual.
+ * @param send_time Time to send the message at, in seconds since 1970.
+ *     If the time is not in the future, the message is sent immediately.
+ * @return The ID of the scheduled message. 0 in case of errors.
+ */
+uint32_t        dc_send_msg_at               (dc_context_t* context, uint32_t chat_id, dc_msg_t* msg, int64_t send_time);
+
+
+/**
+ * Cancel sending a message scheduled using dc_send_msg_at().
+ * The message is deleted from the chat.
+ *
+ * Sends the event #DC_EVENT_MSGS_CHANGED on succcess.
+ *
+ * @memberof dc_context_t
+ * @param context The context object as returned from dc_context_new().
+ * @param msg_id ID of the scheduled message.
+ * @return 1 on success, 0 if the message is not scheduled (anymore) or on other errors.
+ */
+int             dc_cancel_scheduled_msg      (dc_context_t* context, uint32_t msg_id);
+
+
 /**
  * Send a simple text message a given chat.
  *
@@ -894,6 +1150,72 @@ uint32_t        dc_send_text_msg             (dc_context_t* context, uint32_t ch
 uint32_t dc_send_videochat_invitation (dc_context_t* context, uint32_t chat_id);
 
 
+/**
+ * Send a reaction to a message.
+ *
+ * The reaction is typically a single emoji.
+ * A new reaction replaces the previous reaction of the user to the same message,
+ * sending an empty string removes the reaction.
+ *
+ * The reaction is sent as a hidden message to the chat of the message,
+ * other members get notified by #DC_EVENT_REACTIONS_CHANGED.
+ *
+ * @memberof dc_context_t
+ * @param context The context object.
+ * @param msg_id The ID of the message to react to.
+ * @param reaction The reaction to send, an empty string to remove the reaction.
+ *     Passing NULL causes the function to return 0.
+ * @return The ID of the hidden message containing the reaction
+ *     or 0 for errors.
+ */
+uint32_t dc_send_reaction (dc_context_t* context, uint32_t msg_id, const char* reaction);
+
+
+/**
+ * Send a status update for a message with an attachment, e.g. an app or a document.
+ *
+ * The update is appended to the status-update log of the message
+ * and sent as a hidden message to the chat of the message,
+ * the other members append it to their log as well
+ * and get notified by #DC_EVENT_MSG_STATUS_UPDATE.
+ * If the hidden message finally cannot be sent,
+ * the update is removed from the log again and #DC_EVENT_MSG_STATUS_UPDATE is emitted.
+ *
+ * A single update must not be larger than 100 KB
+ * and all updates of a message must not be larger than 1 MB.
+ *
+ * @memberof dc_context_t
+ * @param context The context object.
+ * @param msg_id The ID of the message with the attachment.
+ * @param json The update, any valid JSON.
+ *     Passing NULL causes the function to return 0.
+ * @return The ID of the hidden message containing the update
+ *     or 0 for errors.
+ */
+uint32_t dc_send_status_update (dc_context_t* context, uint32_t msg_id, const char* json);
+
+
+/**
+ * Get the status updates of a message, see dc_send_status_update().
+ *
+ * The updates are returned as a JSON array, e.g.
+ * `[{"serial":1,"payload":{"score":5}},{"serial":3,"payload":"done"}]`.
+ * Serials are increasing in the order the updates arrived,
+ * but not contiguous and only valid on this device.
+ * The array is ordered by the time the updates were sent, so all members get the same order;
+ * an update arriving late may be sorted before updates returned before.
+ *
+ * @memberof dc_context_t
+ * @param context The context object.
+ * @param msg_id The ID of the message with the attachment.
+ * @param since_serial Return only updates with a larger serial,
+ *     pass the last serial seen to get new updates or 0 to get all updates.
+ * @return A JSON array, an empty array if there are no updates.
+ *     Must be released using dc_str_unref() after usage.
+ */
+char* dc_get_status_updates (dc_context_t* context, uint32_t msg_id, uint32_t since_serial);
+
+
 /**
  * Save a draft for a chat in the database.
  *
@@ -984,6 +1306,20 @@ uint32_t        dc_add_device_msg            (dc_context_t* context, const char*
 int             dc_was_device_msg_ever_added (dc_context_t* context, const char* label);
 
 
+/**
+ * Delete a device-message added with a given label,
+ * eg. to retract an announcement added by mistake.
+ * The label is kept, so dc_add_device_msg() will not add the message again.
+ *
+ * @memberof dc_context_t
+ * @param context The context object.
+ * @param label Label of the message to delete.
+ * @return 1=The message was deleted,
+ *     0=There is no message with this label or it was already deleted.
+ */
+int             dc_delete_device_msg_by_label (dc_context_t* context, const char* label);
+
+
 /**
  * Get draft for a chat, if any.
  * See dc_set_draft() for more details about drafts.
@@ -1172,6 +1508,23 @@ uint32_t        dc_get_next_media            (dc_context_t* context, uint32_t ms
 int             dc_set_chat_protection       (dc_context_t* context, uint32_t chat_id, int protect);
 
 
+/**
+ * Get the protection status of a chat.
+ * Other than dc_chat_is_protected(), this checks the verified keys of all members
+ * and tells if the protection of a chat is broken,
+ * e.g. as a member uses a key different from the verified one.
+ * Normal messages cannot be sent to chats with broken protection.
+ *
+ * Changes of the status are reported by #DC_EVENT_CHAT_PROTECTION_CHANGED.
+ *
+ * @memberof dc_context_t
+ * @param context The context object.
+ * @param chat_id The ID of the chat to get the protection status for.
+ * @return One of the @ref DC_CHAT_PROTECTION constants.
+ */
+int             dc_get_chat_protection_status (dc_context_t* context, uint32_t chat_id);
+
+
 /**
  * Set chat visibility to pinned, archived or normal.
  *
@@ -1329,6 +1682,27 @@ dc_chat_t*      dc_get_chat                  (dc_context_t* context, uint32_t ch
 uint32_t        dc_create_group_chat         (dc_context_t* context, int protect, const char* name);
 
 
+/**
+ * Create a new broadcast list.
+ *
+ * Messages sent to a broadcast list are sent out as separate 1:1 messages to each member,
+ * so members do not see each other and their replies end up in the 1:1 chats.
+ * The message is shown once in the broadcast list
+ * and is delivered when it is delivered to all members.
+ *
+ * Members are added and removed using dc_add_contact_to_chat() and dc_remove_contact_from_chat(),
+ * this does not send any messages.
+ * Broadcast lists have the type DC_CHAT_TYPE_BROADCAST, see dc_chat_get_type().
+ *
+ * @memberof dc_context_t
+ * @param context The context object.
+ * @param name The name of the broadcast list.
+ *     The name is only shown locally and may be changed later using dc_set_chat_name().
+ * @return The chat ID of the new broadcast list, 0 on errors.
+ */
+uint32_t        dc_create_broadcast_list     (dc_context_t* context, const char* name);
+
+
 /**
  * Check if a given contact ID is a member of a group chat.
  *
@@ -1459,6 +1833,26 @@ int             dc_set_chat_profile_image    (dc_context_t* context, uint32_t ch
  */
 int             dc_set_chat_mute_duration             (dc_context_t* context, uint32_t chat_id, int64_t duration);
 
+
+/**
+ * Set how notifications of a chat should be presented.
+ *
+ * The profile is a hint for the UI, eg. to play a different sound
+ * or to use a high-importance notification channel.
+ * Muted chats are not notified at all, regardless of the profile.
+ * The profile is synchronized to other devices of the same account
+ * if `sync_msgs` is enabled.
+ *
+ * Sends out #DC_EVENT_CHAT_MODIFIED.
+ *
+ * @memberof dc_context_t
+ * @param context The context object.
+ * @param chat_id The chat ID to set the profile for.
+ * @param profile One of the @ref DC_NOTIFY_PROFILE constants.
+ * @return 1=success, 0=error
+ */
+int             dc_set_chat_notify_profile            (dc_context_t* context, uint32_t chat_id, int profile);
+
 // handle messages
 
 /**
@@ -1543,6 +1937,25 @@ char*           dc_get_msg_html              (dc_context_t* context, uint32_t ms
 char*           dc_get_mime_headers          (dc_context_t* context, uint32_t msg_id);
 
 
+/**
+ * Download the full message of a partially downloaded message.
+ * Messages larger than the `download_limit` config are downloaded partially,
+ * see dc_msg_get_download_state().
+ *
+ * The download is done in the background,
+ * the message state changes to DC_DOWNLOAD_IN_PROGRESS
+ * and #DC_EVENT_MSGS_CHANGED is emitted when the download is done or has failed.
+ * The message keeps its ID and chat,
+ * so UIs just have to reload the message.
+ *
+ * @memberof dc_context_t
+ * @param context The context object.
+ * @param msg_id The message id, must be the id of a message
+ *     in the state DC_DOWNLOAD_UNDOWNLOADED or DC_DOWNLOAD_FAILURE.
+ */
+void            dc_download_full_msg         (dc_context_t* context, uint32_t msg_id);
+
+
 /**
  * Delete messages. The messages are deleted on the current device and
  * on the IMAP server.
@@ -1567,6 +1980,25 @@ void            dc_delete_msgs               (dc_context_t* context, const uint3
 void            dc_forward_msgs              (dc_context_t* context, const uint32_t* msg_ids, int msg_cnt, uint32_t chat_id);
 
 
+/**
+ * Send messages sent before again to all current members of their chats,
+ * e.g. to show the last messages of a group to a member added later.
+ *
+ * The messages keep their text, attachment and date.
+ * The copies are not shown in the chat, the original messages are not changed.
+ * Members that already have the messages ignore the copies.
+ * Only outgoing messages sent by self can be resent,
+ * if any of the messages is not, nothing is sent.
+ *
+ * @memberof dc_context_t
+ * @param context The context object.
+ * @param msg_ids An array of uint32_t containing all message IDs that should be resent.
+ * @param msg_cnt The number of messages IDs in the msg_ids array.
+ * @return 1=success, 0=error.
+ */
+int             dc_resend_msgs               (dc_context_t* context, const uint32_t* msg_ids, int msg_cnt);
+
+
 /**
  * Mark all messages sent by the given contact as _noticed_.
  * This function is typically used to ignore a user in the deaddrop temporarily ("Not now" button).
@@ -1820,6 +2252,43 @@ void            dc_block_contact             (dc_context_t* context, uint32_t co
 char*           dc_get_contact_encrinfo      (dc_context_t* context, uint32_t contact_id);
 
 
+/**
+ * Get digits to compare with a contact out of band, e.g. by reading them aloud during a call.
+ *
+ * The digits are derived from your key and the key of the contact,
+ * the contact gets the same digits on their device.
+ * If the digits match, call dc_mark_contact_verified().
+ *
+ * @memberof dc_context_t
+ * @param context The context object.
+ * @param contact_id ID of the contact to get the digits for.
+ * @return Blocks of five digits separated by spaces,
+ *     must be released using dc_str_unref() after usage.
+ *     NULL if no key of the contact is known.
+ */
+char*           dc_get_contact_verification_words (dc_context_t* context, uint32_t contact_id);
+
+
+/**
+ * Mark the key of a contact as verified,
+ * after comparing dc_get_contact_verification_words() out of band.
+ *
+ * The digits compared are passed again;
+ * if the key of the contact changed in between, the contact is not verified.
+ *
+ * An existing one-to-one chat with the contact becomes protected.
+ * May result in a #DC_EVENT_CONTACTS_CHANGED event.
+ *
+ * @memberof dc_context_t
+ * @param context The context object.
+ * @param contact_id ID of the contact to verify.
+ * @param words The digits returned by dc_get_contact_verification_words() and compared with the contact.
+ * @return 1=success, 0=error, e.g. if no key of the contact is known
+ *     or if the key changed since the digits were returned.
+ */
+int             dc_mark_contact_verified     (dc_context_t* context, uint32_t contact_id, const char* words);
+
+
 /**
  * Delete a contact.  The contact is deleted from the local device.  It may happen that this is not
  * possible as the contact is in use.  In this case, the contact can be blocked.
@@ -1856,11 +2325,13 @@ dc_contact_t*   dc_get_contact               (dc_context_t* context, uint32_t co
 #define         DC_IMEX_IMPORT_SELF_KEYS      2 // param1 is a directory where the keys are searched in and read from
 #define         DC_IMEX_EXPORT_BACKUP        11 // param1 is a directory where the backup is written to
 #define         DC_IMEX_IMPORT_BACKUP        12 // param1 is the file with the backup to import
+#define         DC_IMEX_EXPORT_BACKUP_INCREMENTAL 13 // param1 is a directory where the incremental backup is written to
 
 
 /**
  * Import/export things.
- * During backup import/export IO must not be started, if needed stop IO using dc_stop_io() first.
+ * During backup import IO must not be started, if needed stop IO using dc_stop_io() first.
+ * Backups can be exported while IO is running.
  * What to do is defined by the _what_ parameter which may be one of the following:
  *
  * - **DC_IMEX_EXPORT_BACKUP** (11) - Export a backup to the directory given as `param1`.
@@ -1872,6 +2343,14 @@ dc_contact_t*   dc_get_contact               (dc_context_t* context, uint32_t co
  * - **DC_IMEX_IMPORT_BACKUP** (12) - `param1` is the file (not: directory) to import. The file is normally
  *   created by DC_IMEX_EXPORT_BACKUP and detected by dc_imex_has_backup(). Importing a backup
  *   is only possible as long as the context is not configured or used in another way.
+ *   Incremental backups are applied on top of an account imported from the backup they are based on
+ *   instead; they cannot be imported to an empty account.
+ *
+ * - **DC_IMEX_EXPORT_BACKUP_INCREMENTAL** (13) - Export an incremental backup to the directory given as `param1`.
+ *   The backup contains only the messages, chats and contacts changed
+ *   and the files added since the last backup of this account,
+ *   a full backup has to be exported before.
+ *   The name of the backup is `delta-chat-backup-<day>-<number>-incremental.tar`.
  *
  * - **DC_IMEX_EXPORT_SELF_KEYS** (1) - Export all private keys and all public keys of the user to the
  *   directory given as `param1`.  The default key is written to the files `public-key-default.asc`
@@ -1903,6 +2382,39 @@ dc_contact_t*   dc_get_contact               (dc_context_t* context, uint32_t co
 void            dc_imex                      (dc_context_t* context, int what, const char* param1, const char* param2);
 
 
+/**
+ * Import a single message from an .eml file.
+ *
+ * The message is assigned to a chat as if it was received from the server,
+ * but it is marked as seen, so it does not show up as fresh message
+ * and no read receipt is sent for it.
+ * The message is not imported if a message with the same Message-ID exists already.
+ *
+ * @memberof dc_context_t
+ * @param context The context object.
+ * @param path Path of the .eml file.
+ * @return 1=message imported, 0=message exists already or cannot be imported.
+ */
+int             dc_import_eml                (dc_context_t* context, const char* path);
+
+
+/**
+ * Import all messages from an mbox file, as exported eg. by Thunderbird.
+ *
+ * Each message is imported as by dc_import_eml(),
+ * messages that exist already are skipped.
+ *
+ * While dc_import_mbox() returns immediately, the import may take a while.
+ * During the import, #DC_EVENT_IMPORT_MSGS_PROGRESS events are sent,
+ * the last one has the same number of processed and total messages.
+ *
+ * @memberof dc_context_t
+ * @param context The context object.
+ * @param path Path of the mbox file.
+ */
+void            dc_import_mbox               (dc_context_t* context, const char* path);
+
+
 /**
  * Check if there is a backup file.
  * May only be used on fresh installations (e.g. dc_is_configured() returns 0).
@@ -2013,6 +2525,11 @@ char*           dc_initiate_key_transfer     (dc_context_t* context);
  * You can use dc_msg_get_setupcodebegin() to give the user a hint about the code (useful if the user
  * has created several messages and should not enter the wrong code).
  *
+ * After three wrong setup codes, further attempts are rejected for an increasing time
+ * to slow down guessing the code.
+ * The reason of a failure, e.g. a wrong setup code, a corrupt setup message
+ * or a key for another address, is logged as a warning.
+ *
  * @memberof dc_context_t
  * @param context The context object.
  * @param msg_id ID of the setup message to decrypt.
@@ -2026,6 +2543,17 @@ char*           dc_initiate_key_transfer     (dc_context_t* context);
 int             dc_continue_key_transfer     (dc_context_t* context, uint32_t msg_id, const char* setup_code);
 
 
+/**
+ * Check if dc_initiate_key_transfer() is running,
+ * i.e. the setup message is not sent yet.
+ *
+ * @memberof dc_context_t
+ * @param context The context object.
+ * @return 1=dc_initiate_key_transfer() is running, 0=no key transfer is running.
+ */
+int             dc_has_ongoing_key_transfer  (dc_context_t* context);
+
+
 /**
  * Signal an ongoing process to stop.
  *
@@ -2038,6 +2566,7 @@ int             dc_continue_key_transfer     (dc_context_t* context, uint32_t ms
  * Typical ongoing processes are started by dc_configure(),
  * dc_initiate_key_transfer() or dc_imex(). As there is always at most only
  * one onging process at the same time, there is no need to define _which_ process to exit.
+ * To stop a specific operation, eg. the maintenance, use dc_cancel_operation().
  *
  * @memberof dc_context_t
  * @param context The context object.
@@ -2045,6 +2574,38 @@ int             dc_continue_key_transfer     (dc_context_t* context, uint32_t ms
 void            dc_stop_ongoing_process      (dc_context_t* context);
 
 
+/**
+ * Get the long running operations, eg. started by dc_configure() or dc_imex().
+ *
+ * The operations are returned as a JSON array of objects with the following fields:
+ *
+ * - `kind`: one of the @ref DC_OPERATION constants
+ * - `started_at`: unix timestamp of the start of the operation
+ * - `progress`: progress in permille as reported by the progress events of the operation,
+ *   `null` if not known
+ * - `cancellable`: whether the operation can be stopped by dc_cancel_operation()
+ *
+ * @memberof dc_context_t
+ * @param context The context object.
+ * @return JSON array, must be released using dc_str_unref() after usage.
+ */
+char*           dc_get_running_operations_json (dc_context_t* context);
+
+
+/**
+ * Signal a running operation to stop.
+ *
+ * The function returns _without_ waiting for the operation to return,
+ * which may still take a moment.
+ *
+ * @memberof dc_context_t
+ * @param context The context object.
+ * @param kind One of the @ref DC_OPERATION constants.
+ * @return 1=the operation was signaled to stop, 0=no cancellable operation of this kind is running.
+ */
+int             dc_cancel_operation          (dc_context_t* context, int kind);
+
+
 // out-of-band verification
 
 #define         DC_QR_ASK_VERIFYCONTACT      200 // id=contact
@@ -2053,6 +2614,7 @@ void            dc_stop_ongoing_process      (dc_context_t* context);
 #define         DC_QR_FPR_MISMATCH           220 // id=contact
 #define         DC_QR_FPR_WITHOUT_ADDR       230 // test1=formatted fingerprint
 #define         DC_QR_ACCOUNT                250 // text1=domain
+#define         DC_QR_BACKUP                 251 // text1=domain, text2=URL
 #define         DC_QR_WEBRTC_INSTANCE        260 // text1=domain
 #define         DC_QR_ADDR                   320 // id=contact
 #define         DC_QR_TEXT                   330 // text1=text
@@ -2072,6 +2634,9 @@ void            dc_stop_ongoing_process      (dc_context_t* context);
  * - DC_QR_FPR_MISMATCH with dc_lot_t::id=Contact ID
  * - DC_QR_FPR_WITHOUT_ADDR with dc_lot_t::test1=Formatted fingerprint
  * - DC_QR_ACCOUNT allows creation of an account, dc_lot_t::text1=domain
+ * - DC_QR_BACKUP offers a backup for download from another device,
+ *   dc_lot_t::text1=domain, dc_lot_t::text2=URL of the backup
+ *   that can be imported with dc_imex() after downloading it
  * - DC_QR_WEBRTC_INSTANCE - a shared webrtc-instance
  *   that will be set if dc_set_config_from_qr() is called with the qr-code,
  *   dc_lot_t::text1=domain could be used to ask the user
@@ -2113,6 +2678,37 @@ dc_lot_t*       dc_check_qr                  (dc_context_t* context, const char*
 char*           dc_get_securejoin_qr         (dc_context_t* context, uint32_t chat_id);
 
 
+/**
+ * Revoke the QR code returned by dc_get_securejoin_qr().
+ *
+ * Contacts scanning the revoked QR code cannot join
+ * and get an info message telling them that the invitation was revoked.
+ * The next call to dc_get_securejoin_qr() returns a new QR code.
+ *
+ * @memberof dc_context_t
+ * @param context The context object.
+ * @param chat_id The group-chat-id the QR code was created for,
+ *     0 to revoke the Setup-Contact QR code.
+ * @return 1=success, 0=error
+ */
+int             dc_revoke_securejoin_qr      (dc_context_t* context, uint32_t chat_id);
+
+
+/**
+ * Get the number of QR code invitations that can be used to join a group.
+ *
+ * This is 0 after dc_revoke_securejoin_qr()
+ * until a new QR code is created by dc_get_securejoin_qr().
+ *
+ * @memberof dc_context_t
+ * @param context The context object.
+ * @param chat_id The group-chat-id,
+ *     0 to count Setup-Contact invitations.
+ * @return The number of active invitations.
+ */
+int             dc_get_active_invite_count   (dc_context_t* context, uint32_t chat_id);
+
+
 /**
  * Continue a Setup-Contact or Verified-Group-Invite protocol
  * started on another device with dc_get_securejoin_qr().
@@ -2520,6 +3116,27 @@ void           dc_accounts_stop_io              (dc_accounts_t* accounts);
 void           dc_accounts_maybe_network        (dc_accounts_t* accounts);
 
 
+/**
+ * Drop the chats and contacts kept in memory for all accounts.
+ * This is similar to dc_clear_caches(), see there for details.
+ *
+ * @memberof dc_accounts_t
+ * @param accounts Account manager as created by dc_accounts_new().
+ */
+void           dc_accounts_clear_caches         (dc_accounts_t* accounts);
+
+
+/**
+ * Perform maintenance for all accounts, splitting the given time between them.
+ * This is similar to dc_perform_maintenance(), see there for details.
+ *
+ * @memberof dc_accounts_t
+ * @param accounts Account manager as created by dc_accounts_new().
+ * @param budget_ms The time in milliseconds the maintenance of all accounts may take.
+ */
+void           dc_accounts_perform_maintenance  (dc_accounts_t* accounts, int budget_ms);
+
+
 /**
  * Create the event emitter that is used to receive events.
  *
@@ -2899,6 +3516,7 @@ char*            dc_chat_get_info_json       (dc_context_t* context, size_t chat
 #define         DC_CHAT_TYPE_SINGLE          100
 #define         DC_CHAT_TYPE_GROUP           120
 #define         DC_CHAT_TYPE_MAILINGLIST     140
+#define         DC_CHAT_TYPE_BROADCAST       160
 
 
 /**
@@ -2942,7 +3560,12 @@ uint32_t        dc_chat_get_id               (const dc_chat_t* chat);
  * - DC_CHAT_TYPE_MAILINGLIST (140) - a mailing list, this is similar to groups,
  *   however, the member list cannot be retrieved completely
  *   and cannot be changed using this api.
- *   moreover, for now, mailist lists are read-only.
+ *   moreover, mailing lists are read-only unless their messages
+ *   contain a `List-Post` header, check dc_chat_can_send().
+ *
+ * - DC_CHAT_TYPE_BROADCAST (160) - a broadcast list,
+ *   messages are sent as separate 1:1 messages to each member,
+ *   chats_contacts contain all members, but not DC_CONTACT_ID_SELF.
  *
  * @memberof dc_chat_t
  * @param chat The chat object.
@@ -3108,6 +3731,18 @@ int             dc_chat_is_sending_locations (const dc_chat_t* chat);
 int             dc_chat_is_muted (const dc_chat_t* chat);
 
 
+/**
+ * Get the notification profile of a chat
+ * (can be changed by dc_set_chat_notify_profile()).
+ *
+ * @memberof dc_chat_t
+ * @param chat The chat object.
+ * @return One of the @ref DC_NOTIFY_PROFILE constants,
+ *     DC_NOTIFY_PROFILE_DEFAULT if not set.
+ */
+int             dc_chat_get_notify_profile (const dc_chat_t* chat);
+
+
 /**
  * Get the exact state of the mute of a chat
  *
@@ -3139,6 +3774,7 @@ int64_t          dc_chat_get_remaining_mute_duration (const dc_chat_t* chat);
 #define         DC_STATE_OUT_PREPARING       18
 #define         DC_STATE_OUT_DRAFT           19
 #define         DC_STATE_OUT_PENDING         20
+#define         DC_STATE_OUT_SCHEDULED       21
 #define         DC_STATE_OUT_FAILED          24
 #define         DC_STATE_OUT_DELIVERED       26 // to check if a mail was sent, use dc_msg_is_sent()
 #define         DC_STATE_OUT_MDN_RCVD        28
@@ -3253,6 +3889,8 @@ int             dc_msg_get_viewtype           (const dc_msg_t* msg);
  * - DC_STATE_OUT_DRAFT (19) - Message saved as draft using dc_set_draft()
  * - DC_STATE_OUT_PENDING (20) - The user has pressed the "send" button but the
  *   message is not yet sent and is pending in some way. Maybe we're offline (no checkmark).
+ * - DC_STATE_OUT_SCHEDULED (21) - The message is going to be sent at a later time,
+ *   see dc_send_msg_at(). It enters DC_STATE_OUT_PENDING once that time has come.
  * - DC_STATE_OUT_FAILED (24) - _Unrecoverable_ error (_recoverable_ errors result in pending messages), you'll receive the event #DC_EVENT_MSG_FAILED.
  * - DC_STATE_OUT_DELIVERED (26) - Outgoing message successfully delivered to server (one checkmark). Note, that already delivered messages may get into the state DC_STATE_OUT_FAILED if we get such a hint from the server.
  *   If a sent message changes to this state, you'll receive the event #DC_EVENT_MSG_DELIVERED.
@@ -3468,6 +4106,23 @@ int             dc_msg_get_height             (const dc_msg_t* msg);
 int             dc_msg_get_duration           (const dc_msg_t* msg);
 
 
+/**
+ * Get the waveform of a voice message.
+ * The waveform is a list of amplitudes between 0 and 255,
+ * usually 100, to be drawn as bars in the message bubble.
+ * Use dc_array_get_cnt() and dc_array_get_id() to get the amplitudes.
+ *
+ * The waveform is generated by the sender when the message is sent,
+ * messages from other clients may come without a waveform.
+ *
+ * @memberof dc_msg_t
+ * @param msg The message object.
+ * @return The amplitudes, must be freed using dc_array_unref() when no longer used.
+ *     NULL if the message has no waveform.
+ */
+dc_array_t*     dc_msg_get_waveform           (const dc_msg_t* msg);
+
+
 /**
  * Check if a padlock should be shown beside the message.
  *
@@ -3818,6 +4473,26 @@ int dc_msg_get_videochat_type (const dc_msg_t* msg);
 int dc_msg_has_html (dc_msg_t* msg);
 
 
+/**
+ * Check if the message is downloaded completely.
+ * Messages larger than the `download_limit` config are downloaded partially,
+ * the text of these messages is a placeholder showing the size of the full message.
+ * The UI should offer a button calling dc_download_full_msg() for them.
+ *
+ * @memberof dc_msg_t
+ * @param msg The message object.
+ * @return One of the DC_DOWNLOAD_* constants:
+ *     DC_DOWNLOAD_DONE, DC_DOWNLOAD_UNDOWNLOADED, DC_DOWNLOAD_IN_PROGRESS or DC_DOWNLOAD_FAILURE.
+ *     In case of DC_DOWNLOAD_FAILURE, dc_msg_get_error() returns the reason.
+ */
+int dc_msg_get_download_state (dc_msg_t* msg);
+
+#define DC_DOWNLOAD_DONE           0
+#define DC_DOWNLOAD_UNDOWNLOADED   10
+#define DC_DOWNLOAD_IN_PROGRESS    20
+#define DC_DOWNLOAD_FAILURE        30
+
+
 /**
  * Set the text of a message object.
  * This does not alter any information in the database; this may be done by dc_send_msg() later.
@@ -4215,6 +4890,21 @@ int             dc_contact_is_blocked        (const dc_contact_t* contact);
 int             dc_contact_is_verified       (dc_contact_t* contact);
 
 
+/**
+ * Get the time the contact was seen for the last time.
+ *
+ * This is based on the messages received from the contact,
+ * it does not tell whether the contact is online.
+ * The UI may show this e.g. as "last seen 3 days ago" in the contact profile.
+ *
+ * @memberof dc_contact_t
+ * @param contact The contact object.
+ * @return The time in seconds since 1970, 0 if the contact was never seen
+ *     or for DC_CONTACT_ID_SELF and DC_CONTACT_ID_DEVICE.
+ */
+int64_t         dc_contact_get_last_seen     (dc_contact_t* contact);
+
+
 /**
  * @class dc_provider_t
  *
@@ -4226,6 +4916,8 @@ int             dc_contact_is_verified       (dc_contact_t* contact);
  * Create a provider struct for the given email address.
  *
  * The provider is extracted from the email address and it's information is returned.
+ * Entries of the `provider-overrides.toml` file used by the context
+ * take precedence over the built-in provider database.
  *
  * @memberof dc_provider_t
  * @param context The context object.
@@ -4905,6 +5597,19 @@ void dc_event_unref(dc_event_t* event);
 #define DC_EVENT_ERROR_SELF_NOT_IN_GROUP  410
 
 
+/**
+ * The database file is corrupted.
+ *
+ * The event is emitted only once, when the corruption is detected first.
+ * IO is stopped and further database operations fail.
+ * The UI should inform the user and offer to import a backup.
+ *
+ * @param data1 0
+ * @param data2 (char*) Error string in English language.
+ */
+#define DC_EVENT_DATABASE_CORRUPT         420
+
+
 /**
  * Messages or chats changed.  One or more messages or chats changed for various
  * reasons in the database:
@@ -4918,6 +5623,26 @@ void dc_event_unref(dc_event_t* event);
 #define DC_EVENT_MSGS_CHANGED             2000
 
 
+/**
+ * A contact added, changed or removed their reaction to a message.
+ * Reactions are sent using dc_send_reaction().
+ *
+ * @param data1 (int) chat_id
+ * @param data2 (int) msg_id
+ */
+#define DC_EVENT_REACTIONS_CHANGED        2001
+
+
+/**
+ * A status update was added to the log of a message,
+ * see dc_send_status_update() and dc_get_status_updates().
+ *
+ * @param data1 (int) chat_id
+ * @param data2 (int) msg_id
+ */
+#define DC_EVENT_MSG_STATUS_UPDATE        2002
+
+
 /**
  * There is a fresh message. Typically, the user will show an notification
  * when receiving this message.
@@ -4976,6 +5701,17 @@ void dc_event_unref(dc_event_t* event);
 #define DC_EVENT_MSG_READ                 2015
 
 
+/**
+ * A read receipt for an outgoing message arrived from a contact.
+ * In groups, this event is emitted for each member sending a receipt,
+ * whereas #DC_EVENT_MSG_READ is emitted once the message counts as read.
+ *
+ * @param data1 (int) msg_id
+ * @param data2 (int) contact_id
+ */
+#define DC_EVENT_MSG_READ_RECEIPT         2016
+
+
 /**
  * Chat changed.  The name or the image of a chat group was changed or members were added or removed.
  * Or the verify state of a chat has changed.
@@ -4993,6 +5729,17 @@ void dc_event_unref(dc_event_t* event);
 #define DC_EVENT_CHAT_EPHEMERAL_TIMER_MODIFIED 2021
 
 
+/**
+ * The protection status of a chat changed,
+ * e.g. as the key of a member of a protected chat changed.
+ * See dc_get_chat_protection_status().
+ *
+ * @param data1 (int) chat_id
+ * @param data2 (int) The new protection status, one of the @ref DC_CHAT_PROTECTION constants.
+ */
+#define DC_EVENT_CHAT_PROTECTION_CHANGED  2022
+
+
 /**
  * Contact(s) created, renamed, verified, blocked or deleted.
  *
@@ -5023,6 +5770,16 @@ void dc_event_unref(dc_event_t* event);
 #define DC_EVENT_CONFIGURE_PROGRESS       2041
 
 
+/**
+ * Inform about the stage the configuration started by dc_configure() entered.
+ * Emitted in addition to #DC_EVENT_CONFIGURE_PROGRESS.
+ *
+ * @param data1 (int) One of the DC_CONFIGURE_STAGE_* constants.
+ * @param data2 0
+ */
+#define DC_EVENT_CONFIGURE_STAGE_CHANGED  2042
+
+
 /**
  * Inform about the import/export progress started by dc_imex().
  *
@@ -5045,6 +5802,62 @@ void dc_event_unref(dc_event_t* event);
 #define DC_EVENT_IMEX_FILE_WRITTEN        2052
 
 
+/**
+ * Inform about the progress of importing messages started by dc_import_mbox().
+ *
+ * @param data1 (int) Number of messages processed so far.
+ * @param data2 (int) Estimated number of messages to process.
+ *     When the import is done, data1 and data2 are equal.
+ */
+#define DC_EVENT_IMPORT_MSGS_PROGRESS     2053
+
+
+/**
+ * Inform about the progress of deleting old messages from the server.
+ *
+ * When the config option `delete_server_after` is lowered,
+ * messages already on the server are deleted in the background
+ * if they are older than the new value.
+ *
+ * @param data1 (int) Number of messages processed so far.
+ * @param data2 (int) Number of messages to process.
+ */
+#define DC_EVENT_SERVER_CLEANUP_PROGRESS  2056
+
+
+/**
+ * Deleting old messages from the server after `delete_server_after` was lowered is done,
+ * see #DC_EVENT_SERVER_CLEANUP_PROGRESS.
+ *
+ * @param data1 (int) Number of messages deleted from the server.
+ * @param data2 0
+ */
+#define DC_EVENT_SERVER_CLEANUP_DONE      2057
+
+
+/**
+ * Messages older than `delete_device_after` were deleted from the device.
+ *
+ * Old messages are deleted in the background while IO is running,
+ * see dc_set_config().
+ *
+ * @param data1 (int) Number of deleted messages.
+ * @param data2 (int) Number of deleted files.
+ */
+#define DC_EVENT_DEVICE_CLEANUP_DONE      2058
+
+
+/**
+ * The folders were reconciled with the server,
+ * see dc_resync_folders().
+ *
+ * @param data1 (int) Number of messages on the server matched to local messages.
+ * @param data2 (int) Number of messages on the server not known locally and newer than all matched messages,
+ *     they are downloaded.
+ */
+#define DC_EVENT_FOLDERS_RESYNCED         2059
+
+
 /**
  * Progress information of a secure-join handshake from the view of the inviter
  * (Alice, the person who shows the QR code).
@@ -5076,6 +5889,38 @@ void dc_event_unref(dc_event_t* event);
  */
 #define DC_EVENT_SECUREJOIN_JOINER_PROGRESS       2061
 
+
+/**
+ * Messages with an expired ephemeral timer were deleted from the device,
+ * see dc_set_chat_ephemeral_timer().
+ *
+ * @param data1 (int) Number of deleted messages.
+ * @param data2 (int) Number of deleted files.
+ */
+#define DC_EVENT_EPHEMERAL_CLEANUP_DONE   2062
+
+
+/**
+ * The database of the account was closed for maintenance,
+ * eg. to replace the database file.
+ * Until the database is reopened, all functions accessing it fail.
+ *
+ * @param data1 0
+ * @param data2 0
+ */
+#define DC_EVENT_DATABASE_CLOSED          2070
+
+
+/**
+ * The database of the account was opened again after maintenance.
+ * The content may have changed completely,
+ * so the UI should reload everything shown for the account.
+ *
+ * @param data1 0
+ * @param data2 0
+ */
+#define DC_EVENT_DATABASE_REOPENED        2071
+
 /**
  * @}
  */
@@ -5100,6 +5945,61 @@ void dc_event_unref(dc_event_t* event);
 #define DC_MEDIA_QUALITY_WORSE    1
 
 
+/*
+ * Values for dc_get|set_config("notification_privacy")
+ */
+#define DC_NOTIFICATION_PRIVACY_FULL       0
+#define DC_NOTIFICATION_PRIVACY_NAME_ONLY  1
+#define DC_NOTIFICATION_PRIVACY_COUNT_ONLY 2
+
+
+/**
+ * @defgroup DC_NOTIFY_PROFILE DC_NOTIFY_PROFILE
+ *
+ * Values for dc_set_chat_notify_profile() and dc_chat_get_notify_profile()
+ *
+ * @addtogroup DC_NOTIFY_PROFILE
+ * @{
+ */
+#define DC_NOTIFY_PROFILE_DEFAULT  0
+#define DC_NOTIFY_PROFILE_SILENT   1
+#define DC_NOTIFY_PROFILE_PRIORITY 2
+/**
+ * @}
+ */
+
+
+/**
+ * @defgroup DC_OPERATION DC_OPERATION
+ *
+ * Kinds of long running operations,
+ * see dc_get_running_operations_json() and dc_cancel_operation()
+ *
+ * @addtogroup DC_OPERATION
+ * @{
+ */
+#define DC_OPERATION_CONFIGURE     1
+#define DC_OPERATION_IMEX          2
+#define DC_OPERATION_KEY_TRANSFER  3
+#define DC_OPERATION_SECURE_JOIN   4
+#define DC_OPERATION_STORAGE_USAGE 5
+#define DC_OPERATION_MAINTENANCE   6
+/**
+ * @}
+ */
+
+
+/*
+ * Values for data1 of #DC_EVENT_CONFIGURE_STAGE_CHANGED
+ */
+#define DC_CONFIGURE_STAGE_AUTOCONFIG_LOOKUP 1
+#define DC_CONFIGURE_STAGE_IMAP_CONNECT      2
+#define DC_CONFIGURE_STAGE_IMAP_AUTH         3
+#define DC_CONFIGURE_STAGE_SMTP_CONNECT      4
+#define DC_CONFIGURE_STAGE_SMTP_AUTH         5
+#define DC_CONFIGURE_STAGE_FINALIZE          6
+
+
 /*
  * Values for dc_get|set_config("key_gen_type")
  */
@@ -5200,6 +6100,47 @@ void dc_event_unref(dc_event_t* event);
  */
 #define         DC_CHAT_VISIBILITY_PINNED      2
 
+/**
+ * Chats archived with this visibility are handled as DC_CHAT_VISIBILITY_ARCHIVED,
+ * however, they stay archived if they receive new messages.
+ * This is useful e.g. for noisy mailing lists.
+ *
+ * New messages in these chats are still counted by dc_get_fresh_msg_cnt(),
+ * the config option `badge_no_unarchive` defines if they are counted for the app badge.
+ */
+#define         DC_CHAT_VISIBILITY_NO_UNARCHIVE 3
+
+/**
+ * @}
+ */
+
+
+/**
+ * @defgroup DC_CHAT_PROTECTION DC_CHAT_PROTECTION
+ *
+ * These constants describe the protection status of a chat
+ * as returned by dc_get_chat_protection_status().
+ *
+ * @addtogroup DC_CHAT_PROTECTION
+ * @{
+ */
+
+/**
+ * The chat is not protected.
+ */
+#define         DC_CHAT_PROTECTION_UNPROTECTED 0
+
+/**
+ * The chat is protected and all members are verified.
+ */
+#define         DC_CHAT_PROTECTION_PROTECTED   1
+
+/**
+ * The chat is protected, but some members are not verified anymore,
+ * e.g. as they use a key different from the verified one.
+ */
+#define         DC_CHAT_PROTECTION_BROKEN      2
+
 /**
  * @}
  */
@@ -5605,6 +6546,32 @@ void dc_event_unref(dc_event_t* event);
 /// `%1$s` will be replaced by the number of weeks (always >1) the timer is set to.
 #define DC_STR_EPHEMERAL_WEEKS            96
 
+/// "%1$s message"
+///
+/// Used as the text of messages that are not downloaded completely, see dc_msg_get_download_state().
+//
+/// `%1$s` will be replaced by the human-readable size of the full message, eg. "1.2 MiB".
+#define DC_STR_PARTIAL_DOWNLOAD_MSG_BODY  97
+
+/// "New message"
+///
+/// Used as notification text if `notification_privacy` is set to DC_NOTIFICATION_PRIVACY_NAME_ONLY.
+#define DC_STR_NEW_MESSAGE_NOTIFICATION   98
+
+/// "%1$s new messages"
+///
+/// Used as notification text if `notification_privacy` is set to DC_NOTIFICATION_PRIVACY_COUNT_ONLY.
+//
+/// `%1$s` will be replaced by the number of new messages.
+#define DC_STR_NEW_MESSAGES_NOTIFICATION  99
+
+/// "The invitation by %1$s was revoked."
+///
+/// Added to the chat with the inviter when joining with a QR code revoked by dc_revoke_securejoin_qr().
+//
+/// `%1$s` will be replaced by the e-mail address of the inviter.
+#define DC_STR_SECUREJOIN_REVOKED         100
+
 /**
  * @}
  */
@@ -25,12 +25,13 @@
 use num_traits::{FromPrimitive, ToPrimitive};
 
 use deltachat::chat::{ChatId, ChatVisibility, MuteDuration, ProtectionStatus};
-use deltachat::constants::DC_MSG_ID_LAST_SPECIAL;
+use deltachat::constants::{NotifyProfile, DC_MSG_ID_LAST_SPECIAL};
 use deltachat::contact::{Contact, Origin};
 use deltachat::context::Context;
 use deltachat::ephemeral::Timer as EphemeralTimer;
 use deltachat::key::DcKey;
 use deltachat::message::MsgId;
+use deltachat::ongoing::OperationKind;
 use deltachat::stock_str::StockMessage;
 use deltachat::*;
 use deltachat::{accounts::Accounts, log::LogExt};
@@ -164,6 +165,106 @@
     }
 }
 
+#[no_mangle]
+pub unsafe extern "C" fn dc_set_ui_config(
+    context: *mut dc_context_t,
+    key: *const libc::c_char,
+    value: *const libc::c_char,
+) -> libc::c_int {
+    if context.is_null() || key.is_null() {
+        eprintln!("ignoring careless call to dc_set_ui_config()");
+        return 0;
+    }
+    let ctx = &*context;
+    let key = to_string_lossy(key);
+    block_on(async move {
+        ctx.set_ui_config(&key, to_opt_string_lossy(value).as_deref())
+            .await
+            .log_err(ctx, "dc_set_ui_config() failed")
+            .is_ok() as libc::c_int
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn dc_get_ui_config(
+    context: *mut dc_context_t,
+    key: *const libc::c_char,
+) -> *mut libc::c_char {
+    if context.is_null() || key.is_null() {
+        eprintln!("ignoring careless call to dc_get_ui_config()");
+        return ptr::null_mut();
+    }
+    let ctx = &*context;
+    let key = to_string_lossy(key);
+    block_on(async move {
+        ctx.get_ui_config(&key)
+            .await
+            .log_err(ctx, "dc_get_ui_config() failed")
+            .unwrap_or_default()
+            .strdup()
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn dc_set_config_blob(
+    context: *mut dc_context_t,
+    key: *const libc::c_char,
+    value: *const libc::c_char,
+) -> libc::c_int {
+    if context.is_null() || key.is_null() {
+        eprintln!("ignoring careless call to dc_set_config_blob()");
+        return 0;
+    }
+    let ctx = &*context;
+    let key = to_string_lossy(key);
+    block_on(async move {
+        let value = to_opt_string_lossy(value);
+        ctx.set_config_blob(&key, value.as_deref().map(str::as_bytes))
+            .await
+            .log_err(ctx, "dc_set_config_blob() failed")
+            .is_ok() as libc::c_int
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn dc_get_config_blob(
+    context: *mut dc_context_t,
+    key: *const libc::c_char,
+) -> *mut libc::c_char {
+    if context.is_null() || key.is_null() {
+        eprintln!("ignoring careless call to dc_get_config_blob()");
+        return ptr::null_mut();
+    }
+    let ctx = &*context;
+    let key = to_string_lossy(key);
+    block_on(async move {
+        ctx.get_config_blob(&key)
+            .await
+            .log_err(ctx, "dc_get_config_blob() failed")
+            .unwrap_or_default()
+            .map(|data| String::from_utf8_lossy(&data).into_owned())
+            .strdup()
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn dc_set_config_value_limit(
+    context: *mut dc_context_t,
+    limit: libc::size_t,
+) -> libc::c_int {
+    if context.is_null() {
+        eprintln!("ignoring careless call to dc_set_config_value_limit()");
+        return 0;
+    }
+    let ctx = &*context;
+    block_on(async move {
+        ctx.set_config_value_limit(limit)
+            .await
+            .log_err(ctx, "dc_set_config_value_limit() failed")
+            .is_ok() as libc::c_int
+    })
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn dc_set_stock_translation(
     context: *mut dc_context_t,
@@ -230,6 +331,34 @@
     })
 }
 
+#[no_mangle]
+pub unsafe extern "C" fn dc_get_stats_json(context: *mut dc_context_t) -> *mut libc::c_char {
+    if context.is_null() {
+        eprintln!("ignoring careless call to dc_get_stats_json()");
+        return "".strdup();
+    }
+    let ctx = &*context;
+    block_on(async move {
+        match ctx.get_stats().await {
+            Ok(stats) => serde_json::json!({
+                "messages": stats.messages,
+                "deaddrop_messages": stats.deaddrop_messages,
+                "chats": stats.chats,
+                "contacts": stats.contacts,
+                "blobs": stats.blobs,
+                "blob_bytes": stats.blob_bytes,
+                "messages_per_day": stats.messages_per_day,
+            })
+            .to_string()
+            .strdup(),
+            Err(err) => {
+                error!(ctx, "dc_get_stats_json() failed: {:#}", err);
+                "".strdup()
+            }
+        }
+    })
+}
+
 fn render_info(
     info: BTreeMap<&'static str, String>,
 ) -> std::result::Result<String, std::fmt::Error> {
@@ -349,15 +478,22 @@ fn render_info(
         | EventType::Warning(_)
         | EventType::Error(_)
         | EventType::ErrorNetwork(_)
-        | EventType::ErrorSelfNotInGroup(_) => 0,
+        | EventType::ErrorSelfNotInGroup(_)
+        | EventType::DatabaseCorrupt { .. }
+        | EventType::DatabaseClosed
+        | EventType::DatabaseReopened => 0,
         EventType::MsgsChanged { chat_id, .. }
+        | EventType::ReactionsChanged { chat_id, .. }
+        | EventType::MsgStatusUpdate { chat_id, .. }
         | EventType::IncomingMsg { chat_id, .. }
         | EventType::MsgsNoticed(chat_id)
         | EventType::MsgDelivered { chat_id, .. }
         | EventType::MsgFailed { chat_id, .. }
         | EventType::MsgRead { chat_id, .. }
         | EventType::ChatModified(chat_id)
-        | EventType::ChatEphemeralTimerModified { chat_id, .. } => chat_id.to_u32() as libc::c_int,
+        | EventType::ChatEphemeralTimerModified { chat_id, .. }
+        | EventType::ChatProtectionChanged { chat_id, .. } => chat_id.to_u32() as libc::c_int,
+        EventType::MsgReadReceipt { msg_id, .. } => msg_id.to_u32() as libc::c_int,
         EventType::ContactsChanged(id) | EventType::LocationChanged(id) => {
             let id = id.unwrap_or_default();
             id as libc::c_int
@@ -365,7 +501,14 @@ fn render_info(
         EventType::ConfigureProgress { progress, .. } | EventType::ImexProgress(progress) => {
             *progress as libc::c_int
         }
+        EventType::ConfigureStageChanged(stage) => *stage as libc::c_int,
         EventType::ImexFileWritten(_) => 0,
+        EventType::ImportMsgsProgress { processed, .. }
+        | EventType::ServerCleanupProgress { processed, .. } => *processed as libc::c_int,
+        EventType::ServerCleanupDone { deleted } => *deleted as libc::c_int,
+        EventType::DeviceCleanupDone { deleted_msgs, .. }
+        | EventType::EphemeralCleanupDone { deleted_msgs, .. } => *deleted_msgs as libc::c_int,
+        EventType::FoldersResynced { matched, .. } => *matched as libc::c_int,
         EventType::SecurejoinInviterProgress { contact_id, .. }
         | EventType::SecurejoinJoinerProgress { contact_id, .. } => *contact_id as libc::c_int,
     }
@@ -393,21 +536,35 @@ fn render_info(
         | EventType::Error(_)
         | EventType::ErrorNetwork(_)
         | EventType::ErrorSelfNotInGroup(_)
+        | EventType::DatabaseCorrupt { .. }
         | EventType::ContactsChanged(_)
         | EventType::LocationChanged(_)
         | EventType::ConfigureProgress { .. }
+        | EventType::ConfigureStageChanged(_)
         | EventType::ImexProgress(_)
         | EventType::ImexFileWritten(_)
+        | EventType::ServerCleanupDone { .. }
         | EventType::MsgsNoticed(_)
-        | EventType::ChatModified(_) => 0,
+        | EventType::ChatModified(_)
+        | EventType::DatabaseClosed
+        | EventType::DatabaseReopened => 0,
         EventType::MsgsChanged { msg_id, .. }
+        | EventType::ReactionsChanged { msg_id, .. }
+        | EventType::MsgStatusUpdate { msg_id, .. }
         | EventType::IncomingMsg { msg_id, .. }
         | EventType::MsgDelivered { msg_id, .. }
         | EventType::MsgFailed { msg_id, .. }
         | EventType::MsgRead { msg_id, .. } => msg_id.to_u32() as libc::c_int,
+        EventType::MsgReadReceipt { contact_id, .. } => *contact_id as libc::c_int,
         EventType::SecurejoinInviterProgress { progress, .. }
         | EventType::SecurejoinJoinerProgress { progress, .. } => *progress as libc::c_int,
         EventType::ChatEphemeralTimerModified { timer, .. } => timer.to_u32() as libc::c_int,
+        EventType::ChatProtectionChanged { status, .. } => status.to_u32() as libc::c_int,
+        EventType::ImportMsgsProgress { total, .. }
+        | EventType::ServerCleanupProgress { total, .. } => *total as libc::c_int,
+        EventType::DeviceCleanupDone { deleted_blobs, .. }
+        | EventType::EphemeralCleanupDone { deleted_blobs, .. } => *deleted_blobs as libc::c_int,
+        EventType::FoldersResynced { new, .. } => *new as libc::c_int,
     }
 }
 
@@ -432,23 +589,37 @@ fn render_info(
         | EventType::Warning(msg)
         | EventType::Error(msg)
         | EventType::ErrorNetwork(msg)
-        | EventType::ErrorSelfNotInGroup(msg) => {
+        | EventType::ErrorSelfNotInGroup(msg)
+        | EventType::DatabaseCorrupt { message: msg } => {
             let data2 = msg.to_c_string().unwrap_or_default();
             data2.into_raw()
         }
         EventType::MsgsChanged { .. }
+        | EventType::ReactionsChanged { .. }
+        | EventType::MsgStatusUpdate { .. }
         | EventType::IncomingMsg { .. }
         | EventType::MsgsNoticed(_)
         | EventType::MsgDelivered { .. }
         | EventType::MsgFailed { .. }
         | EventType::MsgRead { .. }
+        | EventType::MsgReadReceipt { .. }
         | EventType::ChatModified(_)
         | EventType::ContactsChanged(_)
         | EventType::LocationChanged(_)
+        | EventType::ConfigureStageChanged(_)
         | EventType::ImexProgress(_)
+        | EventType::ImportMsgsProgress { .. }
+        | EventType::ServerCleanupProgress { .. }
+        | EventType::ServerCleanupDone { .. }
+        | EventType::DeviceCleanupDone { .. }
+        | EventType::EphemeralCleanupDone { .. }
+        | EventType::FoldersResynced { .. }
         | EventType::SecurejoinInviterProgress { .. }
         | EventType::SecurejoinJoinerProgress { .. }
-        | EventType::ChatEphemeralTimerModified { .. } => ptr::null_mut(),
+        | EventType::ChatEphemeralTimerModified { .. }
+        | EventType::ChatProtectionChanged { .. }
+        | EventType::DatabaseClosed
+        | EventType::DatabaseReopened => ptr::null_mut(),
         EventType::ConfigureProgress { comment, .. } => {
             if let Some(comment) = comment {
                 comment.to_c_string().unwrap_or_default().into_raw()
@@ -511,6 +682,17 @@ fn render_info(
         .unwrap_or_else(ptr::null_mut)
 }
 
+#[no_mangle]
+pub unsafe extern "C" fn dc_is_io_running(context: *mut dc_context_t) -> libc::c_int {
+    if context.is_null() {
+        eprintln!("ignoring careless call to dc_is_io_running()");
+        return 0;
+    }
+    let ctx = &*context;
+
+    block_on(ctx.is_io_running()) as libc::c_int
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn dc_stop_io(context: *mut dc_context_t) {
     if context.is_null() {
@@ -535,6 +717,53 @@ fn render_info(
     block_on(async move { ctx.maybe_network().await })
 }
 
+#[no_mangle]
+pub unsafe extern "C" fn dc_clear_caches(context: *mut dc_context_t) {
+    if context.is_null() {
+        eprintln!("ignoring careless call to dc_clear_caches()");
+        return;
+    }
+    let ctx = &*context;
+
+    ctx.clear_caches()
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn dc_perform_maintenance(
+    context: *mut dc_context_t,
+    budget_ms: libc::c_int,
+) -> libc::c_int {
+    if context.is_null() {
+        eprintln!("ignoring careless call to dc_perform_maintenance()");
+        return 0;
+    }
+    let ctx = &*context;
+    let budget = Duration::from_millis(budget_ms.max(0) as u64);
+
+    block_on(async move {
+        ctx.perform_maintenance(budget)
+            .await
+            .map(|report| report.cycle_completed as libc::c_int)
+            .unwrap_or_log_default(ctx, "Failed to perform maintenance")
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn dc_resync_folders(context: *mut dc_context_t) -> libc::c_int {
+    if context.is_null() {
+        eprintln!("ignoring careless call to dc_resync_folders()");
+        return 0;
+    }
+    let ctx = &*context;
+
+    block_on(async move {
+        ctx.resync_folders()
+            .await
+            .map(|_| 1)
+            .unwrap_or_log_default(ctx, "Failed to resync folders")
+    })
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn dc_preconfigure_keypair(
     context: *mut dc_context_t,
@@ -710,6 +939,44 @@ fn render_info(
     .to_u32()
 }
 
+#[no_mangle]
+pub unsafe extern "C" fn dc_send_msg_at(
+    context: *mut dc_context_t,
+    chat_id: u32,
+    msg: *mut dc_msg_t,
+    send_time: i64,
+) -> u32 {
+    if context.is_null() || msg.is_null() {
+        eprintln!("ignoring careless call to dc_send_msg_at()");
+        return 0;
+    }
+    let ctx = &mut *context;
+    let ffi_msg = &mut *msg;
+
+    block_on(async move {
+        chat::send_msg_at(&ctx, ChatId::new(chat_id), &mut ffi_msg.message, send_time)
+            .await
+            .unwrap_or_log_default(&ctx, "Failed to schedule message")
+    })
+    .to_u32()
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn dc_cancel_scheduled_msg(
+    context: *mut dc_context_t,
+    msg_id: u32,
+) -> libc::c_int {
+    if context.is_null() {
+        eprintln!("ignoring careless call to dc_cancel_scheduled_msg()");
+        return 0;
+    }
+    let ctx = &*context;
+
+    block_on(message::cancel_scheduled(ctx, MsgId::new(msg_id)))
+        .log_err(ctx, "Failed to cancel scheduled message")
+        .is_ok() as libc::c_int
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn dc_send_text_msg(
     context: *mut dc_context_t,
@@ -750,6 +1017,78 @@ fn render_info(
     })
 }
 
+#[no_mangle]
+pub unsafe extern "C" fn dc_send_reaction(
+    context: *mut dc_context_t,
+    msg_id: u32,
+    reaction: *const libc::c_char,
+) -> u32 {
+    if context.is_null() || reaction.is_null() {
+        eprintln!("ignoring careless call to dc_send_reaction()");
+        return 0;
+    }
+    let ctx = &*context;
+    let reaction = to_string_lossy(reaction);
+
+    block_on(async move {
+        chat::send_reaction(&ctx, MsgId::new(msg_id), &reaction)
+            .await
+            .map(|msg_id| msg_id.to_u32())
+            .unwrap_or_log_default(&ctx, "Failed to send reaction")
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn dc_send_status_update(
+    context: *mut dc_context_t,
+    msg_id: u32,
+    json: *const libc::c_char,
+) -> u32 {
+    if context.is_null() || json.is_null() {
+        eprintln!("ignoring careless call to dc_send_status_update()");
+        return 0;
+    }
+    let ctx = &*context;
+    let json = to_string_lossy(json);
+
+    block_on(async move {
+        message::send_status_update(&ctx, MsgId::new(msg_id), &json)
+            .await
+            .map(|msg_id| msg_id.to_u32())
+            .unwrap_or_log_default(&ctx, "Failed to send status update")
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn dc_get_status_updates(
+    context: *mut dc_context_t,
+    msg_id: u32,
+    since_serial: u32,
+) -> *mut libc::c_char {
+    if context.is_null() {
+        eprintln!("ignoring careless call to dc_get_status_updates()");
+        return "".strdup();
+    }
+    let ctx = &*context;
+
+    block_on(async move {
+        let updates = message::get_status_updates(&ctx, MsgId::new(msg_id), since_serial)
+            .await
+            .unwrap_or_log_default(&ctx, "Failed to get status updates");
+        let items = updates
+            .into_iter()
+            .map(|update| {
+                serde_json::json!({
+                    "serial": update.serial,
+                    "payload": serde_json::from_str::<serde_json::Value>(&update.json)
+                        .unwrap_or_default(),
+                })
+            })
+            .collect::<Vec<_>>();
+        serde_json::Value::Array(items).to_string().strdup()
+    })
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn dc_set_draft(
     context: *mut dc_context_t,
@@ -815,6 +1154,25 @@ fn render_info(
     })
 }
 
+#[no_mangle]
+pub unsafe extern "C" fn dc_delete_device_msg_by_label(
+    context: *mut dc_context_t,
+    label: *const libc::c_char,
+) -> libc::c_int {
+    if context.is_null() || label.is_null() {
+        eprintln!("ignoring careless call to dc_delete_device_msg_by_label()");
+        return 0;
+    }
+    let ctx = &*context;
+
+    block_on(async move {
+        chat::delete_device_msg_by_label(&ctx, &to_string_lossy(label))
+            .await
+            .log_err(ctx, "Failed to delete device message")
+            .unwrap_or(false) as libc::c_int
+    })
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn dc_get_draft(context: *mut dc_context_t, chat_id: u32) -> *mut dc_msg_t {
     if context.is_null() {
@@ -1060,6 +1418,26 @@ fn from_prim<S, T>(s: S) -> Option<T>
     })
 }
 
+#[no_mangle]
+pub unsafe extern "C" fn dc_get_chat_protection_status(
+    context: *mut dc_context_t,
+    chat_id: u32,
+) -> libc::c_int {
+    if context.is_null() {
+        eprintln!("ignoring careless call to dc_get_chat_protection_status()");
+        return 0;
+    }
+    let ctx = &*context;
+
+    block_on(async move {
+        ChatId::new(chat_id)
+            .get_protection_status(&ctx)
+            .await
+            .map(|status| status.to_u32() as libc::c_int)
+            .unwrap_or_log_default(&ctx, "Failed to get chat protection status")
+    })
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn dc_set_chat_visibility(
     context: *mut dc_context_t,
@@ -1075,6 +1453,7 @@ fn from_prim<S, T>(s: S) -> Option<T>
         0 => ChatVisibility::Normal,
         1 => ChatVisibility::Archived,
         2 => ChatVisibility::Pinned,
+        3 => ChatVisibility::NoUnarchive,
         _ => {
             warn!(
                 ctx,
@@ -1202,6 +1581,26 @@ fn from_prim<S, T>(s: S) -> Option<T>
     })
 }
 
+#[no_mangle]
+pub unsafe extern "C" fn dc_create_broadcast_list(
+    context: *mut dc_context_t,
+    name: *const libc::c_char,
+) -> u32 {
+    if context.is_null() || name.is_null() {
+        eprintln!("ignoring careless call to dc_create_broadcast_list()");
+        return 0;
+    }
+    let ctx = &*context;
+
+    block_on(async move {
+        chat::create_broadcast_list(&ctx, to_string_lossy(name))
+            .await
+            .log_err(ctx, "Failed to create broadcast list")
+            .map(|id| id.to_u32())
+            .unwrap_or(0)
+    })
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn dc_is_contact_in_chat(
     context: *mut dc_context_t,
@@ -1329,6 +1728,33 @@ fn from_prim<S, T>(s: S) -> Option<T>
     })
 }
 
+#[no_mangle]
+pub unsafe extern "C" fn dc_set_chat_notify_profile(
+    context: *mut dc_context_t,
+    chat_id: u32,
+    profile: libc::c_int,
+) -> libc::c_int {
+    if context.is_null() {
+        eprintln!("ignoring careless call to dc_set_chat_notify_profile()");
+        return 0;
+    }
+    let ctx = &*context;
+    let profile = if let Some(p) = NotifyProfile::from_i32(profile) {
+        p
+    } else {
+        warn!(ctx, "bad profile-value for dc_set_chat_notify_profile()");
+        return 0;
+    };
+
+    block_on(async move {
+        ChatId::new(chat_id)
+            .set_notify_profile(&ctx, profile)
+            .await
+            .map(|_| 1)
+            .unwrap_or_log_default(&ctx, "Failed to set notify profile")
+    })
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn dc_get_chat_encrinfo(
     context: *mut dc_context_t,
@@ -1423,6 +1849,23 @@ fn from_prim<S, T>(s: S) -> Option<T>
     block_on(MsgId::new(msg_id).get_html(&ctx)).strdup()
 }
 
+#[no_mangle]
+pub unsafe extern "C" fn dc_download_full_msg(context: *mut dc_context_t, msg_id: u32) {
+    if context.is_null() {
+        eprintln!("ignoring careless call to dc_download_full_msg()");
+        return;
+    }
+    let ctx = &*context;
+
+    block_on(async move {
+        MsgId::new(msg_id)
+            .download_full(&ctx)
+            .await
+            .log_err(ctx, "Failed to download message fully")
+            .ok();
+    })
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn dc_get_mime_headers(
     context: *mut dc_context_t,
@@ -1483,6 +1926,27 @@ fn from_prim<S, T>(s: S) -> Option<T>
     })
 }
 
+#[no_mangle]
+pub unsafe extern "C" fn dc_resend_msgs(
+    context: *mut dc_context_t,
+    msg_ids: *const u32,
+    msg_cnt: libc::c_int,
+) -> libc::c_int {
+    if context.is_null() || msg_ids.is_null() || msg_cnt <= 0 {
+        eprintln!("ignoring careless call to dc_resend_msgs()");
+        return 0;
+    }
+    let msg_ids = convert_and_prune_message_ids(msg_ids, msg_cnt);
+    let ctx = &*context;
+
+    block_on(async move {
+        chat::resend_msgs(&ctx, &msg_ids[..])
+            .await
+            .map(|_| 1)
+            .unwrap_or_log_default(&ctx, "Failed to resend messages")
+    })
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn dc_marknoticed_contact(context: *mut dc_context_t, contact_id: u32) {
     if context.is_null() {
@@ -1713,6 +2177,50 @@ fn from_prim<S, T>(s: S) -> Option<T>
     })
 }
 
+#[no_mangle]
+pub unsafe extern "C" fn dc_get_contact_verification_words(
+    context: *mut dc_context_t,
+    contact_id: u32,
+) -> *mut libc::c_char {
+    if context.is_null() {
+        eprintln!("ignoring careless call to dc_get_contact_verification_words()");
+        return ptr::null_mut();
+    }
+    let ctx = &*context;
+
+    block_on(async move {
+        match contact::get_verification_words(&ctx, contact_id).await {
+            Ok(Some(words)) => words.strdup(),
+            Ok(None) => ptr::null_mut(),
+            Err(err) => {
+                error!(&ctx, "{:#}", err);
+                ptr::null_mut()
+            }
+        }
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn dc_mark_contact_verified(
+    context: *mut dc_context_t,
+    contact_id: u32,
+    words: *const libc::c_char,
+) -> libc::c_int {
+    if context.is_null() || words.is_null() {
+        eprintln!("ignoring careless call to dc_mark_contact_verified()");
+        return 0;
+    }
+    let ctx = &*context;
+    let words = to_string_lossy(words);
+
+    block_on(async move {
+        contact::mark_verified_manually(&ctx, contact_id, &words)
+            .await
+            .log_err(ctx, "Cannot verify contact")
+            .is_ok() as libc::c_int
+    })
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn dc_delete_contact(
     context: *mut dc_context_t,
@@ -1783,6 +2291,41 @@ fn from_prim<S, T>(s: S) -> Option<T>
     }
 }
 
+#[no_mangle]
+pub unsafe extern "C" fn dc_import_eml(
+    context: *mut dc_context_t,
+    path: *const libc::c_char,
+) -> libc::c_int {
+    if context.is_null() || path.is_null() {
+        eprintln!("ignoring careless call to dc_import_eml()");
+        return 0;
+    }
+    let ctx = &*context;
+
+    block_on(async move {
+        imex::import_eml(&ctx, to_string_lossy(path))
+            .await
+            .log_err(ctx, "Cannot import message")
+            .unwrap_or_default() as libc::c_int
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn dc_import_mbox(context: *mut dc_context_t, path: *const libc::c_char) {
+    if context.is_null() || path.is_null() {
+        eprintln!("ignoring careless call to dc_import_mbox()");
+        return;
+    }
+    let ctx = &*context;
+    let path = to_string_lossy(path);
+
+    spawn(async move {
+        imex::import_mbox(&ctx, &path)
+            .await
+            .log_err(ctx, "Cannot import mbox")
+    });
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn dc_imex_has_backup(
     context: *mut dc_context_t,
@@ -1854,6 +2397,17 @@ fn from_prim<S, T>(s: S) -> Option<T>
     })
 }
 
+#[no_mangle]
+pub unsafe extern "C" fn dc_has_ongoing_key_transfer(context: *mut dc_context_t) -> libc::c_int {
+    if context.is_null() {
+        eprintln!("ignoring careless call to dc_has_ongoing_key_transfer()");
+        return 0;
+    }
+    let ctx = &*context;
+
+    imex::has_ongoing_key_transfer(&ctx) as libc::c_int
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn dc_stop_ongoing_process(context: *mut dc_context_t) {
     if context.is_null() {
@@ -1861,7 +2415,57 @@ fn from_prim<S, T>(s: S) -> Option<T>
         return;
     }
     let ctx = &*context;
-    block_on(ctx.stop_ongoing());
+    block_on(ctx.stop_ongoing_process());
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn dc_get_running_operations_json(
+    context: *mut dc_context_t,
+) -> *mut libc::c_char {
+    if context.is_null() {
+        eprintln!("ignoring careless call to dc_get_running_operations_json()");
+        return "".strdup();
+    }
+    let ctx = &*context;
+
+    let operations: Vec<_> = ctx
+        .get_running_operations()
+        .into_iter()
+        .map(|op| {
+            serde_json::json!({
+                "kind": op.kind as u32,
+                "started_at": op.started_at,
+                "progress": op.progress,
+                "cancellable": op.cancellable,
+            })
+        })
+        .collect();
+    serde_json::to_string(&operations)
+        .unwrap_or_log_default(
+            ctx,
+            "dc_get_running_operations_json() failed to serialise to json",
+        )
+        .strdup()
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn dc_cancel_operation(
+    context: *mut dc_context_t,
+    kind: libc::c_int,
+) -> libc::c_int {
+    if context.is_null() {
+        eprintln!("ignoring careless call to dc_cancel_operation()");
+        return 0;
+    }
+    let ctx = &*context;
+
+    match OperationKind::from_i32(kind) {
+        Some(kind) => ctx.cancel_operation(kind) as libc::c_int,
+        None => {
+            warn!(ctx, "bad kind-value for dc_cancel_operation()");
+            0
+        }
+    }
 }
 
 #[no_mangle]
@@ -1905,6 +2509,54 @@ fn from_prim<S, T>(s: S) -> Option<T>
     })
 }
 
+#[no_mangle]
+pub unsafe extern "C" fn dc_revoke_securejoin_qr(
+    context: *mut dc_context_t,
+    chat_id: u32,
+) -> libc::c_int {
+    if context.is_null() {
+        eprintln!("ignoring careless call to dc_revoke_securejoin_qr()");
+        return 0;
+    }
+    let ctx = &*context;
+    let chat_id = if chat_id == 0 {
+        None
+    } else {
+        Some(ChatId::new(chat_id))
+    };
+
+    block_on(async move {
+        securejoin::revoke_qr(&ctx, chat_id)
+            .await
+            .log_err(ctx, "Failed to revoke QR code")
+            .is_ok() as libc::c_int
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn dc_get_active_invite_count(
+    context: *mut dc_context_t,
+    chat_id: u32,
+) -> libc::c_int {
+    if context.is_null() {
+        eprintln!("ignoring careless call to dc_get_active_invite_count()");
+        return 0;
+    }
+    let ctx = &*context;
+    let chat_id = if chat_id == 0 {
+        None
+    } else {
+        Some(ChatId::new(chat_id))
+    };
+
+    block_on(async move {
+        securejoin::get_active_invite_count(&ctx, chat_id)
+            .await
+            .log_err(ctx, "Failed to count invites")
+            .unwrap_or_default() as libc::c_int
+    })
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn dc_join_securejoin(
     context: *mut dc_context_t,
@@ -2421,6 +3073,7 @@ pub struct ChatWrapper {
         ChatVisibility::Normal => 0,
         ChatVisibility::Archived => 1,
         ChatVisibility::Pinned => 2,
+        ChatVisibility::NoUnarchive => 3,
     }
 }
 
@@ -2494,6 +3147,16 @@ pub struct ChatWrapper {
     ffi_chat.chat.is_muted() as libc::c_int
 }
 
+#[no_mangle]
+pub unsafe extern "C" fn dc_chat_get_notify_profile(chat: *mut dc_chat_t) -> libc::c_int {
+    if chat.is_null() {
+        eprintln!("ignoring careless call to dc_chat_get_notify_profile()");
+        return 0;
+    }
+    let ffi_chat = &*chat;
+    ffi_chat.chat.get_notify_profile() as libc::c_int
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn dc_chat_get_remaining_mute_duration(chat: *mut dc_chat_t) -> i64 {
     if chat.is_null() {
@@ -2789,6 +3452,22 @@ pub struct MessageWrapper {
     ffi_msg.message.get_duration()
 }
 
+#[no_mangle]
+pub unsafe extern "C" fn dc_msg_get_waveform(msg: *mut dc_msg_t) -> *mut dc_array::dc_array_t {
+    if msg.is_null() {
+        eprintln!("ignoring careless call to dc_msg_get_waveform()");
+        return ptr::null_mut();
+    }
+    let ffi_msg = &*msg;
+    match ffi_msg.message.get_waveform() {
+        Some(waveform) => {
+            let waveform: Vec<u32> = waveform.into_iter().map(u32::from).collect();
+            Box::into_raw(Box::new(dc_array_t::from(waveform)))
+        }
+        None => ptr::null_mut(),
+    }
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn dc_msg_get_showpadlock(msg: *mut dc_msg_t) -> libc::c_int {
     if msg.is_null() {
@@ -2964,6 +3643,16 @@ pub struct MessageWrapper {
     ffi_msg.message.has_html().into()
 }
 
+#[no_mangle]
+pub unsafe extern "C" fn dc_msg_get_download_state(msg: *mut dc_msg_t) -> libc::c_int {
+    if msg.is_null() {
+        eprintln!("ignoring careless call to dc_msg_get_download_state()");
+        return 0;
+    }
+    let ffi_msg = &*msg;
+    ffi_msg.message.get_download_state() as libc::c_int
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn dc_msg_get_videochat_url(msg: *mut dc_msg_t) -> *mut libc::c_char {
     if msg.is_null() {
@@ -3365,6 +4054,21 @@ pub struct ContactWrapper {
     block_on(async move { ffi_contact.contact.is_verified(&ctx).await as libc::c_int })
 }
 
+#[no_mangle]
+pub unsafe extern "C" fn dc_contact_get_last_seen(contact: *mut dc_contact_t) -> i64 {
+    if contact.is_null() {
+        eprintln!("ignoring careless call to dc_contact_get_last_seen()");
+        return 0;
+    }
+    let ffi_contact = &*contact;
+    let ctx = &*ffi_contact.context;
+
+    block_on(ffi_contact.contact.last_seen(ctx))
+        .log_err(ctx, "Failed to get last seen time")
+        .unwrap_or_default()
+        .unwrap_or_default()
+}
+
 // dc_lot_t
 
 pub type dc_lot_t = lot::Lot;
@@ -3496,7 +4200,7 @@ fn convert_and_prune_message_ids(msg_ids: *const u32, msg_cnt: libc::c_int) -> V
 
 // dc_provider_t
 
-pub type dc_provider_t = provider::Provider;
+pub type dc_provider_t = provider::ProviderInfo;
 
 #[no_mangle]
 pub unsafe extern "C" fn dc_provider_new_from_email(
@@ -3508,10 +4212,14 @@ fn convert_and_prune_message_ids(msg_ids: *const u32, msg_cnt: libc::c_int) -> V
         return ptr::null();
     }
     let addr = to_string_lossy(addr);
-    match block_on(provider::get_provider_info(addr.as_str())) {
-        Some(provider) => provider,
-        None => ptr::null_mut(),
-    }
+    let ctx = &*context;
+    block_on(async move {
+        let socks5_enabled = ctx.get_config_bool(config::Config::Socks5Enabled).await;
+        match provider::get_provider_info(ctx, addr.as_str(), socks5_enabled).await {
+            Some(provider) => Box::into_raw(Box::new(provider)),
+            None => ptr::null_mut(),
+        }
+    })
 }
 
 #[no_mangle]
@@ -3523,7 +4231,7 @@ fn convert_and_prune_message_ids(msg_ids: *const u32, msg_cnt: libc::c_int) -> V
         return "".strdup();
     }
     let provider = &*provider;
-    provider.overview_page.strdup()
+    provider.overview_page().strdup()
 }
 
 #[no_mangle]
@@ -3535,7 +4243,7 @@ fn convert_and_prune_message_ids(msg_ids: *const u32, msg_cnt: libc::c_int) -> V
         return "".strdup();
     }
     let provider = &*provider;
-    provider.before_login_hint.strdup()
+    provider.before_login_hint().strdup()
 }
 
 #[no_mangle]
@@ -3545,7 +4253,7 @@ fn convert_and_prune_message_ids(msg_ids: *const u32, msg_cnt: libc::c_int) -> V
         return 0;
     }
     let provider = &*provider;
-    provider.status as libc::c_int
+    provider.status() as libc::c_int
 }
 
 #[no_mangle]
@@ -3554,8 +4262,7 @@ fn convert_and_prune_message_ids(msg_ids: *const u32, msg_cnt: libc::c_int) -> V
         eprintln!("ignoring careless call to dc_provider_unref()");
         return;
     }
-    // currently, there is nothing to free, the provider info is a static object.
-    // this may change once we start localizing string.
+    let _ = Box::from_raw(provider);
 }
 
 // -- Accounts
@@ -3762,6 +4469,32 @@ fn convert_and_prune_message_ids(msg_ids: *const u32, msg_cnt: libc::c_int) -> V
     block_on(accounts.maybe_network());
 }
 
+#[no_mangle]
+pub unsafe extern "C" fn dc_accounts_clear_caches(accounts: *mut dc_accounts_t) {
+    if accounts.is_null() {
+        eprintln!("ignoring careless call to dc_accounts_clear_caches()");
+        return;
+    }
+
+    let accounts = &*accounts;
+    block_on(accounts.clear_caches());
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn dc_accounts_perform_maintenance(
+    accounts: *mut dc_accounts_t,
+    budget_ms: libc::c_int,
+) {
+    if accounts.is_null() {
+        eprintln!("ignoring careless call to dc_accounts_perform_maintenance()");
+        return;
+    }
+
+    let accounts = &*accounts;
+    let budget = Duration::from_millis(budget_ms.max(0) as u64);
+    block_on(accounts.perform_maintenance_all(budget));
+}
+
 pub type dc_accounts_event_emitter_t = deltachat::accounts::EventEmitter;
 
 #[no_mangle]
@@ -331,6 +331,7 @@ pub async fn cmdline(context: Context, line: &str, chat_id: &mut ChatId) -> Resu
                  continue-key-transfer <msg-id> <setup-code>\n\
                  has-backup\n\
                  export-backup\n\
+                 export-backup-incremental\n\
                  import-backup <backup-file>\n\
                  export-keys\n\
                  import-keys\n\
@@ -460,6 +461,11 @@ pub async fn cmdline(context: Context, line: &str, chat_id: &mut ChatId) -> Resu
             imex(&context, ImexMode::ExportBackup, &dir).await?;
             println!("Exported to {}.", dir.to_string_lossy());
         }
+        "export-backup-incremental" => {
+            let dir = dirs::home_dir().unwrap_or_default();
+            imex(&context, ImexMode::ExportBackupIncremental, &dir).await?;
+            println!("Exported to {}.", dir.to_string_lossy());
+        }
         "import-backup" => {
             ensure!(!arg1.is_empty(), "Argument <backup-file> missing.");
             imex(&context, ImexMode::ImportBackup, arg1).await?;
@@ -493,7 +499,7 @@ pub async fn cmdline(context: Context, line: &str, chat_id: &mut ChatId) -> Resu
             reset_tables(&context, bits).await;
         }
         "stop" => {
-            context.stop_ongoing().await;
+            context.stop_ongoing_process().await;
         }
         "set" => {
             ensure!(!arg1.is_empty(), "Argument <key> missing.");
@@ -1153,15 +1159,24 @@ pub async fn cmdline(context: Context, line: &str, chat_id: &mut ChatId) -> Resu
         }
         "providerinfo" => {
             ensure!(!arg1.is_empty(), "Argument <addr> missing.");
-            match provider::get_provider_info(arg1).await {
+            match provider::get_provider_info(&context, arg1, false).await {
                 Some(info) => {
                     println!("Information for provider belonging to {}:", arg1);
-                    println!("status: {}", info.status as u32);
-                    println!("before_login_hint: {}", info.before_login_hint);
-                    println!("after_login_hint: {}", info.after_login_hint);
-                    println!("overview_page: {}", info.overview_page);
-                    for server in info.server.iter() {
-                        println!("server: {}:{}", server.hostname, server.port,);
+                    println!("status: {}", info.status() as u32);
+                    println!("before_login_hint: {}", info.before_login_hint());
+                    println!("after_login_hint: {}", info.after_login_hint());
+                    println!("overview_page: {}", info.overview_page());
+                    match info {
+                        provider::ProviderInfo::Builtin(provider) => {
+                            for server in provider.server.iter() {
+                                println!("server: {}:{}", server.hostname, server.port);
+                            }
+                        }
+                        provider::ProviderInfo::Override(provider) => {
+                            for server in provider.servers.iter() {
+                                println!("server: {}:{}", server.hostname, server.port);
+                            }
+                        }
                     }
                 }
                 None => {
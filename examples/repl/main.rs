@@ -147,6 +147,7 @@ fn complete(
     "continue-key-transfer",
     "has-backup",
     "export-backup",
+    "export-backup-incremental",
     "import-backup",
     "export-keys",
     "import-keys",
@@ -286,7 +286,7 @@ def is_outgoing(self):
         """Return True if Message is outgoing. """
         return self._msgstate in (
             const.DC_STATE_OUT_PREPARING, const.DC_STATE_OUT_PENDING,
-            const.DC_STATE_OUT_FAILED, const.DC_STATE_OUT_MDN_RCVD,
+            const.DC_STATE_OUT_SCHEDULED, const.DC_STATE_OUT_FAILED, const.DC_STATE_OUT_MDN_RCVD,
             const.DC_STATE_OUT_DELIVERED)
 
     def is_out_preparing(self):
@@ -299,6 +299,11 @@ def is_out_pending(self):
         """
         return self._msgstate == const.DC_STATE_OUT_PENDING
 
+    def is_out_scheduled(self):
+        """Return True if Message is outgoing, but is going to be sent at a later time.
+        """
+        return self._msgstate == const.DC_STATE_OUT_SCHEDULED
+
     def is_out_failed(self):
         """Return True if Message is unrecoverably failed.
         """
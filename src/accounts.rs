@@ -1,4 +1,5 @@
 use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
 
 use async_std::fs;
 use async_std::path::PathBuf;
@@ -9,8 +10,10 @@
 use anyhow::{ensure, Context as _, Result};
 use serde::{Deserialize, Serialize};
 
-use crate::context::Context;
-use crate::events::Event;
+use crate::context::{Context, ShutdownReport};
+use crate::events::{Event, EventType};
+use crate::maintenance::MaintenanceReport;
+use crate::storage_usage::StorageUsage;
 
 /// Account manager, that can handle multiple accounts in a single place.
 #[derive(Debug, Clone)]
@@ -18,6 +21,30 @@ pub struct Accounts {
     dir: PathBuf,
     config: Config,
     accounts: Arc<RwLock<BTreeMap<u32, Context>>>,
+    consistency: Arc<RwLock<AccountsConsistencyReport>>,
+}
+
+/// Inconsistencies between `accounts.toml` and the account directories,
+/// found when opening the accounts, see [`Accounts::get_consistency_report`].
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct AccountsConsistencyReport {
+    /// IDs of the accounts whose database is missing.
+    ///
+    /// These accounts are not loaded, but kept in `accounts.toml` marked as broken,
+    /// so they can be shown and removed using [`Accounts::remove_account`].
+    pub broken: Vec<u32>,
+
+    /// Names of the directories containing a database that do not belong to any account.
+    ///
+    /// These can be registered as accounts again using [`Accounts::adopt_orphan`].
+    pub orphans: Vec<String>,
+}
+
+impl AccountsConsistencyReport {
+    /// Returns true if no inconsistencies were found.
+    pub fn is_consistent(&self) -> bool {
+        self.broken.is_empty() && self.orphans.is_empty()
+    }
 }
 
 impl Accounts {
@@ -56,15 +83,59 @@ pub async fn open(dir: PathBuf) -> Result<Self> {
         ensure!(config_file.exists().await, "accounts.toml does not exist");
 
         let config = Config::from_file(config_file).await?;
+        let consistency = config.check_consistency(&dir).await?;
         let accounts = config.load_accounts().await?;
 
         Ok(Self {
             dir,
             config,
             accounts: Arc::new(RwLock::new(accounts)),
+            consistency: Arc::new(RwLock::new(consistency)),
         })
     }
 
+    /// Returns the inconsistencies found when opening the accounts
+    /// and not resolved since.
+    pub async fn get_consistency_report(&self) -> AccountsConsistencyReport {
+        self.consistency.read().await.clone()
+    }
+
+    /// Registers the orphaned account directory `dir_name` as a new account and selects it.
+    ///
+    /// `dir_name` must be one of [`AccountsConsistencyReport::orphans`].
+    pub async fn adopt_orphan(&self, dir_name: &str) -> Result<u32> {
+        ensure!(
+            self.consistency
+                .read()
+                .await
+                .orphans
+                .iter()
+                .any(|orphan| orphan == dir_name),
+            "not an orphaned account directory: {}",
+            dir_name
+        );
+        let account_config = self
+            .config
+            .add_existing_account(&self.dir, dir_name)
+            .await?;
+
+        let ctx = Context::new_with_log_id(
+            self.config.os_name().await,
+            account_config.dbfile().into(),
+            account_config.id,
+            Some(account_config.id.to_string()),
+        )
+        .await?;
+        self.accounts.write().await.insert(account_config.id, ctx);
+        self.consistency
+            .write()
+            .await
+            .orphans
+            .retain(|orphan| orphan != dir_name);
+
+        Ok(account_config.id)
+    }
+
     /// Get an account by its `id`:
     pub async fn get_account(&self, id: u32) -> Option<Context> {
         self.accounts.read().await.get(&id).cloned()
@@ -93,30 +164,91 @@ pub async fn add_account(&self) -> Result<u32> {
         let os_name = self.config.os_name().await;
         let account_config = self.config.new_account(&self.dir).await?;
 
-        let ctx = Context::new(os_name, account_config.dbfile().into(), account_config.id).await?;
+        let ctx = Context::new_with_log_id(
+            os_name,
+            account_config.dbfile().into(),
+            account_config.id,
+            Some(account_config.id.to_string()),
+        )
+        .await?;
         self.accounts.write().await.insert(account_config.id, ctx);
 
         Ok(account_config.id)
     }
 
     /// Remove an account.
+    ///
+    /// Accounts marked as broken, see [`AccountsConsistencyReport::broken`],
+    /// can be removed as well.
     pub async fn remove_account(&self, id: u32) -> Result<()> {
         let ctx = self.accounts.write().await.remove(&id);
-        ensure!(ctx.is_some(), "no account with this id: {}", id);
-        let ctx = ctx.unwrap();
-        ctx.stop_io().await;
-        drop(ctx);
+        let cfg = self.config.get_account(id).await;
+        match ctx {
+            Some(ctx) => {
+                ctx.stop_io().await;
+                drop(ctx);
+            }
+            None => ensure!(
+                cfg.as_ref().map_or(false, |cfg| cfg.broken),
+                "no account with this id: {}",
+                id
+            ),
+        }
 
-        if let Some(cfg) = self.config.get_account(id).await {
-            fs::remove_dir_all(async_std::path::PathBuf::from(&cfg.dir))
-                .await
-                .context("failed to remove account data")?;
+        if let Some(cfg) = cfg {
+            let dir = async_std::path::PathBuf::from(&cfg.dir);
+            if dir.exists().await {
+                fs::remove_dir_all(dir)
+                    .await
+                    .context("failed to remove account data")?;
+            }
         }
         self.config.remove_account(id).await?;
+        self.consistency
+            .write()
+            .await
+            .broken
+            .retain(|broken| *broken != id);
 
         Ok(())
     }
 
+    /// Closes the database of an account for maintenance, e.g. to replace the database file.
+    ///
+    /// IO of the account is stopped and its database is closed, other accounts keep running.
+    /// The account stays in the list of accounts, but all operations accessing its database
+    /// fail with [`crate::sql::Error::SqlNoConnection`] until the database is opened again
+    /// using [`Accounts::reopen_account_db`].
+    ///
+    /// Closing the database removes its `-wal` and `-shm` files, so only the database file
+    /// itself needs to be replaced.
+    pub async fn close_account_db(&self, id: u32) -> Result<()> {
+        let ctx = self
+            .get_account(id)
+            .await
+            .with_context(|| format!("no account with this id: {}", id))?;
+        ctx.stop_io().await;
+        ctx.sql.close().await;
+        info!(ctx, "Closed database for maintenance");
+        ctx.emit_event(EventType::DatabaseClosed);
+        Ok(())
+    }
+
+    /// Opens the database of an account closed by [`Accounts::close_account_db`] again.
+    ///
+    /// If the database file was replaced by one written by an older version, it is migrated.
+    /// IO is not started again, use [`Context::start_io`] for that.
+    pub async fn reopen_account_db(&self, id: u32) -> Result<()> {
+        let ctx = self
+            .get_account(id)
+            .await
+            .with_context(|| format!("no account with this id: {}", id))?;
+        ctx.sql.open(&ctx, ctx.get_dbfile(), false).await?;
+        info!(ctx, "Reopened database after maintenance");
+        ctx.emit_event(EventType::DatabaseReopened);
+        Ok(())
+    }
+
     /// Migrate an existing account into this structure.
     pub async fn migrate_account(&self, dbfile: PathBuf) -> Result<u32> {
         let blobdir = Context::derive_blobdir(&dbfile);
@@ -154,6 +286,7 @@ pub async fn migrate_account(&self, dbfile: PathBuf) -> Result<u32> {
                     new_dbfile,
                     new_blobdir,
                     account_config.id,
+                    Some(account_config.id.to_string()),
                 )
                 .await?;
                 self.accounts.write().await.insert(account_config.id, ctx);
@@ -181,7 +314,11 @@ pub async fn get_all(&self) -> Vec<u32> {
     }
 
     /// Import a backup using a new account and selects it.
+    ///
+    /// The backup is checked with [`check_backup`](crate::imex::check_backup) first, so
+    /// that no account is created for damaged or unsupported backups.
     pub async fn import_account(&self, file: PathBuf) -> Result<u32> {
+        crate::imex::check_backup(&file, &self.dir).await?;
         let old_id = self.config.get_selected_account().await;
 
         let id = self.add_account().await?;
@@ -199,13 +336,18 @@ pub async fn import_account(&self, file: PathBuf) -> Result<u32> {
         }
     }
 
+    /// Starts IO for all accounts, skipping accounts where it is already running
+    /// and accounts whose database is closed, see [`Accounts::close_account_db`].
     pub async fn start_io(&self) {
         let accounts = &*self.accounts.read().await;
         for account in accounts.values() {
-            account.start_io().await;
+            if account.sql.is_open().await {
+                account.start_io().await;
+            }
         }
     }
 
+    /// Stops IO for all accounts and waits until it has finished.
     pub async fn stop_io(&self) {
         let accounts = &*self.accounts.read().await;
         for account in accounts.values() {
@@ -213,6 +355,76 @@ pub async fn stop_io(&self) {
         }
     }
 
+    /// Shuts down all accounts concurrently, see [Context::shutdown].
+    pub async fn shutdown_all(&self, timeout: Duration) -> BTreeMap<u32, ShutdownReport> {
+        let accounts = &*self.accounts.read().await;
+        let reports =
+            futures::future::join_all(accounts.values().map(|account| account.shutdown(timeout)))
+                .await;
+        accounts.keys().copied().zip(reports).collect()
+    }
+
+    /// Performs maintenance for all accounts within `budget`, see [Context::perform_maintenance].
+    ///
+    /// The budget is split evenly, time left over by one account is passed on to the
+    /// following ones.  Accounts failing maintenance are left out of the result.
+    pub async fn perform_maintenance_all(
+        &self,
+        budget: Duration,
+    ) -> BTreeMap<u32, MaintenanceReport> {
+        let accounts = &*self.accounts.read().await;
+        let start = Instant::now();
+        let mut reports = BTreeMap::new();
+        for (i, (id, account)) in accounts.iter().enumerate() {
+            let remaining = budget.checked_sub(start.elapsed()).unwrap_or_default();
+            let share = remaining / (accounts.len() - i) as u32;
+            match account.perform_maintenance(share).await {
+                Ok(report) => {
+                    reports.insert(*id, report);
+                }
+                Err(err) => warn!(account, "Maintenance failed: {}", err),
+            }
+        }
+        reports
+    }
+
+    /// Marks the fresh messages of all chats of all accounts as noticed,
+    /// see [crate::chat::marknoticed_all].
+    pub async fn marknoticed_all(&self) -> Result<()> {
+        let accounts = &*self.accounts.read().await;
+        futures::future::try_join_all(accounts.values().map(crate::chat::marknoticed_all)).await?;
+        Ok(())
+    }
+
+    /// Returns the number of notified fresh messages of all accounts, to be shown as app badge,
+    /// see [crate::notification::get_badge_cnt].
+    pub async fn get_badge_cnt(&self) -> Result<usize> {
+        let accounts = &*self.accounts.read().await;
+        let cnts = futures::future::try_join_all(
+            accounts.values().map(crate::notification::get_badge_cnt),
+        )
+        .await?;
+        Ok(cnts.into_iter().sum())
+    }
+
+    /// Returns the disk space used by each account,
+    /// see [Context::get_storage_usage].
+    ///
+    /// Accounts failing the scan, e.g. because it was canceled, are left out of the result.
+    pub async fn get_storage_usage_all(&self) -> BTreeMap<u32, StorageUsage> {
+        let accounts = &*self.accounts.read().await;
+        let mut usages = BTreeMap::new();
+        for (id, account) in accounts.iter() {
+            match account.get_storage_usage().await {
+                Ok(usage) => {
+                    usages.insert(*id, usage);
+                }
+                Err(err) => warn!(account, "Failed to get storage usage: {}", err),
+            }
+        }
+        usages
+    }
+
     pub async fn maybe_network(&self) {
         let accounts = &*self.accounts.read().await;
         for account in accounts.values() {
@@ -220,6 +432,15 @@ pub async fn maybe_network(&self) {
         }
     }
 
+    /// Drops the chats and contacts kept in memory for all accounts,
+    /// see [Context::clear_caches].
+    pub async fn clear_caches(&self) {
+        let accounts = &*self.accounts.read().await;
+        for account in accounts.values() {
+            account.clear_caches();
+        }
+    }
+
     /// Unified event emitter.
     pub async fn get_event_emitter(&self) -> EventEmitter {
         let emitters: Vec<_> = self
@@ -320,14 +541,16 @@ pub async fn from_file(file: PathBuf) -> Result<Self> {
         })
     }
 
+    /// Loads all accounts not marked as broken.
     pub async fn load_accounts(&self) -> Result<BTreeMap<u32, Context>> {
         let cfg = &*self.inner.read().await;
         let mut accounts = BTreeMap::new();
-        for account_config in &cfg.accounts {
-            let ctx = Context::new(
+        for account_config in cfg.accounts.iter().filter(|account| !account.broken) {
+            let ctx = Context::new_with_log_id(
                 cfg.os_name.clone(),
                 account_config.dbfile().into(),
                 account_config.id,
+                Some(account_config.id.to_string()),
             )
             .await?;
             accounts.insert(account_config.id, ctx);
@@ -336,6 +559,90 @@ pub async fn load_accounts(&self) -> Result<BTreeMap<u32, Context>> {
         Ok(accounts)
     }
 
+    /// Checks the accounts against the account directories in the root directory `dir`.
+    ///
+    /// Accounts without database are marked as broken, the marks of accounts whose database
+    /// showed up again are removed.  If the selected account is broken, another one is
+    /// selected.  Nothing is deleted.
+    async fn check_consistency(&self, dir: &PathBuf) -> Result<AccountsConsistencyReport> {
+        let mut report = AccountsConsistencyReport::default();
+        let mut changed = false;
+        let mut account_dirs = Vec::new();
+        {
+            let inner = &mut *self.inner.write().await;
+            for account in &mut inner.accounts {
+                let broken = !PathBuf::from(account.dbfile()).exists().await;
+                if broken != account.broken {
+                    account.broken = broken;
+                    changed = true;
+                }
+                if broken {
+                    report.broken.push(account.id);
+                }
+                account_dirs.push(account.dir.clone());
+            }
+
+            let selected_broken = inner
+                .accounts
+                .iter()
+                .any(|account| account.id == inner.selected_account && account.broken);
+            if selected_broken {
+                if let Some(account) = inner.accounts.iter().find(|account| !account.broken) {
+                    inner.selected_account = account.id;
+                    changed = true;
+                }
+            }
+        }
+
+        let mut entries = fs::read_dir(dir)
+            .await
+            .context("failed to read accounts directory")?;
+        while let Some(entry) = entries.next().await {
+            let path = entry?.path();
+            if !path.is_dir().await || !path.join(DB_NAME).exists().await {
+                continue;
+            }
+            let std_path: std::path::PathBuf = path.clone().into();
+            if account_dirs.contains(&std_path) {
+                continue;
+            }
+            if let Some(name) = path.file_name().and_then(|name| name.to_str()) {
+                report.orphans.push(name.to_string());
+            }
+        }
+        report.orphans.sort();
+
+        if changed {
+            self.sync().await?;
+        }
+        Ok(report)
+    }
+
+    /// Registers the existing account directory `dir_name` in the root directory `dir`
+    /// as a new account and selects it.
+    async fn add_existing_account(&self, dir: &PathBuf, dir_name: &str) -> Result<AccountConfig> {
+        let id = {
+            let inner = &mut self.inner.write().await;
+            let id = inner.next_id;
+            let uuid = Uuid::parse_str(dir_name).unwrap_or_else(|_| Uuid::new_v4());
+
+            inner.accounts.push(AccountConfig {
+                id,
+                dir: dir.join(dir_name).into(),
+                uuid,
+                broken: false,
+            });
+            inner.next_id += 1;
+            id
+        };
+
+        self.sync().await?;
+
+        self.select_account(id).await.expect("just added");
+        let cfg = self.get_account(id).await.expect("just added");
+        Ok(cfg)
+    }
+
     /// Create a new account in the given root directory.
     pub async fn new_account(&self, dir: &PathBuf) -> Result<AccountConfig> {
         let id = {
@@ -348,6 +655,7 @@ pub async fn new_account(&self, dir: &PathBuf) -> Result<AccountConfig> {
                 id,
                 dir: target_dir.into(),
                 uuid,
+                broken: false,
             });
             inner.next_id += 1;
             id
@@ -415,6 +723,10 @@ pub struct AccountConfig {
     /// Root directory for all data for this account.
     pub dir: std::path::PathBuf,
     pub uuid: Uuid,
+
+    /// Set if the database of the account is missing, such accounts are not loaded.
+    #[serde(default)]
+    pub broken: bool,
 }
 
 impl AccountConfig {
@@ -428,6 +740,15 @@ pub fn dbfile(&self) -> std::path::PathBuf {
 mod tests {
     use super::*;
 
+    use std::collections::BTreeSet;
+
+    use crate::chat::{self, ChatId};
+    use crate::config::Config as ContextConfig;
+    use crate::contact::Contact;
+    use crate::events::EventType;
+    use crate::ongoing::OperationKind;
+    use crate::test_utils::receive_chat_msg;
+
     #[async_std::test]
     async fn test_account_new_open() {
         let dir = tempfile::tempdir().unwrap();
@@ -506,6 +827,216 @@ async fn test_migrate_account() {
         );
     }
 
+    #[async_std::test]
+    async fn test_consistency_check() {
+        let dir = tempfile::tempdir().unwrap();
+        let p: PathBuf = dir.path().join("accounts").into();
+
+        let accounts = Accounts::new("my_os".into(), p.clone()).await.unwrap();
+        assert!(accounts.get_consistency_report().await.is_consistent());
+        let id = accounts.add_account().await.unwrap();
+        assert_eq!(id, 2);
+        let broken_dir = accounts.config.get_account(2).await.unwrap().dir;
+        drop(accounts);
+
+        // Database of the selected account is lost.
+        fs::remove_dir_all(PathBuf::from(&broken_dir))
+            .await
+            .unwrap();
+
+        // Account directory not listed in accounts.toml.
+        let orphan_dir = p.join("orphan");
+        fs::create_dir(&orphan_dir).await.unwrap();
+        let ctx = Context::new("my_os".into(), orphan_dir.join(DB_NAME), 0)
+            .await
+            .unwrap();
+        ctx.set_config(ContextConfig::Addr, Some("me@mail.com"))
+            .await
+            .unwrap();
+        drop(ctx);
+
+        let accounts = Accounts::open(p.clone()).await.unwrap();
+        assert_eq!(
+            accounts.get_consistency_report().await,
+            AccountsConsistencyReport {
+                broken: vec![2],
+                orphans: vec!["orphan".to_string()],
+            }
+        );
+        assert_eq!(accounts.get_all().await, vec![1]);
+        assert!(accounts.config.get_account(2).await.unwrap().broken);
+        assert_eq!(accounts.config.get_selected_account().await, 1);
+        assert!(!broken_dir.exists());
+
+        assert!(accounts.adopt_orphan("unknown").await.is_err());
+        let id = accounts.adopt_orphan("orphan").await.unwrap();
+        assert_eq!(id, 3);
+        assert_eq!(accounts.config.get_selected_account().await, 3);
+        let ctx = accounts.get_account(3).await.unwrap();
+        assert_eq!(
+            ctx.get_config(ContextConfig::Addr).await.unwrap().unwrap(),
+            "me@mail.com"
+        );
+        assert!(accounts.get_consistency_report().await.orphans.is_empty());
+        drop(ctx);
+        drop(accounts);
+
+        // The broken account is kept until it is removed explicitly.
+        let accounts = Accounts::open(p).await.unwrap();
+        assert_eq!(
+            accounts.get_consistency_report().await,
+            AccountsConsistencyReport {
+                broken: vec![2],
+                orphans: Vec::new(),
+            }
+        );
+        assert_eq!(accounts.get_all().await, vec![1, 3]);
+        accounts.remove_account(2).await.unwrap();
+        assert!(accounts.get_consistency_report().await.is_consistent());
+        assert!(accounts.config.get_account(2).await.is_none());
+    }
+
+    #[async_std::test]
+    async fn test_import_account_cancel() {
+        use crate::events::EventType;
+        use crate::imex::{has_backup, imex, ImexMode};
+        use crate::test_utils::TestContext;
+
+        let alice = TestContext::new_alice().await;
+        for i in 0..500 {
+            fs::write(
+                alice.get_blobdir().join(format!("blob-{}", i)),
+                vec![i as u8; 20_000],
+            )
+            .await
+            .unwrap();
+        }
+        let backup_dir = tempfile::tempdir().unwrap();
+        let backup_dir: PathBuf = backup_dir.path().to_path_buf().into();
+        imex(&alice, ImexMode::ExportBackup, &backup_dir)
+            .await
+            .unwrap();
+        let backup: PathBuf = has_backup(&alice, &backup_dir).await.unwrap().into();
+
+        let dir = tempfile::tempdir().unwrap();
+        let p: PathBuf = dir.path().join("accounts").into();
+        let accounts = Accounts::new("my_os".into(), p.clone()).await.unwrap();
+        let ids_before = accounts.get_all().await;
+        let mut files_before: Vec<_> = fs::read_dir(&p)
+            .await
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect()
+            .await;
+        files_before.sort();
+
+        let import = async_std::task::spawn({
+            let accounts = accounts.clone();
+            async move { accounts.import_account(backup).await }
+        });
+
+        // Cancel once the backup is verified and the account is being replaced.
+        let ctx = loop {
+            if let Some(id) = accounts
+                .get_all()
+                .await
+                .into_iter()
+                .find(|id| !ids_before.contains(id))
+            {
+                break accounts.get_account(id).await.unwrap();
+            }
+            async_std::task::sleep(std::time::Duration::from_millis(1)).await;
+        };
+        let events = ctx.get_event_emitter();
+        while let Some(event) = events.recv().await {
+            if let EventType::ImexProgress(progress) = event.typ {
+                if progress >= 500 {
+                    break;
+                }
+            }
+        }
+        ctx.stop_ongoing_process().await;
+
+        assert!(import.await.is_err());
+        assert_eq!(accounts.get_all().await, ids_before);
+        assert_eq!(accounts.config.get_selected_account().await, 1);
+        let mut files_after: Vec<_> = fs::read_dir(&p)
+            .await
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect()
+            .await;
+        files_after.sort();
+        assert_eq!(files_before, files_after);
+    }
+
+    #[async_std::test]
+    async fn test_close_reopen_account_db() {
+        let dir = tempfile::tempdir().unwrap();
+        let p: PathBuf = dir.path().join("accounts").into();
+        let accounts = Accounts::new("my_os".into(), p).await.unwrap();
+        let other_id = accounts.add_account().await.unwrap();
+        let id = 1;
+        let ctx = accounts.get_account(id).await.unwrap();
+        let other = accounts.get_account(other_id).await.unwrap();
+        ctx.set_config(ContextConfig::Displayname, Some("before"))
+            .await
+            .unwrap();
+        other
+            .set_config(ContextConfig::Displayname, Some("other"))
+            .await
+            .unwrap();
+
+        let fixture_dbfile: PathBuf = dir.path().join("fixture.db").into();
+        let fixture = Context::new("my_os".into(), fixture_dbfile.clone(), 0)
+            .await
+            .unwrap();
+        fixture
+            .set_config(ContextConfig::Displayname, Some("replaced"))
+            .await
+            .unwrap();
+        drop(fixture);
+
+        let events = ctx.get_event_emitter();
+        accounts.close_account_db(id).await.unwrap();
+        assert!(accounts.get_all().await.contains(&id));
+        let closed = accounts.get_account(id).await.unwrap();
+        assert!(matches!(
+            closed.sql.try_get_raw_config("displayname").await,
+            Err(crate::sql::Error::SqlNoConnection)
+        ));
+        assert!(closed
+            .set_config(ContextConfig::Displayname, Some("ignored"))
+            .await
+            .is_err());
+        assert_eq!(
+            other.get_config(ContextConfig::Displayname).await,
+            Some("other".to_string())
+        );
+
+        fs::copy(&fixture_dbfile, ctx.get_dbfile()).await.unwrap();
+        accounts.reopen_account_db(id).await.unwrap();
+        assert_eq!(
+            ctx.get_config(ContextConfig::Displayname).await,
+            Some("replaced".to_string())
+        );
+        assert_eq!(
+            other.get_config(ContextConfig::Displayname).await,
+            Some("other".to_string())
+        );
+        assert!(accounts.reopen_account_db(id).await.is_err());
+
+        let mut closed_event = false;
+        while let Some(event) = events.recv().await {
+            match event.typ {
+                EventType::DatabaseClosed => closed_event = true,
+                EventType::DatabaseReopened => break,
+                _ => {}
+            }
+        }
+        assert!(closed_event);
+    }
+
     /// Tests that accounts are sorted by ID.
     #[async_std::test]
     async fn test_accounts_sorted() {
@@ -524,4 +1055,101 @@ async fn test_accounts_sorted() {
             assert_eq!(ids.get(i), Some(&expected_id));
         }
     }
+
+    async fn count_fresh_msgs(context: &Context) -> i32 {
+        context
+            .sql
+            .query_get_value(
+                context,
+                "SELECT COUNT(*) FROM msgs WHERE state=10;",
+                paramsv![],
+            )
+            .await
+            .unwrap_or_default()
+    }
+
+    #[async_std::test]
+    async fn test_marknoticed_all() {
+        let dir = tempfile::tempdir().unwrap();
+        let p: PathBuf = dir.path().join("accounts").into();
+        let accounts = Accounts::new("my_os".into(), p).await.unwrap();
+        accounts.add_account().await.unwrap();
+
+        let mut expected = BTreeSet::new();
+        for id in accounts.get_all().await {
+            let ctx = accounts.get_account(id).await.unwrap();
+            for (key, value) in &[
+                (ContextConfig::ConfiguredAddr, "alice@example.com"),
+                (ContextConfig::Configured, "1"),
+            ] {
+                ctx.set_config(*key, Some(*value)).await.unwrap();
+            }
+            for (uid, addr) in [(1, "bob@example.net"), (2, "claire@example.org")].iter() {
+                let contact_id = Contact::create(&ctx, "", addr).await.unwrap();
+                let chat_id = chat::create_by_contact_id(&ctx, contact_id).await.unwrap();
+                receive_chat_msg(&ctx, addr, *uid).await;
+                assert_eq!(chat_id.get_fresh_msg_cnt(&ctx).await, 1);
+                expected.insert((id, chat_id));
+            }
+            // a contact request
+            receive_chat_msg(&ctx, "unknown@example.org", 3).await;
+            assert_eq!(count_fresh_msgs(&ctx).await, 3);
+        }
+        assert_eq!(expected.len(), 4);
+
+        let mut emitter = accounts.get_event_emitter().await;
+        accounts.marknoticed_all().await.unwrap();
+        for id in accounts.get_all().await {
+            let ctx = accounts.get_account(id).await.unwrap();
+            // only the contact request is still fresh
+            assert_eq!(count_fresh_msgs(&ctx).await, 1);
+            ctx.emit_event(EventType::MsgsNoticed(ChatId::new(0)));
+        }
+
+        // collect events until both accounts emitted the marker above
+        let mut noticed = BTreeSet::new();
+        let mut markers = 0;
+        while markers < 2 {
+            let event = emitter.recv().await.unwrap();
+            if let EventType::MsgsNoticed(chat_id) = event.typ {
+                if chat_id.is_unset() {
+                    markers += 1;
+                } else {
+                    assert!(noticed.insert((event.id, chat_id)));
+                }
+            }
+        }
+        assert_eq!(noticed, expected);
+    }
+
+    #[async_std::test]
+    async fn test_get_storage_usage_all() {
+        let dir = tempfile::tempdir().unwrap();
+        let p: PathBuf = dir.path().join("accounts").into();
+        let accounts = Accounts::new("my_os".into(), p).await.unwrap();
+        let id = accounts.add_account().await.unwrap();
+
+        let ctx = accounts.get_account(id).await.unwrap();
+        fs::write(ctx.get_blobdir().join("orphan.bin"), vec![0u8; 42])
+            .await
+            .unwrap();
+
+        let usages = accounts.get_storage_usage_all().await;
+        assert_eq!(
+            usages.keys().copied().collect::<Vec<_>>(),
+            accounts.get_all().await
+        );
+        assert_eq!(usages.get(&id).unwrap().other_bytes, 42);
+        assert!(usages.values().all(|usage| usage.db_bytes > 0));
+
+        // An account failing the scan does not hide the others.
+        let busy_id = accounts.add_account().await.unwrap();
+        let busy = accounts.get_account(busy_id).await.unwrap();
+        let _guard = busy
+            .start_operation(OperationKind::StorageUsage, true)
+            .unwrap();
+        let usages = accounts.get_storage_usage_all().await;
+        assert!(usages.get(&busy_id).is_none());
+        assert_eq!(usages.get(&id).unwrap().other_bytes, 42);
+    }
 }
@@ -21,6 +21,9 @@
 use crate::events::EventType;
 use crate::message;
 
+/// Quality used when recoding images to JPEG, the default of the `image` crate.
+const JPEG_QUALITY: u8 = 75;
+
 /// Represents a file in the blob directory.
 ///
 /// The object has a name, which will always be valid UTF-8.  Having a
@@ -76,6 +79,7 @@ pub async fn create(
             blobdir,
             name: format!("$BLOBDIR/{}", name),
         };
+        context.stats_cache.blob_added(data.len() as u64);
         context.emit_event(EventType::NewBlobFile(blob.as_name().to_string()));
         Ok(blob)
     }
@@ -147,19 +151,22 @@ pub async fn create_and_copy(
         let (name, mut dst_file) =
             BlobObject::create_new_file(context.get_blobdir(), &stem, &ext).await?;
         let name_for_err = name.clone();
-        if let Err(err) = io::copy(&mut src_file, &mut dst_file).await {
-            {
-                // Attempt to remove the failed file, swallow errors resulting from that.
-                let path = context.get_blobdir().join(&name_for_err);
-                fs::remove_file(path).await.ok();
+        let bytes = match io::copy(&mut src_file, &mut dst_file).await {
+            Ok(bytes) => bytes,
+            Err(err) => {
+                {
+                    // Attempt to remove the failed file, swallow errors resulting from that.
+                    let path = context.get_blobdir().join(&name_for_err);
+                    fs::remove_file(path).await.ok();
+                }
+                return Err(BlobError::CopyFailure {
+                    blobdir: context.get_blobdir().to_path_buf(),
+                    blobname: name_for_err,
+                    src: src.as_ref().to_path_buf(),
+                    cause: err,
+                });
             }
-            return Err(BlobError::CopyFailure {
-                blobdir: context.get_blobdir().to_path_buf(),
-                blobname: name_for_err,
-                src: src.as_ref().to_path_buf(),
-                cause: err,
-            });
-        }
+        };
 
         // workaround, see create() for details
         let _ = dst_file.flush().await;
@@ -168,6 +175,7 @@ pub async fn create_and_copy(
             blobdir: context.get_blobdir(),
             name: format!("$BLOBDIR/{}", name),
         };
+        context.stats_cache.blob_added(bytes);
         context.emit_event(EventType::NewBlobFile(blob.as_name().to_string()));
         Ok(blob)
     }
@@ -394,23 +402,104 @@ pub async fn recode_to_avatar_size(&self, context: &Context) -> Result<(), BlobE
         self.recode_to_size(context, blob_abs, img_wh).await
     }
 
-    pub async fn recode_to_image_size(&self, context: &Context) -> Result<(), BlobError> {
-        let blob_abs = self.to_abs_path();
-        if message::guess_msgtype_from_suffix(Path::new(&blob_abs))
-            != Some((Viewtype::Image, "image/jpeg"))
-        {
-            return Ok(());
+    /// Creates a blob and recodes it for sending as an image.
+    ///
+    /// The data is copied into the blob directory as by [BlobObject::new_from_path] or
+    /// [BlobObject::create].  Images larger than the bounds of `quality`, images rotated
+    /// by their EXIF orientation and images carrying any EXIF data are recoded to JPEG:
+    /// the orientation is applied and all metadata, e.g. the GPS position, is stripped.
+    /// If the image was not a JPEG before, the recoded image is written to a new blob
+    /// with a `.jpg` extension.
+    ///
+    /// Files not looking like images by their extension, including GIFs, are not
+    /// touched.  Stickers should not be passed to this function at all.
+    ///
+    /// # Errors
+    ///
+    /// In addition to the errors of [BlobObject::create] and
+    /// [BlobObject::new_from_path] the [BlobError::RecodeFailure] is used when the
+    /// image can not be decoded.
+    pub async fn create_and_recode(
+        context: &'a Context,
+        src: BlobSource<'_>,
+        quality: MediaQuality,
+    ) -> Result<RecodedBlob<'a>, BlobError> {
+        let blob = match src {
+            BlobSource::Path(path) => BlobObject::new_from_path(context, path).await?,
+            BlobSource::Bytes { name, data } => BlobObject::create(context, name, data).await?,
+        };
+        if !matches!(
+            message::guess_msgtype_from_suffix(Path::new(&blob.name)),
+            Some((Viewtype::Image, _))
+        ) {
+            return Ok(RecodedBlob {
+                blob,
+                dimensions: None,
+            });
         }
 
-        let img_wh =
-            match MediaQuality::from_i32(context.get_config_int(Config::MediaQuality).await)
-                .unwrap_or_default()
-            {
-                MediaQuality::Balanced => BALANCED_IMAGE_SIZE,
-                MediaQuality::Worse => WORSE_IMAGE_SIZE,
-            };
+        let blob_abs = blob.to_abs_path();
+        let mut img = image::open(&blob_abs).map_err(|err| BlobError::RecodeFailure {
+            blobdir: context.get_blobdir().to_path_buf(),
+            blobname: blob.name.clone(),
+            cause: err,
+        })?;
+        let (has_exif, orientation) = match blob.get_exif_orientation(context) {
+            Ok(orientation) => (true, orientation),
+            Err(_) => (false, 0),
+        };
+        let img_wh = match quality {
+            MediaQuality::Balanced => BALANCED_IMAGE_SIZE,
+            MediaQuality::Worse => WORSE_IMAGE_SIZE,
+        };
+        let do_scale = img.width() > img_wh || img.height() > img_wh;
+        if !do_scale && !has_exif {
+            let dimensions = Some((img.width(), img.height()));
+            return Ok(RecodedBlob { blob, dimensions });
+        }
 
-        self.recode_to_size(context, blob_abs, img_wh).await
+        if do_scale {
+            img = img.thumbnail(img_wh, img_wh);
+        }
+        img = match orientation {
+            90 => img.rotate90(),
+            180 => img.rotate180(),
+            270 => img.rotate270(),
+            _ => img,
+        };
+        if img.color().has_alpha() {
+            img = image::DynamicImage::ImageRgb8(img.to_rgb8());
+        }
+        let dimensions = Some((img.width(), img.height()));
+
+        // The encoder does not write any metadata, so EXIF data is dropped here.
+        let mut encoded = Vec::new();
+        img.write_to(&mut encoded, image::ImageOutputFormat::Jpeg(JPEG_QUALITY))
+            .map_err(|err| BlobError::WriteFailure {
+                blobdir: context.get_blobdir().to_path_buf(),
+                blobname: blob.name.clone(),
+                cause: err.into(),
+            })?;
+        let blob = if message::guess_msgtype_from_suffix(Path::new(&blob.name))
+            == Some((Viewtype::Image, "image/jpeg"))
+        {
+            fs::write(&blob_abs, &encoded)
+                .await
+                .map_err(|err| BlobError::WriteFailure {
+                    blobdir: context.get_blobdir().to_path_buf(),
+                    blobname: blob.name.clone(),
+                    cause: err.into(),
+                })?;
+            blob
+        } else {
+            let stem = Path::new(&blob.name)
+                .file_stem()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .to_string();
+            BlobObject::create(context, format!("{}.jpg", stem), &encoded).await?
+        };
+        Ok(RecodedBlob { blob, dimensions })
     }
 
     async fn recode_to_size(
@@ -472,6 +561,24 @@ pub fn get_exif_orientation(&self, context: &Context) -> Result<i32, Error> {
     }
 }
 
+/// Source of the data for [BlobObject::create_and_recode].
+#[derive(Debug, Clone, Copy)]
+pub enum BlobSource<'s> {
+    /// A file, copied into the blob directory unless it is already a blob.
+    Path(&'s Path),
+    /// Data in memory, the blob name is derived from `name`.
+    Bytes { name: &'s str, data: &'s [u8] },
+}
+
+/// A blob created by [BlobObject::create_and_recode].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecodedBlob<'a> {
+    pub blob: BlobObject<'a>,
+
+    /// Width and height of the blob if it is an image.
+    pub dimensions: Option<(u32, u32)>,
+}
+
 impl<'a> fmt::Display for BlobObject<'a> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "$BLOBDIR/{}", self.name)
@@ -633,6 +740,101 @@ async fn test_create_and_copy() {
         assert!(!whoops.exists().await);
     }
 
+    fn assert_no_exif(blob: &BlobObject) {
+        let file = std::fs::File::open(blob.to_abs_path()).unwrap();
+        let mut bufreader = std::io::BufReader::new(&file);
+        assert!(exif::Reader::new()
+            .read_from_container(&mut bufreader)
+            .is_err());
+    }
+
+    #[async_std::test]
+    async fn test_create_and_recode_landscape() {
+        let t = TestContext::new().await;
+        let data = include_bytes!("../test-data/image/landscape2000x1000.jpg");
+        let src = BlobSource::Bytes {
+            name: "landscape.jpg",
+            data,
+        };
+        let recoded = BlobObject::create_and_recode(&t, src, MediaQuality::Balanced)
+            .await
+            .unwrap();
+        assert_eq!(recoded.dimensions, Some((1280, 640)));
+        assert!(recoded.blob.as_name().ends_with(".jpg"));
+        let img = image::open(recoded.blob.to_abs_path()).unwrap();
+        assert_eq!(img.dimensions(), (1280, 640));
+        assert_no_exif(&recoded.blob);
+    }
+
+    #[async_std::test]
+    async fn test_create_and_recode_portrait() {
+        let t = TestContext::new().await;
+        let src = Path::new("test-data/image/portrait1000x2000.jpg");
+        let recoded = BlobObject::create_and_recode(&t, BlobSource::Path(src), MediaQuality::Worse)
+            .await
+            .unwrap();
+        assert_eq!(recoded.dimensions, Some((320, 640)));
+        let img = image::open(recoded.blob.to_abs_path()).unwrap();
+        assert_eq!(img.dimensions(), (320, 640));
+
+        // The source file is not modified.
+        let img = image::open(src).unwrap();
+        assert_eq!(img.dimensions(), (1000, 2000));
+    }
+
+    #[async_std::test]
+    async fn test_create_and_recode_rotated() {
+        let t = TestContext::new().await;
+        let src = Path::new("test-data/image/rotated2000x1000.jpg");
+        let recoded =
+            BlobObject::create_and_recode(&t, BlobSource::Path(src), MediaQuality::Balanced)
+                .await
+                .unwrap();
+        assert_eq!(recoded.dimensions, Some((640, 1280)));
+        assert_no_exif(&recoded.blob);
+
+        // The left half of the fixture is dark, after rotating by 90 degrees it is on top.
+        let img = image::open(recoded.blob.to_abs_path()).unwrap().to_luma8();
+        assert_eq!(img.dimensions(), (640, 1280));
+        assert!(img.get_pixel(320, 10).0[0] < 100);
+        assert!(img.get_pixel(320, 1270).0[0] > 150);
+    }
+
+    #[async_std::test]
+    async fn test_create_and_recode_passthrough() {
+        let t = TestContext::new().await;
+        let data = include_bytes!("../test-data/image/image100x50.gif");
+        let src = BlobSource::Bytes {
+            name: "anim.gif",
+            data,
+        };
+        let recoded = BlobObject::create_and_recode(&t, src, MediaQuality::Worse)
+            .await
+            .unwrap();
+        assert_eq!(recoded.dimensions, None);
+        assert_eq!(recoded.blob.as_name(), "$BLOBDIR/anim.gif");
+        assert_eq!(
+            fs::read(recoded.blob.to_abs_path()).await.unwrap(),
+            data.to_vec()
+        );
+
+        // Small images without metadata are not recoded either.
+        let data = include_bytes!("../test-data/image/avatar64x64.png");
+        let src = BlobSource::Bytes {
+            name: "small.png",
+            data,
+        };
+        let recoded = BlobObject::create_and_recode(&t, src, MediaQuality::Worse)
+            .await
+            .unwrap();
+        assert_eq!(recoded.dimensions, Some((64, 64)));
+        assert_eq!(recoded.blob.as_name(), "$BLOBDIR/small.png");
+        assert_eq!(
+            fs::read(recoded.blob.to_abs_path()).await.unwrap(),
+            data.to_vec()
+        );
+    }
+
     #[async_std::test]
     async fn test_create_from_path() {
         let t = TestContext::new().await;
@@ -0,0 +1,296 @@
+//! # Broadcast lists
+//!
+//! A broadcast list is a chat of type [Chattype::Broadcast] created by
+//! [chat::create_broadcast_list].  Messages sent to it are stored once in the broadcast
+//! chat, but are sent out as a separate copy to each member, so members do not see each
+//! other and their replies end up in the 1:1 chats.
+//!
+//! The delivery state of each copy is tracked in the `msgs_broadcast` table,
+//! the message itself is delivered once all copies are delivered.
+//!
+//! [Chattype::Broadcast]: crate::constants::Chattype::Broadcast
+//! [chat::create_broadcast_list]: crate::chat::create_broadcast_list
+
+use anyhow::Result;
+
+use crate::context::Context;
+use crate::message::{MessageState, MsgId};
+use crate::sql;
+
+/// Returns the delivery state of the copy of a broadcast message sent to a member,
+/// `None` if no copy was sent to the member yet.
+pub(crate) async fn get_state(
+    context: &Context,
+    msg_id: MsgId,
+    contact_id: u32,
+) -> sql::Result<Option<MessageState>> {
+    context
+        .sql
+        .query_get_value_result(
+            "SELECT state FROM msgs_broadcast WHERE msg_id=? AND contact_id=?;",
+            paramsv![msg_id, contact_id],
+        )
+        .await
+}
+
+/// Sets the delivery state of the copy of a broadcast message sent to a member.
+pub(crate) async fn set_state(
+    context: &Context,
+    msg_id: MsgId,
+    contact_id: u32,
+    state: MessageState,
+) -> sql::Result<()> {
+    context
+        .sql
+        .execute(
+            "INSERT OR REPLACE INTO msgs_broadcast (msg_id, contact_id, state) VALUES (?, ?, ?);",
+            paramsv![msg_id, contact_id, state],
+        )
+        .await?;
+    Ok(())
+}
+
+/// Marks the copy of a broadcast message sent to a member as delivered.
+///
+/// Returns true if all copies of the message are delivered now.
+pub(crate) async fn set_copy_delivered(
+    context: &Context,
+    msg_id: MsgId,
+    contact_id: u32,
+) -> sql::Result<bool> {
+    set_state(context, msg_id, contact_id, MessageState::OutDelivered).await?;
+    let undelivered = context
+        .sql
+        .exists(
+            "SELECT contact_id FROM msgs_broadcast WHERE msg_id=? AND state!=?;",
+            paramsv![msg_id, MessageState::OutDelivered],
+        )
+        .await?;
+    Ok(!undelivered)
+}
+
+/// Returns the members a broadcast message was sent to
+/// together with the delivery state of their copy.
+///
+/// The state of the message itself, see [crate::message::Message::get_state],
+/// is delivered once all copies are delivered
+/// and failed if sending any copy failed.
+pub async fn get_broadcast_states(
+    context: &Context,
+    msg_id: MsgId,
+) -> Result<Vec<(u32, MessageState)>> {
+    let states = context
+        .sql
+        .query_map(
+            "SELECT contact_id, state FROM msgs_broadcast WHERE msg_id=? ORDER BY contact_id;",
+            paramsv![msg_id],
+            |row| {
+                let contact_id: u32 = row.get(0)?;
+                let state: MessageState = row.get(1)?;
+                Ok((contact_id, state))
+            },
+            |rows| {
+                rows.collect::<std::result::Result<Vec<_>, _>>()
+                    .map_err(Into::into)
+            },
+        )
+        .await?;
+    Ok(states)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chat::{self, Chat};
+    use crate::config::Config;
+    use crate::constants::Chattype;
+    use crate::contact::Contact;
+    use crate::job::{self, Action};
+    use crate::message::Message;
+    use crate::param::Param;
+    use crate::test_utils::TestContext;
+
+    /// Returns the recipients of the pending jobs sending the message.
+    async fn job_recipients(t: &TestContext, msg_id: MsgId) -> Vec<(u32, String)> {
+        t.sql
+            .query_map(
+                "SELECT param FROM jobs WHERE action=? AND foreign_id=? ORDER BY id;",
+                paramsv![Action::SendMsgToSmtp, msg_id],
+                |row| row.get::<_, String>(0),
+                |rows| {
+                    rows.collect::<std::result::Result<Vec<_>, _>>()
+                        .map_err(Into::into)
+                },
+            )
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|param| {
+                let param: crate::param::Params = param.parse().unwrap();
+                (
+                    param.get_int(Param::ContactId).unwrap() as u32,
+                    param.get(Param::Recipients).unwrap().to_string(),
+                )
+            })
+            .collect()
+    }
+
+    #[async_std::test]
+    async fn test_broadcast_fan_out() {
+        let alice = TestContext::new_alice().await;
+        let bob = TestContext::new_bob().await;
+        let bob_id = Contact::create(&alice, "Bob", "bob@example.net")
+            .await
+            .unwrap();
+        let claire_id = Contact::create(&alice, "Claire", "claire@example.org")
+            .await
+            .unwrap();
+
+        let chat_id = chat::create_broadcast_list(&alice, "News").await.unwrap();
+        let chat = Chat::load_from_db(&alice, chat_id).await.unwrap();
+        assert_eq!(chat.typ, Chattype::Broadcast);
+        assert!(chat::add_contact_to_chat(&alice, chat_id, bob_id).await);
+        assert!(chat::add_contact_to_chat(&alice, chat_id, claire_id).await);
+        assert_eq!(chat::get_chat_contacts(&alice, chat_id).await.len(), 2);
+
+        let msg_id = chat::send_text_msg(&alice, chat_id, "hello all".to_string())
+            .await
+            .unwrap();
+        let recipients = job_recipients(&alice, msg_id).await;
+        assert_eq!(
+            recipients,
+            vec![
+                (bob_id, "bob@example.net".to_string()),
+                (claire_id, "claire@example.org".to_string())
+            ]
+        );
+        let msg = Message::load_from_db(&alice, msg_id).await.unwrap();
+        assert_eq!(msg.get_state(), MessageState::OutPending);
+        assert_eq!(
+            get_broadcast_states(&alice, msg_id).await.unwrap(),
+            vec![
+                (bob_id, MessageState::OutPending),
+                (claire_id, MessageState::OutPending)
+            ]
+        );
+
+        // The copy to Bob looks like a 1:1 message, so it lands in the 1:1 chat.
+        let first = alice.pop_sent_msg().await;
+        let second = alice.pop_sent_msg().await;
+        let sent = if first.recipient().to_string() == "bob@example.net" {
+            first
+        } else {
+            second
+        };
+        assert_eq!(sent.recipient().to_string(), "bob@example.net");
+        assert!(!sent.payload().contains("Chat-Group-ID"));
+        assert!(!sent.payload().contains("claire@example.org"));
+        bob.recv_msg(&sent).await;
+        let bob_msg = bob.get_last_msg().await;
+        assert_eq!(bob_msg.text.as_deref(), Some("hello all"));
+        let bob_chat = Chat::load_from_db(&bob, bob_msg.chat_id).await.unwrap();
+        assert_eq!(bob_chat.typ, Chattype::Single);
+    }
+
+    #[async_std::test]
+    async fn test_broadcast_states_aggregate() {
+        let alice = TestContext::new_alice().await;
+        let bob_id = Contact::create(&alice, "", "bob@example.net")
+            .await
+            .unwrap();
+        let claire_id = Contact::create(&alice, "", "claire@example.org")
+            .await
+            .unwrap();
+        let chat_id = chat::create_broadcast_list(&alice, "News").await.unwrap();
+        chat::add_contact_to_chat(&alice, chat_id, bob_id).await;
+        chat::add_contact_to_chat(&alice, chat_id, claire_id).await;
+        let msg_id = chat::send_text_msg(&alice, chat_id, "hi".to_string())
+            .await
+            .unwrap();
+
+        assert!(!set_copy_delivered(&alice, msg_id, bob_id).await.unwrap());
+        assert_eq!(
+            get_state(&alice, msg_id, bob_id).await.unwrap(),
+            Some(MessageState::OutDelivered)
+        );
+        assert!(set_copy_delivered(&alice, msg_id, claire_id).await.unwrap());
+
+        // Resending the message only sends the copies not delivered yet.
+        set_state(&alice, msg_id, claire_id, MessageState::OutFailed)
+            .await
+            .unwrap();
+        alice
+            .sql
+            .execute("DELETE FROM jobs;", paramsv![])
+            .await
+            .unwrap();
+        let jobs = job::send_msg_job(&alice, msg_id).await.unwrap();
+        assert_eq!(jobs.len(), 1);
+        assert_eq!(
+            jobs.first().unwrap().param.get_int(Param::ContactId),
+            Some(claire_id as i32)
+        );
+        assert_eq!(
+            get_state(&alice, msg_id, claire_id).await.unwrap(),
+            Some(MessageState::OutPending)
+        );
+    }
+
+    #[async_std::test]
+    async fn test_broadcast_remove_member() {
+        let alice = TestContext::new_alice().await;
+        let bob_id = Contact::create(&alice, "", "bob@example.net")
+            .await
+            .unwrap();
+        let claire_id = Contact::create(&alice, "", "claire@example.org")
+            .await
+            .unwrap();
+        let chat_id = chat::create_broadcast_list(&alice, "News").await.unwrap();
+        chat::add_contact_to_chat(&alice, chat_id, bob_id).await;
+        chat::add_contact_to_chat(&alice, chat_id, claire_id).await;
+        let first_id = chat::send_text_msg(&alice, chat_id, "first".to_string())
+            .await
+            .unwrap();
+        assert_eq!(job_recipients(&alice, first_id).await.len(), 2);
+
+        chat::remove_contact_from_chat(&alice, chat_id, claire_id)
+            .await
+            .unwrap();
+        assert_eq!(chat::get_chat_contacts(&alice, chat_id).await, vec![bob_id]);
+
+        let second_id = chat::send_text_msg(&alice, chat_id, "second".to_string())
+            .await
+            .unwrap();
+        assert_eq!(
+            job_recipients(&alice, second_id).await,
+            vec![(bob_id, "bob@example.net".to_string())]
+        );
+    }
+
+    #[async_std::test]
+    async fn test_broadcast_bcc_self() {
+        let alice = TestContext::new_alice().await;
+        alice.set_config_bool(Config::BccSelf, true).await.unwrap();
+        let bob_id = Contact::create(&alice, "", "bob@example.net")
+            .await
+            .unwrap();
+        let claire_id = Contact::create(&alice, "", "claire@example.org")
+            .await
+            .unwrap();
+        let chat_id = chat::create_broadcast_list(&alice, "News").await.unwrap();
+        chat::add_contact_to_chat(&alice, chat_id, bob_id).await;
+        chat::add_contact_to_chat(&alice, chat_id, claire_id).await;
+        let msg_id = chat::send_text_msg(&alice, chat_id, "hi".to_string())
+            .await
+            .unwrap();
+
+        // Only one copy is sent to self.
+        assert_eq!(
+            job_recipients(&alice, msg_id).await,
+            vec![
+                (bob_id, "bob@example.net\x1ealice@example.org".to_string()),
+                (claire_id, "claire@example.org".to_string())
+            ]
+        );
+    }
+}
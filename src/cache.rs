@@ -0,0 +1,891 @@
+//! # Bounded caches for rows loaded from the database.
+//!
+//! Chats and contacts are loaded very often, e.g. once per message shown in the chatlist.
+//! [`SqlCaches`] keeps the most recently used of them in memory, up to
+//! [`Config::CacheSize`](crate::config::Config::CacheSize) entries each.
+//!
+//! The caches are owned by [`Sql`](crate::sql::Sql). Every connection of the pool reports
+//! changed rows of the cached tables via an SQLite update hook, so the entries are
+//! invalidated for all write paths, including triggers and migrations. As the hook fires
+//! before the change is committed, the changed rows are invalidated once more when the
+//! statement or transaction is done, see [`SqlCaches::flush_pending`].
+//!
+//! Additionally, [`MidFilter`] answers for most Message-IDs not in the `msgs` table that
+//! they do not exist, so that checking incoming messages for duplicates does not need a
+//! query per message.
+
+use std::cell::RefCell;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use rusqlite::hooks::Action;
+
+use crate::chat::Chat;
+use crate::contact::Contact;
+
+/// Default number of chats and of contacts kept in memory.
+pub(crate) const DEFAULT_CACHE_SIZE: usize = 2000;
+
+/// A map holding at most `capacity` entries, evicting the least recently used one.
+#[derive(Debug)]
+pub(crate) struct LruCache<K, V> {
+    capacity: usize,
+
+    /// Incremented on every access, the tick of an entry tells when it was used last.
+    tick: u64,
+
+    entries: HashMap<K, (u64, V)>,
+
+    /// The keys of all entries ordered by their tick.
+    order: BTreeMap<u64, K>,
+}
+
+impl<K: Hash + Eq + Clone, V> LruCache<K, V> {
+    /// Creates an empty cache, a capacity of 0 disables the cache.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            tick: 0,
+            entries: HashMap::new(),
+            order: BTreeMap::new(),
+        }
+    }
+
+    /// Returns the entry for `key` and marks it as used.
+    pub fn get(&mut self, key: &K) -> Option<&V> {
+        self.tick += 1;
+        let tick = self.tick;
+        let (entry_tick, value) = self.entries.get_mut(key)?;
+        self.order.remove(entry_tick);
+        self.order.insert(tick, key.clone());
+        *entry_tick = tick;
+        Some(value)
+    }
+
+    /// Inserts an entry, evicting the least recently used ones if the cache is full.
+    pub fn insert(&mut self, key: K, value: V) {
+        if self.capacity == 0 {
+            return;
+        }
+        self.tick += 1;
+        self.order.insert(self.tick, key.clone());
+        if let Some((old_tick, _)) = self.entries.insert(key, (self.tick, value)) {
+            self.order.remove(&old_tick);
+        }
+        self.evict();
+    }
+
+    pub fn remove(&mut self, key: &K) {
+        if let Some((tick, _)) = self.entries.remove(key) {
+            self.order.remove(&tick);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.order.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Changes the capacity, evicting entries if the cache is shrunk.
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        self.evict();
+    }
+
+    fn evict(&mut self) {
+        while self.entries.len() > self.capacity {
+            let oldest = match self.order.keys().next() {
+                Some(tick) => *tick,
+                None => break,
+            };
+            if let Some(key) = self.order.remove(&oldest) {
+                self.entries.remove(&key);
+            }
+        }
+    }
+}
+
+/// Number of bits of a [`BloomFilter`] per Message-ID it is built for.
+const BLOOM_BITS_PER_ENTRY: usize = 10;
+
+/// Number of bits set per Message-ID, together with [`BLOOM_BITS_PER_ENTRY`] this results
+/// in about 1% false positives.
+const BLOOM_HASHES: u64 = 7;
+
+/// Minimum number of Message-IDs a [`BloomFilter`] is built for.
+const BLOOM_MIN_CAPACITY: usize = 4096;
+
+/// Maximum number of inserted rows of the `msgs` table waiting to be added to the
+/// [`MidFilter`], if there are more, the filter is built again.
+const MAX_UNSYNCED_ROWS: usize = 500;
+
+/// A set of strings that may report strings as contained which were never inserted.
+#[derive(Debug)]
+struct BloomFilter {
+    bits: Vec<u64>,
+
+    /// The number of strings the filter was built for.
+    capacity: usize,
+
+    /// The number of strings inserted.
+    len: usize,
+}
+
+impl BloomFilter {
+    fn new(capacity: usize) -> Self {
+        let capacity = capacity.max(BLOOM_MIN_CAPACITY);
+        Self {
+            bits: vec![0; capacity * BLOOM_BITS_PER_ENTRY / 64 + 1],
+            capacity,
+            len: 0,
+        }
+    }
+
+    fn bit_positions(&self, s: &str) -> Vec<usize> {
+        let mut hasher = DefaultHasher::new();
+        s.hash(&mut hasher);
+        let h1 = hasher.finish();
+        hasher.write_u8(0xff);
+        let h2 = hasher.finish() | 1;
+
+        let bit_count = self.bits.len() as u64 * 64;
+        (0..BLOOM_HASHES)
+            .map(|i| (h1.wrapping_add(i.wrapping_mul(h2)) % bit_count) as usize)
+            .collect()
+    }
+
+    fn insert(&mut self, s: &str) {
+        for pos in self.bit_positions(s) {
+            if let Some(word) = self.bits.get_mut(pos / 64) {
+                *word |= 1u64 << (pos % 64);
+            }
+        }
+        self.len += 1;
+    }
+
+    fn may_contain(&self, s: &str) -> bool {
+        self.bit_positions(s).into_iter().all(|pos| {
+            self.bits
+                .get(pos / 64)
+                .map_or(true, |word| word & (1u64 << (pos % 64)) != 0)
+        })
+    }
+
+    /// Returns true if more strings were inserted than the filter was built for,
+    /// so that it reports too many false positives.
+    fn is_full(&self) -> bool {
+        self.len > self.capacity
+    }
+}
+
+/// What has to be loaded from the database before [`MidFilter::may_contain`] can answer.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum MidFilterUpdate {
+    /// Nothing, the filter is up to date.
+    None,
+
+    /// All rows, see [`MidFilter::build`].
+    Build {
+        epoch: u64,
+        finished: HashMap<u32, u64>,
+    },
+
+    /// The rows inserted meanwhile, see [`MidFilter::sync`].
+    Sync {
+        epoch: u64,
+        finished: HashMap<u32, u64>,
+    },
+}
+
+#[derive(Debug, Default)]
+struct MidFilterState {
+    bloom: Option<BloomFilter>,
+
+    /// Incremented whenever the filter is dropped.
+    ///
+    /// Rows loaded from the database are only added if the filter was not dropped
+    /// meanwhile, as rows dropped from `unsynced` may have been missed.
+    epoch: u64,
+
+    /// Incremented for every finished insert, tells apart repeated inserts of a row.
+    seq: u64,
+
+    /// Rows inserted by statements still running, not visible to other connections yet.
+    in_flight: HashSet<u32>,
+
+    /// Rows inserted by finished statements that were not added to the filter yet,
+    /// with the `seq` of the insert.
+    unsynced: HashMap<u32, u64>,
+}
+
+impl MidFilterState {
+    fn reset(&mut self) {
+        self.bloom = None;
+        self.unsynced.clear();
+        self.epoch += 1;
+    }
+
+    /// Removes the rows loaded by an update from `unsynced`,
+    /// unless they were inserted again meanwhile.
+    fn remove_synced(&mut self, finished: &HashMap<u32, u64>) {
+        for (id, seq) in finished {
+            if self.unsynced.get(id) == Some(seq) {
+                self.unsynced.remove(id);
+            }
+        }
+    }
+}
+
+/// Filter of the Message-IDs in the `msgs` table.
+///
+/// The filter is built from all rows on first use. Rows inserted afterwards are reported
+/// by the update hook and added either by the inserting code, see [`MidFilter::add`], or
+/// by loading them on the next check. The `rfc724_mid` column is never changed by updates,
+/// so they are ignored. Deleted rows are not removed from the filter, so it may report
+/// Message-IDs as contained that do not exist anymore, the same as for false positives
+/// the database has to be queried then.
+#[derive(Debug, Default)]
+pub(crate) struct MidFilter {
+    state: Mutex<MidFilterState>,
+
+    /// Number of checks answered by the filter.
+    filtered: AtomicU64,
+
+    /// Number of checks that had to query the database.
+    queried: AtomicU64,
+}
+
+impl MidFilter {
+    fn row_inserted(&self, id: u32) {
+        self.state.lock().unwrap().in_flight.insert(id);
+    }
+
+    fn statement_done(&self, id: u32) {
+        let mut state = self.state.lock().unwrap();
+        if state.in_flight.remove(&id) {
+            state.seq += 1;
+            let seq = state.seq;
+            state.unsynced.insert(id, seq);
+            if state.unsynced.len() > MAX_UNSYNCED_ROWS {
+                state.reset();
+            }
+        }
+    }
+
+    /// Adds the Message-ID of the row `id` inserted by the caller.
+    ///
+    /// Must be called after the inserting statement or transaction is done.
+    pub fn add(&self, rfc724_mid: &str, id: u32) {
+        let mut state = self.state.lock().unwrap();
+        let full = match &mut state.bloom {
+            Some(bloom) => {
+                bloom.insert(rfc724_mid);
+                bloom.is_full()
+            }
+            None => return,
+        };
+        state.unsynced.remove(&id);
+        if full {
+            state.reset();
+        }
+    }
+
+    /// Returns what has to be loaded from the database to bring the filter up to date.
+    pub fn pending_update(&self) -> MidFilterUpdate {
+        let state = self.state.lock().unwrap();
+        let epoch = state.epoch;
+        let finished = state.unsynced.clone();
+        if state.bloom.is_none() {
+            MidFilterUpdate::Build { epoch, finished }
+        } else if finished.is_empty() {
+            MidFilterUpdate::None
+        } else {
+            MidFilterUpdate::Sync { epoch, finished }
+        }
+    }
+
+    /// Builds the filter from `rows`, all rows of the `msgs` table,
+    /// loaded after [`MidFilter::pending_update`] returned [`MidFilterUpdate::Build`].
+    pub fn build(&self, epoch: u64, finished: &HashMap<u32, u64>, rows: Vec<String>) {
+        let mut state = self.state.lock().unwrap();
+        if state.epoch != epoch || state.bloom.is_some() {
+            return;
+        }
+        let mut bloom = BloomFilter::new(rows.len() * 2);
+        for rfc724_mid in rows {
+            bloom.insert(&rfc724_mid);
+        }
+        state.bloom = Some(bloom);
+        state.remove_synced(finished);
+    }
+
+    /// Adds `rows`, the rows of `finished` still existing,
+    /// loaded after [`MidFilter::pending_update`] returned [`MidFilterUpdate::Sync`].
+    pub fn sync(&self, epoch: u64, finished: &HashMap<u32, u64>, rows: Vec<String>) {
+        let mut state = self.state.lock().unwrap();
+        if state.epoch != epoch {
+            return;
+        }
+        let full = match &mut state.bloom {
+            Some(bloom) => {
+                for rfc724_mid in rows {
+                    bloom.insert(&rfc724_mid);
+                }
+                bloom.is_full()
+            }
+            None => return,
+        };
+        state.remove_synced(finished);
+        if full {
+            state.reset();
+        }
+    }
+
+    /// Returns false if no row of the `msgs` table has the Message-ID `rfc724_mid`.
+    ///
+    /// Returns true if the database has to be queried, also if the filter is not up to
+    /// date as rows were inserted meanwhile.
+    pub fn may_contain(&self, rfc724_mid: &str) -> bool {
+        let may_contain = {
+            let state = self.state.lock().unwrap();
+            match &state.bloom {
+                Some(bloom) if state.in_flight.is_empty() && state.unsynced.is_empty() => {
+                    bloom.may_contain(rfc724_mid)
+                }
+                _ => true,
+            }
+        };
+        if may_contain {
+            self.queried.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.filtered.fetch_add(1, Ordering::Relaxed);
+        }
+        may_contain
+    }
+
+    fn clear(&self) {
+        self.state.lock().unwrap().reset();
+    }
+}
+
+/// Tables with rows kept in [`SqlCaches`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CachedTable {
+    Chats,
+    Contacts,
+
+    /// Only inserted rows are tracked, see [`MidFilter`].
+    Msgs,
+}
+
+impl CachedTable {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "chats" => Some(CachedTable::Chats),
+            "contacts" => Some(CachedTable::Contacts),
+            "msgs" => Some(CachedTable::Msgs),
+            _ => None,
+        }
+    }
+}
+
+thread_local! {
+    /// Rows changed on this thread by statements that are not known to be committed yet,
+    /// together with the address of the caches they belong to.
+    static PENDING: RefCell<Vec<(usize, CachedTable, u32)>> = RefCell::new(Vec::new());
+}
+
+/// Cache statistics, see [`SqlCaches::stats`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct CacheStats {
+    /// Number of loads answered from the caches.
+    pub hits: u64,
+
+    /// Number of loads that had to query the database.
+    pub misses: u64,
+
+    /// Number of chats currently cached.
+    pub chats: usize,
+
+    /// Number of contacts currently cached.
+    pub contacts: usize,
+
+    /// Number of Message-ID checks answered without querying the database.
+    pub mids_filtered: u64,
+
+    /// Number of Message-ID checks that had to query the database.
+    pub mids_queried: u64,
+}
+
+/// Caches for the rows of the `chats` and `contacts` tables.
+///
+/// The chats and contacts are cached as read from the database, names depending on
+/// translations or other rows are computed again on every load.
+#[derive(Debug)]
+pub(crate) struct SqlCaches {
+    chats: Mutex<LruCache<u32, Chat>>,
+    contacts: Mutex<LruCache<u32, Contact>>,
+    mids: MidFilter,
+
+    /// Incremented whenever rows are invalidated.
+    ///
+    /// A row read from the database is only cached if the generation did not change
+    /// meanwhile, as it may have been read before a concurrent change was committed.
+    generation: AtomicU64,
+
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl Default for SqlCaches {
+    fn default() -> Self {
+        Self::new(DEFAULT_CACHE_SIZE)
+    }
+}
+
+impl SqlCaches {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            chats: Mutex::new(LruCache::new(capacity)),
+            contacts: Mutex::new(LruCache::new(capacity)),
+            mids: MidFilter::default(),
+            generation: AtomicU64::new(0),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// Returns the current generation, to be passed to `put_*()` after loading a row.
+    pub fn generation(&self) -> u64 {
+        self.generation.load(Ordering::SeqCst)
+    }
+
+    pub fn get_chat(&self, id: u32) -> Option<Chat> {
+        let chat = self.chats.lock().unwrap().get(&id).cloned();
+        self.count(chat.is_some());
+        chat
+    }
+
+    pub fn put_chat(&self, id: u32, chat: Chat, generation: u64) {
+        let mut chats = self.chats.lock().unwrap();
+        if self.generation() == generation {
+            chats.insert(id, chat);
+        }
+    }
+
+    pub fn get_contact(&self, id: u32) -> Option<Contact> {
+        let contact = self.contacts.lock().unwrap().get(&id).cloned();
+        self.count(contact.is_some());
+        contact
+    }
+
+    pub fn put_contact(&self, id: u32, contact: Contact, generation: u64) {
+        let mut contacts = self.contacts.lock().unwrap();
+        if self.generation() == generation {
+            contacts.insert(id, contact);
+        }
+    }
+
+    /// Returns the filter of the Message-IDs in the `msgs` table.
+    pub fn mids(&self) -> &MidFilter {
+        &self.mids
+    }
+
+    fn count(&self, hit: bool) {
+        if hit {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.misses.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Invalidates a changed row, called from the update hook of the connections.
+    pub fn row_changed(&self, action: Action, table: &str, rowid: i64) {
+        let table = match CachedTable::from_name(table) {
+            Some(CachedTable::Msgs) if action != Action::SQLITE_INSERT => return,
+            Some(table) => table,
+            None => return,
+        };
+        let id = rowid as u32;
+        if table == CachedTable::Msgs {
+            self.mids.row_inserted(id);
+        } else {
+            self.invalidate(table, id);
+        }
+        let key = self as *const Self as usize;
+        PENDING.with(|pending| pending.borrow_mut().push((key, table, id)));
+    }
+
+    /// Invalidates the rows changed on this thread again.
+    ///
+    /// Called when a statement or transaction is done, so that rows read and cached by
+    /// another connection before the change was committed are not kept.
+    pub fn flush_pending(&self) {
+        let key = self as *const Self as usize;
+        let changed: Vec<_> = PENDING.with(|pending| {
+            let mut pending = pending.borrow_mut();
+            let (changed, others) = pending.drain(..).partition(|(k, _, _)| *k == key);
+            *pending = others;
+            changed
+        });
+        for (_, table, id) in changed {
+            if table == CachedTable::Msgs {
+                self.mids.statement_done(id);
+            } else {
+                self.invalidate(table, id);
+            }
+        }
+    }
+
+    fn invalidate(&self, table: CachedTable, id: u32) {
+        // The generation is changed while holding the lock checked by `put_*()`.
+        match table {
+            CachedTable::Chats => {
+                let mut chats = self.chats.lock().unwrap();
+                self.generation.fetch_add(1, Ordering::SeqCst);
+                chats.remove(&id);
+            }
+            CachedTable::Contacts => {
+                let mut contacts = self.contacts.lock().unwrap();
+                self.generation.fetch_add(1, Ordering::SeqCst);
+                contacts.remove(&id);
+            }
+            CachedTable::Msgs => {}
+        }
+    }
+
+    /// Drops all cached rows.
+    pub fn clear(&self) {
+        let mut chats = self.chats.lock().unwrap();
+        let mut contacts = self.contacts.lock().unwrap();
+        self.generation.fetch_add(1, Ordering::SeqCst);
+        chats.clear();
+        contacts.clear();
+        self.mids.clear();
+    }
+
+    /// Changes the number of chats and of contacts kept, 0 disables the caches.
+    pub fn set_capacity(&self, capacity: usize) {
+        self.chats.lock().unwrap().set_capacity(capacity);
+        self.contacts.lock().unwrap().set_capacity(capacity);
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            chats: self.chats.lock().unwrap().len(),
+            contacts: self.contacts.lock().unwrap().len(),
+            mids_filtered: self.mids.filtered.load(Ordering::Relaxed),
+            mids_queried: self.mids.queried.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::chat::{create_group_chat, set_chat_name, ProtectionStatus};
+    use crate::config::Config;
+    use crate::dc_receive_imf::dc_receive_imf;
+    use crate::message::rfc724_mid_exists;
+    use crate::test_utils::TestContext;
+
+    #[test]
+    fn test_lru_evicts_least_recently_used() {
+        let mut cache = LruCache::new(2);
+        cache.insert(1, "one");
+        cache.insert(2, "two");
+        assert_eq!(cache.get(&1), Some(&"one"));
+        cache.insert(3, "three");
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.get(&2), None);
+        assert_eq!(cache.get(&1), Some(&"one"));
+        assert_eq!(cache.get(&3), Some(&"three"));
+
+        cache.set_capacity(1);
+        assert_eq!(cache.len(), 1);
+        assert_eq!(cache.get(&3), Some(&"three"));
+
+        cache.set_capacity(0);
+        cache.insert(4, "four");
+        assert_eq!(cache.len(), 0);
+    }
+
+    #[test]
+    fn test_lru_bound_under_churn() {
+        let mut cache = LruCache::new(100);
+        for i in 0..10_000u32 {
+            cache.insert(i % 1000, i);
+            // Keep a hot entry alive.
+            assert!(i < 1000 || cache.get(&0).is_some());
+            if i % 7 == 0 {
+                cache.remove(&(i % 300));
+            }
+            assert!(cache.len() <= 100);
+            assert_eq!(cache.order.len(), cache.entries.len());
+        }
+        assert_eq!(cache.len(), 100);
+    }
+
+    #[async_std::test]
+    async fn test_cache_hit_avoids_query() {
+        let t = TestContext::new().await;
+        let chat_id = create_group_chat(&t, ProtectionStatus::Unprotected, "foo")
+            .await
+            .unwrap();
+        let contact_id = Contact::create(&t, "Bob", "bob@example.net").await.unwrap();
+        t.clear_caches();
+
+        let before = t.sql.cache_stats();
+        Chat::load_from_db(&t, chat_id).await.unwrap();
+        Contact::load_from_db(&t, contact_id).await.unwrap();
+        let loaded = t.sql.cache_stats();
+        assert_eq!(loaded.misses, before.misses + 2);
+        assert_eq!(loaded.chats, 1);
+        assert_eq!(loaded.contacts, 1);
+
+        let chat = Chat::load_from_db(&t, chat_id).await.unwrap();
+        let contact = Contact::load_from_db(&t, contact_id).await.unwrap();
+        assert_eq!(chat.get_name(), "foo");
+        assert_eq!(contact.get_name(), "Bob");
+        let cached = t.sql.cache_stats();
+        assert_eq!(cached.misses, loaded.misses);
+        assert_eq!(cached.hits, loaded.hits + 2);
+
+        t.clear_caches();
+        let cleared = t.sql.cache_stats();
+        assert_eq!(cleared.chats, 0);
+        assert_eq!(cleared.contacts, 0);
+    }
+
+    #[async_std::test]
+    async fn test_cache_invalidated_on_update() {
+        let t = TestContext::new().await;
+        let chat_id = create_group_chat(&t, ProtectionStatus::Unprotected, "foo")
+            .await
+            .unwrap();
+        let contact_id = Contact::create(&t, "Bob", "bob@example.net").await.unwrap();
+        Chat::load_from_db(&t, chat_id).await.unwrap();
+        Contact::load_from_db(&t, contact_id).await.unwrap();
+
+        set_chat_name(&t, chat_id, "bar").await.unwrap();
+        let chat = Chat::load_from_db(&t, chat_id).await.unwrap();
+        assert_eq!(chat.get_name(), "bar");
+
+        // Raw statements are observed as well.
+        t.sql
+            .execute(
+                "UPDATE contacts SET name='Robert' WHERE id=?;",
+                paramsv![contact_id],
+            )
+            .await
+            .unwrap();
+        let misses = t.sql.cache_stats().misses;
+        let contact = Contact::load_from_db(&t, contact_id).await.unwrap();
+        assert_eq!(contact.get_name(), "Robert");
+        assert_eq!(t.sql.cache_stats().misses, misses + 1);
+
+        t.sql
+            .execute("DELETE FROM contacts WHERE id=?;", paramsv![contact_id])
+            .await
+            .unwrap();
+        assert!(Contact::load_from_db(&t, contact_id).await.is_err());
+    }
+
+    #[async_std::test]
+    async fn test_cache_size_bound() {
+        let t = TestContext::new().await;
+        t.set_config(Config::CacheSize, Some("10")).await.unwrap();
+
+        let mut contact_ids = Vec::new();
+        for i in 0..50 {
+            let addr = format!("contact{}@example.org", i);
+            contact_ids.push(Contact::create(&t, "", &addr).await.unwrap());
+        }
+        for round in 0..3 {
+            for contact_id in &contact_ids {
+                let contact = Contact::load_from_db(&t, *contact_id).await.unwrap();
+                assert_eq!(contact.id, *contact_id);
+                assert!(t.sql.cache_stats().contacts <= 10, "round {}", round);
+            }
+        }
+        assert_eq!(t.sql.cache_stats().contacts, 10);
+
+        t.set_config(Config::CacheSize, Some("0")).await.unwrap();
+        assert_eq!(t.sql.cache_stats().contacts, 0);
+        Contact::load_from_db(&t, *contact_ids.first().unwrap())
+            .await
+            .unwrap();
+        assert_eq!(t.sql.cache_stats().contacts, 0);
+    }
+
+    #[test]
+    fn test_mid_filter_updates() {
+        let filter = MidFilter::default();
+        let finished = match filter.pending_update() {
+            MidFilterUpdate::Build { epoch: 0, finished } => finished,
+            update => panic!("unexpected update {:?}", update),
+        };
+        assert!(filter.may_contain("old@example.org"));
+        filter.build(0, &finished, vec!["old@example.org".to_string()]);
+        assert_eq!(filter.pending_update(), MidFilterUpdate::None);
+        assert!(filter.may_contain("old@example.org"));
+        assert!(!filter.may_contain("new@example.org"));
+
+        // Rows inserted by running statements are not known yet.
+        filter.row_inserted(10);
+        filter.row_inserted(11);
+        filter.row_inserted(12);
+        assert_eq!(filter.pending_update(), MidFilterUpdate::None);
+        assert!(filter.may_contain("new@example.org"));
+
+        // Row 10 was added by the inserting code, row 11 was rolled back.
+        filter.statement_done(10);
+        filter.statement_done(11);
+        filter.statement_done(12);
+        filter.add("new@example.org", 10);
+        let finished = match filter.pending_update() {
+            MidFilterUpdate::Sync { epoch: 0, finished } => finished,
+            update => panic!("unexpected update {:?}", update),
+        };
+        assert_eq!(finished.len(), 2);
+        filter.sync(0, &finished, vec!["raw@example.org".to_string()]);
+        assert_eq!(filter.pending_update(), MidFilterUpdate::None);
+        assert!(filter.may_contain("new@example.org"));
+        assert!(filter.may_contain("raw@example.org"));
+        assert!(!filter.may_contain("other@example.org"));
+
+        // Too many rows waiting drop the filter, loads started before are ignored.
+        for id in 100..(100 + MAX_UNSYNCED_ROWS as u32 + 1) {
+            filter.row_inserted(id);
+            filter.statement_done(id);
+        }
+        assert_eq!(
+            filter.pending_update(),
+            MidFilterUpdate::Build {
+                epoch: 1,
+                finished: HashMap::new()
+            }
+        );
+        filter.build(0, &HashMap::new(), Vec::new());
+        assert!(filter.may_contain("other@example.org"));
+    }
+
+    #[test]
+    fn test_bloom_false_positive_rate() {
+        let mut bloom = BloomFilter::new(10_000);
+        for i in 0..10_000 {
+            bloom.insert(&format!("{}@example.org", i));
+        }
+        assert!(!bloom.is_full());
+        for i in 0..10_000 {
+            assert!(bloom.may_contain(&format!("{}@example.org", i)));
+        }
+        let false_positives = (0..10_000)
+            .filter(|i| bloom.may_contain(&format!("{}@example.net", i)))
+            .count();
+        assert!(false_positives < 300, "{} false positives", false_positives);
+    }
+
+    async fn count_msgs(t: &TestContext) -> i64 {
+        t.sql
+            .query_get_value_result::<i64>("SELECT COUNT(*) FROM msgs;", paramsv![])
+            .await
+            .unwrap()
+            .unwrap_or_default()
+    }
+
+    async fn receive_mid(t: &TestContext, rfc724_mid: &str, uid: u32) {
+        let raw = format!(
+            "From: bob@example.net\n\
+             To: alice@example.org\n\
+             Chat-Version: 1.0\n\
+             Subject: foo\n\
+             Message-ID: <{}>\n\
+             Date: Sun, 22 Mar 2020 22:37:57 +0000\n\
+             \n\
+             hello\n",
+            rfc724_mid
+        );
+        dc_receive_imf(t, raw.as_bytes(), "INBOX", uid, false)
+            .await
+            .unwrap();
+    }
+
+    #[async_std::test]
+    async fn test_mid_filter_avoids_queries() {
+        let t = TestContext::new_alice().await;
+        receive_mid(&t, "first@example.net", 1).await;
+
+        let before = t.sql.cache_stats();
+        for uid in 2..202 {
+            receive_mid(&t, &format!("{}@example.net", uid), uid).await;
+        }
+        let after = t.sql.cache_stats();
+        let filtered = after.mids_filtered - before.mids_filtered;
+        let queried = after.mids_queried - before.mids_queried;
+        assert!(filtered >= 200, "{} checks filtered", filtered);
+        assert!(queried < 10, "{} checks queried", queried);
+
+        for uid in 2..202 {
+            let rfc724_mid = format!("{}@example.net", uid);
+            assert!(rfc724_mid_exists(&t, &rfc724_mid).await.unwrap().is_some());
+        }
+    }
+
+    #[async_std::test]
+    async fn test_mid_filter_delete_and_receive_again() {
+        let t = TestContext::new_alice().await;
+        receive_mid(&t, "foo@example.net", 1).await;
+        let (_, _, msg_id) = rfc724_mid_exists(&t, "foo@example.net")
+            .await
+            .unwrap()
+            .unwrap();
+
+        // Receiving a message again is detected as duplicate.
+        let count = count_msgs(&t).await;
+        receive_mid(&t, "foo@example.net", 1).await;
+        assert_eq!(count_msgs(&t).await, count);
+
+        // The deleted Message-ID stays in the filter, the query tells it does not exist.
+        t.sql
+            .execute("DELETE FROM msgs WHERE id=?;", paramsv![msg_id])
+            .await
+            .unwrap();
+        let queried = t.sql.cache_stats().mids_queried;
+        assert!(rfc724_mid_exists(&t, "foo@example.net")
+            .await
+            .unwrap()
+            .is_none());
+        assert_eq!(t.sql.cache_stats().mids_queried, queried + 1);
+        receive_mid(&t, "foo@example.net", 2).await;
+        let (_, server_uid, received_again) = rfc724_mid_exists(&t, "foo@example.net")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_ne!(received_again, msg_id);
+        assert_eq!(server_uid, 2);
+
+        // Rows inserted by other code are loaded into the filter before the next check.
+        t.sql
+            .execute(
+                "INSERT INTO msgs (rfc724_mid, chat_id) VALUES ('raw@example.net', 3);",
+                paramsv![],
+            )
+            .await
+            .unwrap();
+        assert!(rfc724_mid_exists(&t, "raw@example.net")
+            .await
+            .unwrap()
+            .is_some());
+    }
+}
@@ -13,33 +13,43 @@
 use serde::{Deserialize, Serialize};
 
 use crate::aheader::EncryptPreference;
-use crate::blob::{BlobError, BlobObject};
+use crate::blob::{BlobError, BlobObject, BlobSource};
 use crate::chatlist::dc_get_archived_cnt;
+use crate::chunks;
 use crate::color::str_to_color;
 use crate::config::Config;
 use crate::constants::{
-    Blocked, Chattype, ShowEmails, Viewtype, DC_CHAT_ID_ALLDONE_HINT, DC_CHAT_ID_ARCHIVED_LINK,
-    DC_CHAT_ID_DEADDROP, DC_CHAT_ID_LAST_SPECIAL, DC_CHAT_ID_TRASH, DC_CONTACT_ID_DEVICE,
-    DC_CONTACT_ID_INFO, DC_CONTACT_ID_LAST_SPECIAL, DC_CONTACT_ID_SELF, DC_GCM_ADDDAYMARKER,
-    DC_GCM_INFO_ONLY, DC_RESEND_USER_AVATAR_DAYS,
+    Blocked, Chattype, MediaQuality, NotifyProfile, ShowEmails, Viewtype, DC_CHAT_ID_ALLDONE_HINT,
+    DC_CHAT_ID_ARCHIVED_LINK, DC_CHAT_ID_DEADDROP, DC_CHAT_ID_LAST_SPECIAL, DC_CHAT_ID_TRASH,
+    DC_CONTACT_ID_DEVICE, DC_CONTACT_ID_INFO, DC_CONTACT_ID_LAST_SPECIAL, DC_CONTACT_ID_SELF,
+    DC_GCM_ADDDAYMARKER, DC_GCM_INFO_ONLY, DC_RESEND_USER_AVATAR_DAYS,
 };
 use crate::contact::{addr_cmp, Contact, Origin, VerifiedStatus};
 use crate::context::Context;
 use crate::dc_tools::{
     dc_create_id, dc_create_outgoing_rfc724_mid, dc_create_smeared_timestamp,
-    dc_create_smeared_timestamps, dc_get_abs_path, dc_gm2local_offset, improve_single_line_input,
-    remove_subject_prefix, time, IsNoneOrEmpty,
+    dc_create_smeared_timestamps, dc_get_abs_path, dc_gm2local_offset, dc_timestamp_to_str,
+    improve_single_line_input, remove_subject_prefix, time, IsNoneOrEmpty,
+};
+use crate::e2ee;
+use crate::ephemeral::{
+    delete_expired_messages, hold_deletions, schedule_ephemeral_task, schedule_unmute_task,
+    Timer as EphemeralTimer,
 };
-use crate::ephemeral::{delete_expired_messages, schedule_ephemeral_task, Timer as EphemeralTimer};
 use crate::events::EventType;
 use crate::html::new_html_mimepart;
+use crate::imap::cleanup;
 use crate::job::{self, Action};
-use crate::message::{self, InvalidMsgId, Message, MessageState, MsgId};
+use crate::key::{DcKey, Fingerprint};
+use crate::message::{self, ErrorCode, InvalidMsgId, Message, MessageState, MsgId};
 use crate::mimeparser::SystemMessage;
 use crate::param::{Param, Params};
 use crate::peerstate::{Peerstate, PeerstateVerifiedStatus};
+use crate::scheduler::InterruptInfo;
 use crate::sql;
+use crate::stats::{self, Counter};
 use crate::stock_str;
+use crate::sync::{self, Sync};
 
 /// An chat item, such as a message or a marker.
 #[derive(Debug, Copy, Clone)]
@@ -87,6 +97,37 @@ fn default() -> Self {
     }
 }
 
+/// Protection status of a chat, audited from the verified keys of its members.
+///
+/// Unlike the [ProtectionStatus] stored for a chat, this tells whether the guarantees
+/// of a protected chat still hold, see [ChatId::get_protection_status].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChatProtectionStatus {
+    /// The chat is not protected.
+    Unprotected,
+
+    /// The chat is protected and all members are verified.
+    Protected,
+
+    /// The chat is protected, but some members are not verified anymore.
+    Broken {
+        /// Members without verified key or using a key different from the verified one.
+        offending_contacts: Vec<u32>,
+    },
+}
+
+impl ChatProtectionStatus {
+    /// Returns the number used for the status in the C API:
+    /// 0 for unprotected, 1 for protected and 2 for broken.
+    pub fn to_u32(&self) -> u32 {
+        match self {
+            ChatProtectionStatus::Unprotected => 0,
+            ChatProtectionStatus::Protected => 1,
+            ChatProtectionStatus::Broken { .. } => 2,
+        }
+    }
+}
+
 /// Chat ID, including reserved IDs.
 ///
 /// Some chat IDs are reserved to identify special chat types.  This
@@ -179,14 +220,17 @@ pub async fn set_blocked(self, context: &Context, new_blocked: Blocked) -> bool
             warn!(context, "ignoring setting of Block-status for {}", self);
             return false;
         }
-        context
+        let updated = context
             .sql
             .execute(
                 "UPDATE chats SET blocked=? WHERE id=?;",
                 paramsv![new_blocked, self],
             )
             .await
-            .is_ok()
+            .is_ok();
+        // Messages move between the counters, which can not be adjusted without counting them.
+        context.stats_cache.invalidate();
+        updated
     }
 
     pub async fn unblock(self, context: &Context) {
@@ -223,6 +267,7 @@ pub(crate) async fn inner_set_protection(
                     }
                 }
                 Chattype::Mailinglist => bail!("Cannot protect mailing lists"),
+                Chattype::Broadcast => bail!("Cannot protect broadcast lists"),
                 Chattype::Undefined => bail!("Undefined group type"),
             },
             ProtectionStatus::Unprotected => {}
@@ -237,6 +282,7 @@ pub(crate) async fn inner_set_protection(
             .await?;
 
         context.emit_event(EventType::ChatModified(self));
+        self.update_protection_status(context, true).await?;
 
         // make sure, the receivers will get all keys
         reset_gossiped_timestamp(context, self).await?;
@@ -299,7 +345,113 @@ pub async fn set_protection(
             .await
     }
 
+    /// Returns the protection status of the chat,
+    /// checking the verified keys of all members with a single query.
+    pub async fn get_protection_status(
+        self,
+        context: &Context,
+    ) -> Result<ChatProtectionStatus, Error> {
+        let chat = Chat::load_from_db(context, self).await?;
+        if !chat.is_protected() {
+            return Ok(ChatProtectionStatus::Unprotected);
+        }
+
+        let offending_contacts = context
+            .sql
+            .query_map(
+                "SELECT cc.contact_id, ps.verified_key_fingerprint, \
+                 ps.public_key_fingerprint, ps.gossip_key_fingerprint \
+                 FROM chats_contacts cc \
+                 LEFT JOIN contacts c ON c.id=cc.contact_id \
+                 LEFT JOIN acpeerstates ps ON ps.addr=c.addr COLLATE NOCASE \
+                 WHERE cc.chat_id=? AND cc.contact_id>? \
+                 ORDER BY cc.contact_id;",
+                paramsv![self, DC_CONTACT_ID_LAST_SPECIAL],
+                |row| {
+                    let contact_id: u32 = row.get(0)?;
+                    let verified: Option<String> = row.get(1)?;
+                    let public: Option<String> = row.get(2)?;
+                    let gossip: Option<String> = row.get(3)?;
+                    Ok((contact_id, verified, public, gossip))
+                },
+                |rows| {
+                    let mut offending_contacts = Vec::new();
+                    for row in rows {
+                        let (contact_id, verified, public, gossip) = row?;
+                        if !is_verified_key_current(verified, public, gossip)
+                            && !offending_contacts.contains(&contact_id)
+                        {
+                            offending_contacts.push(contact_id);
+                        }
+                    }
+                    Ok(offending_contacts)
+                },
+            )
+            .await?;
+
+        if offending_contacts.is_empty() {
+            Ok(ChatProtectionStatus::Protected)
+        } else {
+            Ok(ChatProtectionStatus::Broken { offending_contacts })
+        }
+    }
+
+    /// Checks the verified keys of all members of the chat again.
+    ///
+    /// Emits [EventType::ChatProtectionChanged] if the status differs from the last check.
+    pub async fn audit_protection(self, context: &Context) -> Result<ChatProtectionStatus, Error> {
+        self.update_protection_status(context, false).await
+    }
+
+    /// Checks the protection status and remembers the offending members of a broken chat.
+    ///
+    /// If `changed` is true, the status is known to have changed, e.g. as the chat was
+    /// protected or unprotected, and the event is emitted in any case.
+    async fn update_protection_status(
+        self,
+        context: &Context,
+        changed: bool,
+    ) -> Result<ChatProtectionStatus, Error> {
+        let status = self.get_protection_status(context).await?;
+        let offending_contacts = match &status {
+            ChatProtectionStatus::Broken { offending_contacts } => {
+                offending_contacts.iter().join(" ")
+            }
+            ChatProtectionStatus::Unprotected | ChatProtectionStatus::Protected => String::new(),
+        };
+
+        let mut chat = Chat::load_from_db(context, self).await?;
+        let old_offending_contacts = chat
+            .param
+            .get(Param::ProtectionBroken)
+            .unwrap_or_default()
+            .to_string();
+        if old_offending_contacts != offending_contacts {
+            if offending_contacts.is_empty() {
+                chat.param.remove(Param::ProtectionBroken);
+            } else {
+                chat.param.set(Param::ProtectionBroken, &offending_contacts);
+            }
+            chat.update_param(context).await?;
+        } else if !changed {
+            return Ok(status);
+        }
+
+        info!(
+            context,
+            "Protection status of {} changed to {:?}.", self, status
+        );
+        context.emit_event(EventType::ChatProtectionChanged {
+            chat_id: self,
+            status: status.clone(),
+        });
+        Ok(status)
+    }
+
     /// Archives or unarchives a chat.
+    ///
+    /// Chats archived with [ChatVisibility::NoUnarchive]
+    /// stay archived when new messages arrive.
     pub async fn set_visibility(
         self,
         context: &Context,
@@ -311,7 +463,7 @@ pub async fn set_visibility(
             self
         );
 
-        if visibility == ChatVisibility::Archived {
+        if visibility.is_archived() {
             context
                 .sql
                 .execute(
@@ -338,7 +490,8 @@ pub async fn set_visibility(
     }
 
     // note that unarchive() is not the same as set_visibility(Normal) -
-    // eg. unarchive() does not modify pinned chats and does not send events.
+    // eg. unarchive() does not modify pinned chats and chats archived with NoUnarchive
+    // and does not send events.
     pub async fn unarchive(self, context: &Context) -> Result<(), Error> {
         context
             .sql
@@ -350,7 +503,98 @@ pub async fn unarchive(self, context: &Context) -> Result<(), Error> {
         Ok(())
     }
 
+    /// Mutes or unmutes the chat.
+    ///
+    /// A chat muted for a duration is unmuted automatically when the duration expires.
+    pub async fn set_mute_duration(
+        self,
+        context: &Context,
+        duration: MuteDuration,
+    ) -> Result<(), Error> {
+        ensure!(!self.is_special(), "Invalid chat ID");
+        if context
+            .sql
+            .execute(
+                "UPDATE chats SET muted_until=? WHERE id=?;",
+                paramsv![duration, self],
+            )
+            .await
+            .is_ok()
+        {
+            context.emit_event(EventType::ChatModified(self));
+        } else {
+            bail!("Failed to set mute duration, chat might not exist -");
+        }
+        schedule_unmute_task(context).await;
+        Ok(())
+    }
+
+    /// Returns the current mute state of the chat.
+    ///
+    /// An expired timed mute is returned as [MuteDuration::NotMuted].
+    pub async fn get_mute_state(self, context: &Context) -> Result<MuteDuration, Error> {
+        let mute_duration = context
+            .sql
+            .query_get_value_result("SELECT muted_until FROM chats WHERE id=?;", paramsv![self])
+            .await?
+            .with_context(|| format!("chat {} not found", self))?;
+        Ok(mute_duration)
+    }
+
+    /// Sets how notifications of the chat are presented.
+    ///
+    /// A muted chat is not notified regardless of its profile, see [crate::notification].
+    /// The profile is synchronized to other own devices if sync messages are enabled.
+    ///
+    /// Emits [EventType::ChatModified].
+    pub async fn set_notify_profile(
+        self,
+        context: &Context,
+        profile: NotifyProfile,
+    ) -> Result<(), Error> {
+        self.set_notify_profile_ex(context, Sync::Sync, profile, time())
+            .await
+    }
+
+    /// Sets the notification profile changed at `timestamp`,
+    /// synchronizing it to other devices only if requested.
+    pub(crate) async fn set_notify_profile_ex(
+        self,
+        context: &Context,
+        sync: Sync,
+        profile: NotifyProfile,
+        timestamp: i64,
+    ) -> Result<(), Error> {
+        ensure!(!self.is_special(), "Invalid chat ID");
+        let mut chat = Chat::load_from_db(context, self).await?;
+        chat.param.set_int(Param::NotifyProfile, profile as i32);
+        chat.param.set_i64(Param::NotifyProfileTimestamp, timestamp);
+        chat.update_param(context).await?;
+        context.emit_event(EventType::ChatModified(self));
+
+        if sync == Sync::Sync {
+            if let Err(err) = sync::notify_profile_changed(context, &chat, profile, timestamp).await
+            {
+                warn!(
+                    context,
+                    "Cannot synchronize notify profile to other devices: {}", err
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns how notifications of the chat are presented.
+    pub async fn get_notify_profile(self, context: &Context) -> Result<NotifyProfile, Error> {
+        Ok(Chat::load_from_db(context, self)
+            .await?
+            .get_notify_profile())
+    }
+
     /// Deletes a chat.
+    ///
+    /// With [Config::DeleteOnServerWhenDeletingLocally] enabled,
+    /// the messages of the chat are deleted from the server as well.
     pub async fn delete(self, context: &Context) -> Result<(), Error> {
         ensure!(
             !self.is_special(),
@@ -367,8 +611,51 @@ pub async fn delete(self, context: &Context) -> Result<(), Error> {
                 paramsv![self],
             )
             .await?;
-
         context
+            .sql
+            .execute(
+                "DELETE FROM msgs_broadcast WHERE msg_id IN (SELECT id FROM msgs WHERE chat_id=?);",
+                paramsv![self],
+            )
+            .await?;
+        context
+            .sql
+            .execute(
+                "DELETE FROM reactions WHERE msg_id IN (SELECT id FROM msgs WHERE chat_id=?);",
+                paramsv![self],
+            )
+            .await?;
+        context
+            .sql
+            .execute(
+                "DELETE FROM msgs_status_updates WHERE msg_id IN (SELECT id FROM msgs WHERE chat_id=?);",
+                paramsv![self],
+            )
+            .await?;
+        context
+            .sql
+            .execute(
+                "DELETE FROM msgs_chunks WHERE msg_id IN (SELECT id FROM msgs WHERE chat_id=?);",
+                paramsv![self],
+            )
+            .await?;
+
+        let delete_on_server = context
+            .get_config_bool(Config::DeleteOnServerWhenDeletingLocally)
+            .await;
+        let mut deleted_msgs = 0;
+        if delete_on_server {
+            // Messages on the server are kept as tombstones until they are deleted there.
+            deleted_msgs += context
+                .sql
+                .execute(
+                    "UPDATE msgs SET chat_id=?, txt='', txt_folded='', subject='', txt_raw='', mime_headers='', from_id=0, to_id=0, param='', delete_on_server=1 \
+                     WHERE chat_id=? AND server_uid!=0;",
+                    paramsv![ChatId::new(DC_CHAT_ID_TRASH), self],
+                )
+                .await?;
+        }
+        deleted_msgs += context
             .sql
             .execute("DELETE FROM msgs WHERE chat_id=?;", paramsv![self])
             .await?;
@@ -385,12 +672,21 @@ pub async fn delete(self, context: &Context) -> Result<(), Error> {
             .sql
             .execute("DELETE FROM chats WHERE id=?;", paramsv![self])
             .await?;
+        context
+            .stats_cache
+            .adjust_messages(self, chat.blocked, -(deleted_msgs as i64));
+        if chat.blocked == Blocked::Not {
+            context.stats_cache.adjust(Counter::Chats, -1);
+        }
 
         context.emit_event(EventType::MsgsChanged {
             msg_id: MsgId::new(0),
             chat_id: ChatId::new(0),
         });
 
+        if delete_on_server {
+            cleanup::schedule_trashed(context).await;
+        }
         job::kill_action(context, Action::Housekeeping).await;
         let j = job::Job::new(Action::Housekeeping, 0, Params::new(), 10);
         job::add(context, j).await;
@@ -427,11 +723,14 @@ pub async fn set_draft(self, context: &Context, msg: Option<&mut Message>) {
 
     // similar to as dc_set_draft() but does not emit an event
     async fn set_draft_raw(self, context: &Context, msg: &mut Message) -> bool {
-        let deleted = self.maybe_delete_draft(context).await;
-        let set = self.do_set_draft(context, msg).await.is_ok();
-
-        // Can't inline. Both functions above must be called, no shortcut!
-        deleted || set
+        match self.do_set_draft(context, msg).await {
+            Ok(()) => true,
+            Err(err) => {
+                info!(context, "Not setting draft: {}", err);
+                // An unusable draft still replaces the previous one.
+                self.maybe_delete_draft(context).await
+            }
+        }
     }
 
     async fn get_draft_msg_id(self, context: &Context) -> Option<MsgId> {
@@ -445,6 +744,37 @@ async fn get_draft_msg_id(self, context: &Context) -> Option<MsgId> {
             .await
     }
 
+    /// Returns the text and the attachment of the draft without loading the whole message.
+    pub async fn get_draft_info(self, context: &Context) -> Result<Option<DraftInfo>, Error> {
+        if self.is_special() {
+            return Ok(None);
+        }
+        let row = context
+            .sql
+            .query_row_optional(
+                "SELECT txt, type, param FROM msgs WHERE chat_id=? AND state=?;",
+                paramsv![self, MessageState::OutDraft],
+                |row| {
+                    let text: String = row.get(0)?;
+                    let viewtype: Viewtype = row.get(1)?;
+                    let param: String = row.get(2)?;
+                    Ok((text, viewtype, param))
+                },
+            )
+            .await?;
+        let (text, viewtype, param) = match row {
+            Some(row) => row,
+            None => return Ok(None),
+        };
+        let param: Params = param.parse().unwrap_or_default();
+        Ok(Some(DraftInfo {
+            text,
+            viewtype,
+            file: param.get_path(Param::File, context).unwrap_or_default(),
+            mimetype: param.get(Param::MimeType).map(|s| s.to_string()),
+        }))
+    }
+
     pub async fn get_draft(self, context: &Context) -> Result<Option<Message>, Error> {
         if self.is_special() {
             return Ok(None);
@@ -463,14 +793,21 @@ pub async fn get_draft(self, context: &Context) -> Result<Option<Message>, Error
     /// Returns `true`, if message was deleted, `false` otherwise.
     async fn maybe_delete_draft(self, context: &Context) -> bool {
         match self.get_draft_msg_id(context).await {
-            Some(msg_id) => msg_id.delete_from_db(context).await.is_ok(),
+            Some(msg_id) => {
+                let deleted = msg_id.delete_from_db(context).await.is_ok();
+                if deleted {
+                    stats::adjust_chat_messages(context, self, -1).await;
+                }
+                deleted
+            }
             None => false,
         }
     }
 
-    /// Set provided message as draft message for specified chat.
+    /// Set provided message as draft message for specified chat, replacing the previous draft.
     ///
-    /// Return true on success, false on database error.
+    /// Fails without changing the database if the message cannot be used as draft,
+    /// [ChatId::set_draft_raw] deletes the previous draft then.
     async fn do_set_draft(self, context: &Context, msg: &mut Message) -> Result<(), Error> {
         match msg.viewtype {
             Viewtype::Unknown => bail!("Can not set draft of unknown type."),
@@ -478,6 +815,15 @@ async fn do_set_draft(self, context: &Context, msg: &mut Message) -> Result<(),
                 if msg.text.is_none_or_empty() && msg.in_reply_to.is_none_or_empty() {
                     bail!("No text and no quote in draft");
                 }
+                // The UI may attach a file before it decides on the type of the message,
+                // the file is kept with the draft then.
+                if let Some(blob) = msg
+                    .param
+                    .get_blob(Param::File, context, !msg.is_increation())
+                    .await?
+                {
+                    msg.param.set(Param::File, blob.as_name());
+                }
             }
             _ => {
                 let blob = msg
@@ -494,24 +840,38 @@ async fn do_set_draft(self, context: &Context, msg: &mut Message) -> Result<(),
             bail!("Can't set a draft: Can't send");
         }
 
-        context
+        // The previous draft is replaced in one transaction together with the attachment
+        // parameter, so housekeeping always sees a draft referencing the attachment.
+        let replaced = context
             .sql
-            .execute(
-                "INSERT INTO msgs (chat_id, from_id, timestamp, type, state, txt, param, hidden, mime_in_reply_to)
-         VALUES (?,?,?, ?,?,?,?,?,?);",
-                paramsv![
-                    self,
-                    DC_CONTACT_ID_SELF,
-                    time(),
-                    msg.viewtype,
-                    MessageState::OutDraft,
-                    msg.text.as_deref().unwrap_or(""),
-                    msg.param.to_string(),
-                    1,
-                    msg.in_reply_to.as_deref().unwrap_or_default(),
-                ],
-            )
+            .transaction(|tx| {
+                let replaced = tx.execute(
+                    "DELETE FROM msgs WHERE chat_id=? AND state=?;",
+                    rusqlite::params![self, MessageState::OutDraft],
+                )?;
+                tx.execute(
+                    "INSERT INTO msgs (chat_id, from_id, timestamp, type, state, txt, txt_folded, param, hidden, mime_in_reply_to, quoted_msg_id)
+             VALUES (?,?,?, ?,?,?,?,?,?,?,?);",
+                    rusqlite::params![
+                        self,
+                        DC_CONTACT_ID_SELF,
+                        time(),
+                        msg.viewtype,
+                        MessageState::OutDraft,
+                        msg.text.as_deref().unwrap_or(""),
+                        message::search_fold(msg.text.as_deref().unwrap_or("")),
+                        msg.param.to_string(),
+                        1,
+                        msg.in_reply_to.as_deref().unwrap_or_default(),
+                        msg.quoted_msg_id,
+                    ],
+                )?;
+                Ok(replaced)
+            })
             .await?;
+        context
+            .stats_cache
+            .adjust_messages(self, chat.blocked, 1 - replaced as i64);
         Ok(())
     }
 
@@ -586,7 +946,7 @@ async fn parent_query<T, F>(
         let sql = &context.sql;
         let query = format!(
             "SELECT {} \
-             FROM msgs WHERE chat_id=? AND state NOT IN (?, ?, ?, ?) AND NOT hidden \
+             FROM msgs WHERE chat_id=? AND state NOT IN (?, ?, ?, ?, ?) AND NOT hidden \
              ORDER BY timestamp DESC, id DESC \
              LIMIT 1;",
             fields
@@ -598,6 +958,7 @@ async fn parent_query<T, F>(
                 MessageState::OutPreparing,
                 MessageState::OutDraft,
                 MessageState::OutPending,
+                MessageState::OutScheduled,
                 MessageState::OutFailed
             ],
             f,
@@ -643,26 +1004,67 @@ async fn get_parent_mime_headers(self, context: &Context) -> Option<(String, Str
     /// prefer plaintext emails.
     ///
     /// To get more verbose summary for a contact, including its key fingerprint, use [`Contact::get_encrinfo`].
-    pub async fn get_encryption_info(self, context: &Context) -> Result<String, Error> {
-        let mut ret = String::new();
+    /// Returns the encryption state of the chat and of each member.
+    ///
+    /// The verdict is determined the same way as when sending a message to the chat.
+    pub async fn get_encryption_info_structured(
+        self,
+        context: &Context,
+    ) -> Result<EncryptionInfo, Error> {
+        let chat = Chat::load_from_db(context, self).await?;
 
+        let mut members = Vec::new();
+        let mut peerstates = Vec::new();
         for contact_id in get_chat_contacts(context, self)
             .await
-            .iter()
-            .filter(|&contact_id| *contact_id > DC_CONTACT_ID_LAST_SPECIAL)
+            .into_iter()
+            .filter(|contact_id| *contact_id > DC_CONTACT_ID_LAST_SPECIAL)
         {
-            let contact = Contact::load_from_db(context, *contact_id).await?;
-            let addr = contact.get_addr();
-            let peerstate = Peerstate::from_addr(context, addr).await?;
-
-            let stock_message = match peerstate
-                .filter(|peerstate| {
-                    peerstate
-                        .peek_key(PeerstateVerifiedStatus::Unverified)
-                        .is_some()
-                })
-                .map(|peerstate| peerstate.prefer_encrypt)
-            {
+            let contact = Contact::load_from_db(context, contact_id).await?;
+            let peerstate = Peerstate::from_addr(context, contact.get_addr()).await?;
+            let key = peerstate
+                .as_ref()
+                .and_then(|peerstate| peerstate.peek_key(PeerstateVerifiedStatus::Unverified));
+            members.push(MemberEncryptionInfo {
+                contact_id,
+                addr: contact.get_addr().to_string(),
+                prefer_encrypt: key
+                    .and(peerstate.as_ref())
+                    .map(|peerstate| peerstate.prefer_encrypt),
+                fingerprint: key.map(|key| key.fingerprint()),
+                verified: contact.is_verified_ex(context, peerstate.as_ref()).await,
+            });
+            peerstates.push(peerstate);
+        }
+
+        let verdict = if chat.is_protected() {
+            EncryptionVerdict::Guaranteed
+        } else {
+            let own_preference =
+                EncryptPreference::from_i32(context.get_config_int(Config::E2eeEnabled).await)
+                    .unwrap_or_default();
+            let peerstates: Vec<(Option<Peerstate>, &str)> = peerstates
+                .into_iter()
+                .zip(members.iter())
+                .map(|(peerstate, member)| (peerstate, member.addr.as_str()))
+                .collect();
+            if e2ee::should_encrypt(context, own_preference, false, &peerstates)? {
+                EncryptionVerdict::Opportunistic
+            } else {
+                EncryptionVerdict::Unencrypted
+            }
+        };
+
+        Ok(EncryptionInfo { verdict, members })
+    }
+
+    /// Returns a human-readable description of the encryption state of each member.
+    pub async fn get_encryption_info(self, context: &Context) -> Result<String, Error> {
+        let info = self.get_encryption_info_structured(context).await?;
+
+        let mut ret = String::new();
+        for member in info.members {
+            let stock_message = match member.prefer_encrypt {
                 Some(EncryptPreference::Mutual) => stock_str::e2e_preferred(context).await,
                 Some(EncryptPreference::NoPreference) => stock_str::e2e_available(context).await,
                 Some(EncryptPreference::Reset) => stock_str::encr_none(context).await,
@@ -671,7 +1073,7 @@ pub async fn get_encryption_info(self, context: &Context) -> Result<String, Erro
             if !ret.is_empty() {
                 ret.push('\n')
             }
-            ret += &format!("{} {}", addr, stock_message);
+            ret += &format!("{} {}", member.addr, stock_message);
         }
 
         Ok(ret)
@@ -750,31 +1152,18 @@ pub struct Chat {
 impl Chat {
     /// Loads chat from the database by its ID.
     pub async fn load_from_db(context: &Context, chat_id: ChatId) -> Result<Self, Error> {
-        let res = context
-            .sql
-            .query_row(
-                "SELECT c.type, c.name, c.grpid, c.param, c.archived,
-                    c.blocked, c.locations_send_until, c.muted_until, c.protected
-             FROM chats c
-             WHERE c.id=?;",
-                paramsv![chat_id],
-                |row| {
-                    let c = Chat {
-                        id: chat_id,
-                        typ: row.get(0)?,
-                        name: row.get::<_, String>(1)?,
-                        grpid: row.get::<_, String>(2)?,
-                        param: row.get::<_, String>(3)?.parse().unwrap_or_default(),
-                        visibility: row.get(4)?,
-                        blocked: row.get::<_, Option<_>>(5)?.unwrap_or_default(),
-                        is_sending_locations: row.get(6)?,
-                        mute_duration: row.get(7)?,
-                        protected: row.get(8)?,
-                    };
-                    Ok(c)
-                },
-            )
-            .await;
+        let caches = context.sql.caches();
+        let res = match caches.get_chat(chat_id.to_u32()) {
+            Some(chat) => Ok(chat),
+            None => {
+                let generation = caches.generation();
+                let res = Self::load_row(context, chat_id).await;
+                if let Ok(chat) = &res {
+                    caches.put_chat(chat_id.to_u32(), chat.clone(), generation);
+                }
+                res
+            }
+        };
 
         match res {
             Err(err @ crate::sql::Error::Sql(rusqlite::Error::QueryReturnedNoRows)) => {
@@ -816,6 +1205,35 @@ pub async fn load_from_db(context: &Context, chat_id: ChatId) -> Result<Self, Er
         }
     }
 
+    /// Loads the chat as stored in the database, without the names computed on loading.
+    async fn load_row(context: &Context, chat_id: ChatId) -> crate::sql::Result<Self> {
+        context
+            .sql
+            .query_row(
+                "SELECT c.type, c.name, c.grpid, c.param, c.archived,
+                    c.blocked, c.locations_send_until, c.muted_until, c.protected
+             FROM chats c
+             WHERE c.id=?;",
+                paramsv![chat_id],
+                |row| {
+                    let c = Chat {
+                        id: chat_id,
+                        typ: row.get(0)?,
+                        name: row.get::<_, String>(1)?,
+                        grpid: row.get::<_, String>(2)?,
+                        param: row.get::<_, String>(3)?.parse().unwrap_or_default(),
+                        visibility: row.get(4)?,
+                        blocked: row.get::<_, Option<_>>(5)?.unwrap_or_default(),
+                        is_sending_locations: row.get(6)?,
+                        mute_duration: row.get(7)?,
+                        protected: row.get(8)?,
+                    };
+                    Ok(c)
+                },
+            )
+            .await
+    }
+
     pub fn is_self_talk(&self) -> bool {
         self.param.exists(Param::Selftalk)
     }
@@ -830,8 +1248,12 @@ pub fn is_mailing_list(&self) -> bool {
     }
 
     /// Returns true if user can send messages to this chat.
+    ///
+    /// Mailing lists can only be sent to if they have an address to post to.
     pub fn can_send(&self) -> bool {
-        !self.id.is_special() && !self.is_device_talk() && !self.is_mailing_list()
+        !self.id.is_special()
+            && !self.is_device_talk()
+            && (!self.is_mailing_list() || self.param.exists(Param::ListPost))
     }
 
     pub async fn update_param(&mut self, context: &Context) -> Result<(), Error> {
@@ -881,6 +1303,11 @@ pub async fn get_gossiped_timestamp(&self, context: &Context) -> i64 {
         get_gossiped_timestamp(context, self.id).await
     }
 
+    /// Returns the color of the chat, see [crate::color].
+    ///
+    /// One-to-one chats have the color of the contact.  Other chats derive the color from the
+    /// group ID, so it does not change when the chat is renamed, chats without group ID use
+    /// the name.
     pub async fn get_color(&self, context: &Context) -> u32 {
         let mut color = 0;
 
@@ -891,6 +1318,8 @@ pub async fn get_color(&self, context: &Context) -> u32 {
                     color = contact.get_color();
                 }
             }
+        } else if !self.grpid.is_empty() {
+            color = str_to_color(&self.grpid);
         } else {
             color = str_to_color(&self.name);
         }
@@ -911,7 +1340,7 @@ pub async fn get_info(&self, context: &Context) -> Result<ChatInfo, Error> {
             id: self.id,
             type_: self.typ as u32,
             name: self.name.clone(),
-            archived: self.visibility == ChatVisibility::Archived,
+            archived: self.visibility.is_archived(),
             param: self.param.to_string(),
             gossiped_timestamp: self.get_gossiped_timestamp(context).await,
             is_sending_locations: self.is_sending_locations,
@@ -949,6 +1378,14 @@ pub fn is_sending_locations(&self) -> bool {
         self.is_sending_locations
     }
 
+    /// Returns how notifications of the chat are presented, see [ChatId::set_notify_profile].
+    pub fn get_notify_profile(&self) -> NotifyProfile {
+        self.param
+            .get_int(Param::NotifyProfile)
+            .and_then(NotifyProfile::from_i32)
+            .unwrap_or_default()
+    }
+
     pub fn is_muted(&self) -> bool {
         match self.mute_duration {
             MuteDuration::NotMuted => false,
@@ -968,7 +1405,11 @@ async fn prepare_msg_raw(
         let mut to_id = 0;
         let mut location_id = 0;
 
-        if !(self.typ == Chattype::Single || self.typ == Chattype::Group) {
+        if !(self.typ == Chattype::Single
+            || self.typ == Chattype::Group
+            || self.typ == Chattype::Broadcast
+            || (self.typ == Chattype::Mailinglist && self.param.exists(Param::ListPost)))
+        {
             error!(context, "Cannot send to chat type #{}.", self.typ,);
             bail!("Cannot set to chat type #{}", self.typ);
         }
@@ -1084,8 +1525,8 @@ async fn prepare_msg_raw(
                         timestamp,
                         DC_CONTACT_ID_SELF,
                         self.id,
-                        msg.param.get_float(Param::SetLatitude).unwrap_or_default(),
-                        msg.param.get_float(Param::SetLongitude).unwrap_or_default(),
+                        msg.param.get_f64(Param::SetLatitude).unwrap_or_default(),
+                        msg.param.get_f64(Param::SetLongitude).unwrap_or_default(),
                     ],
                 )
                 .await
@@ -1143,17 +1584,19 @@ async fn prepare_msg_raw(
                         type,
                         state,
                         txt,
+                        txt_folded,
                         subject,
                         param,
                         hidden,
                         mime_in_reply_to,
+                        quoted_msg_id,
                         mime_references,
                         mime_modified,
                         mime_headers,
                         location_id,
                         ephemeral_timer,
                         ephemeral_timestamp)
-                        VALUES (?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?);",
+                        VALUES (?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?);",
                 paramsv![
                     new_rfc724_mid,
                     self.id,
@@ -1163,10 +1606,12 @@ async fn prepare_msg_raw(
                     msg.viewtype,
                     msg.state,
                     msg.text.as_ref().cloned().unwrap_or_default(),
+                    message::search_fold(msg.text.as_deref().unwrap_or_default()),
                     &msg.subject,
                     msg.param.to_string(),
                     msg.hidden,
                     msg.in_reply_to.as_deref().unwrap_or_default(),
+                    msg.quoted_msg_id,
                     new_references,
                     new_mime_headers.is_some(),
                     new_mime_headers,
@@ -1180,9 +1625,15 @@ async fn prepare_msg_raw(
         {
             msg_id = context
                 .sql
-                .get_rowid(context, "msgs", "rfc724_mid", new_rfc724_mid)
+                .get_rowid(context, "msgs", "rfc724_mid", &new_rfc724_mid)
                 .await?;
-        } else {
+            context
+                .sql
+                .rfc724_mid_inserted(&new_rfc724_mid, MsgId::new(msg_id));
+            context
+                .stats_cache
+                .adjust_messages(self.id, self.blocked, 1);
+        } else {
             error!(
                 context,
                 "Cannot send message, cannot insert to database ({}).", self.id,
@@ -1199,6 +1650,19 @@ pub enum ChatVisibility {
     Normal,
     Archived,
     Pinned,
+
+    /// Archived, but not unarchived by new incoming messages.
+    ///
+    /// Useful for noisy chats as mailing lists,
+    /// new messages are still counted as fresh.
+    NoUnarchive,
+}
+
+impl ChatVisibility {
+    /// Returns true if the chat is shown in the list of archived chats.
+    pub fn is_archived(self) -> bool {
+        matches!(self, ChatVisibility::Archived | ChatVisibility::NoUnarchive)
+    }
 }
 
 impl rusqlite::types::ToSql for ChatVisibility {
@@ -1207,6 +1671,7 @@ fn to_sql(&self) -> rusqlite::Result<rusqlite::types::ToSqlOutput> {
             ChatVisibility::Normal => 0,
             ChatVisibility::Archived => 1,
             ChatVisibility::Pinned => 2,
+            ChatVisibility::NoUnarchive => 3,
         };
         let val = rusqlite::types::Value::Integer(visibility);
         let out = rusqlite::types::ToSqlOutput::Owned(val);
@@ -1218,6 +1683,7 @@ impl rusqlite::types::FromSql for ChatVisibility {
     fn column_result(value: rusqlite::types::ValueRef) -> rusqlite::types::FromSqlResult<Self> {
         i64::column_result(value).map(|val| {
             match val {
+                3 => ChatVisibility::NoUnarchive,
                 2 => ChatVisibility::Pinned,
                 1 => ChatVisibility::Archived,
                 0 => ChatVisibility::Normal,
@@ -1228,6 +1694,49 @@ fn column_result(value: rusqlite::types::ValueRef) -> rusqlite::types::FromSqlRe
     }
 }
 
+/// Whether messages sent to a chat are end-to-end encrypted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EncryptionVerdict {
+    /// Messages are always encrypted, sending fails if a member has no key.
+    Guaranteed,
+
+    /// Messages are encrypted as the user and the members prefer encryption,
+    /// this may change when a member resets encryption or a member without key is added.
+    Opportunistic,
+
+    /// Messages are sent unencrypted.
+    Unencrypted,
+}
+
+/// The encryption state of a chat member, see [ChatId::get_encryption_info_structured].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MemberEncryptionInfo {
+    /// The contact ID of the member.
+    pub contact_id: u32,
+
+    /// The address of the member.
+    pub addr: String,
+
+    /// The encryption preference of the member, `None` if there is no key of the member.
+    pub prefer_encrypt: Option<EncryptPreference>,
+
+    /// The fingerprint of the key used to encrypt to the member.
+    pub fingerprint: Option<Fingerprint>,
+
+    /// Whether the member is verified.
+    pub verified: VerifiedStatus,
+}
+
+/// The encryption state of a chat, see [ChatId::get_encryption_info_structured].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EncryptionInfo {
+    /// Whether messages sent to the chat are end-to-end encrypted.
+    pub verdict: EncryptionVerdict,
+
+    /// The encryption state of each member, not including the user.
+    pub members: Vec<MemberEncryptionInfo>,
+}
+
 /// The current state of a chat.
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[non_exhaustive]
@@ -1296,6 +1805,22 @@ pub struct ChatInfo {
     // - [ ] email
 }
 
+/// Text and attachment of a draft, see [ChatId::get_draft_info].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DraftInfo {
+    /// The draft text, empty if there is none.
+    pub text: String,
+
+    /// Type of the draft, [Viewtype::Text] if there is no attachment.
+    pub viewtype: Viewtype,
+
+    /// Path of the attachment.
+    pub file: Option<PathBuf>,
+
+    /// MIME type of the attachment, if known.
+    pub mimetype: Option<String>,
+}
+
 /// Create a chat from a message ID.
 ///
 /// Typically you'd do this for a message ID found in the
@@ -1341,6 +1866,144 @@ pub async fn create_by_msg_id(context: &Context, msg_id: MsgId) -> Result<ChatId
     Ok(chat.id)
 }
 
+/// Returns the chat type if the chat is a contact request.
+fn get_contact_request_type(
+    tx: &rusqlite::Transaction<'_>,
+    chat_id: ChatId,
+) -> rusqlite::Result<Option<Chattype>> {
+    use rusqlite::OptionalExtension;
+
+    tx.query_row(
+        "SELECT type FROM chats WHERE id=? AND blocked=?;",
+        params![chat_id, Blocked::Deaddrop],
+        |row| row.get(0),
+    )
+    .optional()
+}
+
+/// Returns the contacts who sent messages to the chat.
+///
+/// As the senders are looked up when deciding on the contact request,
+/// messages arriving after the chat was listed are taken into account.
+fn get_chat_senders(tx: &rusqlite::Transaction<'_>, chat_id: ChatId) -> rusqlite::Result<Vec<u32>> {
+    let mut stmt =
+        tx.prepare("SELECT DISTINCT from_id FROM msgs WHERE chat_id=? AND from_id>?;")?;
+    let senders = stmt
+        .query_map(params![chat_id, DC_CONTACT_ID_LAST_SPECIAL], |row| {
+            row.get(0)
+        })?
+        .collect::<rusqlite::Result<Vec<u32>>>()?;
+    Ok(senders)
+}
+
+/// Accepts the contact requests of the given chats in a single transaction.
+///
+/// Like [create_by_msg_id] for a message of each chat,
+/// the chats are unblocked and the senders become known contacts,
+/// except for mailing lists.
+/// Chats which are not contact requests anymore, e.g. accepted meanwhile, are skipped.
+pub async fn accept_contact_requests(context: &Context, chat_ids: &[ChatId]) -> Result<(), Error> {
+    let accepted = context
+        .sql
+        .transaction(|tx| {
+            let mut accepted = Vec::new();
+            for chat_id in chat_ids {
+                let chat_type = match get_contact_request_type(tx, *chat_id)? {
+                    Some(chat_type) => chat_type,
+                    None => continue,
+                };
+                tx.execute(
+                    "UPDATE chats SET blocked=? WHERE id=?;",
+                    params![Blocked::Not, chat_id],
+                )?;
+                // If the chat is a mailing list, the contacts are not counted as "known"
+                if chat_type != Chattype::Mailinglist {
+                    for contact_id in get_chat_senders(tx, *chat_id)? {
+                        tx.execute(
+                            "UPDATE contacts SET origin=? WHERE id=? AND origin<?;",
+                            params![Origin::CreateChat, contact_id, Origin::CreateChat],
+                        )?;
+                    }
+                }
+                accepted.push(*chat_id);
+            }
+            Ok(accepted)
+        })
+        .await?;
+
+    if !accepted.is_empty() {
+        // Messages move between the counters, which can not be adjusted without counting them.
+        context.stats_cache.invalidate();
+    }
+    emit_contact_requests_changed(context, &accepted);
+    Ok(())
+}
+
+/// Blocks the contact requests of the given chats in a single transaction.
+///
+/// Like deciding to block a single contact request, the senders are blocked,
+/// mailing lists are blocked as a whole.
+/// Chats which are not contact requests anymore, e.g. accepted meanwhile, are skipped.
+pub async fn block_contact_requests(context: &Context, chat_ids: &[ChatId]) -> Result<(), Error> {
+    let blocked = context
+        .sql
+        .transaction(|tx| {
+            let mut blocked = Vec::new();
+            for chat_id in chat_ids {
+                let chat_type = match get_contact_request_type(tx, *chat_id)? {
+                    Some(chat_type) => chat_type,
+                    None => continue,
+                };
+                if chat_type == Chattype::Mailinglist {
+                    tx.execute(
+                        "UPDATE chats SET blocked=? WHERE id=?;",
+                        params![Blocked::Manually, chat_id],
+                    )?;
+                } else {
+                    for contact_id in get_chat_senders(tx, *chat_id)? {
+                        tx.execute(
+                            "UPDATE contacts SET blocked=1 WHERE id=?;",
+                            params![contact_id],
+                        )?;
+                        tx.execute(
+                            "UPDATE chats SET blocked=? WHERE type=? AND id IN \
+                             (SELECT chat_id FROM chats_contacts WHERE contact_id=?);",
+                            params![Blocked::Manually, Chattype::Single, contact_id],
+                        )?;
+                        tx.execute(
+                            "UPDATE msgs SET state=? WHERE from_id=? AND state=?;",
+                            params![MessageState::InNoticed, contact_id, MessageState::InFresh],
+                        )?;
+                    }
+                }
+                blocked.push(*chat_id);
+            }
+            Ok(blocked)
+        })
+        .await?;
+
+    if !blocked.is_empty() {
+        context.stats_cache.invalidate();
+        context.emit_event(EventType::ContactsChanged(None));
+    }
+    emit_contact_requests_changed(context, &blocked);
+    Ok(())
+}
+
+fn emit_contact_requests_changed(context: &Context, chat_ids: &[ChatId]) {
+    if chat_ids.is_empty() {
+        return;
+    }
+    // Sending with 0s as data since multiple messages may have changed.
+    context.emit_event(EventType::MsgsChanged {
+        chat_id: ChatId::new(0),
+        msg_id: MsgId::new(0),
+    });
+    for chat_id in chat_ids {
+        context.emit_event(EventType::ChatModified(*chat_id));
+    }
+}
+
 /// Create a normal chat with a single user.
 ///
 /// To create group chats, see [`create_group_chat`].
@@ -1370,6 +2033,11 @@ pub async fn create_by_contact_id(context: &Context, contact_id: u32) -> Result<
                 let (chat_id, _) =
                     create_or_lookup_by_contact_id(context, contact_id, Blocked::Not).await?;
                 Contact::scaleup_origin_by_id(context, contact_id, Origin::CreateChat).await;
+                // Tell the contact about the timer the new chat inherited.
+                let timer = chat_id.get_ephemeral_timer(context).await?;
+                if timer != EphemeralTimer::Disabled {
+                    chat_id.send_ephemeral_timer_changed(context, timer).await;
+                }
                 chat_id
             }
         }
@@ -1383,16 +2051,52 @@ pub async fn create_by_contact_id(context: &Context, contact_id: u32) -> Result<
     Ok(chat_id)
 }
 
+/// Version of the bundled icons of the "Saved messages" and "Device messages" chats,
+/// to be increased when the icons change.
+pub(crate) const DEVICE_ICONS_VERSION: i32 = 1;
+
+/// Raw config key storing the [DEVICE_ICONS_VERSION] the icons were last updated to.
+pub(crate) const DEVICE_ICONS_VERSION_KEY: &str = "device_icons_version";
+
+/// Returns the blob name of the bundled `icon`.
+///
+/// The file `current` is reused if it has the same content,
+/// so that updating the icons again does not write any files.
+async fn get_icon_blob(
+    context: &Context,
+    current: &Params,
+    name: &str,
+    icon: &[u8],
+) -> Result<String, Error> {
+    if let Ok(Some(path)) = current.get_path(Param::ProfileImage, context) {
+        if let Ok(data) = async_std::fs::read(&path).await {
+            if blake3::hash(&data) == blake3::hash(icon) {
+                if let Some(current) = current.get(Param::ProfileImage) {
+                    return Ok(current.to_string());
+                }
+            }
+        }
+    }
+    let blob = BlobObject::create(context, name, icon).await?;
+    Ok(blob.as_name().to_string())
+}
+
 pub(crate) async fn update_saved_messages_icon(context: &Context) -> Result<(), Error> {
     // if there is no saved-messages chat, there is nothing to update. this is no error.
     if let Ok((chat_id, _)) = lookup_by_contact_id(context, DC_CONTACT_ID_SELF).await {
-        let icon = include_bytes!("../assets/icon-saved-messages.png");
-        let blob = BlobObject::create(context, "icon-saved-messages.png".to_string(), icon).await?;
-        let icon = blob.as_name().to_string();
-
         let mut chat = Chat::load_from_db(context, chat_id).await?;
-        chat.param.set(Param::ProfileImage, icon);
-        chat.update_param(context).await?;
+        let icon = get_icon_blob(
+            context,
+            &chat.param,
+            "icon-saved-messages.png",
+            include_bytes!("../assets/icon-saved-messages.png"),
+        )
+        .await?;
+
+        if chat.param.get(Param::ProfileImage) != Some(icon.as_str()) {
+            chat.param.set(Param::ProfileImage, icon);
+            chat.update_param(context).await?;
+        }
     }
     Ok(())
 }
@@ -1400,17 +2104,25 @@ pub(crate) async fn update_saved_messages_icon(context: &Context) -> Result<(),
 pub(crate) async fn update_device_icon(context: &Context) -> Result<(), Error> {
     // if there is no device-chat, there is nothing to update. this is no error.
     if let Ok((chat_id, _)) = lookup_by_contact_id(context, DC_CONTACT_ID_DEVICE).await {
-        let icon = include_bytes!("../assets/icon-device.png");
-        let blob = BlobObject::create(context, "icon-device.png".to_string(), icon).await?;
-        let icon = blob.as_name().to_string();
-
         let mut chat = Chat::load_from_db(context, chat_id).await?;
-        chat.param.set(Param::ProfileImage, &icon);
-        chat.update_param(context).await?;
+        let icon = get_icon_blob(
+            context,
+            &chat.param,
+            "icon-device.png",
+            include_bytes!("../assets/icon-device.png"),
+        )
+        .await?;
+
+        if chat.param.get(Param::ProfileImage) != Some(icon.as_str()) {
+            chat.param.set(Param::ProfileImage, &icon);
+            chat.update_param(context).await?;
+        }
 
         let mut contact = Contact::load_from_db(context, DC_CONTACT_ID_DEVICE).await?;
-        contact.param.set(Param::ProfileImage, icon);
-        contact.update_param(context).await?;
+        if contact.param.get(Param::ProfileImage) != Some(icon.as_str()) {
+            contact.param.set(Param::ProfileImage, icon);
+            contact.update_param(context).await?;
+        }
     }
     Ok(())
 }
@@ -1494,14 +2206,30 @@ pub(crate) async fn create_or_lookup_by_contact_id(
             Ok(())
         })
         .await?;
+    if create_blocked == Blocked::Not {
+        context.stats_cache.adjust(Counter::Chats, 1);
+    }
 
+    // Icons are only cosmetic, the chat is usable without them.
     if contact_id == DC_CONTACT_ID_SELF {
-        update_saved_messages_icon(context).await?;
+        if let Err(err) = update_saved_messages_icon(context).await {
+            warn!(context, "Failed to update saved messages icon: {:#}", err);
+        }
     } else if contact_id == DC_CONTACT_ID_DEVICE {
-        update_device_icon(context).await?;
+        if let Err(err) = update_device_icon(context).await {
+            warn!(context, "Failed to update device icon: {:#}", err);
+        }
     }
 
-    lookup_by_contact_id(context, contact_id).await
+    let (chat_id, chat_blocked) = lookup_by_contact_id(context, contact_id).await?;
+    if contact_id > DC_CONTACT_ID_LAST_SPECIAL {
+        // New chats inherit the default timer of the contact, existing chats keep theirs.
+        let timer = Contact::get_default_ephemeral_timer(context, contact_id).await?;
+        if timer != EphemeralTimer::Disabled {
+            chat_id.inner_set_ephemeral_timer(context, timer).await?;
+        }
+    }
+    Ok((chat_id, chat_blocked))
 }
 
 pub(crate) async fn lookup_by_contact_id(
@@ -1578,7 +2306,7 @@ async fn prepare_msg_blob(context: &Context, msg: &mut Message) -> Result<(), Er
     if msg.viewtype == Viewtype::Text || msg.viewtype == Viewtype::VideochatInvitation {
         // the caller should check if the message text is empty
     } else if msgtype_has_file(msg.viewtype) {
-        let blob = msg
+        let mut blob = msg
             .param
             .get_blob(Param::File, context, !msg.is_increation())
             .await?
@@ -1587,12 +2315,37 @@ async fn prepare_msg_blob(context: &Context, msg: &mut Message) -> Result<(), Er
             })?;
 
         if msg.viewtype == Viewtype::Image {
-            if let Err(e) = blob.recode_to_image_size(context).await {
-                warn!(context, "Cannot recode image, using original data: {:?}", e);
+            let quality =
+                MediaQuality::from_i32(context.get_config_int(Config::MediaQuality).await)
+                    .unwrap_or_default();
+            let src = blob.to_abs_path();
+            match BlobObject::create_and_recode(context, BlobSource::Path(&src), quality).await {
+                Ok(recoded) => {
+                    if let Some((width, height)) = recoded.dimensions {
+                        msg.param.set_i64(Param::Width, width.into());
+                        msg.param.set_i64(Param::Height, height.into());
+                    }
+                    if recoded.blob.suffix() != blob.suffix() {
+                        msg.param.remove(Param::MimeType);
+                    }
+                    blob = recoded.blob;
+                }
+                Err(e) => warn!(context, "Cannot recode image, using original data: {:?}", e),
             }
         }
         msg.param.set(Param::File, blob.as_name());
 
+        if msg.viewtype == Viewtype::Voice
+            && !msg.param.exists(Param::Waveform)
+            && !msg.is_increation()
+        {
+            // Voice messages are sent without waveform if the format is not supported.
+            match message::generate_waveform(context, &blob).await {
+                Ok(waveform) => msg.set_waveform(&waveform),
+                Err(err) => warn!(context, "Cannot generate waveform: {:#}", err),
+            }
+        }
+
         if msg.viewtype == Viewtype::File || msg.viewtype == Viewtype::Image {
             // Correct the type, take care not to correct already very special
             // formats as GIF or VOICE.
@@ -1637,11 +2390,28 @@ async fn prepare_msg_common(
     let mut chat = Chat::load_from_db(context, chat_id).await?;
     ensure!(chat.can_send(), "cannot send to {}", chat_id);
 
-    // The OutPreparing state is set by dc_prepare_msg() before it
-    // calls this function and the message is left in the OutPreparing
-    // state.  Otherwise we got called by send_msg() and we change the
-    // state to OutPending.
-    if msg.state != MessageState::OutPreparing {
+    // Member and protection changes are still sent, so that a broken chat can be repaired.
+    // Key changes audit the chat, so only chats known as broken are checked again here.
+    if chat.is_protected()
+        && chat.param.exists(Param::ProtectionBroken)
+        && msg.param.get_cmd() == SystemMessage::Unknown
+    {
+        if let ChatProtectionStatus::Broken { offending_contacts } =
+            chat_id.audit_protection(context).await?
+        {
+            bail!(
+                "Cannot send to protected chat {}, contacts {:?} are not verified anymore.",
+                chat_id,
+                offending_contacts
+            );
+        }
+    }
+
+    // The OutPreparing state is set by dc_prepare_msg() and the
+    // OutScheduled state by send_msg_at() before they call this function
+    // and the message is left in that state.  Otherwise we got called by
+    // send_msg() and we change the state to OutPending.
+    if msg.state != MessageState::OutPreparing && msg.state != MessageState::OutScheduled {
         msg.state = MessageState::OutPending;
     }
 
@@ -1653,6 +2423,28 @@ async fn prepare_msg_common(
     Ok(msg.id)
 }
 
+/// Returns true if the verified key of a member is still the key the member uses.
+///
+/// Arguments are the fingerprints of the verified, the Autocrypt and the gossiped key,
+/// the same as for checking incoming messages, the verified key is current if it is
+/// one of the others or if there are no others.
+fn is_verified_key_current(
+    verified: Option<String>,
+    public: Option<String>,
+    gossip: Option<String>,
+) -> bool {
+    let verified = match verified.filter(|fp| !fp.is_empty()) {
+        Some(verified) => verified,
+        None => return false,
+    };
+    let public = public.filter(|fp| !fp.is_empty());
+    let gossip = gossip.filter(|fp| !fp.is_empty());
+    if public.is_none() && gossip.is_none() {
+        return true;
+    }
+    public.as_ref() == Some(&verified) || gossip.as_ref() == Some(&verified)
+}
+
 /// Returns whether a contact is in a chat or not.
 pub async fn is_contact_in_chat(context: &Context, chat_id: ChatId, contact_id: u32) -> bool {
     // this function works for group and for normal chats, however, it is more useful
@@ -1684,6 +2476,10 @@ pub async fn send_msg(
     chat_id: ChatId,
     msg: &mut Message,
 ) -> Result<MsgId, Error> {
+    ensure!(
+        !context.is_shutting_down(),
+        "Cannot send message, context is shutting down"
+    );
     if chat_id.is_unset() {
         let forwards = msg.param.get(Param::PrepForwards);
         if let Some(forwards) = forwards {
@@ -1707,6 +2503,94 @@ pub async fn send_msg(
     send_msg_inner(context, chat_id, msg).await
 }
 
+/// Sends a message to a chat at a later time.
+///
+/// The message is added to the chat in the [MessageState::OutScheduled] state
+/// and handed to the SMTP thread like [send_msg] once `send_time` has come,
+/// or as soon as possible afterwards if the device was offline at that time.
+/// If `send_time` is not in the future, the message is sent immediately.
+///
+/// Scheduled messages can be cancelled with [message::cancel_scheduled].
+pub async fn send_msg_at(
+    context: &Context,
+    chat_id: ChatId,
+    msg: &mut Message,
+    send_time: i64,
+) -> Result<MsgId, Error> {
+    ensure!(
+        !chat_id.is_special(),
+        "Cannot schedule message for special chat"
+    );
+    let delay = send_time - time();
+    if delay <= 0 {
+        return send_msg(context, chat_id, msg).await;
+    }
+
+    msg.state = MessageState::OutScheduled;
+    let msg_id = prepare_msg_common(context, chat_id, msg).await?;
+    job::add(
+        context,
+        job::Job::new(
+            Action::SendScheduledMsg,
+            msg_id.to_u32(),
+            Params::new(),
+            delay,
+        ),
+    )
+    .await;
+    // Let the SMTP thread wake up in time for the new message.
+    context
+        .interrupt_smtp(InterruptInfo::new(false, None))
+        .await;
+    context.emit_event(EventType::MsgsChanged {
+        chat_id: msg.chat_id,
+        msg_id,
+    });
+
+    Ok(msg_id)
+}
+
+/// Passes a message scheduled by [send_msg_at] to the SMTP thread.
+///
+/// Messages which were cancelled in between are skipped.
+pub(crate) async fn send_scheduled_msg(context: &Context, msg_id: MsgId) -> Result<(), Error> {
+    let mut msg = match Message::load_from_db(context, msg_id).await {
+        Ok(msg) if msg.state == MessageState::OutScheduled => msg,
+        _ => {
+            info!(context, "Message {} is not scheduled anymore", msg_id);
+            return Ok(());
+        }
+    };
+
+    let res = async {
+        let chat = Chat::load_from_db(context, msg.chat_id).await?;
+        ensure!(chat.can_send(), "cannot send to {}", msg.chat_id);
+
+        // The message is sorted to the time it is actually sent.
+        let timestamp = dc_create_smeared_timestamp(context).await;
+        context
+            .sql
+            .execute(
+                "UPDATE msgs SET timestamp=? WHERE id=?;",
+                paramsv![timestamp, msg_id],
+            )
+            .await?;
+        msg.timestamp_sort = timestamp;
+        send_msg_inner(context, msg.chat_id, &mut msg).await
+    }
+    .await;
+    if let Err(ref err) = res {
+        message::set_msg_failed(
+            context,
+            msg_id,
+            ErrorCode::InvalidMessage,
+            Some(err.to_string()),
+        )
+        .await;
+    }
+    res.map(|_| ())
+}
+
 /// Tries to send a message synchronously.
 ///
 /// Directly  opens an smtp
@@ -1717,29 +2601,31 @@ pub async fn send_msg_sync(
     chat_id: ChatId,
     msg: &mut Message,
 ) -> Result<MsgId, Error> {
-    if let Some(mut job) = prepare_send_msg(context, chat_id, msg).await? {
-        let mut smtp = crate::smtp::Smtp::new();
+    let jobs = prepare_send_msg(context, chat_id, msg).await?;
+    if jobs.is_empty() {
+        // Nothing to do
+        return Ok(msg.id);
+    }
 
+    let mut smtp = crate::smtp::Smtp::new();
+    let mut queued = false;
+    for mut job in jobs {
         let status = job.send_msg_to_smtp(context, &mut smtp).await;
-
-        match status {
-            job::Status::Finished(Ok(_)) => {
-                context.emit_event(EventType::MsgsChanged {
-                    chat_id: msg.chat_id,
-                    msg_id: msg.id,
-                });
-
-                Ok(msg.id)
-            }
-            _ => {
-                job.save(context).await?;
-                Err(format_err!(
-                    "failed to send message, queued for later sending"
-                ))
-            }
+        if !matches!(status, job::Status::Finished(Ok(_))) {
+            job.save(context).await?;
+            queued = true;
         }
+    }
+
+    if queued {
+        Err(format_err!(
+            "failed to send message, queued for later sending"
+        ))
     } else {
-        // Nothing to do
+        context.emit_event(EventType::MsgsChanged {
+            chat_id: msg.chat_id,
+            msg_id: msg.id,
+        });
         Ok(msg.id)
     }
 }
@@ -1749,8 +2635,11 @@ async fn send_msg_inner(
     chat_id: ChatId,
     msg: &mut Message,
 ) -> Result<MsgId, Error> {
-    if let Some(send_job) = prepare_send_msg(context, chat_id, msg).await? {
-        job::add(context, send_job).await;
+    let jobs = prepare_send_msg(context, chat_id, msg).await?;
+    if !jobs.is_empty() {
+        for send_job in jobs {
+            job::add(context, send_job).await;
+        }
 
         context.emit_event(EventType::MsgsChanged {
             chat_id: msg.chat_id,
@@ -1769,12 +2658,12 @@ async fn prepare_send_msg(
     context: &Context,
     chat_id: ChatId,
     msg: &mut Message,
-) -> Result<Option<crate::job::Job>, Error> {
-    // dc_prepare_msg() leaves the message state to OutPreparing, we
-    // only have to change the state to OutPending in this case.
-    // Otherwise we still have to prepare the message, which will set
-    // the state to OutPending.
-    if msg.state != MessageState::OutPreparing {
+) -> Result<Vec<crate::job::Job>, Error> {
+    // dc_prepare_msg() leaves the message state to OutPreparing and
+    // send_msg_at() to OutScheduled, we only have to change the state to
+    // OutPending in these cases.  Otherwise we still have to prepare the
+    // message, which will set the state to OutPending.
+    if msg.state != MessageState::OutPreparing && msg.state != MessageState::OutScheduled {
         // automatically prepare normal messages
         prepare_msg_common(context, chat_id, msg).await?;
     } else {
@@ -1785,9 +2674,15 @@ async fn prepare_send_msg(
         );
         message::update_msg_state(context, msg.id, MessageState::OutPending).await;
     }
-    let job = job::send_msg_job(context, msg.id).await?;
+    // Large attachments are sent in separate messages after the message itself.
+    let chunks = chunks::prepare_chunks(context, msg).await?;
+    let mut jobs = job::send_msg_job(context, msg.id).await?;
+    for mut chunk in chunks {
+        prepare_msg_common(context, msg.chat_id, &mut chunk).await?;
+        jobs.extend(job::send_msg_job(context, chunk.id).await?);
+    }
 
-    Ok(job)
+    Ok(jobs)
 }
 
 pub async fn send_text_msg(
@@ -1806,6 +2701,37 @@ pub async fn send_text_msg(
     send_msg(context, chat_id, &mut msg).await
 }
 
+/// Sends a reaction to a message, e.g. an emoji.
+///
+/// The reaction replaces the previous reaction of the user to the message,
+/// an empty reaction removes it.
+/// The reaction is sent as a hidden message replying to the message,
+/// see [message::get_reactions] for the reactions to a message.
+pub async fn send_reaction(
+    context: &Context,
+    msg_id: MsgId,
+    reaction: &str,
+) -> Result<MsgId, Error> {
+    let target = Message::load_from_db(context, msg_id).await?;
+    ensure!(
+        !target.chat_id.is_special(),
+        "cannot react to message {} in special chat",
+        msg_id
+    );
+    let reaction = reaction.trim();
+
+    let mut msg = Message::new(Viewtype::Text);
+    msg.text = Some(reaction.to_string());
+    msg.hidden = true;
+    msg.in_reply_to = Some(target.rfc724_mid.clone());
+    msg.param.set_cmd(SystemMessage::Reaction);
+    msg.param.set(Param::Arg, reaction);
+    let reaction_msg_id = send_msg(context, target.chat_id, &mut msg).await?;
+
+    message::set_reaction(context, &target, DC_CONTACT_ID_SELF, reaction).await?;
+    Ok(reaction_msg_id)
+}
+
 pub async fn send_videochat_invitation(context: &Context, chat_id: ChatId) -> Result<MsgId, Error> {
     ensure!(
         !chat_id.is_special(),
@@ -1842,8 +2768,8 @@ pub async fn get_chat_msgs(
 ) -> Vec<ChatItem> {
     match delete_expired_messages(context).await {
         Err(err) => warn!(context, "Failed to delete expired messages: {}", err),
-        Ok(messages_deleted) => {
-            if messages_deleted {
+        Ok(deletion) => {
+            if deletion.any_deleted() {
                 // Trigger reload of chatlist.
                 //
                 // On desktop chatlist is always shown on the side,
@@ -2033,6 +2959,100 @@ pub async fn marknoticed_chat(context: &Context, chat_id: ChatId) -> Result<(),
     Ok(())
 }
 
+/// Marks the fresh messages of all chats as noticed.
+///
+/// As for `marknoticed_chat()`, contact requests are not affected,
+/// so messages in blocked chats and in the deaddrop stay fresh.
+/// `MsgsNoticed` is emitted for each chat that contained fresh messages.
+pub async fn marknoticed_all(context: &Context) -> Result<(), Error> {
+    let chat_ids = context
+        .sql
+        .with_conn(move |mut conn| {
+            let tx = conn.transaction()?;
+            // "WHERE" below uses the index `(state, hidden, chat_id)`, see get_fresh_msg_cnt()
+            let chat_ids = tx
+                .prepare(
+                    "SELECT DISTINCT chat_id FROM msgs
+                      WHERE state=? AND hidden=0
+                        AND chat_id IN (SELECT id FROM chats WHERE id>? AND blocked=0);",
+                )?
+                .query_map(
+                    params![MessageState::InFresh, DC_CHAT_ID_LAST_SPECIAL],
+                    |row| row.get::<_, ChatId>(0),
+                )?
+                .collect::<rusqlite::Result<Vec<_>>>()?;
+            tx.execute(
+                "UPDATE msgs
+                    SET state=?
+                  WHERE state=? AND hidden=0
+                    AND chat_id IN (SELECT id FROM chats WHERE id>? AND blocked=0);",
+                params![
+                    MessageState::InNoticed,
+                    MessageState::InFresh,
+                    DC_CHAT_ID_LAST_SPECIAL
+                ],
+            )?;
+            tx.commit()?;
+            Ok(chat_ids)
+        })
+        .await?;
+
+    for chat_id in chat_ids {
+        context.emit_event(EventType::MsgsNoticed(chat_id));
+    }
+
+    Ok(())
+}
+
+/// A message deleted with [crate::message::delete_msgs], see [get_trashed_msgs].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TrashedMsg {
+    pub msg_id: MsgId,
+
+    /// Chat the message was deleted from,
+    /// `None` if the message cannot be restored.
+    pub chat_id: Option<ChatId>,
+
+    /// Time of the deletion.
+    pub deleted_timestamp: i64,
+}
+
+/// Returns the messages in the trash, most recently deleted first.
+///
+/// Messages can be restored with [crate::message::restore_from_trash]
+/// as long as their chat is known.
+pub async fn get_trashed_msgs(
+    context: &Context,
+    limit: usize,
+    offset: usize,
+) -> Result<Vec<TrashedMsg>, Error> {
+    let msgs = context
+        .sql
+        .query_map(
+            "SELECT id, param, deleted_timestamp FROM msgs \
+             WHERE chat_id=? AND deleted_timestamp>0 AND NOT deleted_locally \
+             ORDER BY deleted_timestamp DESC, id DESC LIMIT ? OFFSET ?;",
+            paramsv![ChatId::new(DC_CHAT_ID_TRASH), limit as i64, offset as i64],
+            |row| {
+                let param: String = row.get(1)?;
+                let param: Params = param.parse().unwrap_or_default();
+                Ok(TrashedMsg {
+                    msg_id: row.get(0)?,
+                    chat_id: param
+                        .get_int(Param::TrashedChatId)
+                        .map(|chat_id| ChatId::new(chat_id as u32)),
+                    deleted_timestamp: row.get(2)?,
+                })
+            },
+            |rows| {
+                rows.collect::<std::result::Result<Vec<_>, _>>()
+                    .map_err(Into::into)
+            },
+        )
+        .await?;
+    Ok(msgs)
+}
+
 pub async fn get_chat_media(
     context: &Context,
     chat_id: ChatId,
@@ -2185,6 +3205,7 @@ pub async fn create_group_chat(
         .await?;
 
     let chat_id = ChatId::new(row_id);
+    context.stats_cache.adjust(Counter::Chats, 1);
     if add_to_chat_contacts_table(context, chat_id, DC_CONTACT_ID_SELF).await {
         let mut draft_msg = Message::new(Viewtype::Text);
         draft_msg.set_text(Some(draft_txt));
@@ -2205,28 +3226,64 @@ pub async fn create_group_chat(
     Ok(chat_id)
 }
 
-/// add a contact to the chats_contact table
-pub(crate) async fn add_to_chat_contacts_table(
+/// Creates a new broadcast list.
+///
+/// Messages sent to a broadcast list are sent out as separate 1:1 messages to each member,
+/// so that members do not see each other and replies end up in the 1:1 chats.
+/// Members are added and removed using [add_contact_to_chat] and [remove_contact_from_chat]
+/// without notifying anyone.
+pub async fn create_broadcast_list(
     context: &Context,
-    chat_id: ChatId,
-    contact_id: u32,
-) -> bool {
-    match context
+    chat_name: impl AsRef<str>,
+) -> Result<ChatId, Error> {
+    let chat_name = improve_single_line_input(chat_name);
+    ensure!(!chat_name.is_empty(), "Invalid chat name");
+
+    let grpid = dc_create_id();
+    context
         .sql
         .execute(
-            "INSERT INTO chats_contacts (chat_id, contact_id) VALUES(?, ?)",
-            paramsv![chat_id, contact_id as i32],
+            "INSERT INTO chats (type, name, grpid, param, created_timestamp) VALUES(?, ?, ?, '', ?);",
+            paramsv![Chattype::Broadcast, chat_name, grpid, time()],
         )
-        .await
-    {
-        Ok(_) => true,
-        Err(err) => {
-            error!(
-                context,
-                "could not add {} to chat {} table: {}", contact_id, chat_id, err
-            );
-
-            false
+        .await?;
+    let row_id = context
+        .sql
+        .get_rowid(context, "chats", "grpid", grpid)
+        .await?;
+    let chat_id = ChatId::new(row_id);
+    context.stats_cache.adjust(Counter::Chats, 1);
+
+    context.emit_event(EventType::MsgsChanged {
+        msg_id: MsgId::new(0),
+        chat_id: ChatId::new(0),
+    });
+
+    Ok(chat_id)
+}
+
+/// add a contact to the chats_contact table
+pub(crate) async fn add_to_chat_contacts_table(
+    context: &Context,
+    chat_id: ChatId,
+    contact_id: u32,
+) -> bool {
+    match context
+        .sql
+        .execute(
+            "INSERT INTO chats_contacts (chat_id, contact_id) VALUES(?, ?)",
+            paramsv![chat_id, contact_id as i32],
+        )
+        .await
+    {
+        Ok(_) => true,
+        Err(err) => {
+            error!(
+                context,
+                "could not add {} to chat {} table: {}", contact_id, chat_id, err
+            );
+
+            false
         }
     }
 }
@@ -2283,7 +3340,7 @@ pub(crate) async fn add_contact_to_chat_ex(
     /*this also makes sure, not contacts are added to special or normal chats*/
     let mut chat = Chat::load_from_db(context, chat_id).await?;
     ensure!(
-        chat.typ == Chattype::Group,
+        chat.typ == Chattype::Group || chat.typ == Chattype::Broadcast,
         "{} is not a group where one can add members",
         chat_id
     );
@@ -2294,7 +3351,9 @@ pub(crate) async fn add_contact_to_chat_ex(
     );
     ensure!(!chat.is_mailing_list(), "Mailing lists can't be changed");
 
-    if !is_contact_in_chat(context, chat_id, DC_CONTACT_ID_SELF as u32).await {
+    if chat.typ == Chattype::Group
+        && !is_contact_in_chat(context, chat_id, DC_CONTACT_ID_SELF as u32).await
+    {
         /* we should respect this - whatever we send to the group, it gets discarded anyway! */
         emit_event!(
             context,
@@ -2340,8 +3399,12 @@ pub(crate) async fn add_contact_to_chat_ex(
         if !add_to_chat_contacts_table(context, chat_id, contact_id).await {
             return Ok(false);
         }
+        if chat.typ == Chattype::Group {
+            update_member_list_timestamp(context, chat_id, time()).await?;
+        }
     }
-    if chat.param.get_int(Param::Unpromoted).unwrap_or_default() == 0 {
+    if chat.typ == Chattype::Group && chat.param.get_int(Param::Unpromoted).unwrap_or_default() == 0
+    {
         msg.viewtype = Viewtype::Text;
         msg.text =
             Some(stock_str::msg_add_member(context, contact.get_addr(), DC_CONTACT_ID_SELF).await);
@@ -2354,6 +3417,27 @@ pub(crate) async fn add_contact_to_chat_ex(
     Ok(true)
 }
 
+/// Records an explicit change of the group member list at `timestamp`.
+///
+/// Older timestamps are ignored.
+pub(crate) async fn update_member_list_timestamp(
+    context: &Context,
+    chat_id: ChatId,
+    timestamp: i64,
+) -> Result<(), Error> {
+    let mut chat = Chat::load_from_db(context, chat_id).await?;
+    if chat
+        .param
+        .get_i64(Param::MemberListTimestamp)
+        .unwrap_or_default()
+        < timestamp
+    {
+        chat.param.set_i64(Param::MemberListTimestamp, timestamp);
+        chat.update_param(context).await?;
+    }
+    Ok(())
+}
+
 pub(crate) async fn reset_gossiped_timestamp(
     context: &Context,
     chat_id: ChatId,
@@ -2439,10 +3523,14 @@ pub(crate) async fn shall_attach_selfavatar(
     Ok(needs_attach)
 }
 
+/// Mute state of a chat.
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum MuteDuration {
     NotMuted,
     Forever,
+
+    /// Muted until the given time, then the chat is unmuted automatically
+    /// and #DC_EVENT_CHAT_MODIFIED is emitted.
     Until(SystemTime),
 }
 
@@ -2473,6 +3561,8 @@ fn column_result(value: rusqlite::types::ValueRef) -> rusqlite::types::FromSqlRe
             0 => Ok(MuteDuration::NotMuted),
             -1 => Ok(MuteDuration::Forever),
             n if n > 0 => match SystemTime::UNIX_EPOCH.checked_add(Duration::from_secs(n as u64)) {
+                // An expired mute may not be reset in the database yet.
+                Some(t) if t <= SystemTime::now() => Ok(MuteDuration::NotMuted),
                 Some(t) => Ok(MuteDuration::Until(t)),
                 None => Err(rusqlite::types::FromSqlError::OutOfRange(n)),
             },
@@ -2486,21 +3576,7 @@ pub async fn set_muted(
     chat_id: ChatId,
     duration: MuteDuration,
 ) -> Result<(), Error> {
-    ensure!(!chat_id.is_special(), "Invalid chat ID");
-    if context
-        .sql
-        .execute(
-            "UPDATE chats SET muted_until=? WHERE id=?;",
-            paramsv![duration, chat_id],
-        )
-        .await
-        .is_ok()
-    {
-        context.emit_event(EventType::ChatModified(chat_id));
-    } else {
-        bail!("Failed to set mute duration, chat might not exist -");
-    }
-    Ok(())
+    chat_id.set_mute_duration(context, duration).await
 }
 
 pub async fn remove_contact_from_chat(
@@ -2524,7 +3600,12 @@ pub async fn remove_contact_from_chat(
     /* we do not check if "contact_id" exists but just delete all records with the id from chats_contacts */
     /* this allows to delete pending references to deleted contacts.  Of course, this should _not_ happen. */
     if let Ok(chat) = Chat::load_from_db(context, chat_id).await {
-        if chat.typ == Chattype::Group {
+        if chat.typ == Chattype::Broadcast {
+            // Members of broadcast lists do not know about each other,
+            // so there is no one to notify.
+            success = remove_from_chat_contacts_table(context, chat_id, contact_id).await;
+            context.emit_event(EventType::ChatModified(chat_id));
+        } else if chat.typ == Chattype::Group {
             if !is_contact_in_chat(context, chat_id, DC_CONTACT_ID_SELF).await {
                 emit_event!(
                     context,
@@ -2566,6 +3647,7 @@ pub async fn remove_contact_from_chat(
                 // removed it first, it would complicate the
                 // check/encryption logic.
                 success = remove_from_chat_contacts_table(context, chat_id, contact_id).await;
+                update_member_list_timestamp(context, chat_id, time()).await?;
                 context.emit_event(EventType::ChatModified(chat_id));
             }
         }
@@ -2621,10 +3703,15 @@ pub async fn set_chat_name(
     let chat = Chat::load_from_db(context, chat_id).await?;
     let mut msg = Message::default();
 
-    if chat.typ == Chattype::Group || chat.typ == Chattype::Mailinglist {
+    if chat.typ == Chattype::Group
+        || chat.typ == Chattype::Mailinglist
+        || chat.typ == Chattype::Broadcast
+    {
         if chat.name == new_name {
             success = true;
-        } else if !is_contact_in_chat(context, chat_id, DC_CONTACT_ID_SELF).await {
+        } else if chat.typ != Chattype::Broadcast
+            && !is_contact_in_chat(context, chat_id, DC_CONTACT_ID_SELF).await
+        {
             emit_event!(
                 context,
                 EventType::ErrorSelfNotInGroup("Cannot set chat name; self not in group".into())
@@ -2640,7 +3727,7 @@ pub async fn set_chat_name(
                 .await
                 .is_ok()
             {
-                if chat.is_promoted() && !chat.is_mailing_list() {
+                if chat.typ == Chattype::Group && chat.is_promoted() {
                     msg.viewtype = Viewtype::Text;
                     msg.text = Some(
                         stock_str::msg_grp_name(context, &chat.name, &new_name, DC_CONTACT_ID_SELF)
@@ -2807,7 +3894,7 @@ pub async fn forward_msgs(
                 let fresh10 = curr_timestamp;
                 curr_timestamp += 1;
                 new_msg_id = chat.prepare_msg_raw(context, &mut msg, fresh10).await?;
-                if let Some(send_job) = job::send_msg_job(context, new_msg_id).await? {
+                for send_job in job::send_msg_job(context, new_msg_id).await? {
                     job::add(context, send_job).await;
                 }
             }
@@ -2824,6 +3911,57 @@ pub async fn forward_msgs(
     Ok(())
 }
 
+/// Sends messages sent before again, e.g. to members added to a group later.
+///
+/// The messages are sent to all current members of their chats, keeping their text,
+/// attachment and date.  The copies are stored as hidden messages in the same chats,
+/// the original messages are not changed.  Each copy gets a new Message-ID and references
+/// the original one, so members that already have the original ignore the copy.
+///
+/// Only messages sent by self can be resent; if any of the messages is not,
+/// an error is returned and none of them is sent.
+pub async fn resend_msgs(context: &Context, msg_ids: &[MsgId]) -> Result<(), Error> {
+    let mut msgs = Vec::with_capacity(msg_ids.len());
+    for msg_id in msg_ids {
+        let msg = Message::load_from_db(context, *msg_id).await?;
+        ensure!(
+            msg.from_id == DC_CONTACT_ID_SELF,
+            "cannot resend message {} not sent by self",
+            msg_id
+        );
+        ensure!(
+            matches!(
+                msg.state,
+                MessageState::OutPending
+                    | MessageState::OutFailed
+                    | MessageState::OutDelivered
+                    | MessageState::OutMdnRcvd
+            ),
+            "cannot resend message {} in state {}",
+            msg_id,
+            msg.state
+        );
+        let chat = Chat::load_from_db(context, msg.chat_id).await?;
+        ensure!(chat.can_send(), "cannot send to {}", chat.id);
+        msgs.push((chat, msg));
+    }
+
+    for (mut chat, mut msg) in msgs {
+        // The copy is hidden, the chat keeps showing the original.
+        let original_mid = msg.rfc724_mid.clone();
+        msg.param.set(Param::ResendOf, original_mid);
+        msg.hidden = true;
+        msg.state = MessageState::OutPending;
+        let timestamp = msg.timestamp_sort;
+        let copy_id = chat.prepare_msg_raw(context, &mut msg, timestamp).await?;
+
+        for send_job in job::send_msg_job(context, copy_id).await? {
+            job::add(context, send_job).await;
+        }
+    }
+    Ok(())
+}
+
 pub(crate) async fn get_chat_contact_cnt(context: &Context, chat_id: ChatId) -> usize {
     context
         .sql
@@ -2930,8 +4068,8 @@ pub async fn add_device_msg_with_importance(
         }
 
         context.sql.execute(
-            "INSERT INTO msgs (chat_id,from_id,to_id, timestamp,timestamp_sent,timestamp_rcvd,type,state, txt,param,rfc724_mid) \
-             VALUES (?,?,?, ?,?,?,?,?, ?,?,?);",
+            "INSERT INTO msgs (chat_id,from_id,to_id, timestamp,timestamp_sent,timestamp_rcvd,type,state, txt,txt_folded,param,rfc724_mid) \
+             VALUES (?,?,?, ?,?,?,?,?, ?,?,?,?);",
             paramsv![
                 chat_id,
                 DC_CONTACT_ID_DEVICE,
@@ -2942,6 +4080,7 @@ pub async fn add_device_msg_with_importance(
                 msg.viewtype,
                 MessageState::InFresh,
                 msg.text.as_ref().cloned().unwrap_or_default(),
+                message::search_fold(msg.text.as_deref().unwrap_or_default()),
                 msg.param.to_string(),
                 rfc724_mid,
             ],
@@ -2952,14 +4091,17 @@ pub async fn add_device_msg_with_importance(
             .get_rowid(context, "msgs", "rfc724_mid", &rfc724_mid)
             .await?;
         msg_id = MsgId::new(row_id);
+        context
+            .stats_cache
+            .adjust_messages(chat_id, Blocked::Not, 1);
     }
 
     if let Some(label) = label {
         context
             .sql
             .execute(
-                "INSERT INTO devmsglabels (label) VALUES (?);",
-                paramsv![label.to_string()],
+                "INSERT INTO devmsglabels (label, msg_id) VALUES (?, ?);",
+                paramsv![label.to_string(), msg_id],
             )
             .await?;
     }
@@ -2983,6 +4125,10 @@ pub async fn add_device_msg(
     add_device_msg_with_importance(context, label, msg, false).await
 }
 
+/// Returns true if a device message with the given label was added before.
+///
+/// This is also the case if the message was deleted since then,
+/// the label is kept with `msg_id=0` in this case.
 pub async fn was_device_msg_ever_added(context: &Context, label: &str) -> Result<bool, Error> {
     ensure!(!label.is_empty(), "empty label");
     if let Ok(()) = context
@@ -3000,22 +4146,64 @@ pub async fn was_device_msg_ever_added(context: &Context, label: &str) -> Result
     Ok(false)
 }
 
+/// Deletes the device message added with the given label,
+/// e.g. to retract a mistaken announcement.
+///
+/// The label is kept, so the message is not added again.
+/// Returns false if there is no such message.
+pub async fn delete_device_msg_by_label(context: &Context, label: &str) -> Result<bool, Error> {
+    ensure!(!label.is_empty(), "empty label");
+    let ids = context
+        .sql
+        .query_row_optional(
+            "SELECT m.id, m.chat_id
+               FROM devmsglabels l
+               INNER JOIN msgs m ON m.id=l.msg_id
+              WHERE l.label=?;",
+            paramsv![label],
+            |row| Ok((row.get::<_, MsgId>(0)?, row.get::<_, ChatId>(1)?)),
+        )
+        .await?;
+    let (msg_id, chat_id) = match ids {
+        Some(ids) => ids,
+        None => return Ok(false),
+    };
+
+    // device messages are not on the server, so there is no need to trash them
+    msg_id.delete_from_db(context).await?;
+    context
+        .sql
+        .execute(
+            "UPDATE devmsglabels SET msg_id=0 WHERE label=?;",
+            paramsv![label],
+        )
+        .await?;
+    context.emit_event(EventType::MsgsChanged {
+        chat_id,
+        msg_id: MsgId::new(0),
+    });
+    Ok(true)
+}
+
 // needed on device-switches during export/import;
 // - deletion in `msgs` with `DC_CONTACT_ID_DEVICE` makes sure,
 //   no wrong information are shown in the device chat
-// - deletion in `devmsglabels` makes sure,
-//   deleted messages are resetted and useful messages can be added again
-pub(crate) async fn delete_and_reset_all_device_msgs(context: &Context) -> Result<(), Error> {
-    context
+// - the labels in `devmsglabels` are kept, so that messages already seen
+//   on the old device, as the welcome message, are not added again
+pub(crate) async fn delete_all_device_msgs(context: &Context) -> Result<(), Error> {
+    let deleted = context
         .sql
         .execute(
             "DELETE FROM msgs WHERE from_id=?;",
             paramsv![DC_CONTACT_ID_DEVICE],
         )
         .await?;
+    context
+        .stats_cache
+        .adjust(Counter::Messages, -(deleted as i64));
     context
         .sql
-        .execute("DELETE FROM devmsglabels;", paramsv![])
+        .execute("UPDATE devmsglabels SET msg_id=0;", paramsv![])
         .await?;
     Ok(())
 }
@@ -3038,7 +4226,7 @@ pub(crate) async fn add_info_msg_with_cmd(
     }
 
     context.sql.execute(
-        "INSERT INTO msgs (chat_id,from_id,to_id, timestamp,type,state, txt,rfc724_mid,ephemeral_timer, param) VALUES (?,?,?, ?,?,?, ?,?,?, ?);",
+        "INSERT INTO msgs (chat_id,from_id,to_id, timestamp,type,state, txt,txt_folded,rfc724_mid,ephemeral_timer, param) VALUES (?,?,?, ?,?,?, ?,?,?,?, ?);",
         paramsv![
             chat_id,
             DC_CONTACT_ID_INFO,
@@ -3047,6 +4235,7 @@ pub(crate) async fn add_info_msg_with_cmd(
             Viewtype::Text,
             MessageState::InNoticed,
             text.as_ref().to_string(),
+            message::search_fold(text.as_ref()),
             rfc724_mid,
             ephemeral_timer,
             param.to_string(),
@@ -3059,6 +4248,7 @@ pub(crate) async fn add_info_msg_with_cmd(
         .await
         .unwrap_or_default();
     let msg_id = MsgId::new(row_id);
+    stats::adjust_chat_messages(context, chat_id, 1).await;
     context.emit_event(EventType::MsgsChanged { chat_id, msg_id });
     Ok(msg_id)
 }
@@ -3069,6 +4259,224 @@ pub(crate) async fn add_info_msg(context: &Context, chat_id: ChatId, text: impl
     }
 }
 
+/// Chat as written to `chat.json` by [`export_chat`].
+#[derive(Debug, Serialize)]
+struct ExportedChat {
+    name: String,
+    chat_type: Chattype,
+    members: Vec<ExportedContact>,
+    /// Set if disappearing messages are enabled or were left out.
+    ephemeral_notice: Option<String>,
+    messages: Vec<ExportedMsg>,
+}
+
+#[derive(Debug, Serialize)]
+struct ExportedContact {
+    id: u32,
+    name: String,
+    addr: String,
+}
+
+#[derive(Debug, Serialize)]
+struct ExportedMsg {
+    id: u32,
+    from_id: u32,
+    from_name: String,
+    timestamp: i64,
+    viewtype: Viewtype,
+    is_info: bool,
+    text: Option<String>,
+    /// Path of the attachment in the archive.
+    file: Option<String>,
+    /// Time at which the message disappears, 0 if it does not.
+    ephemeral_timestamp: i64,
+}
+
+/// Exports a chat to a tar archive in the directory `dest`.
+///
+/// The archive contains `chat.html` for reading, `chat.json` with the messages and members
+/// and the attachments in `blobs/`.  Expired disappearing messages are left out and a
+/// notice is added instead.  Progress is reported as #DC_EVENT_IMEX_PROGRESS, the written
+/// archive as #DC_EVENT_IMEX_FILE_WRITTEN.  Returns the path of the archive.
+pub async fn export_chat(
+    context: &Context,
+    chat_id: ChatId,
+    dest: impl AsRef<Path>,
+) -> Result<PathBuf, Error> {
+    ensure!(
+        !chat_id.is_special(),
+        "cannot export special chat {}",
+        chat_id
+    );
+    let chat = Chat::load_from_db(context, chat_id).await?;
+    let _deletion_hold = hold_deletions(context);
+    let now = time();
+
+    let mut members = Vec::new();
+    for contact_id in get_chat_contacts(context, chat_id).await {
+        let contact = Contact::load_from_db(context, contact_id).await?;
+        members.push(ExportedContact {
+            id: contact_id,
+            name: contact.get_display_name().to_string(),
+            addr: contact.get_addr().to_string(),
+        });
+    }
+
+    let msg_ids: Vec<MsgId> = get_chat_msgs(context, chat_id, 0, None)
+        .await
+        .into_iter()
+        .filter_map(|item| match item {
+            ChatItem::Message { msg_id } => Some(msg_id),
+            _ => None,
+        })
+        .collect();
+
+    let dest_path = dest
+        .as_ref()
+        .join(format!("delta-chat-chat-{}-{}.tar", chat_id.to_u32(), now));
+    let mut builder = async_tar::Builder::new(async_std::fs::File::create(&dest_path).await?);
+
+    let mut messages = Vec::new();
+    let mut expired_cnt = 0;
+    let mut file_names = std::collections::HashSet::new();
+    for (i, msg_id) in msg_ids.iter().enumerate() {
+        let msg = Message::load_from_db(context, *msg_id).await?;
+        if msg.ephemeral_timestamp != 0 && msg.ephemeral_timestamp <= now {
+            expired_cnt += 1;
+            continue;
+        }
+
+        let file = match msg.get_file(context) {
+            Some(path) => match path.file_name() {
+                Some(name) => {
+                    let name = format!("blobs/{}", name.to_string_lossy());
+                    if file_names.contains(&name) {
+                        Some(name)
+                    } else {
+                        // Deleted attachments are left out, the message is exported anyway.
+                        match crate::imex::open_blob_for_export(context, &path).await? {
+                            Some(mut file) => {
+                                builder.append_file(Path::new(&name), &mut file).await?;
+                                file_names.insert(name.clone());
+                                Some(name)
+                            }
+                            None => None,
+                        }
+                    }
+                }
+                None => None,
+            },
+            None => None,
+        };
+        let from = Contact::load_from_db(context, msg.get_from_id()).await?;
+        messages.push(ExportedMsg {
+            id: msg.get_id().to_u32(),
+            from_id: msg.get_from_id(),
+            from_name: msg.get_sender_name(&from),
+            timestamp: msg.get_timestamp(),
+            viewtype: msg.get_viewtype(),
+            is_info: msg.is_info(),
+            text: msg.get_text(),
+            file,
+            ephemeral_timestamp: msg.ephemeral_timestamp,
+        });
+
+        let progress = 1000 * (i + 1) / msg_ids.len();
+        if progress < 1000 {
+            context.emit_event(EventType::ImexProgress(progress));
+        }
+    }
+
+    let ephemeral_notice = if expired_cnt > 0 {
+        Some(format!(
+            "{} disappearing messages already expired and are not included.",
+            expired_cnt
+        ))
+    } else if chat_id.get_ephemeral_timer(context).await? != EphemeralTimer::Disabled {
+        Some("Disappearing messages are enabled in this chat.".to_string())
+    } else {
+        None
+    };
+
+    let exported = ExportedChat {
+        name: chat.get_name().to_string(),
+        chat_type: chat.get_type(),
+        members,
+        ephemeral_notice,
+        messages,
+    };
+    append_export_file(
+        &mut builder,
+        "chat.json",
+        serde_json::to_string_pretty(&exported)?.as_bytes(),
+    )
+    .await?;
+    append_export_file(
+        &mut builder,
+        "chat.html",
+        render_exported_chat(&exported).as_bytes(),
+    )
+    .await?;
+    builder.finish().await?;
+
+    context.emit_event(EventType::ImexProgress(1000));
+    context.emit_event(EventType::ImexFileWritten(dest_path.clone()));
+    Ok(dest_path)
+}
+
+async fn append_export_file(
+    builder: &mut async_tar::Builder<async_std::fs::File>,
+    name: &str,
+    data: &[u8],
+) -> Result<(), Error> {
+    let mut header = async_tar::Header::new_gnu();
+    header.set_size(data.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime(time() as u64);
+    header.set_cksum();
+    builder.append_data(&mut header, name, data).await?;
+    Ok(())
+}
+
+fn render_exported_chat(chat: &ExportedChat) -> String {
+    let mut html = format!(
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>{}</title></head><body>\n<h1>{}</h1>\n",
+        escaper::encode_minimal(&chat.name),
+        escaper::encode_minimal(&chat.name)
+    );
+    html += "<p>Members: ";
+    html += &chat
+        .members
+        .iter()
+        .map(|member| escaper::encode_minimal(&format!("{} <{}>", member.name, member.addr)))
+        .join(", ");
+    html += "</p>\n";
+    if let Some(notice) = &chat.ephemeral_notice {
+        html += &format!("<p><i>{}</i></p>\n", escaper::encode_minimal(notice));
+    }
+    for msg in &chat.messages {
+        html += &format!(
+            "<p><b>{}</b> {}<br>",
+            escaper::encode_minimal(&msg.from_name),
+            dc_timestamp_to_str(msg.timestamp)
+        );
+        if let Some(file) = &msg.file {
+            let file = escaper::encode_minimal(file);
+            if msg.viewtype == Viewtype::Image || msg.viewtype == Viewtype::Gif {
+                html += &format!("<img src=\"{}\" style=\"max-width:100%\"><br>", file);
+            } else {
+                html += &format!("<a href=\"{}\">{}</a><br>", file, file);
+            }
+        }
+        if let Some(text) = &msg.text {
+            html += &escaper::encode_minimal(text).replace('\n', "<br>");
+        }
+        html += "</p>\n";
+    }
+    html += "</body></html>\n";
+    html
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -3077,8 +4485,112 @@ mod tests {
     use crate::constants::{DC_GCL_ARCHIVED_ONLY, DC_GCL_NO_SPECIALS};
     use crate::contact::Contact;
     use crate::dc_receive_imf::dc_receive_imf;
+    use crate::notification;
+    use crate::peerstate::PeerstateKeyType;
     use crate::test_utils::TestContext;
 
+    #[async_std::test]
+    async fn test_export_chat() {
+        use async_std::prelude::*;
+
+        let t = TestContext::new_alice().await;
+        let chat = t.create_chat_with_contact("bob", "bob@example.net").await;
+        t.send_text(chat.id, "hello <bob>").await;
+
+        let image = t.get_blobdir().join("image.png");
+        async_std::fs::write(&image, include_bytes!("../test-data/image/avatar64x64.png"))
+            .await
+            .unwrap();
+        let mut msg = Message::new(Viewtype::Image);
+        msg.set_file(image.to_str().unwrap(), None);
+        t.send_msg(chat.id, &mut msg).await;
+
+        let voice = t.get_blobdir().join("voice.aac");
+        async_std::fs::write(&voice, b"not really aac")
+            .await
+            .unwrap();
+        let mut msg = Message::new(Viewtype::Voice);
+        msg.set_file(voice.to_str().unwrap(), Some("audio/aac"));
+        t.send_msg(chat.id, &mut msg).await;
+
+        let dest = tempfile::tempdir().unwrap();
+        let dest: PathBuf = dest.path().to_path_buf().into();
+        let archive = export_chat(&t, chat.id, &dest).await.unwrap();
+
+        let mut json = None;
+        let mut blobs = Vec::new();
+        let mut entries =
+            async_tar::Archive::new(async_std::fs::File::open(&archive).await.unwrap())
+                .entries()
+                .unwrap();
+        while let Some(entry) = entries.next().await {
+            let mut entry = entry.unwrap();
+            let name = entry.path().unwrap().to_string_lossy().into_owned();
+            if name == "chat.json" {
+                let mut buf = String::new();
+                entry.read_to_string(&mut buf).await.unwrap();
+                json = Some(serde_json::from_str::<serde_json::Value>(&buf).unwrap());
+            } else if let Some(blob) = name.strip_prefix("blobs/") {
+                blobs.push(blob.to_string());
+            }
+        }
+        assert_eq!(blobs.len(), 2);
+
+        let json = json.unwrap();
+        let messages = json.get("messages").unwrap().as_array().unwrap();
+        assert_eq!(messages.len(), 3);
+        for msg in messages.iter().skip(1) {
+            let file = msg.get("file").unwrap().as_str().unwrap();
+            assert!(blobs.contains(&file.strip_prefix("blobs/").unwrap().to_string()));
+        }
+        assert_eq!(json.pointer("/members/0/addr").unwrap(), "bob@example.net");
+        assert_eq!(json.pointer("/messages/0/text").unwrap(), "hello <bob>");
+        assert!(json.get("ephemeral_notice").unwrap().is_null());
+    }
+
+    #[async_std::test]
+    async fn test_export_chat_missing_blob() {
+        use async_std::prelude::*;
+
+        let t = TestContext::new_alice().await;
+        let chat = t.create_chat_with_contact("bob", "bob@example.net").await;
+        let image = t.get_blobdir().join("image.png");
+        async_std::fs::write(&image, include_bytes!("../test-data/image/avatar64x64.png"))
+            .await
+            .unwrap();
+        let mut msg = Message::new(Viewtype::Image);
+        msg.set_file(image.to_str().unwrap(), None);
+        msg.set_text(Some("look".to_string()));
+        t.send_msg(chat.id, &mut msg).await;
+        let msg = Message::load_from_db(&t, msg.id).await.unwrap();
+        async_std::fs::remove_file(msg.get_file(&t).unwrap())
+            .await
+            .unwrap();
+
+        let dest = tempfile::tempdir().unwrap();
+        let dest: PathBuf = dest.path().to_path_buf().into();
+        let archive = export_chat(&t, chat.id, &dest).await.unwrap();
+
+        let mut json = None;
+        let mut entries =
+            async_tar::Archive::new(async_std::fs::File::open(&archive).await.unwrap())
+                .entries()
+                .unwrap();
+        while let Some(entry) = entries.next().await {
+            let mut entry = entry.unwrap();
+            let name = entry.path().unwrap().to_string_lossy().into_owned();
+            assert!(!name.starts_with("blobs/"));
+            if name == "chat.json" {
+                let mut buf = String::new();
+                entry.read_to_string(&mut buf).await.unwrap();
+                json = Some(serde_json::from_str::<serde_json::Value>(&buf).unwrap());
+            }
+        }
+        let json = json.unwrap();
+        assert_eq!(json.pointer("/messages/0/text").unwrap(), "look");
+        assert!(json.pointer("/messages/0/file").unwrap().is_null());
+    }
+
     #[async_std::test]
     async fn test_chat_info() {
         let t = TestContext::new().await;
@@ -3150,6 +4662,93 @@ async fn test_get_draft() {
         assert_eq!(msg_text, draft_text);
     }
 
+    #[async_std::test]
+    async fn test_draft_info() {
+        let t = TestContext::new_alice().await;
+        let chat_id = t.get_self_chat().await.id;
+        assert_eq!(chat_id.get_draft_info(&t).await.unwrap(), None);
+
+        let mut msg = Message::new(Viewtype::Text);
+        msg.set_text(Some("hello".to_string()));
+        chat_id.set_draft(&t, Some(&mut msg)).await;
+        let info = chat_id.get_draft_info(&t).await.unwrap().unwrap();
+        assert_eq!(info.text, "hello");
+        assert_eq!(info.viewtype, Viewtype::Text);
+        assert_eq!(info.file, None);
+
+        // A new draft replaces the previous one.
+        let file = t.get_blobdir().join("report.pdf");
+        async_std::fs::write(&file, b"%PDF").await.unwrap();
+        let mut msg = Message::new(Viewtype::File);
+        msg.set_text(Some("see attachment".to_string()));
+        msg.set_file(file.to_str().unwrap(), Some("application/pdf"));
+        chat_id.set_draft(&t, Some(&mut msg)).await;
+        let info = chat_id.get_draft_info(&t).await.unwrap().unwrap();
+        assert_eq!(info.text, "see attachment");
+        assert_eq!(info.viewtype, Viewtype::File);
+        assert_eq!(info.file, Some(file));
+        assert_eq!(info.mimetype.as_deref(), Some("application/pdf"));
+        assert_eq!(
+            t.sql
+                .query_get_value_result::<i64>(
+                    "SELECT COUNT(*) FROM msgs WHERE state=?;",
+                    paramsv![MessageState::OutDraft],
+                )
+                .await
+                .unwrap(),
+            Some(1)
+        );
+
+        // An unusable draft removes the previous one.
+        let mut msg = Message::new(Viewtype::Text);
+        chat_id.set_draft(&t, Some(&mut msg)).await;
+        assert_eq!(chat_id.get_draft_info(&t).await.unwrap(), None);
+    }
+
+    #[async_std::test]
+    async fn test_draft_attachment_survives_housekeeping() {
+        let t = TestContext::new_alice().await;
+        let chat_id = t
+            .create_chat_with_contact("bob", "bob@example.net")
+            .await
+            .id;
+
+        let file = t.dir.path().join("photo.png");
+        std::fs::write(&file, include_bytes!("../test-data/image/avatar64x64.png")).unwrap();
+        let mut msg = Message::new(Viewtype::Image);
+        msg.set_file(file.to_str().unwrap(), None);
+        chat_id.set_draft(&t, Some(&mut msg)).await;
+        let blob = chat_id
+            .get_draft_info(&t)
+            .await
+            .unwrap()
+            .unwrap()
+            .file
+            .unwrap();
+        assert!(blob.starts_with(t.get_blobdir()));
+
+        crate::sql::housekeeping(&t).await.unwrap();
+        assert!(blob.exists().await);
+        let draft = chat_id.get_draft(&t).await.unwrap().unwrap();
+        assert_eq!(draft.get_file(&t), Some(blob));
+
+        // Housekeeping keeps new files anyway, so check the files in use,
+        // the attachment of a text draft is not found by the scan of the other messages.
+        let file = t.dir.path().join("notes.txt");
+        std::fs::write(&file, b"notes").unwrap();
+        let mut msg = Message::new(Viewtype::Text);
+        msg.set_text(Some("see notes".to_string()));
+        msg.set_file(file.to_str().unwrap(), None);
+        chat_id.set_draft(&t, Some(&mut msg)).await;
+        let info = chat_id.get_draft_info(&t).await.unwrap().unwrap();
+        assert_eq!(info.viewtype, Viewtype::Text);
+        let blob = info.file.unwrap();
+        assert!(blob.starts_with(t.get_blobdir()));
+        let name = blob.file_name().unwrap().to_str().unwrap().to_string();
+        let files_in_use = crate::sql::get_files_in_use(&t).await.unwrap();
+        assert!(files_in_use.contains(&name));
+    }
+
     #[async_std::test]
     async fn test_add_contact_to_chat_ex_add_self() {
         // Adding self to a contact should succeed, even though it's pointless.
@@ -3381,7 +4980,7 @@ async fn test_device_chat_cannot_sent() {
     }
 
     #[async_std::test]
-    async fn test_delete_and_reset_all_device_msgs() {
+    async fn test_delete_all_device_msgs() {
         let t = TestContext::new().await;
         let mut msg = Message::new(Viewtype::Text);
         msg.text = Some("message text".to_string());
@@ -3396,13 +4995,41 @@ async fn test_delete_and_reset_all_device_msgs() {
             .unwrap();
         assert!(msg_id2.is_unset());
 
-        // ... unless everything is deleted and resetted - as needed eg. on device switch
-        delete_and_reset_all_device_msgs(&t).await.unwrap();
-        assert!(!was_device_msg_ever_added(&t, "some-label").await.unwrap());
+        // ... even if all device messages are deleted - as done eg. on device switch
+        delete_all_device_msgs(&t).await.unwrap();
+        assert!(Message::load_from_db(&t, msg_id1).await.is_err());
+        assert!(was_device_msg_ever_added(&t, "some-label").await.unwrap());
         let msg_id3 = add_device_msg(&t, Some("some-label"), Some(&mut msg))
             .await
             .unwrap();
-        assert_ne!(msg_id1, msg_id3);
+        assert!(msg_id3.is_unset());
+    }
+
+    #[async_std::test]
+    async fn test_delete_device_msg_by_label() {
+        let t = TestContext::new().await;
+        let mut msg = Message::new(Viewtype::Text);
+        msg.text = Some("mistaken announcement".to_string());
+        let msg_id = add_device_msg(&t, Some("announcement"), Some(&mut msg))
+            .await
+            .unwrap();
+        assert!(!msg_id.is_unset());
+
+        assert!(delete_device_msg_by_label(&t, "announcement")
+            .await
+            .unwrap());
+        assert!(Message::load_from_db(&t, msg_id).await.is_err());
+        assert!(!delete_device_msg_by_label(&t, "announcement")
+            .await
+            .unwrap());
+        assert!(!delete_device_msg_by_label(&t, "unknown").await.unwrap());
+
+        // the label is kept as a tombstone
+        assert!(was_device_msg_ever_added(&t, "announcement").await.unwrap());
+        let msg_id = add_device_msg(&t, Some("announcement"), Some(&mut msg))
+            .await
+            .unwrap();
+        assert!(msg_id.is_unset());
     }
 
     async fn chatlist_len(ctx: &Context, listflags: usize) -> usize {
@@ -3514,6 +5141,65 @@ async fn test_archive() {
         assert_eq!(chatlist_len(&t, DC_GCL_ARCHIVED_ONLY).await, 1);
     }
 
+    #[async_std::test]
+    async fn test_archived_chat_unarchived_by_message() {
+        let alice = TestContext::new_alice().await;
+        let bob = TestContext::new_bob().await;
+        let alice_chat = alice.create_chat(&bob).await;
+        let bob_chat = bob.create_chat(&alice).await;
+        bob_chat
+            .id
+            .set_visibility(&bob, ChatVisibility::Archived)
+            .await
+            .unwrap();
+        assert_eq!(chatlist_len(&bob, DC_GCL_NO_SPECIALS).await, 0);
+
+        let sent = alice.send_text(alice_chat.id, "hi").await;
+        bob.recv_msg(&sent).await;
+        let bob_chat = Chat::load_from_db(&bob, bob_chat.id).await.unwrap();
+        assert_eq!(bob_chat.get_visibility(), ChatVisibility::Normal);
+        assert_eq!(chatlist_len(&bob, DC_GCL_NO_SPECIALS).await, 1);
+        assert_eq!(chatlist_len(&bob, DC_GCL_ARCHIVED_ONLY).await, 0);
+        assert_eq!(bob_chat.id.get_fresh_msg_cnt(&bob).await, 1);
+        assert_eq!(bob.get_fresh_msgs().await.unwrap().len(), 1);
+    }
+
+    #[async_std::test]
+    async fn test_no_unarchive() {
+        let alice = TestContext::new_alice().await;
+        let bob = TestContext::new_bob().await;
+        let alice_chat = alice.create_chat(&bob).await;
+        let bob_chat = bob.create_chat(&alice).await;
+        bob_chat
+            .id
+            .set_visibility(&bob, ChatVisibility::NoUnarchive)
+            .await
+            .unwrap();
+        assert_eq!(chatlist_len(&bob, 0).await, 1); // only DC_CHAT_ID_ARCHIVED_LINK
+        assert_eq!(chatlist_len(&bob, DC_GCL_NO_SPECIALS).await, 0);
+        assert_eq!(chatlist_len(&bob, DC_GCL_ARCHIVED_ONLY).await, 1);
+
+        // new messages are counted, but the chat stays archived
+        let sent = alice.send_text(alice_chat.id, "hi").await;
+        bob.recv_msg(&sent).await;
+        let bob_chat = Chat::load_from_db(&bob, bob_chat.id).await.unwrap();
+        assert_eq!(bob_chat.get_visibility(), ChatVisibility::NoUnarchive);
+        assert!(bob_chat.get_info(&bob).await.unwrap().archived);
+        assert_eq!(chatlist_len(&bob, DC_GCL_NO_SPECIALS).await, 0);
+        assert_eq!(chatlist_len(&bob, DC_GCL_ARCHIVED_ONLY).await, 1);
+        assert_eq!(bob_chat.id.get_fresh_msg_cnt(&bob).await, 1);
+
+        // by default, the messages are not counted for the badge
+        assert!(bob.get_fresh_msgs().await.unwrap().is_empty());
+        assert_eq!(notification::get_badge_cnt(&bob).await.unwrap(), 0);
+
+        bob.set_config(Config::BadgeNoUnarchive, Some("1"))
+            .await
+            .unwrap();
+        assert_eq!(bob.get_fresh_msgs().await.unwrap().len(), 1);
+        assert_eq!(notification::get_badge_cnt(&bob).await.unwrap(), 1);
+    }
+
     async fn get_chats_from_chat_list(ctx: &Context, listflags: usize) -> Vec<ChatId> {
         let chatlist = Chatlist::try_load(ctx, listflags, None, None)
             .await
@@ -3691,6 +5377,82 @@ async fn test_set_mute_duration() {
         );
     }
 
+    #[async_std::test]
+    async fn test_mute_expires() {
+        let t = TestContext::new_alice().await;
+        let chat = t.create_chat_with_contact("bob", "bob@example.net").await;
+        chat.id
+            .set_mute_duration(
+                &t,
+                MuteDuration::Until(SystemTime::now() + Duration::from_secs(2)),
+            )
+            .await
+            .unwrap();
+        assert!(matches!(
+            chat.id.get_mute_state(&t).await.unwrap(),
+            MuteDuration::Until(_)
+        ));
+        assert!(Chat::load_from_db(&t, chat.id).await.unwrap().is_muted());
+
+        let (event_tx, event_rx) = async_std::channel::unbounded();
+        t.add_event_sink(move |event: crate::events::Event| {
+            let event_tx = event_tx.clone();
+            async move {
+                if let EventType::ChatModified(chat_id) = event.typ {
+                    event_tx.try_send(chat_id).unwrap();
+                }
+            }
+        })
+        .await;
+
+        let chat_id = async_std::future::timeout(Duration::from_secs(10), event_rx.recv())
+            .await
+            .expect("no event when the mute expired")
+            .unwrap();
+        assert_eq!(chat_id, chat.id);
+        assert_eq!(
+            chat.id.get_mute_state(&t).await.unwrap(),
+            MuteDuration::NotMuted
+        );
+        assert!(!Chat::load_from_db(&t, chat.id).await.unwrap().is_muted());
+    }
+
+    #[async_std::test]
+    async fn test_mute_forever_does_not_expire() {
+        let t = TestContext::new_alice().await;
+        let chat = t.create_chat_with_contact("bob", "bob@example.net").await;
+        chat.id
+            .set_mute_duration(&t, MuteDuration::Forever)
+            .await
+            .unwrap();
+
+        assert!(crate::ephemeral::unmute_expired_chats(&t)
+            .await
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            chat.id.get_mute_state(&t).await.unwrap(),
+            MuteDuration::Forever
+        );
+
+        // An expired timed mute is reported as not muted even before the task ran.
+        t.sql
+            .execute(
+                "UPDATE chats SET muted_until=? WHERE id=?;",
+                paramsv![time() - 10, chat.id],
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            chat.id.get_mute_state(&t).await.unwrap(),
+            MuteDuration::NotMuted
+        );
+        assert_eq!(
+            crate::ephemeral::unmute_expired_chats(&t).await.unwrap(),
+            vec![chat.id]
+        );
+    }
+
     #[async_std::test]
     async fn test_add_info_msg() {
         let t = TestContext::new().await;
@@ -3910,4 +5672,577 @@ async fn test_group_with_removed_message_id() {
         assert_eq!(msg.text, Some("ho!".to_string()));
         assert_eq!(get_chat_msgs(&alice, alice_chat_id, 0, None).await.len(), 2);
     }
+
+    #[async_std::test]
+    async fn test_resend_msgs() {
+        let alice = TestContext::new_alice().await;
+        let bob = TestContext::new_bob().await;
+        let claire = TestContext::new().await;
+        claire.configure_addr("claire@example.net").await;
+
+        let alice_chat_id = create_group_chat(&alice, ProtectionStatus::Unprotected, "grp")
+            .await
+            .unwrap();
+        let bob_id = Contact::create(&alice, "", "bob@example.net")
+            .await
+            .unwrap();
+        add_contact_to_chat(&alice, alice_chat_id, bob_id).await;
+        let text_msg = alice.send_text(alice_chat_id, "hi!").await;
+        bob.recv_msg(&text_msg).await;
+        let file = alice.get_blobdir().join("report.pdf");
+        async_std::fs::write(&file, b"%PDF").await.unwrap();
+        let mut msg = Message::new(Viewtype::File);
+        msg.set_file(file.to_str().unwrap(), Some("application/pdf"));
+        let file_msg = alice.send_msg(alice_chat_id, &mut msg).await;
+        bob.recv_msg(&file_msg).await;
+        let bob_chat_id = bob.get_last_msg().await.chat_id;
+
+        // Alice adds Claire and resends the messages sent before.
+        let claire_id = Contact::create(&alice, "", "claire@example.net")
+            .await
+            .unwrap();
+        add_contact_to_chat(&alice, alice_chat_id, claire_id).await;
+        let added_msg = alice.pop_sent_msg().await;
+        bob.recv_msg(&added_msg).await;
+        claire.recv_msg(&added_msg).await;
+        let claire_chat_id = claire.get_last_msg().await.chat_id;
+        let bob_msgs = get_chat_msgs(&bob, bob_chat_id, 0, None).await.len();
+        let claire_msgs = get_chat_msgs(&claire, claire_chat_id, 0, None).await.len();
+        let alice_msgs = get_chat_msgs(&alice, alice_chat_id, 0, None).await.len();
+        let original_text = Message::load_from_db(&alice, text_msg.sender_msg_id)
+            .await
+            .unwrap();
+
+        resend_msgs(&alice, &[text_msg.sender_msg_id, file_msg.sender_msg_id])
+            .await
+            .unwrap();
+        let resent_text = alice.pop_sent_msg().await;
+        let resent_file = alice.pop_sent_msg().await;
+        assert!(resent_text
+            .payload()
+            .contains(&format!("Chat-Resend-Of: <{}>", original_text.rfc724_mid)));
+        assert!(resent_text
+            .recipients()
+            .contains(&"claire@example.net".to_string()));
+
+        // The copies are new hidden messages, the originals are not changed.
+        assert_ne!(resent_text.sender_msg_id, text_msg.sender_msg_id);
+        let copy = Message::load_from_db(&alice, resent_text.sender_msg_id)
+            .await
+            .unwrap();
+        assert!(copy.hidden);
+        assert_ne!(copy.rfc724_mid, original_text.rfc724_mid);
+        let text_after = Message::load_from_db(&alice, text_msg.sender_msg_id)
+            .await
+            .unwrap();
+        assert_eq!(text_after.state, original_text.state);
+        assert_eq!(text_after.param, original_text.param);
+        assert_eq!(
+            get_chat_msgs(&alice, alice_chat_id, 0, None).await.len(),
+            alice_msgs
+        );
+
+        claire.recv_msg(&resent_text).await;
+        let msg = claire.get_last_msg_in(claire_chat_id).await;
+        assert_eq!(msg.text.as_deref(), Some("hi!"));
+        assert_eq!(msg.rfc724_mid, original_text.rfc724_mid);
+        claire.recv_msg(&resent_file).await;
+        let msg = claire.get_last_msg_in(claire_chat_id).await;
+        assert_eq!(msg.viewtype, Viewtype::File);
+        assert_eq!(msg.get_filename().as_deref(), Some("report.pdf"));
+        assert_eq!(
+            get_chat_msgs(&claire, claire_chat_id, 0, None).await.len(),
+            claire_msgs + 2
+        );
+
+        // Bob already has the messages and ignores the copies.
+        bob.recv_msg(&resent_text).await;
+        bob.recv_msg(&resent_file).await;
+        assert_eq!(
+            get_chat_msgs(&bob, bob_chat_id, 0, None).await.len(),
+            bob_msgs
+        );
+
+        // Messages of others cannot be resent.
+        let bob_msg = bob.send_text(bob_chat_id, "hello").await;
+        alice.recv_msg(&bob_msg).await;
+        let foreign_msg = alice.get_last_msg_in(alice_chat_id).await;
+        assert_eq!(foreign_msg.text.as_deref(), Some("hello"));
+        assert!(resend_msgs(&alice, &[foreign_msg.id]).await.is_err());
+        assert!(
+            resend_msgs(&alice, &[text_msg.sender_msg_id, foreign_msg.id])
+                .await
+                .is_err()
+        );
+    }
+
+    async fn receive_contact_request(
+        t: &TestContext,
+        from: &str,
+        headers: &str,
+        n: u32,
+    ) -> Message {
+        dc_receive_imf(
+            t,
+            format!(
+                "From: {}\n\
+                 To: alice@example.com\n\
+                 {}\
+                 Subject: hello\n\
+                 Message-ID: <request{}@example.net>\n\
+                 Date: Sun, 22 Mar 2020 22:37:57 +0000\n\
+                 \n\
+                 hello\n",
+                from, headers, n
+            )
+            .as_bytes(),
+            "INBOX",
+            n,
+            false,
+        )
+        .await
+        .unwrap();
+        let (_, _, msg_id) = message::rfc724_mid_exists(t, &format!("request{}@example.net", n))
+            .await
+            .unwrap()
+            .unwrap();
+        let msg = Message::load_from_db(t, msg_id).await.unwrap();
+        let chat = Chat::load_from_db(t, msg.chat_id).await.unwrap();
+        assert_eq!(chat.blocked, Blocked::Deaddrop);
+        msg
+    }
+
+    #[async_std::test]
+    async fn test_accept_contact_requests() {
+        let t = TestContext::new_alice().await;
+        let bob = receive_contact_request(&t, "bob@example.net", "Chat-Version: 1.0\n", 1).await;
+        let claire =
+            receive_contact_request(&t, "claire@example.org", "Chat-Version: 1.0\n", 2).await;
+        create_by_msg_id(&t, bob.id).await.unwrap();
+
+        let (event_tx, event_rx) = async_std::channel::unbounded();
+        t.add_event_sink(move |event: crate::events::Event| {
+            let event_tx = event_tx.clone();
+            async move {
+                match event.typ {
+                    EventType::MsgsChanged { .. } | EventType::ChatModified(_) => {
+                        event_tx.try_send(event.typ).unwrap()
+                    }
+                    EventType::Info(msg) if msg == "checkpoint" => {
+                        event_tx.try_send(EventType::Info(msg)).unwrap()
+                    }
+                    _ => {}
+                }
+            }
+        })
+        .await;
+
+        let stats = t.get_stats().await.unwrap();
+        accept_contact_requests(&t, &[bob.chat_id, claire.chat_id])
+            .await
+            .unwrap();
+        for chat_id in &[bob.chat_id, claire.chat_id] {
+            let chat = Chat::load_from_db(&t, *chat_id).await.unwrap();
+            assert_eq!(chat.blocked, Blocked::Not);
+        }
+        let contact = Contact::load_from_db(&t, claire.from_id).await.unwrap();
+        assert!(contact.origin >= Origin::CreateChat);
+
+        // The message moved out of the contact requests is counted at once.
+        let stats2 = t.get_stats().await.unwrap();
+        assert_eq!(stats2.deaddrop_messages + 1, stats.deaddrop_messages);
+        assert_eq!(stats2.messages, stats.messages + 1);
+
+        t.emit_event(EventType::Info("checkpoint".to_string()));
+        let mut events = Vec::new();
+        loop {
+            match event_rx.recv().await.unwrap() {
+                EventType::Info(_) => break,
+                event => events.push(event),
+            }
+        }
+        assert_eq!(
+            events,
+            vec![
+                EventType::MsgsChanged {
+                    chat_id: ChatId::new(0),
+                    msg_id: MsgId::new(0)
+                },
+                EventType::ChatModified(claire.chat_id)
+            ]
+        );
+    }
+
+    #[async_std::test]
+    async fn test_block_contact_requests() {
+        let t = TestContext::new_alice().await;
+        t.set_config(Config::ShowEmails, Some("2")).await.unwrap();
+        let bob = receive_contact_request(&t, "bob@example.net", "Chat-Version: 1.0\n", 1).await;
+        let dave = receive_contact_request(&t, "dave@example.com", "Chat-Version: 1.0\n", 2).await;
+        let list = receive_contact_request(
+            &t,
+            "news@example.org",
+            "List-ID: News <news.example.org>\n",
+            3,
+        )
+        .await;
+        assert!(Chat::load_from_db(&t, list.chat_id)
+            .await
+            .unwrap()
+            .is_mailing_list());
+        create_by_msg_id(&t, bob.id).await.unwrap();
+
+        // A message arriving after the contact requests were listed is handled as well.
+        let requests = vec![bob.chat_id, dave.chat_id, list.chat_id];
+        let dave2 = receive_contact_request(&t, "dave@example.com", "Chat-Version: 1.0\n", 4).await;
+        assert_eq!(dave2.chat_id, dave.chat_id);
+
+        block_contact_requests(&t, &requests).await.unwrap();
+        assert!(!Contact::is_blocked_load(&t, bob.from_id).await);
+        assert!(Contact::is_blocked_load(&t, dave.from_id).await);
+        assert!(!Contact::is_blocked_load(&t, list.from_id).await);
+        for (chat_id, blocked) in &[
+            (bob.chat_id, Blocked::Not),
+            (dave.chat_id, Blocked::Manually),
+            (list.chat_id, Blocked::Manually),
+        ] {
+            let chat = Chat::load_from_db(&t, *chat_id).await.unwrap();
+            assert_eq!(chat.blocked, *blocked);
+        }
+        for msg_id in &[dave.id, dave2.id] {
+            let msg = Message::load_from_db(&t, *msg_id).await.unwrap();
+            assert_eq!(msg.state, MessageState::InNoticed);
+        }
+    }
+
+    /// Lets `alice` learn the key of `bob` and returns the contact ID of `bob`.
+    async fn receive_key(alice: &TestContext, bob: &TestContext) -> u32 {
+        let bob_chat = bob.create_chat(alice).await;
+        let sent = bob.send_text(bob_chat.id, "hi").await;
+        alice.recv_msg(&sent).await;
+        alice.get_last_msg().await.from_id
+    }
+
+    #[async_std::test]
+    async fn test_encryption_info_keyless_member() {
+        let alice = TestContext::new_alice().await;
+        let bob = TestContext::new_bob().await;
+        let bob_id = receive_key(&alice, &bob).await;
+
+        let chat_id = create_group_chat(&alice, ProtectionStatus::Unprotected, "Group")
+            .await
+            .unwrap();
+        add_contact_to_chat(&alice, chat_id, bob_id).await;
+        let info = chat_id
+            .get_encryption_info_structured(&alice)
+            .await
+            .unwrap();
+        assert_eq!(info.verdict, EncryptionVerdict::Opportunistic);
+        assert_eq!(info.members.len(), 1);
+        assert_eq!(info.members[0].contact_id, bob_id);
+        assert_eq!(
+            info.members[0].prefer_encrypt,
+            Some(EncryptPreference::Mutual)
+        );
+        assert!(info.members[0].fingerprint.is_some());
+        assert_eq!(info.members[0].verified, VerifiedStatus::Unverified);
+
+        // A single member without key makes the chat unencrypted.
+        let claire_id = Contact::create(&alice, "", "claire@example.org")
+            .await
+            .unwrap();
+        add_contact_to_chat(&alice, chat_id, claire_id).await;
+        let info = chat_id
+            .get_encryption_info_structured(&alice)
+            .await
+            .unwrap();
+        assert_eq!(info.verdict, EncryptionVerdict::Unencrypted);
+        assert_eq!(info.members.len(), 2);
+        let claire = info
+            .members
+            .iter()
+            .find(|member| member.contact_id == claire_id)
+            .unwrap();
+        assert_eq!(claire.addr, "claire@example.org");
+        assert_eq!(claire.prefer_encrypt, None);
+        assert_eq!(claire.fingerprint, None);
+
+        assert_eq!(
+            chat_id.get_encryption_info(&alice).await.unwrap(),
+            format!(
+                "bob@example.net {}\nclaire@example.org {}",
+                stock_str::e2e_preferred(&alice).await,
+                stock_str::encr_none(&alice).await
+            )
+        );
+    }
+
+    #[async_std::test]
+    async fn test_encryption_info_protected_chat() {
+        let alice = TestContext::new_alice().await;
+        let bob = TestContext::new_bob().await;
+        let bob_id = receive_key(&alice, &bob).await;
+
+        let mut peerstate = Peerstate::from_addr(&alice, "bob@example.net")
+            .await
+            .unwrap()
+            .unwrap();
+        let fingerprint = peerstate.public_key_fingerprint.clone().unwrap();
+        assert!(peerstate.set_verified(
+            PeerstateKeyType::PublicKey,
+            &fingerprint,
+            PeerstateVerifiedStatus::BidirectVerified
+        ));
+        peerstate.save_to_db(&alice.sql, false).await.unwrap();
+
+        let chat_id = create_group_chat(&alice, ProtectionStatus::Protected, "Secret")
+            .await
+            .unwrap();
+        assert!(add_contact_to_chat(&alice, chat_id, bob_id).await);
+        let info = chat_id
+            .get_encryption_info_structured(&alice)
+            .await
+            .unwrap();
+        assert_eq!(info.verdict, EncryptionVerdict::Guaranteed);
+        assert_eq!(info.members.len(), 1);
+        assert_eq!(info.members[0].fingerprint, Some(fingerprint));
+        assert_eq!(info.members[0].verified, VerifiedStatus::BidirectVerified);
+    }
+
+    /// Returns the protection changes reported since the last call.
+    async fn protection_events(
+        t: &TestContext,
+        event_rx: &async_std::channel::Receiver<Option<(ChatId, ChatProtectionStatus)>>,
+    ) -> Vec<(ChatId, ChatProtectionStatus)> {
+        t.emit_event(EventType::Info("checkpoint".to_string()));
+        let mut events = Vec::new();
+        while let Some(event) = event_rx.recv().await.unwrap() {
+            events.push(event);
+        }
+        events
+    }
+
+    #[async_std::test]
+    async fn test_protection_broken_by_key_change() {
+        use crate::aheader::Aheader;
+        use crate::events::Event;
+        use crate::test_utils::alice_keypair;
+
+        let alice = TestContext::new_alice().await;
+        let bob = TestContext::new_bob().await;
+        let bob_id = receive_key(&alice, &bob).await;
+
+        let (event_tx, event_rx) = async_std::channel::unbounded();
+        alice
+            .add_event_sink(move |event: Event| {
+                let event_tx = event_tx.clone();
+                async move {
+                    match event.typ {
+                        EventType::ChatProtectionChanged { chat_id, status } => {
+                            event_tx.try_send(Some((chat_id, status))).unwrap()
+                        }
+                        EventType::Info(msg) if msg == "checkpoint" => {
+                            event_tx.try_send(None).unwrap()
+                        }
+                        _ => {}
+                    }
+                }
+            })
+            .await;
+
+        let mut peerstate = Peerstate::from_addr(&alice, "bob@example.net")
+            .await
+            .unwrap()
+            .unwrap();
+        let fingerprint = peerstate.public_key_fingerprint.clone().unwrap();
+        assert!(peerstate.set_verified(
+            PeerstateKeyType::PublicKey,
+            &fingerprint,
+            PeerstateVerifiedStatus::BidirectVerified
+        ));
+        peerstate.save_to_db(&alice.sql, false).await.unwrap();
+
+        let chat_id = create_group_chat(&alice, ProtectionStatus::Protected, "Secret")
+            .await
+            .unwrap();
+        assert!(add_contact_to_chat(&alice, chat_id, bob_id).await);
+        assert_eq!(
+            chat_id.audit_protection(&alice).await.unwrap(),
+            ChatProtectionStatus::Protected
+        );
+        assert!(protection_events(&alice, &event_rx).await.is_empty());
+
+        // Bob's key changes, as received in an Autocrypt header.
+        let header = Aheader::new(
+            "bob@example.net".to_string(),
+            alice_keypair().public,
+            EncryptPreference::Mutual,
+        );
+        peerstate.apply_header(&header, time() + 10);
+        peerstate.save_to_db(&alice.sql, false).await.unwrap();
+        peerstate.handle_fingerprint_change(&alice).await.unwrap();
+
+        let broken = ChatProtectionStatus::Broken {
+            offending_contacts: vec![bob_id],
+        };
+        assert_eq!(
+            protection_events(&alice, &event_rx).await,
+            vec![(chat_id, broken.clone())]
+        );
+        assert_eq!(chat_id.get_protection_status(&alice).await.unwrap(), broken);
+        assert!(Chat::load_from_db(&alice, chat_id)
+            .await
+            .unwrap()
+            .is_protected());
+
+        // Normal messages are blocked, auditing again does not report the status again.
+        assert!(send_text_msg(&alice, chat_id, "hi".to_string())
+            .await
+            .is_err());
+        assert!(protection_events(&alice, &event_rx).await.is_empty());
+
+        // Verifying the new key repairs the chat.
+        let fingerprint = peerstate.public_key_fingerprint.clone().unwrap();
+        assert!(peerstate.set_verified(
+            PeerstateKeyType::PublicKey,
+            &fingerprint,
+            PeerstateVerifiedStatus::BidirectVerified
+        ));
+        peerstate.save_to_db(&alice.sql, false).await.unwrap();
+        send_text_msg(&alice, chat_id, "hi".to_string())
+            .await
+            .unwrap();
+        assert_eq!(
+            protection_events(&alice, &event_rx).await,
+            vec![(chat_id, ChatProtectionStatus::Protected)]
+        );
+        assert!(!Chat::load_from_db(&alice, chat_id)
+            .await
+            .unwrap()
+            .param
+            .exists(Param::ProtectionBroken));
+
+        chat_id
+            .set_protection(&alice, ProtectionStatus::Unprotected)
+            .await
+            .unwrap();
+        assert_eq!(
+            protection_events(&alice, &event_rx).await,
+            vec![(chat_id, ChatProtectionStatus::Unprotected)]
+        );
+        assert_eq!(
+            chat_id.get_protection_status(&alice).await.unwrap(),
+            ChatProtectionStatus::Unprotected
+        );
+    }
+
+    #[async_std::test]
+    async fn test_group_color_from_grpid() -> Result<(), Error> {
+        let t = TestContext::new_alice().await;
+        let chat_id = create_group_chat(&t, ProtectionStatus::Unprotected, "foo").await?;
+        let chat = Chat::load_from_db(&t, chat_id).await?;
+        let color = chat.get_color(&t).await;
+        assert_eq!(color, str_to_color(&chat.grpid));
+
+        set_chat_name(&t, chat_id, "bar").await?;
+        let chat = Chat::load_from_db(&t, chat_id).await?;
+        assert_eq!(chat.get_color(&t).await, color);
+        Ok(())
+    }
+
+    async fn count_jobs(t: &TestContext, action: Action) -> usize {
+        t.sql
+            .query_get_value::<i64>(
+                t,
+                "SELECT COUNT(*) FROM jobs WHERE action=?;",
+                paramsv![action],
+            )
+            .await
+            .unwrap_or_default() as usize
+    }
+
+    #[async_std::test]
+    async fn test_send_msg_at() -> Result<(), Error> {
+        let t = TestContext::new_alice().await;
+        let chat = t.create_chat_with_contact("bob", "bob@example.net").await;
+
+        let mut msg = Message::new(Viewtype::Text);
+        msg.set_text(Some("happy birthday".to_string()));
+        let msg_id = send_msg_at(&t, chat.id, &mut msg, time() + 3600).await?;
+        let msg = Message::load_from_db(&t, msg_id).await?;
+        assert_eq!(msg.get_state(), MessageState::OutScheduled);
+        assert_eq!(t.get_last_msg_in(chat.id).await.id, msg_id);
+        assert_eq!(chat.id.get_fresh_msg_cnt(&t).await, 0);
+        assert_eq!(count_jobs(&t, Action::SendMsgToSmtp).await, 0);
+        assert!(job::load_next(&t, job::Thread::Smtp, &Default::default())
+            .await
+            .is_none());
+
+        // The scheduled message survives a restart.
+        t.sql.close().await;
+        t.sql.open(&t, &t.get_dbfile(), false).await?;
+        assert_eq!(
+            Message::load_from_db(&t, msg_id).await?.get_state(),
+            MessageState::OutScheduled
+        );
+
+        // Time passes.
+        t.sql
+            .execute(
+                "UPDATE jobs SET desired_timestamp=? WHERE action=?;",
+                paramsv![time() - 60, Action::SendScheduledMsg],
+            )
+            .await?;
+        let job = job::load_next(&t, job::Thread::Smtp, &Default::default())
+            .await
+            .unwrap();
+        assert_eq!(job.action, Action::SendScheduledMsg);
+        let mut smtp = crate::smtp::Smtp::new();
+        job::perform_job(&t, job::Connection::Smtp(&mut smtp), job).await;
+        assert_eq!(
+            Message::load_from_db(&t, msg_id).await?.get_state(),
+            MessageState::OutPending
+        );
+        assert!(message::cancel_scheduled(&t, msg_id).await.is_err());
+
+        // Promoting the message again, e.g. by a job left over from a crash,
+        // does not send it twice.
+        send_scheduled_msg(&t, msg_id).await?;
+        let sent = t.take_sent_msgs().await;
+        assert_eq!(sent.len(), 1);
+        assert!(sent.first().unwrap().payload().contains("happy birthday"));
+        assert_eq!(count_jobs(&t, Action::SendScheduledMsg).await, 0);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn test_cancel_scheduled_msg() -> Result<(), Error> {
+        let t = TestContext::new_alice().await;
+        let chat = t.create_chat_with_contact("bob", "bob@example.net").await;
+
+        let mut msg = Message::new(Viewtype::Text);
+        msg.set_text(Some("never sent".to_string()));
+        let msg_id = send_msg_at(&t, chat.id, &mut msg, time() + 3600).await?;
+        message::cancel_scheduled(&t, msg_id).await?;
+        assert!(Message::load_from_db(&t, msg_id).await.is_err());
+        assert_eq!(count_jobs(&t, Action::SendScheduledMsg).await, 0);
+        assert!(message::cancel_scheduled(&t, msg_id).await.is_err());
+
+        // A job running at the scheduled time anyway does not send the cancelled message.
+        send_scheduled_msg(&t, msg_id).await?;
+        assert_eq!(count_jobs(&t, Action::SendMsgToSmtp).await, 0);
+        assert!(t.take_sent_msgs().await.is_empty());
+
+        // Sending at a time in the past sends immediately.
+        let mut msg = Message::new(Viewtype::Text);
+        msg.set_text(Some("sent now".to_string()));
+        let msg_id = send_msg_at(&t, chat.id, &mut msg, time() - 10).await?;
+        assert_eq!(
+            Message::load_from_db(&t, msg_id).await?.get_state(),
+            MessageState::OutPending
+        );
+        assert!(message::cancel_scheduled(&t, msg_id).await.is_err());
+        assert!(t.pop_sent_msg().await.payload().contains("sent now"));
+        Ok(())
+    }
 }
@@ -3,7 +3,7 @@
 use anyhow::{bail, ensure, Result};
 
 use crate::chat;
-use crate::chat::{update_special_chat_names, Chat, ChatId, ChatVisibility};
+use crate::chat::{update_special_chat_names, Chat, ChatId, ChatVisibility, MuteDuration};
 use crate::constants::{
     Chattype, DC_CHAT_ID_ALLDONE_HINT, DC_CHAT_ID_ARCHIVED_LINK, DC_CHAT_ID_DEADDROP,
     DC_CONTACT_ID_DEVICE, DC_CONTACT_ID_SELF, DC_CONTACT_ID_UNDEFINED, DC_GCL_ADD_ALLDONE_HINT,
@@ -96,6 +96,27 @@ pub async fn try_load(
         listflags: usize,
         query: Option<&str>,
         query_contact_id: Option<u32>,
+    ) -> Result<Self> {
+        if cfg!(feature = "legacy-chatlist") {
+            return Chatlist::try_load_legacy(context, listflags, query, query_contact_id).await;
+        }
+
+        let ids = get_chatlist_fast(context, listflags, query, query_contact_id)
+            .await?
+            .into_iter()
+            .map(|entry| (entry.chat_id, entry.msg_id))
+            .collect();
+        Ok(Chatlist { ids })
+    }
+
+    /// Loads the chatlist using one query per filter and no per-chat state.
+    ///
+    /// Used instead of `get_chatlist_fast()` if the `legacy-chatlist` feature is enabled.
+    async fn try_load_legacy(
+        context: &Context,
+        listflags: usize,
+        query: Option<&str>,
+        query_contact_id: Option<u32>,
     ) -> Result<Self> {
         let flag_archived_only = 0 != listflags & DC_GCL_ARCHIVED_ONLY;
         let flag_for_forwarding = 0 != listflags & DC_GCL_FOR_FORWARDING;
@@ -187,7 +208,7 @@ pub async fn try_load(
                                   ORDER BY timestamp DESC, id DESC LIMIT 1)
                  WHERE c.id>9
                    AND c.blocked=0
-                   AND c.archived=1
+                   AND c.archived IN (1, 3)
                  GROUP BY c.id
                  ORDER BY IFNULL(m.timestamp,c.created_timestamp) DESC, m.id DESC;",
                     paramsv![MessageState::OutDraft],
@@ -252,10 +273,10 @@ pub async fn try_load(
                                   ORDER BY timestamp DESC, id DESC LIMIT 1)
                  WHERE c.id>9 AND c.id!=?2
                    AND c.blocked=0
-                   AND NOT c.archived=?3
+                   AND c.archived NOT IN (?3, ?6)
                  GROUP BY c.id
                  ORDER BY c.id=?4 DESC, c.archived=?5 DESC, IFNULL(m.timestamp,c.created_timestamp) DESC, m.id DESC;",
-                paramsv![MessageState::OutDraft, skip_id, ChatVisibility::Archived, sort_id_up, ChatVisibility::Pinned],
+                paramsv![MessageState::OutDraft, skip_id, ChatVisibility::Archived, sort_id_up, ChatVisibility::Pinned, ChatVisibility::NoUnarchive],
                 process_row,
                 process_rows,
             ).await?;
@@ -374,7 +395,9 @@ pub async fn get_summary2(
                                 Contact::load_from_db(context, lastmsg.from_id).await.ok();
                             (Some(lastmsg), lastcontact)
                         }
-                        Chattype::Single | Chattype::Undefined => (Some(lastmsg), None),
+                        Chattype::Single | Chattype::Undefined | Chattype::Broadcast => {
+                            (Some(lastmsg), None)
+                        }
                     }
                 }
             } else {
@@ -399,13 +422,208 @@ pub fn get_index_for_id(&self, id: ChatId) -> Option<usize> {
     }
 }
 
+/// A chatlist entry as returned by `get_chatlist_fast()`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChatlistEntry {
+    pub chat_id: ChatId,
+    /// The last message of the chat, this may be a draft.
+    /// `MsgId::new(0)` for chats without messages.
+    pub msg_id: MsgId,
+    /// The number of fresh messages as returned by `ChatId::get_fresh_msg_cnt()`.
+    pub fresh_msg_cnt: usize,
+    pub has_draft: bool,
+    pub visibility: ChatVisibility,
+    pub mute_duration: MuteDuration,
+}
+
+impl ChatlistEntry {
+    fn special(chat_id: u32, msg_id: MsgId) -> Self {
+        ChatlistEntry {
+            chat_id: ChatId::new(chat_id),
+            msg_id,
+            fresh_msg_cnt: 0,
+            has_draft: false,
+            visibility: ChatVisibility::Normal,
+            mute_duration: MuteDuration::NotMuted,
+        }
+    }
+}
+
+/// Returns the query of [`get_chatlist_fast`] for the given filter and sort prefix.
+///
+/// The last message of each chat is looked up using the index over `(chat_id, timestamp)`,
+/// the fresh message count uses the index over `(state, hidden, chat_id)`,
+/// see `ChatId::get_fresh_msg_cnt()`.
+fn chatlist_query(filter: &str, order: &str) -> String {
+    format!(
+        "SELECT c.id, m.id, IFNULL(f.cnt, 0), d.chat_id IS NOT NULL, c.archived, c.muted_until
+         FROM chats c
+         LEFT JOIN msgs m
+                ON c.id=m.chat_id
+               AND m.id=(
+                       SELECT id
+                         FROM msgs
+                        WHERE chat_id=c.id
+                          AND (hidden=0 OR state=?1)
+                          ORDER BY timestamp DESC, id DESC LIMIT 1)
+         LEFT JOIN (SELECT chat_id, COUNT(*) AS cnt
+                      FROM msgs
+                     WHERE state=10 AND hidden=0
+                     GROUP BY chat_id) f
+                ON c.id=f.chat_id
+         LEFT JOIN (SELECT DISTINCT chat_id FROM msgs WHERE state=?1) d
+                ON c.id=d.chat_id
+         WHERE c.id>9
+           AND c.blocked=0
+           AND {filter}
+         GROUP BY c.id
+         ORDER BY {order} IFNULL(m.timestamp,c.created_timestamp) DESC, m.id DESC;",
+        filter = filter,
+        order = order
+    )
+}
+
+/// Get a list of chats together with the state needed to render each entry.
+///
+/// `listflags`, `query` and `query_contact_id` filter the list as for `Chatlist::try_load()`,
+/// the order and the special entries are the same as well.
+/// Other than that, the last message, the number of fresh messages, the presence of a draft
+/// and the visibility and mute state of all chats are selected in a single query,
+/// so the UI does not need to query them for each chat.
+pub async fn get_chatlist_fast(
+    context: &Context,
+    listflags: usize,
+    query: Option<&str>,
+    query_contact_id: Option<u32>,
+) -> Result<Vec<ChatlistEntry>> {
+    let flag_archived_only = 0 != listflags & DC_GCL_ARCHIVED_ONLY;
+    let flag_for_forwarding = 0 != listflags & DC_GCL_FOR_FORWARDING;
+    let flag_no_specials = 0 != listflags & DC_GCL_NO_SPECIALS;
+    let flag_add_alldone_hint = 0 != listflags & DC_GCL_ADD_ALLDONE_HINT;
+
+    // Note that we do not emit DC_EVENT_MSGS_MODIFIED here even if some
+    // messages get deleted to avoid reloading the same chatlist.
+    if let Err(err) = delete_expired_messages(context).await {
+        warn!(context, "Failed to hide expired messages: {}", err);
+    }
+
+    let (skip_id, sort_id_up) = if flag_for_forwarding {
+        (
+            chat::lookup_by_contact_id(context, DC_CONTACT_ID_DEVICE)
+                .await
+                .unwrap_or_default()
+                .0,
+            chat::lookup_by_contact_id(context, DC_CONTACT_ID_SELF)
+                .await
+                .unwrap_or_default()
+                .0,
+        )
+    } else {
+        (ChatId::new(0), ChatId::new(0))
+    };
+    let query = query.map(str::trim);
+    let str_like_cmd = format!("%{}%", query.unwrap_or_default());
+    let contact_id = query_contact_id.unwrap_or_default() as i32;
+
+    // `?1` is the draft state in all variants,
+    // the filter and the sort prefix decide about the other parameters.
+    let mut add_archived_link_item = false;
+    let (filter, order, params) = if query_contact_id.is_some() {
+        // show chats shared with a given contact
+        (
+            "c.id IN(SELECT chat_id FROM chats_contacts WHERE contact_id=?2)",
+            "c.archived=?3 DESC,",
+            paramsv![MessageState::OutDraft, contact_id, ChatVisibility::Pinned],
+        )
+    } else if flag_archived_only {
+        // show archived chats, this includes the archived device-chat,
+        // see `Chatlist::try_load_legacy()`
+        ("c.archived IN (1, 3)", "", paramsv![MessageState::OutDraft])
+    } else if let Some(query) = query {
+        ensure!(!query.is_empty(), "missing query");
+
+        // allow searching over special names that may change at any time
+        // when the ui calls set_stock_translation()
+        if let Err(err) = update_special_chat_names(context).await {
+            warn!(context, "cannot update special chat names: {:?}", err)
+        }
+        (
+            "c.id!=?2 AND c.name LIKE ?3",
+            "",
+            paramsv![MessageState::OutDraft, skip_id, str_like_cmd],
+        )
+    } else {
+        // show normal chatlist
+        add_archived_link_item = !flag_no_specials;
+        (
+            "c.id!=?2 AND c.archived NOT IN (?3, ?6)",
+            "c.id=?4 DESC, c.archived=?5 DESC,",
+            paramsv![
+                MessageState::OutDraft,
+                skip_id,
+                ChatVisibility::Archived,
+                sort_id_up,
+                ChatVisibility::Pinned,
+                ChatVisibility::NoUnarchive
+            ],
+        )
+    };
+
+    let mut entries = context
+        .sql
+        .query_map(
+            chatlist_query(filter, order),
+            params,
+            |row| {
+                Ok(ChatlistEntry {
+                    chat_id: row.get(0)?,
+                    msg_id: row.get(1).unwrap_or_default(),
+                    fresh_msg_cnt: row.get::<_, i64>(2)? as usize,
+                    has_draft: row.get(3)?,
+                    visibility: row.get(4)?,
+                    mute_duration: row.get(5)?,
+                })
+            },
+            |rows| {
+                rows.collect::<std::result::Result<Vec<_>, _>>()
+                    .map_err(Into::into)
+            },
+        )
+        .await?;
+
+    if add_archived_link_item {
+        if let Some(last_deaddrop_fresh_msg_id) = get_last_deaddrop_fresh_msg(context).await {
+            if !flag_for_forwarding {
+                entries.insert(
+                    0,
+                    ChatlistEntry::special(DC_CHAT_ID_DEADDROP, last_deaddrop_fresh_msg_id),
+                );
+            }
+        }
+        if dc_get_archived_cnt(context).await > 0 {
+            if entries.is_empty() && flag_add_alldone_hint {
+                entries.push(ChatlistEntry::special(
+                    DC_CHAT_ID_ALLDONE_HINT,
+                    MsgId::new(0),
+                ));
+            }
+            entries.push(ChatlistEntry::special(
+                DC_CHAT_ID_ARCHIVED_LINK,
+                MsgId::new(0),
+            ));
+        }
+    }
+
+    Ok(entries)
+}
+
 /// Returns the number of archived chats
 pub async fn dc_get_archived_cnt(context: &Context) -> u32 {
     context
         .sql
         .query_get_value(
             context,
-            "SELECT COUNT(*) FROM chats WHERE blocked=0 AND archived=1;",
+            "SELECT COUNT(*) FROM chats WHERE blocked=0 AND archived IN (1, 3);",
             paramsv![],
         )
         .await
@@ -438,10 +656,80 @@ async fn get_last_deaddrop_fresh_msg(context: &Context) -> Option<MsgId> {
 mod tests {
     use super::*;
 
-    use crate::chat::{create_group_chat, ProtectionStatus};
+    use crate::chat::{add_device_msg, create_group_chat, set_muted, ProtectionStatus};
     use crate::constants::Viewtype;
+    use crate::contact::Origin;
     use crate::stock_str::StockMessage;
-    use crate::test_utils::TestContext;
+    use crate::test_utils::{receive_chat_msg, TestContext};
+
+    #[async_std::test]
+    async fn test_chatlist_query_plan() {
+        let t = TestContext::new().await;
+        let query = chatlist_query(
+            "c.id!=?2 AND c.archived NOT IN (?3, ?6)",
+            "c.id=?4 DESC, c.archived=?5 DESC,",
+        );
+        let plan = t
+            .sql
+            .query_map(
+                format!("EXPLAIN QUERY PLAN {}", query),
+                paramsv![
+                    MessageState::OutDraft,
+                    0,
+                    ChatVisibility::Archived,
+                    0,
+                    ChatVisibility::Pinned,
+                    ChatVisibility::NoUnarchive
+                ],
+                |row| {
+                    Ok((
+                        row.get::<_, i64>(0)?,
+                        row.get::<_, i64>(1)?,
+                        row.get::<_, String>(3)?,
+                    ))
+                },
+                |rows| {
+                    rows.collect::<std::result::Result<Vec<_>, _>>()
+                        .map_err(Into::into)
+                },
+            )
+            .await
+            .unwrap();
+
+        // Only the materialized subqueries, which are small, may be scanned.
+        for (_, _, detail) in &plan {
+            let scans_table = detail.starts_with("SCAN")
+                && !detail.contains("SUBQUERY")
+                && !detail.starts_with("SCAN d")
+                && !detail.starts_with("SCAN f");
+            assert!(!scans_table, "{:#?}", plan);
+        }
+
+        // The last message of each chat is found without sorting its messages.
+        let (subquery_id, _, _) = plan
+            .iter()
+            .find(|(_, _, detail)| detail.starts_with("CORRELATED SCALAR SUBQUERY"))
+            .unwrap();
+        let subquery_plan: Vec<&str> = plan
+            .iter()
+            .filter(|(_, parent, _)| parent == subquery_id)
+            .map(|(_, _, detail)| detail.as_str())
+            .collect();
+        assert!(
+            subquery_plan
+                .iter()
+                .any(|detail| detail.starts_with("SEARCH") && detail.contains("msgs_index8")),
+            "{:#?}",
+            plan
+        );
+        assert!(
+            !subquery_plan
+                .iter()
+                .any(|detail| detail.contains("TEMP B-TREE")),
+            "{:#?}",
+            plan
+        );
+    }
 
     #[async_std::test]
     async fn test_try_load() {
@@ -560,4 +848,128 @@ async fn test_get_summary_unwrap() {
         let summary = chats.get_summary(&t, 0, None).await;
         assert_eq!(summary.get_text2().unwrap(), "foo: bar test"); // the linebreak should be removed from summary
     }
+
+    async fn assert_fast_matches_legacy(
+        t: &TestContext,
+        listflags: usize,
+        query: Option<&str>,
+        query_contact_id: Option<u32>,
+    ) {
+        let legacy = Chatlist::try_load_legacy(t, listflags, query, query_contact_id)
+            .await
+            .unwrap();
+        let entries = get_chatlist_fast(t, listflags, query, query_contact_id)
+            .await
+            .unwrap();
+        let ids: Vec<(ChatId, MsgId)> = entries.iter().map(|e| (e.chat_id, e.msg_id)).collect();
+        assert_eq!(legacy.ids, ids, "listflags={} query={:?}", listflags, query);
+
+        for entry in entries {
+            if entry.chat_id.is_special() {
+                continue;
+            }
+            let chat = Chat::load_from_db(t, entry.chat_id).await.unwrap();
+            assert_eq!(entry.visibility, chat.visibility);
+            assert_eq!(entry.mute_duration, chat.mute_duration);
+            assert_eq!(
+                entry.fresh_msg_cnt,
+                entry.chat_id.get_fresh_msg_cnt(t).await
+            );
+            assert_eq!(
+                entry.has_draft,
+                entry.chat_id.get_draft(t).await.unwrap().is_some()
+            );
+        }
+    }
+
+    #[async_std::test]
+    async fn test_chatlist_fast_parity() {
+        let t = TestContext::new_alice().await;
+
+        let pinned = t
+            .create_chat_with_contact("Bob", "bob@example.net")
+            .await
+            .id;
+        receive_chat_msg(&t, "bob@example.net", 1).await;
+        receive_chat_msg(&t, "bob@example.net", 2).await;
+        pinned
+            .set_visibility(&t, ChatVisibility::Pinned)
+            .await
+            .unwrap();
+
+        let archived = t
+            .create_chat_with_contact("Claire", "claire@example.org")
+            .await
+            .id;
+        t.send_text(archived, "archived").await;
+        archived
+            .set_visibility(&t, ChatVisibility::Archived)
+            .await
+            .unwrap();
+
+        let muted = t
+            .create_chat_with_contact("Dora", "dora@example.org")
+            .await
+            .id;
+        t.send_text(muted, "muted").await;
+        set_muted(&t, muted, MuteDuration::Forever).await.unwrap();
+
+        let with_draft = create_group_chat(&t, ProtectionStatus::Unprotected, "drafts")
+            .await
+            .unwrap();
+        let mut msg = Message::new(Viewtype::Text);
+        msg.set_text(Some("draft".to_string()));
+        with_draft.set_draft(&t, Some(&mut msg)).await;
+
+        create_group_chat(&t, ProtectionStatus::Unprotected, "empty")
+            .await
+            .unwrap();
+        t.get_self_chat().await;
+        let mut msg = Message::new(Viewtype::Text);
+        msg.set_text(Some("device".to_string()));
+        add_device_msg(&t, None, Some(&mut msg)).await.unwrap();
+
+        // a contact request shown as deaddrop
+        receive_chat_msg(&t, "unknown@example.org", 3).await;
+
+        let entries = get_chatlist_fast(&t, DC_GCL_NO_SPECIALS, None, None)
+            .await
+            .unwrap();
+        let pinned_entry = entries.first().unwrap();
+        assert_eq!(pinned_entry.chat_id, pinned);
+        assert_eq!(pinned_entry.fresh_msg_cnt, 2);
+        assert!(entries
+            .iter()
+            .any(|e| e.chat_id == with_draft && e.has_draft));
+        assert!(entries
+            .iter()
+            .any(|e| e.chat_id == muted && e.mute_duration == MuteDuration::Forever));
+        assert!(entries.iter().all(|e| e.chat_id != archived));
+
+        let bob_id = Contact::lookup_id_by_addr(&t, "bob@example.net", Origin::Unknown)
+            .await
+            .unwrap();
+        for listflags in &[
+            0,
+            DC_GCL_NO_SPECIALS,
+            DC_GCL_ARCHIVED_ONLY,
+            DC_GCL_FOR_FORWARDING,
+            DC_GCL_FOR_FORWARDING | DC_GCL_NO_SPECIALS,
+            DC_GCL_ADD_ALLDONE_HINT,
+        ] {
+            assert_fast_matches_legacy(&t, *listflags, None, None).await;
+            assert_fast_matches_legacy(&t, *listflags, Some("o"), None).await;
+            assert_fast_matches_legacy(&t, *listflags, None, bob_id).await;
+        }
+        assert!(get_chatlist_fast(&t, 0, Some(" "), None).await.is_err());
+
+        for chat_id in &[pinned, muted, with_draft] {
+            chat_id
+                .set_visibility(&t, ChatVisibility::Archived)
+                .await
+                .unwrap();
+        }
+        assert_fast_matches_legacy(&t, DC_GCL_ADD_ALLDONE_HINT, None, None).await;
+        assert_fast_matches_legacy(&t, DC_GCL_ARCHIVED_ONLY, None, None).await;
+    }
 }
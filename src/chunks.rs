@@ -0,0 +1,511 @@
+//! # Attachments sent in several parts.
+//!
+//! Attachments larger than the chunk size (see [Config::MaxMessageSize]) are not sent
+//! within their message.  Instead, the message carries a manifest with the size, the
+//! number of parts and the BLAKE3 hash of the file, and the file is split into parts
+//! that are sent as hidden messages replying to the message.
+//!
+//! The receiver shows the message with [DownloadState::InProgress] until all parts have
+//! arrived, then assembles the parts, verifies the hash and sets the file of the message.
+//! If parts are missing, [MsgId::download_full] sends a hidden request for them to the
+//! sender, which sends the requested parts again.
+//!
+//! [MsgId::download_full]: crate::message::MsgId::download_full
+
+use std::time::{Duration, Instant};
+
+use anyhow::{ensure, format_err, Result};
+use async_std::fs;
+use async_std::io::SeekFrom;
+use async_std::path::Path;
+use async_std::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::blob::BlobObject;
+use crate::chat;
+use crate::config::Config;
+use crate::constants::{Viewtype, DC_CONTACT_ID_SELF};
+use crate::context::Context;
+use crate::dc_tools::{dc_delete_file, dc_get_abs_path, dc_get_filebytes};
+use crate::download::{set_download_state, DownloadState};
+use crate::message::{Message, MsgId};
+use crate::mimefactory::UPPER_LIMIT_FILE_SIZE;
+use crate::mimeparser::SystemMessage;
+use crate::param::Param;
+
+/// Filename of the attachment part carried by a [SystemMessage::Chunk] message.
+pub(crate) const CHUNK_FILENAME: &str = "deltachat-chunk.bin";
+
+/// Filename of the manifest sent instead of the attachment.
+pub(crate) const CHUNK_MANIFEST_FILENAME: &str = "chunk-manifest.json";
+
+/// Filename of the request carried by a [SystemMessage::ChunkRequest] message.
+pub(crate) const CHUNK_REQUEST_FILENAME: &str = "chunk-request.json";
+
+/// Minimum time between two answered requests for parts of the same message
+/// by the same contact.
+pub(crate) const CHUNK_REQUEST_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Describes an attachment sent in several parts.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct Manifest {
+    /// Hex encoded BLAKE3 hash of the file contents.
+    pub hash: String,
+
+    /// Number of parts.
+    pub parts: u32,
+
+    /// Size of the file in bytes.
+    pub size: u64,
+
+    /// Size of all parts but the last one in bytes.
+    pub chunk_size: u64,
+
+    pub name: String,
+    pub mimetype: String,
+    pub viewtype: Viewtype,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ChunkRequest {
+    parts: Vec<u32>,
+}
+
+/// Returns the size of the parts of attachments sent in several parts,
+/// smaller attachments are sent within their message.
+pub(crate) async fn chunk_size(context: &Context) -> u64 {
+    match context.get_config_int(Config::MaxMessageSize).await {
+        // Leave room for the base64 encoding and the headers.
+        max if max > 0 => max as u64 / 2,
+        _ => UPPER_LIMIT_FILE_SIZE,
+    }
+}
+
+/// Prepares sending the attachment of `msg` in several parts if it is too large.
+///
+/// Sets the manifest of `msg` and returns the hidden messages carrying the parts,
+/// they have to be sent after `msg`.  Returns an empty list if the attachment
+/// can be sent within `msg`.
+pub(crate) async fn prepare_chunks(context: &Context, msg: &mut Message) -> Result<Vec<Message>> {
+    if !chat::msgtype_has_file(msg.viewtype) || msg.param.exists(Param::ChunkManifest) {
+        return Ok(Vec::new());
+    }
+    let path = match msg.param.get_path(Param::File, context)? {
+        Some(path) => path,
+        None => return Ok(Vec::new()),
+    };
+    let size = dc_get_filebytes(context, &path).await;
+    let chunk_size = chunk_size(context).await;
+    if size <= chunk_size {
+        return Ok(Vec::new());
+    }
+
+    let manifest = Manifest {
+        hash: hash_file(&path).await?,
+        parts: ((size + chunk_size - 1) / chunk_size) as u32,
+        size,
+        chunk_size,
+        name: msg.get_filename().unwrap_or_default(),
+        mimetype: msg
+            .param
+            .get(Param::MimeType)
+            .unwrap_or("application/octet-stream")
+            .to_string(),
+        viewtype: msg.viewtype,
+    };
+    info!(
+        context,
+        "Sending attachment of message {} in {} parts", msg.id, manifest.parts
+    );
+    msg.param
+        .set(Param::ChunkManifest, serde_json::to_string(&manifest)?);
+    msg.update_param(context).await;
+
+    Ok((0..manifest.parts)
+        .map(|index| new_chunk_msg(msg, &manifest, index))
+        .collect())
+}
+
+fn new_chunk_msg(msg: &Message, manifest: &Manifest, index: u32) -> Message {
+    let mut chunk = Message::new(Viewtype::Text);
+    chunk.hidden = true;
+    chunk.text = Some(format!(
+        "Part {} of {} of {}",
+        index + 1,
+        manifest.parts,
+        manifest.name
+    ));
+    chunk.in_reply_to = Some(msg.rfc724_mid.clone());
+    chunk.param.set_cmd(SystemMessage::Chunk);
+    if let Some(file) = msg.param.get(Param::File) {
+        chunk.param.set(Param::File, file);
+    }
+    chunk.param.set_i64(Param::Arg, index.into());
+    chunk.param.set_i64(Param::Arg2, manifest.chunk_size as i64);
+    chunk
+}
+
+/// Reads the part `index` of a file split into parts of `chunk_size` bytes.
+pub(crate) async fn read_chunk(path: &Path, index: u64, chunk_size: u64) -> Result<Vec<u8>> {
+    ensure!(chunk_size > 0, "invalid chunk size");
+    let mut file = fs::File::open(path).await?;
+    file.seek(SeekFrom::Start(index * chunk_size)).await?;
+    let mut data = Vec::new();
+    file.take(chunk_size).read_to_end(&mut data).await?;
+    ensure!(
+        !data.is_empty(),
+        "part {} is beyond the end of the file",
+        index
+    );
+    Ok(data)
+}
+
+async fn hash_file(path: &Path) -> Result<String> {
+    let mut file = fs::File::open(path).await?;
+    let mut hasher = blake3::Hasher::new();
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        let n = file.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(buf.get(..n).unwrap_or_default());
+    }
+    Ok(hasher.finalize().to_hex().to_string())
+}
+
+fn get_manifest(msg: &Message) -> Result<Manifest> {
+    let manifest = msg
+        .param
+        .get(Param::ChunkManifest)
+        .ok_or_else(|| format_err!("message {} is not sent in parts", msg.id))?;
+    Ok(serde_json::from_str(manifest)?)
+}
+
+/// Stores a received part of the attachment of `target`,
+/// the attachment is assembled once all parts have arrived.
+pub(crate) async fn add_chunk(
+    context: &Context,
+    target: &Message,
+    index: u32,
+    data: &[u8],
+) -> Result<()> {
+    let manifest = get_manifest(target)?;
+    if target.param.exists(Param::File) || target.download_state == DownloadState::Done {
+        // Own message or already assembled.
+        return Ok(());
+    }
+    ensure!(
+        index < manifest.parts,
+        "part {} of {} parts",
+        index,
+        manifest.parts
+    );
+    if context
+        .sql
+        .exists(
+            "SELECT part FROM msgs_chunks WHERE msg_id=? AND part=?;",
+            paramsv![target.id, index],
+        )
+        .await?
+    {
+        info!(
+            context,
+            "Part {} of message {} already received", index, target.id
+        );
+        return Ok(());
+    }
+
+    let blob = BlobObject::create(context, "chunk.bin", data).await?;
+    context
+        .sql
+        .execute(
+            "INSERT INTO msgs_chunks (msg_id, part, file) VALUES (?,?,?);",
+            paramsv![target.id, index, blob.as_name()],
+        )
+        .await?;
+
+    let received: u32 = context
+        .sql
+        .query_get_value_result(
+            "SELECT COUNT(*) FROM msgs_chunks WHERE msg_id=?;",
+            paramsv![target.id],
+        )
+        .await?
+        .unwrap_or_default();
+    if received >= manifest.parts {
+        assemble(context, target.id, &manifest).await?;
+    }
+    Ok(())
+}
+
+/// Assembles the received parts and sets the file of the message if the hash matches.
+///
+/// The parts are removed in any case.
+async fn assemble(context: &Context, msg_id: MsgId, manifest: &Manifest) -> Result<()> {
+    let files = get_chunk_files(context, msg_id).await?;
+    let blob = BlobObject::create(context, &manifest.name, &[]).await?;
+    let res = write_parts(context, &blob.to_abs_path(), &files).await;
+    delete_chunks(context, msg_id, &files).await?;
+
+    let error = match res {
+        Ok(hash) if hash == manifest.hash => {
+            let mut msg = Message::load_from_db(context, msg_id).await?;
+            msg.param.set(Param::File, blob.as_name());
+            msg.param.remove(Param::ChunkManifest);
+            msg.update_param(context).await;
+            set_download_state(context, msg_id, DownloadState::Done, None).await?;
+            info!(context, "Assembled attachment of message {}", msg_id);
+            return Ok(());
+        }
+        Ok(_) => "Checksum mismatch of the received attachment".to_string(),
+        Err(err) => format!("Cannot assemble the received attachment: {:#}", err),
+    };
+    warn!(context, "Message {}: {}", msg_id, error);
+    dc_delete_file(context, blob.as_name()).await;
+    set_download_state(context, msg_id, DownloadState::Failure, Some(&error)).await?;
+    Ok(())
+}
+
+/// Appends the files to `path` and returns the hash of the result.
+async fn write_parts(context: &Context, path: &Path, files: &[String]) -> Result<String> {
+    let mut out = fs::OpenOptions::new().write(true).open(path).await?;
+    let mut hasher = blake3::Hasher::new();
+    for file in files {
+        let data = fs::read(dc_get_abs_path(context, file)).await?;
+        hasher.update(&data);
+        out.write_all(&data).await?;
+    }
+    out.flush().await?;
+    Ok(hasher.finalize().to_hex().to_string())
+}
+
+async fn get_chunk_files(context: &Context, msg_id: MsgId) -> Result<Vec<String>> {
+    let files = context
+        .sql
+        .query_map(
+            "SELECT file FROM msgs_chunks WHERE msg_id=? ORDER BY part;",
+            paramsv![msg_id],
+            |row| row.get::<_, String>(0),
+            |rows| {
+                rows.collect::<std::result::Result<Vec<_>, _>>()
+                    .map_err(Into::into)
+            },
+        )
+        .await?;
+    Ok(files)
+}
+
+async fn delete_chunks(context: &Context, msg_id: MsgId, files: &[String]) -> Result<()> {
+    for file in files {
+        dc_delete_file(context, file).await;
+    }
+    context
+        .sql
+        .execute("DELETE FROM msgs_chunks WHERE msg_id=?;", paramsv![msg_id])
+        .await?;
+    Ok(())
+}
+
+/// Requests the missing parts of the attachment of `msg` from the sender.
+pub(crate) async fn request_missing(context: &Context, msg: &Message) -> Result<()> {
+    let manifest = get_manifest(msg)?;
+    let received: Vec<u32> = context
+        .sql
+        .query_map(
+            "SELECT part FROM msgs_chunks WHERE msg_id=?;",
+            paramsv![msg.id],
+            |row| row.get::<_, u32>(0),
+            |rows| {
+                rows.collect::<std::result::Result<Vec<_>, _>>()
+                    .map_err(Into::into)
+            },
+        )
+        .await?;
+    let request = ChunkRequest {
+        parts: (0..manifest.parts)
+            .filter(|index| !received.contains(index))
+            .collect(),
+    };
+    set_download_state(context, msg.id, DownloadState::InProgress, None).await?;
+    if request.parts.is_empty() {
+        return Ok(());
+    }
+    info!(
+        context,
+        "Requesting {} missing parts of message {}",
+        request.parts.len(),
+        msg.id
+    );
+
+    let mut req = Message::new(Viewtype::Text);
+    req.hidden = true;
+    req.text = Some(format!("Requesting parts of {}", manifest.name));
+    req.in_reply_to = Some(msg.rfc724_mid.clone());
+    req.param.set_cmd(SystemMessage::ChunkRequest);
+    req.param.set(Param::Arg, serde_json::to_string(&request)?);
+    chat::send_msg(context, msg.chat_id, &mut req).await?;
+    Ok(())
+}
+
+/// Sends the parts of the attachment of `target` requested by `from_id` with a
+/// [SystemMessage::ChunkRequest] message again.
+///
+/// Only the device that sent `target` still has the manifest and the file and answers.
+/// Requests of a contact for the same message are answered at most once
+/// per [CHUNK_REQUEST_INTERVAL], so that a member cannot make us send a huge file
+/// over and over.
+pub(crate) async fn handle_request(
+    context: &Context,
+    target: &Message,
+    from_id: u32,
+    json: &str,
+) -> Result<()> {
+    if target.from_id != DC_CONTACT_ID_SELF || !target.param.exists(Param::File) {
+        return Ok(());
+    }
+    let manifest = get_manifest(target)?;
+    let request: ChunkRequest = serde_json::from_str(json)?;
+    {
+        let mut answered = context.chunk_requests.lock().await;
+        let now = Instant::now();
+        answered.retain(|_, last| now.duration_since(*last) < CHUNK_REQUEST_INTERVAL);
+        if answered.contains_key(&(target.id, from_id)) {
+            info!(
+                context,
+                "Ignoring repeated request of contact {} for parts of message {}",
+                from_id,
+                target.id
+            );
+            return Ok(());
+        }
+        answered.insert((target.id, from_id), now);
+    }
+    for index in request.parts {
+        if index >= manifest.parts {
+            warn!(context, "Ignoring request for part {}", index);
+            continue;
+        }
+        let mut chunk = new_chunk_msg(target, &manifest, index);
+        chat::send_msg(context, target.chat_id, &mut chunk).await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::chat::accept_contact_requests;
+    use crate::test_utils::TestContext;
+
+    async fn send_large_file(alice: &TestContext, bob: &TestContext, data: &[u8]) -> MsgId {
+        alice
+            .set_config(Config::MaxMessageSize, Some("2000"))
+            .await
+            .unwrap();
+        let file = alice.get_blobdir().join("large.bin");
+        fs::write(&file, data).await.unwrap();
+        let chat = alice.create_chat(bob).await;
+        let mut msg = Message::new(Viewtype::File);
+        msg.set_text(Some("large file".to_string()));
+        msg.set_file(file.to_str().unwrap(), None);
+        chat::send_msg(alice, chat.id, &mut msg).await.unwrap()
+    }
+
+    fn test_data() -> Vec<u8> {
+        (0..3500u32).map(|i| (i % 251) as u8).collect()
+    }
+
+    #[async_std::test]
+    async fn test_missing_chunk_requested() {
+        let alice = TestContext::new_alice().await;
+        let bob = TestContext::new_bob().await;
+        let data = test_data();
+        let alice_msg_id = send_large_file(&alice, &bob, &data).await;
+
+        // The message and four parts of 1000 bytes.
+        let sent = alice.take_sent_msgs().await;
+        assert_eq!(sent.len(), 5);
+        let alice_msg = Message::load_from_db(&alice, alice_msg_id).await.unwrap();
+        assert_eq!(alice_msg.get_download_state(), DownloadState::Done);
+        assert!(alice_msg.get_file(&alice).is_some());
+
+        // The second part is lost.
+        for (i, msg) in sent.iter().enumerate() {
+            if i != 2 {
+                bob.recv_msg(msg).await;
+            }
+        }
+        let bob_msg = bob.get_last_msg().await;
+        assert_eq!(bob_msg.get_viewtype(), Viewtype::File);
+        assert_eq!(bob_msg.get_text(), Some("large file".to_string()));
+        assert_eq!(bob_msg.get_download_state(), DownloadState::InProgress);
+        assert_eq!(bob_msg.get_filebytes(&bob).await, 0);
+        assert!(bob_msg.get_file(&bob).is_none());
+
+        accept_contact_requests(&bob, &[bob_msg.chat_id])
+            .await
+            .unwrap();
+        bob_msg.id.download_full(&bob).await.unwrap();
+        let request = bob.pop_sent_msg().await;
+        alice.recv_msg(&request).await;
+
+        let resent = alice.take_sent_msgs().await;
+        assert_eq!(resent.len(), 1);
+
+        // Repeated requests are not answered for a while.
+        bob_msg.id.download_full(&bob).await.unwrap();
+        alice.recv_msg(&bob.pop_sent_msg().await).await;
+        assert!(alice.take_sent_msgs().await.is_empty());
+
+        bob.recv_msg(resent.first().unwrap()).await;
+
+        let bob_msg = Message::load_from_db(&bob, bob_msg.id).await.unwrap();
+        assert_eq!(bob_msg.get_download_state(), DownloadState::Done);
+        assert!(!bob_msg.param.exists(Param::ChunkManifest));
+        let file = bob_msg.get_file(&bob).unwrap();
+        assert_eq!(fs::read(file).await.unwrap(), data);
+
+        // The parts are removed once assembled.
+        assert!(get_chunk_files(&bob, bob_msg.id).await.unwrap().is_empty());
+    }
+
+    #[async_std::test]
+    async fn test_chunk_hash_mismatch() {
+        let alice = TestContext::new_alice().await;
+        let bob = TestContext::new_bob().await;
+        let data = test_data();
+        send_large_file(&alice, &bob, &data).await;
+        let sent = alice.take_sent_msgs().await;
+        assert_eq!(sent.len(), 5);
+
+        for msg in sent.iter().take(4) {
+            bob.recv_msg(msg).await;
+        }
+        let bob_msg = bob.get_last_msg().await;
+        assert_eq!(bob_msg.get_download_state(), DownloadState::InProgress);
+
+        // Corrupt a stored part before the last part arrives.
+        let files = get_chunk_files(&bob, bob_msg.id).await.unwrap();
+        assert_eq!(files.len(), 3);
+        fs::write(dc_get_abs_path(&bob, files.first().unwrap()), b"garbage")
+            .await
+            .unwrap();
+        bob.recv_msg(sent.last().unwrap()).await;
+
+        let bob_msg = Message::load_from_db(&bob, bob_msg.id).await.unwrap();
+        assert_eq!(bob_msg.get_download_state(), DownloadState::Failure);
+        assert!(bob_msg.error().is_some());
+        assert!(bob_msg.get_file(&bob).is_none());
+        assert!(get_chunk_files(&bob, bob_msg.id).await.unwrap().is_empty());
+    }
+
+    #[async_std::test]
+    async fn test_small_file_not_chunked() {
+        let alice = TestContext::new_alice().await;
+        let bob = TestContext::new_bob().await;
+        send_large_file(&alice, &bob, b"small").await;
+        assert_eq!(alice.take_sent_msgs().await.len(), 1);
+    }
+}
@@ -4,6 +4,17 @@
 //!
 //! Color Vision Deficiency correction is not implemented as Delta Chat does not offer
 //! corresponding settings.
+//!
+//! The color of an identifier is computed as follows, so it is the same on all platforms:
+//!
+//! 1. The first two bytes of the SHA-1 hash of the UTF-8 encoded identifier are read as
+//!    little-endian 16-bit number and scaled to a hue angle in `[0, 360)`.
+//! 2. The angle is converted to RGB in the HSLuv color space with saturation 100 and
+//!    lightness 50.
+//! 3. Each channel is multiplied by 256, truncated and capped at 255, giving `0xRRGGBB`.
+//!
+//! Contacts use their normalized, lowercased address as identifier, groups their group ID.
+//! `test-data/color/vectors.txt` pins the resulting colors.
 use hsluv::hsluv_to_rgb;
 use sha1::{Digest, Sha1};
 
@@ -59,4 +70,18 @@ fn test_rgb_to_u32() {
         assert_eq!(rgb_to_u32((1.0, 0.0, 0.0)), 0xff0000);
         assert_eq!(rgb_to_u32((1.0, 0.5, 0.0)), 0xff8000);
     }
+
+    #[test]
+    fn test_str_to_color_vectors() {
+        let vectors = include_str!("../test-data/color/vectors.txt");
+        let mut count = 0;
+        for line in vectors.lines().filter(|line| !line.starts_with('#')) {
+            let mut parts = line.splitn(2, ' ');
+            let color = u32::from_str_radix(parts.next().unwrap(), 16).unwrap();
+            let input = parts.next().unwrap();
+            assert_eq!(str_to_color(input), color, "color of {:?}", input);
+            count += 1;
+        }
+        assert!(count > 20);
+    }
 }
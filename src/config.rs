@@ -1,23 +1,72 @@
 //! # Key-value configuration management
 
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use num_traits::FromPrimitive;
 use strum::{EnumProperty, IntoEnumIterator};
 use strum_macros::{AsRefStr, Display, EnumIter, EnumProperty, EnumString};
 
 use crate::blob::BlobObject;
-use crate::chat::ChatId;
-use crate::constants::DC_VERSION_STR;
+use crate::constants::{KeyGenType, MediaQuality, NotificationPrivacy, ShowEmails, DC_VERSION_STR};
+use crate::contact::may_be_valid_addr;
 use crate::context::Context;
 use crate::dc_tools::{dc_get_abs_path, improve_single_line_input};
-use crate::events::EventType;
+use crate::ephemeral;
+use crate::folder_roles::{self, FolderRole};
+use crate::imap::cleanup;
 use crate::job;
-use crate::message::MsgId;
+use crate::login_param::CertificateChecks;
 use crate::mimefactory::RECOMMENDED_FILE_SIZE;
-use crate::provider::{get_provider_by_id, Provider};
+use crate::provider::{get_provider_by_id, Provider, Socket};
 use crate::stock_str;
+use crate::sync::{self, Sync};
+
+/// Maximum size of a single UI config value in bytes, see [Context::set_ui_config].
+pub const UI_CONFIG_MAX_VALUE_SIZE: usize = 64 * 1024;
+
+/// Maximum size of all UI config keys and values together in bytes.
+pub const UI_CONFIG_MAX_TOTAL_SIZE: usize = 1024 * 1024;
+
+/// Raw config keys whose values are secrets, e.g. passwords and OAuth2 tokens.
+///
+/// The values must not show up in logs or diagnostics,
+/// they are replaced by [REDACTED_CONFIG_VALUE] instead.
+pub const SECRET_CONFIG_KEYS: [&str; 8] = [
+    "mail_pw",
+    "send_pw",
+    "configured_mail_pw",
+    "configured_send_pw",
+    "oauth2_access_token",
+    "oauth2_refresh_token",
+    // The authorization code the refresh token was requested with.
+    "oauth2_refresh_token_for",
+    "socks5_password",
+];
+
+/// Replacement for the values of [SECRET_CONFIG_KEYS].
+pub const REDACTED_CONFIG_VALUE: &str = "***";
+
+/// Returns true if the value of the raw config key `key` is a secret.
+pub fn is_secret_config_key(key: &str) -> bool {
+    SECRET_CONFIG_KEYS.contains(&key)
+}
+
+/// Result of [Context::import_config_json].
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ConfigImport {
+    /// Keys set by the import.
+    pub imported: Vec<Config>,
+
+    /// Keys not set because they are unknown, secret or cannot be imported.
+    ///
+    /// Keys not set because they already have a value are not listed.
+    pub skipped: Vec<String>,
+}
 
 /// The available configuration keys.
 #[derive(
-    Debug, Clone, Copy, PartialEq, Eq, Display, EnumString, AsRefStr, EnumIter, EnumProperty,
+    Debug, Clone, Copy, PartialEq, Eq, Hash, Display, EnumString, AsRefStr, EnumIter, EnumProperty,
 )]
 #[strum(serialize_all = "snake_case")]
 pub enum Config {
@@ -70,6 +119,15 @@ pub enum Config {
     #[strum(props(default = "0"))] // also change MediaQuality.default() on changes
     MediaQuality,
 
+    /// How much of a message is shown in notifications, see [crate::notification].
+    #[strum(props(default = "0"))] // also change NotificationPrivacy.default() on changes
+    NotificationPrivacy,
+
+    /// If set to "1", fresh messages in chats archived with
+    /// [crate::chat::ChatVisibility::NoUnarchive] are counted for the app badge.
+    #[strum(props(default = "0"))]
+    BadgeNoUnarchive,
+
     /// If set to "1", on the first time `start_io()` is called after configuring,
     /// the newest existing messages are fetched.
     /// Existing recipients are added to the contact database regardless of this setting.
@@ -94,10 +152,81 @@ pub enum Config {
     /// device.
     ///
     /// Equals to 0 by default, which means the message is never
-    /// deleted.
+    /// deleted. Unseen messages and "Saved messages" are kept,
+    /// see [crate::ephemeral::delete_device_expired_messages].
     #[strum(props(default = "0"))]
     DeleteDeviceAfter,
 
+    /// If set to "1", deleting a chat locally also deletes its messages from the server.
+    ///
+    /// Locally deleted messages are then deleted from the server in batches per folder;
+    /// their tombstones are kept until the deletion succeeded.
+    #[strum(props(default = "0"))]
+    DeleteOnServerWhenDeletingLocally,
+
+    /// Seconds the content of locally deleted messages is kept in the trash,
+    /// see [crate::chat::get_trashed_msgs] and [crate::message::restore_from_trash].
+    ///
+    /// The messages are also deleted from the server only after this period.
+    /// Equals to 0 by default, which means the content is removed immediately.
+    #[strum(props(default = "0"))]
+    TrashGracePeriod,
+
+    /// Size limit in bytes of messages downloaded automatically.
+    ///
+    /// Only the header of larger messages is downloaded,
+    /// the full message is downloaded on demand, see [crate::download].
+    /// Equals to 0 by default, which means all messages are downloaded completely.
+    #[strum(props(default = "0"))]
+    DownloadLimit,
+
+    /// Maximum size in bytes of outgoing messages accepted by the provider.
+    ///
+    /// Larger attachments are sent in several parts, see [crate::chunks].
+    /// Equals to 0 by default, which means only attachments exceeding the
+    /// upper limit of the core are split.
+    #[strum(props(default = "0"))]
+    MaxMessageSize,
+
+    /// If set to "1", IMAP, SMTP and the autoconfig lookups are routed through
+    /// the SOCKS5 proxy given by the other `socks5_*` keys, see [crate::socks].
+    #[strum(props(default = "0"))]
+    Socks5Enabled,
+
+    /// Host name or IP address of the SOCKS5 proxy.
+    Socks5Host,
+
+    /// Port of the SOCKS5 proxy.
+    #[strum(props(default = "1080"))]
+    Socks5Port,
+
+    /// User name for the SOCKS5 proxy, empty if the proxy needs no authentication.
+    Socks5User,
+
+    /// Password for the SOCKS5 proxy.
+    Socks5Password,
+
+    /// Seconds allowed for establishing an IMAP or SMTP connection,
+    /// see [crate::network::NetworkPolicy].
+    ///
+    /// If not set, the default or the Tor preset is used.
+    ConnectTimeoutSecs,
+
+    /// Seconds allowed for a single IMAP or SMTP command.
+    IoTimeoutSecs,
+
+    /// Number of quick reconnection attempts after a connection failed.
+    MaxRetries,
+
+    /// Seconds to wait before the first reconnection attempt, doubled for each further attempt.
+    RetryBaseDelay,
+
+    /// Maximum number of chats and of contacts each kept in memory.
+    ///
+    /// Setting it to 0 disables the caches.
+    #[strum(props(default = "2000"))]
+    CacheSize,
+
     SaveMimeHeaders,
     ConfiguredAddr,
     ConfiguredMailServer,
@@ -156,15 +285,51 @@ pub async fn config_exists(&self, key: Config) -> bool {
 
     /// Get a configuration key. Returns `None` if no value is set, and no default value found.
     pub async fn get_config(&self, key: Config) -> Option<String> {
+        let raw = match key {
+            Config::SysVersion | Config::SysMsgsizeMaxRecommended | Config::SysConfigKeys => None,
+            _ => self.sql.get_raw_config(self, key).await,
+        };
+        self.finish_config_value(key, raw).await
+    }
+
+    /// Gets several configuration keys, reading the stored values with a single query.
+    ///
+    /// The values are the same as returned by [`Context::get_config`],
+    /// i.e. keys that are not set and have no default value map to `None`.
+    pub async fn get_config_batch(
+        &self,
+        keys: &[Config],
+    ) -> crate::sql::Result<HashMap<Config, Option<String>>> {
+        let names: Vec<String> = keys.iter().map(|key| key.to_string()).collect();
+        let mut raw = self.sql.get_raw_config_batch(&names).await?;
+
+        let mut values = HashMap::with_capacity(keys.len());
+        for key in keys {
+            let value = self
+                .finish_config_value(*key, raw.remove(key.as_ref()))
+                .await;
+            values.insert(*key, value);
+        }
+        Ok(values)
+    }
+
+    /// Turns a raw stored config value into what [`Context::get_config`] returns.
+    async fn finish_config_value(&self, key: Config, raw: Option<String>) -> Option<String> {
         let value = match key {
             Config::Selfavatar => {
-                let rel_path = self.sql.get_raw_config(self, key).await;
-                rel_path.map(|p| dc_get_abs_path(self, &p).to_string_lossy().into_owned())
+                raw.map(|p| dc_get_abs_path(self, &p).to_string_lossy().into_owned())
             }
             Config::SysVersion => Some((&*DC_VERSION_STR).clone()),
             Config::SysMsgsizeMaxRecommended => Some(format!("{}", RECOMMENDED_FILE_SIZE)),
             Config::SysConfigKeys => Some(get_config_keys_string()),
-            _ => self.sql.get_raw_config(self, key).await,
+            _ => match FolderRole::from_config(key) {
+                // A manually unassigned role must not fall back to the default folder.
+                Some(role) => match folder_roles::get_override(self, role).await {
+                    Some(folder) => return folder,
+                    None => raw,
+                },
+                None => raw,
+            },
         };
 
         if value.is_some() {
@@ -237,61 +402,480 @@ pub async fn get_config_delete_device_after(&self) -> Option<i64> {
 
     /// Set the given config key.
     /// If `None` is passed as a value the value is cleared and set to the default if there is one.
+    ///
+    /// The value is checked with [`Config::validate`] first; invalid values are rejected
+    /// with [`crate::sql::Error::InvalidConfig`] and nothing is written.
+    ///
+    /// Settings describing the user, e.g. the display name, are synchronized to other devices
+    /// if sending sync messages is enabled, see [`crate::sync`].
     pub async fn set_config(&self, key: Config, value: Option<&str>) -> crate::sql::Result<()> {
-        match key {
-            Config::Selfavatar => {
-                self.sql
-                    .execute("UPDATE contacts SET selfavatar_sent=0;", paramsv![])
-                    .await?;
-                self.sql
-                    .set_raw_config_bool(self, "attach_selfavatar", true)
-                    .await?;
-                match value {
-                    Some(value) => {
-                        let blob = BlobObject::new_from_path(self, value).await?;
-                        blob.recode_to_avatar_size(self).await?;
-                        self.sql
-                            .set_raw_config(self, key, Some(blob.as_name()))
-                            .await
-                    }
-                    None => self.sql.set_raw_config(self, key, None).await,
+        self.set_config_ex(Sync::Sync, key, value).await
+    }
+
+    /// Sets the given config key, synchronizing it to other devices only if requested.
+    pub(crate) async fn set_config_ex(
+        &self,
+        sync: Sync,
+        key: Config,
+        value: Option<&str>,
+    ) -> crate::sql::Result<()> {
+        self.set_config_batch_ex(sync, &[(key, value)]).await
+    }
+
+    /// Sets several config keys at once.
+    ///
+    /// All values are validated first and then written in a single transaction,
+    /// so either all keys are changed or none of them.
+    pub async fn set_config_batch(
+        &self,
+        entries: &[(Config, Option<&str>)],
+    ) -> crate::sql::Result<()> {
+        self.set_config_batch_ex(Sync::Sync, entries).await
+    }
+
+    pub(crate) async fn set_config_batch_ex(
+        &self,
+        sync: Sync,
+        entries: &[(Config, Option<&str>)],
+    ) -> crate::sql::Result<()> {
+        for (key, value) in entries {
+            key.validate(*value)?;
+        }
+
+        let mut raw_entries = Vec::with_capacity(entries.len() + 1);
+        for (key, value) in entries {
+            let value = self.prepare_config_value(*key, *value).await?;
+            raw_entries.push((key.to_string(), value));
+        }
+
+        let changes_selfavatar = entries.iter().any(|(key, _)| *key == Config::Selfavatar);
+        if changes_selfavatar {
+            raw_entries.push(("attach_selfavatar".to_string(), Some("1".to_string())));
+        }
+
+        let old_delete_server_after = self.get_config_delete_server_after().await;
+        self.sql
+            .set_raw_config_batch_with(self, raw_entries, move |tx| {
+                if changes_selfavatar {
+                    tx.execute(
+                        "UPDATE contacts SET selfavatar_sent=0;",
+                        rusqlite::params![],
+                    )?;
+                }
+                Ok(())
+            })
+            .await?;
+
+        let changed = |k: Config| entries.iter().any(|(key, _)| *key == k);
+        if changed(Config::DeleteDeviceAfter) && self.scheduler.read().await.is_running() {
+            // Restart the task to delete old messages immediately.
+            ephemeral::schedule_device_deletion_task(self).await;
+        }
+        if changed(Config::DeleteServerAfter) {
+            job::schedule_resync(self).await;
+
+            // Messages already on the server are deleted according to the lowered value.
+            let lowered = match (
+                self.get_config_delete_server_after().await,
+                old_delete_server_after,
+            ) {
+                (Some(new), Some(old)) => new < old,
+                (Some(_), None) => true,
+                (None, _) => false,
+            };
+            if lowered {
+                if let Err(err) = cleanup::schedule(self).await {
+                    warn!(self, "Cannot schedule server cleanup: {:#}", err);
                 }
             }
+        }
+        if changed(Config::Socks5Enabled)
+            || changed(Config::Socks5Host)
+            || changed(Config::Socks5Port)
+            || changed(Config::Socks5User)
+            || changed(Config::Socks5Password)
+            || changed(Config::ConnectTimeoutSecs)
+            || changed(Config::IoTimeoutSecs)
+            || changed(Config::MaxRetries)
+            || changed(Config::RetryBaseDelay)
+        {
+            // Connections are re-established with the new settings on the next attempt.
+            self.maybe_network().await;
+        }
+        if changed(Config::CacheSize) {
+            self.sql.update_cache_size(self).await;
+        }
+        if changed(Config::MvboxWatch) || changed(Config::SentboxWatch) {
+            // The watched folders are selected when the scheduler starts.
+            self.restart_io_if_running().await;
+        }
+        if sync == Sync::Sync {
+            let keys: Vec<Config> = entries.iter().map(|(key, _)| *key).collect();
+            if let Err(err) = sync::config_changed(self, &keys).await {
+                warn!(self, "Cannot synchronize config to other devices: {}", err);
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the value to store for a config key.
+    async fn prepare_config_value(
+        &self,
+        key: Config,
+        value: Option<&str>,
+    ) -> crate::sql::Result<Option<String>> {
+        let value = match key {
+            Config::Selfavatar => match value {
+                Some(value) => {
+                    let blob = BlobObject::new_from_path(self, value).await?;
+                    blob.recode_to_avatar_size(self).await?;
+                    Some(blob.as_name().to_string())
+                }
+                None => None,
+            },
             Config::Selfstatus => {
                 let def = stock_str::status_line(self).await;
-                let val = if value.is_none() || value.unwrap() == def {
+                value.filter(|value| *value != def).map(|v| v.to_string())
+            }
+            Config::Displayname => value.map(improve_single_line_input),
+            _ => value.map(|v| v.to_string()),
+        };
+        Ok(value)
+    }
+
+    pub async fn set_config_bool(&self, key: Config, value: bool) -> crate::sql::Result<()> {
+        self.set_config(key, if value { Some("1") } else { None })
+            .await
+    }
+
+    /// Sets a UI config value, `None` removes the key.
+    ///
+    /// UI config is a key-value store for state of the UI, e.g. the last selected chat.
+    /// It is stored in its own table, separated from the [Config] keys,
+    /// and is included in backups.
+    /// Values are limited to [UI_CONFIG_MAX_VALUE_SIZE] bytes,
+    /// all keys and values together to [UI_CONFIG_MAX_TOTAL_SIZE] bytes.
+    pub async fn set_ui_config(&self, key: &str, value: Option<&str>) -> crate::sql::Result<()> {
+        let invalid = |reason: String| crate::sql::Error::InvalidConfig {
+            key: key.to_string(),
+            reason,
+        };
+        if key.is_empty() {
+            return Err(invalid("empty key".to_string()));
+        }
+        let value = match value {
+            Some(value) => value,
+            None => {
+                self.sql
+                    .execute("DELETE FROM ui_config WHERE keyname=?;", paramsv![key])
+                    .await?;
+                return Ok(());
+            }
+        };
+        if value.len() > UI_CONFIG_MAX_VALUE_SIZE {
+            return Err(invalid(format!(
+                "value of {} bytes exceeds the limit of {} bytes",
+                value.len(),
+                UI_CONFIG_MAX_VALUE_SIZE
+            )));
+        }
+
+        self.sql
+            .transaction(|tx| {
+                let others: i64 = tx.query_row(
+                    "SELECT IFNULL(SUM(LENGTH(CAST(keyname AS BLOB)) + LENGTH(CAST(value AS BLOB))), 0)
+                     FROM ui_config WHERE keyname!=?;",
+                    rusqlite::params![key],
+                    |row| row.get(0),
+                )?;
+                let total = others as usize + key.len() + value.len();
+                if total > UI_CONFIG_MAX_TOTAL_SIZE {
+                    return Err(invalid(format!(
+                        "UI config would take {} bytes, exceeding the limit of {} bytes",
+                        total, UI_CONFIG_MAX_TOTAL_SIZE
+                    )));
+                }
+                tx.execute(
+                    "INSERT OR REPLACE INTO ui_config (keyname, value) VALUES (?, ?);",
+                    rusqlite::params![key, value],
+                )?;
+                Ok(())
+            })
+            .await
+    }
+
+    /// Returns a UI config value set by [Context::set_ui_config].
+    pub async fn get_ui_config(&self, key: &str) -> crate::sql::Result<Option<String>> {
+        self.sql
+            .query_get_value_result(
+                "SELECT value FROM ui_config WHERE keyname=?;",
+                paramsv![key],
+            )
+            .await
+    }
+
+    /// Stores a large value for a raw config key, e.g. theme data of the UI.
+    ///
+    /// The value is kept in a file in the blob directory, so it is not limited by
+    /// [Context::set_config_value_limit].  `None` removes the key.  Keys of [Config] have
+    /// their own semantics and cannot be set this way.
+    pub async fn set_config_blob(&self, key: &str, data: Option<&[u8]>) -> crate::sql::Result<()> {
+        if Config::from_str(key).is_ok() {
+            return Err(crate::sql::Error::InvalidConfig {
+                key: key.to_string(),
+                reason: "use set_config() for this key".to_string(),
+            });
+        }
+        match data {
+            Some(data) => self.sql.set_raw_config_blob(self, key, data).await,
+            None => self.sql.set_raw_config(self, key, None).await,
+        }
+    }
+
+    /// Returns a value stored with [Context::set_config_blob].
+    pub async fn get_config_blob(&self, key: &str) -> crate::sql::Result<Option<Vec<u8>>> {
+        self.sql.get_raw_config_blob(key).await
+    }
+
+    /// Sets the maximum size of config values in bytes,
+    /// [crate::sql::DEFAULT_CONFIG_VALUE_LIMIT] by default.
+    ///
+    /// Larger values must be stored with [Context::set_config_blob].
+    /// The limit is stored in the database.
+    pub async fn set_config_value_limit(&self, limit: usize) -> crate::sql::Result<()> {
+        self.sql.set_config_value_limit(self, limit).await
+    }
+
+    /// Returns the maximum size of config values in bytes,
+    /// see [Context::set_config_value_limit].
+    pub fn get_config_value_limit(&self) -> usize {
+        self.sql.config_value_limit()
+    }
+
+    /// Returns all stored config keys and their values as a JSON object,
+    /// e.g. to attach it to a support request.
+    ///
+    /// If `redact` is set, the values of [SECRET_CONFIG_KEYS] are replaced by
+    /// [REDACTED_CONFIG_VALUE].
+    pub async fn export_config_json(&self, redact: bool) -> crate::sql::Result<String> {
+        let entries = self
+            .sql
+            .query_map(
+                "SELECT keyname, value FROM config ORDER BY keyname;",
+                paramsv![],
+                |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)),
+                |rows| {
+                    rows.collect::<std::result::Result<Vec<_>, _>>()
+                        .map_err(Into::into)
+                },
+            )
+            .await?;
+
+        let mut config = serde_json::Map::new();
+        for (key, value) in entries {
+            let value = if redact && is_secret_config_key(&key) {
+                REDACTED_CONFIG_VALUE.to_string()
+            } else {
+                self.sql.resolve_config_value(value).await?
+            };
+            config.insert(key, serde_json::Value::String(value));
+        }
+        Ok(serde_json::to_string_pretty(&config).map_err(anyhow::Error::from)?)
+    }
+
+    /// Sets the config keys from a JSON object as written by [Context::export_config_json].
+    ///
+    /// Keys that already have a value are only changed if `overwrite` is set,
+    /// values of [SECRET_CONFIG_KEYS] only if `allow_secrets` is set.
+    /// Unknown keys and keys that cannot be set, e.g. the avatar, are skipped.
+    /// All values are validated first, if one of them is invalid, nothing is changed.
+    pub async fn import_config_json(
+        &self,
+        json: &str,
+        overwrite: bool,
+        allow_secrets: bool,
+    ) -> crate::sql::Result<ConfigImport> {
+        let config: serde_json::Map<String, serde_json::Value> =
+            serde_json::from_str(json).map_err(anyhow::Error::from)?;
+
+        let mut result = ConfigImport::default();
+        let mut entries = Vec::new();
+        for (name, value) in &config {
+            let key = match name.parse::<Config>() {
+                Ok(key) if key.is_importable() => key,
+                _ => {
+                    warn!(self, "Not importing unknown config key {:?}", name);
+                    result.skipped.push(name.clone());
+                    continue;
+                }
+            };
+            let value = match value {
+                serde_json::Value::String(value) => Some(value.as_str()),
+                serde_json::Value::Null => None,
+                _ => {
+                    warn!(
+                        self,
+                        "Not importing config key {}: value is no string", name
+                    );
+                    result.skipped.push(name.clone());
+                    continue;
+                }
+            };
+            if is_secret_config_key(name)
+                && (!allow_secrets || value == Some(REDACTED_CONFIG_VALUE))
+            {
+                warn!(self, "Not importing secret config key {}", name);
+                result.skipped.push(name.clone());
+                continue;
+            }
+            if !overwrite && self.config_exists(key).await {
+                continue;
+            }
+            entries.push((key, value));
+            result.imported.push(key);
+        }
+
+        if !entries.is_empty() {
+            self.set_config_batch_ex(Sync::Nosync, &entries).await?;
+        }
+        Ok(result)
+    }
+}
+
+impl Config {
+    /// Checks whether `value` is acceptable for this key.
+    ///
+    /// Unsetting a key is always allowed. Keys without specific requirements accept any value.
+    pub fn validate(self, value: Option<&str>) -> crate::sql::Result<()> {
+        let value = match value {
+            Some(value) => value,
+            None => return Ok(()),
+        };
+        let reason = match self {
+            Config::Addr | Config::ConfiguredAddr => {
+                if value.is_empty() || may_be_valid_addr(value) {
                     None
                 } else {
-                    value
-                };
-
-                self.sql.set_raw_config(self, key, val).await
+                    Some("not a valid email address".to_string())
+                }
             }
-            Config::DeleteDeviceAfter => {
-                let ret = self.sql.set_raw_config(self, key, value).await;
-                // Force chatlist reload to delete old messages immediately.
-                self.emit_event(EventType::MsgsChanged {
-                    msg_id: MsgId::new(0),
-                    chat_id: ChatId::new(0),
-                });
-                ret
+            Config::MailPort
+            | Config::SendPort
+            | Config::ConfiguredMailPort
+            | Config::ConfiguredSendPort => {
+                if value.is_empty() || value.parse::<u16>().is_ok() {
+                    None
+                } else {
+                    Some("port must be a number between 0 and 65535".to_string())
+                }
+            }
+            Config::MailSecurity
+            | Config::SendSecurity
+            | Config::ConfiguredMailSecurity
+            | Config::ConfiguredSendSecurity => {
+                check_enum_value(value, |v| Socket::from_i32(v).is_some())
             }
-            Config::Displayname => {
-                let value = value.map(improve_single_line_input);
-                self.sql.set_raw_config(self, key, value.as_deref()).await
+            Config::ImapCertificateChecks
+            | Config::SmtpCertificateChecks
+            | Config::ConfiguredImapCertificateChecks
+            | Config::ConfiguredSmtpCertificateChecks => {
+                check_enum_value(value, |v| CertificateChecks::from_i32(v).is_some())
             }
-            Config::DeleteServerAfter => {
-                let ret = self.sql.set_raw_config(self, key, value).await;
-                job::schedule_resync(self).await;
-                ret
+            Config::ShowEmails => check_enum_value(value, |v| ShowEmails::from_i32(v).is_some()),
+            Config::MediaQuality => {
+                check_enum_value(value, |v| MediaQuality::from_i32(v).is_some())
             }
-            _ => self.sql.set_raw_config(self, key, value).await,
+            Config::NotificationPrivacy => {
+                check_enum_value(value, |v| NotificationPrivacy::from_i32(v).is_some())
+            }
+            Config::KeyGenType => check_enum_value(value, |v| KeyGenType::from_i32(v).is_some()),
+            Config::Socks5Enabled => check_enum_value(value, |v| v == 0 || v == 1),
+            Config::Socks5Port => match value.parse::<u16>() {
+                Ok(port) if port > 0 => None,
+                _ => Some("port must be a number between 1 and 65535".to_string()),
+            },
+            Config::CacheSize
+            | Config::ConnectTimeoutSecs
+            | Config::IoTimeoutSecs
+            | Config::MaxRetries
+            | Config::RetryBaseDelay => match value.parse::<u32>() {
+                Ok(_) => None,
+                _ => Some("must be a non-negative number".to_string()),
+            },
+            Config::DeleteServerAfter | Config::DeleteDeviceAfter | Config::TrashGracePeriod => {
+                match value.parse::<i64>() {
+                    Ok(timer) if timer >= 0 => None,
+                    _ => Some("must be a non-negative number of seconds".to_string()),
+                }
+            }
+            _ => None,
+        };
+
+        match reason {
+            Some(reason) => Err(crate::sql::Error::InvalidConfig {
+                key: self.to_string(),
+                reason,
+            }),
+            None => Ok(()),
         }
     }
 
-    pub async fn set_config_bool(&self, key: Config, value: bool) -> crate::sql::Result<()> {
-        self.set_config(key, if value { Some("1") } else { None })
-            .await
+    /// Returns false for keys that are computed or refer to local files,
+    /// so that their values cannot be transferred to another account.
+    fn is_importable(self) -> bool {
+        !matches!(
+            self,
+            Config::SysVersion
+                | Config::SysMsgsizeMaxRecommended
+                | Config::SysConfigKeys
+                | Config::Selfavatar
+        )
+    }
+
+    /// Checks the stored configuration for consistency.
+    ///
+    /// Every stored value is validated again and settings that only make sense together
+    /// are checked, e.g. a SOCKS5 password without a SOCKS5 user.
+    /// Ports and security settings without a server are fine,
+    /// configure guesses the hostname from the address then.
+    /// This is run before configuring, so that obviously broken settings
+    /// are reported instead of failing somewhere during the login attempts.
+    pub async fn self_validate(context: &Context) -> crate::sql::Result<()> {
+        for key in Config::iter() {
+            let value = context.sql.get_raw_config(context, key).await;
+            key.validate(value.as_deref())?;
+        }
+
+        let dependencies = [
+            (Config::Socks5Host, Config::Socks5Enabled),
+            (Config::Socks5User, Config::Socks5Password),
+        ];
+        for (required, key) in dependencies.iter() {
+            if is_set(context, *key).await && !is_set(context, *required).await {
+                return Err(crate::sql::Error::InvalidConfig {
+                    key: key.to_string(),
+                    reason: format!("requires {} to be set", required),
+                });
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Returns true if the key is set to a value that differs from "unset" defaults.
+async fn is_set(context: &Context, key: Config) -> bool {
+    match context.sql.get_raw_config(context, key).await {
+        Some(value) => !value.is_empty() && value != "0",
+        None => false,
+    }
+}
+
+/// Returns an error reason if `value` is no number accepted by `is_valid`.
+fn check_enum_value(value: &str, is_valid: impl Fn(i32) -> bool) -> Option<String> {
+    match value.parse::<i32>() {
+        Ok(v) if is_valid(v) => None,
+        _ => Some(format!("unsupported value {:?}", value)),
     }
 }
 
@@ -425,4 +1009,421 @@ async fn test_media_quality_config_option() {
         let media_quality = constants::MediaQuality::from_i32(media_quality).unwrap_or_default();
         assert_eq!(media_quality, constants::MediaQuality::Worse);
     }
+
+    fn is_invalid_config(res: crate::sql::Result<()>, expected_key: Config) -> bool {
+        match res {
+            Err(crate::sql::Error::InvalidConfig { key, .. }) => key == expected_key.to_string(),
+            _ => false,
+        }
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(Config::Addr.validate(Some("alice@example.org")).is_ok());
+        assert!(Config::Addr.validate(Some("")).is_ok());
+        assert!(Config::Addr.validate(None).is_ok());
+        assert!(is_invalid_config(
+            Config::Addr.validate(Some("alice")),
+            Config::Addr
+        ));
+
+        assert!(Config::MailPort.validate(Some("993")).is_ok());
+        assert!(Config::SendPort.validate(Some("65535")).is_ok());
+        assert!(is_invalid_config(
+            Config::MailPort.validate(Some("65536")),
+            Config::MailPort
+        ));
+        assert!(is_invalid_config(
+            Config::SendPort.validate(Some("-1")),
+            Config::SendPort
+        ));
+        assert!(is_invalid_config(
+            Config::SendPort.validate(Some("smtp")),
+            Config::SendPort
+        ));
+
+        assert!(Config::MailSecurity.validate(Some("3")).is_ok());
+        assert!(Config::SendSecurity.validate(Some("4")).is_err());
+        assert!(Config::ImapCertificateChecks.validate(Some("3")).is_ok());
+        assert!(Config::SmtpCertificateChecks.validate(Some("5")).is_err());
+
+        assert!(Config::ShowEmails.validate(Some("2")).is_ok());
+        assert!(is_invalid_config(
+            Config::ShowEmails.validate(Some("3")),
+            Config::ShowEmails
+        ));
+        assert!(Config::MediaQuality.validate(Some("1")).is_ok());
+        assert!(is_invalid_config(
+            Config::MediaQuality.validate(Some("2")),
+            Config::MediaQuality
+        ));
+        assert!(Config::KeyGenType.validate(Some("2")).is_ok());
+        assert!(Config::KeyGenType.validate(Some("rsa")).is_err());
+
+        assert!(Config::DeleteDeviceAfter.validate(Some("3600")).is_ok());
+        assert!(Config::DeleteServerAfter.validate(Some("-5")).is_err());
+
+        assert!(Config::Socks5Enabled.validate(Some("1")).is_ok());
+        assert!(Config::Socks5Enabled.validate(Some("2")).is_err());
+        assert!(Config::Socks5Port.validate(Some("9050")).is_ok());
+        assert!(Config::Socks5Port.validate(Some("0")).is_err());
+        assert!(Config::CacheSize.validate(Some("0")).is_ok());
+        assert!(Config::CacheSize.validate(Some("-1")).is_err());
+        assert!(Config::ConnectTimeoutSecs.validate(Some("30")).is_ok());
+        assert!(Config::MaxRetries.validate(Some("-1")).is_err());
+
+        // Keys without requirements accept anything.
+        assert!(Config::Displayname.validate(Some("any thing")).is_ok());
+    }
+
+    #[async_std::test]
+    async fn test_set_config_rejects_invalid() {
+        let t = TestContext::new().await;
+        t.set_config(Config::ShowEmails, Some("1")).await.unwrap();
+
+        let res = t.set_config(Config::ShowEmails, Some("7")).await;
+        assert!(is_invalid_config(res, Config::ShowEmails));
+        assert_eq!(
+            t.get_config(Config::ShowEmails).await,
+            Some("1".to_string())
+        );
+
+        let res = t.set_config(Config::MailPort, Some("100000")).await;
+        assert!(is_invalid_config(res, Config::MailPort));
+        assert_eq!(t.get_config(Config::MailPort).await, None);
+
+        // Raw access is not checked.
+        t.sql
+            .set_raw_config(&t, Config::MailPort, Some("100000"))
+            .await
+            .unwrap();
+        assert_eq!(
+            t.get_config(Config::MailPort).await,
+            Some("100000".to_string())
+        );
+    }
+
+    #[async_std::test]
+    async fn test_self_validate() {
+        let t = TestContext::new().await;
+        Config::self_validate(&t).await.unwrap();
+
+        // The hostname is guessed by configure if only the port or security is set.
+        t.set_config(Config::MailPort, Some("993")).await.unwrap();
+        t.set_config(Config::SendSecurity, Some("1")).await.unwrap();
+        Config::self_validate(&t).await.unwrap();
+
+        t.set_config(Config::Socks5Password, Some("secret"))
+            .await
+            .unwrap();
+        let res = Config::self_validate(&t).await;
+        assert!(is_invalid_config(res, Config::Socks5Password));
+        t.set_config(Config::Socks5User, Some("alice"))
+            .await
+            .unwrap();
+        Config::self_validate(&t).await.unwrap();
+
+        // Values written bypassing the checks are found as well.
+        t.sql
+            .set_raw_config(&t, Config::MediaQuality, Some("9"))
+            .await
+            .unwrap();
+        let res = Config::self_validate(&t).await;
+        assert!(is_invalid_config(res, Config::MediaQuality));
+    }
+
+    #[async_std::test]
+    async fn test_set_config_batch() {
+        let t = TestContext::new().await;
+        t.set_config_batch(&[
+            (Config::MailServer, Some("imap.example.org")),
+            (Config::MailPort, Some("993")),
+            (Config::Displayname, Some("Alice\nCooper")),
+        ])
+        .await
+        .unwrap();
+        assert_eq!(
+            t.get_config(Config::MailServer).await,
+            Some("imap.example.org".to_string())
+        );
+        assert_eq!(t.get_config_int(Config::MailPort).await, 993);
+        assert_eq!(
+            t.get_config(Config::Displayname).await,
+            Some("Alice Cooper".to_string())
+        );
+
+        // A single invalid value prevents all writes.
+        let res = t
+            .set_config_batch(&[
+                (Config::MailServer, Some("imap.example.net")),
+                (Config::MailPort, None),
+                (Config::SendPort, Some("70000")),
+            ])
+            .await;
+        assert!(is_invalid_config(res, Config::SendPort));
+        assert_eq!(
+            t.get_config(Config::MailServer).await,
+            Some("imap.example.org".to_string())
+        );
+        assert_eq!(t.get_config_int(Config::MailPort).await, 993);
+        assert_eq!(t.get_config(Config::SendPort).await, None);
+    }
+
+    #[async_std::test]
+    async fn test_set_config_batch_selfavatar() {
+        let t = TestContext::new_alice().await;
+        let bob = t.create_chat_with_contact("Bob", "bob@example.net").await;
+        t.sql
+            .execute("UPDATE contacts SET selfavatar_sent=1;", paramsv![])
+            .await
+            .unwrap();
+
+        t.set_config_batch(&[
+            (Config::Displayname, Some("Alice")),
+            (Config::Selfavatar, None),
+        ])
+        .await
+        .unwrap();
+        let contact_id = *crate::chat::get_chat_contacts(&t, bob.id)
+            .await
+            .first()
+            .unwrap();
+        let selfavatar_sent: i64 = t
+            .sql
+            .query_get_value_result(
+                "SELECT selfavatar_sent FROM contacts WHERE id=?;",
+                paramsv![contact_id],
+            )
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(selfavatar_sent, 0);
+        assert!(t.sql.get_raw_config_bool(&t, "attach_selfavatar").await);
+    }
+
+    #[async_std::test]
+    async fn test_get_config_batch() {
+        let t = TestContext::new().await;
+        t.set_config(Config::MailServer, Some("imap.example.org"))
+            .await
+            .unwrap();
+
+        let values = t
+            .get_config_batch(&[
+                Config::MailServer,
+                Config::SendServer,
+                Config::MdnsEnabled,
+                Config::SysVersion,
+            ])
+            .await
+            .unwrap();
+        assert_eq!(values.len(), 4);
+        assert_eq!(
+            values.get(&Config::MailServer),
+            Some(&Some("imap.example.org".to_string()))
+        );
+        assert_eq!(values.get(&Config::SendServer), Some(&None));
+        assert_eq!(
+            values.get(&Config::MdnsEnabled),
+            Some(&Some("1".to_string()))
+        );
+        assert_eq!(
+            values.get(&Config::SysVersion),
+            Some(&t.get_config(Config::SysVersion).await)
+        );
+    }
+
+    #[async_std::test]
+    async fn test_ui_config() {
+        let t = TestContext::new().await;
+        assert_eq!(t.get_ui_config("desktop.last_chat").await.unwrap(), None);
+
+        t.set_ui_config("desktop.last_chat", Some("12"))
+            .await
+            .unwrap();
+        assert_eq!(
+            t.get_ui_config("desktop.last_chat").await.unwrap(),
+            Some("12".to_string())
+        );
+        t.set_ui_config("desktop.last_chat", Some("13"))
+            .await
+            .unwrap();
+        assert_eq!(
+            t.get_ui_config("desktop.last_chat").await.unwrap(),
+            Some("13".to_string())
+        );
+
+        t.set_ui_config("desktop.last_chat", None).await.unwrap();
+        assert_eq!(t.get_ui_config("desktop.last_chat").await.unwrap(), None);
+        assert!(t.set_ui_config("", Some("1")).await.is_err());
+    }
+
+    #[async_std::test]
+    async fn test_ui_config_separated_from_config() {
+        let t = TestContext::new().await;
+        t.set_ui_config("displayname", Some("UI value"))
+            .await
+            .unwrap();
+        assert_eq!(t.get_config(Config::Displayname).await, None);
+
+        t.set_config(Config::Displayname, Some("Alice"))
+            .await
+            .unwrap();
+        assert_eq!(
+            t.get_ui_config("displayname").await.unwrap(),
+            Some("UI value".to_string())
+        );
+        assert_eq!(t.get_ui_config("mail_server").await.unwrap(), None);
+    }
+
+    #[async_std::test]
+    async fn test_ui_config_limits() {
+        let t = TestContext::new().await;
+        let too_big = "x".repeat(UI_CONFIG_MAX_VALUE_SIZE + 1);
+        assert!(matches!(
+            t.set_ui_config("big", Some(&too_big)).await,
+            Err(crate::sql::Error::InvalidConfig { .. })
+        ));
+        assert_eq!(t.get_ui_config("big").await.unwrap(), None);
+
+        // Fill up the total limit with values of the maximum size.
+        let value = "x".repeat(UI_CONFIG_MAX_VALUE_SIZE);
+        let mut i = 0;
+        loop {
+            let key = format!("key{}", i);
+            match t.set_ui_config(&key, Some(&value)).await {
+                Ok(()) => i += 1,
+                Err(crate::sql::Error::InvalidConfig { .. }) => break,
+                Err(err) => panic!("unexpected error: {}", err),
+            }
+        }
+        assert!(i > 0);
+        assert!(i * UI_CONFIG_MAX_VALUE_SIZE <= UI_CONFIG_MAX_TOTAL_SIZE);
+        assert_eq!(t.get_ui_config(&format!("key{}", i)).await.unwrap(), None);
+
+        // Replacing an existing value does not count the old value.
+        t.set_ui_config("key0", Some(&value)).await.unwrap();
+
+        // Removing a value makes room again.
+        t.set_ui_config("key0", None).await.unwrap();
+        t.set_ui_config(&format!("key{}", i), Some(&value))
+            .await
+            .unwrap();
+    }
+
+    #[async_std::test]
+    async fn test_config_blob() {
+        let t = TestContext::new().await;
+        let data = vec![b'x'; t.get_config_value_limit() + 1];
+        t.set_config_blob("theme", Some(&data)).await.unwrap();
+        assert_eq!(t.get_config_blob("theme").await.unwrap(), Some(data));
+        assert!(matches!(
+            t.set_config_blob("selfstatus", Some(b"status")).await,
+            Err(crate::sql::Error::InvalidConfig { .. })
+        ));
+        t.set_config_blob("theme", None).await.unwrap();
+        assert_eq!(t.get_config_blob("theme").await.unwrap(), None);
+
+        // Values larger than the limit can be set after raising it.
+        let status = "x".repeat(100);
+        t.set_config_value_limit(99).await.unwrap();
+        assert!(t
+            .set_config(Config::Selfstatus, Some(&status))
+            .await
+            .is_err());
+        t.set_config_value_limit(100).await.unwrap();
+        t.set_config(Config::Selfstatus, Some(&status))
+            .await
+            .unwrap();
+        assert_eq!(t.get_config(Config::Selfstatus).await, Some(status));
+    }
+
+    #[async_std::test]
+    async fn test_export_config_json_redacted() {
+        let t = TestContext::new_alice().await;
+        t.set_config(Config::MailPw, Some("imap-secret"))
+            .await
+            .unwrap();
+        t.set_config(Config::ConfiguredSendPw, Some("smtp-secret"))
+            .await
+            .unwrap();
+        t.sql
+            .set_raw_config(&t, "oauth2_refresh_token", Some("token-secret"))
+            .await
+            .unwrap();
+        t.sql
+            .set_raw_config(&t, "oauth2_refresh_token_for", Some("code-secret"))
+            .await
+            .unwrap();
+
+        let json = t.export_config_json(true).await.unwrap();
+        for secret in &["imap-secret", "smtp-secret", "token-secret", "code-secret"] {
+            assert!(!json.contains(secret));
+        }
+        let config: serde_json::Map<String, serde_json::Value> =
+            serde_json::from_str(&json).unwrap();
+        assert_eq!(config.get("mail_pw").unwrap(), REDACTED_CONFIG_VALUE);
+        assert_eq!(config.get("addr").unwrap(), "alice@example.com");
+
+        let json = t.export_config_json(false).await.unwrap();
+        assert!(json.contains("imap-secret"));
+    }
+
+    #[async_std::test]
+    async fn test_import_config_json() {
+        let alice = TestContext::new_alice().await;
+        alice
+            .set_config(Config::Displayname, Some("Alice"))
+            .await
+            .unwrap();
+        alice
+            .set_config(Config::MdnsEnabled, Some("0"))
+            .await
+            .unwrap();
+        alice
+            .set_config(Config::MailServer, Some("imap.example.com"))
+            .await
+            .unwrap();
+        alice
+            .set_config(Config::MailPw, Some("imap-secret"))
+            .await
+            .unwrap();
+        let json = alice.export_config_json(true).await.unwrap();
+
+        let t = TestContext::new().await;
+        t.set_config(Config::MailServer, Some("imap.example.net"))
+            .await
+            .unwrap();
+        let result = t.import_config_json(&json, false, true).await.unwrap();
+        assert!(result.imported.contains(&Config::Displayname));
+        assert!(result.skipped.contains(&"mail_pw".to_string()));
+        assert!(result.skipped.contains(&"dbversion".to_string()));
+        assert_eq!(t.get_config(Config::Displayname).await.unwrap(), "Alice");
+        assert!(!t.get_config_bool(Config::MdnsEnabled).await);
+        assert_eq!(t.get_config(Config::MailPw).await, None);
+        assert_eq!(
+            t.get_config(Config::MailServer).await.unwrap(),
+            "imap.example.net"
+        );
+
+        t.import_config_json(&json, true, false).await.unwrap();
+        assert_eq!(
+            t.get_config(Config::MailServer).await.unwrap(),
+            "imap.example.com"
+        );
+
+        // Unknown keys are reported, secrets need to be allowed explicitly.
+        let json = r#"{"no_such_key": "1", "mail_pw": "imap-secret", "bcc_self": "1"}"#;
+        let result = t.import_config_json(json, true, false).await.unwrap();
+        assert_eq!(result.imported, vec![Config::BccSelf]);
+        assert_eq!(result.skipped, vec!["mail_pw", "no_such_key"]);
+        assert_eq!(t.get_config(Config::MailPw).await, None);
+        t.import_config_json(json, true, true).await.unwrap();
+        assert_eq!(t.get_config(Config::MailPw).await.unwrap(), "imap-secret");
+
+        // Invalid values are rejected without changing anything.
+        let json = r#"{"mail_port": "no port", "bcc_self": "0"}"#;
+        assert!(t.import_config_json(json, true, false).await.is_err());
+        assert!(t.get_config_bool(Config::BccSelf).await);
+    }
 }
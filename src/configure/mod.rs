@@ -11,14 +11,17 @@
 use itertools::Itertools;
 use job::Action;
 use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use serde::{Deserialize, Serialize};
 
-use crate::dc_tools::EmailAddress;
-use crate::imap::Imap;
+use crate::dc_tools::{normalize_addr, EmailAddress};
+use crate::events::EventType;
+use crate::imap::{ConnectError, Imap};
 use crate::login_param::{LoginParam, ServerLoginParam};
 use crate::message::Message;
 use crate::oauth2::dc_get_oauth2_addr;
-use crate::provider::{Protocol, Socket, UsernamePattern};
-use crate::smtp::Smtp;
+use crate::ongoing::OperationKind;
+use crate::provider::{Protocol, ProviderInfo, Socket, UsernamePattern};
+use crate::smtp::{self, Smtp};
 use crate::stock_str;
 use crate::{chat, e2ee, provider};
 use crate::{config::Config, dc_tools::time};
@@ -48,6 +51,73 @@ macro_rules! progress {
     };
 }
 
+/// Raw config key of the diagnostics of the last failed configuration.
+const LAST_CONFIGURE_ERROR: &str = "last_configure_error";
+
+/// Stage of the configuration process started by [`Context::configure`].
+///
+/// Connecting and logging in is a single step, so only [ConfigureStage::ImapConnect]
+/// and [ConfigureStage::SmtpConnect] are announced by events;
+/// the auth stages appear in [ConfigureDiagnostics] only.
+#[derive(Debug, Display, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[repr(u32)]
+pub enum ConfigureStage {
+    /// Checking the parameters and looking up the servers to use:
+    /// the OAuth2 address, the provider database and autoconfig.
+    AutoconfigLookup = 1,
+
+    /// Connecting to the IMAP server.
+    ImapConnect = 2,
+
+    /// Logging in to the IMAP server, using a password or an OAuth2 token.
+    ImapAuth = 3,
+
+    /// Connecting to the SMTP server.
+    SmtpConnect = 4,
+
+    /// Logging in to the SMTP server, using a password or an OAuth2 token.
+    SmtpAuth = 5,
+
+    /// Configuring folders, saving the configuration and generating keys.
+    Finalize = 6,
+}
+
+/// Kind of error making a configuration stage fail.
+#[derive(Debug, Display, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ConfigureErrorCode {
+    /// The server could not be reached, e.g. because of a wrong hostname or port,
+    /// a missing network connection or a failed TLS handshake.
+    Connection,
+
+    /// The server rejected the login.
+    AuthFailed,
+
+    /// No OAuth2 access token could be obtained.
+    Oauth2,
+
+    /// Any other error, e.g. a missing password.
+    Other,
+}
+
+/// Diagnostics of a failed configuration.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConfigureDiagnostics {
+    /// The stage that failed.
+    pub stage: ConfigureStage,
+
+    /// Kind of the error.
+    pub code: ConfigureErrorCode,
+
+    /// Hostname of the server tried, if the stage failed while talking to a server.
+    pub host: Option<String>,
+}
+
+impl ConfigureDiagnostics {
+    fn new(stage: ConfigureStage, code: ConfigureErrorCode, host: Option<String>) -> Self {
+        Self { stage, code, host }
+    }
+}
+
 impl Context {
     /// Checks if the context is already configured.
     pub async fn is_configured(&self) -> bool {
@@ -56,8 +126,6 @@ pub async fn is_configured(&self) -> bool {
 
     /// Configures this account with the currently set parameters.
     pub async fn configure(&self) -> Result<()> {
-        use futures::future::FutureExt;
-
         ensure!(
             !self.scheduler.read().await.is_running(),
             "cannot configure, already running"
@@ -66,27 +134,39 @@ pub async fn configure(&self) -> Result<()> {
             self.sql.is_open().await,
             "cannot configure, database not opened."
         );
-        let cancel_channel = self.alloc_ongoing().await?;
+        Config::self_validate(self).await?;
+        let operation = self.start_operation(OperationKind::Configure, true)?;
 
-        let res = self
-            .inner_configure()
-            .race(cancel_channel.recv().map(|_| {
+        self.inner_configure()
+            .race(async {
+                operation.canceled().await;
                 progress!(self, 0);
                 Ok(())
-            }))
-            .await;
-
-        self.free_ongoing().await;
+            })
+            .await
+    }
 
-        res
+    /// Returns the diagnostics of the last configuration
+    /// or `None` if it succeeded or no configuration was tried yet.
+    pub async fn get_last_configure_diagnostics(&self) -> Option<ConfigureDiagnostics> {
+        let json = self.sql.get_raw_config(self, LAST_CONFIGURE_ERROR).await?;
+        match serde_json::from_str(&json) {
+            Ok(diagnostics) => Some(diagnostics),
+            Err(err) => {
+                warn!(self, "Cannot parse {}: {}", LAST_CONFIGURE_ERROR, err);
+                None
+            }
+        }
     }
 
     async fn inner_configure(&self) -> Result<()> {
         info!(self, "Configure ...");
 
         let mut param = LoginParam::from_database(self, "").await;
-        let success = configure(self, &mut param).await;
+        let mut stage = ConfigureStage::AutoconfigLookup;
+        let success = configure(self, &mut param, &mut stage).await;
         self.set_config(Config::NotifyAboutWrongPw, None).await?;
+        save_diagnostics(self, &success, stage).await?;
 
         if let Some(provider) = param.provider {
             if let Some(config_defaults) = &provider.config_defaults {
@@ -142,10 +222,44 @@ async fn inner_configure(&self) -> Result<()> {
     }
 }
 
-async fn configure(ctx: &Context, param: &mut LoginParam) -> Result<()> {
+/// Saves the diagnostics of a configuration that ended in `stage`.
+async fn save_diagnostics(
+    context: &Context,
+    result: &Result<()>,
+    stage: ConfigureStage,
+) -> Result<()> {
+    let json = match result {
+        Ok(_) => None,
+        Err(err) => {
+            let diagnostics = match err.downcast_ref::<ServerError>() {
+                Some(err) => err.diagnostics.clone(),
+                None => ConfigureDiagnostics::new(stage, ConfigureErrorCode::Other, None),
+            };
+            Some(serde_json::to_string(&diagnostics)?)
+        }
+    };
+    context
+        .sql
+        .set_raw_config(context, LAST_CONFIGURE_ERROR, json.as_deref())
+        .await?;
+    Ok(())
+}
+
+fn enter_stage(ctx: &Context, stage: &mut ConfigureStage, next: ConfigureStage) {
+    *stage = next;
+    ctx.emit_event(EventType::ConfigureStageChanged(next));
+}
+
+async fn configure(
+    ctx: &Context,
+    param: &mut LoginParam,
+    stage: &mut ConfigureStage,
+) -> Result<()> {
     progress!(ctx, 1);
+    enter_stage(ctx, stage, ConfigureStage::AutoconfigLookup);
 
     // Check basic settings.
+    param.addr = normalize_addr(&param.addr);
     ensure!(!param.addr.is_empty(), "Please enter an email address.");
 
     // Only check for IMAP password, SMTP password is an "advanced" setting.
@@ -214,48 +328,64 @@ async fn configure(ctx: &Context, param: &mut LoginParam) -> Result<()> {
             "checking internal provider-info for offline autoconfig"
         );
 
-        if let Some(provider) = provider::get_provider_info(&param_domain).await {
-            param.provider = Some(provider);
-            match provider.status {
-                provider::Status::OK | provider::Status::PREPARATION => {
-                    if provider.server.is_empty() {
-                        info!(ctx, "offline autoconfig found, but no servers defined");
+        match get_provider(ctx, &param_domain).await {
+            Some(ProviderInfo::Override(provider_override)) => {
+                if provider_override.servers.is_empty() {
+                    info!(ctx, "provider override found, but no servers defined");
+                    param_autoconfig =
+                        get_autoconfig(ctx, param, &param_domain, &param_addr_urlencoded).await;
+                } else {
+                    info!(ctx, "provider override found");
+                    let servers = provider_override
+                        .servers
+                        .iter()
+                        .map(|s| ServerParams {
+                            protocol: s.protocol,
+                            socket: s.socket,
+                            hostname: s.hostname.clone(),
+                            port: s.port,
+                            username: username_by_pattern(&s.username_pattern, &param.addr),
+                        })
+                        .collect();
+
+                    param_autoconfig = Some(servers)
+                }
+            }
+            Some(ProviderInfo::Builtin(provider)) => {
+                param.provider = Some(provider);
+                match provider.status {
+                    provider::Status::OK | provider::Status::PREPARATION => {
+                        if provider.server.is_empty() {
+                            info!(ctx, "offline autoconfig found, but no servers defined");
+                            param_autoconfig = None;
+                        } else {
+                            info!(ctx, "offline autoconfig found");
+                            let servers = provider
+                                .server
+                                .iter()
+                                .map(|s| ServerParams {
+                                    protocol: s.protocol,
+                                    socket: s.socket,
+                                    hostname: s.hostname.to_string(),
+                                    port: s.port,
+                                    username: username_by_pattern(&s.username_pattern, &param.addr),
+                                })
+                                .collect();
+
+                            param_autoconfig = Some(servers)
+                        }
+                    }
+                    provider::Status::BROKEN => {
+                        info!(ctx, "offline autoconfig found, provider is broken");
                         param_autoconfig = None;
-                    } else {
-                        info!(ctx, "offline autoconfig found");
-                        let servers = provider
-                            .server
-                            .iter()
-                            .map(|s| ServerParams {
-                                protocol: s.protocol,
-                                socket: s.socket,
-                                hostname: s.hostname.to_string(),
-                                port: s.port,
-                                username: match s.username_pattern {
-                                    UsernamePattern::EMAIL => param.addr.to_string(),
-                                    UsernamePattern::EMAILLOCALPART => {
-                                        if let Some(at) = param.addr.find('@') {
-                                            param.addr.split_at(at).0.to_string()
-                                        } else {
-                                            param.addr.to_string()
-                                        }
-                                    }
-                                },
-                            })
-                            .collect();
-
-                        param_autoconfig = Some(servers)
                     }
                 }
-                provider::Status::BROKEN => {
-                    info!(ctx, "offline autoconfig found, provider is broken");
-                    param_autoconfig = None;
-                }
             }
-        } else {
-            info!(ctx, "no offline autoconfig found");
-            param_autoconfig =
-                get_autoconfig(ctx, param, &param_domain, &param_addr_urlencoded).await;
+            None => {
+                info!(ctx, "no offline autoconfig found");
+                param_autoconfig =
+                    get_autoconfig(ctx, param, &param_domain, &param_addr_urlencoded).await;
+            }
         }
     } else {
         param_autoconfig = None;
@@ -340,6 +470,7 @@ async fn configure(ctx: &Context, param: &mut LoginParam) -> Result<()> {
     });
 
     progress!(ctx, 600);
+    enter_stage(ctx, stage, ConfigureStage::ImapConnect);
 
     // Configure IMAP
     let (_s, r) = async_std::channel::bounded(1);
@@ -380,10 +511,11 @@ async fn configure(ctx: &Context, param: &mut LoginParam) -> Result<()> {
         );
     }
     if !imap_configured {
-        bail!(nicer_configuration_error(ctx, errors).await);
+        bail!(ServerError::new(ctx, errors, *stage).await);
     }
 
     progress!(ctx, 850);
+    enter_stage(ctx, stage, ConfigureStage::SmtpConnect);
 
     // Wait for SMTP configuration
     match smtp_config_task.await {
@@ -391,11 +523,12 @@ async fn configure(ctx: &Context, param: &mut LoginParam) -> Result<()> {
             param.smtp = smtp_param;
         }
         Err(errors) => {
-            bail!(nicer_configuration_error(ctx, errors).await);
+            bail!(ServerError::new(ctx, errors, *stage).await);
         }
     }
 
     progress!(ctx, 900);
+    enter_stage(ctx, stage, ConfigureStage::Finalize);
 
     let create_mvbox = ctx.get_config_bool(Config::MvboxWatch).await
         || ctx.get_config_bool(Config::MvboxMove).await;
@@ -434,6 +567,28 @@ async fn configure(ctx: &Context, param: &mut LoginParam) -> Result<()> {
     Ok(())
 }
 
+fn username_by_pattern(pattern: &UsernamePattern, addr: &str) -> String {
+    match pattern {
+        UsernamePattern::EMAIL => addr.to_string(),
+        UsernamePattern::EMAILLOCALPART => {
+            if let Some(at) = addr.find('@') {
+                addr.split_at(at).0.to_string()
+            } else {
+                addr.to_string()
+            }
+        }
+    }
+}
+
+/// Looks up the provider overrides and the provider database for `domain`.
+///
+/// If a SOCKS5 proxy is used, the MX lookup is skipped,
+/// as the DNS request would not go through the proxy.
+async fn get_provider(context: &Context, domain: &str) -> Option<ProviderInfo> {
+    let skip_mx = context.get_config_bool(Config::Socks5Enabled).await;
+    provider::get_provider_info(context, domain, skip_mx).await
+}
+
 /// Retrieve available autoconfigurations.
 ///
 /// A Search configurations from the domain used in the email-address, prefer encrypted
@@ -530,9 +685,20 @@ async fn try_imap_one_param(
         .await
     {
         info!(context, "failure: {}", err);
+        let (stage, code) = match err.downcast_ref::<ConnectError>() {
+            Some(ConnectError::Connect(_)) => {
+                (ConfigureStage::ImapConnect, ConfigureErrorCode::Connection)
+            }
+            Some(ConnectError::Login(_)) => {
+                (ConfigureStage::ImapAuth, ConfigureErrorCode::AuthFailed)
+            }
+            Some(ConnectError::Oauth2(_)) => (ConfigureStage::ImapAuth, ConfigureErrorCode::Oauth2),
+            None => (ConfigureStage::ImapConnect, ConfigureErrorCode::Other),
+        };
         Err(ConfigurationError {
             config: inf,
             msg: err.to_string(),
+            diagnostics: ConfigureDiagnostics::new(stage, code, Some(param.server.clone())),
         })
     } else {
         info!(context, "success: {}", inf);
@@ -559,9 +725,28 @@ async fn try_smtp_one_param(
         .await
     {
         info!(context, "failure: {}", err);
+        let (stage, code) = match err {
+            // The server rejected the credentials.
+            smtp::Error::ConnectionFailure(async_smtp::smtp::error::Error::Permanent(
+                ref response,
+            ))
+            | smtp::Error::ConnectionFailure(async_smtp::smtp::error::Error::Transient(
+                ref response,
+            )) if is_smtp_auth_response(response) => {
+                (ConfigureStage::SmtpAuth, ConfigureErrorCode::AuthFailed)
+            }
+            smtp::Error::Oauth2Error { .. } => {
+                (ConfigureStage::SmtpAuth, ConfigureErrorCode::Oauth2)
+            }
+            smtp::Error::ConnectionFailure(_)
+            | smtp::Error::ConnectionSetupFailure(_)
+            | smtp::Error::Tls(_) => (ConfigureStage::SmtpConnect, ConfigureErrorCode::Connection),
+            _ => (ConfigureStage::SmtpConnect, ConfigureErrorCode::Other),
+        };
         Err(ConfigurationError {
             config: inf,
             msg: err.to_string(),
+            diagnostics: ConfigureDiagnostics::new(stage, code, Some(param.server.clone())),
         })
     } else {
         info!(context, "success: {}", inf);
@@ -570,11 +755,50 @@ async fn try_smtp_one_param(
     }
 }
 
+/// Returns true if the SMTP response is one of the replies defined for
+/// authentication failures, see <https://tools.ietf.org/html/rfc4954#section-6>.
+fn is_smtp_auth_response(response: &async_smtp::smtp::response::Response) -> bool {
+    matches!(
+        response.code.to_string().as_str(),
+        "454" | "530" | "534" | "535"
+    )
+}
+
 #[derive(Debug, thiserror::Error)]
 #[error("Trying {config}…\nError: {msg}")]
 pub struct ConfigurationError {
     config: String,
     msg: String,
+    diagnostics: ConfigureDiagnostics,
+}
+
+/// Error of a configuration stage after all servers failed.
+///
+/// Carries the diagnostics of the most advanced failure,
+/// e.g. a rejected login is preferred over an unreachable server.
+#[derive(Debug, thiserror::Error)]
+#[error("{msg}")]
+struct ServerError {
+    msg: String,
+    diagnostics: ConfigureDiagnostics,
+}
+
+impl ServerError {
+    async fn new(
+        context: &Context,
+        errors: Vec<ConfigurationError>,
+        stage: ConfigureStage,
+    ) -> ServerError {
+        let diagnostics = errors
+            .iter()
+            .map(|err| err.diagnostics.clone())
+            .max_by_key(|diagnostics| diagnostics.stage)
+            .unwrap_or_else(|| ConfigureDiagnostics::new(stage, ConfigureErrorCode::Other, None));
+        ServerError {
+            msg: nicer_configuration_error(context, errors).await,
+            diagnostics,
+        }
+    }
 }
 
 async fn nicer_configuration_error(context: &Context, errors: Vec<ConfigurationError>) -> String {
@@ -623,9 +847,63 @@ pub enum Error {
 mod tests {
     #![allow(clippy::indexing_slicing)]
 
+    use async_std::channel;
+    use async_std::io::BufReader;
+    use async_std::net::TcpListener;
+    use async_std::prelude::*;
+
+    use super::*;
     use crate::config::Config;
+    use crate::events::Event;
     use crate::test_utils::TestContext;
 
+    /// Starts an IMAP server rejecting every login, returns its port.
+    async fn start_rejecting_imap_server() -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        task::spawn(async move {
+            while let Some(Ok(stream)) = listener.incoming().next().await {
+                task::spawn(async move {
+                    let mut reader = BufReader::new(stream.clone());
+                    let mut writer = stream;
+                    writer.write_all(b"* OK IMAP4rev1 ready\r\n").await?;
+                    let mut line = String::new();
+                    while reader.read_line(&mut line).await? > 0 {
+                        let tag = line.split(' ').next().unwrap_or_default();
+                        let response =
+                            format!("{} NO [AUTHENTICATIONFAILED] Invalid credentials\r\n", tag);
+                        writer.write_all(response.as_bytes()).await?;
+                        line.clear();
+                    }
+                    Ok::<_, std::io::Error>(())
+                });
+            }
+        });
+        port
+    }
+
+    /// Returns a port nothing listens on.
+    async fn closed_port() -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        listener.local_addr().unwrap().port()
+    }
+
+    async fn set_local_servers(t: &TestContext, imap_port: u16, smtp_port: u16) {
+        let plain = (Socket::Plain as i32).to_string();
+        for (key, value) in &[
+            (Config::Addr, "alice@example.org".to_string()),
+            (Config::MailPw, "123456".to_string()),
+            (Config::MailServer, "127.0.0.1".to_string()),
+            (Config::MailPort, imap_port.to_string()),
+            (Config::MailSecurity, plain.clone()),
+            (Config::SendServer, "127.0.0.1".to_string()),
+            (Config::SendPort, smtp_port.to_string()),
+            (Config::SendSecurity, plain),
+        ] {
+            t.set_config(*key, Some(value.as_str())).await.unwrap();
+        }
+    }
+
     #[async_std::test]
     async fn test_no_panic_on_bad_credentials() {
         let t = TestContext::new().await;
@@ -635,4 +913,130 @@ async fn test_no_panic_on_bad_credentials() {
         t.set_config(Config::MailPw, Some("123456")).await.unwrap();
         assert!(t.configure().await.is_err());
     }
+
+    #[async_std::test]
+    async fn test_configure_diagnostics_imap_auth() {
+        let t = TestContext::new().await;
+        let (stage_tx, stage_rx) = channel::unbounded();
+        t.add_event_sink(move |event: Event| {
+            let stage_tx = stage_tx.clone();
+            async move {
+                match event.typ {
+                    EventType::ConfigureStageChanged(stage) => {
+                        stage_tx.try_send(Some(stage)).unwrap()
+                    }
+                    EventType::Info(msg) if msg == "end of test" => {
+                        stage_tx.try_send(None).unwrap()
+                    }
+                    _ => {}
+                }
+            }
+        })
+        .await;
+        assert_eq!(t.get_last_configure_diagnostics().await, None);
+
+        let imap_port = start_rejecting_imap_server().await;
+        set_local_servers(&t, imap_port, closed_port().await).await;
+        assert!(t.configure().await.is_err());
+        assert_eq!(
+            t.get_last_configure_diagnostics().await,
+            Some(ConfigureDiagnostics {
+                stage: ConfigureStage::ImapAuth,
+                code: ConfigureErrorCode::AuthFailed,
+                host: Some("127.0.0.1".to_string()),
+            })
+        );
+
+        t.emit_event(EventType::Info("end of test".to_string()));
+        let mut stages = Vec::new();
+        while let Some(stage) = stage_rx.recv().await.unwrap() {
+            stages.push(stage);
+        }
+        assert_eq!(
+            stages,
+            vec![
+                ConfigureStage::AutoconfigLookup,
+                ConfigureStage::ImapConnect
+            ]
+        );
+
+        save_diagnostics(&t, &Ok(()), ConfigureStage::Finalize)
+            .await
+            .unwrap();
+        assert_eq!(t.get_last_configure_diagnostics().await, None);
+    }
+
+    #[async_std::test]
+    async fn test_configure_diagnostics_imap_connect() {
+        let t = TestContext::new().await;
+        set_local_servers(&t, closed_port().await, closed_port().await).await;
+        assert!(t.configure().await.is_err());
+        assert_eq!(
+            t.get_last_configure_diagnostics().await,
+            Some(ConfigureDiagnostics {
+                stage: ConfigureStage::ImapConnect,
+                code: ConfigureErrorCode::Connection,
+                host: Some("127.0.0.1".to_string()),
+            })
+        );
+
+        // A failure before any server is tried has no host.
+        t.set_config(Config::MailPw, None).await.unwrap();
+        assert!(t.configure().await.is_err());
+        assert_eq!(
+            t.get_last_configure_diagnostics().await,
+            Some(ConfigureDiagnostics {
+                stage: ConfigureStage::AutoconfigLookup,
+                code: ConfigureErrorCode::Other,
+                host: None,
+            })
+        );
+    }
+
+    #[async_std::test]
+    async fn test_configure_provider_override() {
+        let t = TestContext::new().await;
+        let imap_port = closed_port().await;
+        let overrides = t.get_blobdir().join("provider-overrides.toml");
+        async_std::fs::write(
+            &overrides,
+            format!(
+                "[[provider]]\n\
+                 domains = [\"example.org\"]\n\
+                 [[provider.server]]\n\
+                 protocol = \"imap\"\n\
+                 socket = \"plain\"\n\
+                 hostname = \"127.0.0.1\"\n\
+                 port = {}\n\
+                 [[provider.server]]\n\
+                 protocol = \"smtp\"\n\
+                 socket = \"plain\"\n\
+                 hostname = \"127.0.0.1\"\n\
+                 port = {}\n",
+                imap_port,
+                closed_port().await
+            ),
+        )
+        .await
+        .unwrap();
+        t.sql
+            .set_raw_config(&t, "provider_overrides_file", overrides.to_str())
+            .await
+            .unwrap();
+        t.set_config(Config::Addr, Some("alice@example.org"))
+            .await
+            .unwrap();
+        t.set_config(Config::MailPw, Some("123456")).await.unwrap();
+
+        // Only the server from the override is tried.
+        assert!(t.configure().await.is_err());
+        assert_eq!(
+            t.get_last_configure_diagnostics().await,
+            Some(ConfigureDiagnostics {
+                stage: ConfigureStage::ImapConnect,
+                code: ConfigureErrorCode::Connection,
+                host: Some("127.0.0.1".to_string()),
+            })
+        );
+    }
 }
@@ -1,14 +1,29 @@
 use crate::context::Context;
+use crate::socks::{self, Socks5Config};
 
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
     #[error("URL request error")]
     GetError(surf::Error),
+
+    #[error("URL request through SOCKS5 proxy failed: {0:#}")]
+    Socks5Error(anyhow::Error),
 }
 
 pub async fn read_url(context: &Context, url: &str) -> Result<String, Error> {
     info!(context, "Requesting URL {}", url);
 
+    if let Some(socks5_config) = Socks5Config::from_database(context).await {
+        return match socks::http_get(&socks5_config, url).await {
+            Ok(res) => Ok(res),
+            Err(err) => {
+                info!(context, "Can\'t read URL {} through proxy: {:#}", url, err);
+
+                Err(Error::Socks5Error(err))
+            }
+        };
+    }
+
     match surf::get(url).recv_string().await {
         Ok(res) => Ok(res),
         Err(err) => {
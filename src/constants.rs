@@ -63,6 +63,52 @@ fn default() -> Self {
     }
 }
 
+/// How much of a message is shown in notifications, see [crate::notification].
+#[derive(
+    Debug, Display, Clone, Copy, PartialEq, Eq, FromPrimitive, ToPrimitive, FromSql, ToSql,
+)]
+#[repr(u8)]
+pub enum NotificationPrivacy {
+    /// Chat name, sender name and message text are shown.
+    Full = 0,
+
+    /// Chat name and sender name are shown, but not the message text.
+    NameOnly = 1,
+
+    /// Only the number of new messages is shown.
+    CountOnly = 2,
+}
+
+impl Default for NotificationPrivacy {
+    fn default() -> Self {
+        NotificationPrivacy::Full // also change Config.NotificationPrivacy props(default) on changes
+    }
+}
+
+/// How notifications of a chat are presented, see [crate::chat::ChatId::set_notify_profile].
+///
+/// Muting wins over the profile: a muted chat is not notified, whatever its profile is.
+#[derive(
+    Debug, Display, Clone, Copy, PartialEq, Eq, FromPrimitive, ToPrimitive, FromSql, ToSql,
+)]
+#[repr(u8)]
+pub enum NotifyProfile {
+    /// Notifications are presented as configured for the app.
+    Default = 0,
+
+    /// Notifications are shown without sound or vibration.
+    Silent = 1,
+
+    /// Notifications are presented with high importance, e.g. with a distinct sound.
+    Priority = 2,
+}
+
+impl Default for NotifyProfile {
+    fn default() -> Self {
+        NotifyProfile::Default
+    }
+}
+
 #[derive(
     Debug, Display, Clone, Copy, PartialEq, Eq, FromPrimitive, ToPrimitive, FromSql, ToSql,
 )]
@@ -153,6 +199,7 @@ pub enum Chattype {
     Single = 100,
     Group = 120,
     Mailinglist = 140,
+    Broadcast = 160,
 }
 
 impl Default for Chattype {
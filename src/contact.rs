@@ -1,14 +1,19 @@
 //! Contacts module
 
+use std::collections::{BTreeMap, BTreeSet};
+
 use anyhow::{bail, ensure, format_err, Context as _, Result};
 use async_std::path::PathBuf;
 use deltachat_derive::{FromSql, ToSql};
 use itertools::Itertools;
 use once_cell::sync::Lazy;
 use regex::Regex;
+use rusqlite::OptionalExtension;
+use sha2::{Digest, Sha256};
 
 use crate::aheader::EncryptPreference;
-use crate::chat::ChatId;
+use crate::blob::BlobObject;
+use crate::chat::{Chat, ChatId, ProtectionStatus};
 use crate::color::str_to_color;
 use crate::config::Config;
 use crate::constants::{
@@ -16,14 +21,20 @@
     DC_CONTACT_ID_LAST_SPECIAL, DC_CONTACT_ID_SELF, DC_GCL_ADD_SELF, DC_GCL_VERIFIED_ONLY,
 };
 use crate::context::Context;
-use crate::dc_tools::{dc_get_abs_path, improve_single_line_input, EmailAddress};
+use crate::dc_tools::{
+    dc_get_abs_path, improve_single_line_input, normalize_addr, time, EmailAddress,
+};
+use crate::ephemeral::Timer as EphemeralTimer;
 use crate::events::EventType;
-use crate::key::{DcKey, SignedPublicKey};
+use crate::key::{DcKey, Fingerprint, SignedPublicKey};
 use crate::login_param::LoginParam;
 use crate::message::MessageState;
 use crate::mimeparser::AvatarAction;
 use crate::param::{Param, Params};
-use crate::peerstate::{Peerstate, PeerstateVerifiedStatus};
+use crate::peerstate::{Peerstate, PeerstateKeyType, PeerstateVerifiedStatus};
+use crate::stats::Counter;
+use crate::sync::Sync;
+use crate::vcard::{self, VcardContact};
 use crate::{chat, stock_str};
 
 /// An object representing a single contact in memory.
@@ -38,7 +49,7 @@
 /// authorized name and given name.
 /// By default, these names are equal, but functions working with contact names
 /// only affect the given name.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Contact {
     /// The contact ID.
     ///
@@ -75,6 +86,21 @@ pub struct Contact {
     status: String,
 }
 
+/// SQL expression for the last time a contact `c` was seen.
+///
+/// This is the newer of the time of the last message received from the contact
+/// and the time the Autocrypt peerstate of the address was last updated, 0 if neither exists.
+const LAST_SEEN_SQL: &str = "MAX( \
+     IFNULL((SELECT ps.last_seen FROM acpeerstates ps WHERE ps.addr=c.addr), 0), \
+     IFNULL((SELECT MAX(m.timestamp) FROM msgs m WHERE m.from_id=c.id), 0))";
+
+/// Filters for [Contact::get_filtered].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContactFilter {
+    /// Contacts seen within the given number of days, see [Contact::last_seen].
+    ContactsRecentlyActive(u32),
+}
+
 /// Possible origins of a contact.
 #[derive(
     Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, FromPrimitive, ToPrimitive, FromSql, ToSql,
@@ -174,7 +200,36 @@ pub enum VerifiedStatus {
 
 impl Contact {
     pub async fn load_from_db(context: &Context, contact_id: u32) -> crate::sql::Result<Self> {
-        let mut res = context
+        let caches = context.sql.caches();
+        let mut res = match caches.get_contact(contact_id) {
+            Some(contact) => contact,
+            None => {
+                let generation = caches.generation();
+                let contact = Self::load_row(context, contact_id).await?;
+                caches.put_contact(contact_id, contact.clone(), generation);
+                contact
+            }
+        };
+        if contact_id == DC_CONTACT_ID_SELF {
+            res.name = stock_str::self_msg(context).await;
+            res.addr = context
+                .get_config(Config::ConfiguredAddr)
+                .await
+                .unwrap_or_default();
+            res.status = context
+                .get_config(Config::Selfstatus)
+                .await
+                .unwrap_or_default();
+        } else if contact_id == DC_CONTACT_ID_DEVICE {
+            res.name = stock_str::device_messages(context).await;
+            res.addr = DC_CONTACT_ID_DEVICE_ADDR.to_string();
+        }
+        Ok(res)
+    }
+
+    /// Loads the contact as stored in the database, without the names of special contacts.
+    async fn load_row(context: &Context, contact_id: u32) -> crate::sql::Result<Self> {
+        context
             .sql
             .query_row(
                 "SELECT c.name, c.addr, c.origin, c.blocked, c.authname, c.param, c.status
@@ -195,22 +250,7 @@ pub async fn load_from_db(context: &Context, contact_id: u32) -> crate::sql::Res
                     Ok(contact)
                 },
             )
-            .await?;
-        if contact_id == DC_CONTACT_ID_SELF {
-            res.name = stock_str::self_msg(context).await;
-            res.addr = context
-                .get_config(Config::ConfiguredAddr)
-                .await
-                .unwrap_or_default();
-            res.status = context
-                .get_config(Config::Selfstatus)
-                .await
-                .unwrap_or_default();
-        } else if contact_id == DC_CONTACT_ID_DEVICE {
-            res.name = stock_str::device_messages(context).await;
-            res.addr = DC_CONTACT_ID_DEVICE_ADDR.to_string();
-        }
-        Ok(res)
+            .await
     }
 
     /// Returns `true` if this contact is blocked.
@@ -306,10 +346,10 @@ pub async fn lookup_id_by_addr(
             bail!("lookup_id_by_addr: empty address");
         }
 
-        let addr_normalized = addr_normalize(addr.as_ref());
+        let addr_normalized = normalize_addr(addr.as_ref());
 
         if let Some(addr_self) = context.get_config(Config::ConfiguredAddr).await {
-            if addr_cmp(addr_normalized, addr_self) {
+            if addr_cmp(&addr_normalized, addr_self) {
                 return Ok(Some(DC_CONTACT_ID_SELF));
             }
         }
@@ -364,7 +404,7 @@ pub(crate) async fn add_or_lookup(
         );
         ensure!(origin != Origin::Unknown, "Missing valid origin");
 
-        let addr = addr_normalize(addr.as_ref()).to_string();
+        let addr = normalize_addr(addr.as_ref());
         let addr_self = context
             .get_config(Config::ConfiguredAddr)
             .await
@@ -515,6 +555,7 @@ pub(crate) async fn add_or_lookup(
                     .get_rowid(context, "contacts", "addr", &addr)
                     .await?;
                 sth_modified = Modifier::Created;
+                context.stats_cache.adjust(Counter::Contacts, 1);
                 info!(context, "added contact id={} addr={}", row_id, &addr);
             } else {
                 error!(context, "Cannot add contact.");
@@ -677,6 +718,49 @@ pub async fn get_all(
         Ok(ret)
     }
 
+    /// Returns known and unblocked contacts matching a filter.
+    ///
+    /// For [ContactFilter::ContactsRecentlyActive], the most recently seen contacts come first.
+    pub async fn get_filtered(context: &Context, filter: ContactFilter) -> Result<Vec<u32>> {
+        let self_addr = context
+            .get_config(Config::ConfiguredAddr)
+            .await
+            .unwrap_or_default();
+
+        match filter {
+            ContactFilter::ContactsRecentlyActive(days) => {
+                let since = time() - i64::from(days) * 24 * 60 * 60;
+                let ids = context
+                    .sql
+                    .query_map(
+                        format!(
+                            "SELECT c.id FROM contacts c \
+                             WHERE c.addr!=?1 \
+                             AND c.id>?2 \
+                             AND c.origin>=?3 \
+                             AND c.blocked=0 \
+                             AND {last_seen}>=?4 \
+                             ORDER BY {last_seen} DESC, c.id;",
+                            last_seen = LAST_SEEN_SQL
+                        ),
+                        paramsv![
+                            self_addr,
+                            DC_CONTACT_ID_LAST_SPECIAL as i32,
+                            Origin::IncomingReplyTo,
+                            since.max(1),
+                        ],
+                        |row| row.get::<_, u32>(0),
+                        |ids| {
+                            ids.collect::<std::result::Result<Vec<_>, _>>()
+                                .map_err(Into::into)
+                        },
+                    )
+                    .await?;
+                Ok(ids)
+            }
+        }
+    }
+
     // add blocked mailinglists as contacts
     // to allow unblocking them as if they are contacts
     // (this way, only one unblock-ffi is needed and only one set of ui-functions,
@@ -705,6 +789,7 @@ async fn update_blocked_mailinglist_contacts(context: &Context) -> Result<()> {
                     .sql
                     .execute("INSERT INTO contacts (addr) VALUES (?);", paramsv![grpid])
                     .await?;
+                context.stats_cache.adjust(Counter::Contacts, 1);
             }
             // always do an update in case the blocking is reset or name is changed
             context
@@ -856,6 +941,7 @@ pub async fn delete(context: &Context, contact_id: u32) -> Result<()> {
                 .await
             {
                 Ok(_) => {
+                    context.stats_cache.adjust(Counter::Contacts, -1);
                     context.emit_event(EventType::ContactsChanged(None));
                     return Ok(());
                 }
@@ -908,6 +994,45 @@ pub async fn update_status(&self, context: &Context) -> Result<()> {
         Ok(())
     }
 
+    /// Sets the ephemeral timer applied to new 1:1 chats with the contact.
+    ///
+    /// Existing chats are not changed.
+    pub async fn set_default_ephemeral_timer(
+        context: &Context,
+        contact_id: u32,
+        timer: EphemeralTimer,
+    ) -> Result<()> {
+        ensure!(
+            contact_id > DC_CONTACT_ID_LAST_SPECIAL,
+            "Can't set default ephemeral timer for special contact {}",
+            contact_id
+        );
+        let updated = context
+            .sql
+            .execute(
+                "UPDATE contacts SET default_ephemeral_timer=? WHERE id=?;",
+                paramsv![timer, contact_id as i32],
+            )
+            .await?;
+        ensure!(updated > 0, "Contact {} does not exist", contact_id);
+        Ok(())
+    }
+
+    /// Returns the ephemeral timer applied to new 1:1 chats with the contact.
+    pub async fn get_default_ephemeral_timer(
+        context: &Context,
+        contact_id: u32,
+    ) -> Result<EphemeralTimer> {
+        let timer = context
+            .sql
+            .query_get_value_result(
+                "SELECT default_ephemeral_timer FROM contacts WHERE id=?;",
+                paramsv![contact_id as i32],
+            )
+            .await?;
+        Ok(timer.unwrap_or_default())
+    }
+
     /// Get the ID of the contact.
     pub fn get_id(&self) -> u32 {
         self.id
@@ -984,8 +1109,11 @@ pub async fn get_profile_image(&self, context: &Context) -> Option<PathBuf> {
     /// The color is calculated from the contact's email address
     /// and can be used for an fallback avatar with white initials
     /// as well as for headlines in bubbles of group chats.
+    ///
+    /// The address is normalized and lowercased first,
+    /// so all spellings of an address get the same color, see [crate::color].
     pub fn get_color(&self) -> u32 {
-        str_to_color(&self.addr)
+        str_to_color(normalize_addr(&self.addr).to_lowercase())
     }
 
     /// Gets the contact's status.
@@ -995,6 +1123,27 @@ pub fn get_status(&self) -> &str {
         self.status.as_str()
     }
 
+    /// Returns when the contact was seen for the last time, if ever.
+    ///
+    /// This is based on the messages received from the contact, not on presence:
+    /// the newer of the last message received from the contact and the last
+    /// Autocrypt peerstate update for its address is returned.
+    /// `None` is returned for SELF and the device contact.
+    pub async fn last_seen(&self, context: &Context) -> Result<Option<i64>> {
+        if self.id <= DC_CONTACT_ID_LAST_SPECIAL {
+            return Ok(None);
+        }
+        let last_seen: i64 = context
+            .sql
+            .query_get_value_result(
+                &format!("SELECT {} FROM contacts c WHERE c.id=?;", LAST_SEEN_SQL),
+                paramsv![self.id as i32],
+            )
+            .await?
+            .unwrap_or_default();
+        Ok(Some(last_seen).filter(|last_seen| *last_seen > 0))
+    }
+
     /// Check if a contact was verified. E.g. by a secure-join QR code scan
     /// and if the key has not changed since this verification.
     ///
@@ -1054,11 +1203,8 @@ pub async fn addr_equals_contact(
         }
 
         if let Ok(contact) = Contact::load_from_db(context, contact_id).await {
-            if !contact.addr.is_empty() {
-                let normalized_addr = addr_normalize(addr.as_ref());
-                if contact.addr == normalized_addr {
-                    return true;
-                }
+            if !contact.addr.is_empty() && addr_cmp(&contact.addr, addr) {
+                return true;
             }
         }
 
@@ -1114,15 +1260,10 @@ pub fn may_be_valid_addr(addr: &str) -> bool {
     res.is_ok()
 }
 
-/// Returns address with whitespace trimmed and `mailto:` prefix removed.
-pub fn addr_normalize(addr: &str) -> &str {
-    let norm = addr.trim();
-
-    if norm.starts_with("mailto:") {
-        norm.get(7..).unwrap_or(norm)
-    } else {
-        norm
-    }
+/// Returns the normalized address, see [normalize_addr].
+#[deprecated(note = "use `dc_tools::normalize_addr()` instead")]
+pub fn addr_normalize(addr: &str) -> String {
+    normalize_addr(addr)
 }
 
 fn sanitize_name_and_addr(name: impl AsRef<str>, addr: impl AsRef<str>) -> (String, String) {
@@ -1170,6 +1311,7 @@ async fn set_block_contact(context: &Context, contact_id: u32, new_blocking: boo
                 "UPDATE chats SET blocked=? WHERE type=? AND id IN (SELECT chat_id FROM chats_contacts WHERE contact_id=?);",
                 paramsv![new_blocking, 100, contact_id as i32]).await.is_ok()
             {
+                context.stats_cache.invalidate();
                 Contact::mark_noticed(context, contact_id).await;
                 context.emit_event(EventType::ContactsChanged(Some(contact_id)));
             }
@@ -1205,7 +1347,7 @@ pub(crate) async fn set_profile_image(
             if contact_id == DC_CONTACT_ID_SELF {
                 if was_encrypted {
                     context
-                        .set_config(Config::Selfavatar, Some(profile_image))
+                        .set_config_ex(Sync::Nosync, Config::Selfavatar, Some(profile_image))
                         .await?;
                 } else {
                     info!(context, "Do not use unencrypted selfavatar.");
@@ -1218,7 +1360,9 @@ pub(crate) async fn set_profile_image(
         AvatarAction::Delete => {
             if contact_id == DC_CONTACT_ID_SELF {
                 if was_encrypted {
-                    context.set_config(Config::Selfavatar, None).await?;
+                    context
+                        .set_config_ex(Sync::Nosync, Config::Selfavatar, None)
+                        .await?;
                 } else {
                     info!(context, "Do not use unencrypted selfavatar deletion.");
                 }
@@ -1247,6 +1391,527 @@ pub(crate) async fn set_status(context: &Context, contact_id: u32, status: Strin
     Ok(())
 }
 
+/// Result of [import_vcard].
+#[derive(Debug, Default)]
+pub struct VcardImport {
+    /// IDs of the imported contacts.
+    pub contact_ids: Vec<u32>,
+
+    /// Entries that were skipped.
+    pub errors: Vec<VcardImportError>,
+}
+
+/// A vCard entry skipped by [import_vcard].
+#[derive(Debug)]
+pub struct VcardImportError {
+    /// Number of the card in the file, starting at 1.
+    pub card: usize,
+
+    /// The address that was skipped, `None` if the whole card was skipped.
+    pub addr: Option<String>,
+
+    /// Why the entry was skipped.
+    pub error: anyhow::Error,
+}
+
+/// Imports contacts from a vCard file, eg. exported from an address book.
+///
+/// Each email address of a card is added as a contact named as the card.
+/// The photo of a card is used as profile image for contacts that have none yet.
+/// Malformed cards and invalid addresses are skipped and returned as errors
+/// together with the imported contacts.
+///
+/// Fails only if the file contains no card at all.
+pub async fn import_vcard(context: &Context, vcard: &str) -> Result<VcardImport> {
+    let cards = vcard::parse_vcards(vcard);
+    ensure!(!cards.is_empty(), "No vCard found");
+
+    let mut import = VcardImport::default();
+    for (i, card) in cards.into_iter().enumerate() {
+        let res = match card {
+            Ok(card) => import_vcard_contact(context, i + 1, card, &mut import).await,
+            Err(err) => Err(err),
+        };
+        if let Err(error) = res {
+            warn!(context, "Skipping vCard #{}: {:#}", i + 1, error);
+            import.errors.push(VcardImportError {
+                card: i + 1,
+                addr: None,
+                error,
+            });
+        }
+    }
+    if !import.contact_ids.is_empty() {
+        context.emit_event(EventType::ContactsChanged(None));
+    }
+    Ok(import)
+}
+
+async fn import_vcard_contact(
+    context: &Context,
+    card_number: usize,
+    card: VcardContact,
+    import: &mut VcardImport,
+) -> Result<()> {
+    let avatar = match &card.photo {
+        Some((data, extension)) => Some(
+            BlobObject::create(context, format!("avatar.{}", extension), data)
+                .await?
+                .as_name()
+                .to_string(),
+        ),
+        None => None,
+    };
+
+    for addr in &card.addrs {
+        // exported contacts without name have their address as name
+        let name = if addr_cmp(&card.display_name, addr) {
+            String::new()
+        } else {
+            normalize_name(&card.display_name)
+        };
+        let (name, addr) = sanitize_name_and_addr(name, addr);
+        let contact_id =
+            match Contact::add_or_lookup(context, name, &addr, Origin::AddressBook).await {
+                Ok((contact_id, _)) => contact_id,
+                Err(error) => {
+                    warn!(context, "Skipping vCard address {:?}: {:#}", addr, error);
+                    import.errors.push(VcardImportError {
+                        card: card_number,
+                        addr: Some(addr),
+                        error,
+                    });
+                    continue;
+                }
+            };
+        if let Some(avatar) = &avatar {
+            let contact = Contact::load_from_db(context, contact_id).await?;
+            if contact_id != DC_CONTACT_ID_SELF
+                && contact.get_profile_image(context).await.is_none()
+            {
+                set_profile_image(
+                    context,
+                    contact_id,
+                    &AvatarAction::Change(avatar.clone()),
+                    false,
+                )
+                .await?;
+            }
+        }
+        import.contact_ids.push(contact_id);
+    }
+    Ok(())
+}
+
+/// Creates a vCard file containing the given contacts,
+/// including their profile images.
+pub async fn make_vcard(context: &Context, contact_ids: &[u32]) -> Result<String> {
+    let mut cards = Vec::new();
+    for contact_id in contact_ids {
+        let contact = Contact::load_from_db(context, *contact_id).await?;
+        let photo = match contact.get_profile_image(context).await {
+            Some(path) => {
+                let extension = path
+                    .extension()
+                    .and_then(|extension| extension.to_str())
+                    .unwrap_or_default()
+                    .to_lowercase();
+                Some((async_std::fs::read(&path).await?, extension))
+            }
+            None => None,
+        };
+        cards.push(VcardContact {
+            display_name: contact.get_display_name().to_string(),
+            addrs: vec![contact.get_addr().to_string()],
+            photo,
+        });
+    }
+    Ok(vcard::make_vcard(&cards))
+}
+
+/// Returns groups of contacts whose addresses are equal after normalization,
+/// ignoring case.
+///
+/// The contacts of each group are sorted by ID, so the first one is the oldest contact.
+/// Groups can be merged with [merge].
+pub async fn find_duplicates(context: &Context) -> Result<Vec<Vec<u32>>> {
+    let mut by_addr: BTreeMap<String, Vec<u32>> = BTreeMap::new();
+    context
+        .sql
+        .query_map(
+            "SELECT id, addr FROM contacts WHERE id>? AND addr!='' ORDER BY id;",
+            paramsv![DC_CONTACT_ID_LAST_SPECIAL],
+            |row| Ok((row.get::<_, u32>(0)?, row.get::<_, String>(1)?)),
+            |rows| {
+                for row in rows {
+                    let (id, addr) = row?;
+                    by_addr
+                        .entry(normalize_addr(&addr).to_lowercase())
+                        .or_default()
+                        .push(id);
+                }
+                Ok(())
+            },
+        )
+        .await?;
+    Ok(by_addr
+        .into_iter()
+        .map(|(_, ids)| ids)
+        .filter(|ids| ids.len() > 1)
+        .collect())
+}
+
+/// Merges the contacts `merge` into the contact `keep` and deletes them.
+///
+/// All contacts must have the same address, see [find_duplicates].
+/// Chat memberships, messages, read receipts, reactions and locations are moved to `keep`.
+/// If `keep` has a 1:1 chat already, the messages of the 1:1 chats of the merged contacts
+/// are moved there and these chats are deleted.  Of the peerstates of the addresses, a verified one is kept, otherwise the most recently
+/// seen one.
+///
+/// Emits `#DC_EVENT_CONTACTS_CHANGED` and `#DC_EVENT_CHAT_MODIFIED` for the chats the
+/// merged contacts were members of.
+pub async fn merge(context: &Context, keep: u32, merge: &[u32]) -> Result<()> {
+    ensure!(
+        keep > DC_CONTACT_ID_LAST_SPECIAL,
+        "Can not merge into special contact"
+    );
+    ensure!(!merge.contains(&keep), "Can not merge contact into itself");
+    let keep_addr = Contact::load_from_db(context, keep).await?.addr;
+    let mut addrs = vec![keep_addr.clone()];
+    for contact_id in merge {
+        ensure!(
+            *contact_id > DC_CONTACT_ID_LAST_SPECIAL,
+            "Can not merge special contact"
+        );
+        let addr = Contact::load_from_db(context, *contact_id).await?.addr;
+        ensure!(
+            addr_cmp(&addr, &keep_addr),
+            "Can not merge contacts with different addresses"
+        );
+        addrs.push(addr);
+    }
+
+    let chat_ids = context
+        .sql
+        .transaction(move |tx| {
+            let mut chat_ids = BTreeSet::new();
+            for contact_id in merge {
+                if let (Some(duplicate), Some(kept)) = (
+                    lookup_single_chat(tx, *contact_id)?,
+                    lookup_single_chat(tx, keep)?,
+                ) {
+                    for statement in &[
+                        "UPDATE msgs SET chat_id=?2 WHERE chat_id=?1;",
+                        "UPDATE locations SET chat_id=?2 WHERE chat_id=?1;",
+                        "DELETE FROM chats_contacts WHERE chat_id=?1;",
+                        "DELETE FROM chats WHERE id=?1;",
+                    ] {
+                        tx.execute(statement, rusqlite::params![duplicate, kept])?;
+                    }
+                    chat_ids.insert(kept);
+                }
+
+                let mut stmt =
+                    tx.prepare("SELECT DISTINCT chat_id FROM chats_contacts WHERE contact_id=?;")?;
+                for chat_id in stmt.query_map(rusqlite::params![contact_id], |row| row.get(0))? {
+                    chat_ids.insert(ChatId::new(chat_id?));
+                }
+
+                // Do not add `keep` to chats it is already a member of.
+                tx.execute(
+                    "DELETE FROM chats_contacts WHERE contact_id=?1 \
+                     AND chat_id IN (SELECT chat_id FROM chats_contacts WHERE contact_id=?2);",
+                    rusqlite::params![contact_id, keep],
+                )?;
+                tx.execute(
+                    "DELETE FROM msgs_mdns WHERE contact_id=?1 \
+                     AND msg_id IN (SELECT msg_id FROM msgs_mdns WHERE contact_id=?2);",
+                    rusqlite::params![contact_id, keep],
+                )?;
+                for statement in &[
+                    "UPDATE chats_contacts SET contact_id=?2 WHERE contact_id=?1;",
+                    "UPDATE msgs SET from_id=?2 WHERE from_id=?1;",
+                    "UPDATE msgs SET to_id=?2 WHERE to_id=?1;",
+                    "UPDATE msgs_mdns SET contact_id=?2 WHERE contact_id=?1;",
+                    "UPDATE locations SET from_id=?2 WHERE from_id=?1;",
+                    "UPDATE OR IGNORE msgs_broadcast SET contact_id=?2 WHERE contact_id=?1;",
+                    "DELETE FROM msgs_broadcast WHERE contact_id=?1;",
+                    "UPDATE OR IGNORE reactions SET contact_id=?2 WHERE contact_id=?1;",
+                    "DELETE FROM reactions WHERE contact_id=?1;",
+                    "UPDATE contacts SET origin=\
+                     (SELECT MAX(origin) FROM contacts WHERE id IN (?1, ?2)) WHERE id=?2;",
+                    "DELETE FROM contacts WHERE id=?1;",
+                ] {
+                    tx.execute(statement, rusqlite::params![contact_id, keep])?;
+                }
+            }
+
+            merge_peerstates(tx, &addrs, &keep_addr)?;
+            Ok(chat_ids)
+        })
+        .await?;
+
+    // Messages may have been moved between chats of different blocked states.
+    context.stats_cache.invalidate();
+    info!(
+        context,
+        "Merged contacts {:?} into {}, {} chats affected.",
+        merge,
+        keep,
+        chat_ids.len()
+    );
+    context.emit_event(EventType::ContactsChanged(None));
+    for chat_id in chat_ids {
+        context.emit_event(EventType::ChatModified(chat_id));
+    }
+    Ok(())
+}
+
+/// Returns the 1:1 chat of the contact, if any.
+fn lookup_single_chat(
+    tx: &rusqlite::Transaction,
+    contact_id: u32,
+) -> rusqlite::Result<Option<ChatId>> {
+    tx.query_row(
+        "SELECT c.id FROM chats c INNER JOIN chats_contacts cc ON c.id=cc.chat_id \
+         WHERE c.type=? AND cc.contact_id=?;",
+        rusqlite::params![Chattype::Single, contact_id],
+        |row| row.get(0),
+    )
+    .optional()
+}
+
+/// Replaces the peerstates of `addrs` by a single one for `keep_addr`.
+///
+/// A verified peerstate is kept, otherwise the most recently seen one.
+fn merge_peerstates(tx: &rusqlite::Transaction, addrs: &[String], keep_addr: &str) -> Result<()> {
+    // Addresses are compared case-insensitively, so the same peerstate may be found
+    // for several addresses.
+    let mut peerstates = BTreeMap::new();
+    for addr in addrs {
+        let mut stmt = tx.prepare(
+            "SELECT id, verified_key IS NOT NULL, last_seen FROM acpeerstates WHERE addr=?;",
+        )?;
+        let rows = stmt.query_map(rusqlite::params![addr], |row| {
+            Ok((
+                row.get::<_, i64>(0)?,
+                (row.get::<_, bool>(1)?, row.get::<_, i64>(2)?),
+            ))
+        })?;
+        for row in rows {
+            let (id, rank) = row?;
+            peerstates.insert(id, rank);
+        }
+    }
+    if let Some((&best, _)) = peerstates.iter().max_by_key(|(_, rank)| **rank) {
+        for id in peerstates.keys().filter(|id| **id != best) {
+            tx.execute(
+                "DELETE FROM acpeerstates WHERE id=?;",
+                rusqlite::params![id],
+            )?;
+        }
+        tx.execute(
+            "UPDATE acpeerstates SET addr=? WHERE id=?;",
+            rusqlite::params![keep_addr, best],
+        )?;
+    }
+    Ok(())
+}
+
+/// Rewrites the addresses of contacts and peerstates stored before addresses were
+/// normalized on input, see [normalize_addr].
+///
+/// Contacts which turn out to have the same address are merged with [merge],
+/// remaining peerstates of the same address are merged as well.
+pub(crate) async fn normalize_stored_addrs(context: &Context) -> Result<()> {
+    context
+        .sql
+        .transaction(|tx| {
+            for table in &["contacts", "acpeerstates"] {
+                let rows = tx
+                    .prepare(&format!("SELECT id, addr FROM {} WHERE addr!='';", table))?
+                    .query_map(rusqlite::NO_PARAMS, |row| {
+                        Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?))
+                    })?
+                    .collect::<rusqlite::Result<Vec<_>>>()?;
+                for (id, addr) in rows {
+                    let normalized = normalize_addr(&addr);
+                    if normalized != addr {
+                        tx.execute(
+                            &format!("UPDATE {} SET addr=? WHERE id=?;", table),
+                            rusqlite::params![normalized, id],
+                        )?;
+                    }
+                }
+            }
+            Ok(())
+        })
+        .await?;
+
+    for duplicates in find_duplicates(context).await? {
+        if let Some((keep, merged)) = duplicates.split_first() {
+            merge(context, *keep, merged).await?;
+        }
+    }
+
+    // Peerstates may differ in case without a contact for each of them.
+    context
+        .sql
+        .transaction(|tx| {
+            let mut by_addr: BTreeMap<String, Vec<String>> = BTreeMap::new();
+            let rows = tx
+                .prepare("SELECT addr FROM acpeerstates ORDER BY id;")?
+                .query_map(rusqlite::NO_PARAMS, |row| row.get::<_, String>(0))?
+                .collect::<rusqlite::Result<Vec<_>>>()?;
+            for addr in rows {
+                by_addr.entry(addr.to_lowercase()).or_default().push(addr);
+            }
+            for (_, addrs) in by_addr {
+                let first = match addrs.first() {
+                    Some(first) if addrs.len() > 1 => first.clone(),
+                    _ => continue,
+                };
+                let keep_addr = tx
+                    .query_row(
+                        "SELECT addr FROM contacts WHERE addr=? COLLATE NOCASE;",
+                        rusqlite::params![first],
+                        |row| row.get::<_, String>(0),
+                    )
+                    .optional()?
+                    .unwrap_or(first);
+                merge_peerstates(tx, &addrs, &keep_addr)?;
+            }
+            Ok(())
+        })
+        .await
+}
+
+/// Number of blocks returned by [get_verification_words].
+const VERIFICATION_BLOCKS: usize = 6;
+
+/// Returns the key of `peerstate` to compare out of band, the same as used for encryption.
+fn verification_key(peerstate: &Peerstate) -> Option<(PeerstateKeyType, Fingerprint)> {
+    if let Some(fingerprint) = &peerstate.public_key_fingerprint {
+        Some((PeerstateKeyType::PublicKey, fingerprint.clone()))
+    } else {
+        peerstate
+            .gossip_key_fingerprint
+            .clone()
+            .map(|fingerprint| (PeerstateKeyType::GossipKey, fingerprint))
+    }
+}
+
+/// Derives blocks of five digits from two fingerprints.
+///
+/// The order of the fingerprints does not matter, so both parties compute the same blocks.
+fn verification_words(fingerprint1: &Fingerprint, fingerprint2: &Fingerprint) -> String {
+    let mut fingerprints = [fingerprint1.hex(), fingerprint2.hex()];
+    fingerprints.sort();
+    let hash = Sha256::digest(fingerprints.concat().as_bytes());
+    hash.chunks(5)
+        .take(VERIFICATION_BLOCKS)
+        .map(|chunk| {
+            let value = chunk
+                .iter()
+                .fold(0u64, |acc, byte| (acc << 8) | u64::from(*byte));
+            format!("{:05}", value % 100_000)
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Returns a sequence of digit blocks to compare with a contact out of band,
+/// e.g. by reading it aloud during a call.
+///
+/// The blocks are derived from the own key and the contact's key, the contact computes
+/// the same blocks on their device if both sides use the keys the other side knows.
+/// If the blocks match, the contact can be verified with [mark_verified_manually].
+///
+/// Returns `None` if no key of the contact is known.
+pub async fn get_verification_words(context: &Context, contact_id: u32) -> Result<Option<String>> {
+    ensure!(
+        contact_id > DC_CONTACT_ID_LAST_SPECIAL,
+        "Can not verify special contact"
+    );
+    let contact = Contact::load_from_db(context, contact_id).await?;
+    let peer_fingerprint = match Peerstate::from_addr(context, &contact.addr)
+        .await?
+        .as_ref()
+        .and_then(verification_key)
+    {
+        Some((_, fingerprint)) => fingerprint,
+        None => return Ok(None),
+    };
+    let self_fingerprint = SignedPublicKey::load_self(context).await?.fingerprint();
+    Ok(Some(verification_words(
+        &self_fingerprint,
+        &peer_fingerprint,
+    )))
+}
+
+/// Marks the key of a contact as verified after comparing the `words`
+/// returned by [get_verification_words] out of band.
+///
+/// The change is recorded in the key history of the contact, see
+/// [crate::peerstate::get_history].  An accepted 1:1 chat with the contact is protected.
+///
+/// Fails if no key of the contact is known or if the key changed since `words` were
+/// returned, so that a key which was not compared is never verified.
+pub async fn mark_verified_manually(context: &Context, contact_id: u32, words: &str) -> Result<()> {
+    ensure!(
+        contact_id > DC_CONTACT_ID_LAST_SPECIAL,
+        "Can not verify special contact"
+    );
+    let contact = Contact::load_from_db(context, contact_id).await?;
+    let mut peerstate = Peerstate::from_addr(context, &contact.addr)
+        .await?
+        .with_context(|| format!("No key known for {}", contact.addr))?;
+    let (key_type, fingerprint) = verification_key(&peerstate)
+        .with_context(|| format!("No key known for {}", contact.addr))?;
+    let self_fingerprint = SignedPublicKey::load_self(context).await?.fingerprint();
+    ensure!(
+        verification_words(&self_fingerprint, &fingerprint) == words.trim(),
+        "Key of {} changed since the verification words were compared",
+        contact.addr
+    );
+    ensure!(
+        peerstate.set_verified(
+            key_type,
+            &fingerprint,
+            PeerstateVerifiedStatus::BidirectVerified
+        ),
+        "Can not verify key of {}",
+        contact.addr
+    );
+    peerstate.save_to_db(&context.sql, false).await?;
+    info!(
+        context,
+        "Verified key {} of contact {} manually.",
+        fingerprint.hex(),
+        contact_id
+    );
+
+    if let Ok((chat_id, blocked)) = chat::lookup_by_contact_id(context, contact_id).await {
+        let chat = Chat::load_from_db(context, chat_id).await?;
+        if blocked == Blocked::Not && !chat.is_protected() {
+            chat_id
+                .inner_set_protection(context, ProtectionStatus::Protected)
+                .await?;
+            chat_id
+                .add_protection_msg(
+                    context,
+                    ProtectionStatus::Protected,
+                    false,
+                    DC_CONTACT_ID_SELF,
+                )
+                .await?;
+        }
+    }
+    context.emit_event(EventType::ContactsChanged(Some(contact_id)));
+    Ok(())
+}
+
 /// Normalize a name.
 ///
 /// - Remove quotes (come from some bad MUA implementations)
@@ -1306,9 +1971,11 @@ pub async fn is_self_addr(&self, addr: &str) -> Result<bool> {
     }
 }
 
+/// Returns true if the addresses refer to the same mailbox,
+/// i.e. if they are equal after [normalize_addr] ignoring case.
 pub fn addr_cmp(addr1: impl AsRef<str>, addr2: impl AsRef<str>) -> bool {
-    let norm1 = addr_normalize(addr1.as_ref()).to_lowercase();
-    let norm2 = addr_normalize(addr2.as_ref()).to_lowercase();
+    let norm1 = normalize_addr(addr1.as_ref()).to_lowercase();
+    let norm2 = normalize_addr(addr2.as_ref()).to_lowercase();
 
     norm1 == norm2
 }
@@ -1333,7 +2000,9 @@ mod tests {
     use super::*;
 
     use crate::chat::send_text_msg;
-    use crate::test_utils::TestContext;
+    use crate::message::Message;
+    use crate::mimeparser::SystemMessage;
+    use crate::test_utils::{alice_keypair, bob_keypair, TestContext};
 
     #[test]
     fn test_may_be_valid_addr() {
@@ -1363,16 +2032,6 @@ fn test_normalize_name() {
         assert_eq!(&normalize_name("\""), "\"");
     }
 
-    #[test]
-    fn test_normalize_addr() {
-        assert_eq!(addr_normalize("mailto:john@doe.com"), "john@doe.com");
-        assert_eq!(addr_normalize("  hello@world.com   "), "hello@world.com");
-
-        // normalisation preserves case to allow user-defined spelling.
-        // however, case is ignored on addr_cmp()
-        assert_ne!(addr_normalize("John@Doe.com"), "john@doe.com");
-    }
-
     #[test]
     fn test_split_address_book() {
         let book = "Name one\nAddress one\nName two\nAddress two\nrest name";
@@ -1727,6 +2386,11 @@ fn test_addr_cmp() {
         assert!(addr_cmp("AA@AA.ORG", "aa@aa.ORG"));
         assert!(addr_cmp(" aa@aa.ORG ", "AA@AA.ORG"));
         assert!(addr_cmp(" mailto:AA@AA.ORG", "Aa@Aa.orG"));
+        assert!(addr_cmp(
+            "info@Bücher.example",
+            "INFO@xn--bcher-kva.example"
+        ));
+        assert!(!addr_cmp("info@bücher.example", "info@bucher.example"));
     }
 
     #[async_std::test]
@@ -1838,4 +2502,455 @@ async fn test_contact_get_encrinfo() -> Result<()> {
 
         Ok(())
     }
+
+    #[async_std::test]
+    async fn test_default_ephemeral_timer() -> Result<()> {
+        let t = TestContext::new_alice().await;
+        let timer = EphemeralTimer::Enabled { duration: 604800 };
+
+        let claire = Contact::create(&t, "Claire", "claire@example.org").await?;
+        let claire_chat = chat::create_by_contact_id(&t, claire).await?;
+        Contact::set_default_ephemeral_timer(&t, claire, timer).await?;
+        assert_eq!(
+            Contact::get_default_ephemeral_timer(&t, claire).await?,
+            timer
+        );
+        assert_eq!(
+            claire_chat.get_ephemeral_timer(&t).await?,
+            EphemeralTimer::Disabled
+        );
+
+        let bob = Contact::create(&t, "Bob", "bob@example.net").await?;
+        assert_eq!(
+            Contact::get_default_ephemeral_timer(&t, bob).await?,
+            EphemeralTimer::Disabled
+        );
+        Contact::set_default_ephemeral_timer(&t, bob, timer).await?;
+        let bob_chat = chat::create_by_contact_id(&t, bob).await?;
+        assert_eq!(bob_chat.get_ephemeral_timer(&t).await?, timer);
+
+        // Chats created without the user, e.g. for contact requests, inherit the timer as well.
+        let dave = Contact::create(&t, "Dave", "dave@example.net").await?;
+        Contact::set_default_ephemeral_timer(&t, dave, timer).await?;
+        let (dave_chat, _) =
+            chat::create_or_lookup_by_contact_id(&t, dave, Blocked::Deaddrop).await?;
+        assert_eq!(dave_chat.get_ephemeral_timer(&t).await?, timer);
+
+        // Creating the chat again does not send another timer change message.
+        assert_eq!(chat::create_by_contact_id(&t, bob).await?, bob_chat);
+        let mut timer_msgs = 0;
+        for item in chat::get_chat_msgs(&t, bob_chat, 0, None).await {
+            if let chat::ChatItem::Message { msg_id } = item {
+                let msg = Message::load_from_db(&t, msg_id).await?;
+                if msg.get_info_type() == SystemMessage::EphemeralTimerChanged {
+                    timer_msgs += 1;
+                }
+            }
+        }
+        assert_eq!(timer_msgs, 1);
+
+        assert!(
+            Contact::set_default_ephemeral_timer(&t, DC_CONTACT_ID_SELF, timer)
+                .await
+                .is_err()
+        );
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn test_import_vcard() -> Result<()> {
+        let t = TestContext::new_alice().await;
+        let import = import_vcard(&t, include_str!("../test-data/vcard/android.vcf")).await?;
+        let android = import.contact_ids;
+        // Alice has two addresses, the card without email address is skipped
+        assert_eq!(android.len(), 3);
+        assert_eq!(import.errors.len(), 1);
+        let error = import.errors.get(0).unwrap();
+        assert_eq!((error.card, error.addr.as_deref()), (3, None));
+
+        let import = import_vcard(&t, include_str!("../test-data/vcard/apple.vcf")).await?;
+        let apple = import.contact_ids;
+        // the invalid address and the card with a broken photo are skipped
+        assert_eq!(apple.len(), 2);
+        let errors: Vec<(usize, Option<&str>)> = import
+            .errors
+            .iter()
+            .map(|error| (error.card, error.addr.as_deref()))
+            .collect();
+        assert_eq!(errors, vec![(2, Some("not an address")), (3, None)]);
+
+        for (contact_id, name, addr) in &[
+            (android.get(0), "Alice Liddell", "alice@example.org"),
+            (android.get(1), "Alice Liddell", "alice.liddell@example.com"),
+            (android.get(2), "Jürgen Müller", "juergen@example.net"),
+            (apple.get(0), "White Rabbit", "rabbit@example.org"),
+            (apple.get(1), "Mad Hatter, Esq.", "hatter@example.com"),
+        ] {
+            let contact = Contact::load_from_db(&t, *contact_id.unwrap()).await?;
+            assert_eq!(contact.get_name(), *name);
+            assert_eq!(contact.get_addr(), *addr);
+            assert_eq!(contact.origin, Origin::AddressBook);
+        }
+
+        let alice = Contact::load_from_db(&t, *android.get(0).unwrap()).await?;
+        let avatar = alice.get_profile_image(&t).await.unwrap();
+        assert_eq!(
+            async_std::fs::read(avatar).await?,
+            include_bytes!("../test-data/image/image100x50.gif").to_vec()
+        );
+        let juergen = Contact::load_from_db(&t, *android.get(2).unwrap()).await?;
+        assert!(juergen.get_profile_image(&t).await.is_none());
+
+        // importing again does not create new contacts
+        let again = import_vcard(&t, include_str!("../test-data/vcard/android.vcf")).await?;
+        assert_eq!(again.contact_ids, android);
+
+        assert!(import_vcard(&t, "no vcard").await.is_err());
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn test_vcard_roundtrip() -> Result<()> {
+        let alice = TestContext::new_alice().await;
+        let contact_ids = import_vcard(&alice, include_str!("../test-data/vcard/apple.vcf"))
+            .await?
+            .contact_ids;
+        let nameless = Contact::create(&alice, "", "nameless@example.org").await?;
+        let mut exported_ids = contact_ids.clone();
+        exported_ids.push(nameless);
+        let vcard = make_vcard(&alice, &exported_ids).await?;
+
+        let bob = TestContext::new_bob().await;
+        let import = import_vcard(&bob, &vcard).await?;
+        assert!(import.errors.is_empty());
+        let imported_ids = import.contact_ids;
+        assert_eq!(imported_ids.len(), exported_ids.len());
+        for (exported_id, imported_id) in exported_ids.iter().zip(imported_ids.iter()) {
+            let exported = Contact::load_from_db(&alice, *exported_id).await?;
+            let imported = Contact::load_from_db(&bob, *imported_id).await?;
+            assert_eq!(exported.get_name(), imported.get_name());
+            assert_eq!(exported.get_addr(), imported.get_addr());
+            match exported.get_profile_image(&alice).await {
+                Some(avatar) => assert_eq!(
+                    async_std::fs::read(avatar).await?,
+                    async_std::fs::read(imported.get_profile_image(&bob).await.unwrap()).await?
+                ),
+                None => assert!(imported.get_profile_image(&bob).await.is_none()),
+            }
+        }
+        Ok(())
+    }
+
+    /// Inserts a contact without looking up existing ones, as old versions or imports did.
+    async fn insert_duplicate(t: &TestContext, addr: &str) -> Result<u32> {
+        t.sql
+            .execute(
+                "INSERT INTO contacts (name, addr, origin) VALUES ('', ?, ?);",
+                paramsv![addr, Origin::IncomingUnknownFrom],
+            )
+            .await?;
+        let id: Option<u32> = t
+            .sql
+            .query_get_value_result("SELECT MAX(id) FROM contacts;", paramsv![])
+            .await?;
+        Ok(id.unwrap_or_default())
+    }
+
+    #[async_std::test]
+    async fn test_merge_duplicates_in_group() -> Result<()> {
+        let t = TestContext::new_alice().await;
+        let bob = Contact::create(&t, "Bob", "bob@example.net").await?;
+        let bob2 = insert_duplicate(&t, "BOB@example.net").await?;
+        let bob3 = insert_duplicate(&t, " mailto:Bob@Example.net").await?;
+        let group = chat::create_group_chat(&t, chat::ProtectionStatus::Unprotected, "grp").await?;
+        chat::add_to_chat_contacts_table(&t, group, bob).await;
+        chat::add_to_chat_contacts_table(&t, group, bob2).await;
+        chat::add_to_chat_contacts_table(&t, group, bob3).await;
+
+        let duplicates = find_duplicates(&t).await?;
+        assert_eq!(duplicates, vec![vec![bob, bob2, bob3]]);
+
+        merge(&t, bob, &[bob2, bob3]).await?;
+        let mut members = chat::get_chat_contacts(&t, group).await;
+        members.sort_unstable();
+        assert_eq!(members, vec![DC_CONTACT_ID_SELF, bob]);
+        assert!(Contact::load_from_db(&t, bob2).await.is_err());
+        assert_eq!(
+            Contact::load_from_db(&t, bob).await?.origin,
+            Origin::ManuallyCreated
+        );
+
+        // Nothing left to merge.
+        assert!(find_duplicates(&t).await?.is_empty());
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn test_merge_single_chats() -> Result<()> {
+        let t = TestContext::new_alice().await;
+        let bob = Contact::create(&t, "Bob", "bob@example.net").await?;
+        let bob2 = insert_duplicate(&t, "BOB@example.net").await?;
+        let chat_id = chat::create_by_contact_id(&t, bob).await?;
+        let chat_id2 = chat::create_by_contact_id(&t, bob2).await?;
+        assert_ne!(chat_id, chat_id2);
+        let msg_id = t.send_text(chat_id2, "hi").await.sender_msg_id;
+
+        merge(&t, bob, &[bob2]).await?;
+        assert!(Chat::load_from_db(&t, chat_id2).await.is_err());
+        assert_eq!(chat::get_chat_contacts(&t, chat_id).await, vec![bob]);
+        assert_eq!(Message::load_from_db(&t, msg_id).await?.chat_id, chat_id);
+        assert_eq!(chat::lookup_by_contact_id(&t, bob).await?.0, chat_id);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn test_merge_keeps_verified_peerstate() -> Result<()> {
+        let t = TestContext::new_alice().await;
+        let bob = Contact::create(&t, "Bob", "bob@example.net").await?;
+        let bob2 = insert_duplicate(&t, "BOB@example.net").await?;
+
+        let key = crate::test_utils::bob_keypair().public;
+        let mut verified = Peerstate::from_header(
+            &crate::aheader::Aheader::new(
+                "BOB@example.net".to_string(),
+                key.clone(),
+                EncryptPreference::Mutual,
+            ),
+            10,
+        );
+        verified.set_verified(
+            crate::peerstate::PeerstateKeyType::PublicKey,
+            &key.fingerprint(),
+            PeerstateVerifiedStatus::BidirectVerified,
+        );
+        verified.save_to_db(&t.sql, true).await?;
+        let mut unverified = verified.clone();
+        unverified.addr = "bob@example.net".to_string();
+        unverified.last_seen = 20;
+        unverified.verified_key = None;
+        unverified.verified_key_fingerprint = None;
+        unverified.save_to_db(&t.sql, true).await?;
+
+        merge(&t, bob, &[bob2]).await?;
+        let count: i64 = t
+            .sql
+            .query_get_value_result("SELECT COUNT(*) FROM acpeerstates;", paramsv![])
+            .await?
+            .unwrap_or_default();
+        assert_eq!(count, 1);
+        let peerstate = Peerstate::from_addr(&t, "bob@example.net").await?.unwrap();
+        assert_eq!(peerstate.addr, "bob@example.net");
+        assert_eq!(peerstate.verified_key_fingerprint, Some(key.fingerprint()));
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn test_normalize_stored_addrs() -> Result<()> {
+        let t = TestContext::new_alice().await;
+        let bob = insert_duplicate(&t, "bob@example.net").await?;
+        let bob2 = insert_duplicate(&t, "bob@EXAMPLE.net").await?;
+        let info = insert_duplicate(&t, "info@Bücher.example").await?;
+
+        let key = crate::test_utils::bob_keypair().public;
+        for (addr, last_seen) in &[("bob@Example.NET", 10), ("Bob@example.net", 20)] {
+            let mut peerstate = Peerstate::from_header(
+                &crate::aheader::Aheader::new(
+                    addr.to_string(),
+                    key.clone(),
+                    EncryptPreference::Mutual,
+                ),
+                *last_seen,
+            );
+            // Simulate peerstates stored before addresses were normalized.
+            peerstate.addr = addr.to_string();
+            peerstate.save_to_db(&t.sql, true).await?;
+        }
+
+        normalize_stored_addrs(&t).await?;
+        assert!(find_duplicates(&t).await?.is_empty());
+        assert_eq!(
+            Contact::load_from_db(&t, bob).await?.addr,
+            "bob@example.net"
+        );
+        assert!(Contact::load_from_db(&t, bob2).await.is_err());
+        assert_eq!(
+            Contact::load_from_db(&t, info).await?.addr,
+            "info@xn--bcher-kva.example"
+        );
+
+        let count: i64 = t
+            .sql
+            .query_get_value_result("SELECT COUNT(*) FROM acpeerstates;", paramsv![])
+            .await?
+            .unwrap_or_default();
+        assert_eq!(count, 1);
+        let peerstate = Peerstate::from_addr(&t, "bob@example.net").await?.unwrap();
+        assert_eq!(peerstate.addr, "bob@example.net");
+        assert_eq!(peerstate.last_seen, 20);
+        Ok(())
+    }
+
+    /// Stores a peerstate for `addr` with `key`, as if a message with an Autocrypt header
+    /// was received.
+    async fn add_peerstate(t: &TestContext, addr: &str, key: &SignedPublicKey) -> Result<()> {
+        let header =
+            crate::aheader::Aheader::new(addr.to_string(), key.clone(), EncryptPreference::Mutual);
+        Peerstate::from_header(&header, time())
+            .save_to_db(&t.sql, true)
+            .await?;
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn test_verification_words_symmetric() -> Result<()> {
+        let alice_fp = alice_keypair().public.fingerprint();
+        let bob_fp = bob_keypair().public.fingerprint();
+        let words = verification_words(&alice_fp, &bob_fp);
+        assert_eq!(words, verification_words(&bob_fp, &alice_fp));
+        assert_ne!(words, verification_words(&alice_fp, &alice_fp));
+        let blocks: Vec<&str> = words.split(' ').collect();
+        assert_eq!(blocks.len(), VERIFICATION_BLOCKS);
+        assert!(blocks
+            .iter()
+            .all(|block| block.len() == 5 && block.chars().all(|c| c.is_ascii_digit())));
+
+        // Both sides compute the same words.
+        let alice = TestContext::new_alice().await;
+        let bob = TestContext::new_bob().await;
+        add_peerstate(&alice, "bob@example.net", &bob_keypair().public).await?;
+        add_peerstate(&bob, "alice@example.com", &alice_keypair().public).await?;
+        let bob_id = Contact::create(&alice, "", "bob@example.net").await?;
+        let alice_id = Contact::create(&bob, "", "alice@example.com").await?;
+        let alice_words = get_verification_words(&alice, bob_id).await?.unwrap();
+        let bob_words = get_verification_words(&bob, alice_id).await?.unwrap();
+        assert_eq!(alice_words, bob_words);
+        assert_eq!(alice_words, words);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn test_mark_verified_manually() -> Result<()> {
+        let t = TestContext::new_alice().await;
+        let key = bob_keypair().public;
+        add_peerstate(&t, "bob@example.net", &key).await?;
+        let bob = Contact::create(&t, "Bob", "bob@example.net").await?;
+        let chat_id = chat::create_by_contact_id(&t, bob).await?;
+        assert!(!Chat::load_from_db(&t, chat_id).await?.is_protected());
+        let words = get_verification_words(&t, bob).await?.unwrap();
+
+        // Words compared for another key do not verify the current one.
+        let alice_fp = alice_keypair().public.fingerprint();
+        let stale_words = verification_words(&alice_fp, &alice_fp);
+        assert!(mark_verified_manually(&t, bob, &stale_words).await.is_err());
+        assert_eq!(
+            Contact::load_from_db(&t, bob).await?.is_verified(&t).await,
+            VerifiedStatus::Unverified
+        );
+
+        mark_verified_manually(&t, bob, &words).await?;
+        let contact = Contact::load_from_db(&t, bob).await?;
+        assert_eq!(
+            contact.is_verified(&t).await,
+            VerifiedStatus::BidirectVerified
+        );
+        let peerstate = Peerstate::from_addr(&t, "bob@example.net").await?.unwrap();
+        assert_eq!(peerstate.verified_key_fingerprint, Some(key.fingerprint()));
+        assert!(Chat::load_from_db(&t, chat_id).await?.is_protected());
+
+        let history = crate::peerstate::get_history(&t, "bob@example.net").await?;
+        let entry = history.first().unwrap();
+        assert_eq!(entry.key, crate::peerstate::ChangedKey::VerifiedKey);
+        assert_eq!(entry.old_fingerprint, None);
+        assert_eq!(entry.new_fingerprint, Some(key.fingerprint()));
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn test_mark_verified_manually_without_key() -> Result<()> {
+        let t = TestContext::new_alice().await;
+        let claire = Contact::create(&t, "", "claire@example.org").await?;
+        assert_eq!(get_verification_words(&t, claire).await?, None);
+        assert!(mark_verified_manually(&t, claire, "").await.is_err());
+        assert_eq!(
+            Contact::load_from_db(&t, claire)
+                .await?
+                .is_verified(&t)
+                .await,
+            VerifiedStatus::Unverified
+        );
+        assert!(mark_verified_manually(&t, DC_CONTACT_ID_SELF, "")
+            .await
+            .is_err());
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn test_contact_color() -> Result<()> {
+        let t = TestContext::new_alice().await;
+        let fiona = Contact::create(&t, "", "Fiona@Example.NET").await?;
+        let contact = Contact::get_by_id(&t, fiona).await?;
+        // Same as for `fiona@example.net` in `test-data/color/vectors.txt`.
+        assert_eq!(contact.get_color(), 0xa76a00);
+        Ok(())
+    }
+
+    async fn last_seen(t: &TestContext, contact_id: u32) -> Option<i64> {
+        let contact = Contact::load_from_db(t, contact_id).await.unwrap();
+        contact.last_seen(t).await.unwrap()
+    }
+
+    #[async_std::test]
+    async fn test_last_seen() -> Result<()> {
+        let t = TestContext::new_alice().await;
+        let now = time();
+        let day = 24 * 60 * 60;
+        let bob = Contact::create(&t, "Bob", "bob@example.net").await?;
+        let claire = Contact::create(&t, "Claire", "claire@example.org").await?;
+        let dave = Contact::create(&t, "Dave", "dave@example.com").await?;
+
+        // Bob's peerstate is newer than his last message, for Claire it is the other way round.
+        for (contact_id, addr, msg_time, peerstate_time) in &[
+            (bob, "bob@example.net", now - 10 * day, now - 2 * day),
+            (claire, "Claire@Example.org", now - 3600, now - 30 * day),
+        ] {
+            t.sql
+                .execute(
+                    "INSERT INTO msgs (chat_id, from_id, timestamp) VALUES (?, ?, ?);",
+                    paramsv![
+                        chat::create_by_contact_id(&t, *contact_id).await?,
+                        *contact_id,
+                        *msg_time
+                    ],
+                )
+                .await?;
+            t.sql
+                .execute(
+                    "INSERT INTO acpeerstates (addr, last_seen) VALUES (?, ?);",
+                    paramsv![*addr, *peerstate_time],
+                )
+                .await?;
+        }
+
+        assert_eq!(last_seen(&t, bob).await, Some(now - 2 * day));
+        assert_eq!(last_seen(&t, claire).await, Some(now - 3600));
+        assert_eq!(last_seen(&t, dave).await, None);
+        assert_eq!(last_seen(&t, DC_CONTACT_ID_SELF).await, None);
+        assert_eq!(last_seen(&t, DC_CONTACT_ID_DEVICE).await, None);
+
+        assert_eq!(
+            Contact::get_filtered(&t, ContactFilter::ContactsRecentlyActive(1)).await?,
+            vec![claire]
+        );
+        assert_eq!(
+            Contact::get_filtered(&t, ContactFilter::ContactsRecentlyActive(7)).await?,
+            vec![claire, bob]
+        );
+
+        Contact::block(&t, claire).await;
+        assert_eq!(
+            Contact::get_filtered(&t, ContactFilter::ContactsRecentlyActive(7)).await?,
+            vec![bob]
+        );
+        Ok(())
+    }
 }
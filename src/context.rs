@@ -3,28 +3,33 @@
 use std::collections::{BTreeMap, HashMap};
 use std::ffi::OsString;
 use std::ops::Deref;
-use std::time::{Instant, SystemTime};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::{Duration, Instant, SystemTime};
 
-use anyhow::{bail, ensure, Result};
+use anyhow::{ensure, Result};
 use async_std::{
-    channel::{self, Receiver, Sender},
     path::{Path, PathBuf},
     sync::{Arc, Mutex, RwLock},
     task,
 };
 
-use crate::chat::{get_chat_cnt, ChatId};
+use crate::chat::{ChatId, ChatVisibility};
 use crate::config::Config;
 use crate::constants::DC_VERSION_STR;
-use crate::contact::Contact;
 use crate::dc_tools::{duration_to_str, time};
 use crate::events::{Event, EventEmitter, EventType, Events};
+use crate::job;
 use crate::key::{DcKey, SignedPublicKey};
+use crate::log_file::LogSink;
 use crate::login_param::LoginParam;
-use crate::message::{self, MessageState, MsgId};
-use crate::scheduler::Scheduler;
+use crate::message::{MessageState, MsgId};
+use crate::network::NetworkPolicy;
+use crate::ongoing::Ongoing;
+use crate::scheduler::{InterruptInfo, Scheduler};
 use crate::securejoin::Bob;
 use crate::sql::Sql;
+use crate::state_batch::{self, StateBatch};
+use crate::stats::StatsCache;
 
 #[derive(Clone, Debug)]
 pub struct Context {
@@ -45,11 +50,17 @@ pub struct InnerContext {
     pub(crate) dbfile: PathBuf,
     /// Blob directory path
     pub(crate) blobdir: PathBuf,
+    /// Whether files can be created in the blob directory,
+    /// see [Context::check_blobdir_writable].
+    blobdir_writable: AtomicBool,
     pub(crate) sql: Sql,
     pub(crate) os_name: Option<String>,
     pub(crate) bob: Bob,
     pub(crate) last_smeared_timestamp: RwLock<i64>,
-    pub(crate) running_state: RwLock<RunningState>,
+    /// Registry of the running long operations, see [crate::ongoing].
+    pub(crate) ongoing: Ongoing,
+    /// Cached counters reported by [Context::get_stats].
+    pub(crate) stats_cache: StatsCache,
     /// Mutex to avoid generating the key for the user more than once.
     pub(crate) generating_key_mutex: Mutex<()>,
     /// Mutex to enforce only a single running oauth2 is running.
@@ -61,23 +72,63 @@ pub struct InnerContext {
 
     pub(crate) scheduler: RwLock<Scheduler>,
     pub(crate) ephemeral_task: RwLock<Option<task::JoinHandle<()>>>,
+    /// Task unmuting chats, see [crate::ephemeral::schedule_unmute_task].
+    pub(crate) unmute_task: RwLock<Option<task::JoinHandle<()>>>,
+    /// Task deleting old messages, see [crate::ephemeral::schedule_device_deletion_task].
+    pub(crate) device_deletion_task: RwLock<Option<task::JoinHandle<()>>>,
+    /// Number of active [crate::ephemeral::DeletionHold]s.
+    pub(crate) deletion_holds: AtomicUsize,
+    /// Mutex to serialize starting and stopping IO.
+    pub(crate) io_mutex: Mutex<()>,
+    /// Number of running IO loop tasks.
+    pub(crate) io_loops: AtomicUsize,
+    /// Set by [Context::shutdown], no new messages are accepted for sending afterwards.
+    shutting_down: AtomicBool,
 
     pub(crate) last_full_folder_scan: Mutex<Option<Instant>>,
 
+    /// Wall clock time of the last clock jump check in this run and the monotonic time it
+    /// was taken at, see [crate::ephemeral::check_clock_jump].
+    pub(crate) last_clock_check: Mutex<Option<(i64, Instant)>>,
+
+    /// Last answered request for parts per message and requesting contact,
+    /// see [crate::chunks::handle_request].
+    pub(crate) chunk_requests: Mutex<HashMap<(MsgId, u32), Instant>>,
+
+    /// Message state changes not yet written, see [crate::state_batch].
+    pub(crate) state_batch: StateBatch,
+
+    /// ID of the job the SMTP thread is performing, see [crate::outbox].
+    ///
+    /// Held while the SMTP thread picks up a job
+    /// and while the outgoing queue is inspected or changed.
+    pub(crate) outbox_job: Mutex<Option<u32>>,
+
     /// ID for this `Context` in the current process.
     ///
     /// This allows for multiple `Context`s open in a single process where each context can
     /// be identified by this ID.
     pub(crate) id: u32,
 
+    /// Identity prefixed to log messages, so logs of multiple contexts can be told apart.
+    log_id: std::sync::RwLock<Option<String>>,
+
+    /// Writer of the log files, see [Context::set_log_file].
+    pub(crate) log_sink: std::sync::RwLock<Option<LogSink>>,
+
     creation_time: SystemTime,
 }
 
-#[derive(Debug)]
-pub struct RunningState {
-    pub ongoing_running: bool,
-    shall_stop_ongoing: bool,
-    cancel_sender: Option<Sender<()>>,
+/// Result of [Context::shutdown].
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ShutdownReport {
+    /// Number of queued messages that were not sent.
+    ///
+    /// They are sent when IO is started the next time.
+    pub unsent_msgs: usize,
+
+    /// Shutdown stopped waiting for messages to be sent.
+    pub timed_out: bool,
 }
 
 /// Return some info about deltachat-core
@@ -98,6 +149,19 @@ pub fn get_info() -> BTreeMap<&'static str, String> {
 impl Context {
     /// Creates new context.
     pub async fn new(os_name: String, dbfile: PathBuf, id: u32) -> Result<Context> {
+        Context::new_with_log_id(os_name, dbfile, id, None).await
+    }
+
+    /// Creates new context with a log id, see [Context::set_log_id].
+    ///
+    /// Unlike setting the log id afterwards, the messages logged while opening and
+    /// migrating the database are prefixed as well.
+    pub async fn new_with_log_id(
+        os_name: String,
+        dbfile: PathBuf,
+        id: u32,
+        log_id: Option<String>,
+    ) -> Result<Context> {
         // pretty_env_logger::try_init_timed().ok();
 
         let mut blob_fname = OsString::new();
@@ -107,7 +171,21 @@ pub async fn new(os_name: String, dbfile: PathBuf, id: u32) -> Result<Context> {
         if !blobdir.exists().await {
             async_std::fs::create_dir_all(&blobdir).await?;
         }
-        Context::with_blobdir(os_name, dbfile, blobdir, id).await
+        Context::with_blobdir(os_name, dbfile, blobdir, id, log_id).await
+    }
+
+    /// Opens an existing database read-only.
+    ///
+    /// No migrations are run and nothing is written to the database or the blob
+    /// directory; APIs that need to write fail with [`crate::sql::Error::ReadOnly`].
+    pub async fn new_readonly(os_name: String, dbfile: PathBuf) -> Result<Context> {
+        ensure!(
+            dbfile.is_file().await,
+            "Database does not exist: {}",
+            dbfile.display()
+        );
+        let blobdir = Context::derive_blobdir(&dbfile);
+        Context::open(os_name, dbfile, blobdir, 0, None, true).await
     }
 
     pub(crate) async fn with_blobdir(
@@ -115,19 +193,32 @@ pub(crate) async fn with_blobdir(
         dbfile: PathBuf,
         blobdir: PathBuf,
         id: u32,
+        log_id: Option<String>,
     ) -> Result<Context> {
         ensure!(
             blobdir.is_dir().await,
             "Blobdir does not exist: {}",
             blobdir.display()
         );
+        Context::open(os_name, dbfile, blobdir, id, log_id, false).await
+    }
 
+    async fn open(
+        os_name: String,
+        dbfile: PathBuf,
+        blobdir: PathBuf,
+        id: u32,
+        log_id: Option<String>,
+        readonly: bool,
+    ) -> Result<Context> {
         let inner = InnerContext {
             id,
             blobdir,
+            blobdir_writable: AtomicBool::new(true),
             dbfile,
             os_name: Some(os_name),
-            running_state: RwLock::new(Default::default()),
+            ongoing: Ongoing::default(),
+            stats_cache: StatsCache::default(),
             sql: Sql::new(),
             bob: Default::default(),
             last_smeared_timestamp: RwLock::new(0),
@@ -138,37 +229,132 @@ pub(crate) async fn with_blobdir(
             events: Events::default(),
             scheduler: RwLock::new(Scheduler::Stopped),
             ephemeral_task: RwLock::new(None),
+            unmute_task: RwLock::new(None),
+            device_deletion_task: RwLock::new(None),
+            deletion_holds: AtomicUsize::new(0),
+            io_mutex: Mutex::new(()),
+            io_loops: AtomicUsize::new(0),
+            shutting_down: AtomicBool::new(false),
+            log_id: std::sync::RwLock::new(log_id),
+            log_sink: std::sync::RwLock::new(None),
             creation_time: std::time::SystemTime::now(),
             last_full_folder_scan: Mutex::new(None),
+            last_clock_check: Mutex::new(None),
+            chunk_requests: Mutex::new(HashMap::new()),
+            state_batch: StateBatch::default(),
+            outbox_job: Mutex::new(None),
         };
 
         let ctx = Context {
             inner: Arc::new(inner),
         };
-        ctx.sql.open(&ctx, &ctx.dbfile, false).await?;
+        ctx.sql.open(&ctx, &ctx.dbfile, readonly).await?;
+        if readonly {
+            ctx.blobdir_writable.store(false, Ordering::Relaxed);
+        } else {
+            ctx.check_blobdir_writable().await;
+        }
 
         Ok(ctx)
     }
 
     /// Starts the IO scheduler.
+    ///
+    /// Does nothing if IO is already running.
     pub async fn start_io(&self) {
+        let _io_lock = self.inner.io_mutex.lock().await;
+        self.inner_start_io().await;
+    }
+
+    async fn inner_start_io(&self) {
         info!(self, "starting IO");
-        if self.inner.is_io_running().await {
+        crate::ephemeral::check_clock_jump(self).await;
+        crate::ephemeral::schedule_unmute_task(self).await;
+        crate::ephemeral::schedule_device_deletion_task(self).await;
+
+        let l = &mut *self.inner.scheduler.write().await;
+        if l.is_running() {
             info!(self, "IO is already running");
             return;
         }
-
-        {
-            let l = &mut *self.inner.scheduler.write().await;
-            l.start(self.clone()).await;
-        }
+        l.start(self.clone()).await;
     }
 
     /// Stops the IO scheduler.
+    ///
+    /// Returns once all IO tasks have finished. Does nothing if IO is not running.
     pub async fn stop_io(&self) {
+        let _io_lock = self.inner.io_mutex.lock().await;
         info!(self, "stopping IO");
 
         self.inner.stop_io().await;
+
+        if let Err(err) = state_batch::flush(self).await {
+            warn!(self, "Failed to write message states: {}", err);
+        }
+    }
+
+    /// Shuts the context down.
+    ///
+    /// New messages are not accepted for sending anymore. If IO is running,
+    /// queued messages are sent until none are left or `timeout` has passed.
+    /// Then IO is stopped and the database is checkpointed and closed.
+    pub async fn shutdown(&self, timeout: Duration) -> ShutdownReport {
+        info!(self, "shutting down");
+        self.shutting_down.store(true, Ordering::SeqCst);
+
+        let start = Instant::now();
+        let mut report = ShutdownReport::default();
+        loop {
+            report.unsent_msgs = match job::count_unsent_msgs(self).await {
+                Ok(count) => count,
+                Err(err) => {
+                    warn!(self, "Failed to count unsent messages: {}", err);
+                    0
+                }
+            };
+            if report.unsent_msgs == 0 || !self.is_io_running().await {
+                break;
+            }
+            if start.elapsed() >= timeout {
+                report.timed_out = true;
+                break;
+            }
+            self.interrupt_smtp(InterruptInfo::new(false, None)).await;
+            task::sleep(Duration::from_millis(100)).await;
+        }
+
+        self.stop_io().await;
+        if let Err(err) = self.sql.checkpoint().await {
+            warn!(self, "Failed to checkpoint database: {}", err);
+        }
+        self.sql.close().await;
+        info!(
+            self,
+            "shut down, {} messages left unsent", report.unsent_msgs
+        );
+        report
+    }
+
+    /// Returns true if [Context::shutdown] was called.
+    pub fn is_shutting_down(&self) -> bool {
+        self.shutting_down.load(Ordering::SeqCst)
+    }
+
+    /// Returns true if the IO scheduler is running.
+    pub async fn is_io_running(&self) -> bool {
+        self.inner.is_io_running().await
+    }
+
+    /// Restarts the IO scheduler if it is running,
+    /// e.g. to apply changed settings to the connections.
+    pub async fn restart_io_if_running(&self) {
+        let _io_lock = self.inner.io_mutex.lock().await;
+        if self.inner.is_io_running().await {
+            info!(self, "restarting IO");
+            self.inner.stop_io().await;
+            self.inner_start_io().await;
+        }
     }
 
     /// Returns a reference to the underlying SQL instance.
@@ -189,12 +375,66 @@ pub fn get_blobdir(&self) -> &Path {
         self.blobdir.as_path()
     }
 
+    /// Returns whether files can be created in the blob directory.
+    ///
+    /// If not, e.g. after the storage permissions of the app were changed,
+    /// received attachments cannot be saved.
+    pub fn get_blobdir_writable(&self) -> bool {
+        self.blobdir_writable.load(Ordering::Relaxed)
+    }
+
+    /// Checks whether files can be created in the blob directory by creating a probe file.
+    ///
+    /// Emits a single error event when the blob directory becomes read-only.
+    pub(crate) async fn check_blobdir_writable(&self) -> bool {
+        let probe = self.blobdir.join(".writable-probe");
+        let writable = match async_std::fs::File::create(&probe).await {
+            Ok(file) => {
+                drop(file);
+                async_std::fs::remove_file(&probe).await.ok();
+                true
+            }
+            Err(_) => false,
+        };
+        let was_writable = self.blobdir_writable.swap(writable, Ordering::Relaxed);
+        if was_writable && !writable {
+            self.emit_event(EventType::Error(format!(
+                "The directory {} is read-only, received attachments cannot be saved. \
+                 Please check the storage permissions of the app.",
+                self.blobdir.display()
+            )));
+        }
+        writable
+    }
+
     /// Emits a single event.
+    ///
+    /// Info and warning messages are prefixed with the log id, if one is set.  Errors are
+    /// shown to the user and are left alone, [Event::id] tells the context.
     pub fn emit_event(&self, event: EventType) {
-        self.events.emit(Event {
-            id: self.id,
-            typ: event,
-        });
+        let typ = match self.get_log_id() {
+            Some(log_id) => match event {
+                EventType::Info(msg) => EventType::Info(format!("[{}] {}", log_id, msg)),
+                EventType::Warning(msg) => EventType::Warning(format!("[{}] {}", log_id, msg)),
+                event => event,
+            },
+            None => event,
+        };
+        if let Some(sink) = &*self.log_sink.read().unwrap() {
+            sink.log(&typ);
+        }
+        self.ongoing.update_progress(&typ);
+        self.events.emit(Event { id: self.id, typ });
+    }
+
+    /// Sets the identity prefixed to the log messages of this context.
+    pub fn set_log_id(&self, log_id: String) {
+        *self.log_id.write().unwrap() = Some(log_id);
+    }
+
+    /// Returns the identity prefixed to the log messages of this context.
+    pub fn get_log_id(&self) -> Option<String> {
+        self.log_id.read().unwrap().clone()
     }
 
     /// Returns a receiver for emitted events.
@@ -210,60 +450,32 @@ pub fn get_id(&self) -> u32 {
         self.id
     }
 
-    // Ongoing process allocation/free/check
-
-    pub async fn alloc_ongoing(&self) -> Result<Receiver<()>> {
-        if self.has_ongoing().await {
-            bail!("There is already another ongoing process running.");
+    /// Signals the running exclusive operation to stop, see [Context::cancel_operation].
+    ///
+    /// Processes such as [`imex`](crate::imex::imex) are aborted at their next
+    /// suspension point, clean up after themselves and report failure.
+    pub async fn stop_ongoing_process(&self) {
+        let running = self.get_running_operations();
+        match running.iter().find(|op| op.kind.is_exclusive()) {
+            Some(op) => {
+                self.cancel_operation(op.kind);
+            }
+            None => info!(self, "No ongoing process to stop."),
         }
-
-        let s_a = &self.running_state;
-        let mut s = s_a.write().await;
-
-        s.ongoing_running = true;
-        s.shall_stop_ongoing = false;
-        let (sender, receiver) = channel::bounded(1);
-        s.cancel_sender = Some(sender);
-
-        Ok(receiver)
-    }
-
-    pub async fn free_ongoing(&self) {
-        let s_a = &self.running_state;
-        let mut s = s_a.write().await;
-
-        s.ongoing_running = false;
-        s.shall_stop_ongoing = true;
-        s.cancel_sender.take();
     }
 
-    pub async fn has_ongoing(&self) -> bool {
-        let s_a = &self.running_state;
-        let s = s_a.read().await;
-
-        s.ongoing_running || !s.shall_stop_ongoing
-    }
-
-    /// Signal an ongoing process to stop.
+    /// Signals the running exclusive operation to stop.
+    #[deprecated(note = "use `stop_ongoing_process()` instead")]
     pub async fn stop_ongoing(&self) {
-        let s_a = &self.running_state;
-        let mut s = s_a.write().await;
-        if let Some(cancel) = s.cancel_sender.take() {
-            if let Err(err) = cancel.send(()).await {
-                warn!(self, "could not cancel ongoing: {:?}", err);
-            }
-        }
-
-        if s.ongoing_running && !s.shall_stop_ongoing {
-            info!(self, "Signaling the ongoing process to stop ASAP.",);
-            s.shall_stop_ongoing = true;
-        } else {
-            info!(self, "No ongoing process to stop.",);
-        };
+        self.stop_ongoing_process().await
     }
 
-    pub async fn shall_stop_ongoing(&self) -> bool {
-        self.running_state.read().await.shall_stop_ongoing
+    /// Drops the chats and contacts kept in memory.
+    ///
+    /// Meant to be called when the operating system asks to release memory,
+    /// the caches are filled again as chats and contacts are loaded.
+    pub fn clear_caches(&self) {
+        self.sql.clear_caches();
     }
 
     /*******************************************************************************
@@ -275,10 +487,13 @@ pub async fn get_info(&self) -> BTreeMap<&'static str, String> {
         let l = LoginParam::from_database(self, "").await;
         let l2 = LoginParam::from_database(self, "configured_").await;
         let displayname = self.get_config(Config::Displayname).await;
-        let chats = get_chat_cnt(self).await as usize;
-        let real_msgs = message::get_real_msg_cnt(self).await as usize;
-        let deaddrop_msgs = message::get_deaddrop_msg_cnt(self).await as usize;
-        let contacts = Contact::get_real_cnt(self).await as usize;
+        let stats = match self.get_stats().await {
+            Ok(stats) => stats,
+            Err(err) => {
+                warn!(self, "Failed to get stats: {:#}", err);
+                Default::default()
+            }
+        };
         let is_configured = self.get_config_int(Config::Configured).await;
         let dbversion = self
             .sql
@@ -332,10 +547,19 @@ pub async fn get_info(&self) -> BTreeMap<&'static str, String> {
 
         // insert values
         res.insert("bot", self.get_config_int(Config::Bot).await.to_string());
-        res.insert("number_of_chats", chats.to_string());
-        res.insert("number_of_chat_messages", real_msgs.to_string());
-        res.insert("messages_in_contact_requests", deaddrop_msgs.to_string());
-        res.insert("number_of_contacts", contacts.to_string());
+        res.insert("number_of_chats", stats.chats.to_string());
+        res.insert("number_of_chat_messages", stats.messages.to_string());
+        res.insert(
+            "messages_in_contact_requests",
+            stats.deaddrop_messages.to_string(),
+        );
+        res.insert("number_of_contacts", stats.contacts.to_string());
+        res.insert(
+            "messages_per_day",
+            format!("{:.1}", stats.average_messages_per_day()),
+        );
+        res.insert("number_of_blobs", stats.blobs.to_string());
+        res.insert("blobdir_bytes", stats.blob_bytes.to_string());
         res.insert("database_dir", self.get_dbfile().display().to_string());
         res.insert("database_version", dbversion.to_string());
         res.insert("journal_mode", journal_mode);
@@ -350,6 +574,10 @@ pub async fn get_info(&self) -> BTreeMap<&'static str, String> {
         res.insert("is_configured", is_configured.to_string());
         res.insert("entered_account_settings", l.to_string());
         res.insert("used_account_settings", l2.to_string());
+        res.insert(
+            "network_policy",
+            NetworkPolicy::from_database(self).await.to_string(),
+        );
         res.insert(
             "fetch_existing_msgs",
             self.get_config_int(Config::FetchExistingMsgs)
@@ -394,6 +622,12 @@ pub async fn get_info(&self) -> BTreeMap<&'static str, String> {
             "media_quality",
             self.get_config_int(Config::MediaQuality).await.to_string(),
         );
+        res.insert(
+            "notification_privacy",
+            self.get_config_int(Config::NotificationPrivacy)
+                .await
+                .to_string(),
+        );
         res.insert(
             "delete_device_after",
             self.get_config_int(Config::DeleteDeviceAfter)
@@ -406,6 +640,65 @@ pub async fn get_info(&self) -> BTreeMap<&'static str, String> {
                 .await
                 .to_string(),
         );
+        res.insert(
+            "delete_on_server_when_deleting_locally",
+            self.get_config_int(Config::DeleteOnServerWhenDeletingLocally)
+                .await
+                .to_string(),
+        );
+        res.insert(
+            "download_limit",
+            self.get_config_int(Config::DownloadLimit).await.to_string(),
+        );
+        res.insert(
+            "max_message_size",
+            self.get_config_int(Config::MaxMessageSize)
+                .await
+                .to_string(),
+        );
+        res.insert(
+            "socks5_enabled",
+            self.get_config_int(Config::Socks5Enabled).await.to_string(),
+        );
+        res.insert(
+            "socks5_host",
+            self.get_config(Config::Socks5Host)
+                .await
+                .unwrap_or_else(|| "<unset>".to_string()),
+        );
+        res.insert(
+            "socks5_port",
+            self.get_config_int(Config::Socks5Port).await.to_string(),
+        );
+        let cache_stats = self.sql.cache_stats();
+        res.insert(
+            "cache_size",
+            self.get_config_int(Config::CacheSize).await.to_string(),
+        );
+        res.insert(
+            "cached_chats_contacts",
+            format!("{}/{}", cache_stats.chats, cache_stats.contacts),
+        );
+        res.insert(
+            "cache_hits_misses",
+            format!("{}/{}", cache_stats.hits, cache_stats.misses),
+        );
+        res.insert(
+            "mid_checks_filtered_queried",
+            format!("{}/{}", cache_stats.mids_filtered, cache_stats.mids_queried),
+        );
+        res.insert(
+            "quota",
+            match self.get_quota_info().await {
+                Some(quota) => quota
+                    .resources
+                    .iter()
+                    .map(|r| format!("{} {}: {}/{}", r.root, r.name, r.usage, r.limit))
+                    .collect::<Vec<_>>()
+                    .join(", "),
+                None => "<unset>".to_string(),
+            },
+        );
         res.insert(
             "last_housekeeping",
             self.get_config_int(Config::LastHousekeeping)
@@ -431,6 +724,8 @@ pub async fn get_info(&self) -> BTreeMap<&'static str, String> {
     /// and is typically used to show notifications.
     /// Moreover, the number of returned messages
     /// can be used for a badge counter on the app icon.
+    /// Messages in chats archived with [ChatVisibility::NoUnarchive]
+    /// are only returned if the `badge_no_unarchive` config is set.
     pub async fn get_fresh_msgs(&self) -> Result<Vec<MsgId>> {
         let ret = self
             .sql
@@ -448,9 +743,15 @@ pub async fn get_fresh_msgs(&self) -> Result<Vec<MsgId>> {
                     "   AND ct.blocked=0",
                     "   AND c.blocked=0",
                     "   AND NOT(c.muted_until=-1 OR c.muted_until>?)",
+                    "   AND (c.archived!=? OR ?)",
                     " ORDER BY m.timestamp DESC,m.id DESC;"
                 ),
-                paramsv![MessageState::InFresh, time()],
+                paramsv![
+                    MessageState::InFresh,
+                    time(),
+                    ChatVisibility::NoUnarchive,
+                    self.get_config_bool(Config::BadgeNoUnarchive).await
+                ],
                 |row| row.get::<_, MsgId>(0),
                 |rows| {
                     let mut ret = Vec::new();
@@ -575,15 +876,11 @@ async fn stop_io(&self) {
         if let Some(ephemeral_task) = self.ephemeral_task.write().await.take() {
             ephemeral_task.cancel().await;
         }
-    }
-}
-
-impl Default for RunningState {
-    fn default() -> Self {
-        RunningState {
-            ongoing_running: false,
-            shall_stop_ongoing: true,
-            cancel_sender: None,
+        if let Some(unmute_task) = self.unmute_task.write().await.take() {
+            unmute_task.cancel().await;
+        }
+        if let Some(device_deletion_task) = self.device_deletion_task.write().await.take() {
+            device_deletion_task.cancel().await;
         }
     }
 }
@@ -597,8 +894,11 @@ mod tests {
     use super::*;
 
     use crate::chat::{get_chat_contacts, get_chat_msgs, set_muted, Chat, MuteDuration};
+    use crate::constants::Viewtype;
+    use crate::contact::Contact;
     use crate::dc_receive_imf::dc_receive_imf;
     use crate::dc_tools::dc_create_outgoing_rfc724_mid;
+    use crate::message::Message;
     use crate::test_utils::TestContext;
     use std::time::Duration;
     use strum::IntoEnumIterator;
@@ -788,7 +1088,7 @@ async fn test_with_empty_blobdir() {
         let tmp = tempfile::tempdir().unwrap();
         let dbfile = tmp.path().join("db.sqlite");
         let blobdir = PathBuf::new();
-        let res = Context::with_blobdir("FakeOS".into(), dbfile.into(), blobdir, 1).await;
+        let res = Context::with_blobdir("FakeOS".into(), dbfile.into(), blobdir, 1, None).await;
         assert!(res.is_err());
     }
 
@@ -797,7 +1097,8 @@ async fn test_with_blobdir_not_exists() {
         let tmp = tempfile::tempdir().unwrap();
         let dbfile = tmp.path().join("db.sqlite");
         let blobdir = tmp.path().join("blobs");
-        let res = Context::with_blobdir("FakeOS".into(), dbfile.into(), blobdir.into(), 1).await;
+        let res =
+            Context::with_blobdir("FakeOS".into(), dbfile.into(), blobdir.into(), 1, None).await;
         assert!(res.is_err());
     }
 
@@ -865,4 +1166,292 @@ async fn test_get_info_completeness() {
             }
         }
     }
+
+    #[async_std::test]
+    async fn test_start_stop_io_idempotent() {
+        let t = TestContext::new().await;
+        assert!(!t.is_io_running().await);
+        t.stop_io().await;
+        t.restart_io_if_running().await;
+        assert!(!t.is_io_running().await);
+
+        t.start_io().await;
+        assert!(t.is_io_running().await);
+        let loops = t.io_loops.load(Ordering::SeqCst);
+        assert!(loops > 0);
+
+        t.start_io().await;
+        assert!(t.is_io_running().await);
+        assert_eq!(t.io_loops.load(Ordering::SeqCst), loops);
+
+        t.restart_io_if_running().await;
+        assert!(t.is_io_running().await);
+        assert_eq!(t.io_loops.load(Ordering::SeqCst), loops);
+
+        t.stop_io().await;
+        assert!(!t.is_io_running().await);
+        assert_eq!(t.io_loops.load(Ordering::SeqCst), 0);
+        t.stop_io().await;
+        assert!(!t.is_io_running().await);
+    }
+
+    #[async_std::test]
+    async fn test_new_readonly() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dbfile: PathBuf = tmp.path().join("db.sqlite").into();
+        assert!(Context::new_readonly("FakeOS".into(), dbfile.clone())
+            .await
+            .is_err());
+
+        let ctx = Context::new("FakeOS".into(), dbfile.clone(), 1)
+            .await
+            .unwrap();
+        let contact_id = Contact::create(&ctx, "Bob", "bob@example.net")
+            .await
+            .unwrap();
+        let chat_id = crate::chat::create_by_contact_id(&ctx, contact_id)
+            .await
+            .unwrap();
+        crate::chat::add_info_msg(&ctx, chat_id, "hello").await;
+        ctx.sql.close().await;
+        drop(ctx);
+        let mtime = std::fs::metadata(&dbfile).unwrap().modified().unwrap();
+
+        let ctx = Context::new_readonly("FakeOS".into(), dbfile.clone())
+            .await
+            .unwrap();
+        assert!(ctx.sql.is_readonly());
+        let chats = crate::chatlist::Chatlist::try_load(&ctx, 0, None, None)
+            .await
+            .unwrap();
+        assert_eq!(chats.len(), 1);
+        assert_eq!(get_chat_msgs(&ctx, chat_id, 0, None).await.len(), 1);
+
+        let err = ctx
+            .sql
+            .set_raw_config(&ctx, "foo", Some("bar"))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, crate::sql::Error::ReadOnly), "{:?}", err);
+        ctx.sql.close().await;
+        assert_eq!(
+            std::fs::metadata(&dbfile).unwrap().modified().unwrap(),
+            mtime
+        );
+    }
+
+    #[async_std::test]
+    async fn test_log_id() {
+        let tmp = tempfile::tempdir().unwrap();
+        let ctx1 = Context::new_with_log_id(
+            "FakeOS".into(),
+            tmp.path().join("db1.sqlite").into(),
+            1,
+            Some("first".to_string()),
+        )
+        .await
+        .unwrap();
+        let ctx2 = Context::new("FakeOS".into(), tmp.path().join("db2.sqlite").into(), 2)
+            .await
+            .unwrap();
+        ctx2.set_log_id("second".to_string());
+        assert_eq!(ctx1.get_log_id(), Some("first".to_string()));
+
+        let events1 = ctx1.get_event_emitter();
+        let events2 = ctx2.get_event_emitter();
+
+        // Messages logged while opening the database carry the log id given at construction.
+        let msg = loop {
+            if let EventType::Info(msg) = events1.recv().await.unwrap().typ {
+                break msg;
+            }
+        };
+        assert!(msg.starts_with("[first] "), "{}", msg);
+
+        info!(ctx1, "hello");
+        warn!(ctx2, "world");
+        error!(ctx2, "shown to the user");
+        ctx2.emit_event(EventType::MsgsNoticed(ChatId::new(10)));
+
+        // Skip the events emitted while opening the database.
+        let msg = loop {
+            let event = events1.recv().await.unwrap();
+            assert_eq!(event.id, 1);
+            if let EventType::Info(msg) = event.typ {
+                if msg.ends_with("hello") {
+                    break msg;
+                }
+            }
+        };
+        assert!(msg.starts_with("[first] "), "{}", msg);
+
+        let msg = loop {
+            let event = events2.recv().await.unwrap();
+            assert_eq!(event.id, 2);
+            if let EventType::Warning(msg) = event.typ {
+                if msg.ends_with("world") {
+                    break msg;
+                }
+            }
+        };
+        assert!(msg.starts_with("[second] "), "{}", msg);
+        let event = events2.recv().await.unwrap();
+        assert_eq!(event.typ, EventType::Error("shown to the user".to_string()));
+        let event = events2.recv().await.unwrap();
+        assert_eq!(event.typ, EventType::MsgsNoticed(ChatId::new(10)));
+    }
+
+    #[async_std::test]
+    async fn test_shutdown() {
+        let t = TestContext::new_alice().await;
+        let chat = t.create_chat_with_contact("bob", "bob@example.net").await;
+        let mut msg = Message::new(Viewtype::Text);
+        msg.set_text(Some("hi".to_string()));
+        crate::chat::send_msg(&t, chat.id, &mut msg).await.unwrap();
+
+        // IO is not running, so nothing can be sent and shutdown does not wait.
+        let start = Instant::now();
+        let report = t.shutdown(Duration::from_secs(60)).await;
+        assert!(start.elapsed() < Duration::from_secs(30));
+        assert_eq!(
+            report,
+            ShutdownReport {
+                unsent_msgs: 1,
+                timed_out: false
+            }
+        );
+        assert!(t.is_shutting_down());
+        assert!(!t.sql.is_open().await);
+
+        let mut wal = t.get_dbfile().as_os_str().to_owned();
+        wal.push("-wal");
+        assert!(!PathBuf::from(wal).exists().await);
+
+        let mut msg = Message::new(Viewtype::Text);
+        assert!(crate::chat::send_msg(&t, chat.id, &mut msg).await.is_err());
+    }
+
+    #[async_std::test]
+    async fn test_shutdown_drains_jobs() {
+        let t = TestContext::new_alice().await;
+        let chat = t.create_chat_with_contact("bob", "bob@example.net").await;
+        let mut msg = Message::new(Viewtype::Text);
+        msg.set_text(Some("hi".to_string()));
+        crate::chat::send_msg(&t, chat.id, &mut msg).await.unwrap();
+        assert_eq!(job::count_unsent_msgs(&t).await.unwrap(), 1);
+
+        // There is no server to send to, so the job is removed as if it was sent with a delay.
+        t.start_io().await;
+        let sender = {
+            let context = Context::clone(&t);
+            task::spawn(async move {
+                task::sleep(Duration::from_millis(500)).await;
+                context
+                    .sql
+                    .execute(
+                        "DELETE FROM jobs WHERE action=?;",
+                        paramsv![job::Action::SendMsgToSmtp],
+                    )
+                    .await
+                    .unwrap();
+            })
+        };
+
+        let start = Instant::now();
+        let report = t.shutdown(Duration::from_secs(60)).await;
+        sender.await;
+        assert!(start.elapsed() >= Duration::from_millis(500));
+        assert_eq!(
+            report,
+            ShutdownReport {
+                unsent_msgs: 0,
+                timed_out: false
+            }
+        );
+        assert!(!t.is_io_running().await);
+        assert!(!t.sql.is_open().await);
+    }
+
+    #[async_std::test]
+    async fn test_shutdown_timeout() {
+        let t = TestContext::new_alice().await;
+        let chat = t.create_chat_with_contact("bob", "bob@example.net").await;
+        let mut msg = Message::new(Viewtype::Text);
+        msg.set_text(Some("hi".to_string()));
+        crate::chat::send_msg(&t, chat.id, &mut msg).await.unwrap();
+
+        t.start_io().await;
+        let report = t.shutdown(Duration::from_millis(300)).await;
+        assert_eq!(
+            report,
+            ShutdownReport {
+                unsent_msgs: 1,
+                timed_out: true
+            }
+        );
+        assert!(!t.is_io_running().await);
+    }
+
+    #[cfg(unix)]
+    #[async_std::test]
+    async fn test_blobdir_readonly() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let t = TestContext::new_alice().await;
+        assert!(t.get_blobdir_writable());
+        t.set_config(Config::ShowEmails, Some("2")).await.unwrap();
+
+        let (event_tx, event_rx) = async_std::channel::unbounded();
+        t.add_event_sink(move |event: Event| {
+            let event_tx = event_tx.clone();
+            async move {
+                if let EventType::Error(msg) = event.typ {
+                    event_tx.try_send(msg).unwrap();
+                }
+            }
+        })
+        .await;
+
+        let set_mode = |mode| {
+            std::fs::set_permissions(t.get_blobdir(), std::fs::Permissions::from_mode(mode))
+                .unwrap()
+        };
+        set_mode(0o555);
+        if std::fs::write(t.get_blobdir().join("probe"), b"").is_ok() {
+            // Permissions are not enforced, e.g. when running as root.
+            set_mode(0o755);
+            return;
+        }
+
+        assert!(!t.check_blobdir_writable().await);
+        assert!(!t.get_blobdir_writable());
+        assert!(!t.check_blobdir_writable().await);
+        let report = crate::sql::housekeeping(&t).await.unwrap();
+        assert!(report.skipped_file_deletion);
+        assert_eq!(report.deleted_files, 0);
+
+        dc_receive_imf(
+            &t,
+            include_bytes!("../test-data/message/pdf_filename_simple.eml"),
+            "INBOX",
+            1,
+            false,
+        )
+        .await
+        .unwrap();
+        let msg = t.get_last_msg().await;
+        assert_eq!(msg.get_viewtype(), Viewtype::File);
+        assert!(msg.get_file(&t).is_none());
+        assert!(msg.is_media_not_saved());
+
+        set_mode(0o755);
+        assert!(t.check_blobdir_writable().await);
+        let report = crate::sql::housekeeping(&t).await.unwrap();
+        assert!(!report.skipped_file_deletion);
+
+        // The error is reported only once.
+        t.emit_event(EventType::Error("end of test".to_string()));
+        assert!(event_rx.recv().await.unwrap().contains("read-only"));
+        assert_eq!(event_rx.recv().await.unwrap(), "end of test");
+    }
 }
@@ -7,33 +7,43 @@
 use sha2::{Digest, Sha256};
 
 use crate::chat::{self, Chat, ChatId, ProtectionStatus};
+use crate::chunks;
 use crate::config::Config;
 use crate::constants::{
     Blocked, Chattype, ShowEmails, Viewtype, DC_CHAT_ID_TRASH, DC_CONTACT_ID_LAST_SPECIAL,
-    DC_CONTACT_ID_SELF,
+    DC_CONTACT_ID_SELF, DC_CONTACT_ID_UNDEFINED,
 };
 use crate::contact::{addr_cmp, normalize_name, Contact, Origin, VerifiedStatus};
 use crate::context::Context;
 use crate::dc_tools::{
     dc_create_smeared_timestamp, dc_extract_grpid_from_rfc724_mid, dc_smeared_time, time,
 };
+use crate::download::DownloadState;
 use crate::ephemeral::{stock_ephemeral_timer_changed, Timer as EphemeralTimer};
 use crate::events::EventType;
 use crate::headerdef::{HeaderDef, HeaderDefMap};
 use crate::job::{self, Action};
 use crate::message::{self, rfc724_mid_exists, Message, MessageState, MessengerMessage, MsgId};
 use crate::mimeparser::{
-    parse_message_ids, AvatarAction, MailinglistType, MimeMessage, SystemMessage,
+    parse_message_id, parse_message_ids, AvatarAction, MailinglistType, MimeMessage, SystemMessage,
 };
 use crate::param::{Param, Params};
 use crate::peerstate::{Peerstate, PeerstateKeyType, PeerstateVerifiedStatus};
 use crate::securejoin::{self, handle_securejoin_handshake, observe_securejoin_on_other_device};
+use crate::stats::Counter;
 use crate::stock_str;
+use crate::sync;
 use crate::{contact, location};
 
 // IndexSet is like HashSet but maintains order of insertion
 type ContactIds = indexmap::IndexSet<u32>;
 
+/// Seconds the clocks of group members may differ.
+///
+/// Member lists implied by messages sent less than this after the last explicit change of the
+/// member list are not applied, as the message may have been sent before the change.
+const MEMBER_LIST_CLOCK_SKEW: i64 = 5 * 60;
+
 #[derive(Debug, PartialEq, Eq)]
 enum CreateEvent {
     MsgsChanged,
@@ -52,16 +62,34 @@ pub async fn dc_receive_imf(
     server_uid: u32,
     seen: bool,
 ) -> Result<()> {
-    dc_receive_imf_inner(context, imf_raw, server_folder, server_uid, seen, false).await
+    dc_receive_imf_inner(
+        context,
+        imf_raw,
+        server_folder,
+        server_uid,
+        seen,
+        None,
+        false,
+        false,
+    )
+    .await
 }
 
+/// Receives a message, see [dc_receive_imf].
+///
+/// `is_import` is set for old messages imported from files, see [crate::imex::import_mbox].
+/// They are added to the chats like received messages,
+/// but do not change contacts, groups, settings or encryption state.
+#[allow(clippy::too_many_arguments)]
 pub(crate) async fn dc_receive_imf_inner(
     context: &Context,
     imf_raw: &[u8],
     server_folder: impl AsRef<str>,
     server_uid: u32,
     seen: bool,
+    is_partial_download: Option<u32>,
     fetching_existing_messages: bool,
+    is_import: bool,
 ) -> Result<()> {
     info!(
         context,
@@ -80,7 +108,14 @@ pub(crate) async fn dc_receive_imf_inner(
         println!("{}", String::from_utf8_lossy(imf_raw));
     }
 
-    let mut mime_parser = match MimeMessage::from_bytes(context, imf_raw).await {
+    let mut mime_parser = match MimeMessage::from_bytes_with_partial(
+        context,
+        imf_raw,
+        is_partial_download,
+        is_import,
+    )
+    .await
+    {
         Err(err) => {
             warn!(context, "dc_receive_imf: can't parse MIME: {}", err);
             return Ok(());
@@ -192,6 +227,8 @@ pub(crate) async fn dc_receive_imf_inner(
             &mut create_event_to_send,
             fetching_existing_messages,
             prevent_rename,
+            is_partial_download,
+            is_import,
         )
         .await
         {
@@ -206,6 +243,11 @@ pub(crate) async fn dc_receive_imf_inner(
         }
     }
 
+    // The header of a partially downloaded message must not change anything
+    // but the placeholder message, the full message is processed later.
+    // Imported messages are old and must not change anything but their chat either.
+    let is_partial = is_partial_download.is_some() || is_import;
+
     if mime_parser.location_kml.is_some() || mime_parser.message_kml.is_some() {
         save_locations(
             context,
@@ -218,7 +260,7 @@ pub(crate) async fn dc_receive_imf_inner(
         .await;
     }
 
-    if let Some(avatar_action) = &mime_parser.user_avatar {
+    if let Some(avatar_action) = mime_parser.user_avatar.as_ref().filter(|_| !is_partial) {
         match contact::set_profile_image(
             context,
             from_id,
@@ -237,21 +279,25 @@ pub(crate) async fn dc_receive_imf_inner(
     }
 
     // Always update the status, even if there is no footer, to allow removing the status.
-    if let Err(err) = contact::set_status(
-        context,
-        from_id,
-        mime_parser.footer.clone().unwrap_or_default(),
-    )
-    .await
-    {
-        warn!(context, "cannot update contact status: {}", err);
+    if !is_partial {
+        if let Err(err) = contact::set_status(
+            context,
+            from_id,
+            mime_parser.footer.clone().unwrap_or_default(),
+        )
+        .await
+        {
+            warn!(context, "cannot update contact status: {}", err);
+        }
     }
 
     // Get user-configured server deletion
     let delete_server_after = context.get_config_delete_server_after().await;
 
-    if !created_db_entries.is_empty() {
-        if needs_delete_job || delete_server_after == Some(0) {
+    // Messages without server folder, e.g. imported ones, have nothing to do on the server.
+    if !created_db_entries.is_empty() && !server_folder.as_ref().is_empty() {
+        // Partially downloaded messages are kept on the server to download them later.
+        if needs_delete_job || (delete_server_after == Some(0) && is_partial_download.is_none()) {
             for db_entry in &created_db_entries {
                 job::add(
                     context,
@@ -298,9 +344,11 @@ pub(crate) async fn dc_receive_imf_inner(
 
     cleanup(context, &create_event_to_send, created_db_entries);
 
-    mime_parser
-        .handle_reports(context, from_id, sent_timestamp, &mime_parser.parts)
-        .await;
+    if !is_partial {
+        mime_parser
+            .handle_reports(context, from_id, sent_timestamp, &mime_parser.parts)
+            .await;
+    }
 
     Ok(())
 }
@@ -376,6 +424,8 @@ async fn add_parts(
     create_event_to_send: &mut Option<CreateEvent>,
     fetching_existing_messages: bool,
     prevent_rename: bool,
+    is_partial_download: Option<u32>,
+    is_import: bool,
 ) -> Result<()> {
     let mut state: MessageState;
     let mut chat_id_blocked = Blocked::Not;
@@ -386,20 +436,143 @@ async fn add_parts(
     // check, if the mail is already in our database - if so, just update the folder/uid
     // (if the mail was moved around) and finish. (we may get a mail twice eg. if it is
     // moved between folders. make sure, this check is done eg. before securejoin-processing) */
-    if let Some((old_server_folder, old_server_uid, _)) =
+    // Partially downloaded messages are replaced by the full message however.
+    let mut replace_msg = None;
+    if let Some((old_server_folder, old_server_uid, old_msg_id)) =
         message::rfc724_mid_exists(context, rfc724_mid).await?
     {
-        if old_server_folder != server_folder.as_ref() || old_server_uid != server_uid {
-            message::update_server_uid(context, rfc724_mid, server_folder.as_ref(), server_uid)
-                .await;
-        }
+        let old_msg = Message::load_from_db(context, old_msg_id).await?;
+        // Messages with an attachment sent in several parts are completed by the parts instead.
+        if is_partial_download.is_none()
+            && old_msg.download_state != DownloadState::Done
+            && !old_msg.param.exists(Param::ChunkManifest)
+        {
+            info!(
+                context,
+                "Replacing partially downloaded message {}", old_msg_id
+            );
+            replace_msg = Some(old_msg);
+        } else {
+            if old_server_folder != server_folder.as_ref() || old_server_uid != server_uid {
+                message::update_server_uid(context, rfc724_mid, server_folder.as_ref(), server_uid)
+                    .await;
+            }
 
-        warn!(context, "Message already in DB");
-        return Ok(());
+            warn!(context, "Message already in DB");
+            return Ok(());
+        }
     }
 
     let parent = get_parent_message(context, mime_parser).await?;
 
+    // Messages resent to new group members are stored with the Message-ID of the original,
+    // so that they are threaded like it.  Members having the original trash the copy.
+    let resend_of = mime_parser
+        .get(HeaderDef::ChatResendOf)
+        .and_then(|value| parse_message_id(value).ok());
+    let rfc724_mid = match &resend_of {
+        Some(original) => {
+            if rfc724_mid_exists(context, original).await?.is_some() {
+                info!(context, "Resent message {} already in DB", original);
+                *chat_id = ChatId::new(DC_CHAT_ID_TRASH);
+                *hidden = true;
+                rfc724_mid
+            } else {
+                original.as_str()
+            }
+        }
+        None => rfc724_mid,
+    };
+
+    if mime_parser.is_system_message == SystemMessage::Reaction {
+        // The reacted message is the one replied to, References may point to a later message.
+        let target = match mime_parser.get(HeaderDef::InReplyTo) {
+            Some(field) => get_rfc724_mid_in_list(context, field).await?,
+            None => None,
+        };
+        match target {
+            Some(target)
+                if from_id == DC_CONTACT_ID_SELF
+                    || chat::is_contact_in_chat(context, target.chat_id, from_id).await =>
+            {
+                let reaction = mime_parser
+                    .get(HeaderDef::ChatReaction)
+                    .cloned()
+                    .unwrap_or_default();
+                if let Err(err) =
+                    message::set_reaction(context, &target, from_id, reaction.trim()).await
+                {
+                    warn!(context, "Cannot set reaction: {:#}", err);
+                }
+            }
+            _ => warn!(context, "Ignoring reaction to unknown message"),
+        }
+        *chat_id = ChatId::new(DC_CHAT_ID_TRASH);
+        *hidden = true;
+    }
+
+    if mime_parser.is_system_message == SystemMessage::StatusUpdate {
+        let target = match mime_parser.get(HeaderDef::InReplyTo) {
+            Some(field) => get_rfc724_mid_in_list(context, field).await?,
+            None => None,
+        };
+        match (target, &mime_parser.status_update) {
+            (Some(target), Some(json))
+                if from_id == DC_CONTACT_ID_SELF
+                    || chat::is_contact_in_chat(context, target.chat_id, from_id).await =>
+            {
+                // The Date header is used as is, so all members order the update alike.
+                if let Err(err) =
+                    message::add_status_update(context, &target, json, *sent_timestamp, rfc724_mid)
+                        .await
+                {
+                    warn!(context, "Cannot add status update: {:#}", err);
+                }
+            }
+            _ => warn!(context, "Ignoring status update of unknown message"),
+        }
+        *chat_id = ChatId::new(DC_CHAT_ID_TRASH);
+        *hidden = true;
+    }
+
+    if mime_parser.is_system_message == SystemMessage::Chunk
+        || mime_parser.is_system_message == SystemMessage::ChunkRequest
+    {
+        let target = match mime_parser.get(HeaderDef::InReplyTo) {
+            Some(field) => get_rfc724_mid_in_list(context, field).await?,
+            None => None,
+        };
+        match target {
+            Some(target)
+                if from_id == DC_CONTACT_ID_SELF
+                    || chat::is_contact_in_chat(context, target.chat_id, from_id).await =>
+            {
+                let res = if mime_parser.is_system_message == SystemMessage::Chunk {
+                    let index = mime_parser
+                        .get(HeaderDef::ChatChunk)
+                        .and_then(|index| index.trim().parse::<u32>().ok());
+                    match (index, &mime_parser.chunk_data) {
+                        (Some(index), Some(data)) => {
+                            chunks::add_chunk(context, &target, index, data).await
+                        }
+                        _ => Err(format_err!("Chunk without index or data")),
+                    }
+                } else {
+                    match &mime_parser.chunk_request {
+                        Some(json) => chunks::handle_request(context, &target, from_id, json).await,
+                        None => Err(format_err!("Chunk request without parts")),
+                    }
+                };
+                if let Err(err) = res {
+                    warn!(context, "Cannot handle chunk message: {:#}", err);
+                }
+            }
+            _ => warn!(context, "Ignoring chunk message of unknown message"),
+        }
+        *chat_id = ChatId::new(DC_CHAT_ID_TRASH);
+        *hidden = true;
+    }
+
     let mut is_dc_message = if mime_parser.has_chat_version() {
         MessengerMessage::Yes
     } else if let Some(parent) = &parent {
@@ -444,7 +617,7 @@ async fn add_parts(
         to_id = DC_CONTACT_ID_SELF;
 
         // handshake may mark contacts as verified and must be processed before chats are created
-        if mime_parser.get(HeaderDef::SecureJoin).is_some() {
+        if mime_parser.get(HeaderDef::SecureJoin).is_some() && is_partial_download.is_none() {
             is_dc_message = MessengerMessage::Yes; // avoid discarding by show_emails setting
             *chat_id = ChatId::new(0);
             allow_creation = true;
@@ -481,6 +654,41 @@ async fn add_parts(
             info!(context, "Message belongs to an NDN (TRASH)",);
         }
 
+        if chat_id.is_unset() {
+            // check if the message belongs to a mailing list;
+            // this is done before looking for groups
+            // so that all posts to a list are assigned to one chat, regardless of the sender
+            match mime_parser.get_mailinglist_type() {
+                MailinglistType::ListIdBased => {
+                    if let Some(list_id) = mime_parser.get(HeaderDef::ListId) {
+                        let (new_chat_id, new_chat_id_blocked) = create_or_lookup_mailinglist(
+                            context,
+                            allow_creation,
+                            list_id,
+                            mime_parser,
+                        )
+                        .await;
+                        *chat_id = new_chat_id;
+                        chat_id_blocked = new_chat_id_blocked;
+                    }
+                }
+                MailinglistType::SenderBased => {
+                    if let Some(sender) = mime_parser.get(HeaderDef::Sender) {
+                        let (new_chat_id, new_chat_id_blocked) = create_or_lookup_mailinglist(
+                            context,
+                            allow_creation,
+                            sender,
+                            mime_parser,
+                        )
+                        .await;
+                        *chat_id = new_chat_id;
+                        chat_id_blocked = new_chat_id_blocked;
+                    }
+                }
+                MailinglistType::None => {}
+            }
+        }
+
         if chat_id.is_unset() {
             // try to create a group
 
@@ -502,6 +710,8 @@ async fn add_parts(
                 create_blocked,
                 from_id,
                 to_ids,
+                *sent_timestamp,
+                is_partial_download.is_some() || is_import,
             )
             .await?;
             *chat_id = new_chat_id;
@@ -515,39 +725,6 @@ async fn add_parts(
             }
         }
 
-        if chat_id.is_unset() {
-            // check if the message belongs to a mailing list
-            match mime_parser.get_mailinglist_type() {
-                MailinglistType::ListIdBased => {
-                    if let Some(list_id) = mime_parser.get(HeaderDef::ListId) {
-                        let (new_chat_id, new_chat_id_blocked) = create_or_lookup_mailinglist(
-                            context,
-                            allow_creation,
-                            list_id,
-                            mime_parser,
-                        )
-                        .await;
-                        *chat_id = new_chat_id;
-                        chat_id_blocked = new_chat_id_blocked;
-                    }
-                }
-                MailinglistType::SenderBased => {
-                    if let Some(sender) = mime_parser.get(HeaderDef::Sender) {
-                        let (new_chat_id, new_chat_id_blocked) = create_or_lookup_mailinglist(
-                            context,
-                            allow_creation,
-                            sender,
-                            mime_parser,
-                        )
-                        .await;
-                        *chat_id = new_chat_id;
-                        chat_id_blocked = new_chat_id_blocked;
-                    }
-                }
-                MailinglistType::None => {}
-            }
-        }
-
         // if contact renaming is prevented (for mailinglists and bots),
         // we use name from From:-header as override name
         if prevent_rename {
@@ -627,7 +804,7 @@ async fn add_parts(
         to_id = to_ids.get_index(0).cloned().unwrap_or_default();
 
         // handshake may mark contacts as verified and must be processed before chats are created
-        if mime_parser.get(HeaderDef::SecureJoin).is_some() {
+        if mime_parser.get(HeaderDef::SecureJoin).is_some() && is_partial_download.is_none() {
             is_dc_message = MessengerMessage::Yes; // avoid discarding by show_emails setting
             *chat_id = ChatId::new(0);
             allow_creation = true;
@@ -647,7 +824,21 @@ async fn add_parts(
             }
         }
 
-        if !context.is_sentbox(&server_folder).await
+        if mime_parser.is_system_message == SystemMessage::ConfigSync {
+            if is_partial_download.is_some() {
+                info!(context, "Sync message is not downloaded completely");
+            } else if is_import {
+                info!(context, "Not applying imported sync message");
+            } else if let Err(err) = sync::receive_sync_items(context, mime_parser).await {
+                warn!(context, "Cannot apply sync message: {}", err);
+            }
+            *chat_id = ChatId::new(DC_CHAT_ID_TRASH);
+            allow_creation = false;
+        }
+
+        // Imported messages have no server folder, they are taken from the user's archive.
+        if !is_import
+            && !context.is_sentbox(&server_folder).await
             && mime_parser.get(HeaderDef::Received).is_none()
         {
             // Most mailboxes have a "Drafts" folder where constantly new emails appear but we don't actually want to show them
@@ -666,6 +857,8 @@ async fn add_parts(
                     Blocked::Not,
                     from_id,
                     to_ids,
+                    *sent_timestamp,
+                    is_partial_download.is_some() || is_import,
                 )
                 .await?;
                 *chat_id = new_chat_id;
@@ -758,6 +951,8 @@ async fn add_parts(
     if !*hidden
         && !location_kml_is
         && !is_mdn
+        && is_partial_download.is_none()
+        && !is_import
         && (is_dc_message != MessengerMessage::Yes
             || parent.is_none()
             || parent.unwrap().ephemeral_timer != ephemeral_timer)
@@ -797,7 +992,7 @@ async fn add_parts(
     }
 
     // if a chat is protected, check additional properties
-    if !chat_id.is_special() {
+    if !chat_id.is_special() && is_partial_download.is_none() && !is_import {
         let chat = Chat::load_from_db(context, *chat_id).await?;
         let new_status = match mime_parser.is_system_message {
             SystemMessage::ChatProtectionEnabled => Some(ProtectionStatus::Protected),
@@ -833,11 +1028,24 @@ async fn add_parts(
         }
     }
 
+    // The full message stays where the partially downloaded message was shown.
+    if let Some(replace_msg) = &replace_msg {
+        if !chat_id.is_trash() {
+            *chat_id = replace_msg.chat_id;
+        }
+        if incoming {
+            state = replace_msg.state;
+        }
+    }
+
     // correct message_timestamp, it should not be used before,
     // however, we cannot do this earlier as we need from_id to be set
     let in_fresh = state == MessageState::InFresh;
     let rcvd_timestamp = time();
-    let sort_timestamp = calc_sort_timestamp(context, *sent_timestamp, *chat_id, in_fresh).await;
+    let sort_timestamp = match &replace_msg {
+        Some(replace_msg) => replace_msg.timestamp_sort,
+        None => calc_sort_timestamp(context, *sent_timestamp, *chat_id, in_fresh).await,
+    };
 
     // Ensure replies to messages are sorted after the parent message.
     //
@@ -865,6 +1073,14 @@ async fn add_parts(
         mime_references = raw.clone();
     }
 
+    // a quote refers to the message in `In-Reply-To`, remember its id if we have it
+    let quoted_msg_id = match parse_message_ids(&mime_in_reply_to).first() {
+        Some(quoted_mid) => rfc724_mid_exists(context, quoted_mid)
+            .await?
+            .map_or_else(MsgId::new_unset, |(_, _, msg_id)| msg_id),
+        None => MsgId::new_unset(),
+    };
+
     // fine, so far.  now, split the message into simple parts usable as "short messages"
     // and add them to the database (mails sent by other messenger clients should result
     // into only one message; mails sent by other clients may result in several messages
@@ -901,6 +1117,15 @@ async fn add_parts(
     // TODO: can this clone be avoided?
     let rfc724_mid = rfc724_mid.to_string();
 
+    // The first part replaces the partially downloaded message, keeping its ID.
+    let mut replace_msg_id = replace_msg.as_ref().map(|msg| msg.id);
+    let download_state = if is_partial_download.is_some() {
+        DownloadState::Undownloaded
+    } else {
+        DownloadState::Done
+    };
+
+    let inserted_mid = rfc724_mid.clone();
     let (new_parts, ids, is_hidden) = context
         .sql
         .with_conn(move |mut conn| {
@@ -910,12 +1135,12 @@ async fn add_parts(
             for part in &mut parts {
                 let mut txt_raw = "".to_string();
                 let mut stmt = conn.prepare_cached(
-                    "INSERT INTO msgs \
-         (rfc724_mid, server_folder, server_uid, chat_id, from_id, to_id, timestamp, \
-         timestamp_sent, timestamp_rcvd, type, state, msgrmsg,  txt, subject, txt_raw, param, \
-         bytes, hidden, mime_headers,  mime_in_reply_to, mime_references, mime_modified, \
-         error, ephemeral_timer, ephemeral_timestamp) \
-         VALUES (?,?,?,?,?,?,?, ?,?,?,?,?,?,?,?,?, ?,?,?,?,?,?, ?,?,?);",
+                    "INSERT OR REPLACE INTO msgs \
+         (id, rfc724_mid, server_folder, server_uid, chat_id, from_id, to_id, timestamp, \
+         timestamp_sent, timestamp_rcvd, type, state, msgrmsg,  txt, txt_folded, subject, txt_raw, param, \
+         bytes, hidden, mime_headers,  mime_in_reply_to, quoted_msg_id, mime_references, mime_modified, \
+         error, ephemeral_timer, ephemeral_timestamp, download_state) \
+         VALUES (?,?,?,?,?,?,?,?, ?,?,?,?,?,?,?,?,?,?, ?,?,?,?,?,?,?, ?,?,?,?);",
                 )?;
 
                 let is_location_kml = location_kml_is
@@ -958,7 +1183,9 @@ async fn add_parts(
                 // also change `MsgId::trash()` and `delete_expired_messages()`
                 let trash = chat_id.is_trash();
 
+                let replace_id = replace_msg_id.take();
                 stmt.execute(paramsv![
+                    replace_id,
                     rfc724_mid,
                     server_folder,
                     server_uid as i32,
@@ -972,6 +1199,11 @@ async fn add_parts(
                     state,
                     is_dc_message,
                     if trash { "" } else { &part.msg },
+                    if trash {
+                        "".to_string()
+                    } else {
+                        message::search_fold(&part.msg)
+                    },
                     if trash { "" } else { &subject },
                     // txt_raw might contain invalid utf8
                     if trash { "" } else { &txt_raw },
@@ -988,25 +1220,46 @@ async fn add_parts(
                         None
                     },
                     mime_in_reply_to,
+                    if part.param.exists(Param::Quote) {
+                        quoted_msg_id
+                    } else {
+                        MsgId::new_unset()
+                    },
                     mime_references,
                     mime_modified,
                     part.error.take().unwrap_or_default(),
                     ephemeral_timer,
-                    ephemeral_timestamp
+                    ephemeral_timestamp,
+                    if part.param.exists(Param::ChunkManifest) {
+                        DownloadState::InProgress
+                    } else {
+                        download_state
+                    }
                 ])?;
 
                 drop(stmt);
-                ids.push(MsgId::new(crate::sql::get_rowid(
-                    &mut conn,
-                    "msgs",
-                    "rfc724_mid",
-                    &rfc724_mid,
-                )?));
+                ids.push(match replace_id {
+                    Some(replace_id) => replace_id,
+                    None => MsgId::new(crate::sql::get_rowid(
+                        &mut conn,
+                        "msgs",
+                        "rfc724_mid",
+                        &rfc724_mid,
+                    )?),
+                });
             }
             Ok((parts, ids, is_hidden))
         })
         .await?;
 
+    for id in &ids {
+        context.sql.rfc724_mid_inserted(&inserted_mid, *id);
+    }
+    // A replaced partially downloaded message is counted already.
+    let inserted = ids.len().saturating_sub(usize::from(replace_msg.is_some()));
+    context
+        .stats_cache
+        .adjust_messages(chat_id, chat_id_blocked, inserted as i64);
     if let Some(id) = ids.iter().last() {
         *insert_msg_id = *id;
     }
@@ -1039,6 +1292,10 @@ async fn add_parts(
             *create_event_to_send = Some(CreateEvent::IncomingMsg);
         }
     }
+    if replace_msg.is_some() && create_event_to_send.is_some() {
+        // The user was already notified about the partially downloaded message.
+        *create_event_to_send = Some(CreateEvent::MsgsChanged);
+    }
 
     async fn update_last_subject(
         context: &Context,
@@ -1167,7 +1424,8 @@ async fn calc_sort_timestamp(
 /// This function tries to extract the group-id from the message and returns the
 /// corresponding chat_id. If the chat does not exist, it is created.
 /// If the message contains groups commands (name, profile image, changed members),
-/// they are executed as well.
+/// they are executed as well, unless `ignore_group_changes` is set because only the header
+/// of the message is downloaded or the message is imported.
 ///
 /// If no group-id could be extracted, message is assigned to the same chat as the
 /// parent message.
@@ -1176,7 +1434,11 @@ async fn calc_sort_timestamp(
 /// a new ad-hoc group is created.
 ///
 /// On success the function returns the found/created (chat_id, chat_blocked) tuple.
-#[allow(non_snake_case, clippy::cognitive_complexity)]
+#[allow(
+    non_snake_case,
+    clippy::too_many_arguments,
+    clippy::cognitive_complexity
+)]
 async fn create_or_lookup_group(
     context: &Context,
     mime_parser: &mut MimeMessage,
@@ -1184,6 +1446,8 @@ async fn create_or_lookup_group(
     create_blocked: Blocked,
     from_id: u32,
     to_ids: &ContactIds,
+    sent_timestamp: i64,
+    ignore_group_changes: bool,
 ) -> Result<(ChatId, Blocked)> {
     let mut chat_id_blocked = Blocked::Not;
     let mut recreate_member_list = false;
@@ -1252,7 +1516,10 @@ async fn create_or_lookup_group(
     let grpname = mime_parser.get(HeaderDef::ChatGroupName).cloned();
     let mut removed_id = None;
 
-    if let Some(removed_addr) = mime_parser.get(HeaderDef::ChatGroupMemberRemoved).cloned() {
+    if ignore_group_changes {
+        // Group changes are applied once the full message is downloaded,
+        // those of imported messages are outdated.
+    } else if let Some(removed_addr) = mime_parser.get(HeaderDef::ChatGroupMemberRemoved).cloned() {
         removed_id = Contact::lookup_id_by_addr(context, &removed_addr, Origin::Unknown).await?;
         match removed_id {
             Some(contact_id) => {
@@ -1319,6 +1586,7 @@ async fn create_or_lookup_group(
         let s = stock_str::unknown_sender_for_chat(context).await;
         mime_parser.repl_msg_by_error(s);
     }
+    let chat_existed = !chat_id.is_unset();
 
     // check if the group does not exist but should be created
     let group_explicitly_left = chat::is_group_explicitly_left(context, &grpid)
@@ -1432,11 +1700,17 @@ async fn create_or_lookup_group(
                 }
             }
         }
-    } else if mime_parser.is_system_message == SystemMessage::ChatProtectionEnabled {
+    } else if mime_parser.is_system_message == SystemMessage::ChatProtectionEnabled
+        && !ignore_group_changes
+    {
         recreate_member_list = true;
     }
 
-    if let Some(avatar_action) = &mime_parser.group_avatar {
+    if let Some(avatar_action) = mime_parser
+        .group_avatar
+        .as_ref()
+        .filter(|_| !ignore_group_changes)
+    {
         info!(context, "group-avatar change for {}", chat_id);
         if let Ok(mut chat) = Chat::load_from_db(context, chat_id).await {
             match avatar_action {
@@ -1487,6 +1761,22 @@ async fn create_or_lookup_group(
     } else if let Some(contact_id) = removed_id {
         chat::remove_from_chat_contacts_table(context, chat_id, contact_id).await;
         send_EVENT_CHAT_MODIFIED = true;
+    } else if chat_existed
+        && !ignore_group_changes
+        && apply_implied_member_list(
+            context,
+            mime_parser,
+            chat_id,
+            from_id,
+            to_ids,
+            sent_timestamp,
+        )
+        .await?
+    {
+        send_EVENT_CHAT_MODIFIED = true;
+    }
+    if recreate_member_list || removed_id.is_some() {
+        chat::update_member_list_timestamp(context, chat_id, sent_timestamp).await?;
     }
 
     if send_EVENT_CHAT_MODIFIED {
@@ -1495,6 +1785,141 @@ async fn create_or_lookup_group(
     Ok((chat_id, chat_id_blocked))
 }
 
+/// Applies the member list implied by the recipients of a group message
+/// if it differs from the local member list,
+/// e.g. because a message adding or removing a member was missed.
+///
+/// The member list is only applied if the sender is a member of the group
+/// and the message was sent clearly after the last explicit change of the member list,
+/// so that delayed messages or messages from senders with a clock running ahead
+/// do not revert newer changes, see [MEMBER_LIST_CLOCK_SKEW].
+///
+/// Returns true if the member list was changed.
+async fn apply_implied_member_list(
+    context: &Context,
+    mime_parser: &MimeMessage,
+    chat_id: ChatId,
+    from_id: u32,
+    to_ids: &ContactIds,
+    sent_timestamp: i64,
+) -> Result<bool> {
+    if mime_parser.get(HeaderDef::ChatVersion).is_none() {
+        // Classic MUAs may drop recipients when replying.
+        return Ok(false);
+    }
+    let chat = Chat::load_from_db(context, chat_id).await?;
+    if chat.typ != Chattype::Group
+        || !chat::is_contact_in_chat(context, chat_id, from_id).await
+        || !chat::is_contact_in_chat(context, chat_id, DC_CONTACT_ID_SELF).await
+    {
+        return Ok(false);
+    }
+    let member_list_timestamp = chat
+        .param
+        .get_i64(Param::MemberListTimestamp)
+        .unwrap_or_default();
+    if sent_timestamp < member_list_timestamp + MEMBER_LIST_CLOCK_SKEW {
+        info!(
+            context,
+            "Not applying member list of chat {} sent around or before the last member list change.",
+            chat_id
+        );
+        return Ok(false);
+    }
+    if chat.is_protected() {
+        if let Err(err) = check_verified_properties(context, mime_parser, from_id, to_ids).await {
+            warn!(
+                context,
+                "Not applying member list of chat {}: {}", chat_id, err
+            );
+            return Ok(false);
+        }
+    }
+
+    let self_addr = context
+        .get_config(Config::ConfiguredAddr)
+        .await
+        .unwrap_or_default();
+    let mut implied_ids = vec![DC_CONTACT_ID_SELF];
+    for &contact_id in to_ids.iter().chain(std::iter::once(&from_id)) {
+        if contact_id > DC_CONTACT_ID_LAST_SPECIAL
+            && !implied_ids.contains(&contact_id)
+            && !Contact::addr_equals_contact(context, &self_addr, contact_id).await
+        {
+            implied_ids.push(contact_id);
+        }
+    }
+    let member_ids = chat::get_chat_contacts(context, chat_id).await;
+    let added_ids: Vec<u32> = implied_ids
+        .iter()
+        .filter(|contact_id| !member_ids.contains(contact_id))
+        .copied()
+        .collect();
+    let removed_ids: Vec<u32> = member_ids
+        .iter()
+        // Only explicit messages remove ourself from the group.
+        .filter(|&&contact_id| contact_id != DC_CONTACT_ID_SELF)
+        .filter(|contact_id| !implied_ids.contains(contact_id))
+        .copied()
+        .collect();
+    if added_ids.is_empty() && removed_ids.is_empty() {
+        return Ok(false);
+    }
+
+    info!(
+        context,
+        "Applying member list of chat {}: added {:?}, removed {:?}",
+        chat_id,
+        added_ids,
+        removed_ids
+    );
+    context
+        .sql
+        .transaction(|tx| {
+            for contact_id in &added_ids {
+                tx.execute(
+                    "INSERT INTO chats_contacts (chat_id, contact_id) VALUES(?, ?);",
+                    rusqlite::params![chat_id, contact_id],
+                )?;
+            }
+            for contact_id in &removed_ids {
+                tx.execute(
+                    "DELETE FROM chats_contacts WHERE chat_id=? AND contact_id=?;",
+                    rusqlite::params![chat_id, contact_id],
+                )?;
+            }
+            let mut param: Params = tx
+                .query_row(
+                    "SELECT param FROM chats WHERE id=?;",
+                    rusqlite::params![chat_id],
+                    |row| row.get::<_, String>(0),
+                )?
+                .parse()
+                .unwrap_or_default();
+            param.set_i64(Param::MemberListTimestamp, sent_timestamp);
+            tx.execute(
+                "UPDATE chats SET param=? WHERE id=?;",
+                rusqlite::params![param.to_string(), chat_id],
+            )?;
+            Ok(())
+        })
+        .await?;
+
+    for contact_id in added_ids {
+        let contact = Contact::get_by_id(context, contact_id).await?;
+        let text =
+            stock_str::msg_add_member(context, contact.get_addr(), DC_CONTACT_ID_UNDEFINED).await;
+        chat::add_info_msg(context, chat_id, text).await;
+    }
+    for contact_id in removed_ids {
+        let contact = Contact::get_by_id(context, contact_id).await?;
+        let text =
+            stock_str::msg_del_member(context, contact.get_addr(), DC_CONTACT_ID_UNDEFINED).await;
+        chat::add_info_msg(context, chat_id, text).await;
+    }
+    Ok(true)
+}
+
 /// Create or lookup a mailing list chat.
 ///
 /// `list_id_header` contains the Id that must be used for the mailing list
@@ -1524,8 +1949,13 @@ async fn create_or_lookup_mailinglist(
         ),
     };
 
-    if let Ok((chat_id, _, blocked)) = chat::get_chat_id_by_grpid(context, &listid).await {
-        return (chat_id, blocked);
+    match lookup_mailinglist(context, &listid).await {
+        Ok(Some((chat_id, blocked))) => {
+            update_list_post(context, chat_id, mime_parser).await;
+            return (chat_id, blocked);
+        }
+        Ok(None) => {}
+        Err(err) => warn!(context, "Cannot look up mailing list {}: {}", listid, err),
     }
 
     // for mailchimp lists, the name in `ListId` is just a long number.
@@ -1584,6 +2014,17 @@ async fn create_or_lookup_mailinglist(
         {
             Ok(chat_id) => {
                 chat::add_to_chat_contacts_table(context, chat_id, DC_CONTACT_ID_SELF).await;
+                if let Err(err) = context
+                    .sql
+                    .execute(
+                        "UPDATE chats SET list_id=? WHERE id=?;",
+                        paramsv![listid, chat_id],
+                    )
+                    .await
+                {
+                    warn!(context, "Cannot set list id of {}: {}", chat_id, err);
+                }
+                update_list_post(context, chat_id, mime_parser).await;
                 (chat_id, Blocked::Deaddrop)
             }
             Err(e) => {
@@ -1603,6 +2044,86 @@ async fn create_or_lookup_mailinglist(
     }
 }
 
+/// Returns the chat and its blocked state of the mailing list with the given list id.
+///
+/// Mailing lists created before chats got the `list_id` column
+/// are only known by their grpid, they are adopted by setting their list id.
+async fn lookup_mailinglist(context: &Context, listid: &str) -> Result<Option<(ChatId, Blocked)>> {
+    let chat = context
+        .sql
+        .query_row_optional(
+            "SELECT id, blocked, list_id FROM chats
+             WHERE type=?2 AND (list_id=?1 OR (list_id='' AND grpid=?1))
+             ORDER BY list_id=?1 DESC LIMIT 1;",
+            paramsv![listid, Chattype::Mailinglist],
+            |row| {
+                let chat_id: ChatId = row.get(0)?;
+                let blocked: Option<Blocked> = row.get(1)?;
+                let adopted = row.get::<_, String>(2)? == listid;
+                Ok((chat_id, blocked.unwrap_or_default(), adopted))
+            },
+        )
+        .await?;
+    let (chat_id, blocked, adopted) = match chat {
+        Some(chat) => chat,
+        None => return Ok(None),
+    };
+    if !adopted {
+        info!(context, "Adopting {} as mailing list {}", chat_id, listid);
+        context
+            .sql
+            .execute(
+                "UPDATE chats SET list_id=? WHERE id=?;",
+                paramsv![listid, chat_id],
+            )
+            .await?;
+    }
+    Ok(Some((chat_id, blocked)))
+}
+
+/// Updates the address to post to a mailing list from the `List-Post` header.
+///
+/// Lists without `List-Post` header, eg. announcement lists, are read-only.
+async fn update_list_post(context: &Context, chat_id: ChatId, mime_parser: &MimeMessage) {
+    let list_post = mime_parser
+        .get(HeaderDef::ListPost)
+        .and_then(|header| parse_list_post(header));
+
+    let mut chat = match Chat::load_from_db(context, chat_id).await {
+        Ok(chat) => chat,
+        Err(err) => {
+            warn!(context, "Cannot load mailing list {}: {}", chat_id, err);
+            return;
+        }
+    };
+    if chat.param.get(Param::ListPost) == list_post.as_deref() {
+        return;
+    }
+    if let Some(addr) = &list_post {
+        chat.param.set(Param::ListPost, addr);
+    } else {
+        chat.param.remove(Param::ListPost);
+    }
+    if let Err(err) = chat.update_param(context).await {
+        warn!(context, "Cannot update List-Post of {}: {}", chat_id, err);
+        return;
+    }
+    context.emit_event(EventType::ChatModified(chat_id));
+}
+
+/// Returns the first `mailto:` address of a `List-Post` header.
+///
+/// Returns `None` for `List-Post: NO` used by lists that do not allow posting.
+fn parse_list_post(header: &str) -> Option<String> {
+    static MAILTO: Lazy<Regex> = Lazy::new(|| Regex::new(r"<mailto:([^>?]+)").unwrap());
+    let addr = MAILTO.captures(header)?.get(1)?.as_str().trim();
+    if contact::may_be_valid_addr(addr) {
+        Some(addr.to_string())
+    } else {
+        None
+    }
+}
+
 fn try_getting_grpid(mime_parser: &MimeMessage) -> Option<String> {
     if let Some(optional_field) = mime_parser.get(HeaderDef::ChatGroupId) {
         return Some(optional_field.clone());
@@ -1722,6 +2243,9 @@ async fn create_multiuser_record(
         .await?;
 
     let chat_id = ChatId::new(row_id);
+    if create_blocked == Blocked::Not {
+        context.stats_cache.adjust(Counter::Chats, 1);
+    }
     info!(
         context,
         "Created group/mailinglist '{}' grpid={} as {}",
@@ -1887,6 +2411,7 @@ async fn check_verified_properties(
                             PeerstateVerifiedStatus::BidirectVerified,
                         );
                         peerstate.save_to_db(&context.sql, false).await?;
+                        peerstate.audit_protected_chats(context).await?;
                         is_verified = true;
                     }
                 }
@@ -2036,6 +2561,7 @@ mod tests {
     use crate::chat::{get_chat_msgs, ChatItem, ChatVisibility};
     use crate::chatlist::Chatlist;
     use crate::constants::{DC_CHAT_ID_DEADDROP, DC_CONTACT_ID_INFO, DC_GCL_NO_SPECIALS};
+    use crate::dc_tools::dc_create_id;
     use crate::message::ContactRequestDecision::*;
     use crate::message::{ContactRequestDecision, Message};
     use crate::test_utils::{get_chat_msg, TestContext};
@@ -3182,6 +3708,152 @@ async fn test_mailing_list_with_mimepart_footer_signed() {
         assert!(!html.contains("footer text"));
     }
 
+    /// Returns `eml` with the `From:` and `Message-ID:` headers replaced,
+    /// so that a single fixture can be used for several posts to a mailing list.
+    fn repost(eml: &[u8], from: &str, message_id: &str) -> Vec<u8> {
+        String::from_utf8_lossy(eml)
+            .lines()
+            .map(|line| {
+                if line.starts_with("From: ") {
+                    format!("From: {}", from)
+                } else if line.starts_with("Message-ID: ") {
+                    format!("Message-ID: <{}>", message_id)
+                } else {
+                    line.to_string()
+                }
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+            .into_bytes()
+    }
+
+    #[async_std::test]
+    async fn test_mailman_list_threading() {
+        let t = TestContext::new_alice().await;
+        t.set_config(Config::ShowEmails, Some("2")).await.unwrap();
+        let eml = include_bytes!("../test-data/message/mailinglist_mailman.eml");
+
+        dc_receive_imf(&t, eml, "INBOX", 1, false).await.unwrap();
+        let chat_id = t.get_last_msg().await.chat_id;
+        let chat = Chat::load_from_db(&t, chat_id).await.unwrap();
+        assert_eq!(chat.typ, Chattype::Mailinglist);
+        assert_eq!(chat.name, "delta-dev");
+        assert!(chat.can_send());
+
+        // Posts by other senders are assigned to the same chat,
+        // also if they are sent by Delta Chat to a group.
+        let post = repost(
+            eml,
+            "Charlie <charlie@example.org>",
+            "mailman-2@example.org",
+        );
+        dc_receive_imf(&t, &post, "INBOX", 2, false).await.unwrap();
+        let post = String::from_utf8(repost(
+            eml,
+            "dave@example.org",
+            "Gr.abcdefghijk.1@example.org",
+        ))
+        .unwrap()
+        .replace(
+            "MIME-Version: 1.0",
+            "Chat-Version: 1.0\nChat-Group-ID: abcdefghijk\nMIME-Version: 1.0",
+        );
+        dc_receive_imf(&t, post.as_bytes(), "INBOX", 3, false)
+            .await
+            .unwrap();
+        assert_eq!(t.get_last_msg().await.chat_id, chat_id);
+        assert_eq!(chat::get_chat_msgs(&t, chat_id, 0, None).await.len(), 3);
+        assert!(chat::get_chat_id_by_grpid(&t, "abcdefghijk").await.is_err());
+
+        // Posts are sent to the list address only.
+        let sent = t.send_text(chat_id, "Next week is fine").await;
+        assert_eq!(sent.recipient().to_string(), "delta-dev@lists.example.org");
+    }
+
+    #[async_std::test]
+    async fn test_google_groups_list_threading() {
+        let t = TestContext::new_alice().await;
+        t.set_config(Config::ShowEmails, Some("2")).await.unwrap();
+        let eml = include_bytes!("../test-data/message/mailinglist_google_groups.eml");
+
+        dc_receive_imf(&t, eml, "INBOX", 1, false).await.unwrap();
+        let chat_id = t.get_last_msg().await.chat_id;
+        let post = repost(eml, "Bob <bob@example.net>", "google-2@example.net");
+        dc_receive_imf(&t, &post, "INBOX", 2, false).await.unwrap();
+        assert_eq!(t.get_last_msg().await.chat_id, chat_id);
+        assert_eq!(chat::get_chat_msgs(&t, chat_id, 0, None).await.len(), 2);
+
+        let chat = Chat::load_from_db(&t, chat_id).await.unwrap();
+        assert_eq!(chat.typ, Chattype::Mailinglist);
+        assert_eq!(chat.name, "rust-users.googlegroups.com");
+        assert!(chat.can_send());
+        assert_eq!(
+            chat.param.get(Param::ListPost),
+            Some("rust-users@googlegroups.com")
+        );
+    }
+
+    #[async_std::test]
+    async fn test_mailing_list_read_only_without_list_post() {
+        let t = TestContext::new_alice().await;
+        t.set_config(Config::ShowEmails, Some("2")).await.unwrap();
+        let eml = include_bytes!("../test-data/message/mailinglist_mailman.eml");
+        let announcement = String::from_utf8_lossy(eml).replace(
+            "List-Post: <mailto:delta-dev@lists.example.org>\n",
+            "List-Post: NO\n",
+        );
+
+        dc_receive_imf(&t, announcement.as_bytes(), "INBOX", 1, false)
+            .await
+            .unwrap();
+        let chat_id = t.get_last_msg().await.chat_id;
+        let chat = Chat::load_from_db(&t, chat_id).await.unwrap();
+        assert_eq!(chat.typ, Chattype::Mailinglist);
+        assert!(!chat.can_send());
+        assert!(chat::send_text_msg(&t, chat_id, "hi".to_string())
+            .await
+            .is_err());
+
+        let post = repost(
+            eml,
+            "Charlie <charlie@example.org>",
+            "mailman-2@example.org",
+        );
+        dc_receive_imf(&t, &post, "INBOX", 2, false).await.unwrap();
+        let chat = Chat::load_from_db(&t, chat_id).await.unwrap();
+        assert!(chat.can_send());
+    }
+
+    #[async_std::test]
+    async fn test_mailing_list_adopted() {
+        let t = TestContext::new_alice().await;
+        t.set_config(Config::ShowEmails, Some("2")).await.unwrap();
+        let eml = include_bytes!("../test-data/message/mailinglist_mailman.eml");
+        dc_receive_imf(&t, eml, "INBOX", 1, false).await.unwrap();
+        let chat_id = t.get_last_msg().await.chat_id;
+
+        // Mailing lists created before the list id was stored are known by their grpid only.
+        t.sql
+            .execute("UPDATE chats SET list_id='' WHERE id=?;", paramsv![chat_id])
+            .await
+            .unwrap();
+
+        let post = repost(
+            eml,
+            "Charlie <charlie@example.org>",
+            "mailman-2@example.org",
+        );
+        dc_receive_imf(&t, &post, "INBOX", 2, false).await.unwrap();
+        assert_eq!(t.get_last_msg().await.chat_id, chat_id);
+        let list_id: String = t
+            .sql
+            .query_get_value_result("SELECT list_id FROM chats WHERE id=?;", paramsv![chat_id])
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(list_id, "delta-dev.lists.example.org");
+    }
+
     #[async_std::test]
     async fn test_dont_show_tokens_in_contacts_list() {
         check_dont_show_in_contacts_list(
@@ -3471,4 +4143,164 @@ async fn test_in_reply_to_two_member_group() {
         assert_eq!(chat.typ, Chattype::Single);
         assert_eq!(msg.get_text().unwrap(), "private reply");
     }
+
+    /// Receives a group message from alice@example.org for the group "foo".
+    async fn recv_group_msg(t: &TestContext, to: &str, date: &str, extra_headers: &str) {
+        let imf_raw = format!(
+            "Received: (Postfix, from userid 1000); Mon, 4 Dec 2006 14:51:39 +0100 (CET)\n\
+             From: alice@example.org\n\
+             To: {}\n\
+             Subject: foo\n\
+             Message-ID: <{}@example.org>\n\
+             Chat-Version: 1.0\n\
+             Chat-Group-ID: foo\n\
+             Chat-Group-Name: foo\n\
+             {}\
+             Date: {}\n\
+             \n\
+             hello\n",
+            to,
+            dc_create_id(),
+            extra_headers,
+            date
+        );
+        dc_receive_imf(t, imf_raw.as_bytes(), "INBOX", 1, false)
+            .await
+            .unwrap();
+    }
+
+    async fn get_member_addrs(t: &TestContext, chat_id: ChatId) -> Vec<String> {
+        let mut addrs = Vec::new();
+        for contact_id in chat::get_chat_contacts(t, chat_id).await {
+            if contact_id != DC_CONTACT_ID_SELF {
+                let contact = Contact::get_by_id(t, contact_id).await.unwrap();
+                addrs.push(contact.get_addr().to_string());
+            }
+        }
+        addrs.sort();
+        addrs
+    }
+
+    #[async_std::test]
+    async fn test_heal_missed_member_changes() {
+        let t = TestContext::new().await;
+        t.configure_addr("bob@example.com").await;
+
+        recv_group_msg(
+            &t,
+            "bob@example.com, charlie@example.net, dave@example.net",
+            "Sun, 22 Mar 2020 22:37:57 +0000",
+            "",
+        )
+        .await;
+        let chat_id = t.get_last_msg().await.chat_id;
+        assert_eq!(
+            get_member_addrs(&t, chat_id).await,
+            vec![
+                "alice@example.org",
+                "charlie@example.net",
+                "dave@example.net"
+            ]
+        );
+
+        // The messages removing Dave and adding Erin were missed.
+        recv_group_msg(
+            &t,
+            "bob@example.com, charlie@example.net, erin@example.net",
+            "Sun, 22 Mar 2020 22:50:00 +0000",
+            "",
+        )
+        .await;
+        assert_eq!(t.get_last_msg().await.chat_id, chat_id);
+        assert_eq!(
+            get_member_addrs(&t, chat_id).await,
+            vec![
+                "alice@example.org",
+                "charlie@example.net",
+                "erin@example.net"
+            ]
+        );
+        let mut info_texts = Vec::new();
+        for item in get_chat_msgs(&t, chat_id, 0, None).await {
+            if let ChatItem::Message { msg_id } = item {
+                let msg = Message::load_from_db(&t, msg_id).await.unwrap();
+                if msg.is_info() {
+                    info_texts.push(msg.get_text().unwrap());
+                }
+            }
+        }
+        assert_eq!(
+            info_texts,
+            vec![
+                "Member erin@example.net added.",
+                "Member dave@example.net removed."
+            ]
+        );
+    }
+
+    #[async_std::test]
+    async fn test_older_member_list_not_applied() {
+        let t = TestContext::new().await;
+        t.configure_addr("bob@example.com").await;
+
+        recv_group_msg(
+            &t,
+            "bob@example.com, charlie@example.net",
+            "Sun, 22 Mar 2020 22:37:57 +0000",
+            "",
+        )
+        .await;
+        let chat_id = t.get_last_msg().await.chat_id;
+        recv_group_msg(
+            &t,
+            "bob@example.com, charlie@example.net",
+            "Mon, 23 Mar 2020 10:00:00 +0000",
+            "Chat-Group-Member-Removed: charlie@example.net\n",
+        )
+        .await;
+        assert_eq!(
+            get_member_addrs(&t, chat_id).await,
+            vec!["alice@example.org"]
+        );
+
+        // A message sent before Charlie was removed arrives late.
+        recv_group_msg(
+            &t,
+            "bob@example.com, charlie@example.net",
+            "Sun, 22 Mar 2020 23:00:00 +0000",
+            "",
+        )
+        .await;
+        assert_eq!(t.get_last_msg().await.chat_id, chat_id);
+        assert_eq!(
+            get_member_addrs(&t, chat_id).await,
+            vec!["alice@example.org"]
+        );
+
+        // Alice's clock runs ahead, the message may have been sent before the removal.
+        recv_group_msg(
+            &t,
+            "bob@example.com, charlie@example.net",
+            "Mon, 23 Mar 2020 10:02:00 +0000",
+            "",
+        )
+        .await;
+        assert_eq!(
+            get_member_addrs(&t, chat_id).await,
+            vec!["alice@example.org"]
+        );
+
+        // Newer messages are applied.
+        recv_group_msg(
+            &t,
+            "bob@example.com, charlie@example.net",
+            "Tue, 24 Mar 2020 10:00:00 +0000",
+            "",
+        )
+        .await;
+        assert_eq!(
+            get_member_addrs(&t, chat_id).await,
+            vec!["alice@example.org", "charlie@example.net"]
+        );
+    }
 }
@@ -339,7 +339,7 @@ pub async fn dc_delete_files_in_dir(context: &Context, path: impl AsRef<Path>) {
             while let Some(entry) = read_dir.next().await {
                 match entry {
                     Ok(file) => {
-                        dc_delete_file(context, file.file_name()).await;
+                        dc_delete_file(context, file.path()).await;
                     }
                     Err(e) => warn!(context, "Could not read file to delete: {}", e),
                 }
@@ -506,9 +506,12 @@ pub fn dc_open_file_std<P: AsRef<std::path::Path>>(
 
 /// Returns Ok((temp_path, dest_path)) on success. The backup can then be written to temp_path. If the backup succeeded,
 /// it can be renamed to dest_path. This guarantees that the backup is complete.
+///
+/// `suffix` is appended to the stem of the file name, e.g. for incremental backups.
 pub(crate) async fn get_next_backup_path(
     folder: impl AsRef<Path>,
     backup_time: i64,
+    suffix: &str,
 ) -> Result<(PathBuf, PathBuf), Error> {
     let folder = PathBuf::from(folder.as_ref());
     let stem = chrono::NaiveDateTime::from_timestamp(backup_time, 0)
@@ -519,10 +522,10 @@ pub(crate) async fn get_next_backup_path(
     // 64 backup files per day should be enough for everyone
     for i in 0..64 {
         let mut tempfile = folder.clone();
-        tempfile.push(format!("{}-{:02}.tar.part", stem, i));
+        tempfile.push(format!("{}-{:02}{}.tar.part", stem, i, suffix));
 
         let mut destfile = folder.clone();
-        destfile.push(format!("{}-{:02}.tar", stem, i));
+        destfile.push(format!("{}-{:02}{}.tar", stem, i, suffix));
 
         if !tempfile.exists().await && !destfile.exists().await {
             return Ok((tempfile, destfile));
@@ -640,6 +643,34 @@ fn to_sql(&self) -> rusqlite::Result<rusqlite::types::ToSqlOutput> {
     }
 }
 
+/// Normalizes an email address for storing and comparing it.
+///
+/// The policy is:
+/// - whitespace around the address and a `mailto:` prefix are removed,
+/// - the domain is lowercased and internationalized domains are converted
+///   to their ASCII form, e.g. `Bücher.Example` becomes `xn--bcher-kva.example`,
+/// - the local part is preserved as it is, as some servers treat it case-sensitively.
+///
+/// Normalized addresses refer to the same mailbox if they are equal ignoring the case
+/// of the local part, see [crate::contact::addr_cmp].  Addresses in the database are stored
+/// normalized, so they can be compared using `COLLATE NOCASE`.
+pub fn normalize_addr(addr: &str) -> String {
+    let addr = addr.trim();
+    let addr = addr.strip_prefix("mailto:").unwrap_or(addr);
+    match addr.rfind('@') {
+        Some(at) => {
+            let local = addr.get(..at).unwrap_or_default();
+            let domain = addr.get(at + 1..).unwrap_or_default();
+            let domain = match url::Host::parse(domain) {
+                Ok(url::Host::Domain(domain)) => domain,
+                _ => domain.to_lowercase(),
+            };
+            format!("{}@{}", local, domain)
+        }
+        None => addr.to_string(),
+    }
+}
+
 /// Makes sure that a user input that is not supposed to contain newlines does not contain newlines.
 pub(crate) fn improve_single_line_input(input: impl AsRef<str>) -> String {
     input
@@ -816,6 +847,24 @@ fn test_dc_create_outgoing_rfc724_mid() {
         );
     }
 
+    #[test]
+    fn test_normalize_addr() {
+        assert_eq!(normalize_addr("mailto:john@doe.com"), "john@doe.com");
+        assert_eq!(normalize_addr("  hello@world.com   "), "hello@world.com");
+        assert_eq!(normalize_addr("John@Doe.COM"), "John@doe.com");
+        assert_eq!(
+            normalize_addr("info@Bücher.example"),
+            "info@xn--bcher-kva.example"
+        );
+        assert_eq!(
+            normalize_addr("info@xn--bcher-kva.example"),
+            "info@xn--bcher-kva.example"
+        );
+        assert_eq!(normalize_addr("a@b@Example.org"), "a@b@example.org");
+        assert_eq!(normalize_addr("no-address"), "no-address");
+        assert_eq!(normalize_addr(""), "");
+    }
+
     #[test]
     fn test_emailaddress_parse() {
         assert_eq!("".parse::<EmailAddress>().is_ok(), false);
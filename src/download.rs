@@ -0,0 +1,450 @@
+//! # Download messages on demand
+//!
+//! Messages larger than the `download_limit` config are not downloaded completely.
+//! Instead, only their header is downloaded and a placeholder message is added to the chat,
+//! its download state is [DownloadState::Undownloaded] and it stores the size of the full message
+//! in [Param::FullMessageSize]. Group changes and other side effects of the message
+//! are applied only when the full message is received.
+//!
+//! When the user requests the full message using [MsgId::download_full],
+//! a job downloads it from the server and passes it to `dc_receive_imf()` again.
+//! The placeholder keeps its ID and chat, but is replaced by the full message.
+//!
+//! Attachments sent in several parts use the same download states, see [crate::chunks].
+
+use anyhow::{bail, Result};
+use deltachat_derive::{FromSql, ToSql};
+use serde::{Deserialize, Serialize};
+
+use crate::chat::ChatId;
+use crate::chunks;
+use crate::context::Context;
+use crate::events::EventType;
+use crate::imap::{ImapActionResult, ImapSession};
+use crate::job::{self, Action, Job};
+use crate::message::{Message, MsgId};
+use crate::param::{Param, Params};
+
+/// Download state of a message.
+#[derive(
+    Debug,
+    Display,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    FromPrimitive,
+    ToPrimitive,
+    FromSql,
+    ToSql,
+    Serialize,
+    Deserialize,
+)]
+#[repr(u32)]
+pub enum DownloadState {
+    /// The message is downloaded completely.
+    Done = 0,
+
+    /// Only the header of the message is downloaded.
+    Undownloaded = 10,
+
+    /// The full message is being downloaded.
+    InProgress = 20,
+
+    /// Downloading the full message failed, see [Message::error] for the reason.
+    /// The download can be started again.
+    Failure = 30,
+}
+
+impl Default for DownloadState {
+    fn default() -> Self {
+        DownloadState::Done
+    }
+}
+
+impl Message {
+    /// Returns whether the message is downloaded completely.
+    pub fn get_download_state(&self) -> DownloadState {
+        self.download_state
+    }
+}
+
+impl MsgId {
+    /// Schedules downloading the full message.
+    ///
+    /// The message is replaced by the full message once it is downloaded,
+    /// `MsgsChanged` is emitted on each change of the download state.
+    pub async fn download_full(self, context: &Context) -> Result<()> {
+        let msg = Message::load_from_db(context, self).await?;
+        if msg.param.exists(Param::ChunkManifest) && msg.download_state != DownloadState::Done {
+            // The attachment is sent in several parts, request the missing ones.
+            return chunks::request_missing(context, &msg).await;
+        }
+        match msg.download_state {
+            DownloadState::Done => bail!("Message {} is already downloaded", self),
+            DownloadState::InProgress => info!(context, "Message {} is being downloaded", self),
+            DownloadState::Undownloaded | DownloadState::Failure => {
+                set_download_state(context, self, DownloadState::InProgress, None).await?;
+                job::add(
+                    context,
+                    Job::new(Action::DownloadMsg, self.to_u32(), Params::new(), 0),
+                )
+                .await;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Sets the download state of a message, `error` replaces the error of the message.
+pub(crate) async fn set_download_state(
+    context: &Context,
+    msg_id: MsgId,
+    state: DownloadState,
+    error: Option<&str>,
+) -> Result<()> {
+    context
+        .sql
+        .execute(
+            "UPDATE msgs SET download_state=?, error=? WHERE id=?;",
+            paramsv![state, error.unwrap_or_default(), msg_id],
+        )
+        .await?;
+    let chat_id: ChatId = context
+        .sql
+        .query_get_value(
+            context,
+            "SELECT chat_id FROM msgs WHERE id=?;",
+            paramsv![msg_id],
+        )
+        .await
+        .unwrap_or_default();
+    context.emit_event(EventType::MsgsChanged { chat_id, msg_id });
+    Ok(())
+}
+
+/// Downloads the full message from the server, replacing the partially downloaded one.
+pub(crate) async fn download_msg(
+    context: &Context,
+    msg_id: MsgId,
+    session: &mut impl ImapSession,
+) -> ImapActionResult {
+    let msg = match Message::load_from_db(context, msg_id).await {
+        Ok(msg) => msg,
+        Err(err) => {
+            warn!(
+                context,
+                "Cannot load message {} to download: {}", msg_id, err
+            );
+            return ImapActionResult::Failed;
+        }
+    };
+    if msg.download_state == DownloadState::Done {
+        return ImapActionResult::AlreadyDone;
+    }
+
+    let folder = msg.server_folder.unwrap_or_default();
+    session
+        .fetch_single_msg(context, &folder, msg.server_uid)
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use async_std::channel;
+    use async_std::net::TcpListener;
+
+    use super::*;
+    use crate::chat::{self, get_chat_msgs, Chat, ChatItem, ProtectionStatus};
+    use crate::config::Config;
+    use crate::constants::DC_FOLDERS_CONFIGURED_VERSION;
+    use crate::contact::{self, Contact};
+    use crate::dc_receive_imf::dc_receive_imf_inner;
+    use crate::dc_tools::time;
+    use crate::ephemeral::Timer as EphemeralTimer;
+    use crate::imap;
+    use crate::job::{Connection, Status, Thread};
+    use crate::login_param::Socket;
+    use crate::message;
+    use crate::scheduler::InterruptInfo;
+    use crate::test_utils::{chat_msg, MockSession, TestContext};
+
+    /// Configures `t` to use the IMAP server at `port` with an already configured INBOX.
+    async fn configure_imap(t: &TestContext, port: u16) {
+        let plain = (Socket::Plain as i32).to_string();
+        for (key, value) in &[
+            (Config::ConfiguredMailServer, "127.0.0.1".to_string()),
+            (Config::ConfiguredMailPort, port.to_string()),
+            (Config::ConfiguredMailSecurity, plain),
+            (Config::ConfiguredMailUser, "alice@example.org".to_string()),
+            (Config::ConfiguredMailPw, "123456".to_string()),
+            (Config::ConfiguredInboxFolder, "INBOX".to_string()),
+        ] {
+            t.set_config(*key, Some(value.as_str())).await.unwrap();
+        }
+        t.sql
+            .set_raw_config_int(&t, "folders_configured", DC_FOLDERS_CONFIGURED_VERSION)
+            .await
+            .unwrap();
+        imap::set_uidvalidity(&t, "INBOX", 1).await.unwrap();
+        imap::set_uid_next(&t, "INBOX", 1).await.unwrap();
+    }
+
+    /// Returns a server with a large and a small message in INBOX.
+    fn server() -> MockSession {
+        let mut server = MockSession::default();
+        server.store(
+            "INBOX",
+            1,
+            chat_msg(
+                "bob@example.net",
+                "big@example.net",
+                &format!("Here they are: {}", "x".repeat(5000)),
+            ),
+        );
+        server.store(
+            "INBOX",
+            2,
+            chat_msg("bob@example.net", "small@example.net", "Did you get them?"),
+        );
+        server
+    }
+
+    async fn get_msg(t: &TestContext, rfc724_mid: &str) -> Message {
+        let (_, _, msg_id) = message::rfc724_mid_exists(&t, rfc724_mid)
+            .await
+            .unwrap()
+            .unwrap();
+        Message::load_from_db(&t, msg_id).await.unwrap()
+    }
+
+    async fn new_imap() -> imap::Imap {
+        let (_sender, receiver) = channel::bounded(1);
+        imap::Imap::new(receiver)
+    }
+
+    /// Receives INBOX of `server` like fetching it with a `download_limit` of 1000,
+    /// returns the ID of the partially downloaded message.
+    async fn receive_partially(t: &TestContext, server: &MockSession) -> MsgId {
+        for uid in server.uids("INBOX") {
+            let raw = server.get("INBOX", uid).unwrap();
+            if raw.len() > 1000 {
+                let (_, header_len) = mailparse::parse_headers(raw).unwrap();
+                let header = raw.get(..header_len).unwrap();
+                let size = Some(raw.len() as u32);
+                dc_receive_imf_inner(t, header, "INBOX", uid, false, size, false, false)
+                    .await
+                    .unwrap();
+            } else {
+                dc_receive_imf_inner(t, raw, "INBOX", uid, false, None, false, false)
+                    .await
+                    .unwrap();
+            }
+        }
+
+        let msg = get_msg(t, "small@example.net").await;
+        assert_eq!(msg.get_download_state(), DownloadState::Done);
+        let msg = get_msg(t, "big@example.net").await;
+        assert_eq!(msg.get_download_state(), DownloadState::Undownloaded);
+        msg.id
+    }
+
+    async fn run_download_job(t: &TestContext, server: &mut MockSession) -> Status {
+        let mut job = job::load_next(t, Thread::Imap, &InterruptInfo::new(false, None))
+            .await
+            .unwrap();
+        assert_eq!(job.action, Action::DownloadMsg);
+        job.download_msg(t, server).await
+    }
+
+    #[async_std::test]
+    async fn test_partial_download_creates_stub() {
+        let t = TestContext::new_alice().await;
+        let server = server();
+
+        let msg_id = receive_partially(&t, &server).await;
+        let msg = Message::load_from_db(&t, msg_id).await.unwrap();
+        assert_eq!(msg.get_text().unwrap(), "[6 KiB message]");
+        assert_eq!(msg.server_uid, 1);
+        assert_eq!(msg.server_folder.as_deref(), Some("INBOX"));
+        let full_size = server.get("INBOX", 1).unwrap().len();
+        assert_eq!(
+            msg.param.get_i64(Param::FullMessageSize),
+            Some(full_size as i64)
+        );
+        assert!(msg.error.is_none());
+
+        // Fetching again does not add the message twice.
+        receive_partially(&t, &server).await;
+        assert_eq!(get_chat_msgs(&t, msg.chat_id, 0, None).await.len(), 2);
+
+        let small_msg = get_msg(&t, "small@example.net").await;
+        assert!(small_msg.id.download_full(&t).await.is_err());
+    }
+
+    #[async_std::test]
+    async fn test_download_full() {
+        let t = TestContext::new_alice().await;
+        let mut server = server();
+
+        let msg_id = receive_partially(&t, &server).await;
+        let chat_id = Message::load_from_db(&t, msg_id).await.unwrap().chat_id;
+
+        msg_id.download_full(&t).await.unwrap();
+        let msg = Message::load_from_db(&t, msg_id).await.unwrap();
+        assert_eq!(msg.get_download_state(), DownloadState::InProgress);
+
+        assert!(matches!(
+            run_download_job(&t, &mut server).await,
+            Status::Finished(Ok(()))
+        ));
+        assert_eq!(server.downloaded, vec![1]);
+        let msg = Message::load_from_db(&t, msg_id).await.unwrap();
+        assert_eq!(msg.get_download_state(), DownloadState::Done);
+        assert_eq!(msg.chat_id, chat_id);
+        assert!(msg.get_text().unwrap().starts_with("Here they are: xxx"));
+        assert_eq!(msg.server_uid, 1);
+        assert!(!msg.param.exists(Param::FullMessageSize));
+
+        let msgs = get_chat_msgs(&t, chat_id, 0, None).await;
+        assert_eq!(msgs.len(), 2);
+        assert!(msgs
+            .iter()
+            .any(|item| matches!(item, ChatItem::Message { msg_id: id } if *id == msg_id)));
+
+        // Downloading again is an error.
+        assert!(msg_id.download_full(&t).await.is_err());
+    }
+
+    #[async_std::test]
+    async fn test_download_full_failure() {
+        let t = TestContext::new_alice().await;
+        let mut server = server();
+
+        let msg_id = receive_partially(&t, &server).await;
+
+        // The message was deleted from the server by another client.
+        server.folders.clear();
+        msg_id.download_full(&t).await.unwrap();
+        assert!(matches!(
+            run_download_job(&t, &mut server).await,
+            Status::Finished(Err(_))
+        ));
+
+        let msg = Message::load_from_db(&t, msg_id).await.unwrap();
+        assert_eq!(msg.get_download_state(), DownloadState::Failure);
+        assert!(msg.error.is_some());
+        assert_eq!(msg.get_text().unwrap(), "[6 KiB message]");
+
+        // The download can be retried.
+        msg_id.download_full(&t).await.unwrap();
+        let msg = Message::load_from_db(&t, msg_id).await.unwrap();
+        assert_eq!(msg.get_download_state(), DownloadState::InProgress);
+        assert!(msg.error.is_none());
+    }
+
+    #[async_std::test]
+    async fn test_partial_download_no_side_effects() {
+        let t = TestContext::new_alice().await;
+        let bob_id = Contact::create(&t, "Bob", "bob@example.net").await.unwrap();
+        contact::set_status(&t, bob_id, "Sent from my phone".to_string())
+            .await
+            .unwrap();
+        let chat_id = chat::create_group_chat(&t, ProtectionStatus::Unprotected, "Group")
+            .await
+            .unwrap();
+        chat::add_contact_to_chat(&t, chat_id, bob_id).await;
+        let grpid = Chat::load_from_db(&t, chat_id).await.unwrap().grpid;
+
+        let header = format!(
+            "From: Bob <bob@example.net>\r\n\
+             To: alice@example.org\r\n\
+             Subject: Group\r\n\
+             Message-ID: <left@example.net>\r\n\
+             Chat-Version: 1.0\r\n\
+             Chat-Group-ID: {}\r\n\
+             Chat-Group-Name: Group\r\n\
+             Chat-Group-Member-Removed: bob@example.net\r\n\
+             Chat-User-Avatar: 0\r\n\
+             Ephemeral-Timer: 60\r\n\
+             Date: Sun, 22 Mar 2020 22:37:57 +0000\r\n\
+             \r\n",
+            grpid
+        );
+        dc_receive_imf_inner(
+            &t,
+            header.as_bytes(),
+            "INBOX",
+            1,
+            false,
+            Some(100_000),
+            false,
+            false,
+        )
+        .await
+        .unwrap();
+
+        // The placeholder is added to the group, but the group is not changed.
+        let msg = get_msg(&t, "left@example.net").await;
+        assert_eq!(msg.get_download_state(), DownloadState::Undownloaded);
+        assert_eq!(msg.chat_id, chat_id);
+        assert!(chat::is_contact_in_chat(&t, chat_id, bob_id).await);
+        assert_eq!(
+            chat_id.get_ephemeral_timer(&t).await.unwrap(),
+            EphemeralTimer::Disabled
+        );
+
+        // The contact is not changed as the footer is not downloaded.
+        let bob = Contact::load_from_db(&t, bob_id).await.unwrap();
+        assert_eq!(bob.get_status(), "Sent from my phone");
+    }
+
+    #[async_std::test]
+    async fn test_download_full_network_back() {
+        let t = TestContext::new_alice().await;
+        let msg_id = receive_partially(&t, &server()).await;
+
+        // The server is not reachable.
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let closed_port = listener.local_addr().unwrap().port();
+        drop(listener);
+        configure_imap(&t, closed_port).await;
+        let mut imap = new_imap().await;
+        msg_id.download_full(&t).await.unwrap();
+        t.sql
+            .execute(
+                "UPDATE jobs SET tries=10 WHERE action=?;",
+                paramsv![Action::DownloadMsg],
+            )
+            .await
+            .unwrap();
+        let start = time();
+        let job = job::load_next(&t, Thread::Imap, &InterruptInfo::new(false, None))
+            .await
+            .unwrap();
+        assert_eq!(job.action, Action::DownloadMsg);
+        job::perform_job(&t, Connection::Inbox(&mut imap), job).await;
+
+        // The download is retried within the hour ...
+        let msg = Message::load_from_db(&t, msg_id).await.unwrap();
+        assert_eq!(msg.get_download_state(), DownloadState::InProgress);
+        let (tries, desired_timestamp): (u32, i64) = t
+            .sql
+            .query_row(
+                "SELECT tries, desired_timestamp FROM jobs WHERE action=?;",
+                paramsv![Action::DownloadMsg],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .await
+            .unwrap();
+        assert_eq!(tries, 11);
+        assert!(desired_timestamp >= start + 30 * 60);
+        assert!(desired_timestamp <= time() + 60 * 60);
+
+        // ... and at once if the network may be back.
+        let job = job::load_next(&t, Thread::Imap, &InterruptInfo::new(true, None))
+            .await
+            .unwrap();
+        assert_eq!(job.action, Action::DownloadMsg);
+    }
+}
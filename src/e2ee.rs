@@ -66,45 +66,7 @@ pub fn should_encrypt(
         e2ee_guaranteed: bool,
         peerstates: &[(Option<Peerstate>, &str)],
     ) -> Result<bool> {
-        let mut prefer_encrypt_count = if self.prefer_encrypt == EncryptPreference::Mutual {
-            1
-        } else {
-            0
-        };
-        for (peerstate, addr) in peerstates {
-            match peerstate {
-                Some(peerstate) => {
-                    info!(
-                        context,
-                        "peerstate for {:?} is {}", addr, peerstate.prefer_encrypt
-                    );
-                    match peerstate.prefer_encrypt {
-                        EncryptPreference::NoPreference => {}
-                        EncryptPreference::Mutual => prefer_encrypt_count += 1,
-                        EncryptPreference::Reset => {
-                            if !e2ee_guaranteed {
-                                return Ok(false);
-                            }
-                        }
-                    };
-                }
-                None => {
-                    let msg = format!("peerstate for {:?} missing, cannot encrypt", addr);
-                    if e2ee_guaranteed {
-                        return Err(format_err!("{}", msg));
-                    } else {
-                        info!(context, "{}", msg);
-                        return Ok(false);
-                    }
-                }
-            }
-        }
-
-        // Count number of recipients, including self.
-        // This does not depend on whether we send a copy to self or not.
-        let recipients_count = peerstates.len() + 1;
-
-        Ok(e2ee_guaranteed || 2 * prefer_encrypt_count > recipients_count)
+        should_encrypt(context, self.prefer_encrypt, e2ee_guaranteed, peerstates)
     }
 
     /// Tries to encrypt the passed in `mail`.
@@ -137,6 +99,57 @@ pub async fn encrypt(
     }
 }
 
+/// Determines if a message to the given peers should be encrypted,
+/// see [EncryptHelper::should_encrypt].
+///
+/// `own_preference` is the encryption preference of the user.
+pub(crate) fn should_encrypt(
+    context: &Context,
+    own_preference: EncryptPreference,
+    e2ee_guaranteed: bool,
+    peerstates: &[(Option<Peerstate>, &str)],
+) -> Result<bool> {
+    let mut prefer_encrypt_count = if own_preference == EncryptPreference::Mutual {
+        1
+    } else {
+        0
+    };
+    for (peerstate, addr) in peerstates {
+        match peerstate {
+            Some(peerstate) => {
+                info!(
+                    context,
+                    "peerstate for {:?} is {}", addr, peerstate.prefer_encrypt
+                );
+                match peerstate.prefer_encrypt {
+                    EncryptPreference::NoPreference => {}
+                    EncryptPreference::Mutual => prefer_encrypt_count += 1,
+                    EncryptPreference::Reset => {
+                        if !e2ee_guaranteed {
+                            return Ok(false);
+                        }
+                    }
+                };
+            }
+            None => {
+                let msg = format!("peerstate for {:?} missing, cannot encrypt", addr);
+                if e2ee_guaranteed {
+                    return Err(format_err!("{}", msg));
+                } else {
+                    info!(context, "{}", msg);
+                    return Ok(false);
+                }
+            }
+        }
+    }
+
+    // Count number of recipients, including self.
+    // This does not depend on whether we send a copy to self or not.
+    let recipients_count = peerstates.len() + 1;
+
+    Ok(e2ee_guaranteed || 2 * prefer_encrypt_count > recipients_count)
+}
+
 /// Tries to decrypt a message, but only if it is structured as an
 /// Autocrypt message.
 ///
@@ -145,10 +158,14 @@ pub async fn encrypt(
 ///
 /// If the message is wrongly signed, this will still return the decrypted
 /// message but the HashSet will be empty.
+///
+/// If `apply_autocrypt` is false, the peerstate of the sender is not updated
+/// from the Autocrypt header, e.g. for imported old messages.
 pub async fn try_decrypt(
     context: &Context,
     mail: &ParsedMail<'_>,
     message_time: i64,
+    apply_autocrypt: bool,
 ) -> Result<(Option<Vec<u8>>, HashSet<Fingerprint>)> {
     let from = mail
         .headers
@@ -161,7 +178,9 @@ pub async fn try_decrypt(
     let mut peerstate = Peerstate::from_addr(context, &from).await?;
 
     // Apply Autocrypt header
-    if let Some(ref header) = Aheader::from_headers(context, &from, &mail.headers) {
+    if let Some(ref header) =
+        Aheader::from_headers(context, &from, &mail.headers).filter(|_| apply_autocrypt)
+    {
         if let Some(ref mut peerstate) = peerstate {
             peerstate.apply_header(header, message_time);
             peerstate.save_to_db(&context.sql, false).await?;
@@ -197,7 +216,8 @@ pub async fn try_decrypt(
 
     if let Some(mut peerstate) = peerstate {
         // If message is not encrypted and it is not a read receipt, degrade encryption.
-        if out_mail.is_none()
+        if apply_autocrypt
+            && out_mail.is_none()
             && message_time > peerstate.last_seen_autocrypt
             && !contains_report(mail)
         {
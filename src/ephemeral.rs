@@ -34,6 +34,14 @@
 //! time after which device will delete the messages it knows about
 //! from the server.
 //!
+//! The settings are independent: with only `delete_device_after` set,
+//! messages deleted from the device stay on the server. Their database
+//! entries are marked as deleted locally and kept while the message may
+//! be on the server, so the message is not downloaded again, e.g. after
+//! it was moved to another folder. If `delete_server_after` is set as
+//! well, the message is deleted from the server once it expires
+//! according to that setting and the database entry is removed then.
+//!
 //! ## How messages are deleted
 //!
 //! When the message is deleted locally, its contents is removed and
@@ -48,18 +56,34 @@
 //!
 //! ## When messages are deleted
 //!
-//! Local deletion happens when the chatlist or chat is loaded. A
-//! `MsgsChanged` event is emitted when a message deletion is due, to
-//! make UI reload displayed messages and cause actual deletion.
+//! Local deletion of ephemeral messages happens when the chatlist or
+//! chat is loaded. A `MsgsChanged` event is emitted when a message
+//! deletion is due, to make UI reload displayed messages and cause
+//! actual deletion.
+//!
+//! Messages older than `delete_device_after` are deleted in batches by
+//! a periodic task while IO is running, see
+//! [schedule_device_deletion_task].
+//!
+//! While a backup or chat export is running, it holds a
+//! [DeletionHold]. Local deletion is then deferred, timers keep
+//! running, and expired messages are deleted once the last hold is
+//! released.
 //!
 //! Server deletion happens by generating IMAP deletion jobs based on
 //! the database entries which are expired either according to their
 //! ephemeral message timers or global `delete_server_after` setting.
+//!
+//! ## Timed chat mutes
+//!
+//! Chats muted for a duration are unmuted by a similar task, see
+//! [schedule_unmute_task].
 
 use std::convert::{TryFrom, TryInto};
 use std::num::ParseIntError;
 use std::str::FromStr;
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use anyhow::{ensure, Error};
 use async_std::task;
@@ -70,10 +94,11 @@
     Viewtype, DC_CHAT_ID_LAST_SPECIAL, DC_CHAT_ID_TRASH, DC_CONTACT_ID_DEVICE, DC_CONTACT_ID_SELF,
 };
 use crate::context::Context;
-use crate::dc_tools::time;
+use crate::dc_tools::{dc_delete_file, time};
 use crate::events::EventType;
 use crate::message::{Message, MessageState, MsgId};
 use crate::mimeparser::SystemMessage;
+use crate::param::{Param, Params};
 use crate::sql;
 use crate::stock_str;
 
@@ -194,6 +219,14 @@ pub async fn set_ephemeral_timer(self, context: &Context, timer: Timer) -> Resul
             return Ok(());
         }
         self.inner_set_ephemeral_timer(context, timer).await?;
+        self.send_ephemeral_timer_changed(context, timer).await;
+        Ok(())
+    }
+
+    /// Sends a message telling the chat members that the ephemeral timer is set to `timer`.
+    ///
+    /// Failures are only logged, the timer is already changed locally.
+    pub(crate) async fn send_ephemeral_timer_changed(self, context: &Context, timer: Timer) {
         let mut msg = Message::new(Viewtype::Text);
         msg.text = Some(stock_ephemeral_timer_changed(context, timer, DC_CONTACT_ID_SELF).await);
         msg.param.set_cmd(SystemMessage::EphemeralTimerChanged);
@@ -203,7 +236,6 @@ pub async fn set_ephemeral_timer(self, context: &Context, timer: Timer) -> Resul
                 "Failed to send a message about ephemeral message timer change: {:?}", err
             );
         }
-        Ok(())
     }
 }
 
@@ -296,71 +328,393 @@ pub(crate) async fn start_ephemeral_timer(self, context: &Context) -> crate::sql
     }
 }
 
+/// Numbers of the items removed by [delete_expired_messages].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ExpiredDeletion {
+    /// Number of expired messages moved to the trash.
+    pub messages: usize,
+    /// Number of blobs deleted from the blob directory.
+    pub blobs: usize,
+    /// Number of deleted read receipts.
+    pub mdns: usize,
+}
+
+impl ExpiredDeletion {
+    /// Returns true if any message has been deleted.
+    pub fn any_deleted(&self) -> bool {
+        self.messages > 0
+    }
+}
+
 /// Deletes messages which are expired according to
-/// `delete_device_after` setting or `ephemeral_timestamp` column.
+/// `ephemeral_timestamp` column.
+///
+/// Messages expired according to `delete_device_after` setting are deleted
+/// by [delete_device_expired_messages].
 ///
-/// Returns true if any message is deleted, so caller can emit
-/// MsgsChanged event. If nothing has been deleted, returns
-/// false. This function does not emit the MsgsChanged event itself,
-/// because it is also called when chatlist is reloaded, and emitting
-/// MsgsChanged there will cause infinite reload loop.
-pub(crate) async fn delete_expired_messages(context: &Context) -> Result<bool, Error> {
-    let mut updated = context
+/// Returns the numbers of deleted messages, blobs and MDNs, so caller can
+/// emit MsgsChanged event if any message is deleted. This function does
+/// not emit the MsgsChanged event itself, because it is also called when
+/// chatlist is reloaded, and emitting MsgsChanged there will cause
+/// infinite reload loop.
+pub(crate) async fn delete_expired_messages(context: &Context) -> Result<ExpiredDeletion, Error> {
+    delete_expired_messages_limited(context, usize::MAX).await
+}
+
+/// Like [delete_expired_messages], but deletes at most `limit` messages.
+pub(crate) async fn delete_expired_messages_limited(
+    context: &Context,
+    limit: usize,
+) -> Result<ExpiredDeletion, Error> {
+    if context.sql.is_readonly() {
+        return Ok(ExpiredDeletion::default());
+    }
+    if context.deletion_holds.load(Ordering::SeqCst) > 0 {
+        info!(context, "Deletion of expired messages is on hold.");
+        return Ok(ExpiredDeletion::default());
+    }
+    check_clock_jump(context).await;
+
+    let now = time();
+    let limit = i64::try_from(limit).unwrap_or(i64::MAX);
+
+    let (deleted, files, mdns) = context
         .sql
-        .execute(
-            // If you change which information is removed here, also change MsgId::trash() and
-            // which information dc_receive_imf::add_parts() still adds to the db if the chat_id is TRASH
-            "UPDATE msgs \
-             SET chat_id=?, txt='', subject='', txt_raw='', mime_headers='', from_id=0, to_id=0, param='' \
-             WHERE \
-             ephemeral_timestamp != 0 \
-             AND ephemeral_timestamp <= ? \
-             AND chat_id != ?",
-            paramsv![DC_CHAT_ID_TRASH, time(), DC_CHAT_ID_TRASH],
-        )
-        .await?
-        > 0;
+        .with_conn(move |mut conn| {
+            let tx = conn.transaction()?;
+            let mut expired: Vec<(MsgId, String)> = Vec::new();
+            {
+                let mut stmt = tx.prepare(
+                    "SELECT id, param FROM msgs \
+                     WHERE ephemeral_timestamp != 0 \
+                     AND ephemeral_timestamp <= ? \
+                     AND chat_id != ? \
+                     LIMIT ?",
+                )?;
+                let rows = stmt
+                    .query_map(rusqlite::params![now, DC_CHAT_ID_TRASH, limit], |row| {
+                        Ok((row.get(0)?, row.get(1)?))
+                    })?;
+                for row in rows {
+                    expired.push(row?);
+                }
+            }
+            for (msg_id, _) in &expired {
+                // If you change which information is removed here, also change MsgId::trash() and
+                // which information dc_receive_imf::add_parts() still adds to the db if the chat_id is TRASH
+                tx.execute(
+                    "UPDATE msgs \
+                     SET chat_id=?, txt='', txt_folded='', subject='', txt_raw='', mime_headers='', from_id=0, to_id=0, param='' \
+                     WHERE id=?",
+                    rusqlite::params![DC_CHAT_ID_TRASH, msg_id],
+                )?;
+            }
+            let deleted = expired.len();
 
-    if let Some(delete_device_after) = context.get_config_delete_device_after().await {
-        let self_chat_id = lookup_by_contact_id(context, DC_CONTACT_ID_SELF)
-            .await
-            .unwrap_or_default()
-            .0;
-        let device_chat_id = lookup_by_contact_id(context, DC_CONTACT_ID_DEVICE)
-            .await
-            .unwrap_or_default()
-            .0;
+            let (mdns, files) = delete_references(&tx, expired)?;
+            tx.commit()?;
+            Ok((deleted, files, mdns))
+        })
+        .await?;
+
+    let blobs = delete_unreferenced_files(context, files).await?;
+    if deleted > 0 {
+        info!(
+            context,
+            "Deleted {} expired messages, {} blobs and {} MDNs.", deleted, blobs, mdns
+        );
+        context.emit_event(EventType::EphemeralCleanupDone {
+            deleted_msgs: deleted,
+            deleted_blobs: blobs,
+        });
+    }
+
+    schedule_ephemeral_task(context).await;
+    Ok(ExpiredDeletion {
+        messages: deleted,
+        blobs,
+        mdns,
+    })
+}
+
+/// Removes the data referencing deleted messages from other tables.
+///
+/// Returns the number of deleted MDNs and the files of the messages.
+fn delete_references(
+    tx: &rusqlite::Transaction,
+    expired: Vec<(MsgId, String)>,
+) -> rusqlite::Result<(usize, Vec<String>)> {
+    let mut mdns = 0;
+    let mut files = Vec::new();
+    for (msg_id, param) in expired {
+        mdns += tx.execute(
+            "DELETE FROM msgs_mdns WHERE msg_id=?;",
+            rusqlite::params![msg_id],
+        )?;
+        tx.execute(
+            "DELETE FROM msgs_broadcast WHERE msg_id=?;",
+            rusqlite::params![msg_id],
+        )?;
+        tx.execute(
+            "DELETE FROM reactions WHERE msg_id=?;",
+            rusqlite::params![msg_id],
+        )?;
+        tx.execute(
+            "DELETE FROM msgs_status_updates WHERE msg_id=?;",
+            rusqlite::params![msg_id],
+        )?;
+        tx.execute(
+            "DELETE FROM msgs_chunks WHERE msg_id=?;",
+            rusqlite::params![msg_id],
+        )?;
+        // The label itself is kept, so that device messages are not added again.
+        tx.execute(
+            "UPDATE devmsglabels SET msg_id=0 WHERE msg_id=?;",
+            rusqlite::params![msg_id],
+        )?;
+        if let Some(file) = param.parse::<Params>().unwrap_or_default().get(Param::File) {
+            files.push(file.to_string());
+        }
+    }
+    Ok((mdns, files))
+}
+
+/// Deletes the files that are not referenced anymore, returns the number of deleted files.
+async fn delete_unreferenced_files(context: &Context, files: Vec<String>) -> sql::Result<usize> {
+    let mut blobs = 0;
+    for file in files {
+        if !file.starts_with("$BLOBDIR") || is_file_referenced(context, &file).await? {
+            continue;
+        }
+        if dc_delete_file(context, &file).await {
+            blobs += 1;
+        }
+    }
+    Ok(blobs)
+}
+
+/// Number of messages deleted per transaction by [delete_device_expired_messages].
+const DEVICE_DELETION_BATCH_SIZE: usize = 500;
+
+/// Interval between two runs of [schedule_device_deletion_task].
+const DEVICE_DELETION_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Deletes messages older than the `delete_device_after` setting.
+///
+/// Messages in "Saved messages" and the device chat as well as unseen messages are kept.
+/// The messages are deleted in batches, so the deletion can be canceled between
+/// batches by dropping the future.  Emits #DC_EVENT_DEVICE_CLEANUP_DONE if any
+/// message was deleted.
+///
+/// The tombstones of the deleted messages are marked as deleted locally and kept
+/// as long as the message is on the server, so the message is not downloaded again.
+/// If `delete_server_after` is set, the message is deleted from the server once it
+/// is expired according to that setting, removing the tombstone.
+///
+/// Returns the number of deleted messages and blobs.
+pub(crate) async fn delete_device_expired_messages(
+    context: &Context,
+) -> Result<(usize, usize), Error> {
+    delete_device_expired_messages_limited(context, usize::MAX).await
+}
 
-        let threshold_timestamp = time() - delete_device_after;
+/// Like [delete_device_expired_messages], but deletes at most `limit` messages.
+pub(crate) async fn delete_device_expired_messages_limited(
+    context: &Context,
+    limit: usize,
+) -> Result<(usize, usize), Error> {
+    let delete_device_after = match context.get_config_delete_device_after().await {
+        Some(delete_device_after) => delete_device_after,
+        None => return Ok((0, 0)),
+    };
+    if context.sql.is_readonly() {
+        return Ok((0, 0));
+    }
+
+    let now = time();
+    let threshold_timestamp = now - delete_device_after;
+    let self_chat_id = lookup_by_contact_id(context, DC_CONTACT_ID_SELF)
+        .await
+        .unwrap_or_default()
+        .0;
+    let device_chat_id = lookup_by_contact_id(context, DC_CONTACT_ID_DEVICE)
+        .await
+        .unwrap_or_default()
+        .0;
 
-        // Delete expired messages
-        //
-        // Only update the rows that have to be updated, to avoid emitting
-        // unnecessary "chat modified" events.
-        let rows_modified = context
+    let mut deleted_msgs = 0;
+    let mut deleted_blobs = 0;
+    while deleted_msgs < limit {
+        if context.deletion_holds.load(Ordering::SeqCst) > 0 {
+            info!(context, "Deletion of old messages is on hold.");
+            break;
+        }
+        let batch_size = (limit - deleted_msgs).min(DEVICE_DELETION_BATCH_SIZE) as i64;
+        let (deleted, files) = context
             .sql
-            .execute(
-                "UPDATE msgs \
-             SET txt = 'DELETED', chat_id = ? \
-             WHERE timestamp < ? \
-             AND chat_id > ? \
-             AND chat_id != ? \
-             AND chat_id != ?",
-                paramsv![
-                    DC_CHAT_ID_TRASH,
-                    threshold_timestamp,
-                    DC_CHAT_ID_LAST_SPECIAL,
-                    self_chat_id,
-                    device_chat_id
-                ],
-            )
+            .with_conn(move |mut conn| {
+                let tx = conn.transaction()?;
+                let mut expired: Vec<(MsgId, String)> = Vec::new();
+                {
+                    let mut stmt = tx.prepare(
+                        "SELECT id, param FROM msgs \
+                         WHERE timestamp < ? \
+                         AND chat_id > ? \
+                         AND chat_id != ? \
+                         AND chat_id != ? \
+                         AND state NOT IN (?, ?) \
+                         LIMIT ?",
+                    )?;
+                    let rows = stmt.query_map(
+                        rusqlite::params![
+                            threshold_timestamp,
+                            DC_CHAT_ID_LAST_SPECIAL,
+                            self_chat_id,
+                            device_chat_id,
+                            MessageState::InFresh,
+                            MessageState::InNoticed,
+                            batch_size
+                        ],
+                        |row| Ok((row.get(0)?, row.get(1)?)),
+                    )?;
+                    for row in rows {
+                        expired.push(row?);
+                    }
+                }
+                for (msg_id, _) in &expired {
+                    // If you change which information is removed here, also change MsgId::trash() and
+                    // which information dc_receive_imf::add_parts() still adds to the db if the chat_id is TRASH
+                    //
+                    // The tombstone is not deleted on the server, see `cleanup::delete_trashed()`,
+                    // and pruned as described at `sql::prune_tombstones()`.
+                    tx.execute(
+                        "UPDATE msgs \
+                         SET chat_id=?, txt='', txt_folded='', subject='', txt_raw='', mime_headers='', \
+                         from_id=0, to_id=0, param='', deleted_locally=1, deleted_timestamp=? \
+                         WHERE id=?",
+                        rusqlite::params![DC_CHAT_ID_TRASH, now, msg_id],
+                    )?;
+                }
+                let deleted = expired.len();
+                let (_, files) = delete_references(&tx, expired)?;
+                tx.commit()?;
+                Ok((deleted, files))
+            })
             .await?;
+        if deleted == 0 {
+            break;
+        }
+        deleted_msgs += deleted;
+        deleted_blobs += delete_unreferenced_files(context, files).await?;
+        task::yield_now().await;
+    }
+
+    if deleted_msgs > 0 {
+        info!(
+            context,
+            "Deleted {} messages older than {} seconds and {} blobs.",
+            deleted_msgs,
+            delete_device_after,
+            deleted_blobs
+        );
+        context.emit_event(EventType::DeviceCleanupDone {
+            deleted_msgs,
+            deleted_blobs,
+        });
+        context.emit_event(EventType::MsgsChanged {
+            chat_id: ChatId::new(0),
+            msg_id: MsgId::new(0),
+        });
+    }
+    Ok((deleted_msgs, deleted_blobs))
+}
 
-        updated |= rows_modified > 0;
+/// Schedules a task deleting messages older than the `delete_device_after` setting
+/// periodically, see [delete_device_expired_messages]. Existing task is cancelled
+/// to make sure at most one such task is scheduled at a time.
+pub async fn schedule_device_deletion_task(context: &Context) {
+    if let Some(device_deletion_task) = context.device_deletion_task.write().await.take() {
+        device_deletion_task.cancel().await;
+    }
+    if context.get_config_delete_device_after().await.is_none() {
+        return;
     }
 
-    schedule_ephemeral_task(context).await;
-    Ok(updated)
+    let context1 = context.clone();
+    let device_deletion_task = task::spawn(async move {
+        loop {
+            if let Err(err) = delete_device_expired_messages(&context1).await {
+                warn!(context1, "Failed to delete old messages: {}", err);
+            }
+            task::sleep(DEVICE_DELETION_INTERVAL).await;
+        }
+    });
+    *context.device_deletion_task.write().await = Some(device_deletion_task);
+}
+
+/// Returns true if `file` is still used by a message that is not deleted
+/// or by a pending job.
+async fn is_file_referenced(context: &Context, file: &str) -> sql::Result<bool> {
+    let referenced = context
+        .sql
+        .query_map(
+            "SELECT param FROM msgs WHERE chat_id!=? AND instr(param, ?)>0 \
+             UNION ALL SELECT param FROM jobs WHERE instr(param, ?)>0;",
+            paramsv![DC_CHAT_ID_TRASH, file, file],
+            |row| row.get::<_, String>(0),
+            |params| {
+                for param in params {
+                    let param: Params = param?.parse().unwrap_or_default();
+                    if param.get(Param::File) == Some(file) {
+                        return Ok(true);
+                    }
+                }
+                Ok(false)
+            },
+        )
+        .await?;
+    Ok(referenced)
+}
+
+/// Defers local deletion of expired messages while it is alive.
+///
+/// Created by [hold_deletions]. When the last hold is dropped,
+/// messages that expired in the meantime are deleted.
+#[derive(Debug)]
+pub(crate) struct DeletionHold {
+    context: Context,
+}
+
+/// Puts local deletion of expired messages on hold until the returned guard is dropped.
+pub(crate) fn hold_deletions(context: &Context) -> DeletionHold {
+    context.deletion_holds.fetch_add(1, Ordering::SeqCst);
+    DeletionHold {
+        context: context.clone(),
+    }
+}
+
+impl Drop for DeletionHold {
+    fn drop(&mut self) {
+        if self.context.deletion_holds.fetch_sub(1, Ordering::SeqCst) == 1 {
+            let context = self.context.clone();
+            task::spawn(async move {
+                match delete_expired_messages(&context).await {
+                    Ok(deletion) if deletion.any_deleted() => emit_event!(
+                        context,
+                        EventType::MsgsChanged {
+                            chat_id: ChatId::new(0),
+                            msg_id: MsgId::new(0)
+                        }
+                    ),
+                    Ok(_) => {}
+                    Err(err) => warn!(context, "Failed to delete expired messages: {}", err),
+                }
+                if let Err(err) = delete_device_expired_messages(&context).await {
+                    warn!(context, "Failed to delete old messages: {}", err);
+                }
+            });
+        }
+    }
 }
 
 /// Schedule a task to emit MsgsChanged event when the next local
@@ -432,6 +786,189 @@ pub async fn schedule_ephemeral_task(context: &Context) {
     }
 }
 
+/// Unmutes chats whose timed mute has expired
+/// and emits #DC_EVENT_CHAT_MODIFIED for each of them.
+///
+/// Returns the unmuted chats.
+pub(crate) async fn unmute_expired_chats(context: &Context) -> sql::Result<Vec<ChatId>> {
+    let chat_ids = context
+        .sql
+        .query_map(
+            "SELECT id FROM chats WHERE muted_until>0 AND muted_until<=?;",
+            paramsv![time()],
+            |row| row.get::<_, ChatId>(0),
+            |rows| {
+                rows.collect::<std::result::Result<Vec<_>, _>>()
+                    .map_err(Into::into)
+            },
+        )
+        .await?;
+    for chat_id in &chat_ids {
+        context
+            .sql
+            .execute(
+                "UPDATE chats SET muted_until=0 WHERE id=?;",
+                paramsv![chat_id],
+            )
+            .await?;
+        context.emit_event(EventType::ChatModified(*chat_id));
+    }
+    Ok(chat_ids)
+}
+
+/// Schedules a task unmuting chats when their timed mute expires,
+/// see [unmute_expired_chats]. Existing task is cancelled to make sure
+/// at most one such task is scheduled at a time.
+pub async fn schedule_unmute_task(context: &Context) {
+    if let Some(unmute_task) = context.unmute_task.write().await.take() {
+        unmute_task.cancel().await;
+    }
+
+    let context1 = context.clone();
+    let unmute_task = task::spawn(async move {
+        loop {
+            if let Err(err) = unmute_expired_chats(&context1).await {
+                warn!(context1, "Failed to unmute chats: {}", err);
+                break;
+            }
+            let muted_until: Option<i64> = match context1
+                .sql
+                .query_get_value_result(
+                    "SELECT MIN(muted_until) FROM chats WHERE muted_until>0;",
+                    paramsv![],
+                )
+                .await
+            {
+                Ok(muted_until) => muted_until,
+                Err(err) => {
+                    warn!(context1, "Can't calculate next unmute: {}", err);
+                    break;
+                }
+            };
+            let muted_until = match muted_until {
+                Some(muted_until) => muted_until,
+                None => break,
+            };
+            let until =
+                UNIX_EPOCH + Duration::from_secs(muted_until.try_into().unwrap_or(u64::MAX));
+            if let Ok(duration) = until.duration_since(SystemTime::now()) {
+                task::sleep(duration).await;
+            }
+        }
+    });
+    *context.unmute_task.write().await = Some(unmute_task);
+}
+
+/// Raw config key storing the wall clock time of the last clock jump check.
+const LAST_CLOCK_CHECK_KEY: &str = "last_clock_check";
+
+/// Wall clock differences smaller than this many seconds are not
+/// considered clock jumps.
+const CLOCK_JUMP_THRESHOLD: i64 = 60;
+
+/// Seconds a message may outlive its ephemeral timer after a clock jump.
+const CLOCK_JUMP_GRACE: i64 = 60;
+
+/// Minimum time between two checks that are recorded, see [check_clock_jump].
+const CLOCK_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Detects wall clock jumps since the last check and reschedules
+/// ephemeral timers accordingly.
+///
+/// While the app is running, the wall clock time elapsed since the last check is compared
+/// to the elapsed monotonic time, so jumps in both directions are detected.  Called before
+/// every deletion of expired messages, so no message vanishes because of a jump.
+///
+/// The wall clock time of the checks is also stored in the database to detect jumps
+/// while the app was not running.  Then the clock moving forward cannot be told apart
+/// from the time the app was stopped, so only a clock going backwards is a jump.
+pub(crate) async fn check_clock_jump(context: &Context) {
+    if context.sql.is_readonly() {
+        return;
+    }
+    let mut last_clock_check = context.last_clock_check.lock().await;
+    let now = time();
+    let (expected_now, due) = match *last_clock_check {
+        Some((last_check, instant)) => {
+            let elapsed = instant.elapsed();
+            let elapsed_secs = i64::try_from(elapsed.as_secs()).unwrap_or(i64::MAX);
+            (
+                Some(last_check.saturating_add(elapsed_secs)),
+                elapsed >= CLOCK_CHECK_INTERVAL,
+            )
+        }
+        None => (
+            context
+                .sql
+                .get_raw_config_int64(context, LAST_CLOCK_CHECK_KEY)
+                .await
+                .filter(|last_check| now < *last_check),
+            true,
+        ),
+    };
+
+    let jumped = match expected_now {
+        Some(expected_now) => match compensate_clock_jump(context, expected_now, now).await {
+            Ok(jumped) => jumped,
+            Err(err) => {
+                warn!(context, "Failed to compensate clock jump: {}", err);
+                return;
+            }
+        },
+        None => false,
+    };
+    if !due && !jumped {
+        return;
+    }
+
+    *last_clock_check = Some((now, Instant::now()));
+    if let Err(err) = context
+        .sql
+        .set_raw_config_int64(context, LAST_CLOCK_CHECK_KEY, now)
+        .await
+    {
+        warn!(
+            context,
+            "Failed to store the time of the clock check: {}", err
+        );
+    }
+}
+
+/// Shifts the ephemeral timestamps of all pending messages by the
+/// difference between `now` and `expected_now`.
+///
+/// Afterwards no message expires later than its ephemeral timer
+/// plus [CLOCK_JUMP_GRACE] from `now`. Returns true if a jump was
+/// compensated.
+pub(crate) async fn compensate_clock_jump(
+    context: &Context,
+    expected_now: i64,
+    now: i64,
+) -> Result<bool, Error> {
+    let jump = now.saturating_sub(expected_now);
+    if jump.abs() < CLOCK_JUMP_THRESHOLD {
+        return Ok(false);
+    }
+
+    let rescheduled = context
+        .sql
+        .execute(
+            "UPDATE msgs \
+             SET ephemeral_timestamp = MIN(ephemeral_timestamp + ?, ? + ephemeral_timer + ?) \
+             WHERE ephemeral_timestamp != 0 \
+             AND chat_id != ?",
+            paramsv![jump, now, CLOCK_JUMP_GRACE, DC_CHAT_ID_TRASH],
+        )
+        .await?;
+    info!(
+        context,
+        "Clock jumped by {}s, rescheduled {} ephemeral messages.", jump, rescheduled
+    );
+
+    schedule_ephemeral_task(context).await;
+    Ok(true)
+}
+
 /// Returns ID of any expired message that should be deleted from the server.
 ///
 /// It looks up the trash chat too, to find messages that are already
@@ -495,6 +1032,10 @@ mod tests {
     use async_std::task::sleep;
 
     use super::*;
+    use crate::config::Config;
+    use crate::dc_receive_imf::dc_receive_imf;
+    use crate::events::Event;
+    use crate::message;
     use crate::test_utils::TestContext;
     use crate::{
         chat::{self, Chat, ChatItem},
@@ -716,6 +1257,242 @@ async fn test_ephemeral_delete_msgs() {
         check_msg_was_deleted(&t, &chat, msg.sender_msg_id).await;
     }
 
+    #[async_std::test]
+    async fn test_deletion_hold() {
+        let t = TestContext::new_alice().await;
+        let chat = t.get_self_chat().await;
+        chat.id
+            .set_ephemeral_timer(&t, Timer::Enabled { duration: 1 })
+            .await
+            .unwrap();
+
+        let hold = hold_deletions(&t);
+        let msg = t
+            .send_text(chat.id, "Saved message, disappearing after 1s")
+            .await;
+        sleep(Duration::from_millis(1100)).await;
+
+        assert_eq!(
+            delete_expired_messages(&t).await.unwrap(),
+            ExpiredDeletion::default()
+        );
+        let loaded = Message::load_from_db(&t, msg.sender_msg_id).await.unwrap();
+        assert_eq!(loaded.chat_id, chat.id);
+        assert_eq!(
+            loaded.text.as_deref(),
+            Some("Saved message, disappearing after 1s")
+        );
+
+        drop(hold);
+        sleep(Duration::from_millis(200)).await;
+        let loaded = Message::load_from_db(&t, msg.sender_msg_id).await.unwrap();
+        assert_eq!(loaded.chat_id, ChatId::new(DC_CHAT_ID_TRASH));
+    }
+
+    #[async_std::test]
+    async fn test_ephemeral_delete_blobs() {
+        let t = TestContext::new_alice().await;
+        let chat = t.get_self_chat().await;
+        let bob_chat = t.create_chat_with_contact("Bob", "bob@example.net").await;
+        chat.id
+            .set_ephemeral_timer(&t, Timer::Enabled { duration: 1 })
+            .await
+            .unwrap();
+
+        let image = t.get_blobdir().join("image.png");
+        async_std::fs::write(&image, include_bytes!("../test-data/image/avatar64x64.png"))
+            .await
+            .unwrap();
+        let mut msg = Message::new(Viewtype::Image);
+        msg.set_file(image.to_str().unwrap(), None);
+        let expiring = t.send_msg(chat.id, &mut msg).await.sender_msg_id;
+        let expiring_file = Message::load_from_db(&t, expiring)
+            .await
+            .unwrap()
+            .get_file(&t)
+            .unwrap();
+
+        let shared = t.get_blobdir().join("shared.txt");
+        async_std::fs::write(&shared, b"shared").await.unwrap();
+        let mut msg = Message::new(Viewtype::File);
+        msg.set_file(shared.to_str().unwrap(), None);
+        let shared_msg = t.send_msg(chat.id, &mut msg).await.sender_msg_id;
+        chat::forward_msgs(&t, &[shared_msg], bob_chat.id)
+            .await
+            .unwrap();
+        let shared_file = Message::load_from_db(&t, shared_msg)
+            .await
+            .unwrap()
+            .get_file(&t)
+            .unwrap();
+
+        let (event_tx, event_rx) = async_std::channel::unbounded();
+        t.add_event_sink(move |event: Event| {
+            let event_tx = event_tx.clone();
+            async move {
+                if let EventType::EphemeralCleanupDone {
+                    deleted_msgs,
+                    deleted_blobs,
+                } = event.typ
+                {
+                    event_tx.try_send((deleted_msgs, deleted_blobs)).unwrap();
+                }
+            }
+        })
+        .await;
+
+        sleep(Duration::from_millis(1100)).await;
+        assert_eq!(
+            delete_expired_messages(&t).await.unwrap(),
+            ExpiredDeletion {
+                messages: 2,
+                blobs: 1,
+                mdns: 0
+            }
+        );
+
+        assert_eq!(
+            async_std::future::timeout(Duration::from_secs(10), event_rx.recv())
+                .await
+                .unwrap()
+                .unwrap(),
+            (2, 1)
+        );
+
+        check_msg_was_deleted(&t, &chat, expiring).await;
+        check_msg_was_deleted(&t, &chat, shared_msg).await;
+        assert!(!expiring_file.exists().await);
+        assert!(shared_file.exists().await);
+    }
+
+    #[async_std::test]
+    async fn test_compensate_clock_jump() {
+        let t = TestContext::new_alice().await;
+        let chat = t.get_self_chat().await;
+        chat.id
+            .set_ephemeral_timer(&t, Timer::Enabled { duration: 3600 })
+            .await
+            .unwrap();
+        let msg_id = t.send_text(chat.id, "Saved message").await.sender_msg_id;
+        let now = time();
+
+        // Small differences are not clock jumps.
+        set_ephemeral_timestamp(&t, msg_id, now + 3600).await;
+        assert!(!compensate_clock_jump(&t, now - 10, now).await.unwrap());
+        assert_eq!(get_ephemeral_timestamp(&t, msg_id).await, now + 3600);
+
+        // Clock jumped two hours forward: the message must not vanish immediately.
+        let expected_now = now - 7200;
+        set_ephemeral_timestamp(&t, msg_id, expected_now + 3600).await;
+        assert!(compensate_clock_jump(&t, expected_now, now).await.unwrap());
+        assert_eq!(get_ephemeral_timestamp(&t, msg_id).await, now + 3600);
+
+        // Clock jumped two hours backward: the message must not linger.
+        let expected_now = now + 7200;
+        set_ephemeral_timestamp(&t, msg_id, expected_now + 3600).await;
+        assert!(compensate_clock_jump(&t, expected_now, now).await.unwrap());
+        assert_eq!(get_ephemeral_timestamp(&t, msg_id).await, now + 3600);
+
+        // No message survives longer than its timer plus grace period.
+        set_ephemeral_timestamp(&t, msg_id, now + 100_000).await;
+        assert!(compensate_clock_jump(&t, now + 120, now).await.unwrap());
+        assert_eq!(
+            get_ephemeral_timestamp(&t, msg_id).await,
+            now + 3600 + CLOCK_JUMP_GRACE
+        );
+    }
+
+    #[async_std::test]
+    async fn test_check_clock_jump() {
+        let t = TestContext::new_alice().await;
+        let chat = t.get_self_chat().await;
+        chat.id
+            .set_ephemeral_timer(&t, Timer::Enabled { duration: 3600 })
+            .await
+            .unwrap();
+        let msg_id = t.send_text(chat.id, "Saved message").await.sender_msg_id;
+        let now = time();
+
+        // The clock moving forward while the app was not running counts as elapsed time.
+        set_ephemeral_timestamp(&t, msg_id, now + 1000).await;
+        t.sql
+            .set_raw_config_int64(&t, LAST_CLOCK_CHECK_KEY, now - 7200)
+            .await
+            .unwrap();
+        *t.last_clock_check.lock().await = None;
+        check_clock_jump(&t).await;
+        assert_eq!(get_ephemeral_timestamp(&t, msg_id).await, now + 1000);
+        let last_check = t
+            .sql
+            .get_raw_config_int64(&t, LAST_CLOCK_CHECK_KEY)
+            .await
+            .unwrap();
+        assert!(last_check >= now);
+
+        // The clock went two hours back, e.g. while the app was not running.
+        set_ephemeral_timestamp(&t, msg_id, now + 7200 + 1000).await;
+        t.sql
+            .set_raw_config_int64(&t, LAST_CLOCK_CHECK_KEY, now + 7200)
+            .await
+            .unwrap();
+        *t.last_clock_check.lock().await = None;
+        check_clock_jump(&t).await;
+        let timestamp = get_ephemeral_timestamp(&t, msg_id).await;
+        assert!(timestamp >= now + 1000 && timestamp <= time() + 1000);
+    }
+
+    #[async_std::test]
+    async fn test_check_clock_jump_while_running() {
+        let t = TestContext::new_alice().await;
+        let chat = t.get_self_chat().await;
+        chat.id
+            .set_ephemeral_timer(&t, Timer::Enabled { duration: 3600 })
+            .await
+            .unwrap();
+        let msg_id = t.send_text(chat.id, "Saved message").await.sender_msg_id;
+        let now = time();
+
+        // The clock jumped two hours forward since the last check.
+        set_ephemeral_timestamp(&t, msg_id, now - 7200 + 1000).await;
+        *t.last_clock_check.lock().await = Some((now - 7200, Instant::now()));
+        let deleted = delete_expired_messages(&t).await.unwrap();
+        assert_eq!(deleted.messages, 0);
+        let timestamp = get_ephemeral_timestamp(&t, msg_id).await;
+        assert!(timestamp >= now + 1000 && timestamp <= time() + 1000);
+
+        // The clock jumped two hours backward since the last check.
+        set_ephemeral_timestamp(&t, msg_id, now + 7200 + 1000).await;
+        *t.last_clock_check.lock().await = Some((now + 7200, Instant::now()));
+        check_clock_jump(&t).await;
+        let timestamp = get_ephemeral_timestamp(&t, msg_id).await;
+        assert!(timestamp >= now + 1000 && timestamp <= time() + 1000);
+
+        // Without a jump nothing changes.
+        check_clock_jump(&t).await;
+        assert_eq!(get_ephemeral_timestamp(&t, msg_id).await, timestamp);
+    }
+
+    async fn set_ephemeral_timestamp(t: &TestContext, msg_id: MsgId, timestamp: i64) {
+        t.sql
+            .execute(
+                "UPDATE msgs SET ephemeral_timestamp=? WHERE id=?;",
+                paramsv![timestamp, msg_id],
+            )
+            .await
+            .unwrap();
+    }
+
+    async fn get_ephemeral_timestamp(t: &TestContext, msg_id: MsgId) -> i64 {
+        t.sql
+            .query_get_value_result(
+                "SELECT ephemeral_timestamp FROM msgs WHERE id=?;",
+                paramsv![msg_id],
+            )
+            .await
+            .unwrap()
+            .unwrap()
+    }
+
     async fn check_msg_was_deleted(t: &TestContext, chat: &Chat, msg_id: MsgId) {
         let chat_items = chat::get_chat_msgs(t, chat.id, 0, None).await;
         // Check that the chat is empty except for possibly info messages:
@@ -738,4 +1515,182 @@ async fn check_msg_was_deleted(t: &TestContext, chat: &Chat, msg_id: MsgId) {
             assert!(rawtxt.is_none_or_empty(), rawtxt);
         }
     }
+
+    async fn recv_old_msg(t: &TestContext, rfc724_mid: &str, server_uid: u32) -> MsgId {
+        let raw = format!(
+            "From: Bob <bob@example.net>\n\
+             To: alice@example.com\n\
+             Chat-Version: 1.0\n\
+             Subject: hi\n\
+             Message-ID: <{}>\n\
+             Date: Sun, 22 Mar 2020 22:37:57 +0000\n\
+             \n\
+             hello\n",
+            rfc724_mid
+        );
+        dc_receive_imf(t, raw.as_bytes(), "INBOX", server_uid, false)
+            .await
+            .unwrap();
+        let (_, _, msg_id) = message::rfc724_mid_exists(t, rfc724_mid)
+            .await
+            .unwrap()
+            .unwrap();
+        msg_id
+    }
+
+    async fn set_timestamp(t: &TestContext, msg_id: MsgId, timestamp: i64) {
+        t.sql
+            .execute(
+                "UPDATE msgs SET timestamp=? WHERE id=?;",
+                paramsv![timestamp, msg_id],
+            )
+            .await
+            .unwrap();
+    }
+
+    #[async_std::test]
+    async fn test_delete_device_after() {
+        let t = TestContext::new_alice().await;
+        let now = time();
+
+        let old = recv_old_msg(&t, "old@example.net", 1).await;
+        let unseen = recv_old_msg(&t, "unseen@example.net", 2).await;
+        let recent = recv_old_msg(&t, "recent@example.net", 3).await;
+        message::update_msg_state(&t, old, MessageState::InSeen).await;
+        message::update_msg_state(&t, recent, MessageState::InSeen).await;
+        set_timestamp(&t, old, now - 7200).await;
+        set_timestamp(&t, unseen, now - 7200).await;
+        set_timestamp(&t, recent, now - 60).await;
+        let chat_id = Message::load_from_db(&t, old).await.unwrap().chat_id;
+
+        let self_chat = t.get_self_chat().await;
+        let saved = t
+            .send_text(self_chat.id, "Saved message")
+            .await
+            .sender_msg_id;
+        set_timestamp(&t, saved, now - 7200).await;
+
+        // Nothing is deleted while the setting is disabled.
+        assert_eq!(delete_device_expired_messages(&t).await.unwrap(), (0, 0));
+
+        t.set_config(Config::DeleteDeviceAfter, Some("3600"))
+            .await
+            .unwrap();
+        assert_eq!(delete_device_expired_messages(&t).await.unwrap(), (1, 0));
+
+        let loaded = Message::load_from_db(&t, old).await.unwrap();
+        assert_eq!(loaded.chat_id, ChatId::new(DC_CHAT_ID_TRASH));
+        assert!(loaded.text.is_none_or_empty());
+        for msg_id in &[unseen, recent] {
+            let loaded = Message::load_from_db(&t, *msg_id).await.unwrap();
+            assert_eq!(loaded.chat_id, chat_id);
+        }
+        let loaded = Message::load_from_db(&t, saved).await.unwrap();
+        assert_eq!(loaded.chat_id, self_chat.id);
+
+        // Running again does not delete anything.
+        assert_eq!(delete_device_expired_messages(&t).await.unwrap(), (0, 0));
+    }
+
+    #[async_std::test]
+    async fn test_delete_device_after_no_redownload() {
+        let t = TestContext::new_alice().await;
+        let msg_id = recv_old_msg(&t, "old@example.net", 1).await;
+        message::update_msg_state(&t, msg_id, MessageState::InSeen).await;
+        set_timestamp(&t, msg_id, time() - 7200).await;
+        t.set_config(Config::DeleteDeviceAfter, Some("3600"))
+            .await
+            .unwrap();
+        assert_eq!(delete_device_expired_messages(&t).await.unwrap(), (1, 0));
+
+        // The message is moved to another folder on the server,
+        // so the resync removes its UID.
+        t.sql
+            .execute("UPDATE msgs SET server_uid=0;", paramsv![])
+            .await
+            .unwrap();
+        crate::sql::housekeeping(&t).await.unwrap();
+        let deleted_locally: bool = t
+            .sql
+            .query_get_value_result(
+                "SELECT deleted_locally FROM msgs WHERE id=?;",
+                paramsv![msg_id],
+            )
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(deleted_locally);
+
+        // The message is fetched again from the other folder, but not shown again.
+        let redownloaded = recv_old_msg(&t, "old@example.net", 5).await;
+        assert_eq!(redownloaded, msg_id);
+        let loaded = Message::load_from_db(&t, msg_id).await.unwrap();
+        assert_eq!(loaded.chat_id, ChatId::new(DC_CHAT_ID_TRASH));
+        assert_eq!(loaded.server_uid, 5);
+    }
+
+    #[async_std::test]
+    async fn test_delete_device_after_tombstones() {
+        let t = TestContext::new_alice().await;
+        t.set_config_bool(Config::DeleteOnServerWhenDeletingLocally, true)
+            .await
+            .unwrap();
+        let msg_id = recv_old_msg(&t, "old@example.net", 1).await;
+        message::update_msg_state(&t, msg_id, MessageState::InSeen).await;
+        set_timestamp(&t, msg_id, time() - 7200).await;
+        t.set_config(Config::DeleteDeviceAfter, Some("3600"))
+            .await
+            .unwrap();
+        assert_eq!(delete_device_expired_messages(&t).await.unwrap(), (1, 0));
+
+        // The message stays on the server and cannot be restored.
+        let delete_on_server: bool = t
+            .sql
+            .query_get_value_result(
+                "SELECT delete_on_server FROM msgs WHERE id=?;",
+                paramsv![msg_id],
+            )
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(!delete_on_server);
+        assert!(chat::get_trashed_msgs(&t, 10, 0).await.unwrap().is_empty());
+
+        crate::sql::housekeeping(&t).await.unwrap();
+        assert!(t
+            .sql
+            .exists("SELECT id FROM msgs WHERE id=?;", paramsv![msg_id])
+            .await
+            .unwrap());
+
+        t.sql
+            .execute(
+                "UPDATE msgs SET deleted_timestamp=deleted_timestamp-? WHERE id=?;",
+                paramsv![crate::sql::DELETED_LOCALLY_TOMBSTONE_LIFETIME + 1, msg_id],
+            )
+            .await
+            .unwrap();
+
+        // The tombstone is kept until the message is deleted on the server.
+        t.set_config(Config::DeleteServerAfter, Some("86400"))
+            .await
+            .unwrap();
+        crate::sql::housekeeping(&t).await.unwrap();
+        assert!(t
+            .sql
+            .exists("SELECT id FROM msgs WHERE id=?;", paramsv![msg_id])
+            .await
+            .unwrap());
+
+        // Otherwise it is removed after a while.
+        t.set_config(Config::DeleteServerAfter, Some("0"))
+            .await
+            .unwrap();
+        crate::sql::housekeeping(&t).await.unwrap();
+        assert!(!t
+            .sql
+            .exists("SELECT id FROM msgs WHERE id=?;", paramsv![msg_id])
+            .await
+            .unwrap());
+    }
 }
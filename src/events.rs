@@ -6,7 +6,8 @@
 use async_std::path::PathBuf;
 use strum::EnumProperty;
 
-use crate::chat::ChatId;
+use crate::chat::{ChatId, ChatProtectionStatus};
+use crate::configure::ConfigureStage;
 use crate::ephemeral::Timer as EphemeralTimer;
 use crate::message::MsgId;
 
@@ -208,6 +209,14 @@ pub enum EventType {
     #[strum(props(id = "410"))]
     ErrorSelfNotInGroup(String),
 
+    /// The database file is corrupted.
+    ///
+    /// Emitted only once, when SQLite reports the database as malformed for the first
+    /// time.  IO is stopped and further database access fails, the user should be asked
+    /// to import a backup or to try recovering the database.
+    #[strum(props(id = "420"))]
+    DatabaseCorrupt { message: String },
+
     /// Messages or chats changed.  One or more messages or chats changed for various
     /// reasons in the database:
     /// - Messages sent, received or removed
@@ -216,6 +225,20 @@ pub enum EventType {
     #[strum(props(id = "2000"))]
     MsgsChanged { chat_id: ChatId, msg_id: MsgId },
 
+    /// A contact added, changed or removed their reaction to a message,
+    /// see message::get_reactions().
+    #[strum(props(id = "2001"))]
+    ReactionsChanged {
+        chat_id: ChatId,
+        msg_id: MsgId,
+        contact_id: u32,
+    },
+
+    /// A status update was added to the log of a message,
+    /// see message::get_status_updates().
+    #[strum(props(id = "2002"))]
+    MsgStatusUpdate { chat_id: ChatId, msg_id: MsgId },
+
     /// There is a fresh message. Typically, the user will show an notification
     /// when receiving this message.
     ///
@@ -243,6 +266,11 @@ pub enum EventType {
     #[strum(props(id = "2015"))]
     MsgRead { chat_id: ChatId, msg_id: MsgId },
 
+    /// A read receipt for an outgoing message arrived from a contact.
+    /// In groups, this is emitted for each member, see message::get_read_receipts().
+    #[strum(props(id = "2016"))]
+    MsgReadReceipt { msg_id: MsgId, contact_id: u32 },
+
     /// Chat changed.  The name or the image of a chat group was changed or members were added or removed.
     /// Or the verify state of a chat has changed.
     /// See dc_set_chat_name(), dc_set_chat_profile_image(), dc_add_contact_to_chat()
@@ -260,6 +288,14 @@ pub enum EventType {
         timer: EphemeralTimer,
     },
 
+    /// The protection status of a chat changed, e.g. as the key of a member of a
+    /// protected chat changed, see [`ChatId::audit_protection`].
+    #[strum(props(id = "2022"))]
+    ChatProtectionChanged {
+        chat_id: ChatId,
+        status: ChatProtectionStatus,
+    },
+
     /// Contact(s) created, renamed, blocked or deleted.
     ///
     /// @param data1 (int) If set, this is the contact_id of an added contact that should be selected.
@@ -286,6 +322,13 @@ pub enum EventType {
         comment: Option<String>,
     },
 
+    /// Inform about the stage the configuration started by configure() entered.
+    ///
+    /// If the configuration fails, the failed stage can be queried
+    /// using `Context::get_last_configure_diagnostics()`.
+    #[strum(props(id = "2042"))]
+    ConfigureStageChanged(ConfigureStage),
+
     /// Inform about the import/export progress started by imex().
     ///
     /// @param data1 (usize) 0=error, 1-999=progress in permille, 1000=success and done
@@ -303,6 +346,61 @@ pub enum EventType {
     #[strum(props(id = "2052"))]
     ImexFileWritten(PathBuf),
 
+    /// Inform about the progress of importing messages started by `imex::import_mbox()`
+    /// or `imex::import_transcript()`.
+    #[strum(props(id = "2053"))]
+    ImportMsgsProgress {
+        /// Number of messages processed so far.
+        processed: usize,
+
+        /// Estimated number of messages to process.
+        total: usize,
+    },
+
+    /// Inform about the progress of deleting old messages from the server
+    /// after `delete_server_after` was lowered.
+    #[strum(props(id = "2056"))]
+    ServerCleanupProgress {
+        /// Number of messages processed so far.
+        processed: usize,
+
+        /// Number of messages to process.
+        total: usize,
+    },
+
+    /// Deleting old messages from the server after `delete_server_after` was lowered
+    /// is done.
+    #[strum(props(id = "2057"))]
+    ServerCleanupDone {
+        /// Number of messages deleted from the server.
+        deleted: usize,
+    },
+
+    /// Messages older than `delete_device_after` were deleted from the device.
+    #[strum(props(id = "2058"))]
+    DeviceCleanupDone {
+        /// Number of deleted messages.
+        deleted_msgs: usize,
+
+        /// Number of deleted files.
+        deleted_blobs: usize,
+    },
+
+    /// The folders were reconciled with the server after the UIDVALIDITY of a folder
+    /// changed or [`crate::context::Context::resync_folders`] was called.
+    #[strum(props(id = "2059"))]
+    FoldersResynced {
+        /// Number of messages on the server matched to local messages.
+        matched: usize,
+
+        /// Number of messages on the server not known locally and newer than all matched
+        /// messages, they are downloaded.
+        new: usize,
+
+        /// Number of local messages not found on the server anymore.
+        orphaned: usize,
+    },
+
     /// Progress information of a secure-join handshake from the view of the inviter
     /// (Alice, the person who shows the QR code).
     ///
@@ -328,4 +426,29 @@ pub enum EventType {
     ///     (Bob has verified alice and waits until Alice does the same for him)
     #[strum(props(id = "2061"))]
     SecurejoinJoinerProgress { contact_id: u32, progress: usize },
+
+    /// Messages with an expired ephemeral timer were deleted from the device.
+    #[strum(props(id = "2062"))]
+    EphemeralCleanupDone {
+        /// Number of deleted messages.
+        deleted_msgs: usize,
+
+        /// Number of deleted files.
+        deleted_blobs: usize,
+    },
+
+    /// The database of the account was closed for maintenance,
+    /// see [`crate::accounts::Accounts::close_account_db`].
+    ///
+    /// Until the database is reopened, all operations accessing it fail.
+    #[strum(props(id = "2070"))]
+    DatabaseClosed,
+
+    /// The database of the account was opened again after maintenance,
+    /// see [`crate::accounts::Accounts::reopen_account_db`].
+    ///
+    /// The content of the database may have changed completely, so the UI should reload
+    /// everything it shows for the account.
+    #[strum(props(id = "2071"))]
+    DatabaseReopened,
 }
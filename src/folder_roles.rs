@@ -0,0 +1,250 @@
+//! # Folder roles
+//!
+//! Delta Chat uses IMAP folders for different purposes, e.g. chat messages are moved
+//! to the "DeltaChat" folder and outgoing messages to the "Sent" folder.
+//! The folders are detected while configuring and scanning folders
+//! and saved in the `configured_*_folder` config keys.
+//!
+//! On some providers detection picks the wrong folders,
+//! so roles can be assigned to folders manually.
+//! Manual assignments are stored separately and take precedence over the detected folders
+//! wherever the configured folders are read, so they are honored from the next fetch on.
+
+use anyhow::{ensure, Result};
+use strum::IntoEnumIterator;
+
+use crate::config::Config;
+use crate::context::Context;
+
+/// Purpose a folder is used for.
+#[derive(Debug, Display, Clone, Copy, PartialEq, Eq, EnumIter)]
+#[strum(serialize_all = "snake_case")]
+pub enum FolderRole {
+    /// The folder watched for incoming messages.
+    Inbox,
+
+    /// The folder chat messages are moved to.
+    Mvbox,
+
+    /// The folder outgoing messages are moved to.
+    Sentbox,
+
+    /// The folder messages of accepted chats are moved out of.
+    Spam,
+}
+
+impl FolderRole {
+    /// Returns the config key holding the folder used for the role.
+    pub fn config(self) -> Config {
+        match self {
+            FolderRole::Inbox => Config::ConfiguredInboxFolder,
+            FolderRole::Mvbox => Config::ConfiguredMvboxFolder,
+            FolderRole::Sentbox => Config::ConfiguredSentboxFolder,
+            FolderRole::Spam => Config::ConfiguredSpamFolder,
+        }
+    }
+
+    /// Returns the role whose folder is held by the config key.
+    pub fn from_config(key: Config) -> Option<FolderRole> {
+        FolderRole::iter().find(|role| role.config() == key)
+    }
+
+    fn override_key(self) -> String {
+        format!("{}_folder_override", self)
+    }
+}
+
+/// Returns the folder manually assigned to a role.
+///
+/// Returns `None` if the role was not assigned manually
+/// and `Some(None)` if it was unassigned manually.
+pub(crate) async fn get_override(context: &Context, role: FolderRole) -> Option<Option<String>> {
+    let folder = context
+        .sql
+        .get_raw_config(context, role.override_key())
+        .await?;
+    Some(Some(folder).filter(|folder| !folder.is_empty()))
+}
+
+async fn set_override(context: &Context, role: FolderRole, folder: Option<&str>) -> Result<()> {
+    context
+        .sql
+        .set_raw_config(
+            context,
+            role.override_key(),
+            Some(folder.unwrap_or_default()),
+        )
+        .await?;
+    Ok(())
+}
+
+impl Context {
+    /// Returns the folders in use and their roles,
+    /// either detected by the core or assigned using [Context::set_folder_role].
+    pub async fn get_folder_roles(&self) -> Vec<(String, FolderRole)> {
+        let mut roles = Vec::new();
+        for role in FolderRole::iter() {
+            if let Some(folder) = self.get_config(role.config()).await {
+                roles.push((folder, role));
+            }
+        }
+        roles
+    }
+
+    /// Assigns a role to a folder, replacing the folder's previous role.
+    ///
+    /// If `role` is `None`, the role of the folder is unassigned,
+    /// so no folder is used for it anymore.
+    /// A role assigned manually to another folder must be unassigned first,
+    /// the inbox role can only be moved to another folder.
+    pub async fn set_folder_role(&self, folder: &str, role: Option<FolderRole>) -> Result<()> {
+        ensure!(!folder.is_empty(), "Folder name must not be empty");
+
+        if let Some(role) = role {
+            if let Some(Some(assigned)) = get_override(self, role).await {
+                ensure!(
+                    assigned == folder,
+                    "Folder role {} is already assigned to {}",
+                    role,
+                    assigned
+                );
+            }
+        }
+
+        for (current_folder, current_role) in self.get_folder_roles().await {
+            if current_folder == folder && Some(current_role) != role {
+                ensure!(
+                    current_role != FolderRole::Inbox,
+                    "Inbox role cannot be unassigned"
+                );
+                set_override(self, current_role, None).await?;
+            }
+        }
+        if let Some(role) = role {
+            set_override(self, role, Some(folder)).await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::chat;
+    use crate::contact::Contact;
+    use crate::dc_receive_imf::dc_receive_imf;
+    use crate::imap::scan_folders::get_watched_folders;
+    use crate::test_utils::TestContext;
+
+    async fn configure_folders(t: &TestContext) {
+        for (key, value) in &[
+            (Config::ConfiguredInboxFolder, "INBOX"),
+            (Config::ConfiguredMvboxFolder, "DeltaChat"),
+            (Config::ConfiguredSentboxFolder, "Sent"),
+        ] {
+            t.set_config(*key, Some(*value)).await.unwrap();
+        }
+    }
+
+    #[async_std::test]
+    async fn test_folder_roles() {
+        let t = TestContext::new_alice().await;
+        configure_folders(&t).await;
+        assert_eq!(
+            t.get_folder_roles().await,
+            vec![
+                ("INBOX".to_string(), FolderRole::Inbox),
+                ("DeltaChat".to_string(), FolderRole::Mvbox),
+                ("Sent".to_string(), FolderRole::Sentbox),
+            ]
+        );
+
+        t.set_folder_role("Chats", Some(FolderRole::Mvbox))
+            .await
+            .unwrap();
+        t.set_folder_role("Junk", Some(FolderRole::Spam))
+            .await
+            .unwrap();
+        // Detection does not replace manual assignments.
+        t.set_config(Config::ConfiguredMvboxFolder, Some("DeltaChat"))
+            .await
+            .unwrap();
+        assert_eq!(
+            t.get_folder_roles().await,
+            vec![
+                ("INBOX".to_string(), FolderRole::Inbox),
+                ("Chats".to_string(), FolderRole::Mvbox),
+                ("Sent".to_string(), FolderRole::Sentbox),
+                ("Junk".to_string(), FolderRole::Spam),
+            ]
+        );
+
+        // Two folders cannot have the same role.
+        assert!(t
+            .set_folder_role("Other", Some(FolderRole::Mvbox))
+            .await
+            .is_err());
+        assert!(t.set_folder_role("INBOX", None).await.is_err());
+
+        // A folder has a single role.
+        t.set_folder_role("Junk", Some(FolderRole::Sentbox))
+            .await
+            .unwrap();
+        t.set_folder_role("Chats", None).await.unwrap();
+        assert_eq!(
+            t.get_folder_roles().await,
+            vec![
+                ("INBOX".to_string(), FolderRole::Inbox),
+                ("Junk".to_string(), FolderRole::Sentbox),
+            ]
+        );
+        t.set_folder_role("Other", Some(FolderRole::Mvbox))
+            .await
+            .unwrap();
+        assert_eq!(
+            t.get_config(Config::ConfiguredMvboxFolder).await.unwrap(),
+            "Other"
+        );
+    }
+
+    #[async_std::test]
+    async fn test_folder_roles_move_and_scan() {
+        let t = TestContext::new_alice().await;
+        configure_folders(&t).await;
+        t.set_config_bool(Config::MvboxMove, true).await.unwrap();
+        t.set_config_bool(Config::MvboxWatch, true).await.unwrap();
+        t.set_config_bool(Config::SentboxWatch, true).await.unwrap();
+        t.set_folder_role("Chats", Some(FolderRole::Mvbox))
+            .await
+            .unwrap();
+        t.set_folder_role("Sent", None).await.unwrap();
+
+        let contact_id = Contact::create(&t, "", "bob@example.net").await.unwrap();
+        chat::create_by_contact_id(&t, contact_id).await.unwrap();
+        dc_receive_imf(
+            &t,
+            b"From: bob@example.net\n\
+              To: alice@example.com\n\
+              Chat-Version: 1.0\n\
+              Subject: foo\n\
+              Message-ID: <folder-roles@example.net>\n\
+              Date: Sun, 22 Mar 2020 22:37:57 +0000\n\
+              \n\
+              hello\n",
+            "INBOX",
+            1,
+            false,
+        )
+        .await
+        .unwrap();
+        let msg = t.get_last_msg().await;
+        let dest = msg.id.needs_move(&t, "INBOX").await.unwrap().unwrap();
+        assert_eq!(t.get_config(dest).await.unwrap(), "Chats");
+        assert_eq!(msg.id.needs_move(&t, "Chats").await.unwrap(), None);
+
+        let mut watched = get_watched_folders(&t).await;
+        watched.sort();
+        assert_eq!(watched, vec!["Chats".to_string(), "INBOX".to_string()]);
+    }
+}
@@ -26,6 +26,7 @@ pub enum HeaderDef {
     XMicrosoftOriginalMessageId,
 
     ListId,
+    ListPost,
     References,
     InReplyTo,
     Precedence,
@@ -43,8 +44,14 @@ pub enum HeaderDef {
     ChatGroupMemberAdded,
     ChatContent,
     ChatDuration,
+    ChatVoiceWaveform,
     ChatDispositionNotificationTo,
     ChatWebrtcRoom,
+    ChatReaction,
+    ChatChunk,
+
+    /// Message-ID of the original message, set on messages resent to new group members.
+    ChatResendOf,
     Autocrypt,
     AutocryptSetupMessage,
     SecureJoin,
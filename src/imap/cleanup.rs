@@ -0,0 +1,638 @@
+//! # Server cleanup
+//!
+//! When `delete_server_after` is lowered, messages which are already on the server
+//! and older than the new threshold are deleted in the background.  The cleanup walks
+//! the folders in pages of local messages ordered by UID, double-checks the Message-IDs
+//! on the server and deletes each page with a single STORE and EXPUNGE.
+//!
+//! Between runs of the [`Action::DeleteOldMsgsOnImap`] job the cleanup pauses to not
+//! hog the connection.  The position is persisted in the raw config, so the cleanup
+//! resumes where it stopped after a restart.
+//!
+//! With `delete_on_server_when_deleting_locally` enabled, locally deleted messages
+//! are deleted from the server the same way by [`Action::DeleteTrashedMsgsOnImap`].
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use super::ImapSession;
+use crate::constants::DC_CHAT_ID_TRASH;
+use crate::context::Context;
+use crate::dc_tools::time;
+use crate::download::DownloadState;
+use crate::events::EventType;
+use crate::job::{self, Action, Job};
+use crate::message::{self, Message, MsgId};
+use crate::param::Params;
+use crate::sql;
+
+/// Number of messages deleted with a single command.
+pub(crate) const PAGE_SIZE: usize = 100;
+
+/// Number of pages processed by a single run of the cleanup job.
+pub(crate) const PAGES_PER_RUN: usize = 5;
+
+/// Seconds to wait between runs of the cleanup job.
+pub(crate) const PAUSE: i64 = 30;
+
+/// Raw config key storing the [`Cursor`].
+const CURSOR_KEY: &str = "server_cleanup_cursor";
+
+/// Position of the cleanup.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct Cursor {
+    /// Folder processed currently, folders are processed in alphabetical order.
+    folder: String,
+
+    /// Highest UID processed in `folder`.
+    uid: u32,
+
+    /// Number of messages processed so far.
+    processed: usize,
+
+    /// Number of messages deleted from the server so far.
+    deleted: usize,
+
+    /// Number of messages to process, counted when the cleanup was scheduled.
+    total: usize,
+}
+
+impl Cursor {
+    async fn load(context: &Context) -> Option<Self> {
+        let raw = context.sql.get_raw_config(context, CURSOR_KEY).await?;
+        serde_json::from_str(&raw).ok()
+    }
+
+    async fn save(&self, context: &Context) -> Result<()> {
+        context
+            .sql
+            .set_raw_config(
+                context,
+                CURSOR_KEY,
+                Some(serde_json::to_string(self)?.as_str()),
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn remove(context: &Context) -> Result<()> {
+        context
+            .sql
+            .set_raw_config(context, CURSOR_KEY, None)
+            .await?;
+        Ok(())
+    }
+}
+
+/// State of the cleanup after [`run`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Progress {
+    /// There are more messages to process.
+    Paused,
+
+    /// All messages are processed.
+    Done,
+}
+
+/// Starts the cleanup from the beginning.
+pub(crate) async fn schedule(context: &Context) -> Result<()> {
+    let threshold = match threshold(context).await {
+        Some(threshold) => threshold,
+        None => return Ok(()),
+    };
+    let total: i64 = context
+        .sql
+        .query_get_value_result(
+            "SELECT COUNT(*) FROM msgs WHERE server_uid!=0 AND timestamp<?;",
+            paramsv![threshold],
+        )
+        .await?
+        .unwrap_or_default();
+    Cursor {
+        total: total as usize,
+        ..Default::default()
+    }
+    .save(context)
+    .await?;
+
+    job::kill_action(context, Action::DeleteOldMsgsOnImap).await;
+    job::add(
+        context,
+        Job::new(Action::DeleteOldMsgsOnImap, 0, Params::new(), 0),
+    )
+    .await;
+    Ok(())
+}
+
+/// Returns the timestamp before which messages are deleted from the server.
+async fn threshold(context: &Context) -> Option<i64> {
+    context
+        .get_config_delete_server_after()
+        .await
+        .map(|delete_server_after| time() - delete_server_after)
+}
+
+/// Processes up to `max_pages` pages of `page_size` messages.
+///
+/// Emits #DC_EVENT_SERVER_CLEANUP_PROGRESS after each page
+/// and #DC_EVENT_SERVER_CLEANUP_DONE when all messages are processed.
+pub(crate) async fn run(
+    context: &Context,
+    session: &mut impl ImapSession,
+    page_size: usize,
+    max_pages: usize,
+) -> Result<Progress> {
+    let mut cursor = match Cursor::load(context).await {
+        Some(cursor) => cursor,
+        None => return Ok(Progress::Done),
+    };
+    let threshold = match threshold(context).await {
+        Some(threshold) => threshold,
+        None => {
+            // Deleting messages from the server was turned off meanwhile.
+            Cursor::remove(context).await?;
+            return Ok(Progress::Done);
+        }
+    };
+
+    let mut pages = 0;
+    while pages < max_pages {
+        let page = load_page(context, &cursor, threshold, page_size).await?;
+        let last_uid = match page.last() {
+            Some(msg) => msg.server_uid,
+            None => {
+                match next_folder(context, &cursor.folder).await? {
+                    Some(folder) => {
+                        cursor.folder = folder;
+                        cursor.uid = 0;
+                    }
+                    None => {
+                        Cursor::remove(context).await?;
+                        info!(
+                            context,
+                            "Server cleanup done, deleted {} messages", cursor.deleted
+                        );
+                        context.emit_event(EventType::ServerCleanupDone {
+                            deleted: cursor.deleted,
+                        });
+                        return Ok(Progress::Done);
+                    }
+                }
+                continue;
+            }
+        };
+
+        cursor.deleted += delete_page(context, session, &cursor.folder, &page).await?;
+        cursor.processed += page.len();
+        cursor.total = cursor.total.max(cursor.processed);
+        cursor.uid = last_uid;
+        cursor.save(context).await?;
+        context.emit_event(EventType::ServerCleanupProgress {
+            processed: cursor.processed,
+            total: cursor.total,
+        });
+        pages += 1;
+    }
+    Ok(Progress::Paused)
+}
+
+/// Schedules deleting the locally deleted messages from the server.
+pub(crate) async fn schedule_trashed(context: &Context) {
+    job::kill_action(context, Action::DeleteTrashedMsgsOnImap).await;
+    job::add(
+        context,
+        Job::new(Action::DeleteTrashedMsgsOnImap, 0, Params::new(), 0),
+    )
+    .await;
+}
+
+/// Deletes the messages the user deleted locally from the server,
+/// folder by folder in pages of `page_size` messages.
+///
+/// Only messages marked by [message::delete_msgs] and [crate::chat::ChatId::delete] are
+/// deleted, other messages in the trash chat, e.g. tombstones of messages deleted
+/// by `delete_device_after`, stay on the server.
+///
+/// The tombstones in the trash chat are removed by [`delete_page`] only after the messages
+/// are deleted from the server, so after an error the next run continues with the rest.
+///
+/// Messages still within the trash grace period are skipped, see [sql::purge_trash].
+///
+/// Returns the number of messages deleted.
+pub(crate) async fn delete_trashed(
+    context: &Context,
+    session: &mut impl ImapSession,
+    page_size: usize,
+) -> Result<usize> {
+    let cutoff = sql::trash_grace_cutoff(context).await;
+    let folders = context
+        .sql
+        .query_map(
+            "SELECT DISTINCT server_folder FROM msgs \
+             WHERE chat_id=? AND delete_on_server AND server_uid!=0 AND server_folder!='' \
+             AND deleted_timestamp<=? \
+             ORDER BY server_folder;",
+            paramsv![DC_CHAT_ID_TRASH, cutoff],
+            |row| row.get::<_, String>(0),
+            |rows| {
+                rows.collect::<std::result::Result<Vec<_>, _>>()
+                    .map_err(Into::into)
+            },
+        )
+        .await?;
+
+    let mut deleted = 0;
+    for folder in folders {
+        let mut uid = 0;
+        loop {
+            let msg_ids = context
+                .sql
+                .query_map(
+                    "SELECT id FROM msgs \
+                     WHERE chat_id=? AND delete_on_server AND server_folder=? AND server_uid>? \
+                     AND deleted_timestamp<=? \
+                     ORDER BY server_uid LIMIT ?;",
+                    paramsv![DC_CHAT_ID_TRASH, folder, uid, cutoff, page_size as i64],
+                    |row| row.get::<_, MsgId>(0),
+                    |rows| {
+                        rows.collect::<std::result::Result<Vec<_>, _>>()
+                            .map_err(Into::into)
+                    },
+                )
+                .await?;
+            let page = load_msgs(context, msg_ids).await?;
+            uid = match page.last() {
+                Some(msg) => msg.server_uid,
+                None => break,
+            };
+            deleted += delete_page(context, session, &folder, &page).await?;
+        }
+    }
+    Ok(deleted)
+}
+
+/// Loads the next page of messages to delete in the current folder of `cursor`.
+async fn load_page(
+    context: &Context,
+    cursor: &Cursor,
+    threshold: i64,
+    page_size: usize,
+) -> Result<Vec<Message>> {
+    let msg_ids = context
+        .sql
+        .query_map(
+            "SELECT id FROM msgs \
+             WHERE server_folder=? AND server_uid>? AND timestamp<? \
+             ORDER BY server_uid LIMIT ?;",
+            paramsv![cursor.folder, cursor.uid, threshold, page_size as i64],
+            |row| row.get::<_, MsgId>(0),
+            |rows| {
+                rows.collect::<std::result::Result<Vec<_>, _>>()
+                    .map_err(Into::into)
+            },
+        )
+        .await?;
+    load_msgs(context, msg_ids).await
+}
+
+async fn load_msgs(context: &Context, msg_ids: Vec<MsgId>) -> Result<Vec<Message>> {
+    let mut msgs = Vec::with_capacity(msg_ids.len());
+    for msg_id in msg_ids {
+        msgs.push(Message::load_from_db(context, msg_id).await?);
+    }
+    Ok(msgs)
+}
+
+/// Returns the folder with messages on the server following `folder`.
+async fn next_folder(context: &Context, folder: &str) -> Result<Option<String>> {
+    let next = context
+        .sql
+        .query_get_value_result(
+            "SELECT MIN(server_folder) FROM msgs WHERE server_folder>? AND server_uid!=0;",
+            paramsv![folder],
+        )
+        .await?;
+    Ok(next)
+}
+
+/// Deletes the messages of a page from the server,
+/// following the same rules as [`Action::DeleteMsgOnImap`].
+///
+/// Returns the number of messages deleted.
+async fn delete_page(
+    context: &Context,
+    session: &mut impl ImapSession,
+    folder: &str,
+    page: &[Message],
+) -> Result<usize> {
+    let mut candidates = Vec::new();
+    let mut unlink = Vec::new();
+    for msg in page {
+        if msg.rfc724_mid.is_empty() || msg.download_state != DownloadState::Done {
+            // Device messages are not on the server, partially downloaded messages are kept
+            // on the server to download them later.
+            continue;
+        }
+        if message::rfc724_mid_cnt(context, &msg.rfc724_mid).await > 1 {
+            // The message is deleted from the server when all parts are deleted.
+            unlink.push(msg);
+        } else {
+            candidates.push(msg);
+        }
+    }
+
+    let mut deleted = Vec::new();
+    if !candidates.is_empty() {
+        let uids: Vec<u32> = candidates.iter().map(|msg| msg.server_uid).collect();
+        let remote = session.fetch_message_ids(context, folder, &uids).await?;
+        for msg in candidates {
+            match remote.get(&msg.server_uid) {
+                Some(remote_message_id) if *remote_message_id == msg.rfc724_mid => {
+                    deleted.push(msg.server_uid);
+                    unlink.push(msg);
+                }
+                Some(remote_message_id) => warn!(
+                    context,
+                    "Not deleting {}/{}: remote message-id '{}' != '{}'",
+                    folder,
+                    msg.server_uid,
+                    remote_message_id,
+                    msg.rfc724_mid
+                ),
+                // The message is gone from the server already.
+                None => unlink.push(msg),
+            }
+        }
+    }
+    if !deleted.is_empty() {
+        session.delete_msgs(context, folder, &deleted).await?;
+    }
+
+    for msg in unlink {
+        message::unlink_deleted_msg(context, msg).await?;
+    }
+    Ok(deleted.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::config::Config;
+    use crate::test_utils::{chat_msg, MockSession, TestContext};
+
+    /// Receives a message into `folder` of `t` and `server`, `age` seconds old.
+    async fn receive(t: &TestContext, server: &mut MockSession, folder: &str, uid: u32, age: i64) {
+        let message_id = format!("{}{}@example.net", folder, uid);
+        server
+            .receive(
+                t,
+                folder,
+                uid,
+                &chat_msg("bob@example.net", &message_id, "hello"),
+            )
+            .await;
+        t.sql
+            .execute(
+                "UPDATE msgs SET timestamp=? WHERE rfc724_mid=?;",
+                paramsv![time() - age, message_id],
+            )
+            .await
+            .unwrap();
+    }
+
+    async fn server_uid(t: &TestContext, folder: &str, uid: u32) -> u32 {
+        let (_, server_uid, _) =
+            message::rfc724_mid_exists(t, &format!("{}{}@example.net", folder, uid))
+                .await
+                .unwrap()
+                .unwrap();
+        server_uid
+    }
+
+    async fn msg_id(t: &TestContext, folder: &str, uid: u32) -> MsgId {
+        let (_, _, msg_id) =
+            message::rfc724_mid_exists(t, &format!("{}{}@example.net", folder, uid))
+                .await
+                .unwrap()
+                .unwrap();
+        msg_id
+    }
+
+    async fn count_jobs(t: &TestContext, action: Action) -> i64 {
+        t.sql
+            .query_get_value_result(
+                "SELECT COUNT(*) FROM jobs WHERE action=?;",
+                paramsv![action],
+            )
+            .await
+            .unwrap()
+            .unwrap_or_default()
+    }
+
+    async fn count_tombstones(t: &TestContext) -> i64 {
+        t.sql
+            .query_get_value_result(
+                "SELECT COUNT(*) FROM msgs WHERE chat_id=?;",
+                paramsv![DC_CHAT_ID_TRASH],
+            )
+            .await
+            .unwrap()
+            .unwrap_or_default()
+    }
+
+    const DAY: i64 = 24 * 60 * 60;
+
+    #[async_std::test]
+    async fn test_cleanup_pages() {
+        let t = TestContext::new_alice().await;
+        let mut server = MockSession::default();
+        for uid in 1..=5 {
+            receive(&t, &mut server, "INBOX", uid, 10 * DAY).await;
+        }
+        receive(&t, &mut server, "INBOX", 6, 60).await;
+        receive(&t, &mut server, "DeltaChat", 3, 10 * DAY).await;
+
+        t.set_config(Config::DeleteServerAfter, Some(DAY.to_string().as_str()))
+            .await
+            .unwrap();
+        assert_eq!(Cursor::load(&t).await.unwrap().total, 6);
+
+        assert_eq!(run(&t, &mut server, 2, 100).await.unwrap(), Progress::Done);
+        assert_eq!(
+            server.fetched_ids,
+            vec![vec![3], vec![1, 2], vec![3, 4], vec![5]]
+        );
+        assert!(server.uids("DeltaChat").is_empty());
+        assert_eq!(server.uids("INBOX"), vec![6]);
+        assert_eq!(server_uid(&t, "INBOX", 1).await, 0);
+        assert_eq!(server_uid(&t, "INBOX", 6).await, 6);
+        assert!(Cursor::load(&t).await.is_none());
+    }
+
+    #[async_std::test]
+    async fn test_cleanup_resumes() {
+        let t = TestContext::new_alice().await;
+        let mut server = MockSession::default();
+        for uid in 1..=5 {
+            receive(&t, &mut server, "INBOX", uid, 10 * DAY).await;
+        }
+        t.set_config(Config::DeleteServerAfter, Some(DAY.to_string().as_str()))
+            .await
+            .unwrap();
+
+        assert_eq!(run(&t, &mut server, 2, 1).await.unwrap(), Progress::Paused);
+        assert_eq!(server.uids("INBOX"), vec![3, 4, 5]);
+        let cursor = Cursor::load(&t).await.unwrap();
+        assert_eq!(cursor.uid, 2);
+        assert_eq!(cursor.processed, 2);
+
+        // A new session after a restart continues at the saved position.
+        let mut server = MockSession {
+            folders: server.folders,
+            ..Default::default()
+        };
+        assert_eq!(run(&t, &mut server, 2, 1).await.unwrap(), Progress::Paused);
+        assert_eq!(server.fetched_ids, vec![vec![3, 4]]);
+        assert_eq!(run(&t, &mut server, 2, 5).await.unwrap(), Progress::Done);
+        assert!(server.uids("INBOX").is_empty());
+
+        // Turning off deletion stops the cleanup.
+        receive(&t, &mut server, "INBOX", 7, 10 * DAY).await;
+        t.set_config(
+            Config::DeleteServerAfter,
+            Some((DAY / 2).to_string().as_str()),
+        )
+        .await
+        .unwrap();
+        assert!(Cursor::load(&t).await.is_some());
+        t.set_config(Config::DeleteServerAfter, Some("0"))
+            .await
+            .unwrap();
+        assert_eq!(run(&t, &mut server, 2, 5).await.unwrap(), Progress::Done);
+        assert_eq!(server.uids("INBOX"), vec![7]);
+    }
+
+    #[async_std::test]
+    async fn test_cleanup_exclusions() {
+        let t = TestContext::new_alice().await;
+        let mut server = MockSession::default();
+        for uid in 1..=3 {
+            receive(&t, &mut server, "INBOX", uid, 10 * DAY).await;
+        }
+
+        // The message on the server is not the one known locally.
+        server.store(
+            "INBOX",
+            1,
+            chat_msg("bob@example.net", "other@example.net", "hello"),
+        );
+
+        // Partially downloaded messages stay on the server.
+        t.sql
+            .execute(
+                "UPDATE msgs SET download_state=? WHERE rfc724_mid=?;",
+                paramsv![DownloadState::Undownloaded, "INBOX2@example.net"],
+            )
+            .await
+            .unwrap();
+
+        t.set_config(Config::DeleteServerAfter, Some(DAY.to_string().as_str()))
+            .await
+            .unwrap();
+        assert_eq!(run(&t, &mut server, 10, 1).await.unwrap(), Progress::Paused);
+        assert_eq!(server.uids("INBOX"), vec![1, 2]);
+        assert_eq!(server_uid(&t, "INBOX", 1).await, 1);
+        assert_eq!(server_uid(&t, "INBOX", 2).await, 2);
+        assert_eq!(server_uid(&t, "INBOX", 3).await, 0);
+    }
+
+    #[async_std::test]
+    async fn test_delete_chat_on_server() {
+        let t = TestContext::new_alice().await;
+        t.set_config_bool(Config::DeleteOnServerWhenDeletingLocally, true)
+            .await
+            .unwrap();
+        let mut server = MockSession::default();
+        for uid in 1..=3 {
+            receive(&t, &mut server, "INBOX", uid, 60).await;
+        }
+        for uid in 1..=2 {
+            receive(&t, &mut server, "DeltaChat", uid, 60).await;
+        }
+        let chat_id = Message::load_from_db(&t, msg_id(&t, "INBOX", 1).await)
+            .await
+            .unwrap()
+            .chat_id;
+        // Messages without a server UID are not kept.
+        crate::chat::add_info_msg(&t, chat_id, "local only").await;
+
+        chat_id.delete(&t).await.unwrap();
+        assert_eq!(count_jobs(&t, Action::DeleteTrashedMsgsOnImap).await, 1);
+        assert_eq!(count_tombstones(&t).await, 5);
+
+        assert_eq!(delete_trashed(&t, &mut server, 2).await.unwrap(), 5);
+        assert_eq!(server.fetched_ids, vec![vec![1, 2], vec![1, 2], vec![3]]);
+        assert!(server.uids("INBOX").is_empty());
+        assert!(server.uids("DeltaChat").is_empty());
+        assert_eq!(count_tombstones(&t).await, 0);
+    }
+
+    #[async_std::test]
+    async fn test_delete_trashed_retry() {
+        let t = TestContext::new_alice().await;
+        t.set_config_bool(Config::DeleteOnServerWhenDeletingLocally, true)
+            .await
+            .unwrap();
+        let mut server = MockSession::default();
+        let mut msg_ids = Vec::new();
+        for uid in 1..=3 {
+            receive(&t, &mut server, "INBOX", uid, 60).await;
+            msg_ids.push(msg_id(&t, "INBOX", uid).await);
+        }
+        // A message trashed without the user deleting it stays on the server.
+        receive(&t, &mut server, "INBOX", 4, 60).await;
+        msg_id(&t, "INBOX", 4).await.trash(&t).await.unwrap();
+
+        message::delete_msgs(&t, &msg_ids).await;
+        assert_eq!(count_jobs(&t, Action::DeleteTrashedMsgsOnImap).await, 1);
+        assert_eq!(count_jobs(&t, Action::DeleteMsgOnImap).await, 0);
+
+        server.fail_delete = true;
+        assert!(delete_trashed(&t, &mut server, 10).await.is_err());
+        assert_eq!(server.uids("INBOX"), vec![1, 2, 3, 4]);
+        assert_eq!(count_tombstones(&t).await, 4);
+        assert_eq!(server_uid(&t, "INBOX", 2).await, 2);
+
+        server.fail_delete = false;
+        assert_eq!(delete_trashed(&t, &mut server, 10).await.unwrap(), 3);
+        assert_eq!(server.uids("INBOX"), vec![4]);
+        assert_eq!(count_tombstones(&t).await, 1);
+        assert_eq!(server_uid(&t, "INBOX", 4).await, 4);
+    }
+
+    #[async_std::test]
+    async fn test_delete_locally_only() {
+        let t = TestContext::new_alice().await;
+        let mut server = MockSession::default();
+        for uid in 1..=3 {
+            receive(&t, &mut server, "INBOX", uid, 60).await;
+        }
+
+        // Without the option, deleting messages deletes them one by one as before.
+        message::delete_msgs(&t, &[msg_id(&t, "INBOX", 3).await]).await;
+        assert_eq!(count_jobs(&t, Action::DeleteMsgOnImap).await, 1);
+
+        // Deleting the chat keeps its messages on the server.
+        let chat_id = Message::load_from_db(&t, msg_id(&t, "INBOX", 1).await)
+            .await
+            .unwrap()
+            .chat_id;
+        chat_id.delete(&t).await.unwrap();
+        assert_eq!(count_jobs(&t, Action::DeleteTrashedMsgsOnImap).await, 0);
+        assert_eq!(count_tombstones(&t).await, 1);
+        // The message deleted one by one is left to its own job.
+        assert_eq!(delete_trashed(&t, &mut server, 10).await.unwrap(), 0);
+        assert_eq!(server.uids("INBOX"), vec![1, 2, 3]);
+    }
+}
@@ -4,10 +4,11 @@
     error::{Error as ImapError, Result as ImapResult},
     Client as ImapClient,
 };
-use async_std::net::{self, TcpStream};
 
 use super::session::Session;
 use crate::login_param::dc_build_tls;
+use crate::network::NetworkPolicy;
+use crate::socks::{self, Socks5Config};
 
 use super::session::SessionStream;
 
@@ -50,7 +51,10 @@ pub async fn login<U: AsRef<str>, P: AsRef<str>>(
                     },
                 )
             })?;
-        Ok(Session { inner: session })
+        Ok(Session {
+            inner: session,
+            io_timeout: NetworkPolicy::default().io_timeout,
+        })
     }
 
     pub async fn authenticate<A: async_imap::Authenticator, S: AsRef<str>>(
@@ -72,18 +76,21 @@ pub async fn authenticate<A: async_imap::Authenticator, S: AsRef<str>>(
                         },
                     )
                 })?;
-        Ok(Session { inner: session })
+        Ok(Session {
+            inner: session,
+            io_timeout: NetworkPolicy::default().io_timeout,
+        })
     }
 
-    pub async fn connect_secure<A: net::ToSocketAddrs, S: AsRef<str>>(
-        addr: A,
-        domain: S,
+    pub async fn connect_secure(
+        domain: &str,
+        port: u16,
         strict_tls: bool,
+        socks5_config: Option<&Socks5Config>,
     ) -> ImapResult<Self> {
-        let stream = TcpStream::connect(addr).await?;
+        let stream = socks::connect_tcp(domain, port, socks5_config).await?;
         let tls = dc_build_tls(strict_tls);
-        let tls_stream: Box<dyn SessionStream> =
-            Box::new(tls.connect(domain.as_ref(), stream).await?);
+        let tls_stream: Box<dyn SessionStream> = Box::new(tls.connect(domain, stream).await?);
         let mut client = ImapClient::new(tls_stream);
 
         let _greeting = client
@@ -97,8 +104,13 @@ pub async fn connect_secure<A: net::ToSocketAddrs, S: AsRef<str>>(
         })
     }
 
-    pub async fn connect_insecure<A: net::ToSocketAddrs>(addr: A) -> ImapResult<Self> {
-        let stream: Box<dyn SessionStream> = Box::new(TcpStream::connect(addr).await?);
+    pub async fn connect_insecure(
+        domain: &str,
+        port: u16,
+        socks5_config: Option<&Socks5Config>,
+    ) -> ImapResult<Self> {
+        let stream: Box<dyn SessionStream> =
+            Box::new(socks::connect_tcp(domain, port, socks5_config).await?);
 
         let mut client = ImapClient::new(stream);
         let _greeting = client
@@ -6,10 +6,14 @@
 use async_std::prelude::*;
 use std::time::{Duration, SystemTime};
 
+use crate::network::{with_timeout, NetworkPolicy};
 use crate::{context::Context, scheduler::InterruptInfo};
 
 use super::session::Session;
 
+/// Interval of polling for new messages if IDLE is not available.
+const FAKE_IDLE_INTERVAL: Duration = Duration::from_secs(60);
+
 impl Imap {
     pub fn can_idle(&self) -> bool {
         self.config.can_idle
@@ -56,9 +60,12 @@ pub async fn idle(
                 return Ok(info);
             }
 
+            let io_timeout = session.io_timeout;
             let mut handle = session.idle();
-            if let Err(err) = handle.init().await {
-                bail!("IMAP IDLE protocol failed to init/complete: {}", err);
+            match with_timeout(io_timeout, handle.init()).await {
+                Ok(Ok(())) => {}
+                Ok(Err(err)) => bail!("IMAP IDLE protocol failed to init/complete: {}", err),
+                Err(err) => bail!("IMAP IDLE protocol failed to init: {}", err),
             }
 
             let (idle_wait, interrupt) = handle.wait_with_timeout(timeout);
@@ -101,12 +108,13 @@ enum Event {
                 }
             }
 
-            let session = handle
-                .done()
-                .timeout(Duration::from_secs(15))
+            let session = with_timeout(io_timeout, handle.done())
                 .await
                 .map_err(|err| format_err!("IMAP IDLE protocol timed out: {}", err))??;
-            self.session = Some(Session { inner: session });
+            self.session = Some(Session {
+                inner: session,
+                io_timeout,
+            });
         } else {
             warn!(context, "Attempted to idle without a session");
         }
@@ -133,9 +141,10 @@ pub(crate) async fn fake_idle(
         };
         info!(context, "IMAP-fake-IDLEing folder={:?}", watch_folder);
 
-        // check every minute if there are new messages
-        // TODO: grow sleep durations / make them more flexible
-        let mut interval = async_std::stream::interval(Duration::from_secs(60));
+        // check every minute if there are new messages,
+        // after failed connection attempts retry faster as configured by the network policy.
+        let policy = NetworkPolicy::from_database(context).await;
+        let mut failures = if self.is_connected() { 0 } else { 1 };
 
         enum Event {
             Tick,
@@ -144,8 +153,8 @@ enum Event {
         // loop until we are interrupted or if we fetched something
         let info = loop {
             use futures::future::FutureExt;
-            match interval
-                .next()
+            let delay = policy.reconnect_delay(failures, FAKE_IDLE_INTERVAL);
+            match async_std::task::sleep(delay)
                 .map(|_| Event::Tick)
                 .race(
                     self.idle_interrupt
@@ -160,8 +169,10 @@ enum Event {
                     // never successfully connected)
                     if let Err(err) = self.connect_configured(context).await {
                         warn!(context, "fake_idle: could not connect: {}", err);
+                        failures += 1;
                         continue;
                     }
+                    failures = 0;
                     if self.config.can_idle {
                         // we only fake-idled because network was gone during IDLE, probably
                         break InterruptInfo::new(false, None);
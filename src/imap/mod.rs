@@ -26,28 +26,35 @@
 };
 use crate::dc_tools::dc_extract_grpid_from_rfc724_mid;
 use crate::events::EventType;
+use crate::folder_roles::{self, FolderRole};
 use crate::headerdef::{HeaderDef, HeaderDefMap};
 use crate::job::{self, Action};
 use crate::login_param::{CertificateChecks, LoginParam, ServerLoginParam};
 use crate::message::{self, update_server_uid, MessageState};
 use crate::mimeparser;
+use crate::network::{with_timeout, NetworkPolicy};
 use crate::oauth2::dc_get_oauth2_access_token;
 use crate::param::Params;
 use crate::provider::Socket;
 use crate::scheduler::InterruptInfo;
+use crate::socks::Socks5Config;
 use crate::stock_str;
 
+pub(crate) mod cleanup;
 mod client;
 mod idle;
+mod ops;
+pub(crate) mod resync;
 pub mod scan_folders;
 pub mod select_folder;
 mod session;
 
 use chat::get_chat_id_by_grpid;
-use client::Client;
+pub(crate) use client::Client;
 use mailparse::SingleInfo;
 use message::Message;
-use session::Session;
+pub(crate) use ops::ImapSession;
+use session::{with_io_timeout, Session};
 
 use self::select_folder::NewlySelected;
 
@@ -65,7 +72,8 @@ pub enum ImapActionResult {
 /// - Chat-Version to check if a message is a chat message
 /// - Autocrypt-Setup-Message to check if a message is an autocrypt setup message,
 ///   not necessarily sent by Delta Chat.
-const PREFETCH_FLAGS: &str = "(UID BODY.PEEK[HEADER.FIELDS (\
+/// - The size to check if a message exceeds the `download_limit`.
+const PREFETCH_FLAGS: &str = "(UID RFC822.SIZE BODY.PEEK[HEADER.FIELDS (\
                               MESSAGE-ID \
                               FROM \
                               IN-REPLY-TO REFERENCES \
@@ -82,6 +90,23 @@ pub enum ImapActionResult {
                              )])";
 const JUST_UID: &str = "(UID)";
 const BODY_FLAGS: &str = "(FLAGS BODY.PEEK[])";
+const HEADER_FLAGS: &str = "(FLAGS RFC822.SIZE BODY.PEEK[HEADER])";
+
+/// Error of [`Imap::connect`], telling which step of connecting failed.
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum ConnectError {
+    /// The server could not be reached or the TLS handshake failed.
+    #[error(transparent)]
+    Connect(anyhow::Error),
+
+    /// The server rejected the login.
+    #[error(transparent)]
+    Login(anyhow::Error),
+
+    /// No OAuth2 access token could be obtained.
+    #[error(transparent)]
+    Oauth2(anyhow::Error),
+}
 
 #[derive(Debug)]
 pub struct Imap {
@@ -125,6 +150,8 @@ struct ImapConfig {
     pub lp: ServerLoginParam,
     pub strict_tls: bool,
     pub oauth2: bool,
+    pub socks5_config: Option<Socks5Config>,
+    pub network_policy: NetworkPolicy,
     pub selected_folder: Option<String>,
     pub selected_mailbox: Option<Mailbox>,
     pub selected_folder_needs_expunge: bool,
@@ -133,6 +160,10 @@ struct ImapConfig {
     /// True if the server has MOVE capability as defined in
     /// https://tools.ietf.org/html/rfc6851
     pub can_move: bool,
+
+    /// True if the server has QUOTA capability as defined in
+    /// https://tools.ietf.org/html/rfc2087
+    pub can_quota: bool,
 }
 
 impl Default for ImapConfig {
@@ -142,11 +173,14 @@ fn default() -> Self {
             lp: Default::default(),
             strict_tls: false,
             oauth2: false,
+            socks5_config: None,
+            network_policy: Default::default(),
             selected_folder: None,
             selected_mailbox: None,
             selected_folder_needs_expunge: false,
             can_idle: false,
             can_move: false,
+            can_quota: false,
         }
     }
 }
@@ -193,18 +227,30 @@ async fn try_setup_handle(&mut self, context: &Context) -> Result<()> {
         }
 
         let oauth2 = self.config.oauth2;
+        let policy = &self.config.network_policy;
 
         let connection_res: ImapResult<Client> = if self.config.lp.security == Socket::STARTTLS
             || self.config.lp.security == Socket::Plain
         {
-            let config = &mut self.config;
+            let config = &self.config;
             let imap_server: &str = config.lp.server.as_ref();
             let imap_port = config.lp.port;
+            let socks5_config = config.socks5_config.as_ref();
 
-            match Client::connect_insecure((imap_server, imap_port)).await {
+            match policy
+                .connect(Client::connect_insecure(
+                    imap_server,
+                    imap_port,
+                    socks5_config,
+                ))
+                .await
+            {
                 Ok(client) => {
                     if config.lp.security == Socket::STARTTLS {
-                        client.secure(imap_server, config.strict_tls).await
+                        policy
+                            .io(client.secure(imap_server, config.strict_tls))
+                            .await
+                            .unwrap_or_else(|err| Err(err.into()))
                     } else {
                         Ok(client)
                     }
@@ -215,8 +261,17 @@ async fn try_setup_handle(&mut self, context: &Context) -> Result<()> {
             let config = &self.config;
             let imap_server: &str = config.lp.server.as_ref();
             let imap_port = config.lp.port;
-
-            Client::connect_secure((imap_server, imap_port), imap_server, config.strict_tls).await
+            let strict_tls = config.strict_tls;
+            let socks5_config = config.socks5_config.as_ref();
+
+            policy
+                .connect(Client::connect_secure(
+                    imap_server,
+                    imap_port,
+                    strict_tls,
+                    socks5_config,
+                ))
+                .await
         };
 
         let login_res = match connection_res {
@@ -235,23 +290,32 @@ async fn try_setup_handle(&mut self, context: &Context) -> Result<()> {
                             user: imap_user.into(),
                             access_token: token,
                         };
-                        client.authenticate("XOAUTH2", auth).await
+                        policy.io(client.authenticate("XOAUTH2", auth)).await
                     } else {
-                        bail!("IMAP Could not get OAUTH token");
+                        return Err(ConnectError::Oauth2(format_err!(
+                            "IMAP Could not get OAUTH token"
+                        ))
+                        .into());
                     }
                 } else {
-                    client.login(imap_user, imap_pw).await
+                    policy.io(client.login(imap_user, imap_pw)).await
                 }
             }
             Err(err) => {
-                bail!(err);
+                return Err(ConnectError::Connect(err.into()).into());
             }
         };
+        // A server not answering the login is treated like an unreachable server.
+        let login_res = match login_res {
+            Ok(login_res) => login_res,
+            Err(err) => return Err(ConnectError::Connect(err.into()).into()),
+        };
 
         self.should_reconnect = false;
 
         match login_res {
-            Ok(session) => {
+            Ok(mut session) => {
+                session.io_timeout = self.config.network_policy.io_timeout;
                 // needs to be set here to ensure it is set on reconnects.
                 self.connected = true;
                 self.session = Some(session);
@@ -287,7 +351,7 @@ async fn try_setup_handle(&mut self, context: &Context) -> Result<()> {
                 }
 
                 self.trigger_reconnect();
-                Err(format_err!("{}\n\n{}", message, err))
+                Err(ConnectError::Login(format_err!("{}\n\n{}", message, err)).into())
             }
         }
     }
@@ -313,7 +377,7 @@ async fn unsetup_handle(&mut self, context: &Context) {
 
         // Logout from the server
         if let Some(mut session) = self.session.take() {
-            if let Err(err) = session.logout().await {
+            if let Err(err) = with_io_timeout(session.io_timeout, session.logout()).await {
                 warn!(context, "failed to logout: {:?}", err);
             }
         }
@@ -330,6 +394,7 @@ async fn free_connect_params(&mut self) {
 
         cfg.can_idle = false;
         cfg.can_move = false;
+        cfg.can_quota = false;
     }
 
     /// Connects to IMAP account using already-configured parameters.
@@ -337,7 +402,13 @@ async fn free_connect_params(&mut self) {
     /// Emits network error if connection fails.
     pub async fn connect_configured(&mut self, context: &Context) -> Result<()> {
         if self.is_connected() && !self.should_reconnect() {
-            return Ok(());
+            if self.config.socks5_config == Socks5Config::from_database(context).await
+                && self.config.network_policy == NetworkPolicy::from_database(context).await
+            {
+                return Ok(());
+            }
+            info!(context, "Network settings changed, reconnecting IMAP");
+            self.trigger_reconnect();
         }
         if !context.is_configured().await {
             bail!("IMAP Connect without configured params");
@@ -391,6 +462,8 @@ pub async fn connect(
                 | CertificateChecks::AcceptInvalidCertificates2 => false,
             };
             config.oauth2 = oauth2;
+            config.socks5_config = Socks5Config::from_database(context).await;
+            config.network_policy = NetworkPolicy::from_database(context).await;
         }
 
         if let Err(err) = self.try_setup_handle(context).await {
@@ -399,8 +472,9 @@ pub async fn connect(
             return Err(err);
         }
 
+        let io_timeout = self.config.network_policy.io_timeout;
         let teardown = match &mut self.session {
-            Some(ref mut session) => match session.capabilities().await {
+            Some(session) => match with_io_timeout(io_timeout, session.capabilities()).await {
                 Ok(caps) => {
                     if !context.sql.is_open().await {
                         warn!(context, "IMAP-LOGIN as {} ok but ABORTING", lp.user,);
@@ -408,6 +482,7 @@ pub async fn connect(
                     } else {
                         let can_idle = caps.has_str("IDLE");
                         let can_move = caps.has_str("MOVE");
+                        let can_quota = caps.has_str("QUOTA");
                         let caps_list = caps.iter().fold(String::new(), |s, c| {
                             if let Capability::Atom(x) = c {
                                 s + &format!(" {}", x)
@@ -418,6 +493,7 @@ pub async fn connect(
 
                         self.config.can_idle = can_idle;
                         self.config.can_move = can_move;
+                        self.config.can_quota = can_quota;
                         self.connected = true;
                         emit_event!(
                             context,
@@ -469,81 +545,6 @@ pub async fn fetch(&mut self, context: &Context, watch_folder: &str) -> Result<(
         Ok(())
     }
 
-    /// Synchronizes UIDs in the database with UIDs on the server.
-    ///
-    /// It is assumed that no operations are taking place on the same
-    /// folder at the moment. Make sure to run it in the same
-    /// thread/task as other network operations on this folder to
-    /// avoid race conditions.
-    pub(crate) async fn resync_folder_uids(
-        &mut self,
-        context: &Context,
-        folder: String,
-    ) -> Result<()> {
-        // Collect pairs of UID and Message-ID.
-        let mut msg_ids = BTreeMap::new();
-
-        self.select_folder(context, Some(&folder)).await?;
-
-        let session = if let Some(ref mut session) = &mut self.session {
-            session
-        } else {
-            bail!("IMAP No Connection established");
-        };
-
-        match session.uid_fetch("1:*", RFC724MID_UID).await {
-            Ok(mut list) => {
-                while let Some(fetch) = list.next().await {
-                    let msg = fetch?;
-
-                    // Get Message-ID
-                    let message_id = get_fetch_headers(&msg)
-                        .and_then(|headers| prefetch_get_message_id(&headers))
-                        .ok();
-
-                    if let (Some(uid), Some(rfc724_mid)) = (msg.uid, message_id) {
-                        msg_ids.insert(uid, rfc724_mid);
-                    }
-                }
-            }
-            Err(err) => {
-                bail!("Can't resync folder {}: {}", folder, err);
-            }
-        }
-
-        info!(
-            context,
-            "Resync: collected {} message IDs in folder {}",
-            msg_ids.len(),
-            &folder
-        );
-
-        // Write collected UIDs to SQLite database.
-        context
-            .sql
-            .with_conn(move |mut conn| {
-                let conn2 = &mut conn;
-                let tx = conn2.transaction()?;
-                tx.execute(
-                    "UPDATE msgs SET server_uid=0 WHERE server_folder=?",
-                    params![folder],
-                )?;
-                for (uid, rfc724_mid) in &msg_ids {
-                    // This may detect previously undetected moved
-                    // messages, so we update server_folder too.
-                    tx.execute(
-                        "UPDATE msgs \
-                         SET server_folder=?,server_uid=? WHERE rfc724_mid=?",
-                        params![folder, uid, rfc724_mid],
-                    )?;
-                }
-                tx.commit()?;
-                Ok(())
-            })
-            .await?;
-        Ok(())
-    }
-
     /// Select a folder and take care of uidvalidity changes.
     /// Also, when selecting a folder for the first time, sets the uid_next to the current
     /// mailbox.uid_next so that no old emails are fetched.
@@ -608,13 +609,13 @@ pub(crate) async fn select_with_uidvalidity(
                 // and thus we only need to get exactly the
                 // last-index message.
                 let set = format!("{}", mailbox.exists);
-                let mut list = session
-                    .fetch(set, JUST_UID)
+                let timeout = session.io_timeout;
+                let mut list = with_io_timeout(timeout, session.fetch(set, JUST_UID))
                     .await
                     .context("Error fetching UID")?;
 
                 let mut new_last_seen_uid = None;
-                while let Some(fetch) = list.next().await.transpose()? {
+                while let Some(fetch) = with_timeout(timeout, list.next()).await?.transpose()? {
                     if fetch.message == mailbox.exists && fetch.uid.is_some() {
                         new_last_seen_uid = fetch.uid;
                     }
@@ -667,9 +668,11 @@ pub(crate) async fn fetch_new_messages<S: AsRef<str>>(
         };
         let read_cnt = msgs.len();
         let folder: &str = folder.as_ref();
+        let download_limit = context.get_config_int(Config::DownloadLimit).await;
 
         let mut read_errors = 0;
         let mut uids = Vec::with_capacity(msgs.len());
+        let mut uids_partial = Vec::new();
         let mut largest_uid_skipped = None;
 
         for (current_uid, msg) in msgs.into_iter() {
@@ -695,7 +698,12 @@ pub(crate) async fn fetch_new_messages<S: AsRef<str>>(
             )
             .await
             {
-                uids.push(current_uid);
+                match msg.size {
+                    Some(size) if download_limit > 0 && size > download_limit as u32 => {
+                        uids_partial.push(current_uid)
+                    }
+                    _ => uids.push(current_uid),
+                }
             } else if read_errors == 0 {
                 // If there were errors (`read_errors != 0`), stop updating largest_uid_skipped so that uid_next will
                 // not be updated and we will retry prefetching next time
@@ -703,11 +711,18 @@ pub(crate) async fn fetch_new_messages<S: AsRef<str>>(
             }
         }
 
-        let (largest_uid_processed, error_cnt) = self
-            .fetch_many_msgs(context, &folder, uids, fetch_existing_msgs)
+        let (largest_uid_fully_fetched, error_cnt) = self
+            .fetch_many_msgs(context, &folder, uids, fetch_existing_msgs, false)
+            .await;
+        read_errors += error_cnt;
+
+        let (largest_uid_partially_fetched, error_cnt) = self
+            .fetch_many_msgs(context, &folder, uids_partial, fetch_existing_msgs, true)
             .await;
         read_errors += error_cnt;
 
+        let largest_uid_processed = max(largest_uid_fully_fetched, largest_uid_partially_fetched);
+
         // determine which uid_next to use to update to
         // dc_receive_imf() returns an `Err` value only on recoverable errors, otherwise it just logs an error.
         // `largest_uid_processed` is the largest uid where dc_receive_imf() did NOT return an error.
@@ -749,22 +764,22 @@ pub async fn get_all_recipients(&mut self, context: &Context) -> Result<Vec<Sing
             .ok_or_else(|| format_err!("Not configured"))?;
 
         let search_command = format!("FROM \"{}\"", self_addr);
-        let uids = session
-            .uid_search(search_command)
+        let timeout = session.io_timeout;
+        let uids = with_io_timeout(timeout, session.uid_search(search_command))
             .await?
             .into_iter()
             .collect();
 
         let mut result = Vec::new();
         for uid_set in &build_sequence_sets(uids) {
-            let mut list = session
-                .uid_fetch(uid_set, "(UID BODY.PEEK[HEADER.FIELDS (FROM TO CC BCC)])")
-                .await
-                .map_err(|err| {
-                    format_err!("IMAP Could not fetch (get_all_recipients()): {}", err)
-                })?;
+            let mut list = with_io_timeout(
+                timeout,
+                session.uid_fetch(uid_set, "(UID BODY.PEEK[HEADER.FIELDS (FROM TO CC BCC)])"),
+            )
+            .await
+            .map_err(|err| format_err!("IMAP Could not fetch (get_all_recipients()): {}", err))?;
 
-            while let Some(fetch) = list.next().await {
+            while let Some(fetch) = with_timeout(timeout, list.next()).await? {
                 let msg = fetch?;
                 match get_fetch_headers(&msg) {
                     Ok(headers) => {
@@ -791,13 +806,13 @@ async fn prefetch(&mut self, uid_next: u32) -> Result<BTreeMap<u32, async_imap::
 
         // fetch messages with larger UID than the last one seen
         let set = format!("{}:*", uid_next);
-        let mut list = session
-            .uid_fetch(set, PREFETCH_FLAGS)
+        let timeout = session.io_timeout;
+        let mut list = with_io_timeout(timeout, session.uid_fetch(set, PREFETCH_FLAGS))
             .await
             .map_err(|err| format_err!("IMAP Could not fetch: {}", err))?;
 
         let mut msgs = BTreeMap::new();
-        while let Some(fetch) = list.next().await {
+        while let Some(fetch) = with_timeout(timeout, list.next()).await? {
             let msg = fetch?;
             if let Some(msg_uid) = msg.uid {
                 msgs.insert(msg_uid, msg);
@@ -833,13 +848,13 @@ async fn prefetch_existing_msgs(&mut self) -> Result<BTreeMap<u32, async_imap::t
         // we can fetch the sequence numbers 900-1000 and get the last 100 messages.
         let first = cmp::max(1, exists - DC_FETCH_EXISTING_MSGS_COUNT);
         let set = format!("{}:*", first);
-        let mut list = session
-            .fetch(&set, PREFETCH_FLAGS)
+        let timeout = session.io_timeout;
+        let mut list = with_io_timeout(timeout, session.fetch(&set, PREFETCH_FLAGS))
             .await
             .map_err(|err| format_err!("IMAP Could not fetch: {}", err))?;
 
         let mut msgs = BTreeMap::new();
-        while let Some(fetch) = list.next().await {
+        while let Some(fetch) = with_timeout(timeout, list.next()).await? {
             let msg = fetch?;
             if let Some(msg_uid) = msg.uid {
                 msgs.insert(msg_uid, msg);
@@ -851,6 +866,9 @@ async fn prefetch_existing_msgs(&mut self) -> Result<BTreeMap<u32, async_imap::t
 
     /// Fetches a list of messages by server UID.
     ///
+    /// If `fetch_partially` is set, only the header of the messages is fetched,
+    /// see [crate::download].
+    ///
     /// Returns the last uid fetch successfully and an error count.
     async fn fetch_many_msgs<S: AsRef<str>>(
         &mut self,
@@ -858,6 +876,7 @@ async fn fetch_many_msgs<S: AsRef<str>>(
         folder: S,
         server_uids: Vec<u32>,
         fetching_existing_messages: bool,
+        fetch_partially: bool,
     ) -> (Option<u32>, usize) {
         if server_uids.is_empty() {
             return (None, 0);
@@ -876,6 +895,7 @@ async fn fetch_many_msgs<S: AsRef<str>>(
         }
 
         let session = self.session.as_mut().unwrap();
+        let timeout = session.io_timeout;
 
         let sets = build_sequence_sets(server_uids.clone());
         let mut read_errors = 0;
@@ -883,26 +903,32 @@ async fn fetch_many_msgs<S: AsRef<str>>(
         let mut last_uid = None;
 
         for set in sets.iter() {
-            let mut msgs = match session.uid_fetch(&set, BODY_FLAGS).await {
-                Ok(msgs) => msgs,
-                Err(err) => {
-                    // TODO: maybe differentiate between IO and input/parsing problems
-                    // so we don't reconnect if we have a (rare) input/output parsing problem?
-                    self.should_reconnect = true;
-                    warn!(
-                        context,
-                        "Error on fetching messages #{} from folder \"{}\"; error={}.",
-                        &set,
-                        folder.as_ref(),
-                        err
-                    );
-                    return (None, server_uids.len());
-                }
+            let fetch_flags = if fetch_partially {
+                HEADER_FLAGS
+            } else {
+                BODY_FLAGS
             };
+            let mut msgs =
+                match with_io_timeout(timeout, session.uid_fetch(&set, fetch_flags)).await {
+                    Ok(msgs) => msgs,
+                    Err(err) => {
+                        // TODO: maybe differentiate between IO and input/parsing problems
+                        // so we don't reconnect if we have a (rare) input/output parsing problem?
+                        self.should_reconnect = true;
+                        warn!(
+                            context,
+                            "Error on fetching messages #{} from folder \"{}\"; error={}.",
+                            &set,
+                            folder.as_ref(),
+                            err
+                        );
+                        return (None, server_uids.len());
+                    }
+                };
 
             let folder = folder.as_ref().to_string();
 
-            while let Some(Ok(msg)) = msgs.next().await {
+            while let Ok(Some(Ok(msg))) = with_timeout(timeout, msgs.next()).await {
                 let server_uid = msg.uid.unwrap_or_default();
 
                 if !server_uids.contains(&server_uid) {
@@ -918,17 +944,21 @@ async fn fetch_many_msgs<S: AsRef<str>>(
                 count += 1;
 
                 let is_deleted = msg.flags().any(|flag| flag == Flag::Deleted);
-                if is_deleted || msg.body().is_none() {
+                let (body, partial) = if fetch_partially {
+                    (msg.header(), Some(msg.size.unwrap_or_default()))
+                } else {
+                    (msg.body(), None)
+                };
+                let body = match body {
+                    Some(body) if !is_deleted => body,
                     // No need to process these.
-                    continue;
-                }
+                    _ => continue,
+                };
 
                 // XXX put flags into a set and pass them to dc_receive_imf
                 let context = context.clone();
                 let folder = folder.clone();
 
-                // safe, as we checked above that there is a body.
-                let body = msg.body().unwrap();
                 let is_seen = msg.flags().any(|flag| flag == Flag::Seen);
 
                 match dc_receive_imf_inner(
@@ -937,7 +967,9 @@ async fn fetch_many_msgs<S: AsRef<str>>(
                     &folder,
                     server_uid,
                     is_seen,
+                    partial,
                     fetching_existing_messages,
+                    false,
                 )
                 .await
                 {
@@ -968,6 +1000,22 @@ pub async fn can_move(&self) -> bool {
         self.config.can_move
     }
 
+    pub fn can_quota(&self) -> bool {
+        self.config.can_quota
+    }
+
+    /// Sends `GETQUOTAROOT` for `folder` and returns the raw response of the server.
+    pub(crate) async fn get_quota_root(&mut self, folder: &str) -> Result<String> {
+        let session = self.session.as_mut().context("no IMAP session")?;
+        let folder = folder.replace('\\', "\\\\").replace('"', "\\\"");
+        let response = with_io_timeout(
+            session.io_timeout,
+            session.run_command_and_read_response(format!("GETQUOTAROOT \"{}\"", folder)),
+        )
+        .await?;
+        Ok(String::from_utf8_lossy(&response).into_owned())
+    }
+
     pub async fn mv(
         &mut self,
         context: &Context,
@@ -994,7 +1042,8 @@ pub async fn mv(
 
         if self.can_move().await {
             if let Some(ref mut session) = &mut self.session {
-                match session.uid_mv(&set, &dest_folder).await {
+                match with_io_timeout(session.io_timeout, session.uid_mv(&set, &dest_folder)).await
+                {
                     Ok(_) => {
                         emit_event!(
                             context,
@@ -1030,7 +1079,9 @@ pub async fn mv(
         }
 
         if let Some(ref mut session) = &mut self.session {
-            if let Err(err) = session.uid_copy(&set, &dest_folder).await {
+            if let Err(err) =
+                with_io_timeout(session.io_timeout, session.uid_copy(&set, &dest_folder)).await
+            {
                 warn!(context, "Could not copy message: {}", err);
                 return ImapActionResult::Failed;
             }
@@ -1085,9 +1136,10 @@ async fn add_flag_finalized_with_set(
         }
         if let Some(ref mut session) = &mut self.session {
             let query = format!("+FLAGS ({})", flag);
-            match session.uid_store(uid_set, &query).await {
+            let timeout = session.io_timeout;
+            match with_io_timeout(timeout, session.uid_store(uid_set, &query)).await {
                 Ok(mut responses) => {
-                    while let Some(_response) = responses.next().await {
+                    while let Ok(Some(_response)) = with_timeout(timeout, responses.next()).await {
                         // Read all the responses
                     }
                 }
@@ -1191,11 +1243,15 @@ pub async fn delete_msg(
         // double-check that we are deleting the correct message-id
         // this comes at the expense of another imap query
         if let Some(ref mut session) = &mut self.session {
-            match session.uid_fetch(set, DELETE_CHECK_FLAGS).await {
+            let timeout = session.io_timeout;
+            match with_io_timeout(timeout, session.uid_fetch(set, DELETE_CHECK_FLAGS)).await {
                 Ok(mut msgs) => {
                     let mut remote_message_id = None;
 
-                    while let Some(response) = msgs.next().await {
+                    while let Some(response) = with_timeout(timeout, msgs.next())
+                        .await
+                        .unwrap_or_else(|err| Some(Err(err.into())))
+                    {
                         match response {
                             Ok(fetch) => {
                                 if fetch.uid == Some(uid) {
@@ -1284,12 +1340,14 @@ pub async fn configure_folders(&mut self, context: &Context, create_mvbox: bool)
         }
 
         if let Some(ref mut session) = &mut self.session {
-            let mut folders = match session.list(Some(""), Some("*")).await {
-                Ok(f) => f,
-                Err(err) => {
-                    bail!("list_folders failed: {}", err);
-                }
-            };
+            let timeout = session.io_timeout;
+            let mut folders =
+                match with_io_timeout(timeout, session.list(Some(""), Some("*"))).await {
+                    Ok(f) => f,
+                    Err(err) => {
+                        bail!("list_folders failed: {}", err);
+                    }
+                };
 
             let mut delimiter = ".".to_string();
             let mut delimiter_is_default = true;
@@ -1298,7 +1356,7 @@ pub async fn configure_folders(&mut self, context: &Context, create_mvbox: bool)
             let mut mvbox_folder = None;
             let mut fallback_folder = get_fallback_folder(&delimiter);
 
-            while let Some(folder) = folders.next().await {
+            while let Some(folder) = with_timeout(timeout, folders.next()).await? {
                 let folder = folder?;
                 info!(context, "Scanning folder: {:?}", folder);
 
@@ -1340,10 +1398,14 @@ pub async fn configure_folders(&mut self, context: &Context, create_mvbox: bool)
             info!(context, "Using \"{}\" as folder-delimiter.", delimiter);
             info!(context, "sentbox folder is {:?}", sentbox_folder);
 
-            if mvbox_folder.is_none() && create_mvbox {
+            // Do not create a folder if the user has chosen one.
+            let mvbox_assigned = folder_roles::get_override(context, FolderRole::Mvbox)
+                .await
+                .is_some();
+            if mvbox_folder.is_none() && create_mvbox && !mvbox_assigned {
                 info!(context, "Creating MVBOX-folder \"DeltaChat\"...",);
 
-                match session.create("DeltaChat").await {
+                match with_io_timeout(timeout, session.create("DeltaChat")).await {
                     Ok(_) => {
                         mvbox_folder = Some("DeltaChat".into());
                         info!(context, "MVBOX-folder created.",);
@@ -1355,7 +1417,7 @@ pub async fn configure_folders(&mut self, context: &Context, create_mvbox: bool)
                             err
                         );
 
-                        match session.create(&fallback_folder).await {
+                        match with_io_timeout(timeout, session.create(&fallback_folder)).await {
                             Ok(_) => {
                                 mvbox_folder = Some(fallback_folder);
                                 info!(
@@ -1373,7 +1435,7 @@ pub async fn configure_folders(&mut self, context: &Context, create_mvbox: bool)
                 // that may be used by other MUAs to list folders.
                 // for the LIST command, the folder is always visible.
                 if let Some(ref mvbox) = mvbox_folder {
-                    if let Err(err) = session.subscribe(mvbox).await {
+                    if let Err(err) = with_io_timeout(timeout, session.subscribe(mvbox)).await {
                         warn!(context, "could not subscribe to {:?}: {:?}", mvbox, err);
                     }
                 }
@@ -1594,7 +1656,7 @@ fn get_fetch_headers(prefetch_msg: &Fetch) -> Result<Vec<mailparse::MailHeader>>
     Ok(headers)
 }
 
-fn prefetch_get_message_id(headers: &[mailparse::MailHeader]) -> Result<String> {
+pub(crate) fn prefetch_get_message_id(headers: &[mailparse::MailHeader]) -> Result<String> {
     if let Some(message_id) = headers.get_header_value(HeaderDef::XMicrosoftOriginalMessageId) {
         Ok(crate::mimeparser::parse_message_id(&message_id)?)
     } else if let Some(message_id) = headers.get_header_value(HeaderDef::MessageId) {
@@ -0,0 +1,228 @@
+//! # Operations on the server used by background jobs
+//!
+//! The server cleanup, the resynchronization of folders, the quota and the download of
+//! partially downloaded messages talk to the server through [`ImapSession`],
+//! so they can be tested without a server.
+
+use std::collections::BTreeMap;
+
+use anyhow::{ensure, Context as _, Result};
+use async_imap::types::Flag;
+use async_std::prelude::*;
+use async_trait::async_trait;
+use itertools::Itertools;
+
+use super::session::with_io_timeout;
+use super::{
+    get_fetch_headers, prefetch_get_message_id, Imap, ImapActionResult, BODY_FLAGS, RFC724MID_UID,
+};
+use crate::context::Context;
+use crate::dc_receive_imf::dc_receive_imf_inner;
+use crate::events::EventType;
+use crate::network::with_timeout;
+
+/// Operations on the server used by background jobs.
+#[async_trait]
+pub(crate) trait ImapSession {
+    /// Returns the Message-IDs of the messages with the given UIDs in `folder`.
+    ///
+    /// Messages which are not on the server anymore are missing from the result.
+    async fn fetch_message_ids(
+        &mut self,
+        context: &Context,
+        folder: &str,
+        uids: &[u32],
+    ) -> Result<BTreeMap<u32, String>>;
+
+    /// Returns the Message-IDs of all messages in `folder` by UID.
+    async fn fetch_all_message_ids(
+        &mut self,
+        context: &Context,
+        folder: &str,
+    ) -> Result<BTreeMap<u32, String>>;
+
+    /// Downloads the messages with the given UIDs from `folder`
+    /// and passes them to `dc_receive_imf()`.
+    async fn fetch_msgs(&mut self, context: &Context, folder: &str, uids: Vec<u32>) -> Result<()>;
+
+    /// Fetches a single message completely and passes it to `dc_receive_imf()`.
+    ///
+    /// Used to replace partially downloaded messages, see [crate::download].
+    async fn fetch_single_msg(
+        &mut self,
+        context: &Context,
+        folder: &str,
+        uid: u32,
+    ) -> ImapActionResult;
+
+    /// Deletes the messages with the given UIDs from `folder`.
+    async fn delete_msgs(&mut self, context: &Context, folder: &str, uids: &[u32]) -> Result<()>;
+
+    /// Returns the raw response to `GETQUOTAROOT` for `folder`,
+    /// `None` if the server does not support quotas.
+    async fn query_quota_root(&mut self, folder: &str) -> Result<Option<String>>;
+}
+
+#[async_trait]
+impl ImapSession for Imap {
+    async fn fetch_message_ids(
+        &mut self,
+        context: &Context,
+        folder: &str,
+        uids: &[u32],
+    ) -> Result<BTreeMap<u32, String>> {
+        self.fetch_message_ids_in_set(context, folder, uids.iter().join(","))
+            .await
+    }
+
+    async fn fetch_all_message_ids(
+        &mut self,
+        context: &Context,
+        folder: &str,
+    ) -> Result<BTreeMap<u32, String>> {
+        self.fetch_message_ids_in_set(context, folder, "1:*".to_string())
+            .await
+            .with_context(|| format!("Can't resync folder {}", folder))
+    }
+
+    async fn fetch_msgs(&mut self, context: &Context, folder: &str, uids: Vec<u32>) -> Result<()> {
+        let count = uids.len();
+        let (_, errors) = self
+            .fetch_many_msgs(context, folder, uids, false, false)
+            .await;
+        ensure!(
+            errors == 0,
+            "{} of {} new messages in {} could not be fetched",
+            errors,
+            count,
+            folder
+        );
+        Ok(())
+    }
+
+    async fn fetch_single_msg(
+        &mut self,
+        context: &Context,
+        folder: &str,
+        uid: u32,
+    ) -> ImapActionResult {
+        if let Some(imapresult) = self
+            .prepare_imap_operation_on_msg(context, folder, uid)
+            .await
+        {
+            return imapresult;
+        }
+
+        // we are connected, and the folder is selected
+        info!(context, "Downloading message {}/{} fully...", folder, uid);
+        let session = match self.session.as_mut() {
+            Some(session) => session,
+            None => return ImapActionResult::RetryLater,
+        };
+        let timeout = session.io_timeout;
+        let mut msgs =
+            match with_io_timeout(timeout, session.uid_fetch(uid.to_string(), BODY_FLAGS)).await {
+                Ok(msgs) => msgs,
+                Err(err) => {
+                    self.should_reconnect = true;
+                    warn!(
+                        context,
+                        "Error on fetching message {}/{}: {}", folder, uid, err
+                    );
+                    return ImapActionResult::RetryLater;
+                }
+            };
+
+        let mut result = ImapActionResult::Failed;
+        while let Ok(Some(Ok(msg))) = with_timeout(timeout, msgs.next()).await {
+            if msg.uid != Some(uid) {
+                continue;
+            }
+            let body = match msg.body() {
+                Some(body) => body,
+                None => continue,
+            };
+            let is_seen = msg.flags().any(|flag| flag == Flag::Seen);
+            result =
+                match dc_receive_imf_inner(context, body, folder, uid, is_seen, None, false, false)
+                    .await
+                {
+                    Ok(()) => ImapActionResult::Success,
+                    Err(err) => {
+                        warn!(context, "dc_receive_imf error: {}", err);
+                        ImapActionResult::RetryLater
+                    }
+                };
+        }
+        if result == ImapActionResult::Failed {
+            warn!(
+                context,
+                "Message {}/{} not found on the server", folder, uid
+            );
+        }
+        result
+    }
+
+    async fn delete_msgs(&mut self, context: &Context, folder: &str, uids: &[u32]) -> Result<()> {
+        self.select_folder(context, Some(folder)).await?;
+        {
+            let session = self.session.as_mut().context("no IMAP session")?;
+            let timeout = session.io_timeout;
+            let mut responses = with_io_timeout(
+                timeout,
+                session.uid_store(uids.iter().join(","), "+FLAGS (\\Deleted)"),
+            )
+            .await?;
+            while let Some(_response) = with_timeout(timeout, responses.next()).await? {
+                // Read all the responses
+            }
+        }
+        self.config.selected_folder_needs_expunge = true;
+        self.maybe_close_folder(context).await?;
+
+        emit_event!(
+            context,
+            EventType::ImapMessageDeleted(format!(
+                "{} IMAP messages in {} deleted by cleanup",
+                uids.len(),
+                folder
+            ))
+        );
+        Ok(())
+    }
+
+    async fn query_quota_root(&mut self, folder: &str) -> Result<Option<String>> {
+        if !self.can_quota() {
+            return Ok(None);
+        }
+        self.get_quota_root(folder).await.map(Some)
+    }
+}
+
+impl Imap {
+    /// Returns the Message-IDs of the messages in the UID set `uid_set` of `folder`.
+    async fn fetch_message_ids_in_set(
+        &mut self,
+        context: &Context,
+        folder: &str,
+        uid_set: String,
+    ) -> Result<BTreeMap<u32, String>> {
+        self.select_folder(context, Some(folder)).await?;
+        let session = self.session.as_mut().context("no IMAP session")?;
+
+        let mut message_ids = BTreeMap::new();
+        let timeout = session.io_timeout;
+        let mut list = with_io_timeout(timeout, session.uid_fetch(uid_set, RFC724MID_UID)).await?;
+        while let Some(fetch) = with_timeout(timeout, list.next()).await? {
+            let fetch = fetch?;
+            if let Some(uid) = fetch.uid {
+                if let Ok(message_id) =
+                    get_fetch_headers(&fetch).and_then(|headers| prefetch_get_message_id(&headers))
+                {
+                    message_ids.insert(uid, message_id);
+                }
+            }
+        }
+        Ok(message_ids)
+    }
+}
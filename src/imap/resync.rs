@@ -0,0 +1,262 @@
+//! # Folder resynchronization
+//!
+//! When the server changes the UIDVALIDITY of a folder, all UIDs stored in the database
+//! for this folder become invalid.  Instead of downloading the folder again, the
+//! Message-IDs of all messages on the server are fetched and matched to the local messages,
+//! which only get their UIDs updated and keep their state.  Messages not known locally
+//! which are newer than all matched messages are downloaded afterwards.  Older unknown
+//! messages, e.g. mail from before the account was set up, are not downloaded,
+//! just as they are not downloaded by the regular fetching.
+//!
+//! The same reconciliation can be started manually with [`Context::resync_folders`],
+//! e.g. if a folder looks wrong.
+
+use anyhow::{ensure, Result};
+
+use super::ImapSession;
+use crate::context::Context;
+use crate::job;
+
+/// Result of resynchronizing one or more folders.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct ResyncStats {
+    /// Number of messages on the server matched to local messages.
+    pub matched: usize,
+
+    /// Number of messages on the server not known locally and newer than all matched messages.
+    pub new: usize,
+
+    /// Number of local messages of the folder not found on the server anymore.
+    pub orphaned: usize,
+}
+
+impl std::ops::AddAssign for ResyncStats {
+    fn add_assign(&mut self, other: Self) {
+        self.matched += other.matched;
+        self.new += other.new;
+        self.orphaned += other.orphaned;
+    }
+}
+
+/// Synchronizes the UIDs of the local messages in `folder` with the server
+/// and downloads the messages not known locally which have a greater UID than
+/// all matched messages.
+///
+/// It is assumed that no operations are taking place on the same
+/// folder at the moment. Make sure to run it in the same
+/// thread/task as other network operations on this folder to
+/// avoid race conditions.
+pub(crate) async fn resync_folder(
+    context: &Context,
+    session: &mut impl ImapSession,
+    folder: &str,
+) -> Result<ResyncStats> {
+    let msg_ids = session.fetch_all_message_ids(context, folder).await?;
+    info!(
+        context,
+        "Resync: collected {} message IDs in folder {}",
+        msg_ids.len(),
+        folder
+    );
+
+    // Sort out the Message-IDs which are certainly unknown without querying the database.
+    let mut known = Vec::with_capacity(msg_ids.len());
+    let mut new_uids = Vec::new();
+    for (uid, rfc724_mid) in msg_ids {
+        if context.sql.rfc724_mid_may_exist(&rfc724_mid).await? {
+            known.push((uid, rfc724_mid));
+        } else {
+            new_uids.push(uid);
+        }
+    }
+
+    let folder_name = folder.to_string();
+    let (matched, max_matched_uid, unmatched, orphaned) = context
+        .sql
+        .with_conn(move |mut conn| {
+            let tx = conn.transaction()?;
+            tx.execute(
+                "UPDATE msgs SET server_uid=0 WHERE server_folder=?",
+                params![folder_name],
+            )?;
+            let mut matched = 0;
+            let mut max_matched_uid = 0;
+            let mut unmatched = Vec::new();
+            for (uid, rfc724_mid) in &known {
+                // This may detect previously undetected moved
+                // messages, so we update server_folder too.
+                let updated = tx.execute(
+                    "UPDATE msgs SET server_folder=?,server_uid=? WHERE rfc724_mid=?",
+                    params![folder_name, uid, rfc724_mid],
+                )?;
+                if updated > 0 {
+                    matched += 1;
+                    max_matched_uid = max_matched_uid.max(*uid);
+                } else {
+                    unmatched.push(*uid);
+                }
+            }
+            let orphaned: i64 = tx.query_row(
+                "SELECT COUNT(*) FROM msgs WHERE server_folder=? AND server_uid=0",
+                params![folder_name],
+                |row| row.get(0),
+            )?;
+            tx.commit()?;
+            Ok((matched, max_matched_uid, unmatched, orphaned as usize))
+        })
+        .await?;
+
+    // Messages older than the newest known message were skipped before,
+    // e.g. because they were on the server before the account was set up.
+    new_uids.extend(unmatched);
+    new_uids.retain(|uid| *uid > max_matched_uid);
+    new_uids.sort_unstable();
+    let stats = ResyncStats {
+        matched,
+        new: new_uids.len(),
+        orphaned,
+    };
+    info!(
+        context,
+        "Resync of folder {}: {} matched, {} new, {} orphaned",
+        folder,
+        stats.matched,
+        stats.new,
+        stats.orphaned
+    );
+
+    if !new_uids.is_empty() {
+        session.fetch_msgs(context, folder, new_uids).await?;
+    }
+    Ok(stats)
+}
+
+impl Context {
+    /// Reconciles the local messages with the folders on the server in the background.
+    ///
+    /// The messages on the server are matched to local messages by their Message-ID,
+    /// which keep their state and only get their UIDs updated.  Messages not known locally
+    /// are downloaded if they are newer than all matched messages.  The result is reported by [`crate::events::EventType::FoldersResynced`].
+    ///
+    /// This happens automatically if the server changes the UIDVALIDITY of a folder.
+    pub async fn resync_folders(&self) -> Result<()> {
+        ensure!(self.is_configured().await, "Not configured");
+        job::schedule_resync(self).await;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::message::{self, MessageState};
+    use crate::test_utils::{chat_msg, receive_chat_msg, MockSession, TestContext};
+
+    async fn count_duplicates(t: &TestContext) -> i64 {
+        t.sql
+            .query_get_value_result(
+                "SELECT COUNT(*) FROM (SELECT rfc724_mid FROM msgs GROUP BY rfc724_mid HAVING COUNT(*)>1);",
+                paramsv![],
+            )
+            .await
+            .unwrap()
+            .unwrap()
+    }
+
+    #[async_std::test]
+    async fn test_resync_after_uidvalidity_change() {
+        let t = TestContext::new_alice().await;
+        let mut server = MockSession::default();
+
+        // 101 messages are known locally, the last one will be deleted on the server.
+        for i in 1..=101 {
+            receive_chat_msg(&t, "bob@example.net", i).await;
+        }
+        let (_, _, seen_msg_id) = message::rfc724_mid_exists(&t, "1@example.org")
+            .await
+            .unwrap()
+            .unwrap();
+        t.sql
+            .execute(
+                "UPDATE msgs SET state=? WHERE id=?;",
+                paramsv![MessageState::InSeen, seen_msg_id],
+            )
+            .await
+            .unwrap();
+
+        // After the UIDVALIDITY change, the server has 100 known messages and 5 new ones
+        // with different UIDs, and an old message from before the account was set up.
+        server.store(
+            "INBOX",
+            1000,
+            chat_msg("bob@example.net", "old@example.net", "hello"),
+        );
+        for i in 1..=105 {
+            let message_id = if i <= 100 {
+                format!("{}@example.org", i)
+            } else {
+                format!("new{}@example.net", i)
+            };
+            server.store(
+                "INBOX",
+                1000 + i,
+                chat_msg("bob@example.net", &message_id, "hello"),
+            );
+        }
+
+        let stats = resync_folder(&t, &mut server, "INBOX").await.unwrap();
+        assert_eq!(
+            stats,
+            ResyncStats {
+                matched: 100,
+                new: 5,
+                orphaned: 1,
+            }
+        );
+        assert_eq!(server.downloaded, vec![1101, 1102, 1103, 1104, 1105]);
+        assert_eq!(count_duplicates(&t).await, 0);
+        assert!(message::rfc724_mid_exists(&t, "old@example.net")
+            .await
+            .unwrap()
+            .is_none());
+
+        for i in 1..=100 {
+            let (folder, uid, _) = message::rfc724_mid_exists(&t, &format!("{}@example.org", i))
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(folder, "INBOX");
+            assert_eq!(uid, 1000 + i);
+        }
+        let (_, uid, _) = message::rfc724_mid_exists(&t, "101@example.org")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(uid, 0);
+        let (_, uid, _) = message::rfc724_mid_exists(&t, "new101@example.net")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(uid, 1101);
+
+        // The state of existing messages is kept.
+        let msg = message::Message::load_from_db(&t, seen_msg_id)
+            .await
+            .unwrap();
+        assert_eq!(msg.state, MessageState::InSeen);
+
+        // A second resync matches everything.
+        let stats = resync_folder(&t, &mut server, "INBOX").await.unwrap();
+        assert_eq!(
+            stats,
+            ResyncStats {
+                matched: 105,
+                new: 0,
+                orphaned: 1,
+            }
+        );
+        assert_eq!(server.downloaded.len(), 5);
+        assert_eq!(count_duplicates(&t).await, 0);
+    }
+}
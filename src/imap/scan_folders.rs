@@ -5,8 +5,10 @@
 use crate::config::Config;
 use crate::context::Context;
 use crate::imap::Imap;
+use crate::network::with_timeout;
 use async_std::prelude::*;
 
+use super::session::with_io_timeout;
 use super::{get_folder_meaning, get_folder_meaning_by_name, FolderMeaning};
 
 impl Imap {
@@ -28,7 +30,14 @@ pub async fn scan_folders(&mut self, context: &Context) -> Result<()> {
         self.setup_handle(context).await?;
         let session = self.session.as_mut();
         let session = session.context("scan_folders(): IMAP No Connection established")?;
-        let folders: Vec<_> = session.list(Some(""), Some("*")).await?.collect().await;
+        let timeout = session.io_timeout;
+        let folders: Vec<_> = with_timeout(
+            timeout,
+            with_io_timeout(timeout, session.list(Some(""), Some("*")))
+                .await?
+                .collect(),
+        )
+        .await?;
         let watched_folders = get_watched_folders(context).await;
 
         let mut sentbox_folder = None;
@@ -87,7 +96,7 @@ pub async fn scan_folders(&mut self, context: &Context) -> Result<()> {
     }
 }
 
-async fn get_watched_folders(context: &Context) -> Vec<String> {
+pub(crate) async fn get_watched_folders(context: &Context) -> Vec<String> {
     let mut res = Vec::new();
     let folder_watched_configured = &[
         (Config::SentboxWatch, Config::ConfiguredSentboxFolder),
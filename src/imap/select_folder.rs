@@ -1,3 +1,4 @@
+use super::session::with_io_timeout;
 use super::Imap;
 
 use crate::context::Context;
@@ -32,7 +33,7 @@ pub(super) async fn close_folder(&mut self, context: &Context) -> Result<()> {
             info!(context, "Expunge messages in \"{}\".", folder);
 
             if let Some(ref mut session) = self.session {
-                match session.close().await {
+                match with_io_timeout(session.io_timeout, session.close()).await {
                     Ok(_) => {
                         info!(context, "close/expunge succeeded");
                     }
@@ -90,7 +91,7 @@ pub(super) async fn select_folder<S: AsRef<str>>(
         // select new folder
         if let Some(ref folder) = folder {
             if let Some(ref mut session) = &mut self.session {
-                let res = session.select(folder).await;
+                let res = with_io_timeout(session.io_timeout, session.select(folder)).await;
 
                 // https://tools.ietf.org/html/rfc3501#section-6.3.1
                 // says that if the server reports select failure we are in
@@ -1,12 +1,20 @@
+use std::future::Future;
 use std::ops::{Deref, DerefMut};
+use std::time::Duration;
 
+use async_imap::error::Result as ImapResult;
 use async_imap::Session as ImapSession;
 use async_native_tls::TlsStream;
 use async_std::net::TcpStream;
 
+use crate::network::with_timeout;
+
 #[derive(Debug)]
 pub(crate) struct Session {
     pub(super) inner: ImapSession<Box<dyn SessionStream>>,
+
+    /// Time allowed for a single command, see [crate::network::NetworkPolicy].
+    pub(super) io_timeout: Duration,
 }
 
 pub(crate) trait SessionStream:
@@ -34,7 +42,19 @@ fn deref_mut(&mut self) -> &mut Self::Target {
 
 impl Session {
     pub fn idle(self) -> async_imap::extensions::idle::Handle<Box<dyn SessionStream>> {
-        let Session { inner } = self;
+        let Session { inner, .. } = self;
         inner.idle()
     }
 }
+
+/// Runs the IMAP command `fut` with the IO timeout `timeout`.
+///
+/// An elapsed timeout is returned as an IO error, so it is handled like a lost connection.
+pub(crate) async fn with_io_timeout<T>(
+    timeout: Duration,
+    fut: impl Future<Output = ImapResult<T>>,
+) -> ImapResult<T> {
+    with_timeout(timeout, fut)
+        .await
+        .unwrap_or_else(|err| Err(err.into()))
+}
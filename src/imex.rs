@@ -1,33 +1,44 @@
 //! # Import/export module
 
 use std::any::Any;
+use std::collections::{BTreeMap, HashMap};
 use std::ffi::OsStr;
 
 use anyhow::{bail, ensure, format_err, Context as _, Result};
+use async_std::io::Read;
 use async_std::path::{Path, PathBuf};
 use async_std::{
     fs::{self, File},
     prelude::*,
 };
+use once_cell::sync::Lazy;
 use rand::{thread_rng, Rng};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 
-use crate::chat;
-use crate::chat::delete_and_reset_all_device_msgs;
+use crate::chat::{self, delete_all_device_msgs, Chat, ChatId};
 use crate::config::Config;
-use crate::constants::{Viewtype, DC_CONTACT_ID_SELF};
+use crate::constants::{Viewtype, DC_CHAT_ID_LAST_SPECIAL, DC_CONTACT_ID_SELF};
+use crate::contact::{addr_cmp, Contact, Origin};
 use crate::context::Context;
+use crate::dc_receive_imf::dc_receive_imf_inner;
 use crate::dc_tools::{
-    dc_copy_file, dc_create_folder, dc_delete_file, dc_delete_files_in_dir, dc_get_filesuffix_lc,
-    dc_open_file_std, dc_read_file, dc_write_file, get_next_backup_path, time, EmailAddress,
+    dc_copy_file, dc_create_folder, dc_create_id, dc_delete_file, dc_delete_files_in_dir,
+    dc_get_abs_path, dc_get_filesuffix_lc, dc_open_file_std, dc_read_file, dc_write_file,
+    get_next_backup_path, time, EmailAddress,
 };
 use crate::e2ee;
+use crate::ephemeral;
 use crate::events::EventType;
-use crate::key::{self, DcKey, DcSecretKey, SignedPublicKey, SignedSecretKey};
-use crate::message::{Message, MsgId};
-use crate::mimeparser::SystemMessage;
+use crate::headerdef::{HeaderDef, HeaderDefMap};
+use crate::key::{self, DcKey, DcSecretKey, Fingerprint, SignedPublicKey, SignedSecretKey};
+use crate::message::{rfc724_mid_exists, search_fold, Message, MessageState, MsgId};
+use crate::mimeparser::{parse_message_id, SystemMessage};
+use crate::ongoing::OperationKind;
 use crate::param::Param;
 use crate::pgp;
-use crate::sql::{self, Sql};
+use crate::sql::{self, Sql, DBVERSION};
 use crate::stock_str;
 use crate::{blob::BlobObject, log::LogExt};
 use ::pgp::types::KeyTrait;
@@ -37,6 +48,45 @@
 const DBFILE_BACKUP_NAME: &str = "dc_database_backup.sqlite";
 const BLOBS_BACKUP_NAME: &str = "blobs_backup";
 
+// Names of the entries in a v2 backup.
+const DBFILE_BACKUP_NAME_V2: &str = "dc.db";
+const BLOBS_BACKUP_NAME_V2: &str = "blobs";
+const MANIFEST_BACKUP_NAME: &str = "manifest.json";
+
+// Name of the database in an incremental backup, otherwise laid out as a v2 backup.
+const DBFILE_INCREMENTAL_NAME: &str = "dc-incremental.db";
+
+// Raw config keys identifying the last backup of an account.
+const BACKUP_ID_KEY: &str = "backup_id";
+const BACKUP_TIME_KEY: &str = "backup_time";
+
+/// Tables of which incremental backups contain the rows changed since the previous backup.
+///
+/// Changes are tracked in the `update_timestamp` column, deleted rows in the
+/// `backup_tombstones` table.
+const INCREMENTAL_TABLES: [&str; 3] = ["msgs", "chats", "contacts"];
+
+/// Tables copied completely into incremental backups.
+const INCREMENTAL_FULL_TABLES: [&str; 14] = [
+    "config",
+    "ui_config",
+    "keypairs",
+    "acpeerstates",
+    "peerstate_history",
+    "msgs_mdns",
+    "msgs_broadcast",
+    "reactions",
+    "msgs_status_updates",
+    "msgs_chunks",
+    "tokens",
+    "leftgrps",
+    "locations",
+    "devmsglabels",
+];
+
+/// Format version written to the manifest of v2 backups.
+const BACKUP_MANIFEST_VERSION: u32 = 2;
+
 #[derive(Debug, Display, Copy, Clone, PartialEq, Eq, FromPrimitive, ToPrimitive)]
 #[repr(i32)]
 pub enum ImexMode {
@@ -61,7 +111,59 @@ pub enum ImexMode {
     /// `param1` is the file (not: directory) to import. The file is normally
     /// created by DC_IMEX_EXPORT_BACKUP and detected by dc_imex_has_backup(). Importing a backup
     /// is only possible as long as the context is not configured or used in another way.
+    ///
+    /// Incremental backups are applied on top of the account instead, which must have been
+    /// imported from the backup the incremental backup is based on.
     ImportBackup = 12,
+
+    /// Export an incremental backup to the directory given as `param1`.
+    /// The backup contains the messages, chats and contacts changed since the last backup
+    /// and the files added to the blob directory since then.
+    /// The name of the backup is `delta-chat-backup-<day>-<number>-incremental.tar`.
+    ExportBackupIncremental = 13,
+}
+
+/// Format of the backups written by [`ImexMode::ExportBackup`].
+///
+/// Both formats are tar files, importing detects the format automatically.
+#[derive(Debug, Display, Copy, Clone, PartialEq, Eq, FromPrimitive, ToPrimitive)]
+#[repr(i32)]
+pub enum BackupFormat {
+    /// The database and the blobs, without any checksums.
+    V1 = 1,
+
+    /// The database, the blobs and a manifest with the BLAKE3 checksums of all files.
+    ///
+    /// The manifest is the last entry, so the backup can be written as a stream.  Imports
+    /// verify all checksums before the account is modified.
+    V2 = 2,
+}
+
+impl Default for BackupFormat {
+    fn default() -> Self {
+        BackupFormat::V2
+    }
+}
+
+/// Options for [`ImexMode::ExportBackup`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct BackupOptions {
+    pub format: BackupFormat,
+
+    /// Export a snapshot of the database taken while it stays open.
+    ///
+    /// IO can keep running during the export then.  If disabled, the database is closed
+    /// during the export, which requires IO to be stopped.
+    pub snapshot: bool,
+}
+
+impl Default for BackupOptions {
+    fn default() -> Self {
+        Self {
+            format: BackupFormat::default(),
+            snapshot: true,
+        }
+    }
 }
 
 /// Import/export things.
@@ -77,45 +179,74 @@ pub enum ImexMode {
 /// - For each file written on export, the function sends #DC_EVENT_IMEX_FILE_WRITTEN
 ///
 /// Only one import-/export-progress can run at the same time.
-/// To cancel an import-/export-progress, drop the future returned by this function.
+/// To cancel an import-/export-progress, call [`Context::cancel_operation`] or drop
+/// the future returned by this function.  Cancelled imports remove everything they have
+/// written already and emit #DC_EVENT_IMEX_PROGRESS with 0, as failed ones do.
+///
+/// Backups are exported with the default [`BackupOptions`].
 pub async fn imex(context: &Context, what: ImexMode, param1: impl AsRef<Path>) -> Result<()> {
-    let cancel = context.alloc_ongoing().await?;
-
-    let res = async {
-        let success = imex_inner(context, what, param1).await;
-        match success {
-            Ok(()) => {
-                info!(context, "IMEX successfully completed");
-                context.emit_event(EventType::ImexProgress(1000));
-                Ok(())
-            }
-            Err(err) => {
-                cleanup_aborted_imex(context, what).await;
-                // We are using Anyhow's .context() and to show the inner error, too, we need the {:#}:
-                error!(context, "{:#}", err);
-                context.emit_event(EventType::ImexProgress(0));
-                bail!("IMEX FAILED to complete: {}", err);
-            }
-        }
-    }
-    .race(async {
-        cancel.recv().await.ok();
-        cleanup_aborted_imex(context, what).await;
-        Err(format_err!("canceled"))
-    })
-    .await;
+    imex_with_options(context, what, param1, BackupOptions::default()).await
+}
+
+/// Like [`imex`], but exports backups with the given options.
+pub async fn imex_with_options(
+    context: &Context,
+    what: ImexMode,
+    param1: impl AsRef<Path>,
+    options: BackupOptions,
+) -> Result<()> {
+    let operation = context.start_operation(OperationKind::Imex, true)?;
+    let incremental = what == ImexMode::ImportBackup
+        && first_backup_entry(param1.as_ref())
+            .await
+            .map_or(false, |name| name == DBFILE_INCREMENTAL_NAME);
+
+    let success = imex_inner(context, what, param1, options)
+        .race(async {
+            operation.canceled().await;
+            Err(format_err!("canceled"))
+        })
+        .await;
 
-    context.free_ongoing().await;
+    // The cleanup must only run once the import/export itself is dropped,
+    // otherwise it could still write files while they are deleted.
+    let res = match success {
+        Ok(()) => {
+            info!(context, "IMEX successfully completed");
+            context.emit_event(EventType::ImexProgress(1000));
+            Ok(())
+        }
+        Err(err) => {
+            cleanup_aborted_imex(context, what, incremental).await;
+            // We are using Anyhow's .context() and to show the inner error, too, we need the {:#}:
+            error!(context, "{:#}", err);
+            context.emit_event(EventType::ImexProgress(0));
+            Err(format_err!("IMEX FAILED to complete: {}", err))
+        }
+    };
 
     res
 }
 
-async fn cleanup_aborted_imex(context: &Context, what: ImexMode) {
-    if what == ImexMode::ImportBackup {
+async fn cleanup_aborted_imex(context: &Context, what: ImexMode, incremental: bool) {
+    // Imports replace the database of an unconfigured account only after the backup was
+    // checked, so there is nothing to clean up if it is still in place.  Incremental
+    // backups are applied in a single transaction to the open database.
+    let untouched = context.sql.is_open().await && !context.is_configured().await;
+    if what == ImexMode::ImportBackup && !incremental && !untouched {
         dc_delete_file(context, context.get_dbfile()).await;
+        for dir in &[BLOBS_BACKUP_NAME, BLOBS_BACKUP_NAME_V2] {
+            fs::remove_dir_all(context.get_blobdir().join(dir))
+                .await
+                .ok();
+        }
         dc_delete_files_in_dir(context, context.get_blobdir()).await;
     }
-    if what == ImexMode::ExportBackup || what == ImexMode::ImportBackup {
+    if (what == ImexMode::ExportBackup
+        || what == ImexMode::ExportBackupIncremental
+        || what == ImexMode::ImportBackup)
+        && !context.sql.is_open().await
+    {
         if let Err(e) = context.sql.open(context, context.get_dbfile(), false).await {
             warn!(context, "Re-opening db after imex failed: {}", e);
         }
@@ -136,6 +267,7 @@ pub async fn has_backup(context: &Context, dir_name: impl AsRef<Path>) -> Result
             let name: String = name.to_string_lossy().into();
             if name.starts_with("delta-chat")
                 && name.ends_with(".tar")
+                && !name.ends_with("-incremental.tar")
                 && (newest_backup_name.is_empty() || name > newest_backup_name)
             {
                 // We just use string comparison to determine which backup is newer.
@@ -206,23 +338,90 @@ pub async fn has_backup_old(context: &Context, dir_name: impl AsRef<Path>) -> Re
     }
 }
 
-pub async fn initiate_key_transfer(context: &Context) -> Result<String> {
-    use futures::future::FutureExt;
+/// Number of wrong setup codes accepted by [continue_key_transfer] without delay.
+const KEY_TRANSFER_FREE_ATTEMPTS: i32 = 3;
 
-    let cancel = context.alloc_ongoing().await?;
-    let res = do_initiate_key_transfer(context)
-        .race(cancel.recv().map(|_| Err(format_err!("canceled"))))
-        .await;
+/// Delay after the first wrong setup code exceeding [KEY_TRANSFER_FREE_ATTEMPTS] in seconds,
+/// doubled with each further wrong code.
+const KEY_TRANSFER_BASE_DELAY: i64 = 60;
 
-    context.free_ongoing().await;
-    res
+/// Upper bound of the delay between two setup code attempts in seconds.
+const KEY_TRANSFER_MAX_DELAY: i64 = 24 * 60 * 60;
+
+// Raw config keys of the number and time of wrong setup codes.
+const KEY_TRANSFER_FAILURES_KEY: &str = "key_transfer_failures";
+const KEY_TRANSFER_LAST_FAILURE_KEY: &str = "key_transfer_last_failure";
+
+/// Error of [continue_key_transfer].
+///
+/// Returned wrapped in [anyhow::Error], use `downcast_ref()` to tell the cases apart.
+#[derive(Debug, thiserror::Error)]
+pub enum KeyTransferError {
+    /// The message is no Autocrypt Setup Message.
+    #[error("Message is no Autocrypt Setup Message.")]
+    NoSetupMessage,
+
+    /// The setup code does not decrypt the setup message.
+    #[error("Wrong setup code.")]
+    WrongSetupCode,
+
+    /// Too many wrong setup codes were entered, try again after the given number of seconds.
+    #[error("Too many wrong setup codes, try again in {0} seconds.")]
+    TooManyAttempts(i64),
+
+    /// The setup message does not contain an encrypted private key.
+    #[error("Autocrypt Setup Message is corrupt: {0:#}")]
+    CorruptMessage(anyhow::Error),
+
+    /// The transferred key is not for the configured address.
+    #[error("Key is for {key_addrs}, not for the configured address {self_addr}.")]
+    KeyMismatch {
+        key_addrs: String,
+        self_addr: String,
+    },
+}
+
+pub async fn initiate_key_transfer(context: &Context) -> Result<String> {
+    let operation = context.start_operation(OperationKind::KeyTransfer, true)?;
+    do_initiate_key_transfer(context)
+        .race(async {
+            operation.canceled().await;
+            Err(format_err!("canceled"))
+        })
+        .await
+}
+
+/// Returns true while [initiate_key_transfer] is sending the Autocrypt Setup Message.
+pub fn has_ongoing_key_transfer(context: &Context) -> bool {
+    context.is_operation_running(OperationKind::KeyTransfer)
 }
 
 async fn do_initiate_key_transfer(context: &Context) -> Result<String> {
-    let mut msg: Message;
     let setup_code = create_setup_code(context);
+    let msg_id = send_setup_message(context, &setup_code).await?;
+    info!(context, "Wait for setup message being sent ...",);
+    while !context.is_operation_canceled(OperationKind::KeyTransfer) {
+        async_std::task::sleep(std::time::Duration::from_secs(1)).await;
+        if let Ok(msg) = Message::load_from_db(context, msg_id).await {
+            if msg.is_sent() {
+                info!(context, "... setup message sent.",);
+                break;
+            }
+        }
+    }
+    // no maybe_add_bcc_self_device_msg() here.
+    // the ui shows the dialog with the setup code on this device,
+    // it would be too much noise to have two things popping up at the same time.
+    // maybe_add_bcc_self_device_msg() is called on the other device
+    // once the transfer is completed.
+    Ok(setup_code)
+}
+
+/// Sends an Autocrypt Setup Message containing the secret key encrypted with `setup_code`
+/// to self, without waiting for it to be sent.
+async fn send_setup_message(context: &Context, setup_code: &str) -> Result<MsgId> {
     /* this may require a keypair to be created. this may take a second ... */
-    let setup_file_content = render_setup_file(context, &setup_code).await?;
+    let setup_file_content = render_setup_file(context, setup_code).await?;
     /* encrypting may also take a while ... */
     let setup_file_blob = BlobObject::create(
         context,
@@ -232,7 +431,7 @@ async fn do_initiate_key_transfer(context: &Context) -> Result<String> {
     .await?;
 
     let chat_id = chat::create_by_contact_id(context, DC_CONTACT_ID_SELF).await?;
-    msg = Message::default();
+    let mut msg = Message::default();
     msg.viewtype = Viewtype::File;
     msg.param.set(Param::File, setup_file_blob.as_name());
 
@@ -242,23 +441,7 @@ async fn do_initiate_key_transfer(context: &Context) -> Result<String> {
     msg.param.set_int(Param::ForcePlaintext, 1);
     msg.param.set_int(Param::SkipAutocrypt, 1);
 
-    let msg_id = chat::send_msg(context, chat_id, &mut msg).await?;
-    info!(context, "Wait for setup message being sent ...",);
-    while !context.shall_stop_ongoing().await {
-        async_std::task::sleep(std::time::Duration::from_secs(1)).await;
-        if let Ok(msg) = Message::load_from_db(context, msg_id).await {
-            if msg.is_sent() {
-                info!(context, "... setup message sent.",);
-                break;
-            }
-        }
-    }
-    // no maybe_add_bcc_self_device_msg() here.
-    // the ui shows the dialog with the setup code on this device,
-    // it would be too much noise to have two things popping up at the same time.
-    // maybe_add_bcc_self_device_msg() is called on the other device
-    // once the transfer is completed.
-    Ok(setup_code)
+    chat::send_msg(context, chat_id, &mut msg).await
 }
 
 /// Renders HTML body of a setup file message.
@@ -309,6 +492,7 @@ pub async fn render_setup_file(context: &Context, passphrase: &str) -> Result<St
     ))
 }
 
+/// Creates a random setup code of nine blocks of four digits, eg. `1234-5678-...`.
 pub fn create_setup_code(_context: &Context) -> String {
     let mut random_val: u16;
     let mut rng = thread_rng();
@@ -347,6 +531,11 @@ async fn maybe_add_bcc_self_device_msg(context: &Context) -> Result<()> {
     Ok(())
 }
 
+/// Imports the secret key of the Autocrypt Setup Message `msg_id` using `setup_code`.
+///
+/// Errors about the setup message or code are [KeyTransferError]s.
+/// After [KEY_TRANSFER_FREE_ATTEMPTS] wrong setup codes,
+/// further attempts are delayed increasingly to slow down guessing the code.
 pub async fn continue_key_transfer(
     context: &Context,
     msg_id: MsgId,
@@ -355,21 +544,78 @@ pub async fn continue_key_transfer(
     ensure!(!msg_id.is_special(), "wrong id");
 
     let msg = Message::load_from_db(context, msg_id).await?;
-    ensure!(
-        msg.is_setupmessage(),
-        "Message is no Autocrypt Setup Message."
-    );
+    if !msg.is_setupmessage() {
+        return Err(KeyTransferError::NoSetupMessage.into());
+    }
+    let filename = msg
+        .get_file(context)
+        .ok_or(KeyTransferError::NoSetupMessage)?;
 
-    if let Some(filename) = msg.get_file(context) {
-        let file = dc_open_file_std(context, filename)?;
-        let sc = normalize_setup_code(setup_code);
-        let armored_key = decrypt_setup_file(&sc, file).await?;
-        set_self_key(context, &armored_key, true, true).await?;
-        maybe_add_bcc_self_device_msg(context).await?;
+    let failures = context
+        .sql
+        .get_raw_config_int(context, KEY_TRANSFER_FAILURES_KEY)
+        .await
+        .unwrap_or_default();
+    let last_failure = context
+        .sql
+        .get_raw_config_int64(context, KEY_TRANSFER_LAST_FAILURE_KEY)
+        .await
+        .unwrap_or_default();
+    let wait = key_transfer_delay(failures) - (time() - last_failure);
+    if wait > 0 {
+        return Err(KeyTransferError::TooManyAttempts(wait).into());
+    }
 
-        Ok(())
+    let file = dc_open_file_std(context, filename)?;
+    let sc = normalize_setup_code(setup_code);
+    let armored_key = match decrypt_setup_file(&sc, file).await {
+        Ok(armored_key) => armored_key,
+        Err(err) => {
+            if let KeyTransferError::WrongSetupCode = err {
+                let sql = &context.sql;
+                sql.set_raw_config_int(context, KEY_TRANSFER_FAILURES_KEY, failures + 1)
+                    .await?;
+                sql.set_raw_config_int64(context, KEY_TRANSFER_LAST_FAILURE_KEY, time())
+                    .await?;
+            }
+            return Err(err.into());
+        }
+    };
+    context
+        .sql
+        .set_raw_config_int(context, KEY_TRANSFER_FAILURES_KEY, 0)
+        .await?;
+
+    let (private_key, _header) =
+        SignedSecretKey::from_asc(&armored_key).map_err(KeyTransferError::CorruptMessage)?;
+    let self_addr = context
+        .get_config(Config::ConfiguredAddr)
+        .await
+        .context("Missing self addr")?;
+    let key_addrs = key_addrs(&private_key);
+    if !key_addrs.iter().any(|addr| addr_cmp(addr, &self_addr)) {
+        return Err(KeyTransferError::KeyMismatch {
+            key_addrs: key_addrs.join(", "),
+            self_addr,
+        }
+        .into());
+    }
+
+    set_self_key(context, &armored_key, true, true).await?;
+    maybe_add_bcc_self_device_msg(context).await?;
+
+    Ok(())
+}
+
+/// Returns the time to wait after `failures` wrong setup codes before the next attempt.
+fn key_transfer_delay(failures: i32) -> i64 {
+    if failures < KEY_TRANSFER_FREE_ATTEMPTS {
+        0
     } else {
-        bail!("Message is no Autocrypt Setup Message.");
+        let exponent = (failures - KEY_TRANSFER_FREE_ATTEMPTS).min(16) as u32;
+        KEY_TRANSFER_BASE_DELAY
+            .saturating_mul(2i64.pow(exponent))
+            .min(KEY_TRANSFER_MAX_DELAY)
     }
 }
 
@@ -430,13 +676,36 @@ async fn set_self_key(
 async fn decrypt_setup_file<T: std::io::Read + std::io::Seek>(
     passphrase: &str,
     file: T,
-) -> Result<String> {
-    let plain_bytes = pgp::symm_decrypt(passphrase, file).await?;
-    let plain_text = std::string::String::from_utf8(plain_bytes)?;
+) -> Result<String, KeyTransferError> {
+    let plain_bytes = match pgp::symm_decrypt(passphrase, file).await {
+        Ok(plain_bytes) => plain_bytes,
+        Err(err) if err.is::<pgp::InvalidArmoredMessage>() => {
+            return Err(KeyTransferError::CorruptMessage(err));
+        }
+        // With a wrong passphrase, decryption fails in different ways.
+        Err(_) => return Err(KeyTransferError::WrongSetupCode),
+    };
+    let plain_text = std::string::String::from_utf8(plain_bytes)
+        .map_err(|err| KeyTransferError::CorruptMessage(err.into()))?;
 
     Ok(plain_text)
 }
 
+/// Returns the addresses of the user IDs of a key.
+fn key_addrs(key: &SignedSecretKey) -> Vec<String> {
+    key.details
+        .users
+        .iter()
+        .map(|user| {
+            let id = user.id.id();
+            match (id.find('<'), id.rfind('>')) {
+                (Some(start), Some(end)) => id.get(start + 1..end).unwrap_or(id).to_string(),
+                _ => id.to_string(),
+            }
+        })
+        .collect()
+}
+
 pub fn normalize_setup_code(s: &str) -> String {
     let mut out = String::new();
     for c in s.chars() {
@@ -450,12 +719,20 @@ pub fn normalize_setup_code(s: &str) -> String {
     out
 }
 
-async fn imex_inner(context: &Context, what: ImexMode, path: impl AsRef<Path>) -> Result<()> {
+async fn imex_inner(
+    context: &Context,
+    what: ImexMode,
+    path: impl AsRef<Path>,
+    options: BackupOptions,
+) -> Result<()> {
     info!(context, "Import/export dir: {}", path.as_ref().display());
     ensure!(context.sql.is_open().await, "Database not opened.");
     context.emit_event(EventType::ImexProgress(10));
 
-    if what == ImexMode::ExportBackup || what == ImexMode::ExportSelfKeys {
+    if what == ImexMode::ExportBackup
+        || what == ImexMode::ExportBackupIncremental
+        || what == ImexMode::ExportSelfKeys
+    {
         // before we export anything, make sure the private key exists
         if e2ee::ensure_secret_key_exists(context).await.is_err() {
             bail!("Cannot create private key or private key not available.");
@@ -468,8 +745,9 @@ async fn imex_inner(context: &Context, what: ImexMode, path: impl AsRef<Path>) -
         ImexMode::ExportSelfKeys => export_self_keys(context, path).await,
         ImexMode::ImportSelfKeys => import_self_keys(context, path).await,
 
-        ImexMode::ExportBackup => export_backup(context, path).await,
-        // import_backup() will call import_backup_old() if this is an old backup.
+        ImexMode::ExportBackup => export_backup(context, path, options).await,
+        ImexMode::ExportBackupIncremental => export_backup_incremental(context, path).await,
+        // import_backup() detects the format of the backup.
         ImexMode::ImportBackup => import_backup(context, path).await,
     }
 }
@@ -485,6 +763,141 @@ async fn import_backup(context: &Context, backup_to_import: impl AsRef<Path>) ->
         return import_backup_old(context, backup_to_import).await;
     }
 
+    match detect_backup_format(backup_to_import.as_ref()).await? {
+        BackupFormat::V1 => import_backup_v1(context, backup_to_import).await,
+        BackupFormat::V2 => import_backup_v2(context, backup_to_import).await,
+    }
+}
+
+/// Returns the name of the first entry of a tar backup, which is its database.
+async fn first_backup_entry(backup: &Path) -> Result<String> {
+    let archive = Archive::new(File::open(backup).await?);
+    let mut entries = archive.entries()?;
+    let first = entries.next().await.context("Backup is empty")??;
+    let name = first.path()?.to_string_lossy().into_owned();
+    Ok(name)
+}
+
+/// Detects the format of a tar backup from the name of its first entry.
+///
+/// Incremental backups are written in the v2 format.
+async fn detect_backup_format(backup: &Path) -> Result<BackupFormat> {
+    let name = first_backup_entry(backup).await?;
+    if name == DBFILE_BACKUP_NAME {
+        Ok(BackupFormat::V1)
+    } else if name == DBFILE_BACKUP_NAME_V2 || name == DBFILE_INCREMENTAL_NAME {
+        Ok(BackupFormat::V2)
+    } else {
+        bail!("Unknown backup format, first entry is {:?}", name);
+    }
+}
+
+/// Information about a backup, see [`check_backup`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BackupInfo {
+    /// Format of the backup, `None` for legacy `.bak` backups.
+    pub format: Option<BackupFormat>,
+    /// Database version of the backed up account.
+    pub dbversion: i32,
+    /// Configured address of the backed up account.
+    pub addr: Option<String>,
+    /// Time the backup was created, in seconds since the epoch.
+    pub timestamp: i64,
+    /// Number of messages in the backup.
+    pub msg_count: usize,
+}
+
+/// Checks a backup without importing it.
+///
+/// Fails if the file is not a backup, if it is damaged or if it was created by a newer
+/// version with a database this version cannot read.  Nothing is modified, the database of
+/// tar backups is only extracted to a temporary file in `tmp_dir`, e.g. the accounts
+/// directory, and removed afterwards.  The system's temporary directory is not used as it
+/// is not available on all platforms, e.g. on Android.
+pub async fn check_backup(
+    backup: impl AsRef<Path>,
+    tmp_dir: impl AsRef<Path>,
+) -> Result<BackupInfo> {
+    let backup = backup.as_ref();
+    let (format, dbfile, _tmp) = if backup.to_string_lossy().ends_with(".bak") {
+        (None, backup.to_path_buf(), None)
+    } else {
+        let format = detect_backup_format(backup).await?;
+        if format == BackupFormat::V2 {
+            verify_backup_v2(None, backup, |_| {}).await?;
+        }
+        let tmp = tmp_dir
+            .as_ref()
+            .join(format!("dc-check-backup-{}.sqlite", uuid::Uuid::new_v4()));
+        let tmp_guard = DeleteOnDrop(tmp.clone());
+        extract_backup_dbfile(backup, &tmp).await?;
+        (Some(format), tmp, Some(tmp_guard))
+    };
+
+    let dbfile: std::path::PathBuf = dbfile.into();
+    let info = read_backup_info(&dbfile)
+        .with_context(|| format!("{} is not a Delta Chat backup", backup.display()))?;
+    ensure!(
+        info.dbversion <= DBVERSION,
+        "Backup was created by a newer version (database version {}, supported up to {})",
+        info.dbversion,
+        DBVERSION
+    );
+    Ok(BackupInfo { format, ..info })
+}
+
+/// Copies the database of a tar backup to `dest`.
+async fn extract_backup_dbfile(backup: &Path, dest: &Path) -> Result<()> {
+    let archive = Archive::new(File::open(backup).await?);
+    let mut entries = archive.entries()?;
+    while let Some(entry) = entries.next().await {
+        let f = &mut entry?;
+        let name = f.path()?.to_string_lossy().into_owned();
+        if name == DBFILE_BACKUP_NAME
+            || name == DBFILE_BACKUP_NAME_V2
+            || name == DBFILE_INCREMENTAL_NAME
+        {
+            async_std::io::copy(f, &mut File::create(dest).await?).await?;
+            return Ok(());
+        }
+    }
+    bail!("Backup does not contain a database");
+}
+
+fn read_backup_info(dbfile: &std::path::Path) -> Result<BackupInfo> {
+    use rusqlite::{Connection, OpenFlags, OptionalExtension};
+
+    let conn = Connection::open_with_flags(dbfile, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+    let get_config = |key: &str| -> Result<Option<String>> {
+        let value = conn
+            .query_row("SELECT value FROM config WHERE keyname=?;", &[key], |row| {
+                row.get(0)
+            })
+            .optional()?;
+        Ok(value)
+    };
+    let dbversion = get_config("dbversion")?
+        .context("Database version missing")?
+        .parse()?;
+    let addr = get_config("configured_addr")?;
+    let timestamp = get_config("backup_time")?
+        .and_then(|time| time.parse().ok())
+        .unwrap_or_default();
+    let msg_count: isize = conn.query_row(
+        "SELECT COUNT(*) FROM msgs WHERE chat_id>?;",
+        &[DC_CHAT_ID_LAST_SPECIAL],
+        |row| row.get(0),
+    )?;
+    Ok(BackupInfo {
+        format: None,
+        dbversion,
+        addr,
+        timestamp,
+        msg_count: msg_count as usize,
+    })
+}
+
+async fn import_backup_v1(context: &Context, backup_to_import: impl AsRef<Path>) -> Result<()> {
     info!(
         context,
         "Import \"{}\" to \"{}\".",
@@ -513,6 +926,9 @@ async fn import_backup(context: &Context, backup_to_import: impl AsRef<Path>) ->
 
     let mut entries = archive.entries()?;
     while let Some(file) = entries.next().await {
+        if context.is_operation_canceled(OperationKind::Imex) {
+            bail!("canceled");
+        }
         let f = &mut file?;
 
         let current_pos = f.raw_file_position();
@@ -550,18 +966,134 @@ async fn import_backup(context: &Context, backup_to_import: impl AsRef<Path>) ->
         .await
         .context("Could not re-open db")?;
 
-    delete_and_reset_all_device_msgs(context).await?;
+    delete_all_device_msgs(context).await?;
 
     Ok(())
 }
 
-async fn import_backup_old(context: &Context, backup_to_import: impl AsRef<Path>) -> Result<()> {
+/// Size and checksum of a file in a v2 backup.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct ManifestEntry {
+    size: u64,
+    /// Hex encoded BLAKE3 hash of the file contents.
+    blake3: String,
+}
+
+impl ManifestEntry {
+    /// Reads `reader` to the end and returns the entry describing its contents.
+    async fn from_reader(reader: &mut (impl Read + Unpin)) -> std::io::Result<Self> {
+        let mut hasher = blake3::Hasher::new();
+        let mut buf = vec![0; 64 * 1024];
+        let mut size = 0;
+        loop {
+            let n = reader.read(&mut buf).await?;
+            if n == 0 {
+                break;
+            }
+            hasher.update(buf.get(..n).unwrap_or_default());
+            size += n as u64;
+        }
+        Ok(Self {
+            size,
+            blake3: hasher.finalize().to_hex().to_string(),
+        })
+    }
+}
+
+/// The last entry of a v2 backup.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct BackupManifest {
+    version: u32,
+    /// Random id of the backup, empty for backups written before ids were added.
+    #[serde(default)]
+    id: String,
+    /// Id of the backup an incremental backup applies to, `None` for full backups.
+    #[serde(default)]
+    base: Option<String>,
+    /// Entries of all other files in the backup, keyed by their path in the archive.
+    files: BTreeMap<String, ManifestEntry>,
+}
+
+/// Checks all files of a v2 backup against its manifest.
+///
+/// `on_progress` is called with the permille of the backup read so far.  If `context` is
+/// given, the check is aborted when its imex operation is canceled.
+async fn verify_backup_v2(
+    context: Option<&Context>,
+    backup: &Path,
+    mut on_progress: impl FnMut(u64),
+) -> Result<BackupManifest> {
+    let backup_file = File::open(backup).await?;
+    let file_size = backup_file.metadata().await?.len().max(1);
+    let archive = Archive::new(backup_file);
+
+    let mut files = BTreeMap::new();
+    let mut manifest = None;
+    let mut entries = archive.entries()?;
+    while let Some(entry) = entries.next().await {
+        if let Some(context) = context {
+            if context.is_operation_canceled(OperationKind::Imex) {
+                bail!("canceled");
+            }
+        }
+        let f = &mut entry?;
+
+        on_progress(1000 * f.raw_file_position() / file_size);
+
+        let name = f.path()?.to_string_lossy().into_owned();
+        if name == MANIFEST_BACKUP_NAME {
+            let mut buf = Vec::new();
+            f.read_to_end(&mut buf).await?;
+            manifest = Some(
+                serde_json::from_slice::<BackupManifest>(&buf)
+                    .context("Invalid backup manifest")?,
+            );
+        } else {
+            files.insert(name, ManifestEntry::from_reader(f).await?);
+        }
+    }
+
+    let manifest = manifest.context("Backup manifest is missing, the backup may be truncated")?;
+    ensure!(
+        manifest.version == BACKUP_MANIFEST_VERSION,
+        "Unsupported backup version {}",
+        manifest.version
+    );
+    let dbfile_name = if manifest.base.is_some() {
+        DBFILE_INCREMENTAL_NAME
+    } else {
+        DBFILE_BACKUP_NAME_V2
+    };
+    ensure!(
+        manifest.files.contains_key(dbfile_name),
+        "Backup does not contain a database"
+    );
+    for (name, expected) in &manifest.files {
+        match files.get(name) {
+            Some(found) => ensure!(found == expected, "Checksum mismatch for {}", name),
+            None => bail!("{} is missing in the backup", name),
+        }
+    }
+    if let Some(name) = files
+        .keys()
+        .find(|name| !manifest.files.contains_key(*name))
+    {
+        bail!("{} is not listed in the backup manifest", name);
+    }
+    Ok(manifest)
+}
+
+async fn import_backup_v2(context: &Context, backup_to_import: impl AsRef<Path>) -> Result<()> {
+    let backup_to_import = backup_to_import.as_ref();
     info!(
         context,
         "Import \"{}\" to \"{}\".",
-        backup_to_import.as_ref().display(),
+        backup_to_import.display(),
         context.get_dbfile().display()
     );
+    if first_backup_entry(backup_to_import).await? == DBFILE_INCREMENTAL_NAME {
+        return import_backup_incremental(context, backup_to_import).await;
+    }
 
     ensure!(
         !context.is_configured().await,
@@ -571,6 +1103,17 @@ async fn import_backup_old(context: &Context, backup_to_import: impl AsRef<Path>
         !context.scheduler.read().await.is_running(),
         "cannot import backup, IO already running"
     );
+
+    // Read the whole backup once before touching the account,
+    // so that damaged backups do not destroy it.
+    verify_backup_v2(Some(context), backup_to_import, |permille| {
+        let progress = 10 + 490 * permille / 1000;
+        if progress > 10 {
+            context.emit_event(EventType::ImexProgress(progress as usize));
+        }
+    })
+    .await?;
+
     context.sql.close().await;
     dc_delete_file(context, context.get_dbfile()).await;
     ensure!(
@@ -578,44 +1121,271 @@ async fn import_backup_old(context: &Context, backup_to_import: impl AsRef<Path>
         "Cannot delete old database."
     );
 
-    ensure!(
-        dc_copy_file(context, backup_to_import.as_ref(), context.get_dbfile()).await,
-        "could not copy file"
-    );
-    /* error already logged */
-    /* re-open copied database file */
+    let blobdir = context.get_blobdir();
+    let backup_file = File::open(backup_to_import).await?;
+    let file_size = backup_file.metadata().await?.len().max(1);
+    let archive = Archive::new(backup_file);
+
+    let mut entries = archive.entries()?;
+    while let Some(file) = entries.next().await {
+        if context.is_operation_canceled(OperationKind::Imex) {
+            bail!("canceled");
+        }
+        let f = &mut file?;
+
+        let current_pos = f.raw_file_position();
+        let progress = 500 + 500 * current_pos / file_size;
+        if progress < 1000 {
+            context.emit_event(EventType::ImexProgress(progress as usize));
+        }
+
+        let path = f.path()?.into_owned();
+        let name = path.to_string_lossy().into_owned();
+        if name == MANIFEST_BACKUP_NAME {
+            continue;
+        }
+
+        // async_tar can't unpack to a specified file name, so we unpack to the blobdir and
+        // then move the unpacked file.
+        f.unpack_in(blobdir).await?;
+        if name == DBFILE_BACKUP_NAME_V2 {
+            fs::rename(blobdir.join(DBFILE_BACKUP_NAME_V2), context.get_dbfile()).await?;
+        } else {
+            let from_path = blobdir.join(&name);
+            if from_path.is_file().await {
+                if let Some(file_name) = from_path.file_name() {
+                    fs::rename(&from_path, blobdir.join(file_name)).await?;
+                } else {
+                    warn!(context, "No file name");
+                }
+            }
+        }
+    }
+    fs::remove_dir(blobdir.join(BLOBS_BACKUP_NAME_V2))
+        .await
+        .ok();
+
     context
         .sql
         .open(context, &context.get_dbfile(), false)
         .await
         .context("Could not re-open db")?;
 
-    delete_and_reset_all_device_msgs(context).await?;
+    delete_all_device_msgs(context).await?;
 
-    let total_files_cnt = context
-        .sql
-        .query_get_value::<isize>(context, "SELECT COUNT(*) FROM backup_blobs;", paramsv![])
-        .await
-        .unwrap_or_default() as usize;
-    info!(
-        context,
-        "***IMPORT-in-progress: total_files_cnt={:?}", total_files_cnt,
+    Ok(())
+}
+
+/// Applies an incremental backup to the account.
+///
+/// The account must have been imported from the backup the incremental backup is based
+/// on, with all previous incremental backups applied.  The database is changed in a
+/// single transaction, so it is not modified if applying the backup fails.
+async fn import_backup_incremental(context: &Context, backup_to_import: &Path) -> Result<()> {
+    ensure!(
+        !context.scheduler.read().await.is_running(),
+        "cannot import backup, IO already running"
     );
 
-    // Load IDs only for now, without the file contents, to avoid
-    // consuming too much memory.
-    let file_ids = context
-        .sql
-        .query_map(
-            "SELECT id FROM backup_blobs ORDER BY id",
-            paramsv![],
-            |row| row.get(0),
-            |ids| {
-                ids.collect::<std::result::Result<Vec<i64>, _>>()
-                    .map_err(Into::into)
-            },
-        )
-        .await?;
+    let manifest = verify_backup_v2(Some(context), backup_to_import, |permille| {
+        let progress = 10 + 490 * permille / 1000;
+        if progress > 10 {
+            context.emit_event(EventType::ImexProgress(progress as usize));
+        }
+    })
+    .await?;
+    let base = manifest.base.context("Not an incremental backup")?;
+    let own = BackupMarker::load(context).await;
+    ensure!(
+        own.id.as_deref() == Some(base.as_str()),
+        "Incremental backup applies to backup {}, but the last backup of this account is {}",
+        base,
+        own.id.as_deref().unwrap_or("unknown")
+    );
+
+    // Next to the database, the system's temporary directory is not available everywhere.
+    let dbfile = context
+        .get_dbfile()
+        .with_file_name(format!("dc-incremental-{}.sqlite", uuid::Uuid::new_v4()));
+    let _d = DeleteOnDrop(dbfile.clone());
+    extract_backup_dbfile(backup_to_import, &dbfile).await?;
+    let info = read_backup_info(&std::path::PathBuf::from(dbfile.clone()))?;
+    ensure!(
+        info.dbversion == DBVERSION,
+        "Incremental backup has database version {}, expected {}",
+        info.dbversion,
+        DBVERSION
+    );
+
+    // New blobs do not harm if applying the database fails afterwards,
+    // they are removed by the housekeeping.
+    let blobdir = context.get_blobdir();
+    let backup_file = File::open(backup_to_import).await?;
+    let file_size = backup_file.metadata().await?.len().max(1);
+    let archive = Archive::new(backup_file);
+    let mut entries = archive.entries()?;
+    while let Some(file) = entries.next().await {
+        let f = &mut file?;
+
+        let progress = 500 + 500 * f.raw_file_position() / file_size;
+        if progress < 1000 {
+            context.emit_event(EventType::ImexProgress(progress as usize));
+        }
+
+        let name = f.path()?.to_string_lossy().into_owned();
+        if !name.starts_with(&format!("{}/", BLOBS_BACKUP_NAME_V2)) {
+            continue;
+        }
+        f.unpack_in(blobdir).await?;
+        let from_path = blobdir.join(&name);
+        if let Some(file_name) = from_path.file_name() {
+            fs::rename(&from_path, blobdir.join(file_name)).await?;
+        }
+    }
+    fs::remove_dir(blobdir.join(BLOBS_BACKUP_NAME_V2))
+        .await
+        .ok();
+
+    let mut incremental = context.sql.attach(&dbfile, "incremental").await?;
+    incremental.transaction(|tx| Ok(apply_incremental(tx)?))?;
+    incremental.detach()?;
+
+    context.emit_event(EventType::ContactsChanged(None));
+    context.emit_event(EventType::MsgsChanged {
+        chat_id: chat::ChatId::new(0),
+        msg_id: MsgId::new(0),
+    });
+    Ok(())
+}
+
+/// Applies the incremental backup attached as `incremental` to the database.
+fn apply_incremental(tx: &rusqlite::Connection) -> rusqlite::Result<()> {
+    for table in INCREMENTAL_TABLES.iter() {
+        // Deletions first, the id of a deleted row may be used by a new row.
+        tx.execute(
+            &format!(
+                "DELETE FROM main.{} WHERE id IN
+                 (SELECT row_id FROM incremental.backup_tombstones WHERE tbl=?);",
+                table
+            ),
+            params![table],
+        )?;
+        copy_incremental_rows(tx, table, false)?;
+    }
+    // Backups of older versions do not contain the folded text of the messages.
+    tx.execute(
+        "UPDATE main.msgs SET txt_folded=search_fold(txt) WHERE id IN (SELECT id FROM incremental.msgs);",
+        params![],
+    )?;
+    tx.execute(
+        "DELETE FROM main.chats_contacts
+         WHERE chat_id IN (SELECT id FROM incremental.chats)
+         OR chat_id IN (SELECT row_id FROM incremental.backup_tombstones WHERE tbl='chats');",
+        params![],
+    )?;
+    tx.execute(
+        "INSERT INTO main.chats_contacts (chat_id, contact_id)
+         SELECT chat_id, contact_id FROM incremental.chats_contacts;",
+        params![],
+    )?;
+    for table in INCREMENTAL_FULL_TABLES.iter() {
+        copy_incremental_rows(tx, table, true)?;
+    }
+    Ok(())
+}
+
+/// Copies the rows of `table` from the attached incremental backup, matching the columns
+/// by name.  If `replace_all` is set, all existing rows are removed first, otherwise rows
+/// with the same primary key are replaced.
+fn copy_incremental_rows(
+    conn: &rusqlite::Connection,
+    table: &str,
+    replace_all: bool,
+) -> rusqlite::Result<()> {
+    let columns = conn
+        .prepare(&format!("PRAGMA incremental.table_info({});", table))?
+        .query_map(params![], |row| row.get::<_, String>(1))?
+        .collect::<rusqlite::Result<Vec<_>>>()?
+        .join(",");
+    if columns.is_empty() {
+        // The table does not exist in the backup.
+        return Ok(());
+    }
+    if replace_all {
+        conn.execute(&format!("DELETE FROM main.{};", table), params![])?;
+    }
+    conn.execute(
+        &format!(
+            "INSERT OR REPLACE INTO main.{table} ({columns}) SELECT {columns} FROM incremental.{table};",
+            table = table,
+            columns = columns
+        ),
+        params![],
+    )?;
+    Ok(())
+}
+
+async fn import_backup_old(context: &Context, backup_to_import: impl AsRef<Path>) -> Result<()> {
+    info!(
+        context,
+        "Import \"{}\" to \"{}\".",
+        backup_to_import.as_ref().display(),
+        context.get_dbfile().display()
+    );
+
+    ensure!(
+        !context.is_configured().await,
+        "Cannot import backups to accounts in use."
+    );
+    ensure!(
+        !context.scheduler.read().await.is_running(),
+        "cannot import backup, IO already running"
+    );
+    context.sql.close().await;
+    dc_delete_file(context, context.get_dbfile()).await;
+    ensure!(
+        !context.get_dbfile().exists().await,
+        "Cannot delete old database."
+    );
+
+    ensure!(
+        dc_copy_file(context, backup_to_import.as_ref(), context.get_dbfile()).await,
+        "could not copy file"
+    );
+    /* error already logged */
+    /* re-open copied database file */
+    context
+        .sql
+        .open(context, &context.get_dbfile(), false)
+        .await
+        .context("Could not re-open db")?;
+
+    delete_all_device_msgs(context).await?;
+
+    let total_files_cnt = context
+        .sql
+        .query_get_value::<isize>(context, "SELECT COUNT(*) FROM backup_blobs;", paramsv![])
+        .await
+        .unwrap_or_default() as usize;
+    info!(
+        context,
+        "***IMPORT-in-progress: total_files_cnt={:?}", total_files_cnt,
+    );
+
+    // Load IDs only for now, without the file contents, to avoid
+    // consuming too much memory.
+    let file_ids = context
+        .sql
+        .query_map(
+            "SELECT id FROM backup_blobs ORDER BY id",
+            paramsv![],
+            |row| row.get(0),
+            |ids| {
+                ids.collect::<std::result::Result<Vec<i64>, _>>()
+                    .map_err(Into::into)
+            },
+        )
+        .await?;
 
     let mut all_files_extracted = true;
     for (processed_files_cnt, file_id) in file_ids.into_iter().enumerate() {
@@ -629,7 +1399,7 @@ async fn import_backup_old(context: &Context, backup_to_import: impl AsRef<Path>
             )
             .await?;
 
-        if context.shall_stop_ongoing().await {
+        if context.is_operation_canceled(OperationKind::Imex) {
             all_files_extracted = false;
             break;
         }
@@ -666,59 +1436,244 @@ async fn import_backup_old(context: &Context, backup_to_import: impl AsRef<Path>
  * Export backup
  ******************************************************************************/
 #[allow(unused)]
-async fn export_backup(context: &Context, dir: impl AsRef<Path>) -> Result<()> {
+async fn export_backup(
+    context: &Context,
+    dir: impl AsRef<Path>,
+    options: BackupOptions,
+) -> Result<()> {
+    // Messages expiring during the export are deleted once it is done.
+    let _deletion_hold = ephemeral::hold_deletions(context);
+
     // get a fine backup file name (the name includes the date so that multiple backup instances are possible)
     let now = time();
-    let (temp_path, dest_path) = get_next_backup_path(dir, now).await?;
+    let (temp_path, dest_path) = get_next_backup_path(dir, now, "").await?;
     let _d = DeleteOnDrop(temp_path.clone());
 
-    context
-        .sql
-        .set_raw_config_int(context, "backup_time", now as i32)
-        .await?;
-    sql::housekeeping(context).await.ok_or_log(context);
+    // The new marker is part of the exported database,
+    // so incremental backups can be applied to imports of this backup.
+    let previous = BackupMarker::load(context).await;
+    let marker = BackupMarker {
+        id: Some(dc_create_id()),
+        time: now,
+    };
+    marker.store(context).await?;
+    if let Some(report) = sql::housekeeping(context).await.ok_or_log(context) {
+        report.warnings.emit(context);
+    }
 
-    context
-        .sql
-        .execute("VACUUM;", paramsv![])
-        .await
-        .map_err(|e| warn!(context, "Vacuum failed, exporting anyway {}", e));
+    let res = if options.snapshot {
+        // The snapshot is written next to the backup, the blobdir is exported as well.
+        let snapshot_path = PathBuf::from(format!("{}.sqlite", temp_path.display()));
+        let _s = DeleteOnDrop(snapshot_path.clone());
+        context.sql.backup_to(&snapshot_path).await?;
 
-    ensure!(
-        !context.scheduler.read().await.is_running(),
-        "cannot export backup, IO already running"
-    );
+        info!(
+            context,
+            "Backup snapshot of '{}' to '{}'.",
+            context.get_dbfile().display(),
+            dest_path.display(),
+        );
+        export_backup_file(context, options.format, &snapshot_path, &temp_path, &marker).await
+    } else {
+        context
+            .sql
+            .execute("VACUUM;", paramsv![])
+            .await
+            .map_err(|e| warn!(context, "Vacuum failed, exporting anyway {}", e));
 
-    // we close the database during the export
-    context.sql.close().await;
+        ensure!(
+            !context.scheduler.read().await.is_running(),
+            "cannot export backup, IO already running"
+        );
 
-    info!(
-        context,
-        "Backup '{}' to '{}'.",
-        context.get_dbfile().display(),
-        dest_path.display(),
-    );
+        // we close the database during the export
+        context.sql.close().await;
 
-    let res = export_backup_inner(context, &temp_path).await;
+        info!(
+            context,
+            "Backup '{}' to '{}'.",
+            context.get_dbfile().display(),
+            dest_path.display(),
+        );
 
-    // we re-open the database after export is finished
-    context
-        .sql
-        .open(context, &context.get_dbfile(), false)
+        let res = export_backup_file(
+            context,
+            options.format,
+            context.get_dbfile(),
+            &temp_path,
+            &marker,
+        )
         .await;
 
+        // we re-open the database after export is finished
+        context
+            .sql
+            .open(context, &context.get_dbfile(), false)
+            .await;
+        res
+    };
+
     match &res {
         Ok(_) => {
             fs::rename(temp_path, &dest_path).await?;
             context.emit_event(EventType::ImexFileWritten(dest_path));
+            prune_backup_tombstones(context, marker.time).await?;
         }
         Err(e) => {
             error!(context, "backup failed: {}", e);
+            previous.store(context).await.ok_or_log(context);
         }
     }
 
     res
 }
+
+/// Exports an incremental backup.
+///
+/// A snapshot of the database is reduced to the rows changed since the last backup,
+/// see [INCREMENTAL_TABLES], and written together with the new blobs as a v2 backup.
+async fn export_backup_incremental(context: &Context, dir: impl AsRef<Path>) -> Result<()> {
+    let _deletion_hold = ephemeral::hold_deletions(context);
+
+    let base = BackupMarker::load(context).await;
+    let base_id = base
+        .id
+        .clone()
+        .context("No previous backup, an incremental backup needs a full backup first")?;
+
+    let now = time();
+    let (temp_path, dest_path) = get_next_backup_path(dir, now, "-incremental").await?;
+    let _d = DeleteOnDrop(temp_path.clone());
+    let marker = BackupMarker {
+        id: Some(dc_create_id()),
+        time: now,
+    };
+    marker.store(context).await?;
+
+    let snapshot_path = PathBuf::from(format!("{}.sqlite", temp_path.display()));
+    let _s = DeleteOnDrop(snapshot_path.clone());
+    info!(
+        context,
+        "Incremental backup of '{}' since {} to '{}'.",
+        context.get_dbfile().display(),
+        base.time,
+        dest_path.display(),
+    );
+    let res: Result<()> = async {
+        context.sql.backup_to(&snapshot_path).await?;
+        let path: std::path::PathBuf = snapshot_path.clone().into();
+        let since = base.time;
+        async_std::task::spawn_blocking(move || reduce_to_changes(&path, since)).await?;
+        export_backup_v2_inner(context, &snapshot_path, &temp_path, &marker, Some(&base)).await
+    }
+    .await;
+
+    match &res {
+        Ok(_) => {
+            fs::rename(temp_path, &dest_path).await?;
+            context.emit_event(EventType::ImexFileWritten(dest_path));
+
+            prune_backup_tombstones(context, now).await?;
+        }
+        Err(e) => {
+            error!(context, "incremental backup of {} failed: {}", base_id, e);
+            base.store(context).await.ok_or_log(context);
+        }
+    }
+    res
+}
+
+/// Removes the tombstones of rows deleted before the backup at `backup_time`,
+/// later incremental backups do not need them.
+async fn prune_backup_tombstones(context: &Context, backup_time: i64) -> Result<()> {
+    context
+        .sql
+        .execute(
+            "DELETE FROM backup_tombstones WHERE timestamp<?;",
+            paramsv![backup_time],
+        )
+        .await?;
+    Ok(())
+}
+
+/// Removes everything from the database snapshot `dbfile`
+/// that is not part of an incremental backup since `since`.
+fn reduce_to_changes(dbfile: &std::path::Path, since: i64) -> Result<()> {
+    let conn = rusqlite::Connection::open(dbfile)?;
+
+    // The triggers would record the deletions below as tombstones.
+    let triggers = conn
+        .prepare("SELECT name FROM sqlite_master WHERE type='trigger';")?
+        .query_map(params![], |row| row.get::<_, String>(0))?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    for trigger in triggers {
+        conn.execute(&format!("DROP TRIGGER \"{}\";", trigger), params![])?;
+    }
+
+    let tables = conn
+        .prepare("SELECT name FROM sqlite_master WHERE type='table' AND name NOT LIKE 'sqlite_%';")?
+        .query_map(params![], |row| row.get::<_, String>(0))?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    for table in tables {
+        if INCREMENTAL_TABLES.contains(&table.as_str()) {
+            conn.execute(
+                &format!("DELETE FROM \"{}\" WHERE update_timestamp<?;", table),
+                params![since],
+            )?;
+        } else if table == "backup_tombstones" {
+            conn.execute(
+                "DELETE FROM backup_tombstones WHERE timestamp<?;",
+                params![since],
+            )?;
+        } else if table == "chats_contacts" {
+            conn.execute(
+                "DELETE FROM chats_contacts WHERE chat_id NOT IN (SELECT id FROM chats);",
+                params![],
+            )?;
+        } else if !INCREMENTAL_FULL_TABLES.contains(&table.as_str()) {
+            conn.execute(&format!("DELETE FROM \"{}\";", table), params![])?;
+        }
+    }
+    conn.execute("VACUUM;", params![])?;
+    Ok(())
+}
+
+/// Id and time of the last backup of an account.
+///
+/// Both are stored in the raw config before the database is exported, so a backup
+/// contains its own marker.  Incremental backups contain the changes since the time of
+/// the last backup and can only be applied to an account with the same id.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct BackupMarker {
+    id: Option<String>,
+    time: i64,
+}
+
+impl BackupMarker {
+    async fn load(context: &Context) -> Self {
+        Self {
+            id: context.sql.get_raw_config(context, BACKUP_ID_KEY).await,
+            time: context
+                .sql
+                .get_raw_config_int64(context, BACKUP_TIME_KEY)
+                .await
+                .unwrap_or_default(),
+        }
+    }
+
+    async fn store(&self, context: &Context) -> Result<()> {
+        context
+            .sql
+            .set_raw_config(context, BACKUP_ID_KEY, self.id.as_deref())
+            .await?;
+        context
+            .sql
+            .set_raw_config_int64(context, BACKUP_TIME_KEY, self.time)
+            .await?;
+        Ok(())
+    }
+}
+
 struct DeleteOnDrop(PathBuf);
 impl Drop for DeleteOnDrop {
     fn drop(&mut self) {
@@ -728,14 +1683,47 @@ fn drop(&mut self) {
     }
 }
 
-async fn export_backup_inner(context: &Context, temp_path: &PathBuf) -> Result<()> {
+/// Writes the backup of the database `dbfile` and the blobdir to `temp_path`.
+async fn export_backup_file(
+    context: &Context,
+    format: BackupFormat,
+    dbfile: &Path,
+    temp_path: &Path,
+    marker: &BackupMarker,
+) -> Result<()> {
+    match format {
+        BackupFormat::V1 => export_backup_inner(context, dbfile, temp_path).await,
+        BackupFormat::V2 => export_backup_v2_inner(context, dbfile, temp_path, marker, None).await,
+    }
+}
+
+/// Opens a file of the blobdir for the export.
+///
+/// Returns `None` if the file was deleted after the blobdir was listed, e.g. by an
+/// ephemeral message expiring while IO keeps running.
+pub(crate) async fn open_blob_for_export(context: &Context, path: &Path) -> Result<Option<File>> {
+    match File::open(path).await {
+        Ok(file) => Ok(Some(file)),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+            warn!(
+                context,
+                "Export: {} does not exist (anymore), skipping",
+                path.display()
+            );
+            Ok(None)
+        }
+        Err(err) => Err(err.into()),
+    }
+}
+
+async fn export_backup_inner(context: &Context, dbfile: &Path, temp_path: &Path) -> Result<()> {
     let file = File::create(temp_path).await?;
 
     let mut builder = async_tar::Builder::new(file);
 
     // append_path_with_name() wants the source path as the first argument, append_dir_all() wants it as the second argument.
     builder
-        .append_path_with_name(context.get_dbfile(), DBFILE_BACKUP_NAME)
+        .append_path_with_name(dbfile, DBFILE_BACKUP_NAME)
         .await?;
 
     let read_dir: Vec<_> = fs::read_dir(context.get_blobdir()).await?.collect().await;
@@ -743,6 +1731,9 @@ async fn export_backup_inner(context: &Context, temp_path: &PathBuf) -> Result<(
     let mut written_files = 0;
 
     for entry in read_dir.into_iter() {
+        if context.is_operation_canceled(OperationKind::Imex) {
+            bail!("canceled");
+        }
         let entry = entry?;
         let name = entry.file_name();
         if !entry.file_type().await?.is_file() {
@@ -753,7 +1744,10 @@ async fn export_backup_inner(context: &Context, temp_path: &PathBuf) -> Result<(
             );
             continue;
         }
-        let mut file = File::open(entry.path()).await?;
+        let mut file = match open_blob_for_export(context, &entry.path()).await? {
+            Some(file) => file,
+            None => continue,
+        };
         let path_in_archive = PathBuf::from(BLOBS_BACKUP_NAME).join(name);
         builder.append_file(path_in_archive, &mut file).await?;
 
@@ -769,6 +1763,104 @@ async fn export_backup_inner(context: &Context, temp_path: &PathBuf) -> Result<(
     Ok(())
 }
 
+/// Writes a v2 backup.
+///
+/// If `base` is set, an incremental backup with only the blobs modified since the base
+/// backup is written.
+async fn export_backup_v2_inner(
+    context: &Context,
+    dbfile: &Path,
+    temp_path: &Path,
+    marker: &BackupMarker,
+    base: Option<&BackupMarker>,
+) -> Result<()> {
+    let dbfile_name = if base.is_some() {
+        DBFILE_INCREMENTAL_NAME
+    } else {
+        DBFILE_BACKUP_NAME_V2
+    };
+    let mut total_size = fs::metadata(dbfile).await?.len();
+    let mut files = vec![(dbfile.to_path_buf(), dbfile_name.to_string())];
+    let mut read_dir = fs::read_dir(context.get_blobdir()).await?;
+    while let Some(entry) = read_dir.next().await {
+        let entry = entry?;
+        let name = entry.file_name();
+        let metadata = match entry.metadata().await {
+            Ok(metadata) => metadata,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(err) => return Err(err.into()),
+        };
+        if !metadata.is_file() {
+            warn!(
+                context,
+                "Export: Found dir entry {} that is not a file, ignoring",
+                name.to_string_lossy()
+            );
+            continue;
+        }
+        if let Some(base) = base {
+            let modified = metadata
+                .modified()?
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs() as i64;
+            if modified < base.time {
+                continue;
+            }
+        }
+        total_size += metadata.len();
+        let path_in_archive = format!("{}/{}", BLOBS_BACKUP_NAME_V2, name.to_string_lossy());
+        files.push((entry.path(), path_in_archive));
+    }
+    let total_size = total_size.max(1);
+
+    let mut builder = async_tar::Builder::new(File::create(temp_path).await?);
+    let mut manifest = BackupManifest {
+        version: BACKUP_MANIFEST_VERSION,
+        id: marker.id.clone().unwrap_or_default(),
+        base: base.and_then(|base| base.id.clone()),
+        files: BTreeMap::new(),
+    };
+    let mut written_size = 0;
+    for (path, path_in_archive) in files {
+        if context.is_operation_canceled(OperationKind::Imex) {
+            bail!("canceled");
+        }
+        let mut file = match open_blob_for_export(context, &path).await? {
+            Some(file) => file,
+            None => continue,
+        };
+        let entry = ManifestEntry::from_reader(&mut file).await?;
+        file.seek(std::io::SeekFrom::Start(0)).await?;
+        builder
+            .append_file(Path::new(&path_in_archive), &mut file)
+            .await?;
+
+        written_size += entry.size;
+        manifest.files.insert(path_in_archive, entry);
+
+        let progress = 1000 * written_size / total_size;
+        if progress > 10 && progress < 1000 {
+            // We already emitted ImexProgress(10) above
+            emit_event!(context, EventType::ImexProgress(progress as usize));
+        }
+    }
+
+    // The manifest is written last, after the checksums of all files are known.
+    let manifest = serde_json::to_vec(&manifest)?;
+    let mut header = async_tar::Header::new_gnu();
+    header.set_size(manifest.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime(time() as u64);
+    header.set_cksum();
+    builder
+        .append_data(&mut header, MANIFEST_BACKUP_NAME, manifest.as_slice())
+        .await?;
+
+    builder.finish().await?;
+    Ok(())
+}
+
 /*******************************************************************************
  * Classic key import
  ******************************************************************************/
@@ -883,9 +1975,110 @@ async fn export_self_keys(context: &Context, dir: impl AsRef<Path>) -> Result<()
     Ok(())
 }
 
-/*******************************************************************************
- * Classic key export
- ******************************************************************************/
+/// Exports the own private key with the given fingerprint to the file `path`.
+pub async fn export_key(
+    context: &Context,
+    fingerprint: &Fingerprint,
+    path: impl AsRef<Path>,
+) -> Result<()> {
+    let path = path.as_ref();
+    let (_, key) = key::load_self_keypairs(context)
+        .await?
+        .into_iter()
+        .find(|(info, _)| &info.fingerprint == fingerprint)
+        .with_context(|| format!("No own key with fingerprint {}", fingerprint.hex()))?;
+
+    dc_write_file(context, path, key.to_asc(None).as_bytes()).await?;
+    context.emit_event(EventType::ImexFileWritten(path.to_path_buf()));
+    Ok(())
+}
+
+/// What [`import_key`] changed or, for dry runs, would change.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyImportReport {
+    pub fingerprint: Fingerprint,
+    /// Addresses found in the user IDs of the key.
+    pub key_addrs: Vec<String>,
+    /// Whether the key was stored already.
+    pub already_present: bool,
+    /// Whether the key is the default key after the import.
+    pub is_default: bool,
+    /// The previous default key, if it is replaced by the imported key.
+    pub replaced_default: Option<Fingerprint>,
+    /// Problems which do not prevent the import.
+    pub warnings: Vec<String>,
+}
+
+/// Imports the armored private key in the file `path`.
+///
+/// The key only becomes the default key if `set_default` is true or if it already was the
+/// default key.  With `dry_run` nothing is stored, the report tells what would change.
+pub async fn import_key(
+    context: &Context,
+    path: impl AsRef<Path>,
+    set_default: bool,
+    dry_run: bool,
+) -> Result<KeyImportReport> {
+    let path = path.as_ref();
+    let buf = dc_read_file(context, path).await?;
+    let (private_key, _header) = SignedSecretKey::from_asc(&String::from_utf8_lossy(&buf))
+        .with_context(|| format!("{} does not contain an armored private key", path.display()))?;
+    let public_key = private_key.split_public_key()?;
+    let fingerprint = DcKey::fingerprint(&public_key);
+
+    let self_addr = context
+        .get_config(Config::ConfiguredAddr)
+        .await
+        .context("Missing self addr")?;
+    let key_addrs = key_addrs(&private_key);
+    let mut warnings = Vec::new();
+    if !key_addrs.iter().any(|addr| addr_cmp(addr, &self_addr)) {
+        let warning = format!(
+            "Key {} is for {}, not for the configured address {}",
+            fingerprint.hex(),
+            key_addrs.join(", "),
+            self_addr
+        );
+        warn!(context, "{}", warning);
+        warnings.push(warning);
+    }
+
+    let keys = key::list_keypairs(context).await?;
+    let existing = keys.iter().find(|info| info.fingerprint == fingerprint);
+    let current_default = keys.iter().find(|info| info.is_default);
+    let is_default = set_default || existing.map_or(false, |info| info.is_default);
+    let replaced_default = current_default
+        .filter(|info| set_default && info.fingerprint != fingerprint)
+        .map(|info| info.fingerprint.clone());
+    let report = KeyImportReport {
+        fingerprint,
+        key_addrs,
+        already_present: existing.is_some(),
+        is_default,
+        replaced_default,
+        warnings,
+    };
+
+    if !dry_run {
+        let keypair = pgp::KeyPair {
+            addr: EmailAddress::new(&self_addr)?,
+            public: public_key,
+            secret: private_key,
+        };
+        let key_use = if is_default {
+            key::KeyPairUse::Default
+        } else {
+            key::KeyPairUse::ReadOnly
+        };
+        key::store_self_keypair(context, &keypair, key_use).await?;
+        info!(context, "stored self key: {:?}", keypair.secret.key_id());
+    }
+    Ok(report)
+}
+
+/*******************************************************************************
+ * Classic key export
+ ******************************************************************************/
 async fn export_key_to_asc_file<T>(
     context: &Context,
     dir: impl AsRef<Path>,
@@ -925,16 +2118,1048 @@ async fn export_key_to_asc_file<T>(
     res
 }
 
+/// Numbers of messages processed by [`import_mbox`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ImportStats {
+    /// Messages passed to the receive pipeline.
+    pub imported: usize,
+
+    /// Messages skipped because a message with the same Message-ID exists already
+    /// or because they are Secure-Join handshake messages.
+    pub skipped: usize,
+
+    /// Messages that could not be parsed or received.
+    pub failed: usize,
+}
+
+/// Imports a single message from an .eml file.
+///
+/// The message is assigned to a chat as if it was fetched from the server,
+/// but it is marked as seen, so it neither shows up as fresh nor triggers a read receipt.
+/// Old messages do not change the state of contacts, groups, settings or encryption,
+/// only key gossip is processed.
+///
+/// Returns `false` if the message was skipped, see [`ImportStats::skipped`].
+pub async fn import_eml(context: &Context, path: impl AsRef<Path>) -> Result<bool> {
+    let raw = dc_read_file(context, path).await?;
+    import_msg(context, &raw).await
+}
+
+/// Imports all messages from an mbox file, as exported eg. by Thunderbird.
+///
+/// The file is read message by message, each message is imported as by [`import_eml`].
+/// While importing, #DC_EVENT_IMPORT_MSGS_PROGRESS reports the number of processed messages
+/// and the number of messages in the file, estimated from the number of bytes read.
+pub async fn import_mbox(context: &Context, path: impl AsRef<Path>) -> Result<ImportStats> {
+    let path = dc_get_abs_path(context, path);
+    let file = File::open(&path)
+        .await
+        .with_context(|| format!("cannot open {}", path.display()))?;
+    let size = file.metadata().await?.len() as usize;
+    let mut reader = async_std::io::BufReader::new(file);
+
+    let mut stats = ImportStats::default();
+    let mut bytes_read = 0;
+    let mut last_permille = 0;
+    let mut raw = Vec::new();
+    let mut line = Vec::new();
+    let mut after_blank_line = true;
+    loop {
+        line.clear();
+        let n = reader.read_until(b'\n', &mut line).await?;
+        if bytes_read == 0 {
+            ensure!(
+                line.starts_with(b"From "),
+                "{} is no mbox file",
+                path.display()
+            );
+        }
+        bytes_read += n;
+
+        if n > 0 && !(after_blank_line && line.starts_with(b"From ")) {
+            raw.extend_from_slice(unescape_mbox_line(&line));
+            after_blank_line = line == b"\n" || line == b"\r\n";
+            continue;
+        }
+
+        if !raw.is_empty() {
+            match import_msg(context, &raw).await {
+                Ok(true) => stats.imported += 1,
+                Ok(false) => stats.skipped += 1,
+                Err(err) => {
+                    warn!(context, "Cannot import message: {:#}", err);
+                    stats.failed += 1;
+                }
+            }
+            raw.clear();
+
+            let permille = bytes_read * 1000 / size.max(1);
+            if permille > last_permille {
+                last_permille = permille;
+                let processed = stats.imported + stats.skipped + stats.failed;
+                context.emit_event(EventType::ImportMsgsProgress {
+                    processed,
+                    total: processed * size / bytes_read.max(1),
+                });
+            }
+        }
+        if n == 0 {
+            break;
+        }
+        after_blank_line = false;
+    }
+
+    let processed = stats.imported + stats.skipped + stats.failed;
+    context.emit_event(EventType::ImportMsgsProgress {
+        processed,
+        total: processed,
+    });
+    info!(
+        context,
+        "Imported messages from {}: {:?}",
+        path.display(),
+        stats
+    );
+    Ok(stats)
+}
+
+/// Removes the quoting of "From " lines in the body of mbox messages.
+fn unescape_mbox_line(line: &[u8]) -> &[u8] {
+    let quotes = line.iter().take_while(|&&c| c == b'>').count();
+    match line.get(quotes..) {
+        Some(rest) if quotes > 0 && rest.starts_with(b"From ") => line.get(1..).unwrap_or_default(),
+        _ => line,
+    }
+}
+
+/// Passes a single message to the receive pipeline.
+///
+/// Returns `false` if the message was skipped, see [`ImportStats::skipped`].
+async fn import_msg(context: &Context, raw: &[u8]) -> Result<bool> {
+    let (headers, _) = mailparse::parse_headers(raw)?;
+    ensure!(
+        headers.get_header(HeaderDef::From_).is_some(),
+        "message has no From: header"
+    );
+
+    if let Some(rfc724_mid) = headers
+        .get_header_value(HeaderDef::MessageId)
+        .and_then(|mid| parse_message_id(&mid).ok())
+    {
+        if rfc724_mid_exists(context, &rfc724_mid).await?.is_some() {
+            info!(
+                context,
+                "Not importing {}, message exists already", rfc724_mid
+            );
+            return Ok(false);
+        }
+    }
+
+    // Old handshakes must not be continued, this would send messages.
+    if headers.get_header(HeaderDef::SecureJoin).is_some() {
+        info!(context, "Not importing Secure-Join message");
+        return Ok(false);
+    }
+
+    // Messages without server folder are not touched on the server.
+    dc_receive_imf_inner(context, raw, "", 0, true, None, false, true).await?;
+    Ok(true)
+}
+
+/// Order of day, month and year in the dates of a transcript, see [`TranscriptFormat`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DateOrder {
+    /// Eg. `31/12/2020` or `31.12.20`.
+    DayMonthYear,
+
+    /// Eg. `12/31/20`.
+    MonthDayYear,
+
+    /// Eg. `2020-12-31`.
+    YearMonthDay,
+}
+
+/// Format of a chat transcript imported by [`import_transcript`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TranscriptFormat {
+    /// Text file written by the "Export chat" function of WhatsApp without media,
+    /// eg. `31/12/2020, 23:59 - Alice: Happy new year!`.
+    ///
+    /// Dates are written in the locale and the local time of the exporting device
+    /// without saying which one, so the order of day and month
+    /// and the offset of the local time to UTC in seconds have to be given.
+    WhatsApp {
+        date_order: DateOrder,
+        utc_offset: i32,
+    },
+}
+
+/// A message read from a transcript.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct TranscriptEntry {
+    timestamp: i64,
+    sender: String,
+    text: String,
+}
+
+/// Number of messages inserted per transaction by [`import_transcript`].
+const TRANSCRIPT_BATCH_SIZE: usize = 200;
+
+/// Texts WhatsApp writes instead of attachments that were not exported.
+const WHATSAPP_MEDIA_PLACEHOLDERS: [&str; 9] = [
+    "<Media omitted>",
+    "<Medien ausgeschlossen>",
+    "image omitted",
+    "video omitted",
+    "audio omitted",
+    "sticker omitted",
+    "GIF omitted",
+    "document omitted",
+    "Contact card omitted",
+];
+
+/// Matches the first line of a message or a system message in a WhatsApp transcript,
+/// eg. `12/31/20, 11:59 PM - Alice: text` or `[31.12.20, 23:59:10] Alice: text`.
+static WHATSAPP_LINE_RE: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(
+        r"^\[?(\d{1,4})[./-](\d{1,2})[./-](\d{1,4}),?\s(\d{1,2}):(\d{2})(?::(\d{2}))?\s?(?:([AaPp])\.?\s?[Mm]\.?)?(?:\]\s|\s-\s)(.*)$",
+    )
+    .unwrap()
+});
+
+/// Imports the messages of a chat transcript exported by another messenger into `chat_id`.
+///
+/// Senders are mapped to contacts by `senders`, which should map the own name
+/// to [DC_CONTACT_ID_SELF]; senders not found there are added as hidden contacts
+/// with a made-up address. The messages are added as seen with the timestamps
+/// from the transcript. Attachments are not part of the transcript,
+/// their placeholders are skipped as are system messages and messages imported before.
+/// While importing, #DC_EVENT_IMPORT_MSGS_PROGRESS reports the number of processed messages.
+pub async fn import_transcript(
+    context: &Context,
+    chat_id: ChatId,
+    format: TranscriptFormat,
+    path: impl AsRef<Path>,
+    senders: &BTreeMap<String, u32>,
+) -> Result<ImportStats> {
+    ensure!(
+        !chat_id.is_special(),
+        "cannot import transcript into special chat {}",
+        chat_id
+    );
+    Chat::load_from_db(context, chat_id).await?;
+
+    let raw = dc_read_file(context, &path).await?;
+    let text = String::from_utf8_lossy(&raw);
+    let (entries, skipped) = match format {
+        TranscriptFormat::WhatsApp {
+            date_order,
+            utc_offset,
+        } => parse_whatsapp_transcript(&text, date_order, utc_offset)?,
+    };
+
+    let to_id = chat::get_chat_contacts(context, chat_id)
+        .await
+        .into_iter()
+        .find(|contact_id| *contact_id != DC_CONTACT_ID_SELF)
+        .unwrap_or_default();
+    let mut contact_ids: BTreeMap<String, u32> = senders.clone();
+    let mut occurrences: HashMap<String, usize> = HashMap::new();
+    let mut rows = Vec::with_capacity(entries.len());
+    for entry in entries {
+        let from_id = match contact_ids.get(&entry.sender) {
+            Some(contact_id) => *contact_id,
+            None => {
+                let contact_id = add_transcript_contact(context, &entry.sender).await?;
+                contact_ids.insert(entry.sender.clone(), contact_id);
+                contact_id
+            }
+        };
+        let (to_id, state) = if from_id == DC_CONTACT_ID_SELF {
+            (to_id, MessageState::OutDelivered)
+        } else {
+            (DC_CONTACT_ID_SELF, MessageState::InSeen)
+        };
+
+        // Identical messages sent within the same minute are told apart by their position,
+        // so that importing the transcript again skips all of them.
+        let key = format!(
+            "{}\n{}\n{}\n{}",
+            chat_id, entry.sender, entry.timestamp, entry.text
+        );
+        let occurrence = occurrences.entry(key.clone()).or_insert(0);
+        *occurrence += 1;
+        let hash = Sha256::digest(format!("{}\n{}", key, occurrence).as_bytes());
+        let rfc724_mid = format!(
+            "transcript.{}@localhost",
+            hex::encode(hash.get(..16).unwrap_or_default())
+        );
+
+        rows.push((
+            from_id,
+            to_id,
+            entry.timestamp,
+            state,
+            entry.text,
+            rfc724_mid,
+        ));
+    }
+
+    let total = rows.len();
+    let mut stats = ImportStats {
+        skipped,
+        ..Default::default()
+    };
+    for batch in rows.chunks(TRANSCRIPT_BATCH_SIZE) {
+        let batch = batch.to_vec();
+        let (imported, duplicates) = context
+            .sql
+            .with_conn(move |mut conn| {
+                let tx = conn.transaction()?;
+                let mut imported = 0;
+                let mut duplicates = 0;
+                for (from_id, to_id, timestamp, state, text, rfc724_mid) in batch {
+                    let exists: bool = tx.query_row(
+                        "SELECT COUNT(*)>0 FROM msgs WHERE rfc724_mid=?;",
+                        rusqlite::params![rfc724_mid],
+                        |row| row.get(0),
+                    )?;
+                    if exists {
+                        duplicates += 1;
+                        continue;
+                    }
+                    tx.execute(
+                        "INSERT INTO msgs (chat_id, from_id, to_id, timestamp, timestamp_sent, timestamp_rcvd, type, state, txt, txt_folded, rfc724_mid) \
+                         VALUES (?,?,?, ?,?,?, ?,?,?,?,?);",
+                        rusqlite::params![
+                            chat_id,
+                            from_id,
+                            to_id,
+                            timestamp,
+                            timestamp,
+                            timestamp,
+                            Viewtype::Text,
+                            state,
+                            text,
+                            search_fold(&text),
+                            rfc724_mid
+                        ],
+                    )?;
+                    imported += 1;
+                }
+                tx.commit()?;
+                Ok((imported, duplicates))
+            })
+            .await?;
+        stats.imported += imported;
+        stats.skipped += duplicates;
+
+        context.emit_event(EventType::ImportMsgsProgress {
+            processed: stats.imported + stats.skipped - skipped,
+            total,
+        });
+    }
+
+    context.emit_event(EventType::MsgsChanged {
+        chat_id,
+        msg_id: MsgId::new(0),
+    });
+    info!(
+        context,
+        "Imported transcript {} into {}: {:?}",
+        path.as_ref().display(),
+        chat_id,
+        stats
+    );
+    Ok(stats)
+}
+
+/// Returns a hidden contact standing in for a sender of a transcript.
+async fn add_transcript_contact(context: &Context, name: &str) -> Result<u32> {
+    let hash = Sha256::digest(name.as_bytes());
+    let addr = format!(
+        "{}@transcript.invalid",
+        hex::encode(hash.get(..8).unwrap_or_default())
+    );
+    let (contact_id, _) = Contact::add_or_lookup(context, name, addr, Origin::Hidden).await?;
+    Ok(contact_id)
+}
+
+/// Parses a WhatsApp transcript, see [`TranscriptFormat::WhatsApp`].
+///
+/// Returns the messages and the number of skipped system messages and media placeholders.
+fn parse_whatsapp_transcript(
+    text: &str,
+    date_order: DateOrder,
+    utc_offset: i32,
+) -> Result<(Vec<TranscriptEntry>, usize)> {
+    let mut entries: Vec<TranscriptEntry> = Vec::new();
+    let mut skipped = 0;
+
+    // Lines not starting with a date continue the previous message, unless it was skipped.
+    let mut continues_message = false;
+    for line in text.lines() {
+        let line = line.trim_start_matches(|c| c == '\u{feff}' || c == '\u{200e}');
+        let caps = match WHATSAPP_LINE_RE.captures(line) {
+            Some(caps) => caps,
+            None => {
+                if let Some(entry) = entries.last_mut().filter(|_| continues_message) {
+                    entry.text.push('\n');
+                    entry.text += line;
+                }
+                continue;
+            }
+        };
+
+        let timestamp = parse_whatsapp_timestamp(&caps, date_order, utc_offset)
+            .with_context(|| format!("invalid date in line {:?}", line))?;
+        let rest = caps.get(8).map_or("", |m| m.as_str());
+        let mut parts = rest.splitn(2, ": ");
+        match (parts.next(), parts.next()) {
+            (Some(sender), Some(text)) if !is_whatsapp_media_placeholder(text) => {
+                entries.push(TranscriptEntry {
+                    timestamp,
+                    sender: sender.to_string(),
+                    text: text.trim_start_matches('\u{200e}').to_string(),
+                });
+                continues_message = true;
+            }
+            _ => {
+                skipped += 1;
+                continues_message = false;
+            }
+        }
+    }
+    Ok((entries, skipped))
+}
+
+/// Converts the date and time captured by [`WHATSAPP_LINE_RE`] to a timestamp.
+fn parse_whatsapp_timestamp(
+    caps: &regex::Captures,
+    date_order: DateOrder,
+    utc_offset: i32,
+) -> Result<i64> {
+    let num = |i: usize| -> Result<u32> {
+        match caps.get(i) {
+            Some(m) => Ok(m.as_str().parse::<u32>()?),
+            None => Ok(0),
+        }
+    };
+    let (year, month, day) = match date_order {
+        DateOrder::DayMonthYear => (num(3)?, num(2)?, num(1)?),
+        DateOrder::MonthDayYear => (num(3)?, num(1)?, num(2)?),
+        DateOrder::YearMonthDay => (num(1)?, num(2)?, num(3)?),
+    };
+    let year = if year < 100 { year + 2000 } else { year };
+
+    let mut hour = num(4)?;
+    if let Some(am_pm) = caps.get(7) {
+        ensure!((1..=12).contains(&hour), "invalid hour {}", hour);
+        hour %= 12;
+        if am_pm.as_str().eq_ignore_ascii_case("p") {
+            hour += 12;
+        }
+    }
+
+    let datetime = chrono::NaiveDate::from_ymd_opt(year as i32, month, day)
+        .ok_or_else(|| format_err!("no valid date, wrong date order?"))?
+        .and_hms_opt(hour, num(5)?, num(6)?)
+        .ok_or_else(|| format_err!("no valid time"))?;
+    Ok(datetime.timestamp() - i64::from(utc_offset))
+}
+
+fn is_whatsapp_media_placeholder(text: &str) -> bool {
+    let text = text.trim().trim_start_matches('\u{200e}');
+    WHATSAPP_MEDIA_PLACEHOLDERS.contains(&text)
+        || (text.starts_with("<attached: ") && text.ends_with('>'))
+}
+
 #[cfg(test)]
 mod tests {
+    #![allow(clippy::indexing_slicing)]
+
     use super::*;
 
+    use crate::aheader::{Aheader, EncryptPreference};
+    use crate::constants::DC_CONTACT_ID_DEVICE;
+    use crate::dc_receive_imf::dc_receive_imf;
+    use crate::peerstate::Peerstate;
     use crate::pgp::{split_armored_data, HEADER_AUTOCRYPT, HEADER_SETUPCODE};
     use crate::stock_str::StockMessage;
-    use crate::test_utils::{alice_keypair, TestContext};
+    use crate::test_utils::{alice_keypair, bob_keypair, TestContext};
 
     use ::pgp::armor::BlockType;
 
+    /// Writes `count` small files into the blobdir and returns their names and contents.
+    async fn create_blobs(t: &TestContext, count: usize) -> Vec<(String, Vec<u8>)> {
+        let mut blobs = Vec::new();
+        for i in 0..count {
+            let name = format!("blob-{}.txt", i);
+            let content = format!("content of blob {}", i)
+                .repeat(i % 7 + 1)
+                .into_bytes();
+            fs::write(t.get_blobdir().join(&name), &content)
+                .await
+                .unwrap();
+            blobs.push((name, content));
+        }
+        blobs
+    }
+
+    async fn check_backup_roundtrip(format: BackupFormat) {
+        let alice = TestContext::new_alice().await;
+        let blobs = create_blobs(&alice, 300).await;
+        let backup_dir = tempfile::tempdir().unwrap();
+        let backup_dir: PathBuf = backup_dir.path().to_path_buf().into();
+
+        let options = BackupOptions {
+            format,
+            ..Default::default()
+        };
+        imex_with_options(&alice, ImexMode::ExportBackup, &backup_dir, options)
+            .await
+            .unwrap();
+        let backup = has_backup(&alice, &backup_dir).await.unwrap();
+        assert_eq!(
+            detect_backup_format(Path::new(&backup)).await.unwrap(),
+            format
+        );
+
+        let t = TestContext::new().await;
+        imex(&t, ImexMode::ImportBackup, &backup).await.unwrap();
+        assert_eq!(
+            t.get_config(Config::ConfiguredAddr).await,
+            alice.get_config(Config::ConfiguredAddr).await
+        );
+        for (name, content) in blobs {
+            assert_eq!(
+                fs::read(t.get_blobdir().join(&name)).await.unwrap(),
+                content
+            );
+        }
+    }
+
+    #[async_std::test]
+    async fn test_device_msg_labels_survive_backup() {
+        let alice = TestContext::new_alice().await;
+        let mut msg = Message::new(Viewtype::Text);
+        msg.text = Some("welcome".to_string());
+        let msg_id = chat::add_device_msg(&alice, Some("welcome"), Some(&mut msg))
+            .await
+            .unwrap();
+        assert!(!msg_id.is_unset());
+
+        let backup_dir = tempfile::tempdir().unwrap();
+        let backup_dir: PathBuf = backup_dir.path().to_path_buf().into();
+        imex(&alice, ImexMode::ExportBackup, &backup_dir)
+            .await
+            .unwrap();
+        let backup = has_backup(&alice, &backup_dir).await.unwrap();
+
+        let t = TestContext::new().await;
+        imex(&t, ImexMode::ImportBackup, &backup).await.unwrap();
+        assert!(chat::was_device_msg_ever_added(&t, "welcome")
+            .await
+            .unwrap());
+        let msg_id = chat::add_device_msg(&t, Some("welcome"), Some(&mut msg))
+            .await
+            .unwrap();
+        assert!(msg_id.is_unset());
+    }
+
+    #[async_std::test]
+    async fn test_ui_config_survives_backup() {
+        let alice = TestContext::new_alice().await;
+        alice
+            .set_ui_config("desktop.last_chat", Some("12"))
+            .await
+            .unwrap();
+
+        let backup_dir = tempfile::tempdir().unwrap();
+        let backup_dir: PathBuf = backup_dir.path().to_path_buf().into();
+        imex(&alice, ImexMode::ExportBackup, &backup_dir)
+            .await
+            .unwrap();
+        let backup = has_backup(&alice, &backup_dir).await.unwrap();
+
+        let t = TestContext::new().await;
+        imex(&t, ImexMode::ImportBackup, &backup).await.unwrap();
+        assert_eq!(
+            t.get_ui_config("desktop.last_chat").await.unwrap(),
+            Some("12".to_string())
+        );
+    }
+
+    #[async_std::test]
+    async fn test_export_import_backup_v1() {
+        check_backup_roundtrip(BackupFormat::V1).await;
+    }
+
+    #[async_std::test]
+    async fn test_export_import_backup_v2() {
+        check_backup_roundtrip(BackupFormat::V2).await;
+    }
+
+    /// Returns the messages, chats, chat members, contacts and blobs of an account.
+    async fn account_content(t: &TestContext) -> Vec<String> {
+        let mut content = t
+            .sql
+            .query_map(
+                "SELECT 'msg ' || id || ' ' || chat_id || ' ' || txt FROM msgs WHERE from_id!=?
+                 UNION ALL SELECT 'chat ' || id || ' ' || name FROM chats
+                 UNION ALL SELECT 'member ' || chat_id || ' ' || contact_id FROM chats_contacts
+                 UNION ALL SELECT 'contact ' || id || ' ' || name || ' ' || addr FROM contacts;",
+                paramsv![DC_CONTACT_ID_DEVICE],
+                |row| row.get::<_, String>(0),
+                |rows| {
+                    rows.collect::<std::result::Result<Vec<_>, _>>()
+                        .map_err(Into::into)
+                },
+            )
+            .await
+            .unwrap();
+        let mut dir = fs::read_dir(t.get_blobdir()).await.unwrap();
+        while let Some(entry) = dir.next().await {
+            let entry = entry.unwrap();
+            content.push(format!(
+                "blob {} {:?}",
+                entry.file_name().to_string_lossy(),
+                fs::read(entry.path()).await.unwrap()
+            ));
+        }
+        content.sort();
+        content
+    }
+
+    #[async_std::test]
+    async fn test_backup_change_tracking() {
+        let t = TestContext::new_alice().await;
+        let chat_id = t
+            .create_chat_with_contact("bob", "bob@example.net")
+            .await
+            .id;
+        let msg_id = t.send_text(chat_id, "hi").await.sender_msg_id;
+        async fn update_timestamp(t: &TestContext, msg_id: MsgId) -> i64 {
+            t.sql
+                .query_get_value_result(
+                    "SELECT update_timestamp FROM msgs WHERE id=?;",
+                    paramsv![msg_id],
+                )
+                .await
+                .unwrap()
+                .unwrap()
+        }
+        async fn set_text(t: &TestContext, msg_id: MsgId, text: &str) {
+            t.sql
+                .execute("UPDATE msgs SET txt=? WHERE id=?;", paramsv![text, msg_id])
+                .await
+                .unwrap();
+        }
+
+        // Nothing is tracked before the first backup.
+        set_text(&t, msg_id, "changed").await;
+        assert_eq!(update_timestamp(&t, msg_id).await, 0);
+
+        // The first change after a backup marks the row.
+        let backup_time = time() - 100;
+        t.sql
+            .set_raw_config_int64(&t, BACKUP_TIME_KEY, backup_time)
+            .await
+            .unwrap();
+        set_text(&t, msg_id, "changed again").await;
+        assert!(update_timestamp(&t, msg_id).await >= backup_time);
+
+        // Marked rows are not written again.
+        t.sql
+            .execute(
+                "UPDATE msgs SET update_timestamp=? WHERE id=?;",
+                paramsv![backup_time + 1, msg_id],
+            )
+            .await
+            .unwrap();
+        set_text(&t, msg_id, "changed once more").await;
+        assert_eq!(update_timestamp(&t, msg_id).await, backup_time + 1);
+
+        // Full backups prune the tombstones.
+        t.sql
+            .execute(
+                "INSERT INTO backup_tombstones (tbl, row_id, timestamp) VALUES ('msgs', 1, 1);",
+                paramsv![],
+            )
+            .await
+            .unwrap();
+        let backup_dir = tempfile::tempdir().unwrap();
+        imex(&t, ImexMode::ExportBackup, backup_dir.path())
+            .await
+            .unwrap();
+        let tombstones: i32 = t
+            .sql
+            .query_get_value_result(
+                "SELECT COUNT(*) FROM backup_tombstones WHERE timestamp=1;",
+                paramsv![],
+            )
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(tombstones, 0);
+    }
+
+    /// Returns the path of the incremental backup in `dir`.
+    async fn find_incremental_backup(dir: &Path) -> PathBuf {
+        let mut dir = fs::read_dir(dir).await.unwrap();
+        while let Some(entry) = dir.next().await {
+            let entry = entry.unwrap();
+            if entry
+                .file_name()
+                .to_string_lossy()
+                .ends_with("-incremental.tar")
+            {
+                return entry.path();
+            }
+        }
+        panic!("no incremental backup found");
+    }
+
+    #[async_std::test]
+    async fn test_export_import_backup_incremental() {
+        let alice = TestContext::new_alice().await;
+        let bob = alice
+            .create_chat_with_contact("bob", "bob@example.net")
+            .await
+            .id;
+        alice.send_text(bob, "before the base backup").await;
+        alice.send_text(bob, "deleted later").await;
+        let deleted = alice.get_last_msg_in(bob).await.id;
+        create_blobs(&alice, 3).await;
+
+        let backup_dir = tempfile::tempdir().unwrap();
+        let backup_dir: PathBuf = backup_dir.path().to_path_buf().into();
+        imex(&alice, ImexMode::ExportBackup, &backup_dir)
+            .await
+            .unwrap();
+        let base = has_backup(&alice, &backup_dir).await.unwrap();
+
+        alice.send_text(bob, "after the base backup").await;
+        let claire = alice
+            .create_chat_with_contact("claire", "claire@example.org")
+            .await
+            .id;
+        alice.send_text(claire, "hi claire").await;
+        Contact::create(&alice, "Bob Bobsen", "bob@example.net")
+            .await
+            .unwrap();
+        alice
+            .sql
+            .execute("DELETE FROM msgs WHERE id=?;", paramsv![deleted])
+            .await
+            .unwrap();
+        fs::write(alice.get_blobdir().join("new-blob.txt"), b"new")
+            .await
+            .unwrap();
+
+        imex(&alice, ImexMode::ExportBackupIncremental, &backup_dir)
+            .await
+            .unwrap();
+        // Incremental backups are not offered for importing to new accounts.
+        assert_eq!(has_backup(&alice, &backup_dir).await.unwrap(), base);
+        let incremental = find_incremental_backup(&backup_dir).await;
+        assert!(
+            check_backup(&incremental, alice.get_blobdir())
+                .await
+                .unwrap()
+                .msg_count
+                < 4
+        );
+
+        let full_dir = tempfile::tempdir().unwrap();
+        let full_dir: PathBuf = full_dir.path().to_path_buf().into();
+        imex(&alice, ImexMode::ExportBackup, &full_dir)
+            .await
+            .unwrap();
+        let full = has_backup(&alice, &full_dir).await.unwrap();
+
+        let t = TestContext::new().await;
+        imex(&t, ImexMode::ImportBackup, &base).await.unwrap();
+        imex(&t, ImexMode::ImportBackup, &incremental)
+            .await
+            .unwrap();
+        // The extracted database is removed again.
+        let mut files = fs::read_dir(t.get_dbfile().parent().unwrap())
+            .await
+            .unwrap();
+        while let Some(file) = files.next().await {
+            let name = file.unwrap().file_name();
+            assert!(!name.to_string_lossy().starts_with("dc-incremental-"));
+        }
+        let t_full = TestContext::new().await;
+        imex(&t_full, ImexMode::ImportBackup, &full).await.unwrap();
+
+        let content = account_content(&t).await;
+        assert_eq!(content, account_content(&t_full).await);
+        assert!(content
+            .iter()
+            .any(|row| row.ends_with("after the base backup")));
+        assert!(content.iter().any(|row| row.contains("Bob Bobsen")));
+        assert!(!content.iter().any(|row| row.ends_with("deleted later")));
+        assert!(content
+            .iter()
+            .any(|row| row.starts_with("blob new-blob.txt")));
+    }
+
+    #[async_std::test]
+    async fn test_import_backup_incremental_wrong_base() {
+        let alice = TestContext::new_alice().await;
+        let backup_dir = tempfile::tempdir().unwrap();
+        let backup_dir: PathBuf = backup_dir.path().to_path_buf().into();
+
+        // An incremental backup needs a full backup first.
+        assert!(imex(&alice, ImexMode::ExportBackupIncremental, &backup_dir)
+            .await
+            .is_err());
+
+        imex(&alice, ImexMode::ExportBackup, &backup_dir)
+            .await
+            .unwrap();
+        let chat = alice
+            .create_chat_with_contact("bob", "bob@example.net")
+            .await
+            .id;
+        alice.send_text(chat, "hi").await;
+        imex(&alice, ImexMode::ExportBackupIncremental, &backup_dir)
+            .await
+            .unwrap();
+        let incremental = find_incremental_backup(&backup_dir).await;
+
+        // Another backup of the same address is not the base.
+        let alice2 = TestContext::new_alice().await;
+        let other_dir = tempfile::tempdir().unwrap();
+        let other_dir: PathBuf = other_dir.path().to_path_buf().into();
+        imex(&alice2, ImexMode::ExportBackup, &other_dir)
+            .await
+            .unwrap();
+        let other = has_backup(&alice2, &other_dir).await.unwrap();
+
+        let t = TestContext::new().await;
+        imex(&t, ImexMode::ImportBackup, &other).await.unwrap();
+        let content = account_content(&t).await;
+        assert!(imex(&t, ImexMode::ImportBackup, &incremental)
+            .await
+            .is_err());
+        assert!(t.sql.is_open().await);
+        assert!(t.is_configured().await);
+        assert_eq!(account_content(&t).await, content);
+
+        // Nor can it be imported to a new account.
+        let t = TestContext::new().await;
+        assert!(imex(&t, ImexMode::ImportBackup, &incremental)
+            .await
+            .is_err());
+        assert!(!t.is_configured().await);
+    }
+
+    #[async_std::test]
+    async fn test_check_backup() {
+        let alice = TestContext::new_alice().await;
+        let chat = alice
+            .create_chat_with_contact("bob", "bob@example.net")
+            .await;
+        alice.send_text(chat.id, "hi").await;
+        let backup_dir = tempfile::tempdir().unwrap();
+        let backup_dir: PathBuf = backup_dir.path().to_path_buf().into();
+        imex(&alice, ImexMode::ExportBackup, &backup_dir)
+            .await
+            .unwrap();
+        let backup = has_backup(&alice, &backup_dir).await.unwrap();
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let tmp_dir: PathBuf = tmp_dir.path().to_path_buf().into();
+
+        let info = check_backup(&backup, &tmp_dir).await.unwrap();
+        assert_eq!(info.format, Some(BackupFormat::V2));
+        assert_eq!(info.dbversion, DBVERSION);
+        assert_eq!(info.addr, Some("alice@example.com".to_string()));
+        assert!(info.timestamp > 0);
+        let msg_count: isize = alice
+            .sql
+            .query_get_value(
+                &alice,
+                "SELECT COUNT(*) FROM msgs WHERE chat_id>9;",
+                paramsv![],
+            )
+            .await
+            .unwrap();
+        assert_eq!(info.msg_count, msg_count as usize);
+        assert!(info.msg_count > 0);
+
+        // Truncated backup.
+        let data = fs::read(&backup).await.unwrap();
+        let truncated = backup_dir.join("truncated.tar");
+        fs::write(&truncated, data.get(..data.len() / 2).unwrap())
+            .await
+            .unwrap();
+        assert!(check_backup(&truncated, &tmp_dir).await.is_err());
+
+        // Not a backup at all.
+        let garbage = backup_dir.join("garbage.tar");
+        fs::write(&garbage, b"garbage").await.unwrap();
+        assert!(check_backup(&garbage, &tmp_dir).await.is_err());
+
+        // The extracted database is always removed.
+        assert!(fs::read_dir(&tmp_dir).await.unwrap().next().await.is_none());
+    }
+
+    #[async_std::test]
+    async fn test_check_backup_newer_dbversion() {
+        let alice = TestContext::new_alice().await;
+        alice
+            .sql
+            .set_raw_config_int(&alice, "dbversion", DBVERSION + 1)
+            .await
+            .unwrap();
+        let backup_dir = tempfile::tempdir().unwrap();
+        let backup_dir: PathBuf = backup_dir.path().to_path_buf().into();
+        imex(&alice, ImexMode::ExportBackup, &backup_dir)
+            .await
+            .unwrap();
+        let backup = has_backup(&alice, &backup_dir).await.unwrap();
+
+        let err = check_backup(&backup, alice.get_blobdir())
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("newer version"));
+    }
+
+    #[async_std::test]
+    async fn test_export_backup_snapshot_concurrent() {
+        let alice = TestContext::new_alice().await;
+        let chat = alice
+            .create_chat_with_contact("bob", "bob@example.net")
+            .await;
+        create_blobs(&alice, 50).await;
+        let backup_dir = tempfile::tempdir().unwrap();
+        let backup_dir: PathBuf = backup_dir.path().to_path_buf().into();
+
+        let msg_cnt_before = chat.id.get_msg_cnt(&alice).await;
+        let (res, ()) = futures::join!(imex(&alice, ImexMode::ExportBackup, &backup_dir), async {
+            for i in 0..50 {
+                chat::add_info_msg(&alice, chat.id, format!("message {}", i)).await;
+                async_std::task::yield_now().await;
+            }
+        });
+        res.unwrap();
+
+        // The live database is not replaced by the snapshot.
+        assert!(alice.sql.is_open().await);
+        assert_eq!(chat.id.get_msg_cnt(&alice).await, msg_cnt_before + 50);
+
+        let backup = has_backup(&alice, &backup_dir).await.unwrap();
+        check_backup(&backup, alice.get_blobdir()).await.unwrap();
+
+        // Only the backup is left, the snapshot is removed.
+        let files: Vec<_> = fs::read_dir(&backup_dir)
+            .await
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect()
+            .await;
+        assert_eq!(files.len(), 1);
+    }
+
+    #[async_std::test]
+    async fn test_import_truncated_backup() {
+        let alice = TestContext::new_alice().await;
+        create_blobs(&alice, 20).await;
+        let backup_dir = tempfile::tempdir().unwrap();
+        let backup_dir: PathBuf = backup_dir.path().to_path_buf().into();
+        imex(&alice, ImexMode::ExportBackup, &backup_dir)
+            .await
+            .unwrap();
+        let backup = has_backup(&alice, &backup_dir).await.unwrap();
+
+        let data = fs::read(&backup).await.unwrap();
+        let truncated = backup_dir.join("truncated.tar");
+        fs::write(&truncated, data.get(..data.len() / 2).unwrap())
+            .await
+            .unwrap();
+
+        let t = TestContext::new().await;
+        let blobs_before: Vec<_> = fs::read_dir(t.get_blobdir())
+            .await
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect()
+            .await;
+        assert!(imex(&t, ImexMode::ImportBackup, &truncated).await.is_err());
+
+        // The target context must not have been modified.
+        assert!(t.sql.is_open().await);
+        assert!(!t.is_configured().await);
+        let blobs_after: Vec<_> = fs::read_dir(t.get_blobdir())
+            .await
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect()
+            .await;
+        assert_eq!(blobs_before, blobs_after);
+    }
+
+    #[async_std::test]
+    async fn test_export_import_single_key() {
+        let alice = TestContext::new_alice().await;
+        let bob_key = crate::test_utils::bob_keypair();
+        key::store_self_keypair(&alice, &bob_key, key::KeyPairUse::ReadOnly)
+            .await
+            .unwrap();
+        let keys = key::list_keypairs(&alice).await.unwrap();
+        assert_eq!(keys.len(), 2);
+        let alice_fp = DcKey::fingerprint(&alice_keypair().public);
+        let bob_fp = DcKey::fingerprint(&bob_key.public);
+        assert!(keys
+            .iter()
+            .any(|info| info.fingerprint == alice_fp && info.is_default));
+        assert!(keys
+            .iter()
+            .any(|info| info.fingerprint == bob_fp && !info.is_default));
+
+        let path = alice.get_blobdir().join("bob-key.asc");
+        export_key(&alice, &bob_fp, &path).await.unwrap();
+
+        let t = TestContext::new_alice().await;
+        let report = import_key(&t, &path, false, true).await.unwrap();
+        assert_eq!(report.fingerprint, bob_fp);
+        assert_eq!(report.key_addrs, vec!["bob@example.net".to_string()]);
+        assert!(!report.already_present);
+        assert!(!report.is_default);
+        assert_eq!(report.replaced_default, None);
+        assert_eq!(report.warnings.len(), 1);
+        assert_eq!(key::list_keypairs(&t).await.unwrap().len(), 1);
+
+        let report = import_key(&t, &path, false, false).await.unwrap();
+        assert!(!report.is_default);
+        let keys = key::list_keypairs(&t).await.unwrap();
+        assert_eq!(keys.len(), 2);
+        let default: Vec<_> = keys
+            .iter()
+            .filter(|info| info.is_default)
+            .map(|info| &info.fingerprint)
+            .collect();
+        assert_eq!(default, vec![&alice_fp]);
+
+        // Making it the default reports the replaced key.
+        let report = import_key(&t, &path, true, true).await.unwrap();
+        assert!(report.already_present);
+        assert_eq!(report.replaced_default, Some(alice_fp));
+    }
+
+    #[async_std::test]
+    async fn test_import_key_garbage() {
+        let t = TestContext::new_alice().await;
+        let path = t.get_blobdir().join("garbage.asc");
+        fs::write(&path, b"this is not a key").await.unwrap();
+        let err = import_key(&t, &path, true, false).await.unwrap_err();
+        assert!(format!("{:#}", err).contains("does not contain an armored private key"));
+        assert_eq!(key::list_keypairs(&t).await.unwrap().len(), 1);
+    }
+
     #[async_std::test]
     async fn test_render_setup_file() {
         let t = TestContext::new().await;
@@ -1064,4 +3289,435 @@ async fn test_split_and_decrypt() {
         assert_eq!(headers.get(HEADER_AUTOCRYPT), Some(&"mutual".to_string()));
         assert!(headers.get(HEADER_SETUPCODE).is_none());
     }
+
+    #[async_std::test]
+    async fn test_key_transfer() {
+        let alice = TestContext::new_alice().await;
+        let alice2 = TestContext::new().await;
+        alice2.configure_addr("alice@example.com").await;
+        assert!(!has_ongoing_key_transfer(&alice));
+
+        let setup_code = create_setup_code(&alice);
+        send_setup_message(&alice, &setup_code).await.unwrap();
+        alice2.recv_msg(&alice.pop_sent_msg().await).await;
+        let msg = alice2.get_last_msg().await;
+        assert!(msg.is_setupmessage());
+
+        let err = continue_key_transfer(
+            &alice2,
+            msg.id,
+            "1234-1234-1234-1234-1234-1234-1234-1234-1234",
+        )
+        .await
+        .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<KeyTransferError>(),
+            Some(KeyTransferError::WrongSetupCode)
+        ));
+        let failures = alice2
+            .sql
+            .get_raw_config_int(&alice2, KEY_TRANSFER_FAILURES_KEY)
+            .await;
+        assert_eq!(failures, Some(1));
+
+        // After too many wrong codes, even the right code has to wait.
+        alice2
+            .sql
+            .set_raw_config_int(&alice2, KEY_TRANSFER_FAILURES_KEY, 5)
+            .await
+            .unwrap();
+        let err = continue_key_transfer(&alice2, msg.id, &setup_code)
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<KeyTransferError>(),
+            Some(KeyTransferError::TooManyAttempts(wait)) if *wait > 0 && *wait <= 4 * 60
+        ));
+
+        alice2
+            .sql
+            .set_raw_config_int64(&alice2, KEY_TRANSFER_LAST_FAILURE_KEY, 0)
+            .await
+            .unwrap();
+        continue_key_transfer(&alice2, msg.id, &setup_code)
+            .await
+            .unwrap();
+        let key = SignedPublicKey::load_self(&alice2).await.unwrap();
+        assert_eq!(
+            DcKey::fingerprint(&key),
+            DcKey::fingerprint(&alice_keypair().public)
+        );
+        let failures = alice2
+            .sql
+            .get_raw_config_int(&alice2, KEY_TRANSFER_FAILURES_KEY)
+            .await;
+        assert_eq!(failures, Some(0));
+    }
+
+    /// Address of the key in [S_EM_SETUPFILE].
+    const S_EM_ADDR: &str = "a1ebd68d-8c77-45b8-b033-8cac3f7d206d@autocrypt.org";
+
+    /// Receives an Autocrypt Setup Message sent by `addr` to itself containing `setup_file`.
+    async fn receive_setup_message(t: &TestContext, addr: &str, setup_file: &str) -> Message {
+        let eml = format!(
+            "From: <{addr}>\n\
+             To: <{addr}>\n\
+             Subject: Autocrypt Setup Message\n\
+             Date: Sun, 05 Nov 2017 09:00:00 +0000\n\
+             Message-ID: <{id}@autocrypt.org>\n\
+             Autocrypt-Setup-Message: v1\n\
+             MIME-Version: 1.0\n\
+             Content-Type: multipart/mixed; boundary=\"boundary\"\n\
+             \n\
+             --boundary\n\
+             Content-Type: text/plain; charset=utf-8\n\
+             \n\
+             This message contains all information to transfer your Autocrypt settings.\n\
+             \n\
+             --boundary\n\
+             Content-Type: application/autocrypt-setup\n\
+             Content-Disposition: attachment; filename=\"autocrypt-setup-message.html\"\n\
+             \n\
+             <html><body><pre>\n\
+             {setup_file}\n\
+             </pre></body></html>\n\
+             \n\
+             --boundary--\n",
+            addr = addr,
+            id = dc_create_id(),
+            setup_file = setup_file
+        );
+        dc_receive_imf(t, eml.as_bytes(), "INBOX", 1, false)
+            .await
+            .unwrap();
+        let msg = t.get_last_msg().await;
+        assert!(msg.is_setupmessage());
+        msg
+    }
+
+    #[async_std::test]
+    async fn test_key_transfer_old_client() {
+        let t = TestContext::new().await;
+        t.configure_addr(S_EM_ADDR).await;
+        let msg = receive_setup_message(&t, S_EM_ADDR, S_EM_SETUPFILE).await;
+        continue_key_transfer(&t, msg.id, S_EM_SETUPCODE)
+            .await
+            .unwrap();
+        let key = SignedPublicKey::load_self(&t).await.unwrap();
+        assert_eq!(
+            DcKey::fingerprint(&key).hex(),
+            "E60468CE44D77C3FCE9FD07271DBC5657FDE65A7"
+        );
+    }
+
+    #[async_std::test]
+    async fn test_key_transfer_errors() {
+        let t = TestContext::new_alice().await;
+
+        // The key of the setup message is for another address.
+        let msg = receive_setup_message(&t, "alice@example.com", S_EM_SETUPFILE).await;
+        let err = continue_key_transfer(&t, msg.id, S_EM_SETUPCODE)
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<KeyTransferError>(),
+            Some(KeyTransferError::KeyMismatch { .. })
+        ));
+        let key = SignedPublicKey::load_self(&t).await.unwrap();
+        assert_eq!(
+            DcKey::fingerprint(&key),
+            DcKey::fingerprint(&alice_keypair().public)
+        );
+
+        // The setup message contains no encrypted key at all.
+        let msg = receive_setup_message(&t, "alice@example.com", "no key here").await;
+        let err = continue_key_transfer(&t, msg.id, S_EM_SETUPCODE)
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<KeyTransferError>(),
+            Some(KeyTransferError::CorruptMessage(_))
+        ));
+        let failures = t
+            .sql
+            .get_raw_config_int(&t, KEY_TRANSFER_FAILURES_KEY)
+            .await;
+        assert_eq!(failures, None);
+
+        let chat_id = t.create_chat_with_contact("", "bob@example.net").await.id;
+        let msg_id = chat::send_text_msg(&t, chat_id, "hi".to_string())
+            .await
+            .unwrap();
+        let err = continue_key_transfer(&t, msg_id, S_EM_SETUPCODE)
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<KeyTransferError>(),
+            Some(KeyTransferError::NoSetupMessage)
+        ));
+    }
+
+    const MBOX: &[u8] = include_bytes!("../test-data/message/thunderbird.mbox");
+
+    async fn get_msg(t: &TestContext, rfc724_mid: &str) -> Message {
+        let (_, _, msg_id) = rfc724_mid_exists(t, rfc724_mid).await.unwrap().unwrap();
+        Message::load_from_db(t, msg_id).await.unwrap()
+    }
+
+    #[async_std::test]
+    async fn test_import_mbox() {
+        let alice = TestContext::new_alice().await;
+        let dir = tempfile::tempdir().unwrap();
+        let path: PathBuf = dir.path().join("Inbox").into();
+        fs::write(&path, MBOX).await.unwrap();
+
+        let stats = import_mbox(&alice, &path).await.unwrap();
+        assert_eq!(
+            stats,
+            ImportStats {
+                imported: 3,
+                skipped: 1,
+                failed: 1
+            }
+        );
+        assert!(alice.get_fresh_msgs().await.unwrap().is_empty());
+
+        let msg = get_msg(&alice, "import1@example.net").await;
+        assert_eq!(msg.get_state(), MessageState::InSeen);
+        assert_eq!(msg.get_text().unwrap(), "hello alice\nFrom the old mailbox");
+        let msg = get_msg(&alice, "import2@example.org").await;
+        assert_eq!(msg.get_state(), MessageState::OutDelivered);
+        assert_eq!(msg.server_uid, 0);
+        // Outgoing messages are no drafts, although they have no Received header.
+        assert!(!msg.chat_id.is_special());
+        assert_eq!(
+            msg.chat_id,
+            get_msg(&alice, "import1@example.net").await.chat_id
+        );
+
+        // Importing again does not duplicate messages.
+        let stats = import_mbox(&alice, &path).await.unwrap();
+        assert_eq!(stats.imported, 0);
+        assert_eq!(stats.skipped, 4);
+
+        // Messages are assigned to the same chats as on normal receive.
+        let t = TestContext::new_alice().await;
+        for raw in String::from_utf8_lossy(MBOX).split("\n\nFrom ") {
+            let raw = raw.splitn(2, '\n').nth(1).unwrap_or_default();
+            let raw = format!(
+                "Received: (Postfix, from userid 1000); Mon, 4 Jan 2021 14:51:39 +0100 (CET)\n{}",
+                raw
+            );
+            dc_receive_imf(&t, raw.as_bytes(), "INBOX", 1, false)
+                .await
+                .unwrap();
+        }
+        for rfc724_mid in &[
+            "import1@example.net",
+            "import2@example.org",
+            "import3@example.net",
+        ] {
+            let imported = get_msg(&alice, rfc724_mid).await;
+            let received = get_msg(&t, rfc724_mid).await;
+            assert_eq!(imported.chat_id, received.chat_id);
+            let imported_chat = Chat::load_from_db(&alice, imported.chat_id).await.unwrap();
+            let received_chat = Chat::load_from_db(&t, received.chat_id).await.unwrap();
+            assert_eq!(imported_chat.get_name(), received_chat.get_name());
+            assert_eq!(imported_chat.blocked, received_chat.blocked);
+        }
+    }
+
+    #[async_std::test]
+    async fn test_import_eml() {
+        let alice = TestContext::new_alice().await;
+        let dir = tempfile::tempdir().unwrap();
+        let path: PathBuf = dir.path().join("msg.eml").into();
+        fs::write(
+            &path,
+            b"From: Bob <bob@example.net>\n\
+              To: alice@example.org\n\
+              Subject: Hello\n\
+              Message-ID: <eml@example.net>\n\
+              Chat-Version: 1.0\n\
+              Date: Mon, 4 Jan 2021 10:00:00 +0000\n\
+              \n\
+              hello from the eml file\n",
+        )
+        .await
+        .unwrap();
+
+        assert!(import_eml(&alice, &path).await.unwrap());
+        assert!(!import_eml(&alice, &path).await.unwrap());
+        let msg = get_msg(&alice, "eml@example.net").await;
+        assert_eq!(msg.get_text().unwrap(), "hello from the eml file");
+        assert_eq!(msg.get_state(), MessageState::InSeen);
+
+        fs::write(&path, b"no message").await.unwrap();
+        assert!(import_eml(&alice, &path).await.is_err());
+    }
+
+    #[async_std::test]
+    async fn test_import_no_side_effects() {
+        let alice = TestContext::new_alice().await;
+        dc_receive_imf(
+            &alice,
+            b"Received: (Postfix, from userid 1000); Mon, 4 Dec 2006 14:51:39 +0100 (CET)\n\
+              From: Bob <bob@example.net>\n\
+              To: alice@example.org, claire@example.org\n\
+              Subject: Group\n\
+              Message-ID: <current@example.net>\n\
+              Chat-Version: 1.0\n\
+              Chat-Group-ID: sideeffects1\n\
+              Chat-Group-Name: Current name\n\
+              Date: Mon, 4 Jan 2021 10:00:00 +0000\n\
+              \n\
+              hello group\n",
+            "INBOX",
+            1,
+            false,
+        )
+        .await
+        .unwrap();
+        let chat_id = get_msg(&alice, "current@example.net").await.chat_id;
+        assert_eq!(chat::get_chat_contacts(&alice, chat_id).await.len(), 3);
+
+        // An old message removing Claire, renaming the group and carrying Bob's key.
+        let bob_key = bob_keypair().public;
+        let aheader = Aheader::new(
+            "bob@example.net".to_string(),
+            bob_key,
+            EncryptPreference::Mutual,
+        );
+        let dir = tempfile::tempdir().unwrap();
+        let path: PathBuf = dir.path().join("old.eml").into();
+        fs::write(
+            &path,
+            format!(
+                "From: Bob <bob@example.net>\n\
+                 To: alice@example.org\n\
+                 Subject: Group\n\
+                 Message-ID: <old@example.net>\n\
+                 Chat-Version: 1.0\n\
+                 Chat-Group-ID: sideeffects1\n\
+                 Chat-Group-Name: Old name\n\
+                 Chat-Group-Name-Changed: Current name\n\
+                 Chat-Group-Member-Removed: claire@example.org\n\
+                 Autocrypt: {}\n\
+                 Date: Sun, 3 Jan 2021 10:00:00 +0000\n\
+                 \n\
+                 Member claire@example.org removed.\n",
+                aheader
+            ),
+        )
+        .await
+        .unwrap();
+        assert!(import_eml(&alice, &path).await.unwrap());
+
+        let msg = get_msg(&alice, "old@example.net").await;
+        assert_eq!(msg.chat_id, chat_id);
+        assert_eq!(chat::get_chat_contacts(&alice, chat_id).await.len(), 3);
+        let chat = Chat::load_from_db(&alice, chat_id).await.unwrap();
+        assert_eq!(chat.get_name(), "Current name");
+        assert!(Peerstate::from_addr(&alice, "bob@example.net")
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    const WHATSAPP_TRANSCRIPT: &[u8] =
+        include_bytes!("../test-data/message/whatsapp_transcript.txt");
+
+    #[async_std::test]
+    async fn test_import_transcript() {
+        let alice = TestContext::new_alice().await;
+        let chat = alice
+            .create_chat_with_contact("Bob", "bob@example.net")
+            .await;
+        let dir = tempfile::tempdir().unwrap();
+        let path: PathBuf = dir.path().join("WhatsApp Chat with Bob.txt").into();
+        fs::write(&path, WHATSAPP_TRANSCRIPT).await.unwrap();
+        let mut senders = BTreeMap::new();
+        senders.insert("Alice".to_string(), DC_CONTACT_ID_SELF);
+
+        // 13/04/21 is no valid date if the month comes first.
+        let format = TranscriptFormat::WhatsApp {
+            date_order: DateOrder::MonthDayYear,
+            utc_offset: 7200,
+        };
+        assert!(import_transcript(&alice, chat.id, format, &path, &senders)
+            .await
+            .is_err());
+
+        let format = TranscriptFormat::WhatsApp {
+            date_order: DateOrder::DayMonthYear,
+            utc_offset: 7200,
+        };
+        let stats = import_transcript(&alice, chat.id, format, &path, &senders)
+            .await
+            .unwrap();
+        assert_eq!(
+            stats,
+            ImportStats {
+                imported: 5,
+                skipped: 2,
+                failed: 0
+            }
+        );
+        assert!(alice.get_fresh_msgs().await.unwrap().is_empty());
+
+        let mut msgs = Vec::new();
+        for item in chat::get_chat_msgs(&alice, chat.id, 0, None).await {
+            if let chat::ChatItem::Message { msg_id } = item {
+                msgs.push(Message::load_from_db(&alice, msg_id).await.unwrap());
+            }
+        }
+        let texts: Vec<_> = msgs.iter().map(|msg| msg.get_text().unwrap()).collect();
+        assert_eq!(
+            texts,
+            vec![
+                "Hi Alice 👋",
+                "Hi Bob!\nHow are you?\nLong time no see 😊",
+                "Fine, thanks.\n\nSee you on 04/03? 🎉",
+                "ok",
+                "ok"
+            ]
+        );
+        // 03/04/21, 09:15 in UTC+2
+        assert_eq!(msgs[0].get_timestamp(), 1617434100);
+        assert_eq!(msgs[4].get_timestamp(), 1618329720);
+
+        assert_eq!(msgs[1].get_from_id(), DC_CONTACT_ID_SELF);
+        assert_eq!(msgs[1].get_state(), MessageState::OutDelivered);
+        assert_eq!(msgs[0].get_state(), MessageState::InSeen);
+        let bob = Contact::load_from_db(&alice, msgs[0].get_from_id())
+            .await
+            .unwrap();
+        assert_eq!(bob.get_display_name(), "Bob");
+        assert!(bob.get_addr().ends_with("@transcript.invalid"));
+        assert_eq!(msgs[2].get_from_id(), bob.id);
+
+        // Importing again does not duplicate messages.
+        let stats = import_transcript(&alice, chat.id, format, &path, &senders)
+            .await
+            .unwrap();
+        assert_eq!(stats.imported, 0);
+        assert_eq!(stats.skipped, 7);
+    }
+
+    #[test]
+    fn test_parse_whatsapp_transcript() {
+        let (entries, skipped) = parse_whatsapp_transcript(
+            "12/31/20, 11:59\u{202f}PM - Alice: Happy new year!\n\
+             [01.01.21, 00:00:05] Bob: \u{200e}image omitted\n\
+             1/1/21, 12:01 AM - Bob: Thanks",
+            DateOrder::MonthDayYear,
+            0,
+        )
+        .unwrap();
+        assert_eq!(skipped, 1);
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].timestamp, 1609459140);
+        assert_eq!(entries[0].text, "Happy new year!");
+        assert_eq!(entries[1].timestamp, 1609459260);
+    }
 }
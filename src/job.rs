@@ -12,20 +12,24 @@
 use itertools::Itertools;
 use rand::{thread_rng, Rng};
 
+use crate::broadcast;
 use crate::dc_tools::{dc_delete_file, dc_read_file, time};
+use crate::download::{self, DownloadState};
 use crate::ephemeral::load_imap_deletion_msgid;
 use crate::events::EventType;
-use crate::imap::{Imap, ImapActionResult};
+use crate::imap::resync::{self, ResyncStats};
+use crate::imap::{cleanup, Imap, ImapActionResult, ImapSession};
 use crate::location;
 use crate::message::MsgId;
-use crate::message::{self, Message, MessageState};
+use crate::message::{self, ErrorCode, Message, MessageState, MsgError};
 use crate::mimefactory::MimeFactory;
+use crate::mimeparser::SystemMessage;
 use crate::param::{Param, Params};
 use crate::smtp::Smtp;
 use crate::{blob::BlobObject, contact::normalize_name, contact::Modifier, contact::Origin};
 use crate::{
     chat::{self, Chat, ChatId, ChatItem},
-    constants::DC_CHAT_ID_DEADDROP,
+    constants::{DC_CHAT_ID_DEADDROP, DC_CONTACT_ID_LAST_SPECIAL},
 };
 use crate::{config::Config, constants::Blocked};
 use crate::{constants::Chattype, contact::Contact};
@@ -35,6 +39,14 @@
 // results in ~3 weeks for the last backoff timespan
 const JOB_RETRIES: u32 = 17;
 
+/// Number of tries after which sending a message is given up
+/// and the message is marked as failed with [ErrorCode::RetriesExhausted].
+const SEND_MSG_RETRIES: u32 = 10;
+
+/// Upper bound of the time between two tries of sending or downloading a message
+/// in seconds.
+const MAX_BACKOFF: i64 = 60 * 60;
+
 /// Thread IDs
 #[derive(
     Debug, Display, Copy, Clone, PartialEq, Eq, FromPrimitive, ToPrimitive, FromSql, ToSql,
@@ -102,6 +114,15 @@ pub enum Action {
     MoveMsg = 200,
     DeleteMsgOnImap = 210,
 
+    // Locally deleted messages are deleted from the server in batches per folder.
+    DeleteTrashedMsgsOnImap = 212,
+
+    // Deleting old messages in batches is preferred over deleting them one by one.
+    DeleteOldMsgsOnImap = 215,
+
+    // Downloading a message is requested by the user and should not wait for other jobs.
+    DownloadMsg = 250,
+
     // UID synchronization is high-priority to make sure correct UIDs
     // are used by message moving/deletion.
     ResyncFolders = 300,
@@ -110,6 +131,9 @@ pub enum Action {
     MaybeSendLocations = 5005, // low priority ...
     MaybeSendLocationsEnded = 5007,
     SendMdn = 5010,
+
+    // Scheduled messages are passed to SendMsgToSmtp when their time has come.
+    SendScheduledMsg = 5900,
     SendMsgToSmtp = 5901, // ... high priority
 }
 
@@ -129,13 +153,17 @@ fn from(action: Action) -> Thread {
             Housekeeping => Thread::Imap,
             FetchExistingMsgs => Thread::Imap,
             DeleteMsgOnImap => Thread::Imap,
+            DeleteTrashedMsgsOnImap => Thread::Imap,
+            DeleteOldMsgsOnImap => Thread::Imap,
             ResyncFolders => Thread::Imap,
             MarkseenMsgOnImap => Thread::Imap,
             MoveMsg => Thread::Imap,
+            DownloadMsg => Thread::Imap,
 
             MaybeSendLocations => Thread::Smtp,
             MaybeSendLocationsEnded => Thread::Smtp,
             SendMdn => Thread::Smtp,
+            SendScheduledMsg => Thread::Smtp,
             SendMsgToSmtp => Thread::Smtp,
         }
     }
@@ -150,7 +178,7 @@ pub struct Job {
     pub added_timestamp: i64,
     pub tries: u32,
     pub param: Params,
-    pub pending_error: Option<String>,
+    pub pending_error: Option<MsgError>,
 }
 
 impl fmt::Display for Job {
@@ -179,6 +207,88 @@ pub fn delay_seconds(&self) -> i64 {
         self.desired_timestamp - self.added_timestamp
     }
 
+    /// Returns the ID of the message sent by this job, if any.
+    fn msg_id(&self) -> Option<MsgId> {
+        if self.action == Action::SendMsgToSmtp && self.foreign_id != 0 {
+            Some(MsgId::new(self.foreign_id))
+        } else {
+            None
+        }
+    }
+
+    /// Returns the number of tries after which the job is given up.
+    fn max_tries(&self) -> u32 {
+        if self.msg_id().is_some() {
+            SEND_MSG_RETRIES
+        } else {
+            JOB_RETRIES
+        }
+    }
+
+    /// Records the error of a failed try on the message sent by this job.
+    ///
+    /// If the job is going to be retried, the error is stored as transient error which does
+    /// not replace a permanent error.  Otherwise the message is marked as failed.
+    async fn record_msg_error(&self, context: &Context, status: &Status, retry: bool) {
+        let msg_id = match self.msg_id() {
+            Some(msg_id) => msg_id,
+            None => return,
+        };
+        if let Some(contact_id) = self.param.get_int(Param::ContactId) {
+            let failed = match status {
+                Status::Finished(res) => res.is_err(),
+                Status::RetryNow | Status::RetryLater => !retry,
+            };
+            if failed {
+                broadcast::set_state(context, msg_id, contact_id as u32, MessageState::OutFailed)
+                    .await
+                    .ok_or_log(context);
+            }
+        }
+
+        match status {
+            Status::Finished(Ok(())) => {}
+            Status::Finished(Err(err)) => {
+                let code = err
+                    .downcast_ref::<MsgError>()
+                    .map_or(ErrorCode::Unknown, |err| err.code);
+                message::set_msg_failed(context, msg_id, code, Some(err.to_string())).await;
+            }
+            Status::RetryNow | Status::RetryLater => {
+                let error = self.pending_error.clone().unwrap_or_else(|| {
+                    MsgError::new(ErrorCode::Unknown, "Message could not be sent.")
+                });
+                if retry {
+                    message::set_msg_transient_error(context, msg_id, &error).await;
+                } else {
+                    message::set_msg_failed(
+                        context,
+                        msg_id,
+                        ErrorCode::RetriesExhausted,
+                        Some(format!(
+                            "Giving up after {} tries: {}",
+                            self.tries + 1,
+                            error.text
+                        )),
+                    )
+                    .await;
+                }
+            }
+        }
+    }
+
+    /// Returns true if the job is stored in the database or was never saved.
+    async fn exists(&self, context: &Context) -> bool {
+        if self.job_id == 0 {
+            return true;
+        }
+        context
+            .sql
+            .exists("SELECT id FROM jobs WHERE id=?;", paramsv![self.job_id])
+            .await
+            .unwrap_or_default()
+    }
+
     /// Deletes the job from the database.
     async fn delete(self, context: &Context) -> Result<()> {
         if self.job_id != 0 {
@@ -250,11 +360,16 @@ async fn smtp_send<F, Fut>(
             info!(context, "smtp-sending out mime message:");
             println!("{}", String::from_utf8_lossy(&message));
         }
-        let status = match smtp.send(context, recipients, message, job_id).await {
+        match smtp.send(context, recipients, message, job_id).await {
             Err(crate::smtp::send::Error::SendError(err)) => {
                 // Remote error, retry later.
                 warn!(context, "SMTP failed to send: {}", err);
-                self.pending_error = Some(err.to_string());
+                let code = match err {
+                    async_smtp::smtp::error::Error::Permanent(_)
+                    | async_smtp::smtp::error::Error::Transient(_) => ErrorCode::SmtpTransient,
+                    _ => ErrorCode::Network,
+                };
+                self.pending_error = Some(MsgError::new(code, err.to_string()));
 
                 let res = match err {
                     async_smtp::smtp::error::Error::Permanent(ref response) => {
@@ -287,7 +402,11 @@ async fn smtp_send<F, Fut>(
                             // Yandex error "554 5.7.1 [2] Message rejected under suspicion of SPAM; https://ya.cc/..."
                             // should definitely go here, because user has to open the link to
                             // resume message sending.
-                            Status::Finished(Err(format_err!("Permanent SMTP error: {}", err)))
+                            Status::Finished(Err(MsgError::new(
+                                ErrorCode::SmtpPermanent,
+                                format!("Permanent SMTP error: {}", err),
+                            )
+                            .into()))
                         }
                     }
                     async_smtp::smtp::error::Error::Transient(ref response) => {
@@ -304,7 +423,11 @@ async fn smtp_send<F, Fut>(
                                 // receive as a transient error are misconfigurations of the smtp server.
                                 // See https://tools.ietf.org/html/rfc3463#section-3.2
                                 info!(context, "Smtp-job #{} Received extended status code {} for a transient error. This looks like a misconfigured smtp server, let's fail immediatly", self.job_id, first_word);
-                                Status::Finished(Err(format_err!("Permanent SMTP error: {}", err)))
+                                Status::Finished(Err(MsgError::new(
+                                    ErrorCode::SmtpPermanent,
+                                    format!("Permanent SMTP error: {}", err),
+                                )
+                                .into()))
                             } else {
                                 Status::RetryLater
                             }
@@ -331,7 +454,11 @@ async fn smtp_send<F, Fut>(
                 // Local error, job is invalid, do not retry.
                 smtp.disconnect().await;
                 warn!(context, "SMTP job is invalid: {}", err);
-                Status::Finished(Err(err.into()))
+                Status::Finished(Err(MsgError::new(
+                    ErrorCode::InvalidMessage,
+                    err.to_string(),
+                )
+                .into()))
             }
             Err(crate::smtp::send::Error::NoTransport) => {
                 // Should never happen.
@@ -343,20 +470,14 @@ async fn smtp_send<F, Fut>(
                 job_try!(success_cb().await);
                 Status::Finished(Ok(()))
             }
-        };
-
-        if let Status::Finished(Err(err)) = &status {
-            // We couldn't send the message, so mark it as failed
-            let msg_id = MsgId::new(self.foreign_id);
-            message::set_msg_failed(context, msg_id, Some(err.to_string())).await;
         }
-        status
     }
 
     pub(crate) async fn send_msg_to_smtp(&mut self, context: &Context, smtp: &mut Smtp) -> Status {
         //  SMTP server, if not yet done
         if let Err(err) = smtp.connect_configured(context).await {
             warn!(context, "SMTP connection failure: {:?}", err);
+            self.pending_error = Some(MsgError::new(ErrorCode::Network, err.to_string()));
             return Status::RetryLater;
         }
 
@@ -395,11 +516,22 @@ pub(crate) async fn send_msg_to_smtp(&mut self, context: &Context, smtp: &mut Sm
         };
 
         let foreign_id = self.foreign_id;
+        let broadcast_contact_id = self.param.get_int(Param::ContactId);
         self.smtp_send(context, recipients_list, body, self.job_id, smtp, || {
             async move {
                 // smtp success, update db ASAP, then delete smtp file
                 if 0 != foreign_id {
-                    set_delivered(context, MsgId::new(foreign_id)).await;
+                    let msg_id = MsgId::new(foreign_id);
+                    if let Some(contact_id) = broadcast_contact_id {
+                        // a copy of a broadcast message, the message is delivered
+                        // once all copies are delivered
+                        if broadcast::set_copy_delivered(context, msg_id, contact_id as u32).await?
+                        {
+                            set_delivered(context, msg_id).await;
+                        }
+                    } else {
+                        set_delivered(context, msg_id).await;
+                    }
                 }
                 // now also delete the generated file
                 dc_delete_file(context, filename).await;
@@ -502,6 +634,7 @@ async fn send_mdn(&mut self, context: &Context, smtp: &mut Smtp) -> Status {
         // connect to SMTP server, if not yet done
         if let Err(err) = smtp.connect_configured(context).await {
             warn!(context, "SMTP connection failure: {:?}", err);
+            self.pending_error = Some(MsgError::new(ErrorCode::Network, err.to_string()));
             return Status::RetryLater;
         }
 
@@ -623,27 +756,7 @@ async fn delete_msg_on_imap(&mut self, context: &Context, imap: &mut Imap) -> St
                     }
                 }
             }
-            if msg.chat_id.is_trash() || msg.hidden {
-                // Messages are stored in trash chat only to keep
-                // their server UID and Message-ID. Once message is
-                // deleted from the server, database record can be
-                // removed as well.
-                //
-                // Hidden messages are similar to trashed, but are
-                // related to some chat. We also delete their
-                // database records.
-                job_try!(msg.id.delete_from_db(context).await)
-            } else {
-                // Remove server UID from the database record.
-                //
-                // We have either just removed the message from the
-                // server, in which case UID is not valid anymore, or
-                // we have more refernces to the same server UID, so
-                // we remove UID to reduce the number of messages
-                // pointing to the corresponding UID. Once the counter
-                // reaches zero, we will remove the message.
-                job_try!(msg.id.unlink(context).await);
-            }
+            job_try!(message::unlink_deleted_msg(context, &msg).await);
             Status::Finished(Ok(()))
         } else {
             /* eg. device messages have no Message-ID */
@@ -651,6 +764,89 @@ async fn delete_msg_on_imap(&mut self, context: &Context, imap: &mut Imap) -> St
         }
     }
 
+    /// Deletes the locally deleted messages from the server,
+    /// see [`Config::DeleteOnServerWhenDeletingLocally`].
+    async fn delete_trashed_msgs_on_imap(&mut self, context: &Context, imap: &mut Imap) -> Status {
+        if let Err(err) = imap.connect_configured(context).await {
+            warn!(context, "could not connect: {:?}", err);
+            return Status::RetryLater;
+        }
+
+        match cleanup::delete_trashed(context, imap, cleanup::PAGE_SIZE).await {
+            Ok(deleted) => {
+                info!(
+                    context,
+                    "Deleted {} trashed messages from the server", deleted
+                );
+                Status::Finished(Ok(()))
+            }
+            Err(err) => {
+                // The tombstones are kept, so the next try continues with the remaining ones.
+                warn!(context, "Deleting trashed messages failed: {:#}", err);
+                Status::RetryLater
+            }
+        }
+    }
+
+    /// Deletes a part of the messages older than `delete_server_after` from the server
+    /// and schedules itself again if there are more, see [`cleanup`].
+    async fn delete_old_msgs_on_imap(&mut self, context: &Context, imap: &mut Imap) -> Status {
+        if let Err(err) = imap.connect_configured(context).await {
+            warn!(context, "could not connect: {:?}", err);
+            return Status::RetryLater;
+        }
+
+        match cleanup::run(context, imap, cleanup::PAGE_SIZE, cleanup::PAGES_PER_RUN).await {
+            Ok(cleanup::Progress::Done) => Status::Finished(Ok(())),
+            Ok(cleanup::Progress::Paused) => {
+                add(
+                    context,
+                    Job::new(
+                        Action::DeleteOldMsgsOnImap,
+                        0,
+                        Params::new(),
+                        cleanup::PAUSE,
+                    ),
+                )
+                .await;
+                Status::Finished(Ok(()))
+            }
+            Err(err) => {
+                warn!(context, "Server cleanup failed: {:#}", err);
+                Status::RetryLater
+            }
+        }
+    }
+
+    /// Downloads the full message to replace a partially downloaded one.
+    ///
+    /// If the download keeps failing, the message is marked as failed to download,
+    /// the user can retry it later.
+    pub(crate) async fn download_msg(
+        &mut self,
+        context: &Context,
+        session: &mut impl ImapSession,
+    ) -> Status {
+        let msg_id = MsgId::new(self.foreign_id);
+        match download::download_msg(context, msg_id, session).await {
+            ImapActionResult::Success | ImapActionResult::AlreadyDone => Status::Finished(Ok(())),
+            ImapActionResult::RetryLater if self.tries + 1 < self.max_tries() => Status::RetryLater,
+            ImapActionResult::RetryLater | ImapActionResult::Failed => {
+                let err = format_err!("Failed to download message {}", msg_id);
+                job_try!(
+                    download::set_download_state(
+                        context,
+                        msg_id,
+                        DownloadState::Failure,
+                        Some(&err.to_string()),
+                    )
+                    .await
+                );
+                Status::Finished(Err(err))
+            }
+        }
+    }
+
     /// Read the recipients from old emails sent by the user and add them as contacts.
     /// This way, we can already offer them some email addresses they can write to.
     ///
@@ -712,7 +908,7 @@ async fn fetch_existing_msgs(&mut self, context: &Context, imap: &mut Imap) -> S
                             chat.id.unblock(context).await;
                         }
                     }
-                    Chattype::Single | Chattype::Undefined => {}
+                    Chattype::Single | Chattype::Undefined | Chattype::Broadcast => {}
                 }
             }
         }
@@ -736,26 +932,22 @@ async fn resync_folders(&mut self, context: &Context, imap: &mut Imap) -> Status
             return Status::RetryLater;
         }
 
-        if let Some(sentbox_folder) = &context.get_config(Config::ConfiguredSentboxFolder).await {
-            job_try!(
-                imap.resync_folder_uids(context, sentbox_folder.to_string())
-                    .await
-            );
-        }
-
-        if let Some(inbox_folder) = &context.get_config(Config::ConfiguredInboxFolder).await {
-            job_try!(
-                imap.resync_folder_uids(context, inbox_folder.to_string())
-                    .await
-            );
+        let mut stats = ResyncStats::default();
+        for folder_config in &[
+            Config::ConfiguredSentboxFolder,
+            Config::ConfiguredInboxFolder,
+            Config::ConfiguredMvboxFolder,
+        ] {
+            if let Some(folder) = context.get_config(*folder_config).await {
+                stats += job_try!(resync::resync_folder(context, imap, &folder).await);
+            }
         }
 
-        if let Some(mvbox_folder) = &context.get_config(Config::ConfiguredMvboxFolder).await {
-            job_try!(
-                imap.resync_folder_uids(context, mvbox_folder.to_string())
-                    .await
-            );
-        }
+        context.emit_event(EventType::FoldersResynced {
+            matched: stats.matched,
+            new: stats.new,
+            orphaned: stats.orphaned,
+        });
         Status::Finished(Ok(()))
     }
 
@@ -850,6 +1042,7 @@ pub async fn action_exists(context: &Context, action: Action) -> bool {
 
 async fn set_delivered(context: &Context, msg_id: MsgId) {
     message::update_msg_state(context, msg_id, MessageState::OutDelivered).await;
+    message::clear_transient_error(context, msg_id).await;
     let chat_id: ChatId = context
         .sql
         .query_get_value(
@@ -907,26 +1100,46 @@ async fn add_all_recipients_as_contacts(context: &Context, imap: &mut Imap, fold
     };
 }
 
-/// Constructs a job for sending a message.
+/// Constructs the jobs for sending a message.
 ///
-/// Returns `None` if no messages need to be sent out.
+/// Usually, this is a single job.  Messages to broadcast lists are sent out as a separate
+/// copy to each member, so there is one job per member.
+/// Returns an empty list if no messages need to be sent out.
 ///
-/// In order to be processed, must be `add`ded.
-pub async fn send_msg_job(context: &Context, msg_id: MsgId) -> Result<Option<Job>> {
+/// In order to be processed, the jobs must be `add`ded.
+pub async fn send_msg_job(context: &Context, msg_id: MsgId) -> Result<Vec<Job>> {
     let mut msg = Message::load_from_db(context, msg_id).await?;
     msg.try_calc_and_set_dimensions(context).await.ok();
 
     /* create message */
     let needs_encryption = msg.param.get_bool(Param::GuaranteeE2ee).unwrap_or_default();
 
-    let attach_selfavatar = match chat::shall_attach_selfavatar(context, msg.chat_id).await {
-        Ok(attach_selfavatar) => attach_selfavatar,
-        Err(err) => {
-            warn!(context, "job: cannot get selfavatar-state: {}", err);
-            false
+    let attach_selfavatar = if msg.param.get_cmd() == SystemMessage::ConfigSync {
+        // Sync messages carry the avatar only if it was changed.
+        msg.param.get_bool(Param::Arg2).unwrap_or_default()
+    } else {
+        match chat::shall_attach_selfavatar(context, msg.chat_id).await {
+            Ok(attach_selfavatar) => attach_selfavatar,
+            Err(err) => {
+                warn!(context, "job: cannot get selfavatar-state: {}", err);
+                false
+            }
         }
     };
 
+    let chat = Chat::load_from_db(context, msg.chat_id).await?;
+    if chat.typ == Chattype::Broadcast {
+        let jobs = send_broadcast_msg_jobs(context, &mut msg, attach_selfavatar).await?;
+        if jobs.is_empty() {
+            info!(
+                context,
+                "message {} has no recipient, skipping smtp-send", msg_id
+            );
+            set_delivered(context, msg_id).await;
+        }
+        return Ok(jobs);
+    }
+
     let mimefactory = MimeFactory::from_msg(context, &msg, attach_selfavatar).await?;
 
     let mut recipients = mimefactory.recipients();
@@ -937,10 +1150,7 @@ pub async fn send_msg_job(context: &Context, msg_id: MsgId) -> Result<Option<Job
         .unwrap_or_default();
     let lowercase_from = from.to_lowercase();
 
-    // Send BCC to self if it is enabled and we are not going to
-    // delete it immediately.
-    if context.get_config_bool(Config::BccSelf).await
-        && context.get_config_delete_server_after().await != Some(0)
+    if should_bcc_self(context).await
         && !recipients
             .iter()
             .any(|x| x.to_lowercase() == lowercase_from)
@@ -955,13 +1165,19 @@ pub async fn send_msg_job(context: &Context, msg_id: MsgId) -> Result<Option<Job
             "message {} has no recipient, skipping smtp-send", msg_id
         );
         set_delivered(context, msg_id).await;
-        return Ok(None);
+        return Ok(Vec::new());
     }
 
     let rendered_msg = match mimefactory.render(context).await {
         Ok(res) => Ok(res),
         Err(err) => {
-            message::set_msg_failed(context, msg_id, Some(err.to_string())).await;
+            message::set_msg_failed(
+                context,
+                msg_id,
+                ErrorCode::InvalidMessage,
+                Some(err.to_string()),
+            )
+            .await;
             Err(err)
         }
     }?;
@@ -971,6 +1187,7 @@ pub async fn send_msg_job(context: &Context, msg_id: MsgId) -> Result<Option<Job
         message::set_msg_failed(
             context,
             msg_id,
+            ErrorCode::E2eeUnavailable,
             Some("End-to-end-encryption unavailable unexpectedly."),
         )
         .await;
@@ -1024,7 +1241,92 @@ pub async fn send_msg_job(context: &Context, msg_id: MsgId) -> Result<Option<Job
 
     let job = create(Action::SendMsgToSmtp, msg_id.to_u32() as i32, param, 0)?;
 
-    Ok(Some(job))
+    Ok(vec![job])
+}
+
+/// Returns whether sent messages are sent as BCC to self, i.e. it is enabled
+/// and the messages are not going to be deleted from the server immediately.
+async fn should_bcc_self(context: &Context) -> bool {
+    context.get_config_bool(Config::BccSelf).await
+        && context.get_config_delete_server_after().await != Some(0)
+}
+
+/// Constructs a job for each member of a broadcast list the message is not yet delivered to.
+///
+/// Each member gets a separate copy, encrypted to that member only if possible,
+/// so that members do not see each other.
+/// If BCC to self is enabled, the first copy is sent to self as well,
+/// the other devices ignore the further copies as they have the same Message-ID.
+async fn send_broadcast_msg_jobs(
+    context: &Context,
+    msg: &mut Message,
+    attach_selfavatar: bool,
+) -> Result<Vec<Job>> {
+    let mut copies = Vec::new();
+    {
+        let mimefactory = MimeFactory::from_msg(context, msg, attach_selfavatar).await?;
+        for contact_id in chat::get_chat_contacts(context, msg.chat_id).await {
+            if contact_id <= DC_CONTACT_ID_LAST_SPECIAL
+                || broadcast::get_state(context, msg.id, contact_id).await?
+                    == Some(MessageState::OutDelivered)
+            {
+                continue;
+            }
+            let contact = Contact::load_from_db(context, contact_id).await?;
+            let mut copy = mimefactory.clone();
+            copy.retain_recipient(contact.get_addr());
+            match copy.render(context).await {
+                Ok(rendered_msg) => {
+                    copies.push((contact_id, contact.get_addr().to_string(), rendered_msg))
+                }
+                Err(err) => {
+                    message::set_msg_failed(
+                        context,
+                        msg.id,
+                        ErrorCode::InvalidMessage,
+                        Some(err.to_string()),
+                    )
+                    .await;
+                    return Err(err);
+                }
+            }
+        }
+    }
+
+    let mut bcc_self = if should_bcc_self(context).await {
+        context.get_config(Config::ConfiguredAddr).await
+    } else {
+        None
+    };
+    let mut jobs = Vec::with_capacity(copies.len());
+    for (contact_id, addr, rendered_msg) in copies {
+        let blob =
+            BlobObject::create(context, &rendered_msg.rfc724_mid, &rendered_msg.message).await?;
+        let mut param = Params::new();
+        param.set(Param::File, blob.as_name());
+        match bcc_self.take() {
+            Some(self_addr) => param.set(Param::Recipients, format!("{}\x1e{}", addr, self_addr)),
+            None => param.set(Param::Recipients, addr),
+        };
+        param.set_int(Param::ContactId, contact_id as i32);
+        broadcast::set_state(context, msg.id, contact_id, MessageState::OutPending).await?;
+        jobs.push(create(
+            Action::SendMsgToSmtp,
+            msg.id.to_u32() as i32,
+            param,
+            0,
+        )?);
+        msg.subject = rendered_msg.subject;
+    }
+
+    if attach_selfavatar && !jobs.is_empty() {
+        if let Err(err) = msg.chat_id.set_selfavatar_timestamp(context, time()).await {
+            error!(context, "Failed to set selfavatar timestamp: {:?}", err);
+        }
+    }
+    msg.update_subject(context).await;
+
+    Ok(jobs)
 }
 
 pub(crate) enum Connection<'a> {
@@ -1071,7 +1373,25 @@ fn smtp(&mut self) -> &mut Smtp {
     }
 }
 
-pub(crate) async fn perform_job(context: &Context, mut connection: Connection<'_>, mut job: Job) {
+pub(crate) async fn perform_job(context: &Context, connection: Connection<'_>, job: Job) {
+    if let Connection::Smtp(_) = connection {
+        {
+            // Claim the job, so that it is not cancelled while it is performed.
+            let mut outbox_job = context.outbox_job.lock().await;
+            if !job.exists(context).await {
+                info!(context, "{}-job {} was cancelled", &connection, &job);
+                return;
+            }
+            *outbox_job = Some(job.job_id);
+        }
+        perform_claimed_job(context, connection, job).await;
+        *context.outbox_job.lock().await = None;
+    } else {
+        perform_claimed_job(context, connection, job).await;
+    }
+}
+
+async fn perform_claimed_job(context: &Context, mut connection: Connection<'_>, mut job: Job) {
     info!(context, "{}-job {} started...", &connection, &job);
 
     let try_res = match perform_job_action(context, &mut job, &mut connection, 0).await {
@@ -1079,17 +1399,28 @@ pub(crate) async fn perform_job(context: &Context, mut connection: Connection<'_
         x => x,
     };
 
+    let max_tries = job.max_tries();
+    let retry = match try_res {
+        Status::RetryNow | Status::RetryLater => job.tries + 1 < max_tries,
+        Status::Finished(_) => false,
+    };
+    job.record_msg_error(context, &try_res, retry).await;
+
     match try_res {
         Status::RetryNow | Status::RetryLater => {
             let tries = job.tries + 1;
 
-            if tries < JOB_RETRIES {
+            if tries < max_tries {
                 info!(
                     context,
                     "{} thread increases job {} tries to {}", &connection, job, tries
                 );
                 job.tries = tries;
-                let time_offset = get_backoff_time_offset(tries);
+                let time_offset = if job.msg_id().is_some() || job.action == Action::DownloadMsg {
+                    get_send_backoff_time_offset(tries)
+                } else {
+                    get_backoff_time_offset(tries)
+                };
                 job.desired_timestamp = time() + time_offset;
                 info!(
                     context,
@@ -1108,7 +1439,7 @@ pub(crate) async fn perform_job(context: &Context, mut connection: Connection<'_
                     "{} thread removes job {} as it exhausted {} retries",
                     &connection,
                     job,
-                    JOB_RETRIES
+                    max_tries
                 );
                 job.delete(context).await.unwrap_or_else(|err| {
                     error!(context, "failed to delete job: {}", err);
@@ -1150,17 +1481,31 @@ async fn perform_job_action(
         Action::Unknown => Status::Finished(Err(format_err!("Unknown job id found"))),
         Action::SendMsgToSmtp => job.send_msg_to_smtp(context, connection.smtp()).await,
         Action::SendMdn => job.send_mdn(context, connection.smtp()).await,
+        Action::SendScheduledMsg => {
+            Status::Finished(chat::send_scheduled_msg(context, MsgId::new(job.foreign_id)).await)
+        }
         Action::MaybeSendLocations => location::job_maybe_send_locations(context, job).await,
         Action::MaybeSendLocationsEnded => {
             location::job_maybe_send_locations_ended(context, job).await
         }
         Action::DeleteMsgOnImap => job.delete_msg_on_imap(context, connection.inbox()).await,
+        Action::DeleteTrashedMsgsOnImap => {
+            job.delete_trashed_msgs_on_imap(context, connection.inbox())
+                .await
+        }
+        Action::DeleteOldMsgsOnImap => {
+            job.delete_old_msgs_on_imap(context, connection.inbox())
+                .await
+        }
         Action::ResyncFolders => job.resync_folders(context, connection.inbox()).await,
         Action::MarkseenMsgOnImap => job.markseen_msg_on_imap(context, connection.inbox()).await,
         Action::MoveMsg => job.move_msg(context, connection.inbox()).await,
         Action::FetchExistingMsgs => job.fetch_existing_msgs(context, connection.inbox()).await,
+        Action::DownloadMsg => job.download_msg(context, connection.inbox()).await,
         Action::Housekeeping => {
-            sql::housekeeping(context).await.ok_or_log(context);
+            if let Some(report) = sql::housekeeping(context).await.ok_or_log(context) {
+                report.warnings.emit(context);
+            }
             Status::Finished(Ok(()))
         }
     };
@@ -1181,6 +1526,46 @@ fn get_backoff_time_offset(tries: u32) -> i64 {
     seconds as i64
 }
 
+/// Returns the time until the next try of sending or downloading a message
+/// which failed `tries` times, in seconds.
+///
+/// The time doubles with each try, starting with one minute, and is capped at
+/// [MAX_BACKOFF]. `jitter` in the range `0.0..1.0` picks a time in the upper half of
+/// this interval, so that messages which failed together are not retried together.
+fn send_backoff_time_offset(tries: u32, jitter: f64) -> i64 {
+    let exponent = tries.saturating_sub(1).min(16);
+    let max = (60_i64 << exponent).min(MAX_BACKOFF);
+    let min = max / 2;
+    min + ((max - min) as f64 * jitter) as i64
+}
+
+fn get_send_backoff_time_offset(tries: u32) -> i64 {
+    send_backoff_time_offset(tries, thread_rng().gen())
+}
+
+/// Schedules sending a message again, resetting the number of tries.
+///
+/// If the job sending the message was given up, a new one is created.
+pub(crate) async fn resend_msg(context: &Context, msg_id: MsgId) -> Result<()> {
+    let updated = context
+        .sql
+        .execute(
+            "UPDATE jobs SET tries=0, desired_timestamp=? WHERE action=? AND foreign_id=?;",
+            paramsv![time(), Action::SendMsgToSmtp, msg_id],
+        )
+        .await?;
+    if updated > 0 {
+        context
+            .interrupt_smtp(InterruptInfo::new(false, Some(msg_id)))
+            .await;
+    } else {
+        for job in send_msg_job(context, msg_id).await? {
+            add(context, job).await;
+        }
+    }
+    Ok(())
+}
+
 async fn send_mdn(context: &Context, msg: &Message) -> Result<()> {
     let mut param = Params::new();
     param.set(Param::MsgId, msg.id.to_u32().to_string());
@@ -1209,6 +1594,54 @@ pub fn create(action: Action, foreign_id: i32, param: Params, delay_seconds: i64
     Ok(Job::new(action, foreign_id as u32, param, delay_seconds))
 }
 
+/// Returns the number of messages waiting to be sent via SMTP.
+pub(crate) async fn count_unsent_msgs(context: &Context) -> sql::Result<usize> {
+    let count: i32 = context
+        .sql
+        .query_get_value_result(
+            "SELECT COUNT(*) FROM jobs WHERE action=?;",
+            paramsv![Action::SendMsgToSmtp],
+        )
+        .await?
+        .unwrap_or_default();
+    Ok(count as usize)
+}
+
+/// Returns the time of the next scheduled message to be sent, if any.
+///
+/// The SMTP thread does not idle beyond this time.
+pub(crate) async fn next_scheduled_send(context: &Context) -> Option<i64> {
+    context
+        .sql
+        .query_get_value(
+            context,
+            "SELECT desired_timestamp FROM jobs WHERE action=? \
+             ORDER BY desired_timestamp LIMIT 1;",
+            paramsv![Action::SendScheduledMsg],
+        )
+        .await
+}
+
+/// Deletes the jobs for messages that do not exist anymore.
+///
+/// Returns the number of deleted jobs.
+pub(crate) async fn prune(context: &Context) -> sql::Result<usize> {
+    context
+        .sql
+        .execute(
+            "DELETE FROM jobs WHERE action IN (?, ?, ?, ?, ?) \
+             AND foreign_id NOT IN (SELECT id FROM msgs);",
+            paramsv![
+                Action::DownloadMsg,
+                Action::MarkseenMsgOnImap,
+                Action::MoveMsg,
+                Action::SendScheduledMsg,
+                Action::SendMsgToSmtp
+            ],
+        )
+        .await
+}
+
 /// Adds a job to the database, scheduling it.
 pub async fn add(context: &Context, job: Job) {
     let action = job.action;
@@ -1222,9 +1655,12 @@ pub async fn add(context: &Context, job: Job) {
             Action::Unknown => unreachable!(),
             Action::Housekeeping
             | Action::DeleteMsgOnImap
+            | Action::DeleteTrashedMsgsOnImap
+            | Action::DeleteOldMsgsOnImap
             | Action::ResyncFolders
             | Action::MarkseenMsgOnImap
             | Action::FetchExistingMsgs
+            | Action::DownloadMsg
             | Action::MoveMsg => {
                 info!(context, "interrupt: imap");
                 context
@@ -1234,6 +1670,7 @@ pub async fn add(context: &Context, job: Job) {
             Action::MaybeSendLocations
             | Action::MaybeSendLocationsEnded
             | Action::SendMdn
+            | Action::SendScheduledMsg
             | Action::SendMsgToSmtp => {
                 info!(context, "interrupt: smtp");
                 context
@@ -1397,6 +1834,7 @@ pub(crate) async fn load_next(
 mod tests {
     use super::*;
 
+    use crate::events::Event;
     use crate::test_utils::TestContext;
 
     async fn insert_job(context: &Context, foreign_id: i64) {
@@ -1446,6 +1884,221 @@ async fn test_load_next_job_two() {
         assert!(jobs.is_some());
     }
 
+    /// Returns the events received until a checkpoint event emitted by this function.
+    async fn events_until_checkpoint(
+        t: &TestContext,
+        event_rx: &async_std::channel::Receiver<EventType>,
+    ) -> Vec<EventType> {
+        t.emit_event(EventType::Info("checkpoint".to_string()));
+        let mut events = Vec::new();
+        loop {
+            match event_rx.recv().await.unwrap() {
+                EventType::Info(_) => break events,
+                event => events.push(event),
+            }
+        }
+    }
+
+    #[async_std::test]
+    async fn test_send_msg_errors() {
+        let t = TestContext::new_alice().await;
+        let chat = t.create_chat_with_contact("bob", "bob@example.net").await;
+
+        let (event_tx, event_rx) = async_std::channel::bounded(100);
+        t.add_event_sink(move |event: Event| {
+            let event_tx = event_tx.clone();
+            async move {
+                match &event.typ {
+                    EventType::MsgFailed { .. } | EventType::MsgsChanged { .. } => {
+                        event_tx.try_send(event.typ.clone()).unwrap()
+                    }
+                    EventType::Info(msg) if msg == "checkpoint" => {
+                        event_tx.try_send(event.typ.clone()).unwrap()
+                    }
+                    _ => {}
+                }
+            }
+        })
+        .await;
+        let msg_id = chat::send_text_msg(&t, chat.id, "hi".to_string())
+            .await
+            .unwrap();
+        events_until_checkpoint(&t, &event_rx).await;
+
+        // SMTP is not configured, so connecting fails and sending is retried later.
+        let job = load_next(&t, Thread::Smtp, &InterruptInfo::new(false, None))
+            .await
+            .unwrap();
+        perform_job(&t, Connection::Smtp(&mut Smtp::new()), job).await;
+        let msg = Message::load_from_db(&t, msg_id).await.unwrap();
+        assert_eq!(msg.state, MessageState::OutPending);
+        assert_eq!(msg.get_error().unwrap().code, ErrorCode::Network);
+        let events = events_until_checkpoint(&t, &event_rx).await;
+        assert!(events.contains(&EventType::MsgsChanged {
+            chat_id: chat.id,
+            msg_id
+        }));
+        assert!(!events
+            .iter()
+            .any(|event| matches!(event, EventType::MsgFailed { .. })));
+
+        // The server rejects the message permanently.
+        let mut job = Job::new(Action::SendMsgToSmtp, msg_id.to_u32(), Params::new(), 0);
+        let status = Status::Finished(Err(MsgError::new(
+            ErrorCode::SmtpPermanent,
+            "Permanent SMTP error: 554 5.7.1 Message rejected",
+        )
+        .into()));
+        job.record_msg_error(&t, &status, false).await;
+        let msg = Message::load_from_db(&t, msg_id).await.unwrap();
+        assert_eq!(msg.state, MessageState::OutFailed);
+        assert_eq!(
+            msg.get_error().unwrap(),
+            MsgError::new(
+                ErrorCode::SmtpPermanent,
+                "Permanent SMTP error: 554 5.7.1 Message rejected"
+            )
+        );
+        assert_eq!(
+            events_until_checkpoint(&t, &event_rx).await,
+            vec![EventType::MsgFailed {
+                chat_id: chat.id,
+                msg_id
+            }]
+        );
+
+        // A later transient error does not replace the permanent one.
+        job.pending_error = Some(MsgError::new(ErrorCode::Network, "Connection refused"));
+        job.record_msg_error(&t, &Status::RetryLater, true).await;
+        let msg = Message::load_from_db(&t, msg_id).await.unwrap();
+        assert_eq!(msg.get_error().unwrap().code, ErrorCode::SmtpPermanent);
+        assert!(events_until_checkpoint(&t, &event_rx).await.is_empty());
+    }
+
+    #[test]
+    fn test_send_backoff_time_offset() {
+        assert_eq!(send_backoff_time_offset(1, 0.0), 30);
+        assert_eq!(send_backoff_time_offset(1, 0.99), 59);
+        assert_eq!(send_backoff_time_offset(2, 0.0), 60);
+        assert_eq!(send_backoff_time_offset(6, 0.5), 1440);
+        assert_eq!(send_backoff_time_offset(7, 0.0), MAX_BACKOFF / 2);
+        assert_eq!(send_backoff_time_offset(100, 0.0), MAX_BACKOFF / 2);
+        assert!(send_backoff_time_offset(100, 0.999) < MAX_BACKOFF);
+        for tries in 1..SEND_MSG_RETRIES {
+            let offset = get_send_backoff_time_offset(tries);
+            assert!((30..=MAX_BACKOFF).contains(&offset));
+        }
+    }
+
+    #[test]
+    fn test_backoff_time_offset() {
+        // Jobs not sending messages keep backing off for weeks.
+        let last = JOB_RETRIES - 1;
+        assert!((1..=2_i64.pow(last - 1) * 60).contains(&get_backoff_time_offset(last)));
+        assert!((0..100).any(|_| get_backoff_time_offset(last) > MAX_BACKOFF));
+    }
+
+    async fn send_job_tries(t: &TestContext, msg_id: MsgId) -> Option<(u32, i64)> {
+        t.sql
+            .query_row_optional(
+                "SELECT tries, desired_timestamp FROM jobs WHERE action=? AND foreign_id=?;",
+                paramsv![Action::SendMsgToSmtp, msg_id],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .await
+            .unwrap()
+    }
+
+    #[async_std::test]
+    async fn test_send_msg_retries() {
+        let t = TestContext::new_alice().await;
+        let chat = t.create_chat_with_contact("bob", "bob@example.net").await;
+
+        let (event_tx, event_rx) = async_std::channel::bounded(100);
+        t.add_event_sink(move |event: Event| {
+            let event_tx = event_tx.clone();
+            async move {
+                match &event.typ {
+                    EventType::MsgFailed { .. } => event_tx.try_send(event.typ.clone()).unwrap(),
+                    EventType::Info(msg) if msg == "checkpoint" => {
+                        event_tx.try_send(event.typ.clone()).unwrap()
+                    }
+                    _ => {}
+                }
+            }
+        })
+        .await;
+        let msg_id = chat::send_text_msg(&t, chat.id, "hi".to_string())
+            .await
+            .unwrap();
+
+        // SMTP is not configured, so every try fails.
+        for tries in 1..SEND_MSG_RETRIES {
+            let job = load_next(&t, Thread::Smtp, &InterruptInfo::new(false, Some(msg_id)))
+                .await
+                .unwrap();
+            let start = time();
+            perform_job(&t, Connection::Smtp(&mut Smtp::new()), job).await;
+
+            let (job_tries, desired_timestamp) = send_job_tries(&t, msg_id).await.unwrap();
+            assert_eq!(job_tries, tries);
+            let max = (60 << (tries - 1)).min(MAX_BACKOFF);
+            assert!(desired_timestamp >= start + max / 2);
+            assert!(desired_timestamp <= time() + max);
+            let msg = Message::load_from_db(&t, msg_id).await.unwrap();
+            assert_eq!(msg.state, MessageState::OutPending);
+
+            // The job is not due, but retried at once if the network may be back.
+            assert!(
+                load_next(&t, Thread::Smtp, &InterruptInfo::new(false, None))
+                    .await
+                    .is_none()
+            );
+            assert!(load_next(&t, Thread::Smtp, &InterruptInfo::new(true, None))
+                .await
+                .is_some());
+        }
+        assert!(events_until_checkpoint(&t, &event_rx).await.is_empty());
+
+        let job = load_next(&t, Thread::Smtp, &InterruptInfo::new(true, None))
+            .await
+            .unwrap();
+        perform_job(&t, Connection::Smtp(&mut Smtp::new()), job).await;
+        assert!(send_job_tries(&t, msg_id).await.is_none());
+        let msg = Message::load_from_db(&t, msg_id).await.unwrap();
+        assert_eq!(msg.state, MessageState::OutFailed);
+        assert_eq!(msg.get_error().unwrap().code, ErrorCode::RetriesExhausted);
+        assert_eq!(
+            events_until_checkpoint(&t, &event_rx).await,
+            vec![EventType::MsgFailed {
+                chat_id: chat.id,
+                msg_id
+            }]
+        );
+
+        // Resending creates a new job.
+        message::resend(&t, &[msg_id]).await.unwrap();
+        let msg = Message::load_from_db(&t, msg_id).await.unwrap();
+        assert_eq!(msg.state, MessageState::OutPending);
+        assert_eq!(msg.get_error(), None);
+        assert_eq!(send_job_tries(&t, msg_id).await.unwrap().0, 0);
+
+        // Resending a pending message resets the tries of its job.
+        let job = load_next(&t, Thread::Smtp, &InterruptInfo::new(false, None))
+            .await
+            .unwrap();
+        perform_job(&t, Connection::Smtp(&mut Smtp::new()), job).await;
+        assert_eq!(send_job_tries(&t, msg_id).await.unwrap().0, 1);
+        message::resend(&t, &[msg_id]).await.unwrap();
+        let (job_tries, desired_timestamp) = send_job_tries(&t, msg_id).await.unwrap();
+        assert_eq!(job_tries, 0);
+        assert!(desired_timestamp <= time());
+
+        // Delivered messages cannot be resent.
+        message::update_msg_state(&t, msg_id, MessageState::OutDelivered).await;
+        assert!(message::resend(&t, &[msg_id]).await.is_err());
+    }
+
     #[async_std::test]
     async fn test_load_next_job_one() {
         let t = TestContext::new().await;
@@ -354,6 +354,95 @@ pub async fn store_self_keypair(
     Ok(())
 }
 
+/// Information about one of the own keypairs, see [`list_keypairs`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyInfo {
+    pub fingerprint: Fingerprint,
+    /// The address the keypair was stored for.
+    pub addr: String,
+    /// Whether this is the key used to encrypt new messages.
+    pub is_default: bool,
+    /// Time the keypair was stored, in seconds since the epoch.
+    pub created: i64,
+}
+
+/// Loads all own keypairs together with their secret keys.
+pub(crate) async fn load_self_keypairs(
+    context: &Context,
+) -> Result<Vec<(KeyInfo, SignedSecretKey)>> {
+    let rows = context
+        .sql
+        .query_map(
+            "SELECT addr, is_default, public_key, private_key, created FROM keypairs ORDER BY id;",
+            paramsv![],
+            |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, i32>(1)?,
+                    row.get::<_, Vec<u8>>(2)?,
+                    row.get::<_, Vec<u8>>(3)?,
+                    row.get::<_, i64>(4)?,
+                ))
+            },
+            |rows| {
+                rows.collect::<std::result::Result<Vec<_>, _>>()
+                    .map_err(Into::into)
+            },
+        )
+        .await?;
+
+    rows.into_iter()
+        .map(|(addr, is_default, public_key, private_key, created)| {
+            let public_key = SignedPublicKey::from_slice(&public_key)?;
+            let info = KeyInfo {
+                fingerprint: DcKey::fingerprint(&public_key),
+                addr,
+                is_default: is_default != 0,
+                created,
+            };
+            Ok((info, SignedSecretKey::from_slice(&private_key)?))
+        })
+        .collect()
+}
+
+/// Lists all own keypairs, the default one as well as the ones only kept to decrypt
+/// old messages.
+///
+/// Only the public keys are loaded.
+pub async fn list_keypairs(context: &Context) -> Result<Vec<KeyInfo>> {
+    let rows = context
+        .sql
+        .query_map(
+            "SELECT addr, is_default, public_key, created FROM keypairs ORDER BY id;",
+            paramsv![],
+            |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, i32>(1)?,
+                    row.get::<_, Vec<u8>>(2)?,
+                    row.get::<_, i64>(3)?,
+                ))
+            },
+            |rows| {
+                rows.collect::<std::result::Result<Vec<_>, _>>()
+                    .map_err(Into::into)
+            },
+        )
+        .await?;
+
+    rows.into_iter()
+        .map(|(addr, is_default, public_key, created)| {
+            let public_key = SignedPublicKey::from_slice(&public_key)?;
+            Ok(KeyInfo {
+                fingerprint: DcKey::fingerprint(&public_key),
+                addr,
+                is_default: is_default != 0,
+                created,
+            })
+        })
+        .collect()
+}
+
 /// A key fingerprint
 #[derive(Clone, Eq, PartialEq, Hash)]
 pub struct Fingerprint(Vec<u8>);
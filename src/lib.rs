@@ -42,15 +42,20 @@ impl<T: rusqlite::ToSql + Send + Sync> ToSql for T {}
 
 mod aheader;
 mod blob;
+pub mod broadcast;
+mod cache;
 pub mod chat;
 pub mod chatlist;
+pub mod chunks;
 pub mod config;
-mod configure;
+pub mod configure;
 pub mod constants;
 pub mod contact;
 pub mod context;
+pub mod download;
 mod e2ee;
 pub mod ephemeral;
+pub mod folder_roles;
 mod imap;
 pub mod imex;
 mod scheduler;
@@ -60,22 +65,36 @@ impl<T: rusqlite::ToSql + Send + Sync> ToSql for T {}
 pub mod key;
 mod keyring;
 pub mod location;
+mod log_file;
 mod login_param;
 pub mod lot;
+pub mod maintenance;
 pub mod message;
 mod mimefactory;
 pub mod mimeparser;
+pub mod network;
+pub mod notification;
 pub mod oauth2;
+pub mod ongoing;
+pub mod outbox;
 mod param;
 pub mod peerstate;
 pub mod pgp;
 pub mod provider;
 pub mod qr;
+pub mod quota;
 pub mod securejoin;
 mod simplify;
 mod smtp;
+mod socks;
+mod state_batch;
+pub mod stats;
 pub mod stock_str;
+pub mod storage_usage;
+mod sync;
 mod token;
+mod vcard;
+mod waveform;
 #[macro_use]
 mod dehtml;
 mod color;
@@ -93,5 +112,5 @@ impl<T: rusqlite::ToSql + Send + Sync> ToSql for T {}
 /// if set IMAP protocol commands and responses will be printed
 pub const DCC_IMAP_DEBUG: &str = "DCC_IMAP_DEBUG";
 
-#[cfg(test)]
-mod test_utils;
+#[cfg(any(test, feature = "internals"))]
+pub mod test_utils;
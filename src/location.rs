@@ -1,8 +1,13 @@
 //! Location handling
 
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+
 use anyhow::{ensure, Error};
 use bitflags::bitflags;
 use quick_xml::events::{BytesEnd, BytesStart, BytesText};
+use rusqlite::types::Value;
+use serde_json::json;
 
 use crate::chat::{self, ChatId};
 use crate::config::Config;
@@ -16,6 +21,13 @@
 use crate::param::Params;
 use crate::stock_str;
 
+/// Raw config key of the number of days after which streamed locations are deleted,
+/// 0 keeps them forever.
+pub(crate) const DELETE_LOCATION_AFTER_DAYS_KEY: &str = "delete_location_after_days";
+
+/// Number of days streamed locations are kept if not configured otherwise.
+const DEFAULT_DELETE_LOCATION_AFTER_DAYS: i64 = 30;
+
 /// Location record
 #[derive(Debug, Clone, Default)]
 pub struct Location {
@@ -407,6 +419,109 @@ pub async fn delete_all(context: &Context) -> Result<(), Error> {
     Ok(())
 }
 
+/// Deletes streamed locations older than configured in `delete_location_after_days`.
+///
+/// Independent locations, i.e. locations attached to messages, are kept.
+/// Returns the number of deleted locations.
+pub(crate) async fn prune(context: &Context) -> Result<usize, Error> {
+    let days = context
+        .sql
+        .get_raw_config_int64(context, DELETE_LOCATION_AFTER_DAYS_KEY)
+        .await
+        .unwrap_or(DEFAULT_DELETE_LOCATION_AFTER_DAYS);
+    if days <= 0 {
+        return Ok(0);
+    }
+
+    let deleted = context
+        .sql
+        .execute(
+            "DELETE FROM locations WHERE independent=0 AND timestamp<?;",
+            paramsv![time() - days * 24 * 60 * 60],
+        )
+        .await?;
+    if deleted > 0 {
+        info!(context, "Pruned {} locations", deleted);
+        context.emit_event(EventType::LocationChanged(None));
+    }
+    Ok(deleted)
+}
+
+/// Exports the locations of a chat in the given time range as GeoJSON.
+///
+/// The result is a `FeatureCollection` with a feature for the track of each contact,
+/// a `LineString` or a `Point` if there is a single location,
+/// and a `Point` feature for each independent location.
+/// The timestamps of the track positions are in the `timestamps` property.
+///
+/// As in [get_range], `timestamp_to` of 0 means now.
+pub async fn export_geojson(
+    context: &Context,
+    chat_id: ChatId,
+    timestamp_from: i64,
+    mut timestamp_to: i64,
+) -> String {
+    if timestamp_to == 0 {
+        timestamp_to = time() + 10;
+    }
+    let mut locations = get_range(context, Some(chat_id), None, timestamp_from, timestamp_to).await;
+    // get_range() returns independent locations regardless of their timestamp.
+    locations.retain(|location| {
+        location.timestamp >= timestamp_from && location.timestamp <= timestamp_to
+    });
+    locations.reverse();
+
+    let mut tracks: BTreeMap<u32, Vec<&Location>> = BTreeMap::new();
+    let mut features = Vec::new();
+    for location in &locations {
+        if location.independent != 0 {
+            features.push(json!({
+                "type": "Feature",
+                "geometry": {
+                    "type": "Point",
+                    "coordinates": [location.longitude, location.latitude],
+                },
+                "properties": {
+                    "contact_id": location.contact_id,
+                    "timestamp": location.timestamp,
+                    "marker": location.marker,
+                },
+            }));
+        } else {
+            tracks
+                .entry(location.contact_id)
+                .or_default()
+                .push(location);
+        }
+    }
+
+    for (contact_id, track) in tracks {
+        let coordinates: Vec<_> = track
+            .iter()
+            .map(|location| json!([location.longitude, location.latitude]))
+            .collect();
+        let timestamps: Vec<_> = track.iter().map(|location| location.timestamp).collect();
+        let geometry = match coordinates.as_slice() {
+            [point] => json!({ "type": "Point", "coordinates": point }),
+            _ => json!({ "type": "LineString", "coordinates": coordinates }),
+        };
+        features.push(json!({
+            "type": "Feature",
+            "geometry": geometry,
+            "properties": {
+                "contact_id": contact_id,
+                "timestamps": timestamps,
+            },
+        }));
+    }
+
+    json!({
+        "type": "FeatureCollection",
+        "features": features,
+    })
+    .to_string()
+}
+
 pub async fn get_kml(context: &Context, chat_id: ChatId) -> Result<(String, u32), Error> {
     let mut last_added_location_id = 0;
 
@@ -541,63 +656,44 @@ pub async fn save(
 ) -> Result<u32, Error> {
     ensure!(!chat_id.is_special(), "Invalid chat id");
 
-    let mut newest_timestamp = 0;
-    let mut newest_location_id = 0;
-
-    for location in locations {
-        let &Location {
-            timestamp,
-            latitude,
-            longitude,
-            accuracy,
-            ..
-        } = location;
-        let (loc_id, ts) = context
-            .sql
-            .with_conn(move |mut conn| {
-                let mut stmt_test = conn
-                    .prepare_cached("SELECT id FROM locations WHERE timestamp=? AND from_id=?")?;
-                let mut stmt_insert = conn.prepare_cached(
-                    "INSERT INTO locations\
+    let rows: Vec<Vec<Value>> = locations
+        .iter()
+        .map(|location| {
+            vec![
+                location.timestamp.into(),
+                contact_id.into(),
+                chat_id.to_u32().into(),
+                location.latitude.into(),
+                location.longitude.into(),
+                location.accuracy.into(),
+                independent.into(),
+            ]
+        })
+        .collect();
+    // Locations which are not independent are skipped if the contact already sent a
+    // location with the same timestamp, including the ones inserted before from `rows`.
+    let ids = context
+        .sql
+        .insert_many_bind(
+            "INSERT INTO locations \
              (timestamp, from_id, chat_id, latitude, longitude, accuracy, independent) \
-             VALUES (?,?,?,?,?,?,?);",
-                )?;
-
-                let exists = stmt_test.exists(paramsv![timestamp, contact_id as i32])?;
+             SELECT ?1, ?2, ?3, ?4, ?5, ?6, ?7 \
+             WHERE ?7 OR NOT EXISTS (SELECT 1 FROM locations WHERE timestamp=?1 AND from_id=?2);",
+            rows,
+        )
+        .await?;
 
-                if independent || !exists {
-                    stmt_insert.execute(paramsv![
-                        timestamp,
-                        contact_id as i32,
-                        chat_id,
-                        latitude,
-                        longitude,
-                        accuracy,
-                        independent,
-                    ])?;
-
-                    if timestamp > newest_timestamp {
-                        // okay to drop, as we use cached prepared statements
-                        drop(stmt_test);
-                        drop(stmt_insert);
-                        newest_timestamp = timestamp;
-                        newest_location_id = crate::sql::get_rowid2(
-                            &mut conn,
-                            "locations",
-                            "timestamp",
-                            timestamp,
-                            "from_id",
-                            contact_id as i32,
-                        )?;
-                    }
-                }
-                Ok((newest_location_id, newest_timestamp))
-            })
-            .await?;
-        newest_timestamp = ts;
-        newest_location_id = loc_id;
+    // The ID of the newest inserted location, the first one if several have the same timestamp.
+    let mut newest_timestamp = 0;
+    let mut newest_location_id = 0;
+    for (location, id) in locations.iter().zip(ids) {
+        if let Some(id) = id {
+            if location.timestamp > newest_timestamp {
+                newest_timestamp = location.timestamp;
+                newest_location_id = u32::try_from(id)?;
+            }
+        }
     }
-
     Ok(newest_location_id)
 }
 
@@ -798,6 +894,202 @@ async fn test_get_message_kml() {
         assert_eq!(locations_ref[0].timestamp, timestamp);
     }
 
+    #[async_std::test]
+    async fn test_save_locations() {
+        let t = TestContext::new_alice().await;
+        let chat = t.create_chat_with_contact("bob", "bob@example.net").await;
+        let contact_id = chat::get_chat_contacts(&t, chat.id)
+            .await
+            .first()
+            .copied()
+            .unwrap();
+        let locations: Vec<Location> = (1..=3)
+            .map(|i| Location {
+                latitude: 50.0 + i as f64,
+                longitude: 8.0,
+                accuracy: 10.0,
+                timestamp: 1_600_000_000 + i,
+                ..Default::default()
+            })
+            .collect();
+        let count_locations = || {
+            t.sql.query_get_value::<i32>(
+                &t,
+                "SELECT COUNT(*) FROM locations WHERE from_id=?;",
+                paramsv![contact_id],
+            )
+        };
+
+        let newest_id = save(&t, chat.id, contact_id, &locations, false)
+            .await
+            .unwrap();
+        assert_eq!(count_locations().await, Some(3));
+        let newest_timestamp: i64 = t
+            .sql
+            .query_get_value(
+                &t,
+                "SELECT timestamp FROM locations WHERE id=?;",
+                paramsv![newest_id],
+            )
+            .await
+            .unwrap();
+        assert_eq!(newest_timestamp, 1_600_000_003);
+
+        // Locations with known timestamps are skipped, unless they are independent.
+        let newest_id = save(&t, chat.id, contact_id, &locations, false)
+            .await
+            .unwrap();
+        assert_eq!(newest_id, 0);
+        assert_eq!(count_locations().await, Some(3));
+        let newest_id = save(&t, chat.id, contact_id, &locations, true)
+            .await
+            .unwrap();
+        assert_ne!(newest_id, 0);
+        assert_eq!(count_locations().await, Some(6));
+        // The ID of the inserted location is returned, not the one with the same timestamp.
+        let independent: bool = t
+            .sql
+            .query_get_value(
+                &t,
+                "SELECT independent FROM locations WHERE id=?;",
+                paramsv![newest_id],
+            )
+            .await
+            .unwrap();
+        assert!(independent);
+    }
+
+    #[async_std::test]
+    async fn test_prune_locations() {
+        let t = TestContext::new_alice().await;
+        let chat = t.create_chat_with_contact("bob", "bob@example.net").await;
+        let day = 24 * 60 * 60;
+        let location = |timestamp| Location {
+            latitude: 50.0,
+            longitude: 8.0,
+            timestamp,
+            ..Default::default()
+        };
+        let old = time() - 40 * day;
+        save(&t, chat.id, DC_CONTACT_ID_SELF, &[location(old)], false)
+            .await
+            .unwrap();
+        save(&t, chat.id, DC_CONTACT_ID_SELF, &[location(old)], true)
+            .await
+            .unwrap();
+        save(
+            &t,
+            chat.id,
+            DC_CONTACT_ID_SELF,
+            &[location(time() - day)],
+            false,
+        )
+        .await
+        .unwrap();
+
+        t.sql
+            .set_raw_config(&t, DELETE_LOCATION_AFTER_DAYS_KEY, Some("0"))
+            .await
+            .unwrap();
+        assert_eq!(prune(&t).await.unwrap(), 0);
+        t.sql
+            .set_raw_config(&t, DELETE_LOCATION_AFTER_DAYS_KEY, Some("60"))
+            .await
+            .unwrap();
+        assert_eq!(prune(&t).await.unwrap(), 0);
+
+        // By default, locations are kept for 30 days.
+        t.sql
+            .set_raw_config(&t, DELETE_LOCATION_AFTER_DAYS_KEY, None)
+            .await
+            .unwrap();
+        crate::sql::housekeeping(&t).await.unwrap();
+        let locations = get_range(&t, Some(chat.id), None, 0, 0).await;
+        assert_eq!(locations.len(), 2);
+        assert!(locations
+            .iter()
+            .any(|location| location.timestamp == old && location.independent == 1));
+        assert!(locations
+            .iter()
+            .any(|location| location.timestamp > old && location.independent == 0));
+    }
+
+    #[async_std::test]
+    async fn test_export_geojson() {
+        let t = TestContext::new_alice().await;
+        let chat = t.create_chat_with_contact("bob", "bob@example.net").await;
+        let bob = chat::get_chat_contacts(&t, chat.id)
+            .await
+            .first()
+            .copied()
+            .unwrap();
+        let claire = crate::contact::Contact::create(&t, "", "claire@example.org")
+            .await
+            .unwrap();
+        let now = time();
+        let trajectory: Vec<Location> = (0..3)
+            .map(|i| Location {
+                latitude: 50.0 + i as f64,
+                longitude: 8.0 - i as f64,
+                timestamp: now - 100 + i,
+                ..Default::default()
+            })
+            .collect();
+        save(&t, chat.id, bob, &trajectory, false).await.unwrap();
+        save(&t, chat.id, claire, &trajectory[1..2], false)
+            .await
+            .unwrap();
+        let poi = Location {
+            latitude: 40.0,
+            longitude: 9.0,
+            timestamp: now - 50,
+            ..Default::default()
+        };
+        save(&t, chat.id, claire, &[poi], true).await.unwrap();
+
+        let geojson: serde_json::Value =
+            serde_json::from_str(&export_geojson(&t, chat.id, now - 1000, 0).await).unwrap();
+        assert_eq!(geojson["type"], "FeatureCollection");
+        let features = geojson["features"].as_array().unwrap();
+        assert_eq!(features.len(), 3);
+        let poi_point = features
+            .iter()
+            .find(|feature| feature["properties"]["timestamp"] == now - 50)
+            .unwrap();
+        assert_eq!(poi_point["geometry"]["coordinates"], json!([9.0, 40.0]));
+
+        let bob_track = features
+            .iter()
+            .find(|feature| feature["properties"]["contact_id"] == bob)
+            .unwrap();
+        assert_eq!(bob_track["type"], "Feature");
+        assert_eq!(bob_track["geometry"]["type"], "LineString");
+        assert_eq!(
+            bob_track["geometry"]["coordinates"],
+            json!([[8.0, 50.0], [7.0, 51.0], [6.0, 52.0]])
+        );
+        assert_eq!(
+            bob_track["properties"]["timestamps"],
+            json!([now - 100, now - 99, now - 98])
+        );
+
+        let claire_point = features
+            .iter()
+            .find(|feature| feature["properties"]["contact_id"] == claire)
+            .unwrap();
+        assert_eq!(claire_point["geometry"]["type"], "Point");
+        assert_eq!(claire_point["geometry"]["coordinates"], json!([7.0, 51.0]));
+        assert_eq!(claire_point["properties"]["timestamps"], json!([now - 99]));
+
+        // Locations outside of the time range are not exported.
+        let geojson: serde_json::Value =
+            serde_json::from_str(&export_geojson(&t, chat.id, now - 99, now - 99).await).unwrap();
+        assert_eq!(geojson["features"].as_array().unwrap().len(), 2);
+        let geojson: serde_json::Value =
+            serde_json::from_str(&export_geojson(&t, chat.id, 1, 2).await).unwrap();
+        assert!(geojson["features"].as_array().unwrap().is_empty());
+    }
+
     #[test]
     fn test_is_marker() {
         assert!(is_marker("f"));
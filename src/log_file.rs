@@ -0,0 +1,431 @@
+//! # Log files
+//!
+//! Besides emitting them as events, a context can write its log messages to files itself,
+//! so that they can be sent to support without relying on the system log, which is often
+//! truncated. See [Context::set_log_file].
+//!
+//! Each line of a log file is a JSON object with the `timestamp`, `level` and `message` of a
+//! log message. The values of [SECRET_CONFIG_KEYS] are replaced by [REDACTED_CONFIG_VALUE].
+//! Once the current file `deltachat.log` would exceed the size limit, it is renamed to
+//! `deltachat.1.log`, `deltachat.1.log` to `deltachat.2.log` and so on, and the oldest file is
+//! deleted.
+//!
+//! Log messages are queued for a separate task writing the files, so emitting an event never
+//! waits for the disk. If the queue is full, log messages are dropped.
+
+use std::io::{Cursor, Write as _};
+use std::sync::{Arc, RwLock};
+
+use anyhow::{bail, ensure, Result};
+use async_std::channel::{self, Receiver, Sender};
+use async_std::fs::{self, OpenOptions};
+use async_std::path::{Path, PathBuf};
+use async_std::prelude::*;
+use async_std::task;
+use serde::{Deserialize, Serialize};
+
+use crate::config::{REDACTED_CONFIG_VALUE, SECRET_CONFIG_KEYS};
+use crate::context::Context;
+use crate::dc_tools::time;
+use crate::events::EventType;
+
+/// Name of the log file currently written.
+const LOG_FILE_NAME: &str = "deltachat.log";
+
+/// Number of log messages waiting to be written before further messages are dropped.
+const LOG_QUEUE_SIZE: usize = 1000;
+
+/// A line of a log file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct LogLine {
+    timestamp: i64,
+    level: String,
+    message: String,
+}
+
+#[derive(Debug)]
+enum LogCommand {
+    Write(LogLine),
+
+    /// Sends a reply once all log messages queued before are written.
+    Flush(Sender<()>),
+}
+
+/// Queue of log messages to be written to the log files of a context.
+#[derive(Debug)]
+pub(crate) struct LogSink {
+    dir: PathBuf,
+    max_files: usize,
+    sender: Sender<LogCommand>,
+    secrets: Arc<RwLock<Vec<String>>>,
+}
+
+impl LogSink {
+    fn new(dir: PathBuf, max_files: usize, max_size: u64, secrets: Vec<String>) -> Self {
+        let (sender, receiver) = channel::bounded(LOG_QUEUE_SIZE);
+        let secrets = Arc::new(RwLock::new(secrets));
+        let writer = LogWriter {
+            dir: dir.clone(),
+            max_files,
+            max_size,
+            secrets: Arc::clone(&secrets),
+            file: None,
+            size: 0,
+        };
+        // The task ends once the sink is dropped and the queue is drained.
+        task::spawn(write_logs(receiver, writer));
+        Self {
+            dir,
+            max_files,
+            sender,
+            secrets,
+        }
+    }
+
+    /// Queues a log event for writing, other events are ignored.
+    pub(crate) fn log(&self, event: &EventType) {
+        let (level, message) = match event {
+            EventType::Info(msg) => ("info", msg),
+            EventType::Warning(msg) => ("warning", msg),
+            EventType::Error(msg) => ("error", msg),
+            EventType::ErrorNetwork(msg) => ("error_network", msg),
+            _ => return,
+        };
+        let line = LogLine {
+            timestamp: time(),
+            level: level.to_string(),
+            message: message.clone(),
+        };
+        // Never block the emitter, rather lose the message.
+        self.sender.try_send(LogCommand::Write(line)).ok();
+    }
+
+    /// Adds a value to be redacted from the log messages written from now on.
+    fn add_secret(&self, secret: &str) {
+        if secret.is_empty() {
+            return;
+        }
+        let mut secrets = self.secrets.write().unwrap();
+        if !secrets.iter().any(|known| known == secret) {
+            secrets.push(secret.to_string());
+        }
+    }
+}
+
+/// Returns the path of the log file with the given index, 0 is the current file.
+fn log_file_path(dir: &Path, index: usize) -> PathBuf {
+    if index == 0 {
+        dir.join(LOG_FILE_NAME)
+    } else {
+        dir.join(format!("deltachat.{}.log", index))
+    }
+}
+
+fn redact(message: &str, secrets: &[String]) -> String {
+    secrets.iter().fold(message.to_string(), |message, secret| {
+        message.replace(secret.as_str(), REDACTED_CONFIG_VALUE)
+    })
+}
+
+#[derive(Debug)]
+struct LogWriter {
+    dir: PathBuf,
+    max_files: usize,
+    max_size: u64,
+    secrets: Arc<RwLock<Vec<String>>>,
+    file: Option<fs::File>,
+    size: u64,
+}
+
+impl LogWriter {
+    async fn write(&mut self, mut line: LogLine) -> Result<()> {
+        line.message = redact(&line.message, &self.secrets.read().unwrap());
+        let mut data = serde_json::to_vec(&line)?;
+        data.push(b'\n');
+
+        if self.file.is_none() {
+            fs::create_dir_all(&self.dir).await?;
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(log_file_path(&self.dir, 0))
+                .await?;
+            self.size = file.metadata().await?.len();
+            self.file = Some(file);
+        }
+        if self.size > 0 && self.size + data.len() as u64 > self.max_size {
+            self.rotate().await?;
+            self.file = Some(
+                OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(log_file_path(&self.dir, 0))
+                    .await?,
+            );
+        }
+
+        if let Some(file) = self.file.as_mut() {
+            file.write_all(&data).await?;
+            self.size += data.len() as u64;
+        }
+        Ok(())
+    }
+
+    async fn flush(&mut self) -> Result<()> {
+        if let Some(file) = self.file.as_mut() {
+            file.flush().await?;
+        }
+        Ok(())
+    }
+
+    /// Renames the log files to make room for a new current file.
+    async fn rotate(&mut self) -> Result<()> {
+        if let Some(mut file) = self.file.take() {
+            file.flush().await?;
+        }
+        let oldest = log_file_path(&self.dir, self.max_files - 1);
+        if oldest.exists().await {
+            fs::remove_file(&oldest).await?;
+        }
+        for index in (0..self.max_files - 1).rev() {
+            let path = log_file_path(&self.dir, index);
+            if path.exists().await {
+                fs::rename(&path, log_file_path(&self.dir, index + 1)).await?;
+            }
+        }
+        self.size = 0;
+        Ok(())
+    }
+}
+
+async fn write_logs(receiver: Receiver<LogCommand>, mut writer: LogWriter) {
+    // Errors are not logged, this would only add log messages that cannot be written.
+    while let Ok(command) = receiver.recv().await {
+        match command {
+            LogCommand::Write(line) => {
+                writer.write(line).await.ok();
+                if receiver.is_empty() {
+                    writer.flush().await.ok();
+                }
+            }
+            LogCommand::Flush(reply) => {
+                writer.flush().await.ok();
+                reply.send(()).await.ok();
+            }
+        }
+    }
+    writer.flush().await.ok();
+}
+
+impl Context {
+    /// Writes the log messages of this context to files in `dir` from now on,
+    /// `None` stops writing log files.
+    ///
+    /// At most `max_files` files are kept, each of them is at most `max_size` bytes
+    /// unless a single log message is larger.
+    pub async fn set_log_file(
+        &self,
+        dir: Option<PathBuf>,
+        max_files: usize,
+        max_size: u64,
+    ) -> Result<()> {
+        let sink = match dir {
+            Some(dir) => {
+                ensure!(max_files > 0, "At least one log file is required");
+                ensure!(max_size > 0, "Log files must not be empty");
+                let mut secrets = Vec::new();
+                for key in &SECRET_CONFIG_KEYS {
+                    if let Some(value) = self.sql.get_raw_config(self, key).await {
+                        if !value.is_empty() {
+                            secrets.push(value);
+                        }
+                    }
+                }
+                Some(LogSink::new(dir, max_files, max_size, secrets))
+            }
+            None => None,
+        };
+        *self.log_sink.write().unwrap() = sink;
+        Ok(())
+    }
+
+    /// Returns a zip archive of the log files set by [Context::set_log_file],
+    /// containing the log messages since the given timestamp.
+    pub async fn collect_logs(&self, since: i64) -> Result<Vec<u8>> {
+        let (dir, max_files, sender) = match &*self.log_sink.read().unwrap() {
+            Some(sink) => (sink.dir.clone(), sink.max_files, sink.sender.clone()),
+            None => bail!("No log file set"),
+        };
+
+        // Queued log messages are written first.
+        let (reply_sender, reply_receiver) = channel::bounded(1);
+        if sender.send(LogCommand::Flush(reply_sender)).await.is_ok() {
+            reply_receiver.recv().await.ok();
+        }
+
+        let mut zip = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        // The oldest file comes first.
+        for index in (0..max_files).rev() {
+            let path = log_file_path(&dir, index);
+            let content = match fs::read_to_string(&path).await {
+                Ok(content) => content,
+                Err(_) => continue,
+            };
+            let lines: Vec<&str> = content
+                .lines()
+                .filter(|line| {
+                    serde_json::from_str::<LogLine>(line)
+                        .map(|line| line.timestamp >= since)
+                        .unwrap_or_default()
+                })
+                .collect();
+            if lines.is_empty() {
+                continue;
+            }
+            let name = path
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .to_string();
+            zip.start_file(name, zip::write::FileOptions::default())?;
+            for line in lines {
+                zip.write_all(line.as_bytes())?;
+                zip.write_all(b"\n")?;
+            }
+        }
+        Ok(zip.finish()?.into_inner())
+    }
+
+    /// Redacts the given secret from the log files written from now on.
+    pub(crate) fn add_log_secret(&self, secret: &str) {
+        if let Some(sink) = &*self.log_sink.read().unwrap() {
+            sink.add_secret(secret);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Read;
+
+    use crate::config::Config;
+    use crate::test_utils::TestContext;
+
+    async fn read_lines(path: &Path) -> Vec<LogLine> {
+        fs::read_to_string(path)
+            .await
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+
+    async fn flush(t: &TestContext) {
+        // Flushing is a side effect of collecting the logs.
+        t.collect_logs(0).await.unwrap();
+    }
+
+    #[async_std::test]
+    async fn test_log_file_rotation() {
+        let t = TestContext::new().await;
+        let dir = PathBuf::from(t.dir.path().join("logs"));
+        t.set_log_file(Some(dir.clone()), 3, 500).await.unwrap();
+
+        for i in 0..30 {
+            info!(t, "log message number {:02}", i);
+        }
+        flush(&t).await;
+
+        let current = log_file_path(&dir, 0);
+        assert!(current.exists().await);
+        assert!(log_file_path(&dir, 1).exists().await);
+        assert!(log_file_path(&dir, 2).exists().await);
+        assert!(!log_file_path(&dir, 3).exists().await);
+        for index in 0..3 {
+            let path = log_file_path(&dir, index);
+            assert!(fs::metadata(&path).await.unwrap().len() <= 500);
+        }
+
+        // The newest messages are in the current file, the oldest were deleted.
+        let lines = read_lines(&current).await;
+        let last = lines.last().unwrap();
+        assert_eq!(last.level, "info");
+        assert!(last.message.ends_with("log message number 29"));
+        let oldest = read_lines(&log_file_path(&dir, 2)).await;
+        assert!(!oldest
+            .iter()
+            .any(|line| line.message.ends_with("log message number 00")));
+    }
+
+    #[async_std::test]
+    async fn test_log_file_redaction() {
+        let t = TestContext::new().await;
+        t.set_config(Config::MailPw, Some("imap-secret"))
+            .await
+            .unwrap();
+        let dir = PathBuf::from(t.dir.path().join("logs"));
+        t.set_log_file(Some(dir.clone()), 2, 100_000).await.unwrap();
+        t.set_config(Config::SendPw, Some("smtp-secret"))
+            .await
+            .unwrap();
+
+        error!(t, "IMAP login with password imap-secret failed");
+        warn!(t, "SMTP login with password smtp-secret failed");
+        flush(&t).await;
+
+        let content = fs::read_to_string(log_file_path(&dir, 0)).await.unwrap();
+        assert!(!content.contains("imap-secret"));
+        assert!(!content.contains("smtp-secret"));
+        let lines = read_lines(&log_file_path(&dir, 0)).await;
+        assert!(lines.iter().any(|line| line.level == "error"
+            && line
+                .message
+                .ends_with("IMAP login with password *** failed")));
+        assert!(lines.iter().any(|line| line.level == "warning"
+            && line
+                .message
+                .ends_with("SMTP login with password *** failed")));
+    }
+
+    #[async_std::test]
+    async fn test_collect_logs() {
+        let t = TestContext::new().await;
+        assert!(t.collect_logs(0).await.is_err());
+
+        let dir = PathBuf::from(t.dir.path().join("logs"));
+        t.set_log_file(Some(dir.clone()), 3, 300).await.unwrap();
+        for i in 0..10 {
+            info!(t, "collected message {}", i);
+        }
+        let zip = t.collect_logs(0).await.unwrap();
+
+        let mut archive = zip::ZipArchive::new(Cursor::new(zip)).unwrap();
+        let mut content = String::new();
+        let mut names = Vec::new();
+        for i in 0..archive.len() {
+            let mut file = archive.by_index(i).unwrap();
+            names.push(file.name().to_string());
+            file.read_to_string(&mut content).unwrap();
+        }
+        assert_eq!(names.last().unwrap(), LOG_FILE_NAME);
+        assert!(content.contains("collected message 9"));
+
+        // Messages are collected from the oldest to the newest.
+        let positions: Vec<usize> = (7..10)
+            .map(|i| content.find(&format!("collected message {}", i)).unwrap())
+            .collect();
+        let mut sorted = positions.clone();
+        sorted.sort_unstable();
+        assert_eq!(positions, sorted);
+
+        // Nothing is newer than the future.
+        let zip = t.collect_logs(time() + 3600).await.unwrap();
+        let archive = zip::ZipArchive::new(Cursor::new(zip)).unwrap();
+        assert_eq!(archive.len(), 0);
+
+        t.set_log_file(None, 0, 0).await.unwrap();
+        assert!(t.collect_logs(0).await.is_err());
+    }
+}
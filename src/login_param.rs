@@ -3,6 +3,7 @@
 use std::borrow::Cow;
 use std::fmt;
 
+use crate::config::REDACTED_CONFIG_VALUE;
 use crate::provider::{get_provider_by_id, Provider};
 use crate::{context::Context, provider::Socket};
 
@@ -237,7 +238,7 @@ pub async fn save_to_database(
 impl fmt::Display for LoginParam {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let unset = "0";
-        let pw = "***";
+        let pw = REDACTED_CONFIG_VALUE;
 
         let flags_readable = get_readable_flags(self.server_flags);
 
@@ -1,7 +1,5 @@
 use deltachat_derive::{FromSql, ToSql};
 
-use crate::key::Fingerprint;
-
 /// An object containing a set of values.
 /// The meaning of the values is defined by the function returning the object.
 /// Lot objects are created
@@ -16,9 +14,6 @@ pub struct Lot {
     pub(crate) timestamp: i64,
     pub(crate) state: LotState,
     pub(crate) id: u32,
-    pub(crate) fingerprint: Option<Fingerprint>,
-    pub(crate) invitenumber: Option<String>,
-    pub(crate) auth: Option<String>,
 }
 
 #[repr(u8)]
@@ -95,6 +90,9 @@ pub enum LotState {
     /// text1=domain
     QrAccount = 250,
 
+    /// text1=domain, text2=URL of the backup
+    QrBackup = 251,
+
     /// text1=domain, text2=instance pattern
     QrWebrtcInstance = 260,
 
@@ -117,6 +115,7 @@ pub enum LotState {
     MsgOutPreparing = 18,
     MsgOutDraft = 19,
     MsgOutPending = 20,
+    MsgOutScheduled = 21,
     MsgOutFailed = 24,
     MsgOutDelivered = 26,
     MsgOutMdnRcvd = 28,
@@ -0,0 +1,425 @@
+//! # Periodic maintenance.
+//!
+//! [Context::perform_maintenance] runs the database and blob directory cleanup in small
+//! steps within a time budget, so that it can be called from short background tasks,
+//! e.g. on mobile platforms.  Progress is persisted, the next call continues where the
+//! previous one stopped.
+
+use std::collections::HashSet;
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use async_std::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::context::Context;
+use crate::ephemeral;
+use crate::job;
+use crate::ongoing::OperationKind;
+use crate::sql::{self, Warnings};
+use crate::token;
+
+/// Raw config key of the persisted [Cursor].
+const CURSOR_KEY: &str = "maintenance_cursor";
+
+/// Number of tombstones removed at once.
+const TOMBSTONES_PER_BATCH: usize = 100;
+
+/// Number of blob directory entries checked at once.
+const BLOBS_PER_BATCH: usize = 100;
+
+/// Number of messages deleted or purged at once by each deletion of the
+/// [MaintenanceStep::Retention] step.
+const RETENTION_PER_BATCH: usize = 500;
+
+/// Steps of a maintenance cycle, in the order they are performed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MaintenanceStep {
+    /// Checkpoints the database write-ahead log.
+    Checkpoint,
+
+    /// Removes tombstones of messages that are not on the server anymore.
+    Tombstones,
+
+    /// Removes tokens of deleted chats and jobs of deleted messages.
+    TokensAndJobs,
+
+    /// Deletes unreferenced files from the blob directory.
+    Blobs,
+
+    /// Deletes messages according to the ephemeral timers and `delete_device_after`
+    /// and purges trashed messages after their grace period.
+    Retention,
+}
+
+impl Default for MaintenanceStep {
+    fn default() -> Self {
+        MaintenanceStep::Checkpoint
+    }
+}
+
+impl MaintenanceStep {
+    /// Returns the step after this one, `None` for the last step of the cycle.
+    fn next(self) -> Option<Self> {
+        match self {
+            MaintenanceStep::Checkpoint => Some(MaintenanceStep::Tombstones),
+            MaintenanceStep::Tombstones => Some(MaintenanceStep::TokensAndJobs),
+            MaintenanceStep::TokensAndJobs => Some(MaintenanceStep::Blobs),
+            MaintenanceStep::Blobs => Some(MaintenanceStep::Retention),
+            MaintenanceStep::Retention => None,
+        }
+    }
+}
+
+/// Progress of the current maintenance cycle, persisted between calls.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct Cursor {
+    step: MaintenanceStep,
+
+    /// Name of the last checked blob directory entry of the [MaintenanceStep::Blobs] step.
+    blob_cursor: Option<String>,
+}
+
+/// Blob directory entries left to check and the files in use,
+/// determined once per [Context::perform_maintenance] call.
+#[derive(Debug)]
+struct BlobScan {
+    /// Unchecked entries after the cursor, in descending order of their names.
+    names: Vec<String>,
+
+    files_in_use: HashSet<String>,
+}
+
+impl BlobScan {
+    async fn load(context: &Context, cursor: Option<&str>) -> Result<Self> {
+        let mut names = Vec::new();
+        let mut dir_handle = async_std::fs::read_dir(context.get_blobdir()).await?;
+        while let Some(entry) = dir_handle.next().await {
+            let name = entry?.file_name().to_string_lossy().into_owned();
+            if cursor.map_or(true, |last| name.as_str() > last) {
+                names.push(name);
+            }
+        }
+        names.sort_unstable_by(|a, b| b.cmp(a));
+        let files_in_use = sql::get_files_in_use(context).await?;
+        Ok(BlobScan {
+            names,
+            files_in_use,
+        })
+    }
+}
+
+/// Result of [Context::perform_maintenance].
+#[derive(Debug, Default)]
+pub struct MaintenanceReport {
+    /// Steps finished by this call, in the order they were finished.
+    pub completed_steps: Vec<MaintenanceStep>,
+
+    /// Step the next call starts with or continues.
+    pub next_step: MaintenanceStep,
+
+    /// The last step of the cycle was finished, the next call starts a new cycle.
+    pub cycle_completed: bool,
+
+    /// Number of removed message tombstones.
+    pub deleted_tombstones: usize,
+
+    /// Number of removed tokens of deleted chats.
+    pub deleted_tokens: usize,
+
+    /// Number of removed jobs of deleted messages.
+    pub deleted_jobs: usize,
+
+    /// Number of unreferenced files deleted from the blob directory.
+    pub deleted_files: usize,
+
+    /// Steps which failed, they are skipped until the next cycle.
+    pub warnings: Warnings,
+}
+
+impl Context {
+    /// Performs maintenance steps until `budget` is used up or the cycle is completed.
+    ///
+    /// At least one unit of work is done, even if `budget` is zero, so repeated calls always
+    /// make progress.  Steps that take long, like checking the blob directory, are split
+    /// into batches.  The progress is stored in the database and the next call continues
+    /// from there.
+    ///
+    /// Failing steps are reported as warnings and do not stop the cycle.
+    /// Canceling the maintenance with [Context::cancel_operation] stops it after the current
+    /// unit of work.
+    pub async fn perform_maintenance(&self, budget: Duration) -> Result<MaintenanceReport> {
+        let operation = self.start_operation(OperationKind::Maintenance, true)?;
+        let start = Instant::now();
+        let mut report = MaintenanceReport::default();
+        let mut blob_scan = None;
+        let mut cursor = load_cursor(self).await.unwrap_or_else(|err| {
+            warn!(self, "Invalid maintenance cursor, starting over: {}", err);
+            Cursor::default()
+        });
+
+        loop {
+            let step = cursor.step;
+            let finished = match perform_step(self, &mut cursor, &mut blob_scan, &mut report).await
+            {
+                Ok(finished) => finished,
+                Err(err) => {
+                    report
+                        .warnings
+                        .push(format!("Maintenance step {:?} failed: {}", step, err));
+                    true
+                }
+            };
+            if finished {
+                report.completed_steps.push(step);
+                cursor.blob_cursor = None;
+                match step.next() {
+                    Some(next) => cursor.step = next,
+                    None => {
+                        cursor.step = MaintenanceStep::default();
+                        report.cycle_completed = true;
+                    }
+                }
+            }
+            if report.cycle_completed || start.elapsed() >= budget || operation.is_canceled() {
+                break;
+            }
+        }
+
+        report.next_step = cursor.step;
+        self.sql
+            .set_raw_config(self, CURSOR_KEY, Some(&serde_json::to_string(&cursor)?))
+            .await?;
+        info!(
+            self,
+            "Maintenance finished {:?} in {:?}, continuing with {:?}.",
+            report.completed_steps,
+            start.elapsed(),
+            report.next_step
+        );
+        Ok(report)
+    }
+}
+
+async fn load_cursor(context: &Context) -> Result<Cursor> {
+    match context.sql.get_raw_config(context, CURSOR_KEY).await {
+        Some(cursor) => Ok(serde_json::from_str(&cursor)?),
+        None => Ok(Cursor::default()),
+    }
+}
+
+/// Performs one unit of work of the current step.
+///
+/// Returns true if the step is finished.
+async fn perform_step(
+    context: &Context,
+    cursor: &mut Cursor,
+    blob_scan: &mut Option<BlobScan>,
+    report: &mut MaintenanceReport,
+) -> Result<bool> {
+    match cursor.step {
+        MaintenanceStep::Checkpoint => {
+            context.sql.checkpoint().await?;
+            Ok(true)
+        }
+        MaintenanceStep::Tombstones => {
+            let deleted = sql::prune_tombstones_batch(context, TOMBSTONES_PER_BATCH).await?;
+            report.deleted_tombstones += deleted;
+            Ok(deleted < TOMBSTONES_PER_BATCH)
+        }
+        MaintenanceStep::TokensAndJobs => {
+            report.deleted_tokens += token::prune(context).await?;
+            report.deleted_jobs += job::prune(context).await?;
+            Ok(true)
+        }
+        MaintenanceStep::Blobs => {
+            if !context.check_blobdir_writable().await {
+                info!(
+                    context,
+                    "Maintenance: Blob directory is read-only, not deleting files."
+                );
+                return Ok(true);
+            }
+            perform_blobs_batch(context, cursor, blob_scan, report).await
+        }
+        MaintenanceStep::Retention => {
+            let expired = ephemeral::delete_expired_messages_limited(context, RETENTION_PER_BATCH)
+                .await?
+                .messages;
+            let (device_expired, _) =
+                ephemeral::delete_device_expired_messages_limited(context, RETENTION_PER_BATCH)
+                    .await?;
+            let purged = sql::purge_trash_limited(context, RETENTION_PER_BATCH).await?;
+            Ok(expired < RETENTION_PER_BATCH
+                && device_expired < RETENTION_PER_BATCH
+                && purged < RETENTION_PER_BATCH)
+        }
+    }
+}
+
+/// Deletes the unreferenced files among the next [BLOBS_PER_BATCH] blob directory entries.
+///
+/// Entries are checked in the order of their names, so the cursor stays valid when files
+/// are added or removed in between.  The directory is listed and the files in use are
+/// determined only by the first batch of a call, recently created files are not deleted
+/// anyway.
+async fn perform_blobs_batch(
+    context: &Context,
+    cursor: &mut Cursor,
+    blob_scan: &mut Option<BlobScan>,
+    report: &mut MaintenanceReport,
+) -> Result<bool> {
+    let mut scan = match blob_scan.take() {
+        Some(scan) => scan,
+        None => BlobScan::load(context, cursor.blob_cursor.as_deref()).await?,
+    };
+    let batch = scan
+        .names
+        .split_off(scan.names.len().saturating_sub(BLOBS_PER_BATCH));
+
+    let mut unreferenced_count = 0;
+    for name in &batch {
+        let path = context.get_blobdir().join(name);
+        if sql::delete_blob_if_unreferenced(
+            context,
+            &scan.files_in_use,
+            &path,
+            name,
+            &mut unreferenced_count,
+        )
+        .await
+        {
+            report.deleted_files += 1;
+        }
+    }
+    if let Some(last) = batch.into_iter().next() {
+        cursor.blob_cursor = Some(last);
+    }
+    if scan.names.is_empty() {
+        Ok(true)
+    } else {
+        *blob_scan = Some(scan);
+        Ok(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::constants::DC_CHAT_ID_TRASH;
+    use crate::job::Action;
+    use crate::test_utils::TestContext;
+
+    #[async_std::test]
+    async fn test_maintenance_resumes() -> Result<()> {
+        let t = TestContext::new_alice().await;
+
+        let report = t.perform_maintenance(Duration::from_secs(0)).await?;
+        assert_eq!(report.completed_steps, vec![MaintenanceStep::Checkpoint]);
+        assert_eq!(report.next_step, MaintenanceStep::Tombstones);
+        assert!(!report.cycle_completed);
+        assert_eq!(load_cursor(&t).await?.step, MaintenanceStep::Tombstones);
+
+        let mut calls = 0;
+        loop {
+            calls += 1;
+            let report = t.perform_maintenance(Duration::from_secs(0)).await?;
+            assert!(report.warnings.is_empty());
+            if report.cycle_completed {
+                break;
+            }
+            assert!(calls < 10);
+        }
+        assert_eq!(load_cursor(&t).await?, Cursor::default());
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn test_maintenance_prunes() -> Result<()> {
+        let t = TestContext::new_alice().await;
+        for _ in 0..250 {
+            t.sql
+                .execute(
+                    "INSERT INTO msgs (chat_id, server_uid) VALUES (?, 0);",
+                    paramsv![DC_CHAT_ID_TRASH],
+                )
+                .await?;
+        }
+        t.sql
+            .execute(
+                "INSERT INTO tokens (namespc, foreign_id, token, timestamp) VALUES (?, ?, ?, ?);",
+                paramsv![token::Namespace::Auth, 1234, "token", 0],
+            )
+            .await?;
+        t.sql
+            .execute(
+                "INSERT INTO jobs (added_timestamp, thread, action, foreign_id, param, desired_timestamp) \
+                 VALUES (0, 0, ?, 1234, '', 0);",
+                paramsv![Action::MoveMsg],
+            )
+            .await?;
+        let blob = t.get_blobdir().join("unreferenced.txt");
+        async_std::fs::write(&blob, b"data").await?;
+
+        // Checkpoint, then one batch of tombstones per call.
+        t.perform_maintenance(Duration::from_secs(0)).await?;
+        let report = t.perform_maintenance(Duration::from_secs(0)).await?;
+        assert!(report.completed_steps.is_empty());
+        assert_eq!(report.deleted_tombstones, TOMBSTONES_PER_BATCH);
+        assert_eq!(report.next_step, MaintenanceStep::Tombstones);
+
+        let report = t.perform_maintenance(Duration::from_secs(60)).await?;
+        assert!(report.cycle_completed);
+        assert_eq!(report.deleted_tombstones, 150);
+        assert_eq!(report.deleted_tokens, 1);
+        assert_eq!(report.deleted_jobs, 1);
+        // Recently created files are kept.
+        assert_eq!(report.deleted_files, 0);
+        assert!(blob.exists().await);
+        Ok(())
+    }
+
+    async fn count_unpurged(t: &TestContext) -> Result<Option<isize>> {
+        let count = t
+            .sql
+            .query_get_value_result(
+                "SELECT COUNT(*) FROM msgs WHERE chat_id=? AND param!='';",
+                paramsv![DC_CHAT_ID_TRASH],
+            )
+            .await?;
+        Ok(count)
+    }
+
+    #[async_std::test]
+    async fn test_maintenance_retention_batches() -> Result<()> {
+        let t = TestContext::new_alice().await;
+        for _ in 0..RETENTION_PER_BATCH + 10 {
+            t.sql
+                .execute(
+                    "INSERT INTO msgs (chat_id, server_uid, deleted_timestamp, param) \
+                     VALUES (?, 1, 1, 'a=b');",
+                    paramsv![DC_CHAT_ID_TRASH],
+                )
+                .await?;
+        }
+        let cursor = Cursor {
+            step: MaintenanceStep::Retention,
+            blob_cursor: None,
+        };
+        t.sql
+            .set_raw_config(&t, CURSOR_KEY, Some(&serde_json::to_string(&cursor)?))
+            .await?;
+
+        let report = t.perform_maintenance(Duration::from_secs(0)).await?;
+        assert!(report.completed_steps.is_empty());
+        assert_eq!(report.next_step, MaintenanceStep::Retention);
+        assert_eq!(count_unpurged(&t).await?, Some(10));
+
+        let report = t.perform_maintenance(Duration::from_secs(0)).await?;
+        assert_eq!(report.completed_steps, vec![MaintenanceStep::Retention]);
+        assert!(report.cycle_completed);
+        assert_eq!(count_unpurged(&t).await?, Some(0));
+        Ok(())
+    }
+}
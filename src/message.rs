@@ -1,17 +1,21 @@
 //! # Messages and their identifiers
 
-use anyhow::{ensure, Error};
+use std::convert::TryFrom;
+
+use anyhow::{ensure, format_err, Error};
 use async_std::path::{Path, PathBuf};
 use deltachat_derive::{FromSql, ToSql};
 use itertools::Itertools;
+use num_traits::FromPrimitive;
 use serde::{Deserialize, Serialize};
 
+use crate::blob::BlobObject;
 use crate::chat::{self, Chat, ChatId};
 use crate::config::Config;
 use crate::constants::{
     Blocked, Chattype, VideochatType, Viewtype, DC_CHAT_ID_DEADDROP, DC_CHAT_ID_TRASH,
-    DC_CONTACT_ID_INFO, DC_CONTACT_ID_LAST_SPECIAL, DC_CONTACT_ID_SELF, DC_MAX_GET_INFO_LEN,
-    DC_MAX_GET_TEXT_LEN, DC_MSG_ID_LAST_SPECIAL,
+    DC_CONTACT_ID_INFO, DC_CONTACT_ID_LAST_SPECIAL, DC_CONTACT_ID_SELF, DC_ELLIPSE,
+    DC_MAX_GET_INFO_LEN, DC_MAX_GET_TEXT_LEN, DC_MSG_ID_LAST_SPECIAL,
 };
 use crate::contact::{Contact, Origin};
 use crate::context::Context;
@@ -19,14 +23,20 @@
     dc_get_filebytes, dc_get_filemeta, dc_gm2local_offset, dc_read_file, dc_timestamp_to_str,
     dc_truncate, time,
 };
+use crate::download::DownloadState;
 use crate::ephemeral::Timer as EphemeralTimer;
 use crate::events::EventType;
+use crate::imap::cleanup;
 use crate::job::{self, Action};
+use crate::key::{self, Fingerprint};
 use crate::log::LogExt;
 use crate::lot::{Lot, LotState, Meaning};
-use crate::mimeparser::{FailureReport, SystemMessage};
+use crate::mimeparser::{parse_message_ids, FailureReport, SystemMessage};
 use crate::param::{Param, Params};
+use crate::peerstate::{self, ChangedKey, Peerstate, PeerstateChange};
 use crate::pgp::split_armored_data;
+use crate::state_batch;
+use crate::stats;
 use crate::stock_str;
 use std::collections::BTreeMap;
 
@@ -152,22 +162,54 @@ async fn needs_move_to_mvbox(self, context: &Context, msg: &Message) -> Result<b
     /// We keep some infos to
     /// 1. not download the same message again
     /// 2. be able to delete the message on the server if we want to
+    ///
+    /// If [Config::TrashGracePeriod] is set, the text is only deleted after the grace period
+    /// and the message can be restored until then, see [restore_from_trash].
     pub async fn trash(self, context: &Context) -> crate::sql::Result<()> {
         let chat_id = ChatId::new(DC_CHAT_ID_TRASH);
+        let now = time();
+        if context.get_config_i64(Config::TrashGracePeriod).await > 0 {
+            let (orig_chat_id, param, chat_created): (ChatId, String, i64) = context
+                .sql
+                .query_row(
+                    "SELECT m.chat_id, m.param, IFNULL(c.created_timestamp, 0)
+                       FROM msgs m LEFT JOIN chats c ON c.id=m.chat_id
+                      WHERE m.id=?",
+                    paramsv![self],
+                    |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+                )
+                .await?;
+            if orig_chat_id.is_trash() {
+                return Ok(());
+            }
+            let mut param: Params = param.parse().unwrap_or_default();
+            param.set_int(Param::TrashedChatId, orig_chat_id.to_u32() as i32);
+            param.set_i64(Param::TrashedChatCreated, chat_created);
+            context
+                .sql
+                .execute(
+                    "UPDATE msgs SET chat_id=?, param=?, deleted_timestamp=? WHERE id=?",
+                    paramsv![chat_id, param.to_string(), now, self],
+                )
+                .await?;
+            return Ok(());
+        }
+
         context
             .sql
             .execute(
-                // If you change which information is removed here, also change delete_expired_messages() and
-                // which information dc_receive_imf::add_parts() still adds to the db if the chat_id is TRASH
-                "UPDATE msgs SET chat_id=?, txt='', subject='', txt_raw='', mime_headers='', from_id=0, to_id=0, param='' WHERE id=?",
-                paramsv![chat_id, self],
+                // If you change which information is removed here, also change delete_expired_messages(), ChatId::delete(),
+                // sql::purge_trash() and which information dc_receive_imf::add_parts() still adds to the db if the chat_id is TRASH
+                "UPDATE msgs SET chat_id=?, txt='', txt_folded='', subject='', txt_raw='', mime_headers='', from_id=0, to_id=0, param='', deleted_timestamp=? WHERE id=?",
+                paramsv![chat_id, now, self],
             )
             .await?;
 
         Ok(())
     }
 
-    /// Deletes a message and corresponding MDNs from the database.
+    /// Deletes a message, corresponding MDNs, broadcast states, reactions and status updates
+    /// from the database.
     pub async fn delete_from_db(self, context: &Context) -> crate::sql::Result<()> {
         // We don't use transactions yet, so remove MDNs first to make
         // sure they are not left while the message is deleted.
@@ -175,6 +217,25 @@ pub async fn delete_from_db(self, context: &Context) -> crate::sql::Result<()> {
             .sql
             .execute("DELETE FROM msgs_mdns WHERE msg_id=?;", paramsv![self])
             .await?;
+        context
+            .sql
+            .execute("DELETE FROM msgs_broadcast WHERE msg_id=?;", paramsv![self])
+            .await?;
+        context
+            .sql
+            .execute("DELETE FROM reactions WHERE msg_id=?;", paramsv![self])
+            .await?;
+        context
+            .sql
+            .execute(
+                "DELETE FROM msgs_status_updates WHERE msg_id=?;",
+                paramsv![self],
+            )
+            .await?;
+        context
+            .sql
+            .execute("DELETE FROM msgs_chunks WHERE msg_id=?;", paramsv![self])
+            .await?;
         context
             .sql
             .execute("DELETE FROM msgs WHERE id=?;", paramsv![self])
@@ -259,6 +320,64 @@ fn column_result(value: rusqlite::types::ValueRef) -> rusqlite::types::FromSqlRe
 #[error("Invalid Message ID.")]
 pub struct InvalidMsgId;
 
+/// Classification of an error associated with a message.
+#[derive(Debug, Display, Copy, Clone, PartialEq, Eq, FromPrimitive, ToPrimitive)]
+#[repr(u32)]
+pub enum ErrorCode {
+    /// The error was not classified.
+    Unknown = 0,
+
+    /// The server could not be reached, sending is retried later.
+    Network = 10,
+
+    /// The SMTP server rejected the message temporarily, sending is retried later.
+    SmtpTransient = 20,
+
+    /// The SMTP server rejected the message permanently.
+    SmtpPermanent = 30,
+
+    /// A non-delivery notification was received for the message.
+    Ndn = 40,
+
+    /// The message must be end-to-end encrypted, but encryption is not available.
+    E2eeUnavailable = 50,
+
+    /// The message could not be prepared for sending.
+    InvalidMessage = 60,
+
+    /// Sending failed too often with transient errors and was given up.
+    RetriesExhausted = 70,
+
+    /// Sending was cancelled by the user.
+    Cancelled = 80,
+}
+
+impl ErrorCode {
+    /// Returns true if the failed operation is retried later.
+    pub fn is_transient(self) -> bool {
+        matches!(self, ErrorCode::Network | ErrorCode::SmtpTransient)
+    }
+}
+
+/// An error associated with a message, see [`Message::get_error`].
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("{text}")]
+pub struct MsgError {
+    /// Classification of the error.
+    pub code: ErrorCode,
+    /// Error text which can be shown to the user.
+    pub text: String,
+}
+
+impl MsgError {
+    pub fn new(code: ErrorCode, text: impl Into<String>) -> Self {
+        Self {
+            code,
+            text: text.into(),
+        }
+    }
+}
+
 #[derive(
     Debug,
     Copy,
@@ -311,6 +430,7 @@ pub struct Message {
     pub(crate) subject: String,
     pub(crate) rfc724_mid: String,
     pub(crate) in_reply_to: Option<String>,
+    pub(crate) quoted_msg_id: MsgId,
     pub(crate) server_folder: Option<String>,
     pub(crate) server_uid: u32,
     pub(crate) is_dc_message: MessengerMessage,
@@ -318,6 +438,7 @@ pub struct Message {
     pub(crate) chat_blocked: Blocked,
     pub(crate) location_id: u32,
     pub(crate) error: Option<String>,
+    pub(crate) download_state: DownloadState,
     pub(crate) param: Params,
 }
 
@@ -342,6 +463,7 @@ pub async fn load_from_db(context: &Context, id: MsgId) -> Result<Message, Error
                     "    m.id AS id,",
                     "    rfc724_mid AS rfc724mid,",
                     "    m.mime_in_reply_to AS mime_in_reply_to,",
+                    "    m.quoted_msg_id AS quoted_msg_id,",
                     "    m.server_folder AS server_folder,",
                     "    m.server_uid AS server_uid,",
                     "    m.chat_id AS chat_id,",
@@ -355,6 +477,7 @@ pub async fn load_from_db(context: &Context, id: MsgId) -> Result<Message, Error
                     "    m.type AS type,",
                     "    m.state AS state,",
                     "    m.error AS error,",
+                    "    m.download_state AS download_state,",
                     "    m.msgrmsg AS msgrmsg,",
                     "    m.mime_modified AS mime_modified,",
                     "    m.txt AS txt,",
@@ -391,6 +514,7 @@ pub async fn load_from_db(context: &Context, id: MsgId) -> Result<Message, Error
                         id: row.get("id")?,
                         rfc724_mid: row.get::<_, String>("rfc724mid")?,
                         in_reply_to: row.get::<_, Option<String>>("mime_in_reply_to")?,
+                        quoted_msg_id: row.get("quoted_msg_id")?,
                         server_folder: row.get::<_, Option<String>>("server_folder")?,
                         server_uid: row.get("server_uid")?,
                         chat_id: row.get("chat_id")?,
@@ -405,6 +529,7 @@ pub async fn load_from_db(context: &Context, id: MsgId) -> Result<Message, Error
                         state: row.get("state")?,
                         error: Some(row.get::<_, String>("error")?)
                             .filter(|error| !error.is_empty()),
+                        download_state: row.get("download_state")?,
                         is_dc_message: row.get("msgrmsg")?,
                         mime_modified: row.get("mime_modified")?,
                         text: Some(text),
@@ -442,6 +567,14 @@ pub fn get_file(&self, context: &Context) -> Option<PathBuf> {
         self.param.get_path(Param::File, context).unwrap_or(None)
     }
 
+    /// Returns true if the attachment of this received message could not be saved,
+    /// see [Context::get_blobdir_writable].
+    pub fn is_media_not_saved(&self) -> bool {
+        self.param
+            .get_bool(Param::MediaNotSaved)
+            .unwrap_or_default()
+    }
+
     pub async fn try_calc_and_set_dimensions(&mut self, context: &Context) -> Result<(), Error> {
         if chat::msgtype_has_file(self.viewtype) {
             let file_param = self.param.get_path(Param::File, context)?;
@@ -454,8 +587,8 @@ pub async fn try_calc_and_set_dimensions(&mut self, context: &Context) -> Result
 
                     if let Ok(buf) = dc_read_file(context, path_and_filename).await {
                         if let Ok((width, height)) = dc_get_filemeta(&buf) {
-                            self.param.set_int(Param::Width, width as i32);
-                            self.param.set_int(Param::Height, height as i32);
+                            self.param.set_i64(Param::Width, width.into());
+                            self.param.set_i64(Param::Height, height.into());
                         }
                     }
 
@@ -496,8 +629,8 @@ pub fn set_location(&mut self, latitude: f64, longitude: f64) {
             return;
         }
 
-        self.param.set_float(Param::SetLatitude, latitude);
-        self.param.set_float(Param::SetLongitude, longitude);
+        self.param.set_f64(Param::SetLatitude, latitude);
+        self.param.set_f64(Param::SetLongitude, longitude);
     }
 
     pub fn get_timestamp(&self) -> i64 {
@@ -540,6 +673,22 @@ pub fn get_state(&self) -> MessageState {
         self.state
     }
 
+    /// Returns the number of contacts who sent a read receipt for the message,
+    /// see [get_read_receipts] for the contacts.
+    ///
+    /// The receipts are not loaded with the message, so the count is always up to date.
+    pub async fn get_read_receipts_count(&self, context: &Context) -> usize {
+        context
+            .sql
+            .query_get_value::<isize>(
+                context,
+                "SELECT COUNT(*) FROM msgs_mdns WHERE msg_id=?;",
+                paramsv![self.id],
+            )
+            .await
+            .unwrap_or_default() as usize
+    }
+
     pub fn get_received_timestamp(&self) -> i64 {
         self.timestamp_rcvd
     }
@@ -597,6 +746,144 @@ pub fn get_ephemeral_timestamp(&self) -> i64 {
         self.ephemeral_timestamp
     }
 
+    /// Returns details about the message, e.g. timestamps, encryption and read receipts.
+    ///
+    /// [get_msg_info] formats the same details as a human-readable string.
+    pub async fn get_info_structured(&self, context: &Context) -> Result<MsgInfo, Error> {
+        let (text_raw, has_raw_headers) = context
+            .sql
+            .query_row_optional(
+                "SELECT txt_raw, IFNULL(LENGTH(mime_headers), 0)>0 FROM msgs WHERE id=?;",
+                paramsv![self.id],
+                |row| Ok((row.get::<_, String>(0)?, row.get::<_, bool>(1)?)),
+            )
+            .await?
+            .ok_or_else(|| format_err!("Cannot load message {}.", self.id))?;
+
+        let from = Contact::load_from_db(context, self.from_id)
+            .await
+            .map(|contact| contact.get_name_n_addr())
+            .unwrap_or_default();
+
+        let timestamp_received = if self.from_id != DC_CONTACT_ID_SELF {
+            Some(if self.timestamp_rcvd != 0 {
+                self.timestamp_rcvd
+            } else {
+                self.timestamp_sort
+            })
+        } else {
+            None
+        };
+
+        let rows = context
+            .sql
+            .query_map(
+                "SELECT contact_id, timestamp_sent FROM msgs_mdns WHERE msg_id=?;",
+                paramsv![self.id],
+                |row| Ok((row.get::<_, u32>(0)?, row.get::<_, i64>(1)?)),
+                |rows| rows.collect::<Result<Vec<_>, _>>().map_err(Into::into),
+            )
+            .await?;
+        let mut read_receipts = Vec::with_capacity(rows.len());
+        for (contact_id, timestamp) in rows {
+            let contact = Contact::load_from_db(context, contact_id)
+                .await
+                .map(|contact| contact.get_name_n_addr())
+                .unwrap_or_default();
+            read_receipts.push(MsgReadReceipt {
+                contact_id,
+                contact,
+                timestamp,
+            });
+        }
+
+        let e2ee_errors = self.param.get_int(Param::ErroneousE2ee).unwrap_or_default();
+        let encrypted = if e2ee_errors != 0 {
+            e2ee_errors & 0x2 != 0
+        } else {
+            self.get_showpadlock()
+        };
+        let encryption = if encrypted {
+            let self_fingerprint = key::list_keypairs(context)
+                .await?
+                .into_iter()
+                .find(|keypair| keypair.is_default)
+                .map(|keypair| keypair.fingerprint.hex());
+            let peers = if self.from_id == DC_CONTACT_ID_SELF {
+                chat::get_chat_contacts(context, self.chat_id)
+                    .await
+                    .into_iter()
+                    .filter(|contact_id| *contact_id != DC_CONTACT_ID_SELF)
+                    .collect()
+            } else {
+                vec![self.from_id]
+            };
+            // The keys may have changed since, the history tells which were used then.
+            let msg_time = if self.from_id == DC_CONTACT_ID_SELF {
+                self.timestamp_sort
+            } else {
+                self.timestamp_rcvd
+            };
+            Some(MsgEncryptionInfo {
+                valid_signature: e2ee_errors == 0,
+                self_fingerprint,
+                peer_keys: load_peer_keys(context, &peers, msg_time).await?,
+            })
+        } else {
+            None
+        };
+
+        let ephemeral_timer = match self.ephemeral_timer {
+            EphemeralTimer::Enabled { duration } => Some(duration),
+            EphemeralTimer::Disabled => None,
+        };
+
+        let file = match self.get_file(context) {
+            Some(path) => {
+                let bytes = dc_get_filebytes(context, &path).await;
+                Some((path, bytes))
+            }
+            None => None,
+        };
+        let mimetype = if self.viewtype != Viewtype::Text {
+            Some(self.get_filemime().unwrap_or_default())
+        } else {
+            None
+        };
+        let w = self.param.get_i64(Param::Width).unwrap_or_default();
+        let h = self.param.get_i64(Param::Height).unwrap_or_default();
+        let duration = self.param.get_i64(Param::Duration).unwrap_or_default();
+
+        Ok(MsgInfo {
+            msg_id: self.id,
+            from_id: self.from_id,
+            from,
+            timestamp_sent: self.get_timestamp(),
+            timestamp_received,
+            state: self.state,
+            is_info: self.from_id == DC_CONTACT_ID_INFO || self.to_id == DC_CONTACT_ID_INFO,
+            has_location: self.has_location(),
+            encryption,
+            read_receipts,
+            error: self.error.clone(),
+            ephemeral_timer,
+            ephemeral_timestamp: Some(self.ephemeral_timestamp).filter(|ts| *ts != 0),
+            viewtype: self.viewtype,
+            mimetype,
+            file,
+            dimensions: if w != 0 || h != 0 { Some((w, h)) } else { None },
+            duration: Some(duration).filter(|duration| *duration != 0),
+            text_raw: dc_truncate(text_raw.trim(), DC_MAX_GET_INFO_LEN).to_string(),
+            rfc724_mid: Some(self.rfc724_mid.clone()).filter(|mid| !mid.is_empty()),
+            server_location: self
+                .server_folder
+                .clone()
+                .filter(|folder| !folder.is_empty())
+                .map(|folder| (folder, self.server_uid)),
+            has_raw_headers,
+        })
+    }
+
     pub async fn get_summary(&mut self, context: &Context, chat: Option<&Chat>) -> Lot {
         let mut ret = Lot::new();
 
@@ -615,7 +902,7 @@ pub async fn get_summary(&mut self, context: &Context, chat: Option<&Chat>) -> L
                 Chattype::Group | Chattype::Mailinglist => {
                     Contact::get_by_id(context, self.from_id).await.ok()
                 }
-                Chattype::Single | Chattype::Undefined => None,
+                Chattype::Single | Chattype::Undefined | Chattype::Broadcast => None,
             }
         } else {
             None
@@ -837,6 +1124,20 @@ pub fn set_duration(&mut self, duration: i32) {
         self.param.set_int(Param::Duration, duration);
     }
 
+    /// Returns the waveform of a voice message, see [generate_waveform].
+    pub fn get_waveform(&self) -> Option<Vec<u8>> {
+        let waveform = base64::decode(self.param.get(Param::Waveform)?).ok()?;
+        if waveform.is_empty() {
+            None
+        } else {
+            Some(waveform)
+        }
+    }
+
+    pub fn set_waveform(&mut self, waveform: &[u8]) {
+        self.param.set(Param::Waveform, base64::encode(waveform));
+    }
+
     pub async fn latefiling_mediasize(
         &mut self,
         context: &Context,
@@ -857,6 +1158,7 @@ pub async fn latefiling_mediasize(
     /// Sets message quote.
     ///
     /// Message-Id is used to set Reply-To field, message text is used for quote.
+    /// The id of the quoted message is stored as well, see [Message::quoted_message].
     ///
     /// Encryption is required if quoted message was encrypted.
     ///
@@ -868,6 +1170,7 @@ pub async fn set_quote(&mut self, context: &Context, quote: &Message) -> Result<
             "Message without Message-Id cannot be quoted"
         );
         self.in_reply_to = Some(quote.rfc724_mid.clone());
+        self.quoted_msg_id = quote.id;
 
         if quote
             .param
@@ -891,25 +1194,52 @@ pub async fn set_quote(&mut self, context: &Context, quote: &Message) -> Result<
         Ok(())
     }
 
+    /// Returns the text of the quote as it was when the quote was set.
+    ///
+    /// This snapshot is kept even if the quoted message is deleted later.
     pub fn quoted_text(&self) -> Option<String> {
         self.param.get(Param::Quote).map(|s| s.to_string())
     }
 
+    /// Returns the quoted message, e.g. to jump to it.
+    ///
+    /// The message is looked up by the stored id, for messages quoted before the id was
+    /// stored by the Message-Id from `In-Reply-To`.  If the quoted message was deleted,
+    /// `None` is returned and [Message::quoted_text] should be displayed instead.
     pub async fn quoted_message(&self, context: &Context) -> Result<Option<Message>, Error> {
-        if self.param.get(Param::Quote).is_some() {
-            if let Some(in_reply_to) = &self.in_reply_to {
-                if let Some((_, _, msg_id)) = rfc724_mid_exists(context, in_reply_to).await? {
-                    let msg = Message::load_from_db(context, msg_id).await?;
-                    return if msg.chat_id.is_trash() {
-                        // If message is already moved to trash chat, pretend it does not exist.
-                        Ok(None)
-                    } else {
-                        Ok(Some(msg))
-                    };
-                }
+        if self.param.get(Param::Quote).is_none() {
+            return Ok(None);
+        }
+        let quoted_mid = match self
+            .in_reply_to
+            .as_deref()
+            .map(parse_message_ids)
+            .and_then(|mids| mids.into_iter().next())
+        {
+            Some(quoted_mid) => quoted_mid,
+            None => return Ok(None),
+        };
+        let msg_id = if self.quoted_msg_id.is_special() {
+            match rfc724_mid_exists(context, &quoted_mid).await? {
+                Some((_, _, msg_id)) => msg_id,
+                None => return Ok(None),
             }
+        } else {
+            self.quoted_msg_id
+        };
+        let msg = match Message::load_from_db(context, msg_id).await {
+            Ok(msg) => msg,
+            // The message was deleted from the database.
+            Err(_) if msg_id == self.quoted_msg_id => return Ok(None),
+            Err(err) => return Err(err),
+        };
+        if msg.chat_id.is_trash() || msg.rfc724_mid != quoted_mid {
+            // If message is already moved to trash chat or the id was reused
+            // by another message, pretend it does not exist.
+            Ok(None)
+        } else {
+            Ok(Some(msg))
         }
-        Ok(None)
     }
 
     pub async fn update_param(&self, context: &Context) {
@@ -949,6 +1279,20 @@ pub(crate) async fn update_subject(&self, context: &Context) {
     pub fn error(&self) -> Option<String> {
         self.error.clone()
     }
+
+    /// Gets the error status of the message together with its classification.
+    ///
+    /// See [`Message::error`] for the meaning of the error text.  Errors stored before the
+    /// classification was introduced are reported as [`ErrorCode::Unknown`].
+    pub fn get_error(&self) -> Option<MsgError> {
+        let text = self.error.clone()?;
+        let code = self
+            .param
+            .get_int(Param::ErrorCode)
+            .and_then(ErrorCode::from_i32)
+            .unwrap_or(ErrorCode::Unknown);
+        Some(MsgError { code, text })
+    }
 }
 
 #[derive(Display, Debug, FromPrimitive)]
@@ -1001,6 +1345,10 @@ pub enum MessageState {
     /// checkmark).
     OutPending = 20,
 
+    /// The message is stored to be sent at a later time, see
+    /// [crate::chat::send_msg_at]. It enters OutPending once that time has come.
+    OutScheduled = 21,
+
     /// *Unrecoverable* error (*recoverable* errors result in pending
     /// messages).
     OutFailed = 24,
@@ -1034,6 +1382,7 @@ fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
                 Self::OutPreparing => "Preparing",
                 Self::OutDraft => "Draft",
                 Self::OutPending => "Pending",
+                Self::OutScheduled => "Scheduled",
                 Self::OutFailed => "Failed",
                 Self::OutDelivered => "Delivered",
                 Self::OutMdnRcvd => "Read",
@@ -1053,6 +1402,7 @@ fn from(s: MessageState) -> Self {
             OutPreparing => LotState::MsgOutPreparing,
             OutDraft => LotState::MsgOutDraft,
             OutPending => LotState::MsgOutPending,
+            OutScheduled => LotState::MsgOutScheduled,
             OutFailed => LotState::MsgOutFailed,
             OutDelivered => LotState::MsgOutDelivered,
             OutMdnRcvd => LotState::MsgOutMdnRcvd,
@@ -1072,7 +1422,13 @@ pub fn is_outgoing(self) -> bool {
         use MessageState::*;
         matches!(
             self,
-            OutPreparing | OutDraft | OutPending | OutFailed | OutDelivered | OutMdnRcvd
+            OutPreparing
+                | OutDraft
+                | OutPending
+                | OutScheduled
+                | OutFailed
+                | OutDelivered
+                | OutMdnRcvd
         )
     }
 }
@@ -1111,7 +1467,7 @@ pub async fn fill(
                         self.text1_meaning = Meaning::Text1Username;
                     }
                 }
-                Chattype::Single | Chattype::Undefined => {
+                Chattype::Single | Chattype::Undefined | Chattype::Broadcast => {
                     self.text1 = None;
                     self.text1_meaning = Meaning::None;
                 }
@@ -1208,154 +1564,279 @@ pub async fn decide_on_contact_request(
     created_chat_id
 }
 
-pub async fn get_msg_info(context: &Context, msg_id: MsgId) -> String {
-    let mut ret = String::new();
+/// Details about a message as shown in the message info, see [Message::get_info_structured].
+///
+/// Fields are `None` where the data is not available, e.g. for unencrypted messages
+/// or messages not seen on the server yet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MsgInfo {
+    pub msg_id: MsgId,
+    pub from_id: u32,
 
-    let msg = Message::load_from_db(context, msg_id).await;
-    if msg.is_err() {
-        return ret;
-    }
+    /// Name and address of the sender.
+    pub from: String,
 
-    let msg = msg.unwrap_or_default();
+    pub timestamp_sent: i64,
 
-    let rawtxt: Option<String> = context
-        .sql
-        .query_get_value(
-            context,
-            "SELECT txt_raw FROM msgs WHERE id=?;",
-            paramsv![msg_id],
-        )
-        .await;
+    /// Time the message was received, `None` for outgoing messages.
+    pub timestamp_received: Option<i64>,
 
-    if rawtxt.is_none() {
-        ret += &format!("Cannot load message {}.", msg_id);
-        return ret;
+    pub state: MessageState,
+
+    /// True for device-internal messages, e.g. info messages.
+    pub is_info: bool,
+
+    pub has_location: bool,
+
+    /// Encryption details, `None` if the message was not encrypted.
+    pub encryption: Option<MsgEncryptionInfo>,
+
+    /// Read receipts received for the message.
+    pub read_receipts: Vec<MsgReadReceipt>,
+
+    pub error: Option<String>,
+
+    /// Duration of the ephemeral timer in seconds, `None` if the timer is disabled.
+    pub ephemeral_timer: Option<u32>,
+
+    /// Time the message is deleted by the ephemeral timer, `None` if not scheduled.
+    pub ephemeral_timestamp: Option<i64>,
+
+    pub viewtype: Viewtype,
+
+    /// Mimetype of the attachment, `None` for text messages.
+    pub mimetype: Option<String>,
+
+    /// Path and size in bytes of the attachment.
+    pub file: Option<(PathBuf, u64)>,
+
+    /// Width and height of images and videos.
+    pub dimensions: Option<(i64, i64)>,
+
+    /// Duration of audio and video files in milliseconds.
+    pub duration: Option<i64>,
+
+    /// Raw text of the message, truncated to a reasonable length.
+    pub text_raw: String,
+
+    pub rfc724_mid: Option<String>,
+
+    /// Folder and UID the message was last seen at on the server.
+    pub server_location: Option<(String, u32)>,
+
+    /// True if the raw MIME headers are available, see [get_mime_headers].
+    pub has_raw_headers: bool,
+}
+
+/// Encryption details of a message, part of [MsgInfo].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MsgEncryptionInfo {
+    /// False if the message was encrypted, but not signed with a valid signature.
+    pub valid_signature: bool,
+
+    /// Fingerprint of the own key, as hex string.
+    pub self_fingerprint: Option<String>,
+
+    /// Keys of the sender for incoming messages, of the recipients for outgoing messages.
+    pub peer_keys: Vec<MsgPeerKey>,
+}
+
+/// Key of a contact involved in an encrypted message, part of [MsgEncryptionInfo].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MsgPeerKey {
+    pub contact_id: u32,
+    pub addr: String,
+
+    /// Fingerprint of the key, as hex string.
+    pub fingerprint: String,
+
+    /// True if the key was verified, e.g. by scanning a QR code.
+    pub verified: bool,
+}
+
+/// Read receipt of a message, part of [MsgInfo].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MsgReadReceipt {
+    pub contact_id: u32,
+
+    /// Name and address of the contact.
+    pub contact: String,
+
+    pub timestamp: i64,
+}
+
+/// Returns the keys `contact_ids` had at `timestamp`, as known from their peerstates
+/// and the history of their key changes, see [peerstate::get_history].
+async fn load_peer_keys(
+    context: &Context,
+    contact_ids: &[u32],
+    timestamp: i64,
+) -> Result<Vec<MsgPeerKey>, Error> {
+    let mut peer_keys = Vec::new();
+    for contact_id in contact_ids {
+        let contact = Contact::load_from_db(context, *contact_id).await?;
+        let peerstate = match Peerstate::from_addr(context, &contact.addr).await? {
+            Some(peerstate) => peerstate,
+            None => continue,
+        };
+        let history = peerstate::get_history(context, &contact.addr).await?;
+        let fingerprint = fingerprint_at(
+            &history,
+            ChangedKey::PublicKey,
+            peerstate.public_key_fingerprint.clone(),
+            timestamp,
+        )
+        .or_else(|| peerstate.gossip_key_fingerprint.clone());
+        let verified_fingerprint = fingerprint_at(
+            &history,
+            ChangedKey::VerifiedKey,
+            peerstate.verified_key_fingerprint.clone(),
+            timestamp,
+        );
+        if let Some(fingerprint) = fingerprint {
+            peer_keys.push(MsgPeerKey {
+                contact_id: *contact_id,
+                addr: contact.addr.clone(),
+                fingerprint: fingerprint.hex(),
+                verified: verified_fingerprint.as_ref() == Some(&fingerprint),
+            });
+        }
     }
-    let rawtxt = rawtxt.unwrap_or_default();
-    let rawtxt = dc_truncate(rawtxt.trim(), DC_MAX_GET_INFO_LEN);
+    Ok(peer_keys)
+}
 
-    let fts = dc_timestamp_to_str(msg.get_timestamp());
-    ret += &format!("Sent: {}", fts);
+/// Returns the fingerprint of `key` at `timestamp` by undoing the later changes in `history`,
+/// which is ordered newest first, starting from the `current` fingerprint.
+fn fingerprint_at(
+    history: &[PeerstateChange],
+    key: ChangedKey,
+    current: Option<Fingerprint>,
+    timestamp: i64,
+) -> Option<Fingerprint> {
+    history
+        .iter()
+        .filter(|change| change.key == key && change.timestamp > timestamp)
+        .last()
+        .map_or(current, |change| change.old_fingerprint.clone())
+}
 
-    let name = Contact::load_from_db(context, msg.from_id)
-        .await
-        .map(|contact| contact.get_name_n_addr())
-        .unwrap_or_default();
+/// Returns a human-readable description of the message,
+/// formatted from [Message::get_info_structured].
+pub async fn get_msg_info(context: &Context, msg_id: MsgId) -> String {
+    let msg = match Message::load_from_db(context, msg_id).await {
+        Ok(msg) => msg,
+        Err(_) => return String::new(),
+    };
+    let info = match msg.get_info_structured(context).await {
+        Ok(info) => info,
+        Err(err) => {
+            warn!(context, "Cannot load info for {}: {:#}", msg_id, err);
+            return format!("Cannot load message {}.", msg_id);
+        }
+    };
 
-    ret += &format!(" by {}", name);
-    ret += "\n";
+    let mut ret = String::new();
+    ret += &format!(
+        "Sent: {} by {}\n",
+        dc_timestamp_to_str(info.timestamp_sent),
+        info.from
+    );
 
-    if msg.from_id != DC_CONTACT_ID_SELF as u32 {
-        let s = dc_timestamp_to_str(if 0 != msg.timestamp_rcvd {
-            msg.timestamp_rcvd
-        } else {
-            msg.timestamp_sort
-        });
-        ret += &format!("Received: {}", &s);
-        ret += "\n";
+    if let Some(timestamp_received) = info.timestamp_received {
+        ret += &format!("Received: {}\n", dc_timestamp_to_str(timestamp_received));
     }
 
-    if let EphemeralTimer::Enabled { duration } = msg.ephemeral_timer {
+    if let Some(duration) = info.ephemeral_timer {
         ret += &format!("Ephemeral timer: {}\n", duration);
     }
 
-    if msg.ephemeral_timestamp != 0 {
-        ret += &format!(
-            "Expires: {}\n",
-            dc_timestamp_to_str(msg.ephemeral_timestamp)
-        );
+    if let Some(ephemeral_timestamp) = info.ephemeral_timestamp {
+        ret += &format!("Expires: {}\n", dc_timestamp_to_str(ephemeral_timestamp));
     }
 
-    if msg.from_id == DC_CONTACT_ID_INFO || msg.to_id == DC_CONTACT_ID_INFO {
+    if info.is_info {
         // device-internal message, no further details needed
         return ret;
     }
 
-    if let Ok(rows) = context
-        .sql
-        .query_map(
-            "SELECT contact_id, timestamp_sent FROM msgs_mdns WHERE msg_id=?;",
-            paramsv![msg_id],
-            |row| {
-                let contact_id: i32 = row.get(0)?;
-                let ts: i64 = row.get(1)?;
-                Ok((contact_id, ts))
-            },
-            |rows| rows.collect::<Result<Vec<_>, _>>().map_err(Into::into),
-        )
-        .await
-    {
-        for (contact_id, ts) in rows {
-            let fts = dc_timestamp_to_str(ts);
-            ret += &format!("Read: {}", fts);
-
-            let name = Contact::load_from_db(context, contact_id as u32)
-                .await
-                .map(|contact| contact.get_name_n_addr())
-                .unwrap_or_default();
-
-            ret += &format!(" by {}", name);
-            ret += "\n";
-        }
+    for receipt in &info.read_receipts {
+        ret += &format!(
+            "Read: {} by {}\n",
+            dc_timestamp_to_str(receipt.timestamp),
+            receipt.contact
+        );
     }
 
-    ret += &format!("State: {}", msg.state);
+    ret += &format!("State: {}", info.state);
 
-    if msg.has_location() {
+    if info.has_location {
         ret += ", Location sent";
     }
 
-    let e2ee_errors = msg.param.get_int(Param::ErroneousE2ee).unwrap_or_default();
-
-    if 0 != e2ee_errors {
-        if 0 != e2ee_errors & 0x2 {
+    if let Some(encryption) = &info.encryption {
+        if encryption.valid_signature {
+            ret += ", Encrypted";
+        } else {
             ret += ", Encrypted, no valid signature";
         }
-    } else if 0 != msg.param.get_int(Param::GuaranteeE2ee).unwrap_or_default() {
-        ret += ", Encrypted";
     }
 
     ret += "\n";
 
-    if let Some(error) = msg.error.as_ref() {
+    if let Some(error) = &info.error {
         ret += &format!("Error: {}", error);
     }
 
-    if let Some(path) = msg.get_file(context) {
-        let bytes = dc_get_filebytes(context, &path).await;
+    if let Some((path, bytes)) = &info.file {
         ret += &format!("\nFile: {}, {}, bytes\n", path.display(), bytes);
     }
 
-    if msg.viewtype != Viewtype::Text {
-        ret += "Type: ";
-        ret += &format!("{}", msg.viewtype);
-        ret += "\n";
-        ret += &format!("Mimetype: {}\n", &msg.get_filemime().unwrap_or_default());
+    if let Some(mimetype) = &info.mimetype {
+        ret += &format!("Type: {}\n", info.viewtype);
+        ret += &format!("Mimetype: {}\n", mimetype);
     }
-    let w = msg.param.get_int(Param::Width).unwrap_or_default();
-    let h = msg.param.get_int(Param::Height).unwrap_or_default();
-    if w != 0 || h != 0 {
-        ret += &format!("Dimension: {} x {}\n", w, h,);
+    if let Some((w, h)) = info.dimensions {
+        ret += &format!("Dimension: {} x {}\n", w, h);
     }
-    let duration = msg.param.get_int(Param::Duration).unwrap_or_default();
-    if duration != 0 {
-        ret += &format!("Duration: {} ms\n", duration,);
+    if let Some(duration) = info.duration {
+        ret += &format!("Duration: {} ms\n", duration);
     }
-    if !rawtxt.is_empty() {
-        ret += &format!("\n{}\n", rawtxt);
+    if !info.text_raw.is_empty() {
+        ret += &format!("\n{}\n", info.text_raw);
     }
-    if !msg.rfc724_mid.is_empty() {
-        ret += &format!("\nMessage-ID: {}", msg.rfc724_mid);
+    if let Some(rfc724_mid) = &info.rfc724_mid {
+        ret += &format!("\nMessage-ID: {}", rfc724_mid);
     }
-    if let Some(ref server_folder) = msg.server_folder {
-        if !server_folder.is_empty() {
-            ret += &format!("\nLast seen as: {}/{}", server_folder, msg.server_uid);
-        }
+    if let Some((server_folder, server_uid)) = &info.server_location {
+        ret += &format!("\nLast seen as: {}/{}", server_folder, server_uid);
     }
 
     ret
 }
 
+/// Decodes the voice message `blob` and returns its waveform, see [crate::waveform].
+///
+/// The waveform is also saved next to the blob with the `.waveform` suffix,
+/// housekeeping keeps this file as long as the blob is in use.
+pub async fn generate_waveform(context: &Context, blob: &BlobObject<'_>) -> Result<Vec<u8>, Error> {
+    let path = blob.to_abs_path();
+    let data = async_std::fs::read(&path).await?;
+    let extension = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(|extension| extension.to_lowercase());
+    let waveform = async_std::task::spawn_blocking(move || {
+        crate::waveform::compute(data, extension.as_deref())
+    })
+    .await?;
+
+    let mut sidecar = path.into_os_string();
+    sidecar.push(".waveform");
+    async_std::fs::write(&sidecar, &waveform).await?;
+    info!(context, "Generated waveform for {}.", blob.as_name());
+    Ok(waveform)
+}
+
 pub fn guess_msgtype_from_suffix(path: &Path) -> Option<(Viewtype, &str)> {
     let extension: &str = &path.extension()?.to_str()?.to_lowercase();
     let info = match extension {
@@ -1442,24 +1923,98 @@ pub async fn get_mime_headers(context: &Context, msg_id: MsgId) -> Option<String
         .await
 }
 
+/// Sends outgoing messages again which failed or are still pending.
+///
+/// The number of tries is reset and sending is retried immediately.
+pub async fn resend(context: &Context, msg_ids: &[MsgId]) -> Result<(), Error> {
+    for msg_id in msg_ids {
+        let mut msg = Message::load_from_db(context, *msg_id).await?;
+        ensure!(
+            matches!(
+                msg.state,
+                MessageState::OutFailed | MessageState::OutPending
+            ),
+            "Cannot resend message {} in state {}",
+            msg_id,
+            msg.state
+        );
+
+        msg.param.remove(Param::ErrorCode);
+        context
+            .sql
+            .execute(
+                "UPDATE msgs SET state=?, error='', param=? WHERE id=?;",
+                paramsv![MessageState::OutPending, msg.param.to_string(), msg_id],
+            )
+            .await?;
+        context.emit_event(EventType::MsgsChanged {
+            chat_id: msg.chat_id,
+            msg_id: *msg_id,
+        });
+
+        job::resend_msg(context, *msg_id).await?;
+    }
+    Ok(())
+}
+
 pub async fn delete_msgs(context: &Context, msg_ids: &[MsgId]) {
+    let batched = context
+        .get_config_bool(Config::DeleteOnServerWhenDeletingLocally)
+        .await;
+    // Messages are deleted from the server only when they cannot be restored anymore.
+    let grace_period = context
+        .get_config_i64(Config::TrashGracePeriod)
+        .await
+        .max(0);
     for msg_id in msg_ids.iter() {
-        if let Ok(msg) = Message::load_from_db(context, *msg_id).await {
+        let msg = Message::load_from_db(context, *msg_id).await.ok();
+        if let Some(msg) = &msg {
             if msg.location_id > 0 {
                 delete_poi_location(context, msg.location_id).await;
             }
         }
-        if let Err(err) = msg_id.trash(context).await {
-            error!(context, "Unable to trash message {}: {}", msg_id, err);
+        match msg_id.trash(context).await {
+            Ok(()) => {
+                if let Some(msg) = &msg {
+                    stats::adjust_chat_messages(context, msg.chat_id, -1).await;
+                }
+            }
+            Err(err) => error!(context, "Unable to trash message {}: {}", msg_id, err),
+        }
+        if batched {
+            // Only messages deleted by the user are deleted on the server,
+            // see [cleanup::delete_trashed].
+            if let Err(err) = context
+                .sql
+                .execute(
+                    "UPDATE msgs SET delete_on_server=1 WHERE id=?;",
+                    paramsv![msg_id],
+                )
+                .await
+            {
+                error!(
+                    context,
+                    "Unable to mark message {} for deletion on the server: {}", msg_id, err
+                );
+            }
+        } else {
+            job::add(
+                context,
+                job::Job::new(
+                    Action::DeleteMsgOnImap,
+                    msg_id.to_u32(),
+                    Params::new(),
+                    grace_period,
+                ),
+            )
+            .await;
         }
-        job::add(
-            context,
-            job::Job::new(Action::DeleteMsgOnImap, msg_id.to_u32(), Params::new(), 0),
-        )
-        .await;
     }
 
     if !msg_ids.is_empty() {
+        if batched {
+            cleanup::schedule_trashed(context).await;
+        }
         context.emit_event(EventType::MsgsChanged {
             chat_id: ChatId::new(0),
             msg_id: MsgId::new(0),
@@ -1473,7 +2028,122 @@ pub async fn delete_msgs(context: &Context, msg_ids: &[MsgId]) {
     }
 }
 
-async fn delete_poi_location(context: &Context, location_id: u32) -> bool {
+/// Cancels sending a message scheduled by [chat::send_msg_at] and deletes it.
+///
+/// Fails if the message is not scheduled anymore, e.g. because it is already being sent.
+pub async fn cancel_scheduled(context: &Context, msg_id: MsgId) -> Result<(), Error> {
+    // The SMTP thread holds the same lock while it picks up a job.
+    let outbox_job = context.outbox_job.lock().await;
+
+    let msg = Message::load_from_db(context, msg_id).await?;
+    ensure!(
+        msg.state == MessageState::OutScheduled,
+        "Message {} is not scheduled",
+        msg_id
+    );
+    if let Some(job_id) = *outbox_job {
+        ensure!(
+            !context
+                .sql
+                .exists(
+                    "SELECT id FROM jobs WHERE id=? AND action=? AND foreign_id=?;",
+                    paramsv![job_id, Action::SendScheduledMsg, msg_id],
+                )
+                .await?,
+            "Message {} is being sent",
+            msg_id
+        );
+    }
+    context
+        .sql
+        .execute(
+            "DELETE FROM jobs WHERE action=? AND foreign_id=?;",
+            paramsv![Action::SendScheduledMsg, msg_id],
+        )
+        .await?;
+    msg_id.delete_from_db(context).await?;
+    context.emit_event(EventType::MsgsChanged {
+        chat_id: msg.chat_id,
+        msg_id: MsgId::new(0),
+    });
+    Ok(())
+}
+
+/// Moves messages deleted by [delete_msgs] back to the chats they were deleted from.
+///
+/// Only messages deleted within [Config::TrashGracePeriod] can be restored,
+/// other messages and messages of chats deleted in between are skipped.
+/// A chat created later with the ID of a deleted chat is detected by its creation timestamp.
+///
+/// Returns the number of restored messages.
+pub async fn restore_from_trash(context: &Context, msg_ids: &[MsgId]) -> Result<usize, Error> {
+    let mut restored = 0;
+    for msg_id in msg_ids {
+        let row: Option<(ChatId, String)> = context
+            .sql
+            .query_row_optional(
+                "SELECT chat_id, param FROM msgs WHERE id=?",
+                paramsv![msg_id],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .await?;
+        let (chat_id, param) = row.unwrap_or_default();
+        let mut param: Params = param.parse().unwrap_or_default();
+        let orig_chat_id = match param.get_int(Param::TrashedChatId) {
+            Some(orig_chat_id) if chat_id.is_trash() => ChatId::new(orig_chat_id as u32),
+            _ => {
+                warn!(context, "Message {} cannot be restored.", msg_id);
+                continue;
+            }
+        };
+        let chat_created: Option<i64> = context
+            .sql
+            .query_get_value_result(
+                "SELECT created_timestamp FROM chats WHERE id=?",
+                paramsv![orig_chat_id],
+            )
+            .await?;
+        let orig_chat = match Chat::load_from_db(context, orig_chat_id).await {
+            Ok(chat) if chat_created == param.get_i64(Param::TrashedChatCreated) => chat,
+            _ => {
+                warn!(
+                    context,
+                    "Message {} cannot be restored, {} was deleted.", msg_id, orig_chat_id
+                );
+                continue;
+            }
+        };
+
+        param.remove(Param::TrashedChatId);
+        param.remove(Param::TrashedChatCreated);
+        context
+            .sql
+            .execute(
+                "UPDATE msgs SET chat_id=?, param=?, deleted_timestamp=0, delete_on_server=0 WHERE id=?",
+                paramsv![orig_chat_id, param.to_string(), msg_id],
+            )
+            .await?;
+        // The message is not going to be deleted on the server anymore.
+        context
+            .sql
+            .execute(
+                "DELETE FROM jobs WHERE action=? AND foreign_id=?",
+                paramsv![Action::DeleteMsgOnImap, msg_id],
+            )
+            .await?;
+        context
+            .stats_cache
+            .adjust_messages(orig_chat_id, orig_chat.blocked, 1);
+        context.emit_event(EventType::MsgsChanged {
+            chat_id: orig_chat_id,
+            msg_id: *msg_id,
+        });
+        restored += 1;
+    }
+    Ok(restored)
+}
+
+async fn delete_poi_location(context: &Context, location_id: u32) -> bool {
     context
         .sql
         .execute(
@@ -1668,7 +2338,15 @@ pub async fn exists(context: &Context, msg_id: MsgId) -> bool {
     }
 }
 
-pub async fn set_msg_failed(context: &Context, msg_id: MsgId, error: Option<impl AsRef<str>>) {
+/// Marks a message as failed and stores the error.
+///
+/// Emits [`EventType::MsgFailed`].
+pub async fn set_msg_failed(
+    context: &Context,
+    msg_id: MsgId,
+    code: ErrorCode,
+    error: Option<impl AsRef<str>>,
+) {
     if let Ok(mut msg) = Message::load_from_db(context, msg_id).await {
         let error = error.map(|e| e.as_ref().to_string()).unwrap_or_default();
         if msg.state.can_fail() {
@@ -1680,12 +2358,13 @@ pub async fn set_msg_failed(context: &Context, msg_id: MsgId, error: Option<impl
                 "{} seems to have failed ({}), but state is {}", msg_id, error, msg.state
             )
         }
+        msg.param.set_int(Param::ErrorCode, code as i32);
 
         match context
             .sql
             .execute(
-                "UPDATE msgs SET state=?, error=? WHERE id=?;",
-                paramsv![msg.state, error, msg_id],
+                "UPDATE msgs SET state=?, error=?, param=? WHERE id=?;",
+                paramsv![msg.state, error, msg.param.to_string(), msg_id],
             )
             .await
         {
@@ -1697,10 +2376,75 @@ pub async fn set_msg_failed(context: &Context, msg_id: MsgId, error: Option<impl
                 warn!(context, "{:?}", e);
             }
         }
+
+        if msg.state == MessageState::OutFailed
+            && msg.param.get_cmd() == SystemMessage::StatusUpdate
+        {
+            rollback_status_update(context, &msg)
+                .await
+                .ok_or_log_msg(context, "Cannot roll back status update");
+        }
+    }
+}
+
+/// Stores the error of a failed try to send a message which is going to be retried.
+///
+/// A permanent error stored before is not replaced, so the user still sees why the
+/// message failed.  Emits [`EventType::MsgsChanged`] if the error was stored.
+pub(crate) async fn set_msg_transient_error(context: &Context, msg_id: MsgId, error: &MsgError) {
+    if let Ok(mut msg) = Message::load_from_db(context, msg_id).await {
+        if msg.state == MessageState::OutFailed
+            || msg.get_error().map_or(false, |e| !e.code.is_transient())
+        {
+            info!(
+                context,
+                "{} not replacing permanent error with: {}", msg_id, error
+            );
+            return;
+        }
+        msg.param.set_int(Param::ErrorCode, error.code as i32);
+
+        match context
+            .sql
+            .execute(
+                "UPDATE msgs SET error=?, param=? WHERE id=?;",
+                paramsv![error.text, msg.param.to_string(), msg_id],
+            )
+            .await
+        {
+            Ok(_) => context.emit_event(EventType::MsgsChanged {
+                chat_id: msg.chat_id,
+                msg_id,
+            }),
+            Err(e) => {
+                warn!(context, "{:?}", e);
+            }
+        }
+    }
+}
+
+/// Removes a transient error once the message could be sent.
+pub(crate) async fn clear_transient_error(context: &Context, msg_id: MsgId) {
+    if let Ok(mut msg) = Message::load_from_db(context, msg_id).await {
+        if msg.get_error().map_or(false, |e| e.code.is_transient()) {
+            msg.param.remove(Param::ErrorCode);
+            context
+                .sql
+                .execute(
+                    "UPDATE msgs SET error='', param=? WHERE id=?;",
+                    paramsv![msg.param.to_string(), msg_id],
+                )
+                .await
+                .ok_or_log(context);
+        }
     }
 }
 
-/// returns Some if an event should be send
+/// Returns the chat and message ID if the message is read by all recipients now.
+///
+/// The new message state is queued, see [crate::state_batch],
+/// `MsgRead` is emitted once the state is written.
+/// `MsgReadReceipt` is emitted for each receipt not seen before.
 pub async fn handle_mdn(
     context: &Context,
     from_id: u32,
@@ -1745,6 +2489,7 @@ pub async fn handle_mdn(
         if msg_state == MessageState::OutPreparing
             || msg_state == MessageState::OutPending
             || msg_state == MessageState::OutDelivered
+            || msg_state == MessageState::OutMdnRcvd
         {
             let mdn_already_in_table = context
                 .sql
@@ -1756,17 +2501,36 @@ pub async fn handle_mdn(
                 .unwrap_or_default();
 
             if !mdn_already_in_table {
-                context.sql.execute(
-                    "INSERT INTO msgs_mdns (msg_id, contact_id, timestamp_sent) VALUES (?, ?, ?);",
-                    paramsv![msg_id, from_id as i32, timestamp_sent],
-                )
+                // Reports without a usable Date header are timestamped on arrival.
+                let timestamp_sent = if timestamp_sent > 0 {
+                    timestamp_sent
+                } else {
+                    time()
+                };
+                match context
+                    .sql
+                    .execute(
+                        "INSERT INTO msgs_mdns (msg_id, contact_id, timestamp_sent) VALUES (?, ?, ?);",
+                        paramsv![msg_id, from_id as i32, timestamp_sent],
+                    )
                     .await
-                           .unwrap_or_default(); // TODO: better error handling
+                {
+                    Ok(_) => context.emit_event(EventType::MsgReadReceipt {
+                        msg_id,
+                        contact_id: from_id,
+                    }),
+                    Err(err) => warn!(context, "Failed to save MDN: {}", err),
+                }
             }
+        }
 
+        // Receipts arriving after the message is read by all do not change its state.
+        if msg_state == MessageState::OutPreparing
+            || msg_state == MessageState::OutPending
+            || msg_state == MessageState::OutDelivered
+        {
             // Normal chat? that's quite easy.
             if chat_type == Chattype::Single {
-                update_msg_state(context, msg_id, MessageState::OutMdnRcvd).await;
                 read_by_all = true;
             } else {
                 // send event about new state
@@ -1794,11 +2558,17 @@ pub async fn handle_mdn(
                 // for rounding, SELF is already included!
                 let soll_cnt = (chat::get_chat_contact_cnt(context, chat_id).await + 1) / 2;
                 if ist_cnt >= soll_cnt {
-                    update_msg_state(context, msg_id, MessageState::OutMdnRcvd).await;
                     read_by_all = true;
                 } // else wait for more receipts
             }
         }
+        if read_by_all {
+            if let Err(err) =
+                state_batch::queue(context, chat_id, msg_id, MessageState::OutMdnRcvd).await
+            {
+                warn!(context, "Failed to queue MDN state: {}", err);
+            }
+        }
         return if read_by_all {
             Some((chat_id, msg_id))
         } else {
@@ -1808,6 +2578,273 @@ pub async fn handle_mdn(
     None
 }
 
+/// Returns the contacts who sent a read receipt for the message
+/// together with the time the receipts were sent, oldest first.
+pub async fn get_read_receipts(context: &Context, msg_id: MsgId) -> Result<Vec<(u32, i64)>, Error> {
+    let receipts = context
+        .sql
+        .query_map(
+            "SELECT contact_id, timestamp_sent FROM msgs_mdns
+             WHERE msg_id=?
+             ORDER BY timestamp_sent, contact_id;",
+            paramsv![msg_id],
+            |row| {
+                let contact_id: u32 = row.get(0)?;
+                let timestamp: i64 = row.get(1)?;
+                Ok((contact_id, timestamp))
+            },
+            |rows| rows.collect::<Result<Vec<_>, _>>().map_err(Into::into),
+        )
+        .await?;
+    Ok(receipts)
+}
+
+/// Sets the reaction of a contact to a message, an empty reaction removes it.
+///
+/// A contact has at most one reaction to a message, a new reaction replaces the previous one.
+pub(crate) async fn set_reaction(
+    context: &Context,
+    msg: &Message,
+    contact_id: u32,
+    reaction: &str,
+) -> Result<(), Error> {
+    if reaction.is_empty() {
+        context
+            .sql
+            .execute(
+                "DELETE FROM reactions WHERE msg_id=? AND contact_id=?;",
+                paramsv![msg.id, contact_id],
+            )
+            .await?;
+    } else {
+        context
+            .sql
+            .execute(
+                "INSERT OR REPLACE INTO reactions (msg_id, contact_id, reaction) VALUES (?, ?, ?);",
+                paramsv![msg.id, contact_id, reaction],
+            )
+            .await?;
+    }
+    context.emit_event(EventType::ReactionsChanged {
+        chat_id: msg.chat_id,
+        msg_id: msg.id,
+        contact_id,
+    });
+    Ok(())
+}
+
+/// Returns the contacts who reacted to the message together with their reaction,
+/// ordered by contact ID.
+///
+/// Reactions of deleted contacts are not returned.
+pub async fn get_reactions(context: &Context, msg_id: MsgId) -> Result<Vec<(u32, String)>, Error> {
+    let reactions = context
+        .sql
+        .query_map(
+            "SELECT r.contact_id, r.reaction FROM reactions r
+             INNER JOIN contacts c ON c.id=r.contact_id
+             WHERE r.msg_id=?
+             ORDER BY r.contact_id;",
+            paramsv![msg_id],
+            |row| {
+                let contact_id: u32 = row.get(0)?;
+                let reaction: String = row.get(1)?;
+                Ok((contact_id, reaction))
+            },
+            |rows| rows.collect::<Result<Vec<_>, _>>().map_err(Into::into),
+        )
+        .await?;
+    Ok(reactions)
+}
+
+/// Name of the attachment carrying a status update.
+pub(crate) const STATUS_UPDATE_FILENAME: &str = "status-update.json";
+
+/// Maximum size of a single status update in bytes.
+pub const STATUS_UPDATE_MAX_SIZE: usize = 100_000;
+
+/// Maximum size of all status updates of a message in bytes.
+pub const STATUS_UPDATES_MAX_TOTAL_SIZE: usize = 1_000_000;
+
+/// An entry of the status-update log of a message, see [get_status_updates].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StatusUpdate {
+    /// Increasing number of the update in the order the updates arrived,
+    /// only valid on this device.
+    pub serial: u32,
+
+    /// The update as serialized JSON.
+    pub json: String,
+}
+
+/// Checks that a status update can be added to the log of a message.
+///
+/// Fails if the update is not valid JSON, is larger than [STATUS_UPDATE_MAX_SIZE]
+/// or the log would grow beyond [STATUS_UPDATES_MAX_TOTAL_SIZE].
+async fn check_status_update(context: &Context, msg: &Message, json: &str) -> Result<(), Error> {
+    ensure!(
+        chat::msgtype_has_file(msg.viewtype),
+        "message {} has no attachment to update",
+        msg.id
+    );
+    ensure!(
+        json.len() <= STATUS_UPDATE_MAX_SIZE,
+        "status update of {} bytes exceeds the limit of {} bytes",
+        json.len(),
+        STATUS_UPDATE_MAX_SIZE
+    );
+    serde_json::from_str::<serde_json::Value>(json)?;
+
+    let total: i64 = context
+        .sql
+        .query_get_value_result(
+            "SELECT IFNULL(SUM(LENGTH(CAST(update_item AS BLOB))), 0)
+             FROM msgs_status_updates WHERE msg_id=?;",
+            paramsv![msg.id],
+        )
+        .await?
+        .unwrap_or_default();
+    ensure!(
+        total as usize + json.len() <= STATUS_UPDATES_MAX_TOTAL_SIZE,
+        "status updates of message {} exceed the limit of {} bytes",
+        msg.id,
+        STATUS_UPDATES_MAX_TOTAL_SIZE
+    );
+    Ok(())
+}
+
+/// Appends a status update to the log of a message.
+///
+/// `timestamp` and `uid` are the Date and the Message-ID of the message carrying the update,
+/// they determine the order of the log, so that all members see the same order
+/// regardless of the order the updates arrived in.
+/// Fails for the reasons given at [check_status_update].
+/// Returns the serial of the appended update.
+pub(crate) async fn add_status_update(
+    context: &Context,
+    msg: &Message,
+    json: &str,
+    timestamp: i64,
+    uid: &str,
+) -> Result<u32, Error> {
+    check_status_update(context, msg, json).await?;
+    context
+        .sql
+        .execute(
+            "INSERT INTO msgs_status_updates (msg_id, update_item, timestamp, uid)
+             VALUES (?, ?, ?, ?);",
+            paramsv![msg.id, json, timestamp, uid],
+        )
+        .await?;
+    let serial = context
+        .sql
+        .get_rowid(context, "msgs_status_updates", "uid", uid)
+        .await?;
+
+    context.emit_event(EventType::MsgStatusUpdate {
+        chat_id: msg.chat_id,
+        msg_id: msg.id,
+    });
+    Ok(serial)
+}
+
+/// Sends a status update for a message with an attachment, e.g. an app or a document.
+///
+/// The update is sent as a hidden message to the chat, so that the other members append it
+/// to their log of the message, and appended to the log on this device, see [get_status_updates].
+/// If the hidden message cannot be sent, the update is removed from the log again,
+/// sending is retried as for other messages before.
+/// Returns the ID of the hidden message.
+pub async fn send_status_update(
+    context: &Context,
+    msg_id: MsgId,
+    json: &str,
+) -> Result<MsgId, Error> {
+    let target = Message::load_from_db(context, msg_id).await?;
+    ensure!(
+        !target.chat_id.is_special(),
+        "cannot update message {} in special chat",
+        msg_id
+    );
+    check_status_update(context, &target, json).await?;
+
+    let mut msg = Message::new(Viewtype::Text);
+    msg.hidden = true;
+    msg.in_reply_to = Some(target.rfc724_mid.clone());
+    msg.param.set_cmd(SystemMessage::StatusUpdate);
+    msg.param.set(Param::Arg, json);
+    let update_msg_id = chat::send_msg(context, target.chat_id, &mut msg).await?;
+    let update_msg = Message::load_from_db(context, update_msg_id).await?;
+    add_status_update(
+        context,
+        &target,
+        json,
+        update_msg.timestamp_sort,
+        &update_msg.rfc724_mid,
+    )
+    .await?;
+    Ok(update_msg_id)
+}
+
+/// Removes the status update carried by a message that could not be sent
+/// from the log of the updated message.
+async fn rollback_status_update(context: &Context, update_msg: &Message) -> Result<(), Error> {
+    let deleted = context
+        .sql
+        .execute(
+            "DELETE FROM msgs_status_updates WHERE uid=?;",
+            paramsv![update_msg.rfc724_mid],
+        )
+        .await?;
+    if deleted > 0 {
+        if let Some(target) = update_msg
+            .in_reply_to
+            .as_deref()
+            .and_then(|mid| parse_message_ids(mid).into_iter().next())
+        {
+            if let Some((_, _, target_id)) = rfc724_mid_exists(context, &target).await? {
+                context.emit_event(EventType::MsgStatusUpdate {
+                    chat_id: update_msg.chat_id,
+                    msg_id: target_id,
+                });
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Returns the status updates of a message with a serial larger than `since_serial`.
+///
+/// The updates are ordered by the time they were sent and the Message-ID of the messages
+/// carrying them, so all members see the same order.
+/// As updates may arrive late, an update received later may be sorted before updates
+/// returned before; to apply them in the final order, the log has to be read again.
+///
+/// Pass 0 as `since_serial` to get all updates.
+pub async fn get_status_updates(
+    context: &Context,
+    msg_id: MsgId,
+    since_serial: u32,
+) -> Result<Vec<StatusUpdate>, Error> {
+    let updates = context
+        .sql
+        .query_map(
+            "SELECT id, update_item FROM msgs_status_updates
+             WHERE msg_id=? AND id>?
+             ORDER BY timestamp, uid, id;",
+            paramsv![msg_id, since_serial],
+            |row| {
+                Ok(StatusUpdate {
+                    serial: row.get(0)?,
+                    json: row.get(1)?,
+                })
+            },
+            |rows| rows.collect::<Result<Vec<_>, _>>().map_err(Into::into),
+        )
+        .await?;
+    Ok(updates)
+}
+
 /// Marks a message as failed after an ndn (non-delivery-notification) arrived.
 /// Where appropriate, also adds an info message telling the user which of the recipients of a group message failed.
 pub(crate) async fn handle_ndn(
@@ -1846,7 +2883,7 @@ pub(crate) async fn handle_ndn(
 
     for (i, msg) in msgs.into_iter().enumerate() {
         let (msg_id, chat_id, chat_type) = msg?;
-        set_msg_failed(context, msg_id, error.as_ref()).await;
+        set_msg_failed(context, msg_id, ErrorCode::Ndn, error.as_ref()).await;
         if i == 0 {
             // Add only one info msg for all failed messages
             ndn_maybe_add_info_msg(context, failed, chat_id, chat_type).await?;
@@ -1884,7 +2921,7 @@ async fn ndn_maybe_add_info_msg(
             // If we get an NDN for the mailing list, just issue a warning.
             warn!(context, "ignoring NDN for mailing list.");
         }
-        Chattype::Single | Chattype::Undefined => {}
+        Chattype::Single | Chattype::Undefined | Chattype::Broadcast => {}
     }
     Ok(())
 }
@@ -1964,12 +3001,15 @@ pub async fn estimate_deletion_cnt(
              WHERE m.id > ?
                AND timestamp < ?
                AND chat_id != ?
-               AND chat_id != ? AND hidden = 0;",
+               AND chat_id != ? AND hidden = 0
+               AND state NOT IN (?, ?);",
                 paramsv![
                     DC_MSG_ID_LAST_SPECIAL,
                     threshold_timestamp,
                     self_chat_id,
-                    ChatId::new(DC_CHAT_ID_TRASH)
+                    ChatId::new(DC_CHAT_ID_TRASH),
+                    MessageState::InFresh,
+                    MessageState::InNoticed
                 ],
                 |row| row.get(0),
             )
@@ -1978,6 +3018,34 @@ pub async fn estimate_deletion_cnt(
     Ok(cnt as usize)
 }
 
+/// Removes the server UID of a message that is not on the server anymore.
+///
+/// Also called for messages which are not deleted on the server
+/// because other records point to the same server message.
+pub(crate) async fn unlink_deleted_msg(context: &Context, msg: &Message) -> crate::sql::Result<()> {
+    if msg.chat_id.is_trash() || msg.hidden {
+        // Messages are stored in trash chat only to keep
+        // their server UID and Message-ID. Once message is
+        // deleted from the server, database record can be
+        // removed as well.
+        //
+        // Hidden messages are similar to trashed, but are
+        // related to some chat. We also delete their
+        // database records.
+        msg.id.delete_from_db(context).await
+    } else {
+        // Remove server UID from the database record.
+        //
+        // We have either just removed the message from the
+        // server, in which case UID is not valid anymore, or
+        // we have more refernces to the same server UID, so
+        // we remove UID to reduce the number of messages
+        // pointing to the corresponding UID. Once the counter
+        // reaches zero, we will remove the message.
+        msg.id.unlink(context).await
+    }
+}
+
 /// Counts number of database records pointing to specified
 /// Message-ID.
 ///
@@ -2010,6 +3078,9 @@ pub(crate) async fn rfc724_mid_exists(
         warn!(context, "Empty rfc724_mid passed to rfc724_mid_exists");
         return Ok(None);
     }
+    if !context.sql.rfc724_mid_may_exist(rfc724_mid).await? {
+        return Ok(None);
+    }
 
     let res = context
         .sql
@@ -2051,50 +3122,386 @@ pub async fn update_server_uid(
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::chat::ChatItem;
-    use crate::constants::DC_CONTACT_ID_DEVICE;
-    use crate::dc_receive_imf::dc_receive_imf;
-    use crate::test_utils as test;
-    use crate::test_utils::TestContext;
+/// A message found by [search].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SearchResult {
+    pub msg_id: MsgId,
+    pub chat_id: ChatId,
+    pub timestamp: i64,
 
-    #[test]
-    fn test_guess_msgtype_from_suffix() {
-        assert_eq!(
-            guess_msgtype_from_suffix(Path::new("foo/bar-sth.mp3")),
-            Some((Viewtype::Audio, "audio/mpeg"))
-        );
+    /// The part of the message text around the first match.
+    ///
+    /// Cut off parts are marked with an ellipsis, line breaks are replaced by spaces.
+    pub snippet: String,
+}
+
+/// Number of characters shown before and after the match in [SearchResult::snippet].
+const SNIPPET_CONTEXT_CHARS: usize = 30;
+
+/// Letters with diacritics and the letters they are matched by.
+///
+/// Only lowercase letters are needed as the text is lowercased before folding.
+const DIACRITICS: [(&str, &str); 24] = [
+    ("a", "àáâãäåāăą"),
+    ("ae", "æ"),
+    ("c", "çćĉċč"),
+    ("d", "ðďđ"),
+    ("e", "èéêëēĕėęě"),
+    ("g", "ĝğġģ"),
+    ("h", "ĥħ"),
+    ("i", "ìíîïĩīĭįı"),
+    ("ij", "ĳ"),
+    ("j", "ĵ"),
+    ("k", "ķĸ"),
+    ("l", "ĺļľŀł"),
+    ("n", "ñńņňŉŋ"),
+    ("o", "òóôõöøōŏő"),
+    ("oe", "œ"),
+    ("r", "ŕŗř"),
+    ("s", "śŝşšſ"),
+    ("ss", "ß"),
+    ("t", "ţťŧ"),
+    ("th", "þ"),
+    ("u", "ùúûüũūŭůűų"),
+    ("w", "ŵ"),
+    ("y", "ýÿŷ"),
+    ("z", "źżž"),
+];
+
+/// Folds text for case and diacritic insensitive matching.
+///
+/// Each folded character is returned together with the index of the character
+/// of `text` it originates from.
+fn fold_for_search(text: &str) -> Vec<(char, usize)> {
+    let mut folded = Vec::with_capacity(text.len());
+    for (i, c) in text.chars().enumerate() {
+        for lower in c.to_lowercase() {
+            if ('\u{300}'..='\u{36f}').contains(&lower) {
+                // Combining diacritical mark, as in decomposed text.
+                continue;
+            }
+            match DIACRITICS
+                .iter()
+                .find(|(_, letters)| !lower.is_ascii() && letters.contains(lower))
+            {
+                Some((base, _)) => folded.extend(base.chars().map(|b| (b, i))),
+                None => folded.push((lower, i)),
+            }
+        }
     }
+    folded
+}
 
-    // chat_msg means that the message was sent by Delta Chat
-    // The tuples are (folder, mvbox_move, chat_msg, expected_destination)
-    const COMBINATIONS_ACCEPTED_CHAT: &[(&str, bool, bool, &str)] = &[
-        ("INBOX", false, false, "INBOX"),
-        ("INBOX", false, true, "INBOX"),
-        ("INBOX", true, false, "INBOX"),
-        ("INBOX", true, true, "DeltaChat"),
-        ("Sent", false, false, "Sent"),
-        ("Sent", false, true, "Sent"),
-        ("Sent", true, false, "Sent"),
-        ("Sent", true, true, "DeltaChat"),
-        ("Spam", false, false, "INBOX"), // Move classical emails in accepted chats from Spam to Inbox, not 100% sure on this, we could also just never move non-chat-msgs
-        ("Spam", false, true, "INBOX"),
-        ("Spam", true, false, "INBOX"), // Move classical emails in accepted chats from Spam to Inbox, not 100% sure on this, we could also just never move non-chat-msgs
-        ("Spam", true, true, "DeltaChat"),
-    ];
+/// Returns the range of characters of the original text matching `needle`.
+fn find_folded(haystack: &[(char, usize)], needle: &[char]) -> Option<(usize, usize)> {
+    if needle.is_empty() || haystack.len() < needle.len() {
+        return None;
+    }
+    let pos = haystack
+        .windows(needle.len())
+        .position(|window| window.iter().map(|(c, _)| c).eq(needle.iter()))?;
+    let first = haystack.get(pos)?.1;
+    let last = haystack.get(pos + needle.len() - 1)?.1;
+    Some((first, last + 1))
+}
 
-    // These are the same as above, but all messages in Spam stay in Spam
-    const COMBINATIONS_DEADDROP: &[(&str, bool, bool, &str)] = &[
-        ("INBOX", false, false, "INBOX"),
-        ("INBOX", false, true, "INBOX"),
-        ("INBOX", true, false, "INBOX"),
-        ("INBOX", true, true, "DeltaChat"),
-        ("Sent", false, false, "Sent"),
-        ("Sent", false, true, "Sent"),
-        ("Sent", true, false, "Sent"),
-        ("Sent", true, true, "DeltaChat"),
+fn make_snippet(text: &str, matched: Option<(usize, usize)>) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    let (match_start, match_end) = matched.unwrap_or((0, 0));
+    let start = match_start.saturating_sub(SNIPPET_CONTEXT_CHARS);
+    let end = std::cmp::min(chars.len(), match_end + SNIPPET_CONTEXT_CHARS);
+
+    let mut snippet = String::new();
+    if start > 0 {
+        snippet += DC_ELLIPSE;
+        snippet.push(' ');
+    }
+    snippet.extend(chars.get(start..end).unwrap_or_default().iter().map(|c| {
+        if c.is_whitespace() {
+            ' '
+        } else {
+            *c
+        }
+    }));
+    if end < chars.len() {
+        snippet.push(' ');
+        snippet += DC_ELLIPSE;
+    }
+    snippet
+}
+
+/// Where to continue a search.
+enum SearchStart {
+    Offset(usize),
+    Before { timestamp: i64, msg_id: MsgId },
+}
+
+/// Searches messages by text and sender name.
+///
+/// The search is case insensitive and ignores diacritics, e.g. `cafe` matches `Café`.
+/// Results are ordered newest first. If `chat_id` is given, only this chat is searched.
+///
+/// As new messages are added at the beginning of the results, paging by `offset` may return
+/// a message twice if messages arrive in between; use [search_before] to continue
+/// a search from its last result instead.
+pub async fn search(
+    context: &Context,
+    query: &str,
+    chat_id: Option<ChatId>,
+    offset: usize,
+    limit: usize,
+) -> Result<Vec<SearchResult>, Error> {
+    search_inner(context, query, chat_id, SearchStart::Offset(offset), limit).await
+}
+
+/// Continues a [search], returning results older than the message `before`.
+pub async fn search_before(
+    context: &Context,
+    query: &str,
+    chat_id: Option<ChatId>,
+    before: MsgId,
+    limit: usize,
+) -> Result<Vec<SearchResult>, Error> {
+    let timestamp: i64 = context
+        .sql
+        .query_get_value_result("SELECT timestamp FROM msgs WHERE id=?;", paramsv![before])
+        .await?
+        .ok_or_else(|| format_err!("Message {} does not exist", before))?;
+    let start = SearchStart::Before {
+        timestamp,
+        msg_id: before,
+    };
+    search_inner(context, query, chat_id, start, limit).await
+}
+
+/// Returns the number of messages [search] would find in total.
+pub async fn search_count(
+    context: &Context,
+    query: &str,
+    chat_id: Option<ChatId>,
+) -> Result<usize, Error> {
+    let filter = match SearchFilter::new(query, chat_id, &SearchStart::Offset(0)) {
+        Some(filter) => filter,
+        None => return Ok(0),
+    };
+    let count: i64 = context
+        .sql
+        .query_get_value_result(
+            format!(
+                "SELECT COUNT(*)
+                   FROM msgs m
+                   LEFT JOIN contacts ct ON m.from_id=ct.id
+                   LEFT JOIN chats c ON m.chat_id=c.id
+                  WHERE {};",
+                filter.condition
+            ),
+            filter.params(),
+        )
+        .await?
+        .unwrap_or_default();
+    Ok(usize::try_from(count).unwrap_or_default())
+}
+
+/// Folds text like [fold_for_search], to be compared in SQL.
+///
+/// The folded text of messages is stored in `msgs.txt_folded` when they are added,
+/// the function is also registered as the `search_fold()` SQL function.
+pub(crate) fn search_fold(text: &str) -> String {
+    fold_for_search(text).into_iter().map(|(c, _)| c).collect()
+}
+
+/// Escapes the `LIKE` wildcards in `text`, `\` is used as escape character.
+fn escape_like(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if c == '%' || c == '_' || c == '\\' {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// The `WHERE` clause of a search and its parameters.
+struct SearchFilter {
+    condition: String,
+    text_pattern: String,
+    name_pattern: String,
+    chat_id: Option<ChatId>,
+    before: Option<(i64, MsgId)>,
+}
+
+impl SearchFilter {
+    /// Returns `None` if the query matches nothing.
+    fn new(query: &str, chat_id: Option<ChatId>, start: &SearchStart) -> Option<Self> {
+        let needle = escape_like(&search_fold(query.trim()));
+        if needle.is_empty() {
+            return None;
+        }
+
+        let mut conditions = vec![
+            "m.hidden=0",
+            "ct.blocked=0",
+            // Sender names only match at the beginning, as before.
+            // The names are folded once per contact, not once per message.
+            "(m.txt_folded LIKE ? ESCAPE '\\' \
+              OR m.from_id IN (SELECT id FROM contacts WHERE search_fold(name) LIKE ? ESCAPE '\\'))",
+        ];
+        if chat_id.is_some() {
+            conditions.push("m.chat_id=?");
+        } else {
+            conditions.push("m.chat_id>9");
+            conditions.push("c.blocked=0");
+        }
+        let before = match start {
+            SearchStart::Offset(_) => None,
+            SearchStart::Before { timestamp, msg_id } => {
+                conditions.push("(m.timestamp<? OR (m.timestamp=? AND m.id<?))");
+                Some((*timestamp, *msg_id))
+            }
+        };
+        Some(SearchFilter {
+            condition: conditions.join(" AND "),
+            text_pattern: format!("%{}%", needle),
+            name_pattern: format!("{}%", needle),
+            chat_id,
+            before,
+        })
+    }
+
+    /// Returns the parameters in the order of the placeholders in the condition.
+    fn params(&self) -> Vec<&dyn crate::ToSql> {
+        let mut params: Vec<&dyn crate::ToSql> = vec![&self.text_pattern, &self.name_pattern];
+        if let Some(chat_id) = &self.chat_id {
+            params.push(chat_id);
+        }
+        if let Some((timestamp, msg_id)) = &self.before {
+            params.push(timestamp);
+            params.push(timestamp);
+            params.push(msg_id);
+        }
+        params
+    }
+}
+
+async fn search_inner(
+    context: &Context,
+    query: &str,
+    chat_id: Option<ChatId>,
+    start: SearchStart,
+    limit: usize,
+) -> Result<Vec<SearchResult>, Error> {
+    if limit == 0 {
+        return Ok(Vec::new());
+    }
+    let filter = match SearchFilter::new(query, chat_id, &start) {
+        Some(filter) => filter,
+        None => return Ok(Vec::new()),
+    };
+    let needle: Vec<char> = search_fold(query.trim()).chars().collect();
+    let offset = match start {
+        SearchStart::Offset(offset) => i64::try_from(offset).unwrap_or(i64::MAX),
+        SearchStart::Before { .. } => 0,
+    };
+    // A negative limit means no limit to SQLite.
+    let limit = i64::try_from(limit).unwrap_or(-1);
+
+    let sql = format!(
+        "SELECT m.id, m.chat_id, m.timestamp, m.txt
+           FROM msgs m
+           LEFT JOIN contacts ct ON m.from_id=ct.id
+           LEFT JOIN chats c ON m.chat_id=c.id
+          WHERE {}
+          ORDER BY m.timestamp DESC, m.id DESC
+          LIMIT ? OFFSET ?;",
+        filter.condition
+    );
+    let mut params = filter.params();
+    params.push(&limit);
+    params.push(&offset);
+
+    context
+        .sql
+        .query_map(
+            sql,
+            params,
+            |row| {
+                let msg_id: MsgId = row.get(0)?;
+                let chat_id: ChatId = row.get(1)?;
+                let timestamp: i64 = row.get(2)?;
+                let text: String = row.get::<_, Option<String>>(3)?.unwrap_or_default();
+                Ok((msg_id, chat_id, timestamp, text))
+            },
+            |rows| {
+                let mut results = Vec::new();
+                for row in rows {
+                    let (msg_id, chat_id, timestamp, text) = row?;
+                    let matched = find_folded(&fold_for_search(&text), &needle);
+                    results.push(SearchResult {
+                        msg_id,
+                        chat_id,
+                        timestamp,
+                        snippet: make_snippet(&text, matched),
+                    });
+                }
+                Ok(results)
+            },
+        )
+        .await
+        .map_err(Into::into)
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::indexing_slicing)]
+
+    use std::collections::HashSet;
+
+    use super::*;
+    use crate::chat::ChatItem;
+    use crate::constants::DC_CONTACT_ID_DEVICE;
+    use crate::dc_receive_imf::dc_receive_imf;
+    use crate::events::Event;
+    use crate::key::DcKey;
+    use crate::peerstate::ToSave;
+    use crate::test_utils as test;
+    use crate::test_utils::TestContext;
+
+    #[test]
+    fn test_guess_msgtype_from_suffix() {
+        assert_eq!(
+            guess_msgtype_from_suffix(Path::new("foo/bar-sth.mp3")),
+            Some((Viewtype::Audio, "audio/mpeg"))
+        );
+    }
+
+    // chat_msg means that the message was sent by Delta Chat
+    // The tuples are (folder, mvbox_move, chat_msg, expected_destination)
+    const COMBINATIONS_ACCEPTED_CHAT: &[(&str, bool, bool, &str)] = &[
+        ("INBOX", false, false, "INBOX"),
+        ("INBOX", false, true, "INBOX"),
+        ("INBOX", true, false, "INBOX"),
+        ("INBOX", true, true, "DeltaChat"),
+        ("Sent", false, false, "Sent"),
+        ("Sent", false, true, "Sent"),
+        ("Sent", true, false, "Sent"),
+        ("Sent", true, true, "DeltaChat"),
+        ("Spam", false, false, "INBOX"), // Move classical emails in accepted chats from Spam to Inbox, not 100% sure on this, we could also just never move non-chat-msgs
+        ("Spam", false, true, "INBOX"),
+        ("Spam", true, false, "INBOX"), // Move classical emails in accepted chats from Spam to Inbox, not 100% sure on this, we could also just never move non-chat-msgs
+        ("Spam", true, true, "DeltaChat"),
+    ];
+
+    // These are the same as above, but all messages in Spam stay in Spam
+    const COMBINATIONS_DEADDROP: &[(&str, bool, bool, &str)] = &[
+        ("INBOX", false, false, "INBOX"),
+        ("INBOX", false, true, "INBOX"),
+        ("INBOX", true, false, "INBOX"),
+        ("INBOX", true, true, "DeltaChat"),
+        ("Sent", false, false, "Sent"),
+        ("Sent", false, true, "Sent"),
+        ("Sent", true, false, "Sent"),
+        ("Sent", true, true, "DeltaChat"),
         ("Spam", false, false, "Spam"),
         ("Spam", false, true, "Spam"),
         ("Spam", true, false, "Spam"),
@@ -2531,6 +3938,57 @@ async fn test_quote() {
         assert!(quoted_msg.get_text() == msg2.quoted_text());
     }
 
+    #[async_std::test]
+    async fn test_quote_over_the_wire() {
+        let alice = TestContext::new_alice().await;
+        let bob = TestContext::new_bob().await;
+        let alice_chat = alice.create_chat(&bob).await;
+        let bob_chat = bob.create_chat(&alice).await;
+
+        let sent = alice.send_text(alice_chat.id, "Quoted message").await;
+        let alice_quoted = alice.get_last_msg_in(alice_chat.id).await;
+        bob.recv_msg(&sent).await;
+        let bob_quoted = bob.get_last_msg_in(bob_chat.id).await;
+
+        let mut msg = Message::new(Viewtype::Text);
+        msg.set_text(Some("Reply".to_string()));
+        msg.set_quote(&bob, &bob_quoted).await.unwrap();
+        let sent = bob.send_msg(bob_chat.id, &mut msg).await;
+        let bob_reply = bob.get_last_msg_in(bob_chat.id).await;
+        assert_eq!(bob_reply.quoted_msg_id, bob_quoted.id);
+        let quoted = bob_reply.quoted_message(&bob).await.unwrap().unwrap();
+        assert_eq!(quoted.id, bob_quoted.id);
+
+        alice.recv_msg(&sent).await;
+        let alice_reply = alice.get_last_msg_in(alice_chat.id).await;
+        assert_eq!(alice_reply.get_text(), Some("Reply".to_string()));
+        assert_eq!(
+            alice_reply.quoted_text(),
+            Some("Quoted message".to_string())
+        );
+        assert_eq!(alice_reply.quoted_msg_id, alice_quoted.id);
+        let quoted = alice_reply.quoted_message(&alice).await.unwrap().unwrap();
+        assert_eq!(quoted.id, alice_quoted.id);
+
+        // The snapshot is kept after the quoted message is deleted,
+        // but there is nothing to jump to anymore.
+        delete_msgs(&alice, &[alice_quoted.id]).await;
+        let alice_reply = Message::load_from_db(&alice, alice_reply.id).await.unwrap();
+        assert_eq!(
+            alice_reply.quoted_text(),
+            Some("Quoted message".to_string())
+        );
+        assert!(alice_reply.quoted_message(&alice).await.unwrap().is_none());
+
+        // The same if the quoted message is removed from the database.
+        bob.sql
+            .execute("DELETE FROM msgs WHERE id=?;", paramsv![bob_quoted.id])
+            .await
+            .unwrap();
+        assert_eq!(bob_reply.quoted_text(), Some("Quoted message".to_string()));
+        assert!(bob_reply.quoted_message(&bob).await.unwrap().is_none());
+    }
+
     #[async_std::test]
     async fn test_get_chat_id() {
         // Alice receives a message that pops up as a contact request
@@ -2605,4 +4063,673 @@ async fn test_set_override_sender_name() {
         let chat = Chat::load_from_db(&bob, msg.chat_id).await.unwrap();
         assert_ne!(chat.typ, Chattype::Mailinglist);
     }
+
+    #[test]
+    fn test_fold_for_search() {
+        let fold = search_fold;
+        assert_eq!(fold("Café AU Lait"), "cafe au lait");
+        assert_eq!(fold("Straße"), "strasse");
+        assert_eq!(fold("Cafe\u{301}"), "cafe");
+        assert_eq!(fold("Ελληνικά"), "ελληνικά");
+    }
+
+    #[test]
+    fn test_make_snippet() {
+        let text = format!("{} needle {}", "a".repeat(50), "b".repeat(50));
+        let needle: Vec<char> = "NEEDLE".to_lowercase().chars().collect();
+        let matched = find_folded(&fold_for_search(&text), &needle);
+        assert_eq!(matched, Some((51, 57)));
+        assert_eq!(
+            make_snippet(&text, matched),
+            format!(
+                "{} {} needle {} {}",
+                DC_ELLIPSE,
+                "a".repeat(29),
+                "b".repeat(29),
+                DC_ELLIPSE
+            )
+        );
+
+        assert_eq!(make_snippet("short\ntext", None), "short text");
+    }
+
+    #[async_std::test]
+    async fn test_search_scoping_and_folding() {
+        let t = TestContext::new_alice().await;
+        let chat1 = t.create_chat_with_contact("bob", "bob@example.net").await;
+        let chat2 = t
+            .create_chat_with_contact("claire", "claire@example.net")
+            .await;
+        t.send_text(chat1.id, "Meet me at the Café").await;
+        t.send_text(chat2.id, "the cafe is closed").await;
+        t.send_text(chat2.id, "nothing to see").await;
+
+        let results = search(&t, "CAFE", None, 0, 10).await.unwrap();
+        assert_eq!(results.len(), 2);
+        assert_eq!(results.get(0).unwrap().chat_id, chat2.id);
+        assert_eq!(results.get(0).unwrap().snippet, "the cafe is closed");
+        assert_eq!(results.get(1).unwrap().chat_id, chat1.id);
+
+        let results = search(&t, "café", Some(chat1.id), 0, 10).await.unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results.get(0).unwrap().snippet, "Meet me at the Café");
+
+        assert_eq!(search_count(&t, "cafe", None).await.unwrap(), 2);
+        assert_eq!(search_count(&t, "cafe", Some(chat2.id)).await.unwrap(), 1);
+        assert_eq!(search_count(&t, "  ", None).await.unwrap(), 0);
+        assert_eq!(search_count(&t, "%", None).await.unwrap(), 0);
+        assert_eq!(search_count(&t, "c_fe", None).await.unwrap(), 0);
+    }
+
+    #[async_std::test]
+    async fn test_search_folded_text() {
+        async fn txt_folded(t: &TestContext, msg_id: MsgId) -> String {
+            t.sql
+                .query_get_value_result("SELECT txt_folded FROM msgs WHERE id=?;", paramsv![msg_id])
+                .await
+                .unwrap()
+                .unwrap()
+        }
+
+        let t = TestContext::new_alice().await;
+        let chat = t.create_chat_with_contact("bob", "bob@example.net").await;
+        let sent = t.send_text(chat.id, "Café STRASSE").await.sender_msg_id;
+        assert_eq!(txt_folded(&t, sent).await, "cafe strasse");
+
+        // Sender names are searched as well.
+        test::receive_chat_msg(&t, "bob@example.net", 1).await;
+        let results = search(&t, "BO", None, 0, 10).await.unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results.get(0).unwrap().snippet, "hello");
+
+        // The stored folded text is searched.
+        t.sql
+            .execute(
+                "UPDATE msgs SET txt_folded='boulevard' WHERE id=?;",
+                paramsv![sent],
+            )
+            .await
+            .unwrap();
+        assert_eq!(search_count(&t, "strasse", None).await.unwrap(), 0);
+        assert_eq!(search_count(&t, "boulevard", None).await.unwrap(), 1);
+
+        sent.trash(&t).await.unwrap();
+        assert_eq!(txt_folded(&t, sent).await, "");
+    }
+
+    #[async_std::test]
+    async fn test_search_paging() {
+        let t = TestContext::new_alice().await;
+        let chat = t.create_chat_with_contact("bob", "bob@example.net").await;
+        let mut sent = Vec::new();
+        for i in 0..5 {
+            let msg = t.send_text(chat.id, &format!("photo {}", i)).await;
+            sent.push(msg.sender_msg_id);
+        }
+        t.send_text(chat.id, "no match").await;
+
+        let page1 = search(&t, "photo", Some(chat.id), 0, 2).await.unwrap();
+        let ids: Vec<MsgId> = page1.iter().map(|r| r.msg_id).collect();
+        assert_eq!(ids, vec![*sent.get(4).unwrap(), *sent.get(3).unwrap()]);
+
+        // A new message arrives while paging.
+        t.send_text(chat.id, "photo 5").await;
+        let last = page1.last().unwrap().msg_id;
+        let page2 = search_before(&t, "photo", Some(chat.id), last, 2)
+            .await
+            .unwrap();
+        let ids: Vec<MsgId> = page2.iter().map(|r| r.msg_id).collect();
+        assert_eq!(ids, vec![*sent.get(2).unwrap(), *sent.get(1).unwrap()]);
+
+        let last = page2.last().unwrap().msg_id;
+        let page3 = search_before(&t, "photo", Some(chat.id), last, 2)
+            .await
+            .unwrap();
+        let ids: Vec<MsgId> = page3.iter().map(|r| r.msg_id).collect();
+        assert_eq!(ids, vec![*sent.get(0).unwrap()]);
+
+        // Paging by offset sees the new message.
+        let page = search(&t, "photo", Some(chat.id), 2, 2).await.unwrap();
+        let ids: Vec<MsgId> = page.iter().map(|r| r.msg_id).collect();
+        assert_eq!(ids, vec![*sent.get(3).unwrap(), *sent.get(2).unwrap()]);
+        assert_eq!(search_count(&t, "photo", Some(chat.id)).await.unwrap(), 6);
+    }
+
+    #[async_std::test]
+    async fn test_get_read_receipts() {
+        let t = TestContext::new_alice().await;
+        let chat_id = chat::create_group_chat(&t, chat::ProtectionStatus::Unprotected, "grp")
+            .await
+            .unwrap();
+        let mut members = Vec::new();
+        for addr in &["bob@example.net", "claire@example.org", "dave@example.com"] {
+            let contact_id = Contact::create(&t, "", addr).await.unwrap();
+            assert!(chat::add_contact_to_chat(&t, chat_id, contact_id).await);
+            members.push(contact_id);
+        }
+        let (bob, claire, dave) = (members[0], members[1], members[2]);
+        let msg_id = chat::send_text_msg(&t, chat_id, "hi".to_string())
+            .await
+            .unwrap();
+        let msg = Message::load_from_db(&t, msg_id).await.unwrap();
+
+        let (event_tx, event_rx) = async_std::channel::unbounded();
+        t.add_event_sink(move |event: Event| {
+            let event_tx = event_tx.clone();
+            async move {
+                match event.typ {
+                    EventType::MsgReadReceipt { msg_id, contact_id } => {
+                        event_tx.try_send(Some((msg_id, contact_id))).unwrap()
+                    }
+                    EventType::Info(msg) if msg == "checkpoint" => event_tx.try_send(None).unwrap(),
+                    _ => {}
+                }
+            }
+        })
+        .await;
+
+        // Half of the members are enough to consider the message read.
+        assert_eq!(handle_mdn(&t, claire, &msg.rfc724_mid, 2000).await, None);
+        assert_eq!(
+            handle_mdn(&t, bob, &msg.rfc724_mid, 1000).await,
+            Some((chat_id, msg_id))
+        );
+        assert_eq!(handle_mdn(&t, claire, &msg.rfc724_mid, 3000).await, None);
+        state_batch::flush(&t).await.unwrap();
+        assert_eq!(
+            Message::load_from_db(&t, msg_id).await.unwrap().state,
+            MessageState::OutMdnRcvd
+        );
+
+        // Later receipts are still recorded, receipts without a date get the time of arrival.
+        let before = time();
+        assert_eq!(handle_mdn(&t, dave, &msg.rfc724_mid, 0).await, None);
+
+        let receipts = get_read_receipts(&t, msg_id).await.unwrap();
+        assert_eq!(receipts.len(), 3);
+        assert_eq!(receipts[0], (bob, 1000));
+        assert_eq!(receipts[1], (claire, 2000));
+        assert_eq!(receipts[2].0, dave);
+        assert!(receipts[2].1 >= before);
+        assert_eq!(msg.get_read_receipts_count(&t).await, 3);
+
+        t.emit_event(EventType::Info("checkpoint".to_string()));
+        let mut events = Vec::new();
+        while let Some(event) = event_rx.recv().await.unwrap() {
+            events.push(event);
+        }
+        assert_eq!(
+            events,
+            vec![(msg_id, claire), (msg_id, bob), (msg_id, dave)]
+        );
+    }
+
+    #[async_std::test]
+    async fn test_reaction_over_the_wire() {
+        let alice = TestContext::new_alice().await;
+        let bob = TestContext::new_bob().await;
+        let alice_chat = alice.create_chat(&bob).await;
+        let bob_chat = bob.create_chat(&alice).await;
+
+        let sent = alice.send_text(alice_chat.id, "Hi Bob").await;
+        let alice_msg = alice.get_last_msg_in(alice_chat.id).await;
+        bob.recv_msg(&sent).await;
+        let bob_msg = bob.get_last_msg_in(bob_chat.id).await;
+        assert_eq!(bob_msg.get_text(), Some("Hi Bob".to_string()));
+
+        chat::send_reaction(&bob, bob_msg.id, "👍").await.unwrap();
+        assert_eq!(
+            get_reactions(&bob, bob_msg.id).await.unwrap(),
+            vec![(DC_CONTACT_ID_SELF, "👍".to_string())]
+        );
+
+        // The reaction is not shown as a message in the chat.
+        alice.recv_msg(&bob.pop_sent_msg().await).await;
+        assert_eq!(alice.get_last_msg_in(alice_chat.id).await.id, alice_msg.id);
+        assert_eq!(alice_chat.id.get_fresh_msg_cnt(&alice).await, 0);
+
+        let bob_id = Contact::lookup_id_by_addr(&alice, "bob@example.net", Origin::Unknown)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            get_reactions(&alice, alice_msg.id).await.unwrap(),
+            vec![(bob_id, "👍".to_string())]
+        );
+    }
+
+    #[async_std::test]
+    async fn test_reaction_replaced_and_removed() {
+        let alice = TestContext::new_alice().await;
+        let bob = TestContext::new_bob().await;
+        let alice_chat = alice.create_chat(&bob).await;
+        let bob_chat = bob.create_chat(&alice).await;
+
+        let sent = alice.send_text(alice_chat.id, "Hi Bob").await;
+        let alice_msg = alice.get_last_msg_in(alice_chat.id).await;
+        bob.recv_msg(&sent).await;
+        let bob_msg = bob.get_last_msg_in(bob_chat.id).await;
+        let bob_id = Contact::lookup_id_by_addr(&alice, "bob@example.net", Origin::Unknown)
+            .await
+            .unwrap()
+            .unwrap();
+
+        chat::send_reaction(&bob, bob_msg.id, "👍").await.unwrap();
+        alice.recv_msg(&bob.pop_sent_msg().await).await;
+        chat::send_reaction(&bob, bob_msg.id, "❤️").await.unwrap();
+        alice.recv_msg(&bob.pop_sent_msg().await).await;
+        assert_eq!(
+            get_reactions(&alice, alice_msg.id).await.unwrap(),
+            vec![(bob_id, "❤️".to_string())]
+        );
+
+        chat::send_reaction(&bob, bob_msg.id, "").await.unwrap();
+        assert!(get_reactions(&bob, bob_msg.id).await.unwrap().is_empty());
+        alice.recv_msg(&bob.pop_sent_msg().await).await;
+        assert!(get_reactions(&alice, alice_msg.id)
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[async_std::test]
+    async fn test_reactions_of_deleted_contact() {
+        let t = TestContext::new_alice().await;
+        let chat = t.create_chat_with_contact("bob", "bob@example.net").await;
+        let msg_id = t.send_text(chat.id, "Hi").await.sender_msg_id;
+        let msg = Message::load_from_db(&t, msg_id).await.unwrap();
+
+        let bob = Contact::lookup_id_by_addr(&t, "bob@example.net", Origin::Unknown)
+            .await
+            .unwrap()
+            .unwrap();
+        let claire = Contact::create(&t, "", "claire@example.net").await.unwrap();
+        set_reaction(&t, &msg, bob, "👍").await.unwrap();
+        set_reaction(&t, &msg, claire, "😀").await.unwrap();
+        assert_eq!(get_reactions(&t, msg_id).await.unwrap().len(), 2);
+
+        Contact::delete(&t, claire).await.unwrap();
+        assert_eq!(
+            get_reactions(&t, msg_id).await.unwrap(),
+            vec![(bob, "👍".to_string())]
+        );
+    }
+
+    async fn send_file_msg(t: &TestContext, chat_id: ChatId) -> test::SentMessage {
+        let file = t.get_blobdir().join("app.json");
+        async_std::fs::write(&file, b"{}").await.unwrap();
+        let mut msg = Message::new(Viewtype::File);
+        msg.set_file(file.to_str().unwrap(), None);
+        t.send_msg(chat_id, &mut msg).await
+    }
+
+    fn payloads(updates: &[StatusUpdate]) -> Vec<&str> {
+        updates.iter().map(|update| update.json.as_str()).collect()
+    }
+
+    #[async_std::test]
+    async fn test_status_updates_over_the_wire() {
+        let alice = TestContext::new_alice().await;
+        let bob = TestContext::new_bob().await;
+        let alice_chat = alice.create_chat(&bob).await;
+        let bob_chat = bob.create_chat(&alice).await;
+
+        let sent = send_file_msg(&alice, alice_chat.id).await;
+        let alice_msg_id = sent.sender_msg_id;
+        bob.recv_msg(&sent).await;
+        let bob_msg = bob.get_last_msg_in(bob_chat.id).await;
+        assert_eq!(bob_msg.get_viewtype(), Viewtype::File);
+
+        send_status_update(&alice, alice_msg_id, r#"{"move":1}"#)
+            .await
+            .unwrap();
+        bob.recv_msg(&alice.pop_sent_msg().await).await;
+        send_status_update(&bob, bob_msg.id, r#"{"move":2}"#)
+            .await
+            .unwrap();
+        alice.recv_msg(&bob.pop_sent_msg().await).await;
+
+        let alice_updates = get_status_updates(&alice, alice_msg_id, 0).await.unwrap();
+        let bob_updates = get_status_updates(&bob, bob_msg.id, 0).await.unwrap();
+        assert_eq!(
+            payloads(&alice_updates),
+            vec![r#"{"move":1}"#, r#"{"move":2}"#]
+        );
+        assert_eq!(payloads(&alice_updates), payloads(&bob_updates));
+
+        let first = alice_updates.get(0).unwrap().serial;
+        let since_first = get_status_updates(&alice, alice_msg_id, first)
+            .await
+            .unwrap();
+        assert_eq!(payloads(&since_first), vec![r#"{"move":2}"#]);
+        assert!(since_first.get(0).unwrap().serial > first);
+
+        // The hidden update messages are not shown in the chats.
+        assert_eq!(alice.get_last_msg_in(alice_chat.id).await.id, alice_msg_id);
+        assert_eq!(bob.get_last_msg_in(bob_chat.id).await.id, bob_msg.id);
+        assert_eq!(alice_chat.id.get_fresh_msg_cnt(&alice).await, 0);
+    }
+
+    #[async_std::test]
+    async fn test_status_updates_order() {
+        let alice = TestContext::new_alice().await;
+        let bob = TestContext::new_bob().await;
+        let alice_chat = alice.create_chat(&bob).await;
+        let bob_chat = bob.create_chat(&alice).await;
+        let sent = send_file_msg(&alice, alice_chat.id).await;
+        let alice_msg_id = sent.sender_msg_id;
+        bob.recv_msg(&sent).await;
+        let bob_msg = bob.get_last_msg_in(bob_chat.id).await;
+
+        // Both send an update before receiving the update of the other one.
+        send_status_update(&bob, bob_msg.id, r#"{"move":2}"#)
+            .await
+            .unwrap();
+        send_status_update(&alice, alice_msg_id, r#"{"move":1}"#)
+            .await
+            .unwrap();
+        alice.recv_msg(&bob.pop_sent_msg().await).await;
+        bob.recv_msg(&alice.pop_sent_msg().await).await;
+
+        let alice_updates = get_status_updates(&alice, alice_msg_id, 0).await.unwrap();
+        let bob_updates = get_status_updates(&bob, bob_msg.id, 0).await.unwrap();
+        assert_eq!(alice_updates.len(), 2);
+        assert_eq!(payloads(&alice_updates), payloads(&bob_updates));
+    }
+
+    #[async_std::test]
+    async fn test_status_update_rollback() {
+        let t = TestContext::new_alice().await;
+        let chat = t.create_chat_with_contact("bob", "bob@example.net").await;
+        let msg_id = send_file_msg(&t, chat.id).await.sender_msg_id;
+        send_status_update(&t, msg_id, r#"{"move":1}"#)
+            .await
+            .unwrap();
+        let second = send_status_update(&t, msg_id, r#"{"move":2}"#)
+            .await
+            .unwrap();
+        assert_eq!(get_status_updates(&t, msg_id, 0).await.unwrap().len(), 2);
+
+        // A transient error keeps the update, the message is sent again later.
+        let error = MsgError::new(ErrorCode::Network, "no network");
+        set_msg_transient_error(&t, second, &error).await;
+        assert_eq!(get_status_updates(&t, msg_id, 0).await.unwrap().len(), 2);
+
+        set_msg_failed(&t, second, ErrorCode::SmtpPermanent, Some("rejected")).await;
+        let updates = get_status_updates(&t, msg_id, 0).await.unwrap();
+        assert_eq!(payloads(&updates), vec![r#"{"move":1}"#]);
+    }
+
+    #[async_std::test]
+    async fn test_status_update_limits() {
+        let t = TestContext::new_alice().await;
+        let chat = t.create_chat_with_contact("bob", "bob@example.net").await;
+        let text_msg_id = t.send_text(chat.id, "no attachment").await.sender_msg_id;
+        assert!(send_status_update(&t, text_msg_id, "{}").await.is_err());
+
+        let msg_id = send_file_msg(&t, chat.id).await.sender_msg_id;
+        let msg = Message::load_from_db(&t, msg_id).await.unwrap();
+        assert!(
+            add_status_update(&t, &msg, "{not json", 1, "invalid@example.org")
+                .await
+                .is_err()
+        );
+        let too_large = format!("\"{}\"", "a".repeat(STATUS_UPDATE_MAX_SIZE - 1));
+        assert!(
+            add_status_update(&t, &msg, &too_large, 1, "too-large@example.org")
+                .await
+                .is_err()
+        );
+
+        let largest = format!("\"{}\"", "a".repeat(STATUS_UPDATE_MAX_SIZE - 2));
+        for i in 0..STATUS_UPDATES_MAX_TOTAL_SIZE / STATUS_UPDATE_MAX_SIZE {
+            let uid = format!("{}@example.org", i);
+            add_status_update(&t, &msg, &largest, 1, &uid)
+                .await
+                .unwrap();
+        }
+        assert!(add_status_update(&t, &msg, "1", 1, "last@example.org")
+            .await
+            .is_err());
+        assert!(send_status_update(&t, msg_id, "1").await.is_err());
+        assert_eq!(
+            get_status_updates(&t, msg_id, 0).await.unwrap().len(),
+            STATUS_UPDATES_MAX_TOTAL_SIZE / STATUS_UPDATE_MAX_SIZE
+        );
+
+        msg_id.delete_from_db(&t).await.unwrap();
+        assert!(get_status_updates(&t, msg_id, 0).await.unwrap().is_empty());
+    }
+
+    #[async_std::test]
+    async fn test_get_info_structured_encrypted() {
+        let alice = TestContext::new_alice().await;
+        let bob = TestContext::new_bob().await;
+
+        // Bob learns Alice's key from her first message and replies encrypted.
+        let alice_chat = alice.create_chat(&bob).await;
+        let sent = alice.send_text(alice_chat.get_id(), "hi bob").await;
+        bob.recv_msg(&sent).await;
+        let bob_chat = bob.create_chat(&alice).await;
+        let sent = bob.send_text(bob_chat.get_id(), "hi alice").await;
+        alice.recv_msg(&sent).await;
+
+        let msg = alice.get_last_msg_in(alice_chat.get_id()).await;
+        assert!(msg.get_showpadlock());
+        let info = msg.get_info_structured(&alice).await.unwrap();
+        assert_eq!(info.msg_id, msg.id);
+        assert!(info.from.contains("bob@example.net"));
+        assert!(info.timestamp_received.is_some());
+        assert_eq!(info.state, MessageState::InFresh);
+        assert!(!info.is_info);
+        assert_eq!(
+            info.encryption,
+            Some(MsgEncryptionInfo {
+                valid_signature: true,
+                self_fingerprint: Some(test::alice_keypair().public.fingerprint().hex()),
+                peer_keys: vec![MsgPeerKey {
+                    contact_id: msg.from_id,
+                    addr: "bob@example.net".to_string(),
+                    fingerprint: test::bob_keypair().public.fingerprint().hex(),
+                    verified: false,
+                }],
+            })
+        );
+        assert!(info.read_receipts.is_empty());
+        assert_eq!(info.error, None);
+        assert_eq!(info.ephemeral_timer, None);
+        assert_eq!(info.ephemeral_timestamp, None);
+        assert_eq!(info.mimetype, None);
+        assert_eq!(info.file, None);
+        assert_eq!(info.rfc724_mid, Some(msg.rfc724_mid.clone()));
+        assert_eq!(info.server_location, Some(("INBOX".to_string(), 1)));
+        assert!(!info.has_raw_headers);
+
+        // Bob's key changes later, the key used for the message is still reported.
+        let mut peerstate = Peerstate::from_addr(&alice, "bob@example.net")
+            .await
+            .unwrap()
+            .unwrap();
+        let new_key = test::alice_keypair().public;
+        peerstate.public_key_fingerprint = Some(new_key.fingerprint());
+        peerstate.public_key = Some(new_key.clone());
+        peerstate.to_save = Some(ToSave::All);
+        peerstate.save_to_db(&alice.sql, false).await.unwrap();
+        alice
+            .sql
+            .execute(
+                "UPDATE peerstate_history SET timestamp=? WHERE new_fingerprint=?;",
+                paramsv![time() + 10, new_key.fingerprint().hex()],
+            )
+            .await
+            .unwrap();
+        let info = msg.get_info_structured(&alice).await.unwrap();
+        let peer_keys = info.encryption.unwrap().peer_keys;
+        assert_eq!(
+            peer_keys.first().unwrap().fingerprint,
+            test::bob_keypair().public.fingerprint().hex()
+        );
+
+        let text = get_msg_info(&alice, msg.id).await;
+        assert!(text.contains("Encrypted"));
+        assert!(text.contains("Last seen as: INBOX/1"));
+    }
+
+    #[async_std::test]
+    async fn test_get_info_structured_failed() {
+        let t = TestContext::new_alice().await;
+        let chat = t.create_chat_with_contact("", "bob@example.net").await;
+        let sent = t.send_text(chat.get_id(), "hi").await;
+        let msg_id = sent.sender_msg_id;
+        set_msg_failed(
+            &t,
+            msg_id,
+            ErrorCode::SmtpPermanent,
+            Some("550 no such user"),
+        )
+        .await;
+
+        let msg = Message::load_from_db(&t, msg_id).await.unwrap();
+        let info = msg.get_info_structured(&t).await.unwrap();
+        assert_eq!(info.from_id, DC_CONTACT_ID_SELF);
+        assert_eq!(info.timestamp_received, None);
+        assert_eq!(info.state, MessageState::OutFailed);
+        assert_eq!(info.encryption, None);
+        assert_eq!(info.error, Some("550 no such user".to_string()));
+        assert_eq!(info.server_location, None);
+
+        let text = get_msg_info(&t, msg_id).await;
+        assert!(text.contains("Error: 550 no such user"));
+        assert!(!text.contains("Received:"));
+    }
+
+    #[async_std::test]
+    async fn test_voice_waveform() -> Result<(), Error> {
+        let alice = TestContext::new_alice().await;
+        let bob = TestContext::new_bob().await;
+        let alice_chat = alice.create_chat(&bob).await;
+
+        let blob = BlobObject::create(
+            &alice,
+            "voice.opus",
+            include_bytes!("../test-data/message/voice.opus"),
+        )
+        .await?;
+        let waveform = generate_waveform(&alice, &blob).await?;
+        assert_eq!(waveform.len(), crate::waveform::WAVEFORM_BUCKETS);
+        assert_eq!(waveform.iter().max(), Some(&255));
+        // The fixture gets quieter after one second.
+        assert!(waveform.first() > waveform.last());
+
+        // The sidecar file is kept as long as the blob is in use.
+        let name = format!(
+            "{}.waveform",
+            blob.as_name().trim_start_matches("$BLOBDIR/")
+        );
+        assert!(alice.get_blobdir().join(&name).exists().await);
+        let mut files_in_use = HashSet::new();
+        files_in_use.insert(blob.as_name().trim_start_matches("$BLOBDIR/").to_string());
+        let mut unreferenced_count = 0;
+        assert!(
+            !crate::sql::delete_blob_if_unreferenced(
+                &alice,
+                &files_in_use,
+                &alice.get_blobdir().join(&name),
+                &name,
+                &mut unreferenced_count,
+            )
+            .await
+        );
+        assert_eq!(unreferenced_count, 0);
+
+        let mut msg = Message::new(Viewtype::Voice);
+        msg.set_file(blob.as_name(), Some("audio/ogg"));
+        let sent = alice.send_msg(alice_chat.id, &mut msg).await;
+        let alice_msg = alice.get_last_msg_in(alice_chat.id).await;
+        assert_eq!(alice_msg.get_waveform(), Some(waveform.clone()));
+
+        bob.recv_msg(&sent).await;
+        let bob_msg = bob.get_last_msg().await;
+        assert_eq!(bob_msg.get_viewtype(), Viewtype::Voice);
+        assert_eq!(bob_msg.get_waveform(), Some(waveform));
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn test_restore_from_trash() -> Result<(), Error> {
+        let t = TestContext::new_alice().await;
+        t.set_config(Config::TrashGracePeriod, Some("3600")).await?;
+        let chat = t.create_chat_with_contact("", "bob@example.net").await;
+        let msg1 = t.send_text(chat.id, "first").await.sender_msg_id;
+        let msg2 = t.send_text(chat.id, "second").await.sender_msg_id;
+
+        delete_msgs(&t, &[msg1, msg2]).await;
+        let trashed = chat::get_trashed_msgs(&t, 10, 0).await?;
+        assert_eq!(trashed.len(), 2);
+        assert!(trashed
+            .iter()
+            .all(|trashed| trashed.chat_id == Some(chat.id)));
+        assert!(trashed.iter().all(|trashed| trashed.deleted_timestamp > 0));
+        assert_eq!(chat::get_trashed_msgs(&t, 10, 1).await?.len(), 1);
+        assert_eq!(chat::get_chat_msgs(&t, chat.id, 0, None).await.len(), 0);
+
+        let (event_tx, event_rx) = async_std::channel::bounded(10);
+        t.add_event_sink(move |event: Event| {
+            let event_tx = event_tx.clone();
+            async move {
+                if let EventType::MsgsChanged { chat_id, msg_id } = event.typ {
+                    event_tx.try_send((chat_id, msg_id)).ok();
+                }
+            }
+        })
+        .await;
+        assert_eq!(restore_from_trash(&t, &[msg1]).await?, 1);
+        assert_eq!(event_rx.recv().await.unwrap(), (chat.id, msg1));
+        let msg = Message::load_from_db(&t, msg1).await?;
+        assert_eq!(msg.chat_id, chat.id);
+        assert_eq!(msg.get_text(), Some("first".to_string()));
+        assert!(!msg.param.exists(Param::TrashedChatId));
+        let jobs: i64 = t
+            .sql
+            .query_get_value_result(
+                "SELECT COUNT(*) FROM jobs WHERE action=? AND foreign_id=?;",
+                paramsv![Action::DeleteMsgOnImap, msg1],
+            )
+            .await?
+            .unwrap_or_default();
+        assert_eq!(jobs, 0);
+
+        // Restoring twice does nothing.
+        assert_eq!(restore_from_trash(&t, &[msg1]).await?, 0);
+
+        // Within the grace period, nothing is pruned.
+        crate::sql::housekeeping(&t).await?;
+        let trashed = chat::get_trashed_msgs(&t, 10, 0).await?;
+        assert_eq!(trashed.len(), 1);
+        assert_eq!(trashed[0].msg_id, msg2);
+
+        t.sql
+            .execute(
+                "UPDATE msgs SET deleted_timestamp=deleted_timestamp-7200 WHERE id=?;",
+                paramsv![msg2],
+            )
+            .await?;
+        crate::sql::housekeeping(&t).await?;
+        assert!(chat::get_trashed_msgs(&t, 10, 0).await?.is_empty());
+        assert!(Message::load_from_db(&t, msg2).await.is_err());
+        assert_eq!(restore_from_trash(&t, &[msg1]).await?, 0);
+
+        // A chat reusing the ID of the original chat is not restored into.
+        let msg3 = t.send_text(chat.id, "third").await.sender_msg_id;
+        delete_msgs(&t, &[msg3]).await;
+        t.sql
+            .execute(
+                "UPDATE chats SET created_timestamp=created_timestamp+1 WHERE id=?;",
+                paramsv![chat.id],
+            )
+            .await?;
+        assert_eq!(restore_from_trash(&t, &[msg3]).await?, 0);
+        assert!(Message::load_from_db(&t, msg3).await?.chat_id.is_trash());
+        Ok(())
+    }
 }
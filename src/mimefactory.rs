@@ -1,5 +1,6 @@
 use crate::blob::BlobObject;
 use crate::chat::{self, Chat};
+use crate::chunks;
 use crate::config::Config;
 use crate::constants::{Chattype, Viewtype, DC_FROM_HANDSHAKE};
 use crate::contact::Contact;
@@ -17,9 +18,10 @@
 use crate::message::{self, Message, MsgId};
 use crate::mimeparser::SystemMessage;
 use crate::param::Param;
-use crate::peerstate::{Peerstate, PeerstateVerifiedStatus};
+use crate::peerstate::{peerstate_key, Peerstate, PeerstateVerifiedStatus};
 use crate::simplify::escape_message_footer_marks;
 use crate::stock_str;
+use crate::sync::SYNC_ITEMS_FILENAME;
 use anyhow::Context as _;
 use anyhow::{bail, ensure, format_err, Error};
 use chrono::TimeZone;
@@ -31,7 +33,7 @@
 // as an upper limit, we double the size; the core won't send messages larger than this
 // to get the netto sizes, we subtract 1 mb header-overhead and the base64-overhead.
 pub const RECOMMENDED_FILE_SIZE: u64 = 24 * 1024 * 1024 / 4 * 3;
-const UPPER_LIMIT_FILE_SIZE: u64 = 49 * 1024 * 1024 / 4 * 3;
+pub(crate) const UPPER_LIMIT_FILE_SIZE: u64 = 49 * 1024 * 1024 / 4 * 3;
 
 #[derive(Debug, Clone)]
 pub enum Loaded {
@@ -111,6 +113,11 @@ pub async fn from_msg(
 
         if chat.is_self_talk() {
             recipients.push((from_displayname.to_string(), from_addr.to_string()));
+        } else if chat.is_mailing_list() {
+            // Posts go to the list only, the list distributes them to the members.
+            if let Some(list_post) = chat.param.get(Param::ListPost) {
+                recipients.push((String::new(), list_post.to_string()));
+            }
         } else {
             context
                 .sql
@@ -235,16 +242,18 @@ async fn peerstates_for_recipients(
             .await
             .ok_or_else(|| format_err!("Not configured"))?;
 
-        let mut res = Vec::new();
-        for (_, addr) in self
+        let addrs: Vec<&str> = self
             .recipients
             .iter()
-            .filter(|(_, addr)| addr != &self_addr)
-        {
-            res.push((Peerstate::from_addr(context, addr).await?, addr.as_str()));
-        }
+            .map(|(_, addr)| addr.as_str())
+            .filter(|addr| *addr != self_addr)
+            .collect();
+        let peerstates = Peerstate::from_addrs(context, &addrs).await?;
 
-        Ok(res)
+        Ok(addrs
+            .into_iter()
+            .map(|addr| (peerstates.get(&peerstate_key(addr)).cloned(), addr))
+            .collect())
     }
 
     fn is_e2ee_guaranteed(&self) -> bool {
@@ -406,6 +415,15 @@ pub fn recipients(&self) -> Vec<String> {
             .collect()
     }
 
+    /// Removes all recipients except for `addr`.
+    ///
+    /// Used to render the separate copies of a broadcast message.
+    pub(crate) fn retain_recipient(&mut self, addr: &str) {
+        let addr_lc = addr.to_lowercase();
+        self.recipients
+            .retain(|(_, cur)| cur.to_lowercase() == addr_lc);
+    }
+
     pub async fn render(mut self, context: &Context) -> Result<RenderedEmail, Error> {
         // Headers that are encrypted
         // - Chat-*, except Chat-Version
@@ -501,7 +519,16 @@ pub async fn render(mut self, context: &Context) -> Result<RenderedEmail, Error>
         protected_headers.push(Header::new("Subject".into(), encoded_subject));
 
         let rfc724_mid = match self.loaded {
-            Loaded::Message { .. } => self.msg.rfc724_mid.clone(),
+            Loaded::Message { .. } => {
+                if let Some(resend_of) = self.msg.param.get(Param::ResendOf) {
+                    // Receivers having the original message recognize the copy by this header.
+                    protected_headers.push(Header::new(
+                        "Chat-Resend-Of".into(),
+                        render_rfc724_mid(resend_of),
+                    ));
+                }
+                self.msg.rfc724_mid.clone()
+            }
             Loaded::MDN { .. } => dc_create_outgoing_rfc724_mid(None, &self.from_addr),
         };
 
@@ -660,8 +687,8 @@ pub async fn render(mut self, context: &Context) -> Result<RenderedEmail, Error>
     }
 
     fn get_message_kml_part(&self) -> Option<PartBuilder> {
-        let latitude = self.msg.param.get_float(Param::SetLatitude)?;
-        let longitude = self.msg.param.get_float(Param::SetLongitude)?;
+        let latitude = self.msg.param.get_f64(Param::SetLatitude)?;
+        let longitude = self.msg.param.get_f64(Param::SetLongitude)?;
 
         let kml_file = location::get_message_kml(self.msg.timestamp_sort, latitude, longitude);
         let part = PartBuilder::new()
@@ -678,6 +705,81 @@ fn get_message_kml_part(&self) -> Option<PartBuilder> {
         Some(part)
     }
 
+    fn get_sync_items_part(&self) -> Option<PartBuilder> {
+        if self.msg.param.get_cmd() != SystemMessage::ConfigSync {
+            return None;
+        }
+        let items = self.msg.param.get(Param::Arg)?;
+        let part = PartBuilder::new()
+            .content_type(&mime::APPLICATION_JSON)
+            .header((
+                "Content-Disposition",
+                format!("attachment; filename=\"{}\"", SYNC_ITEMS_FILENAME),
+            ))
+            .body(items);
+        Some(part)
+    }
+
+    fn get_status_update_part(&self) -> Option<PartBuilder> {
+        if self.msg.param.get_cmd() != SystemMessage::StatusUpdate {
+            return None;
+        }
+        let json = self.msg.param.get(Param::Arg)?;
+        let part = PartBuilder::new()
+            .content_type(&mime::APPLICATION_JSON)
+            .header((
+                "Content-Disposition",
+                format!(
+                    "attachment; filename=\"{}\"",
+                    message::STATUS_UPDATE_FILENAME
+                ),
+            ))
+            .body(json);
+        Some(part)
+    }
+
+    fn get_chunk_request_part(&self) -> Option<PartBuilder> {
+        if self.msg.param.get_cmd() != SystemMessage::ChunkRequest {
+            return None;
+        }
+        let json = self.msg.param.get(Param::Arg)?;
+        let part = PartBuilder::new()
+            .content_type(&mime::APPLICATION_JSON)
+            .header((
+                "Content-Disposition",
+                format!(
+                    "attachment; filename=\"{}\"",
+                    chunks::CHUNK_REQUEST_FILENAME
+                ),
+            ))
+            .body(json);
+        Some(part)
+    }
+
+    /// Returns the part of the attachment sent by a [SystemMessage::Chunk] message.
+    async fn get_chunk_part(&self, context: &Context) -> Result<Option<PartBuilder>, Error> {
+        if self.msg.param.get_cmd() != SystemMessage::Chunk {
+            return Ok(None);
+        }
+        let path = self
+            .msg
+            .param
+            .get_path(Param::File, context)?
+            .ok_or_else(|| format_err!("chunk message has no file"))?;
+        let index = self.msg.param.get_i64(Param::Arg).unwrap_or_default();
+        let chunk_size = self.msg.param.get_i64(Param::Arg2).unwrap_or_default();
+        let data = chunks::read_chunk(&path, index as u64, chunk_size as u64).await?;
+        let part = PartBuilder::new()
+            .content_type(&mime::APPLICATION_OCTET_STREAM)
+            .header((
+                "Content-Disposition",
+                format!("attachment; filename=\"{}\"", chunks::CHUNK_FILENAME),
+            ))
+            .header(("Content-Transfer-Encoding", "base64"))
+            .body(wrapped_base64_encode(&data));
+        Ok(Some(part))
+    }
+
     async fn get_location_kml_part(&mut self, context: &Context) -> Result<PartBuilder, Error> {
         let (kml_content, last_added_location_id) =
             location::get_kml(context, self.msg.chat_id).await?;
@@ -793,6 +895,54 @@ async fn render_message(
                     "ephemeral-timer-changed".to_string(),
                 ));
             }
+            SystemMessage::ConfigSync => {
+                protected_headers.push(Header::new(
+                    "Chat-Content".to_string(),
+                    "config-sync".to_string(),
+                ));
+                unprotected_headers.push(Header::new(
+                    "Auto-Submitted".to_string(),
+                    "auto-generated".to_string(),
+                ));
+            }
+            SystemMessage::Reaction => {
+                protected_headers.push(Header::new(
+                    "Chat-Content".to_string(),
+                    "reaction".to_string(),
+                ));
+                // A missing reaction removes the previous reaction.
+                let reaction = self.msg.param.get(Param::Arg).unwrap_or_default();
+                if !reaction.is_empty() {
+                    protected_headers.push(Header::new(
+                        "Chat-Reaction".to_string(),
+                        maybe_encode_words(reaction),
+                    ));
+                }
+            }
+            SystemMessage::StatusUpdate => {
+                protected_headers.push(Header::new(
+                    "Chat-Content".to_string(),
+                    "status-update".to_string(),
+                ));
+            }
+            SystemMessage::Chunk => {
+                protected_headers
+                    .push(Header::new("Chat-Content".to_string(), "chunk".to_string()));
+                protected_headers.push(Header::new(
+                    "Chat-Chunk".to_string(),
+                    self.msg
+                        .param
+                        .get_int(Param::Arg)
+                        .unwrap_or_default()
+                        .to_string(),
+                ));
+            }
+            SystemMessage::ChunkRequest => {
+                protected_headers.push(Header::new(
+                    "Chat-Content".to_string(),
+                    "chunk-request".to_string(),
+                ));
+            }
             SystemMessage::LocationOnly => {
                 // This should prevent automatic replies,
                 // such as non-delivery reports.
@@ -898,6 +1048,12 @@ async fn render_message(
         {
             if self.msg.viewtype == Viewtype::Voice {
                 protected_headers.push(Header::new("Chat-Voice-Message".into(), "1".into()));
+                if let Some(waveform) = self.msg.param.get(Param::Waveform) {
+                    protected_headers.push(Header::new(
+                        "Chat-Voice-Waveform".into(),
+                        waveform.to_string(),
+                    ));
+                }
             }
             let duration_ms = self.msg.param.get_int(Param::Duration).unwrap_or_default();
             if duration_ms > 0 {
@@ -980,7 +1136,21 @@ async fn render_message(
 
         // add attachment part
         if chat::msgtype_has_file(self.msg.viewtype) {
-            if !is_file_size_okay(context, self.msg).await {
+            if let Some(manifest) = self.msg.param.get(Param::ChunkManifest) {
+                // The attachment is sent in separate messages, see [crate::chunks].
+                parts.push(
+                    PartBuilder::new()
+                        .content_type(&mime::APPLICATION_JSON)
+                        .header((
+                            "Content-Disposition",
+                            format!(
+                                "attachment; filename=\"{}\"",
+                                chunks::CHUNK_MANIFEST_FILENAME
+                            ),
+                        ))
+                        .body(manifest),
+                );
+            } else if !is_file_size_okay(context, self.msg).await {
                 bail!(
                     "Message exceeds the recommended {} MB.",
                     RECOMMENDED_FILE_SIZE / 1_000_000,
@@ -999,6 +1169,22 @@ async fn render_message(
             parts.push(msg_kml_part);
         }
 
+        if let Some(sync_part) = self.get_sync_items_part() {
+            parts.push(sync_part);
+        }
+
+        if let Some(status_update_part) = self.get_status_update_part() {
+            parts.push(status_update_part);
+        }
+
+        if let Some(chunk_part) = self.get_chunk_part(context).await? {
+            parts.push(chunk_part);
+        }
+
+        if let Some(chunk_request_part) = self.get_chunk_request_part() {
+            parts.push(chunk_request_part);
+        }
+
         if location::is_sending_locations_to_chat(context, Some(self.msg.chat_id)).await {
             match self.get_location_kml_part(context).await {
                 Ok(part) => parts.push(part),
@@ -12,13 +12,13 @@
 
 use crate::aheader::Aheader;
 use crate::blob::BlobObject;
+use crate::chunks;
 use crate::constants::{Viewtype, DC_DESIRED_TEXT_LEN, DC_ELLIPSE};
-use crate::contact::addr_normalize;
+use crate::contact::addr_cmp;
 use crate::context::Context;
-use crate::dc_tools::{dc_get_filemeta, dc_truncate};
+use crate::dc_tools::{dc_get_filemeta, dc_truncate, normalize_addr};
 use crate::dehtml::dehtml;
 use crate::e2ee;
-use crate::events::EventType;
 use crate::format_flowed::unformat_flowed;
 use crate::headerdef::{HeaderDef, HeaderDefMap};
 use crate::key::Fingerprint;
@@ -28,6 +28,8 @@
 use crate::peerstate::Peerstate;
 use crate::simplify::simplify;
 use crate::stock_str;
+use crate::sync::{SyncItems, SYNC_ITEMS_FILENAME};
+use crate::waveform::MAX_RECEIVED_WAVEFORM_LEN;
 
 /// A parsed MIME message.
 ///
@@ -61,6 +63,16 @@ pub struct MimeMessage {
     pub is_system_message: SystemMessage,
     pub location_kml: Option<location::Kml>,
     pub message_kml: Option<location::Kml>,
+    pub(crate) sync_items: Option<SyncItems>,
+
+    /// Status update of a message with an attachment, see [message::send_status_update].
+    pub(crate) status_update: Option<String>,
+
+    /// Part of an attachment sent in several parts, see [crate::chunks].
+    pub(crate) chunk_data: Option<Vec<u8>>,
+
+    /// Request for missing parts of an attachment, see [crate::chunks].
+    pub(crate) chunk_request: Option<String>,
     pub(crate) user_avatar: Option<AvatarAction>,
     pub(crate) group_avatar: Option<AvatarAction>,
     pub(crate) mdn_reports: Vec<Report>,
@@ -124,6 +136,22 @@ pub enum SystemMessage {
     // Chat protection state changed
     ChatProtectionEnabled = 11,
     ChatProtectionDisabled = 12,
+
+    /// Hidden self-sent message synchronizing settings to other devices.
+    ConfigSync = 13,
+
+    /// Hidden message reacting to the message it replies to.
+    Reaction = 14,
+
+    /// Hidden message carrying a status update of the message it replies to.
+    StatusUpdate = 15,
+
+    /// Hidden message carrying a part of the attachment of the message it replies to,
+    /// see [crate::chunks].
+    Chunk = 16,
+
+    /// Hidden message requesting missing parts of the attachment of the message it replies to.
+    ChunkRequest = 17,
 }
 
 impl Default for SystemMessage {
@@ -136,6 +164,23 @@ fn default() -> Self {
 
 impl MimeMessage {
     pub async fn from_bytes(context: &Context, body: &[u8]) -> Result<Self> {
+        MimeMessage::from_bytes_with_partial(context, body, None, false).await
+    }
+
+    /// Parses a message.
+    ///
+    /// If `partial` is set, `body` contains only the header of the message
+    /// and `partial` is the size of the full message.
+    /// The message is not decrypted then and gets a single placeholder part.
+    ///
+    /// If `is_import` is set, the Autocrypt header of the message is not applied,
+    /// key gossip is still processed.
+    pub(crate) async fn from_bytes_with_partial(
+        context: &Context,
+        body: &[u8],
+        partial: Option<u32>,
+        is_import: bool,
+    ) -> Result<Self> {
         let mail = mailparse::parse_mail(body)?;
 
         let message_time = mail
@@ -167,8 +212,11 @@ pub async fn from_bytes(context: &Context, body: &[u8]) -> Result<Self> {
         let mut mail_raw = Vec::new();
         let mut gossipped_addr = Default::default();
 
-        let (mail, signatures, warn_empty_signature) =
-            match e2ee::try_decrypt(context, &mail, message_time).await {
+        let (mail, signatures, warn_empty_signature) = if partial.is_some() {
+            // The encrypted part is not downloaded.
+            (mail, Default::default(), false)
+        } else {
+            match e2ee::try_decrypt(context, &mail, message_time, !is_import).await {
                 Ok((raw, signatures)) => {
                     if let Some(raw) = raw {
                         // Encrypted, but maybe unsigned message. Only if
@@ -234,7 +282,8 @@ pub async fn from_bytes(context: &Context, body: &[u8]) -> Result<Self> {
                     warn!(context, "decryption failed: {}", err);
                     (mail, Default::default(), true)
                 }
-            };
+            }
+        };
 
         let mut parser = MimeMessage {
             parts: Vec::new(),
@@ -252,6 +301,10 @@ pub async fn from_bytes(context: &Context, body: &[u8]) -> Result<Self> {
             is_system_message: SystemMessage::Unknown,
             location_kml: None,
             message_kml: None,
+            sync_items: None,
+            status_update: None,
+            chunk_data: None,
+            chunk_request: None,
             user_avatar: None,
             group_avatar: None,
             failure_report: None,
@@ -259,10 +312,19 @@ pub async fn from_bytes(context: &Context, body: &[u8]) -> Result<Self> {
             is_mime_modified: false,
             decoded_data: Vec::new(),
         };
-        parser.parse_mime_recursive(context, &mail, false).await?;
-        parser.maybe_remove_bad_parts();
-        parser.maybe_remove_inline_mailinglist_footer();
-        parser.heuristically_parse_ndn(context).await;
+        match partial {
+            Some(org_bytes) => {
+                parser
+                    .create_stub_from_partial_download(context, org_bytes)
+                    .await;
+            }
+            None => {
+                parser.parse_mime_recursive(context, &mail, false).await?;
+                parser.maybe_remove_bad_parts();
+                parser.maybe_remove_inline_mailinglist_footer();
+                parser.heuristically_parse_ndn(context).await;
+            }
+        }
         parser.parse_headers(context);
 
         if warn_empty_signature && parser.signatures.is_empty() {
@@ -305,6 +367,16 @@ fn parse_system_message_headers(&mut self, context: &Context) {
                 self.is_system_message = SystemMessage::ChatProtectionEnabled;
             } else if value == "protection-disabled" {
                 self.is_system_message = SystemMessage::ChatProtectionDisabled;
+            } else if value == "config-sync" {
+                self.is_system_message = SystemMessage::ConfigSync;
+            } else if value == "reaction" {
+                self.is_system_message = SystemMessage::Reaction;
+            } else if value == "status-update" {
+                self.is_system_message = SystemMessage::StatusUpdate;
+            } else if value == "chunk" {
+                self.is_system_message = SystemMessage::Chunk;
+            } else if value == "chunk-request" {
+                self.is_system_message = SystemMessage::ChunkRequest;
             }
         }
     }
@@ -382,6 +454,13 @@ fn parse_attachments(&mut self) {
         if let Some(mut part) = self.parts.pop() {
             if part.typ == Viewtype::Audio && self.get(HeaderDef::ChatVoiceMessage).is_some() {
                 part.typ = Viewtype::Voice;
+                if let Some(waveform) = self.get(HeaderDef::ChatVoiceWaveform) {
+                    if base64::decode(waveform).map_or(false, |waveform| {
+                        !waveform.is_empty() && waveform.len() <= MAX_RECEIVED_WAVEFORM_LEN
+                    }) {
+                        part.param.set(Param::Waveform, waveform);
+                    }
+                }
             }
             if part.typ == Viewtype::Image {
                 if let Some(value) = self.get(HeaderDef::ChatContent) {
@@ -451,7 +530,7 @@ fn parse_headers(&mut self, context: &Context) {
         if !self.decrypting_failed && !self.parts.is_empty() {
             if let Some(ref dn_to) = self.chat_disposition_notification_to {
                 if let Some(from) = self.from.get(0) {
-                    if from.addr.to_lowercase() == dn_to.addr.to_lowercase() {
+                    if addr_cmp(&from.addr, &dn_to.addr) {
                         if let Some(part) = self.parts.last_mut() {
                             part.param.set_int(Param::WantsMdn, 1);
                         }
@@ -909,27 +988,75 @@ async fn do_add_single_file_part(
                 return;
             }
         }
+        if filename == message::STATUS_UPDATE_FILENAME {
+            self.status_update = Some(String::from_utf8_lossy(decoded_data).into_owned());
+            return;
+        }
+        if filename == chunks::CHUNK_FILENAME {
+            self.chunk_data = Some(decoded_data.to_vec());
+            return;
+        }
+        if filename == chunks::CHUNK_REQUEST_FILENAME {
+            self.chunk_request = Some(String::from_utf8_lossy(decoded_data).into_owned());
+            return;
+        }
+        if filename == chunks::CHUNK_MANIFEST_FILENAME {
+            match serde_json::from_slice::<chunks::Manifest>(decoded_data) {
+                Ok(manifest) => {
+                    // The file is added once all parts arrived.
+                    let mut part = Part {
+                        typ: manifest.viewtype,
+                        org_filename: Some(manifest.name.clone()),
+                        mimetype: manifest.mimetype.parse().ok(),
+                        bytes: manifest.size as usize,
+                        is_related,
+                        ..Default::default()
+                    };
+                    part.param.set(Param::MimeType, &manifest.mimetype);
+                    part.param
+                        .set(Param::ChunkManifest, String::from_utf8_lossy(decoded_data));
+                    self.do_add_single_part(part);
+                }
+                Err(err) => warn!(context, "failed to parse chunk manifest: {}", err),
+            }
+            return;
+        }
+        if filename == SYNC_ITEMS_FILENAME {
+            self.sync_items = SyncItems::parse(decoded_data)
+                .map_err(|err| {
+                    warn!(context, "failed to parse sync items: {}", err);
+                })
+                .ok();
+            return;
+        }
         /* we have a regular file attachment,
         write decoded data to new blob object */
 
         let blob = match BlobObject::create(context, filename, decoded_data).await {
-            Ok(blob) => blob,
+            Ok(blob) => Some(blob),
             Err(err) => {
-                error!(
+                if context.check_blobdir_writable().await {
+                    error!(
+                        context,
+                        "Could not add blob for mime part {}, error {}", filename, err
+                    );
+                    return;
+                }
+                // The message is still shown, the user can ask the sender to send the file again.
+                warn!(
                     context,
-                    "Could not add blob for mime part {}, error {}", filename, err
+                    "Could not save mime part {}, the blob directory is read-only", filename
                 );
-                return;
+                None
             }
         };
-        info!(context, "added blobfile: {:?}", blob.as_name());
 
         /* create and register Mime part referencing the new Blob object */
         let mut part = Part::default();
         if mime_type.type_() == mime::IMAGE {
             if let Ok((width, height)) = dc_get_filemeta(decoded_data) {
-                part.param.set_int(Param::Width, width as i32);
-                part.param.set_int(Param::Height, height as i32);
+                part.param.set_i64(Param::Width, width.into());
+                part.param.set_i64(Param::Height, height.into());
             }
         }
 
@@ -937,13 +1064,36 @@ async fn do_add_single_file_part(
         part.org_filename = Some(filename.to_string());
         part.mimetype = Some(mime_type);
         part.bytes = decoded_data.len();
-        part.param.set(Param::File, blob.as_name());
+        match blob {
+            Some(blob) => {
+                info!(context, "added blobfile: {:?}", blob.as_name());
+                part.param.set(Param::File, blob.as_name());
+            }
+            None => {
+                part.param.set_int(Param::MediaNotSaved, 1);
+            }
+        }
         part.param.set(Param::MimeType, raw_mime);
         part.is_related = is_related;
 
         self.do_add_single_part(part);
     }
 
+    /// Adds the placeholder part of a partially downloaded message, see [crate::download].
+    async fn create_stub_from_partial_download(&mut self, context: &Context, org_bytes: u32) {
+        let mut param = Params::new();
+        param.set_i64(Param::FullMessageSize, org_bytes.into());
+        self.do_add_single_part(Part {
+            typ: Viewtype::Text,
+            msg: format!(
+                "[{}]",
+                stock_str::partial_download_msg_body(context, org_bytes).await
+            ),
+            param,
+            ..Default::default()
+        });
+    }
+
     fn do_add_single_part(&mut self, mut part: Part) {
         if self.was_encrypted() {
             part.param.set_int(Param::GuaranteeE2ee, 1);
@@ -1215,11 +1365,7 @@ pub async fn handle_reports(
             for original_message_id in
                 std::iter::once(&report.original_message_id).chain(&report.additional_message_ids)
             {
-                if let Some((chat_id, msg_id)) =
-                    message::handle_mdn(context, from_id, original_message_id, sent_timestamp).await
-                {
-                    context.emit_event(EventType::MsgRead { chat_id, msg_id });
-                }
+                message::handle_mdn(context, from_id, original_message_id, sent_timestamp).await;
             }
         }
 
@@ -1272,7 +1418,7 @@ async fn update_gossip_peerstates(
         if let Ok(ref header) = gossip_header {
             if get_recipients(&mail.headers)
                 .iter()
-                .any(|info| info.addr == header.addr.to_lowercase())
+                .any(|info| addr_cmp(&info.addr, &header.addr))
             {
                 let mut peerstate = Peerstate::from_addr(context, &header.addr).await?;
                 if let Some(ref mut peerstate) = peerstate {
@@ -1550,14 +1696,14 @@ fn get_all_addresses_from_header<F>(headers: &[MailHeader], pred: F) -> Vec<Sing
                 match addr {
                     mailparse::MailAddr::Single(ref info) => {
                         result.push(SingleInfo {
-                            addr: addr_normalize(&info.addr).to_lowercase(),
+                            addr: normalize_addr(&info.addr),
                             display_name: info.display_name.clone(),
                         });
                     }
                     mailparse::MailAddr::Group(ref infos) => {
                         for info in &infos.addrs {
                             result.push(SingleInfo {
-                                addr: addr_normalize(&info.addr).to_lowercase(),
+                                addr: normalize_addr(&info.addr),
                                 display_name: info.display_name.clone(),
                             });
                         }
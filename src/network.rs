@@ -0,0 +1,412 @@
+//! # Network timeouts and retries.
+//!
+//! [NetworkPolicy] holds the timeouts and the retry behaviour used by the IMAP and SMTP
+//! connection code.  It is read from the `connect_timeout_secs`, `io_timeout_secs`,
+//! `max_retries` and `retry_base_delay` config keys on every connection attempt,
+//! so changes take effect on the next connection without a restart.
+//!
+//! Connecting is a single attempt.  Failed IMAP connections are retried by the fake IDLE
+//! loop, see [NetworkPolicy::reconnect_delay], SMTP connections by the job backoff.
+//!
+//! Keys that are not set fall back to a preset.  If the SOCKS5 proxy points at one of the
+//! default Tor ports, the Tor preset with longer timeouts is used, as building circuits
+//! takes much longer than connecting directly.
+
+use std::fmt;
+use std::future::Future;
+use std::time::Duration;
+
+use async_std::io;
+
+use crate::config::Config;
+use crate::context::Context;
+use crate::socks::Socks5Config;
+
+/// Default SOCKS5 ports of the Tor daemon and of the Tor Browser.
+const TOR_PORTS: [u16; 2] = [9050, 9150];
+
+/// Allowed range of the connect timeout in seconds.
+const CONNECT_TIMEOUT_RANGE: (u64, u64) = (1, 300);
+
+/// Allowed range of the IO timeout in seconds.
+const IO_TIMEOUT_RANGE: (u64, u64) = (1, 600);
+
+/// Allowed range of the number of retries.
+const MAX_RETRIES_RANGE: (u64, u64) = (0, 10);
+
+/// Allowed range of the base delay between retries in seconds.
+const RETRY_BASE_DELAY_RANGE: (u64, u64) = (0, 60);
+
+/// Upper bound of the delay between two connection attempts.
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(5 * 60);
+
+/// Timeouts and retry behaviour of IMAP and SMTP connections.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NetworkPolicy {
+    /// Time allowed for establishing a connection, from connecting the socket
+    /// up to receiving the server greeting, including the proxy and TLS handshakes.
+    pub connect_timeout: Duration,
+
+    /// Time allowed for a single command on an established connection.
+    pub io_timeout: Duration,
+
+    /// Number of quick reconnection attempts after a connection failed,
+    /// further attempts are made at the regular polling interval.
+    pub max_retries: u32,
+
+    /// Delay before the first retry, doubled for each further retry.
+    pub retry_base_delay: Duration,
+
+    /// True if the Tor preset was applied.
+    pub tor: bool,
+}
+
+impl Default for NetworkPolicy {
+    fn default() -> Self {
+        NetworkPolicy {
+            connect_timeout: Duration::from_secs(20),
+            io_timeout: Duration::from_secs(30),
+            max_retries: 1,
+            retry_base_delay: Duration::from_secs(2),
+            tor: false,
+        }
+    }
+}
+
+impl NetworkPolicy {
+    /// Preset for connections through Tor.
+    pub fn tor() -> Self {
+        NetworkPolicy {
+            connect_timeout: Duration::from_secs(90),
+            io_timeout: Duration::from_secs(120),
+            max_retries: 3,
+            retry_base_delay: Duration::from_secs(5),
+            tor: true,
+        }
+    }
+
+    /// Reads the effective policy from the config, clamping the values to sane ranges.
+    pub async fn from_database(context: &Context) -> Self {
+        let tor = Socks5Config::from_database(context)
+            .await
+            .map_or(false, |socks5_config| {
+                TOR_PORTS.contains(&socks5_config.port)
+            });
+        let preset = if tor {
+            NetworkPolicy::tor()
+        } else {
+            NetworkPolicy::default()
+        };
+
+        NetworkPolicy {
+            connect_timeout: Duration::from_secs(
+                read_clamped(
+                    context,
+                    Config::ConnectTimeoutSecs,
+                    preset.connect_timeout.as_secs(),
+                    CONNECT_TIMEOUT_RANGE,
+                )
+                .await,
+            ),
+            io_timeout: Duration::from_secs(
+                read_clamped(
+                    context,
+                    Config::IoTimeoutSecs,
+                    preset.io_timeout.as_secs(),
+                    IO_TIMEOUT_RANGE,
+                )
+                .await,
+            ),
+            max_retries: read_clamped(
+                context,
+                Config::MaxRetries,
+                u64::from(preset.max_retries),
+                MAX_RETRIES_RANGE,
+            )
+            .await as u32,
+            retry_base_delay: Duration::from_secs(
+                read_clamped(
+                    context,
+                    Config::RetryBaseDelay,
+                    preset.retry_base_delay.as_secs(),
+                    RETRY_BASE_DELAY_RANGE,
+                )
+                .await,
+            ),
+            tor,
+        }
+    }
+
+    /// Returns the delay before the retry number `retry`, starting with 1.
+    pub fn retry_delay(&self, retry: u32) -> Duration {
+        let factor = 1u32 << retry.saturating_sub(1).min(16);
+        self.retry_base_delay
+            .checked_mul(factor)
+            .unwrap_or(MAX_RETRY_DELAY)
+            .min(MAX_RETRY_DELAY)
+    }
+
+    /// Returns the delay before the next connection attempt after `failures` failed
+    /// attempts in a row.
+    ///
+    /// The first `max_retries` retries are made with backoff,
+    /// afterwards and without failures `interval` is used.
+    pub fn reconnect_delay(&self, failures: u32, interval: Duration) -> Duration {
+        if failures == 0 || failures > self.max_retries {
+            interval
+        } else {
+            self.retry_delay(failures).min(interval)
+        }
+    }
+
+    /// Runs the connection attempt `connect` with the connect timeout.
+    ///
+    /// There is no retry here, the caller retries according to [NetworkPolicy::reconnect_delay].
+    pub(crate) async fn connect<T, E, Fut>(&self, connect: Fut) -> Result<T, E>
+    where
+        Fut: Future<Output = Result<T, E>>,
+        E: From<io::Error>,
+    {
+        with_timeout(self.connect_timeout, connect)
+            .await
+            .unwrap_or_else(|err| Err(E::from(err)))
+    }
+
+    /// Runs `fut` with the IO timeout.
+    pub(crate) async fn io<T>(&self, fut: impl Future<Output = T>) -> io::Result<T> {
+        with_timeout(self.io_timeout, fut).await
+    }
+}
+
+impl fmt::Display for NetworkPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "connect_timeout:{}s,io_timeout:{}s,max_retries:{},retry_base_delay:{}s,tor:{}",
+            self.connect_timeout.as_secs(),
+            self.io_timeout.as_secs(),
+            self.max_retries,
+            self.retry_base_delay.as_secs(),
+            self.tor as i32
+        )
+    }
+}
+
+/// Reads `key` as a number, `default` is used if the key is not set or invalid.
+async fn read_clamped(context: &Context, key: Config, default: u64, range: (u64, u64)) -> u64 {
+    let (min, max) = range;
+    context
+        .get_config(key)
+        .await
+        .and_then(|value| value.parse::<u64>().ok())
+        .unwrap_or(default)
+        .max(min)
+        .min(max)
+}
+
+/// Runs `fut` with `timeout`, an elapsed timeout is returned as [io::ErrorKind::TimedOut].
+pub(crate) async fn with_timeout<T>(
+    timeout: Duration,
+    fut: impl Future<Output = T>,
+) -> io::Result<T> {
+    async_std::future::timeout(timeout, fut).await.map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::TimedOut,
+            format!("timed out after {}s", timeout.as_secs()),
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::net::Ipv4Addr;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Instant;
+
+    use async_std::channel;
+    use async_std::net::{TcpListener, TcpStream};
+    use async_std::task;
+
+    use crate::imap::Imap;
+    use crate::login_param::ServerLoginParam;
+    use crate::provider::Socket;
+    use crate::smtp::Smtp;
+    use crate::test_utils::TestContext;
+
+    /// Starts a server accepting connections without ever answering.
+    ///
+    /// Returns its port and the number of accepted connections.
+    async fn start_silent_server() -> (u16, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let accepted = Arc::new(AtomicUsize::new(0));
+        let counter = accepted.clone();
+        task::spawn(async move {
+            let mut streams: Vec<TcpStream> = Vec::new();
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                counter.fetch_add(1, Ordering::SeqCst);
+                streams.push(stream);
+            }
+        });
+        (port, accepted)
+    }
+
+    fn login_param(port: u16) -> ServerLoginParam {
+        ServerLoginParam {
+            server: "127.0.0.1".to_string(),
+            user: "alice".to_string(),
+            password: "secret".to_string(),
+            port,
+            security: Socket::Plain,
+            ..Default::default()
+        }
+    }
+
+    /// Connects to the IMAP server at `port`, returns the time until the connection failed.
+    async fn imap_connect_time(t: &TestContext, imap: &mut Imap, port: u16) -> Duration {
+        let start = Instant::now();
+        let res = imap
+            .connect(t, &login_param(port), "alice@example.org", false, false)
+            .await;
+        assert!(res.is_err());
+        start.elapsed()
+    }
+
+    fn assert_within(elapsed: Duration, expected: Duration) {
+        assert!(elapsed >= expected, "{:?} < {:?}", elapsed, expected);
+        assert!(
+            elapsed < expected + Duration::from_millis(1500),
+            "{:?} too long, expected {:?}",
+            elapsed,
+            expected
+        );
+    }
+
+    #[async_std::test]
+    async fn test_presets_and_clamping() {
+        let t = TestContext::new().await;
+        assert_eq!(
+            NetworkPolicy::from_database(&t).await,
+            NetworkPolicy::default()
+        );
+
+        t.set_config(Config::Socks5Enabled, Some("1"))
+            .await
+            .unwrap();
+        t.set_config(Config::Socks5Host, Some("127.0.0.1"))
+            .await
+            .unwrap();
+        t.set_config(Config::Socks5Port, Some("9050"))
+            .await
+            .unwrap();
+        assert_eq!(NetworkPolicy::from_database(&t).await, NetworkPolicy::tor());
+
+        t.set_config(Config::ConnectTimeoutSecs, Some("100000"))
+            .await
+            .unwrap();
+        t.set_config(Config::IoTimeoutSecs, Some("0"))
+            .await
+            .unwrap();
+        t.set_config(Config::MaxRetries, Some("0")).await.unwrap();
+        let policy = NetworkPolicy::from_database(&t).await;
+        assert_eq!(policy.connect_timeout, Duration::from_secs(300));
+        assert_eq!(policy.io_timeout, Duration::from_secs(1));
+        assert_eq!(policy.max_retries, 0);
+        assert_eq!(policy.retry_base_delay, Duration::from_secs(5));
+        assert!(policy.tor);
+        assert_eq!(
+            policy.to_string(),
+            "connect_timeout:300s,io_timeout:1s,max_retries:0,retry_base_delay:5s,tor:1"
+        );
+
+        t.set_config(Config::Socks5Port, Some("1080"))
+            .await
+            .unwrap();
+        assert!(!NetworkPolicy::from_database(&t).await.tor);
+    }
+
+    #[test]
+    fn test_retry_delay() {
+        let policy = NetworkPolicy::default();
+        assert_eq!(policy.retry_delay(1), Duration::from_secs(2));
+        assert_eq!(policy.retry_delay(2), Duration::from_secs(4));
+        assert_eq!(policy.retry_delay(3), Duration::from_secs(8));
+        assert_eq!(policy.retry_delay(100), MAX_RETRY_DELAY);
+
+        let interval = Duration::from_secs(60);
+        assert_eq!(policy.reconnect_delay(0, interval), interval);
+        assert_eq!(policy.reconnect_delay(1, interval), Duration::from_secs(2));
+        assert_eq!(policy.reconnect_delay(2, interval), interval);
+
+        let policy = NetworkPolicy::tor();
+        assert_eq!(policy.reconnect_delay(1, interval), Duration::from_secs(5));
+        assert_eq!(policy.reconnect_delay(3, interval), Duration::from_secs(20));
+        assert_eq!(policy.reconnect_delay(4, interval), interval);
+        assert_eq!(
+            policy.reconnect_delay(2, Duration::from_secs(3)),
+            Duration::from_secs(3)
+        );
+    }
+
+    #[async_std::test]
+    async fn test_connect_timeout() {
+        let t = TestContext::new().await;
+        let (port, accepted) = start_silent_server().await;
+        t.set_config(Config::ConnectTimeoutSecs, Some("1"))
+            .await
+            .unwrap();
+        t.set_config(Config::MaxRetries, Some("0")).await.unwrap();
+
+        let (_sender, receiver) = channel::bounded(1);
+        let mut imap = Imap::new(receiver);
+        let elapsed = imap_connect_time(&t, &mut imap, port).await;
+        assert_within(elapsed, Duration::from_secs(1));
+        assert_eq!(accepted.load(Ordering::SeqCst), 1);
+
+        // The changed config is used by the next connection.
+        t.set_config(Config::ConnectTimeoutSecs, Some("2"))
+            .await
+            .unwrap();
+        let elapsed = imap_connect_time(&t, &mut imap, port).await;
+        assert_within(elapsed, Duration::from_secs(2));
+
+        let mut smtp = Smtp::new();
+        let start = Instant::now();
+        let res = smtp
+            .connect(&t, &login_param(port), "alice@example.org", false, false)
+            .await;
+        assert!(res.is_err());
+        assert_within(start.elapsed(), Duration::from_secs(2));
+    }
+
+    /// Connecting makes a single attempt, retries are left to the IMAP loops.
+    #[async_std::test]
+    async fn test_connect_no_retries() {
+        let t = TestContext::new().await;
+        let (port, accepted) = start_silent_server().await;
+        t.set_config(Config::ConnectTimeoutSecs, Some("1"))
+            .await
+            .unwrap();
+        t.set_config(Config::MaxRetries, Some("2")).await.unwrap();
+        t.set_config(Config::RetryBaseDelay, Some("0"))
+            .await
+            .unwrap();
+
+        let (_sender, receiver) = channel::bounded(1);
+        let mut imap = Imap::new(receiver);
+        let elapsed = imap_connect_time(&t, &mut imap, port).await;
+        assert_within(elapsed, Duration::from_secs(1));
+        assert_eq!(accepted.load(Ordering::SeqCst), 1);
+
+        let mut smtp = Smtp::new();
+        let res = smtp
+            .connect(&t, &login_param(port), "alice@example.org", false, false)
+            .await;
+        assert!(res.is_err());
+        assert_eq!(accepted.load(Ordering::SeqCst), 2);
+    }
+}
@@ -0,0 +1,429 @@
+//! # Notifications
+//!
+//! Decides whether and how incoming messages are notified,
+//! so that all UIs agree on muted chats, mentions and hidden previews.
+
+use num_traits::FromPrimitive;
+
+use crate::chat::{Chat, ChatId, ChatVisibility};
+use crate::config::Config;
+use crate::constants::{
+    Blocked, Chattype, NotificationPrivacy, NotifyProfile, DC_CHAT_ID_LAST_SPECIAL,
+    DC_CONTACT_ID_SELF,
+};
+use crate::contact::Contact;
+use crate::context::Context;
+use crate::message::{Message, MessageState, MsgId};
+use crate::sql;
+use crate::stock_str;
+
+/// Approximate number of characters of the message text shown in notifications.
+const NOTIFICATION_TEXT_CHARACTERS: usize = 160;
+
+/// What a notification about a message should show.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NotificationInfo {
+    /// The chat containing the message.
+    pub chat_id: ChatId,
+
+    /// Name of the chat, empty if hidden by the `notification_privacy` config.
+    pub chat_name: String,
+
+    /// Display name of the sender, empty if hidden by the `notification_privacy` config.
+    pub sender_name: String,
+
+    /// Text of the notification.
+    pub text: String,
+
+    /// Whether the message is a contact request.
+    pub is_contact_request: bool,
+
+    /// Whether the message mentions the user.
+    ///
+    /// Mentions in groups are notified even if the group is muted.
+    pub is_mention: bool,
+
+    /// Whether no notification should be shown, eg. because the chat is muted.
+    pub suppressed: bool,
+
+    /// How the notification should be presented, set per chat.
+    ///
+    /// Muting takes precedence: if the notification is suppressed,
+    /// it is not shown even for [NotifyProfile::Priority].
+    pub notify_profile: NotifyProfile,
+}
+
+/// A fresh message that may be notified.
+struct FreshMsg {
+    msg: Message,
+    chat: Chat,
+    is_mention: bool,
+    suppressed: bool,
+}
+
+/// Loads a fresh incoming message, returns `None` if the message is not notified at all.
+async fn load_fresh_msg(context: &Context, msg_id: MsgId) -> Option<FreshMsg> {
+    let msg = match Message::load_from_db(context, msg_id).await {
+        Ok(msg) => msg,
+        Err(err) => {
+            warn!(context, "Cannot load message {} to notify: {}", msg_id, err);
+            return None;
+        }
+    };
+    if msg.state != MessageState::InFresh
+        || msg.hidden
+        || msg.from_id == DC_CONTACT_ID_SELF
+        || msg.chat_id.is_special()
+    {
+        return None;
+    }
+
+    let chat = match Chat::load_from_db(context, msg.chat_id).await {
+        Ok(chat) => chat,
+        Err(err) => {
+            warn!(
+                context,
+                "Cannot load chat {} to notify: {}", msg.chat_id, err
+            );
+            return None;
+        }
+    };
+    if chat.blocked == Blocked::Manually {
+        return None;
+    }
+
+    let is_mention = chat.typ == Chattype::Group && mentions_self(context, &msg).await;
+    let suppressed = chat.is_muted() && !is_mention;
+    Some(FreshMsg {
+        msg,
+        chat,
+        is_mention,
+        suppressed,
+    })
+}
+
+/// Returns whether the message mentions the user.
+///
+/// This is the case if the text contains the address of the user or their name prefixed by `@`,
+/// or if the message quotes a message of the user.
+async fn mentions_self(context: &Context, msg: &Message) -> bool {
+    let text = msg.text.as_deref().unwrap_or_default().to_lowercase();
+    if let Some(addr) = context.get_config(Config::ConfiguredAddr).await {
+        if !addr.is_empty() && text.contains(&addr.to_lowercase()) {
+            return true;
+        }
+    }
+    if let Some(name) = context.get_config(Config::Displayname).await {
+        if !name.is_empty() && text.contains(&format!("@{}", name.to_lowercase())) {
+            return true;
+        }
+    }
+    match msg.quoted_message(context).await {
+        Ok(Some(quote)) => quote.from_id == DC_CONTACT_ID_SELF,
+        Ok(None) => false,
+        Err(err) => {
+            warn!(context, "Cannot load quote of message {}: {}", msg.id, err);
+            false
+        }
+    }
+}
+
+/// Returns what a notification about a fresh incoming message should show.
+///
+/// Returns `None` if the message should not be notified at all,
+/// eg. because it is outgoing, already seen or hidden.
+/// If the notification is only suppressed, eg. because the chat is muted,
+/// [NotificationInfo::suppressed] is set.
+pub async fn get_notification_info(context: &Context, msg_id: MsgId) -> Option<NotificationInfo> {
+    let FreshMsg {
+        msg,
+        chat,
+        is_mention,
+        suppressed,
+    } = load_fresh_msg(context, msg_id).await?;
+
+    let sender_name = if chat.is_device_talk() {
+        // Device messages are not sent by anyone, the chat name is shown instead.
+        chat.get_name().to_string()
+    } else {
+        match Contact::get_by_id(context, msg.from_id).await {
+            Ok(contact) => msg.get_sender_name(&contact),
+            Err(err) => {
+                warn!(context, "Cannot load sender of message {}: {}", msg_id, err);
+                return None;
+            }
+        }
+    };
+
+    let privacy =
+        NotificationPrivacy::from_i32(context.get_config_int(Config::NotificationPrivacy).await)
+            .unwrap_or_default();
+    let (chat_name, sender_name, text) = match privacy {
+        NotificationPrivacy::Full => (
+            chat.get_name().to_string(),
+            sender_name,
+            msg.get_summarytext(context, NOTIFICATION_TEXT_CHARACTERS)
+                .await,
+        ),
+        NotificationPrivacy::NameOnly => (
+            chat.get_name().to_string(),
+            sender_name,
+            stock_str::new_message_notification(context).await,
+        ),
+        NotificationPrivacy::CountOnly => {
+            let cnt = get_badge_cnt(context).await.unwrap_or_else(|err| {
+                warn!(context, "Cannot count notified messages: {}", err);
+                1
+            });
+            (
+                String::new(),
+                String::new(),
+                stock_str::new_messages_notification(context, cnt).await,
+            )
+        }
+    };
+
+    Some(NotificationInfo {
+        chat_id: chat.id,
+        chat_name,
+        sender_name,
+        text,
+        is_contact_request: chat.blocked == Blocked::Deaddrop,
+        is_mention,
+        suppressed,
+        notify_profile: chat.get_notify_profile(),
+    })
+}
+
+/// Returns the number of fresh messages that are notified, to be shown as app badge.
+///
+/// Messages in muted chats are counted only if they mention the user,
+/// see [get_notification_info].
+/// Messages in chats archived with [ChatVisibility::NoUnarchive]
+/// are counted only if the `badge_no_unarchive` config is set.
+pub async fn get_badge_cnt(context: &Context) -> sql::Result<usize> {
+    let chat_cnts = context
+        .sql
+        .query_map(
+            "SELECT chat_id, COUNT(*) FROM msgs
+             WHERE state=? AND hidden=0 AND chat_id>? AND from_id!=?
+             GROUP BY chat_id;",
+            paramsv![
+                MessageState::InFresh,
+                DC_CHAT_ID_LAST_SPECIAL,
+                DC_CONTACT_ID_SELF
+            ],
+            |row| Ok((row.get::<_, ChatId>(0)?, row.get::<_, i64>(1)?)),
+            |rows| {
+                rows.collect::<std::result::Result<Vec<_>, _>>()
+                    .map_err(Into::into)
+            },
+        )
+        .await?;
+
+    let include_no_unarchive = context.get_config_bool(Config::BadgeNoUnarchive).await;
+    let mut cnt = 0;
+    for (chat_id, chat_cnt) in chat_cnts {
+        let chat = match Chat::load_from_db(context, chat_id).await {
+            Ok(chat) => chat,
+            Err(err) => {
+                warn!(context, "Cannot load chat {} to count: {}", chat_id, err);
+                continue;
+            }
+        };
+        if chat.blocked == Blocked::Manually
+            || chat.visibility == ChatVisibility::NoUnarchive && !include_no_unarchive
+        {
+            continue;
+        }
+        if !chat.is_muted() {
+            cnt += chat_cnt as usize;
+        } else if chat.typ == Chattype::Group {
+            // Only mentions are notified in muted groups.
+            let msg_ids = context
+                .sql
+                .query_map(
+                    "SELECT id FROM msgs WHERE chat_id=? AND state=? AND hidden=0 AND from_id!=?;",
+                    paramsv![chat_id, MessageState::InFresh, DC_CONTACT_ID_SELF],
+                    |row| row.get::<_, MsgId>(0),
+                    |rows| {
+                        rows.collect::<std::result::Result<Vec<_>, _>>()
+                            .map_err(Into::into)
+                    },
+                )
+                .await?;
+            for msg_id in msg_ids {
+                match Message::load_from_db(context, msg_id).await {
+                    Ok(msg) => {
+                        if mentions_self(context, &msg).await {
+                            cnt += 1;
+                        }
+                    }
+                    Err(err) => warn!(context, "Cannot load message {} to count: {}", msg_id, err),
+                }
+            }
+        }
+    }
+    Ok(cnt)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chat::{self, MuteDuration, ProtectionStatus};
+    use crate::test_utils::TestContext;
+
+    #[async_std::test]
+    async fn test_muted_group_with_mention() {
+        let alice = TestContext::new_alice().await;
+        let bob = TestContext::new_bob().await;
+        alice
+            .set_config(Config::Displayname, Some("Alice"))
+            .await
+            .unwrap();
+        bob.set_config(Config::Displayname, Some("Bob"))
+            .await
+            .unwrap();
+
+        let alice_chat_id = chat::create_group_chat(&alice, ProtectionStatus::Unprotected, "Team")
+            .await
+            .unwrap();
+        let bob_id = Contact::create(&alice, "", "bob@example.net")
+            .await
+            .unwrap();
+        chat::add_contact_to_chat(&alice, alice_chat_id, bob_id).await;
+
+        bob.recv_msg(&alice.send_text(alice_chat_id, "hello").await)
+            .await;
+        let msg = bob.get_last_msg().await;
+        chat::set_muted(&bob, msg.chat_id, MuteDuration::Forever)
+            .await
+            .unwrap();
+        let info = get_notification_info(&bob, msg.id).await.unwrap();
+        assert_eq!(info.chat_id, msg.chat_id);
+        assert_eq!(info.chat_name, "Team");
+        assert_eq!(info.sender_name, "Alice");
+        assert_eq!(info.text, "hello");
+        assert!(!info.is_mention);
+        assert!(info.suppressed);
+        assert_eq!(get_badge_cnt(&bob).await.unwrap(), 0);
+
+        bob.recv_msg(&alice.send_text(alice_chat_id, "@bob look at this").await)
+            .await;
+        let msg = bob.get_last_msg().await;
+        let info = get_notification_info(&bob, msg.id).await.unwrap();
+        assert!(info.is_mention);
+        assert!(!info.suppressed);
+        assert_eq!(get_badge_cnt(&bob).await.unwrap(), 1);
+
+        // Outgoing messages are not notified.
+        let msg = alice.get_last_msg().await;
+        assert!(get_notification_info(&alice, msg.id).await.is_none());
+    }
+
+    #[async_std::test]
+    async fn test_notification_privacy() {
+        let alice = TestContext::new_alice().await;
+        let bob = TestContext::new_bob().await;
+        alice
+            .set_config(Config::Displayname, Some("Alice"))
+            .await
+            .unwrap();
+        let alice_chat = alice.create_chat(&bob).await;
+        bob.recv_msg(&alice.send_text(alice_chat.id, "one").await)
+            .await;
+        bob.recv_msg(&alice.send_text(alice_chat.id, "two").await)
+            .await;
+        let msg = bob.get_last_msg().await;
+
+        bob.set_config(Config::NotificationPrivacy, Some("1"))
+            .await
+            .unwrap();
+        let info = get_notification_info(&bob, msg.id).await.unwrap();
+        assert_eq!(info.chat_name, "Alice");
+        assert_eq!(info.sender_name, "Alice");
+        assert_eq!(info.text, "New message");
+
+        bob.set_config(Config::NotificationPrivacy, Some("2"))
+            .await
+            .unwrap();
+        let info = get_notification_info(&bob, msg.id).await.unwrap();
+        assert_eq!(info.chat_name, "");
+        assert_eq!(info.sender_name, "");
+        assert_eq!(info.text, "2 new messages");
+        assert!(!info.suppressed);
+
+        assert!(bob
+            .set_config(Config::NotificationPrivacy, Some("3"))
+            .await
+            .is_err());
+    }
+
+    #[async_std::test]
+    async fn test_contact_request() {
+        let alice = TestContext::new_alice().await;
+        let bob = TestContext::new_bob().await;
+        let alice_chat = alice.create_chat(&bob).await;
+        bob.recv_msg(&alice.send_text(alice_chat.id, "hi, it's me").await)
+            .await;
+        let msg = bob.get_last_msg().await;
+
+        let info = get_notification_info(&bob, msg.id).await.unwrap();
+        assert!(info.is_contact_request);
+        assert!(!info.suppressed);
+        assert_eq!(info.sender_name, "alice@example.com");
+        assert_eq!(info.text, "hi, it's me");
+        assert_eq!(get_badge_cnt(&bob).await.unwrap(), 1);
+
+        // Seen messages are not notified.
+        crate::message::markseen_msgs(&bob, vec![msg.id]).await;
+        assert!(get_notification_info(&bob, msg.id).await.is_none());
+        assert_eq!(get_badge_cnt(&bob).await.unwrap(), 0);
+    }
+
+    #[async_std::test]
+    async fn test_notify_profile() {
+        let alice = TestContext::new_alice().await;
+        let bob = TestContext::new_bob().await;
+        let alice_chat = alice.create_chat(&bob).await;
+        let bob_chat = bob.create_chat(&alice).await;
+        bob.recv_msg(&alice.send_text(alice_chat.id, "hi").await)
+            .await;
+        let msg = bob.get_last_msg().await;
+        assert_eq!(msg.chat_id, bob_chat.id);
+
+        assert_eq!(
+            bob_chat.id.get_notify_profile(&bob).await.unwrap(),
+            NotifyProfile::Default
+        );
+        let info = get_notification_info(&bob, msg.id).await.unwrap();
+        assert_eq!(info.notify_profile, NotifyProfile::Default);
+
+        bob_chat
+            .id
+            .set_notify_profile(&bob, NotifyProfile::Priority)
+            .await
+            .unwrap();
+        assert_eq!(
+            bob_chat.id.get_notify_profile(&bob).await.unwrap(),
+            NotifyProfile::Priority
+        );
+        let info = get_notification_info(&bob, msg.id).await.unwrap();
+        assert_eq!(info.notify_profile, NotifyProfile::Priority);
+        assert!(!info.suppressed);
+
+        // Muting wins over the profile.
+        chat::set_muted(&bob, bob_chat.id, MuteDuration::Forever)
+            .await
+            .unwrap();
+        let info = get_notification_info(&bob, msg.id).await.unwrap();
+        assert_eq!(info.notify_profile, NotifyProfile::Priority);
+        assert!(info.suppressed);
+        assert_eq!(get_badge_cnt(&bob).await.unwrap(), 0);
+
+        assert!(ChatId::new(DC_CHAT_ID_LAST_SPECIAL)
+            .set_notify_profile(&bob, NotifyProfile::Silent)
+            .await
+            .is_err());
+    }
+}
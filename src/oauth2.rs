@@ -6,7 +6,7 @@
 use serde::Deserialize;
 
 use crate::context::Context;
-use crate::dc_tools::time;
+use crate::dc_tools::{normalize_addr, time};
 use crate::provider;
 use crate::provider::Oauth2Authorizer;
 
@@ -269,9 +269,12 @@ async fn from_address(addr: impl AsRef<str>) -> Option<Self> {
             .find('@')
             .map(|index| addr_normalized.split_at(index + 1).1)
         {
-            if let Some(oauth2_authorizer) = provider::get_provider_info(domain)
-                .await
-                .and_then(|provider| provider.oauth2_authorizer.as_ref())
+            let provider = match provider::get_provider_by_domain(domain) {
+                Some(provider) => Some(provider),
+                None => provider::get_provider_by_mx(domain).await,
+            };
+            if let Some(oauth2_authorizer) =
+                provider.and_then(|provider| provider.oauth2_authorizer.as_ref())
             {
                 return Some(match oauth2_authorizer {
                     Oauth2Authorizer::Gmail => OAUTH2_GMAIL,
@@ -339,23 +342,12 @@ fn replace_in_uri(uri: impl AsRef<str>, key: impl AsRef<str>, value: impl AsRef<
     uri.as_ref().replace(key.as_ref(), &value_urlencoded)
 }
 
-fn normalize_addr(addr: &str) -> &str {
-    let normalized = addr.trim();
-    normalized.trim_start_matches("mailto:")
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
 
     use crate::test_utils::TestContext;
 
-    #[test]
-    fn test_normalize_addr() {
-        assert_eq!(normalize_addr(" hello@mail.de  "), "hello@mail.de");
-        assert_eq!(normalize_addr("mailto:hello@mail.de  "), "hello@mail.de");
-    }
-
     #[test]
     fn test_replace_in_uri() {
         assert_eq!(
@@ -0,0 +1,315 @@
+//! # Long running operations
+//!
+//! Operations which take a while, like configuring, importing or exporting a backup
+//! or joining a group, register at the [Context] while they run.  UIs can list them with
+//! [Context::get_running_operations] and cancel them with [Context::cancel_operation].
+//!
+//! An operation is registered by [Context::start_operation] and removed from the registry
+//! when the returned [OperationGuard] is dropped, so entries do not outlive the task running
+//! the operation, even if it panics or its future is dropped.
+//!
+//! Apart from maintenance and storage usage scans, only one operation runs at a time.
+
+use std::sync::Mutex;
+
+use anyhow::{bail, Result};
+use async_std::channel::{self, Receiver, Sender};
+
+use crate::context::Context;
+use crate::dc_tools::time;
+use crate::events::EventType;
+
+/// Kind of a long running operation.
+///
+/// Each kind runs at most once at a time.
+#[derive(
+    Debug, Display, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, FromPrimitive, ToPrimitive,
+)]
+#[repr(u32)]
+pub enum OperationKind {
+    /// [Context::configure].
+    Configure = 1,
+
+    /// [crate::imex::imex].
+    Imex = 2,
+
+    /// [crate::imex::initiate_key_transfer].
+    KeyTransfer = 3,
+
+    /// [crate::securejoin::dc_join_securejoin] joining a group.
+    SecureJoin = 4,
+
+    /// [Context::get_storage_usage].
+    StorageUsage = 5,
+
+    /// [Context::perform_maintenance].
+    Maintenance = 6,
+}
+
+impl OperationKind {
+    /// Returns true if no other exclusive operation may run at the same time.
+    ///
+    /// Maintenance and storage usage scans only read or tidy up and may run besides
+    /// the other operations.
+    pub(crate) fn is_exclusive(self) -> bool {
+        !matches!(
+            self,
+            OperationKind::Maintenance | OperationKind::StorageUsage
+        )
+    }
+}
+
+/// Information about a running operation, see [Context::get_running_operations].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OperationInfo {
+    pub kind: OperationKind,
+
+    /// Unix timestamp of the start of the operation.
+    pub started_at: i64,
+
+    /// Progress in permille as last reported by the progress event of the operation,
+    /// `None` if the operation did not report progress yet or does not report it at all.
+    pub progress: Option<usize>,
+
+    /// Whether the operation can be canceled with [Context::cancel_operation].
+    pub cancellable: bool,
+}
+
+#[derive(Debug)]
+struct Operation {
+    info: OperationInfo,
+    cancel_sender: Sender<()>,
+    canceled: bool,
+}
+
+/// Registry of the running operations.
+#[derive(Debug, Default)]
+pub(crate) struct Ongoing {
+    operations: Mutex<Vec<Operation>>,
+}
+
+impl Ongoing {
+    /// Records the progress reported by `event` for the operation emitting it.
+    pub(crate) fn update_progress(&self, event: &EventType) {
+        let (kind, progress) = match event {
+            EventType::ConfigureProgress { progress, .. } => (OperationKind::Configure, *progress),
+            EventType::ImexProgress(progress) => (OperationKind::Imex, *progress),
+            _ => return,
+        };
+        let mut operations = self.operations.lock().unwrap();
+        if let Some(operation) = operations.iter_mut().find(|op| op.info.kind == kind) {
+            operation.info.progress = Some(progress);
+        }
+    }
+
+    fn remove(&self, kind: OperationKind) {
+        self.operations
+            .lock()
+            .unwrap()
+            .retain(|op| op.info.kind != kind);
+    }
+
+    fn is_canceled(&self, kind: OperationKind) -> bool {
+        self.operations
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|op| op.info.kind == kind && op.canceled)
+    }
+}
+
+/// Keeps an operation registered until it is dropped, see [Context::start_operation].
+#[derive(Debug)]
+pub(crate) struct OperationGuard {
+    context: Context,
+    kind: OperationKind,
+    cancel_receiver: Receiver<()>,
+}
+
+impl OperationGuard {
+    /// Resolves once the operation is canceled.
+    pub(crate) async fn canceled(&self) {
+        self.cancel_receiver.recv().await.ok();
+    }
+
+    /// Returns true if the operation was canceled.
+    pub(crate) fn is_canceled(&self) -> bool {
+        self.context.ongoing.is_canceled(self.kind)
+    }
+}
+
+impl Drop for OperationGuard {
+    fn drop(&mut self) {
+        self.context.ongoing.remove(self.kind);
+    }
+}
+
+impl Context {
+    /// Registers a running operation of the given kind.
+    ///
+    /// Fails if an operation of the same kind is running already or,
+    /// for exclusive operations, if another exclusive operation is running.
+    pub(crate) fn start_operation(
+        &self,
+        kind: OperationKind,
+        cancellable: bool,
+    ) -> Result<OperationGuard> {
+        let mut operations = self.ongoing.operations.lock().unwrap();
+        if operations
+            .iter()
+            .any(|op| op.info.kind == kind || (kind.is_exclusive() && op.info.kind.is_exclusive()))
+        {
+            bail!("There is already another ongoing process running.");
+        }
+
+        let (cancel_sender, cancel_receiver) = channel::bounded(1);
+        operations.push(Operation {
+            info: OperationInfo {
+                kind,
+                started_at: time(),
+                progress: None,
+                cancellable,
+            },
+            cancel_sender,
+            canceled: false,
+        });
+        Ok(OperationGuard {
+            context: self.clone(),
+            kind,
+            cancel_receiver,
+        })
+    }
+
+    /// Returns the operations running at the moment, in the order they were started.
+    pub fn get_running_operations(&self) -> Vec<OperationInfo> {
+        self.ongoing
+            .operations
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|op| op.info.clone())
+            .collect()
+    }
+
+    /// Signals the running operation of the given kind to stop.
+    ///
+    /// Operations are aborted at their next suspension point, clean up after themselves
+    /// and report failure.  Returns false if no cancellable operation of this kind is running.
+    pub fn cancel_operation(&self, kind: OperationKind) -> bool {
+        let canceled = match self
+            .ongoing
+            .operations
+            .lock()
+            .unwrap()
+            .iter_mut()
+            .find(|op| op.info.kind == kind && op.info.cancellable)
+        {
+            Some(operation) => {
+                operation.canceled = true;
+                operation.cancel_sender.try_send(()).ok();
+                true
+            }
+            None => false,
+        };
+        if canceled {
+            info!(self, "Signaling {} to stop ASAP.", kind);
+        } else {
+            info!(self, "No {} to stop.", kind);
+        }
+        canceled
+    }
+
+    /// Returns true if the operation of the given kind was canceled and did not stop yet.
+    pub(crate) fn is_operation_canceled(&self, kind: OperationKind) -> bool {
+        self.ongoing.is_canceled(kind)
+    }
+
+    /// Returns true if the operation of the given kind is running.
+    pub fn is_operation_running(&self, kind: OperationKind) -> bool {
+        self.ongoing
+            .operations
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|op| op.info.kind == kind)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::panic::AssertUnwindSafe;
+    use std::time::Duration;
+
+    use async_std::prelude::*;
+
+    use crate::test_utils::TestContext;
+
+    #[async_std::test]
+    async fn test_cancel_operation() {
+        let t = TestContext::new().await;
+        assert!(t.get_running_operations().is_empty());
+        assert!(!t.cancel_operation(OperationKind::Imex));
+
+        let (started_tx, started_rx) = channel::bounded(1);
+        let context = t.ctx.clone();
+        let task = async_std::task::spawn(async move {
+            let guard = context.start_operation(OperationKind::Imex, true)?;
+            started_tx.send(()).await.unwrap();
+            // A fake operation which only finishes when it is canceled.
+            guard
+                .canceled()
+                .race(async {
+                    async_std::task::sleep(Duration::from_secs(60)).await;
+                })
+                .await;
+            Ok::<_, anyhow::Error>(guard.is_canceled())
+        });
+        started_rx.recv().await.unwrap();
+
+        let operations = t.get_running_operations();
+        assert_eq!(operations.len(), 1);
+        let info = operations.first().unwrap();
+        assert_eq!(info.kind, OperationKind::Imex);
+        assert!(info.cancellable);
+        assert!(info.started_at > 0);
+        assert_eq!(info.progress, None);
+
+        t.emit_event(EventType::ImexProgress(300));
+        assert_eq!(
+            t.get_running_operations().first().unwrap().progress,
+            Some(300)
+        );
+
+        // Only one exclusive operation runs at a time,
+        // maintenance and storage usage scans may run besides.
+        assert!(t.start_operation(OperationKind::Configure, true).is_err());
+        assert!(t.start_operation(OperationKind::Maintenance, true).is_ok());
+        let scan = t
+            .start_operation(OperationKind::StorageUsage, true)
+            .unwrap();
+        assert!(t
+            .start_operation(OperationKind::StorageUsage, true)
+            .is_err());
+        drop(scan);
+
+        assert!(t.cancel_operation(OperationKind::Imex));
+        assert!(task.await.unwrap());
+        assert!(t.get_running_operations().is_empty());
+    }
+
+    #[async_std::test]
+    async fn test_operation_removed_on_panic() {
+        let t = TestContext::new().await;
+        let res = std::panic::catch_unwind(AssertUnwindSafe(|| {
+            let _guard = t
+                .start_operation(OperationKind::StorageUsage, true)
+                .unwrap();
+            panic!("operation failed");
+        }));
+        assert!(res.is_err());
+        assert!(t.get_running_operations().is_empty());
+        assert!(t.start_operation(OperationKind::Configure, true).is_ok());
+    }
+}
@@ -0,0 +1,269 @@
+//! # Outgoing message queue
+//!
+//! Messages are sent by jobs of the SMTP thread, which are retried until sending succeeds
+//! or is given up. The queue of these jobs can be inspected and single messages can be
+//! removed from it, e.g. to find out why messages are stuck.
+//!
+//! Messages which could not be sent are listed as well, until they are deleted.
+//!
+//! The SMTP thread records the job it is performing under the same lock,
+//! so a message is never cancelled while it is being sent.
+
+use anyhow::Result;
+
+use crate::chat::ChatId;
+use crate::constants::DC_CHAT_ID_LAST_SPECIAL;
+use crate::context::Context;
+use crate::dc_tools::dc_delete_file;
+use crate::job::Action;
+use crate::message::{self, ErrorCode, Message, MessageState, MsgError, MsgId};
+use crate::param::{Param, Params};
+
+/// Approximate number of characters of [OutgoingInfo::snippet].
+const SNIPPET_CHARACTERS: usize = 50;
+
+/// A message waiting to be sent.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutgoingInfo {
+    pub msg_id: MsgId,
+    pub chat_id: ChatId,
+    pub state: MessageState,
+    pub subject: String,
+    /// Beginning of the message text.
+    pub snippet: String,
+    /// Number of failed tries of the job sending the message,
+    /// 0 for messages which failed to send.
+    pub tries: u32,
+    /// Timestamp of the next try, it may be earlier if the network becomes available.
+    ///
+    /// `None` for messages which failed to send and are not retried.
+    pub next_try: Option<i64>,
+    /// Error of the last failed try.
+    pub error: Option<MsgError>,
+}
+
+impl Context {
+    /// Returns the messages waiting to be sent, in the order they are going to be tried,
+    /// followed by the messages which failed to send.
+    pub async fn get_outgoing_queue(&self) -> Result<Vec<OutgoingInfo>> {
+        let _guard = self.outbox_job.lock().await;
+
+        let mut entries: Vec<(MsgId, u32, Option<i64>)> = self
+            .sql
+            .query_map(
+                "SELECT foreign_id, tries, desired_timestamp FROM jobs
+                 WHERE action=?
+                 ORDER BY desired_timestamp, added_timestamp;",
+                paramsv![Action::SendMsgToSmtp],
+                |row| {
+                    let msg_id: MsgId = row.get(0)?;
+                    let tries: u32 = row.get(1)?;
+                    let next_try: i64 = row.get(2)?;
+                    Ok((msg_id, tries, Some(next_try)))
+                },
+                |rows| rows.collect::<Result<Vec<_>, _>>().map_err(Into::into),
+            )
+            .await?;
+        let failed: Vec<MsgId> = self
+            .sql
+            .query_map(
+                "SELECT id FROM msgs WHERE state=? AND chat_id>? ORDER BY timestamp, id;",
+                paramsv![MessageState::OutFailed, DC_CHAT_ID_LAST_SPECIAL],
+                |row| row.get::<_, MsgId>(0),
+                |rows| rows.collect::<Result<Vec<_>, _>>().map_err(Into::into),
+            )
+            .await?;
+        for msg_id in failed {
+            if !entries.iter().any(|(id, _, _)| *id == msg_id) {
+                entries.push((msg_id, 0, None));
+            }
+        }
+
+        let mut queue = Vec::with_capacity(entries.len());
+        for (msg_id, tries, next_try) in entries {
+            let msg = match Message::load_from_db(self, msg_id).await {
+                Ok(msg) => msg,
+                Err(err) => {
+                    warn!(self, "Cannot load queued message {}: {:#}", msg_id, err);
+                    continue;
+                }
+            };
+            queue.push(OutgoingInfo {
+                msg_id,
+                chat_id: msg.chat_id,
+                state: msg.state,
+                subject: msg.subject.clone(),
+                snippet: msg.get_summarytext(self, SNIPPET_CHARACTERS).await,
+                tries,
+                next_try,
+                error: msg.get_error(),
+            });
+        }
+        Ok(queue)
+    }
+
+    /// Cancels sending the given messages and marks them as failed.
+    ///
+    /// Messages which are not waiting to be sent, including the message being sent
+    /// at the moment, are skipped.
+    pub async fn clear_outgoing(&self, msg_ids: &[MsgId]) -> Result<()> {
+        let outbox_job = self.outbox_job.lock().await;
+
+        for msg_id in msg_ids {
+            let jobs: Vec<(u32, Params)> = self
+                .sql
+                .query_map(
+                    "SELECT id, param FROM jobs WHERE action=? AND foreign_id=?;",
+                    paramsv![Action::SendMsgToSmtp, msg_id],
+                    |row| Ok((row.get::<_, u32>(0)?, row.get::<_, String>(1)?)),
+                    |rows| {
+                        rows.map(|row| {
+                            let (job_id, param) = row?;
+                            Ok((job_id, param.parse().unwrap_or_default()))
+                        })
+                        .collect::<Result<Vec<_>, rusqlite::Error>>()
+                        .map_err(Into::into)
+                    },
+                )
+                .await?;
+            if jobs.is_empty() {
+                info!(self, "Message {} is not queued for sending", msg_id);
+                continue;
+            }
+            if jobs.iter().any(|(job_id, _)| Some(*job_id) == *outbox_job) {
+                info!(self, "Message {} is being sent", msg_id);
+                continue;
+            }
+
+            self.sql
+                .execute(
+                    "DELETE FROM jobs WHERE action=? AND foreign_id=?;",
+                    paramsv![Action::SendMsgToSmtp, msg_id],
+                )
+                .await?;
+            for (_, param) in jobs {
+                if let Ok(Some(file)) = param.get_path(Param::File, self) {
+                    dc_delete_file(self, file).await;
+                }
+            }
+            message::set_msg_failed(
+                self,
+                *msg_id,
+                ErrorCode::Cancelled,
+                Some("Sending was cancelled."),
+            )
+            .await;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #![allow(clippy::indexing_slicing)]
+
+    use super::*;
+
+    use async_std::channel;
+
+    use crate::chat;
+    use crate::events::{Event, EventType};
+    use crate::job::{self, Connection, Thread};
+    use crate::scheduler::InterruptInfo;
+    use crate::smtp::Smtp;
+    use crate::test_utils::TestContext;
+
+    #[async_std::test]
+    async fn test_outgoing_queue() {
+        let t = TestContext::new_alice().await;
+        let chat = t.create_chat_with_contact("bob", "bob@example.net").await;
+        let (event_tx, event_rx) = channel::unbounded();
+        t.add_event_sink(move |event: Event| {
+            let event_tx = event_tx.clone();
+            async move {
+                if let EventType::MsgFailed { msg_id, .. } = event.typ {
+                    event_tx.try_send(msg_id).unwrap();
+                }
+            }
+        })
+        .await;
+        assert!(t.get_outgoing_queue().await.unwrap().is_empty());
+
+        let mut msg_ids = Vec::new();
+        for text in &["one", "two", "three"] {
+            msg_ids.push(
+                chat::send_text_msg(&t, chat.id, text.to_string())
+                    .await
+                    .unwrap(),
+            );
+        }
+
+        // SMTP is not configured, so sending fails and is retried later.
+        let job = job::load_next(&t, Thread::Smtp, &InterruptInfo::new(false, None))
+            .await
+            .unwrap();
+        job::perform_job(&t, Connection::Smtp(&mut Smtp::new()), job).await;
+
+        let queue = t.get_outgoing_queue().await.unwrap();
+        assert_eq!(queue.len(), 3);
+        let failed = queue.iter().find(|info| info.tries == 1).unwrap();
+        assert_eq!(failed.chat_id, chat.id);
+        assert_eq!(failed.state, MessageState::OutPending);
+        assert_eq!(failed.error.as_ref().unwrap().code, ErrorCode::Network);
+        assert!(failed.next_try > queue.first().unwrap().next_try);
+        let snippets: Vec<&str> = queue.iter().map(|info| info.snippet.as_str()).collect();
+        for text in &["one", "two", "three"] {
+            assert!(snippets.contains(text));
+        }
+
+        // A job loaded before cancelling is not performed.
+        let job = job::load_next(
+            &t,
+            Thread::Smtp,
+            &InterruptInfo::new(false, Some(msg_ids[1])),
+        )
+        .await
+        .unwrap();
+        t.clear_outgoing(&msg_ids[1..2]).await.unwrap();
+        job::perform_job(&t, Connection::Smtp(&mut Smtp::new()), job).await;
+
+        // The cancelled message is listed as failed after the pending ones.
+        let queue = t.get_outgoing_queue().await.unwrap();
+        assert_eq!(queue.len(), 3);
+        assert!(queue[..2].iter().all(|info| info.msg_id != msg_ids[1]));
+        let cancelled = queue.last().unwrap();
+        assert_eq!(cancelled.msg_id, msg_ids[1]);
+        assert_eq!(cancelled.state, MessageState::OutFailed);
+        assert_eq!(cancelled.tries, 0);
+        assert_eq!(cancelled.next_try, None);
+        assert_eq!(cancelled.error.as_ref().unwrap().code, ErrorCode::Cancelled);
+        assert_eq!(event_rx.recv().await.unwrap(), msg_ids[1]);
+
+        // Messages not in the queue are skipped.
+        t.clear_outgoing(&msg_ids[1..2]).await.unwrap();
+        assert_eq!(t.get_outgoing_queue().await.unwrap().len(), 3);
+
+        // The message being sent is skipped.
+        let job = job::load_next(
+            &t,
+            Thread::Smtp,
+            &InterruptInfo::new(false, Some(msg_ids[2])),
+        )
+        .await
+        .unwrap();
+        *t.outbox_job.lock().await = Some(job.job_id);
+        t.clear_outgoing(&msg_ids[2..]).await.unwrap();
+        *t.outbox_job.lock().await = None;
+        let msg = Message::load_from_db(&t, msg_ids[2]).await.unwrap();
+        assert_eq!(msg.state, MessageState::OutPending);
+        assert_eq!(
+            t.get_outgoing_queue()
+                .await
+                .unwrap()
+                .iter()
+                .filter(|info| info.next_try.is_some())
+                .count(),
+            2
+        );
+    }
+}
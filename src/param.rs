@@ -4,7 +4,6 @@
 
 use anyhow::{bail, Error};
 use async_std::path::PathBuf;
-use itertools::Itertools;
 use num_traits::FromPrimitive;
 use serde::{Deserialize, Serialize};
 
@@ -36,6 +35,17 @@ pub enum Param {
     /// For Messages
     Duration = b'd',
 
+    /// For Voice messages: base64-encoded waveform, see [crate::message::generate_waveform].
+    Waveform = b'W',
+
+    /// For Messages in the trash: ID of the chat the message was deleted from,
+    /// see [crate::message::restore_from_trash].
+    TrashedChatId = b'X',
+
+    /// For Messages in the trash: creation timestamp of the chat the message was deleted from,
+    /// used to detect that the chat ID was reused by another chat.
+    TrashedChatCreated = b'Z',
+
     /// For Messages
     MimeType = b'm',
 
@@ -67,6 +77,13 @@ pub enum Param {
     /// For Messages: quoted text.
     Quote = b'q',
 
+    /// For Messages: [`crate::message::ErrorCode`] of the error stored in `msgs.error`.
+    ErrorCode = b'z',
+
+    /// For Messages: set if the attachment of a received message could not be saved,
+    /// e.g. because the blob directory is read-only.
+    MediaNotSaved = b'N',
+
     /// For Messages
     Cmd = b'S',
 
@@ -111,6 +128,9 @@ pub enum Param {
     /// For Jobs: space-separated list of message recipients
     Recipients = b'R',
 
+    /// For Jobs: contact a copy of a broadcast message is sent to
+    ContactId = b'C',
+
     /// For Groups
     ///
     /// An unpromoted group has not had any messages sent to it and thus only exists on the
@@ -126,6 +146,16 @@ pub enum Param {
     /// For Chats
     Selftalk = b'K',
 
+    /// For Mailing lists: address to post to, taken from the `List-Post` header.
+    ///
+    /// Mailing lists without this parameter are read-only.
+    ListPost = b'p',
+
+    /// For Groups: timestamp of the last explicit change of the member list.
+    ///
+    /// Member lists implied by the recipients of messages sent before are not applied.
+    MemberListTimestamp = b'k',
+
     /// For Chats: On sending a new message we set the subject to "Re: <last subject>".
     /// Usually we just use the subject of the parent message, but if the parent message
     /// is deleted, we use the LastSubject of the chat.
@@ -136,12 +166,41 @@ pub enum Param {
 
     /// For MDN-sending job
     MsgId = b'I',
+
+    /// For Messages: manifest of an attachment sent in several parts, see [crate::chunks].
+    ChunkManifest = b'Y',
+
+    /// For Messages: size of a partially downloaded message in bytes, see [crate::download].
+    FullMessageSize = b'L',
+
+    /// For Messages: Message-ID of the message resent by this copy,
+    /// see [crate::chat::resend_msgs].
+    ResendOf = b'Q',
+
+    /// For Chats: space-separated IDs of the members of a protected chat
+    /// that are not verified anymore, see [crate::chat::ChatId::audit_protection].
+    ProtectionBroken = b'B',
+
+    /// For Chats: how notifications are presented, see [crate::constants::NotifyProfile].
+    NotifyProfile = b'j',
+
+    /// For Chats: timestamp of the last change of [Param::NotifyProfile],
+    /// changes synchronized from other devices are only applied if they are newer.
+    NotifyProfileTimestamp = b'J',
 }
 
 /// An object for handling key=value parameter lists.
 ///
 /// The structure is serialized by calling `to_string()` on it.
 ///
+/// Values are stored as strings; the typed accessors use these formats:
+///
+/// - integers (`get_int`, `get_i64`) and floats (`get_f64`) in their decimal `Display` form,
+/// - booleans (`get_bool`) as `1` or `0`, any other integer is read as `true`,
+/// - files (`get_path`, `get_blob`) as `$BLOBDIR/<name>` for blobs or as an absolute path.
+///
+/// Malformed values are read as `None` by the typed getters.
+///
 /// Only for library-internal use.
 #[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
 pub struct Params {
@@ -154,44 +213,102 @@ fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
             if i > 0 {
                 writeln!(f)?;
             }
-            write!(
-                f,
-                "{}={}",
-                *key as u8 as char,
-                value.split('\n').join("\n\n")
-            )?;
+            if value.contains(|c| c == '\n' || c == '\r') {
+                write!(
+                    f,
+                    "{}{}{}",
+                    *key as u8 as char,
+                    ESCAPED_SEPARATOR,
+                    escape(value)
+                )?;
+            } else {
+                write!(f, "{}={}", *key as u8 as char, value)?;
+            }
         }
         Ok(())
     }
 }
 
+/// Separator used instead of `=` for values written escaped.
+///
+/// Values without line breaks are written as `k=value` unchanged, so their serialization is
+/// the same as before escaping was introduced and backslashes in e.g. Windows paths keep
+/// their meaning. Values containing line breaks are written as `k:escaped` on a single line.
+const ESCAPED_SEPARATOR: char = ':';
+
+fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '=' => escaped.push_str("\\="),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn unescape(value: &str) -> String {
+    let mut unescaped = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => unescaped.push('\n'),
+            Some('r') => unescaped.push('\r'),
+            Some(c) => unescaped.push(c),
+            None => unescaped.push('\\'),
+        }
+    }
+    unescaped
+}
+
 impl str::FromStr for Params {
     type Err = Error;
 
+    /// Parses serialized params.
+    ///
+    /// Besides the escaped format, the legacy format is accepted, where a line break in
+    /// a value was written as an empty line followed by the rest of the value.
     fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
         let mut inner = BTreeMap::new();
-        let mut lines = s.lines().peekable();
+        let mut lines = s.split('\n').peekable();
 
         while let Some(line) = lines.next() {
-            if let [key, value] = line.splitn(2, '=').collect::<Vec<_>>()[..] {
-                let key = key.to_string();
-                let mut value = value.to_string();
-                while let Some(s) = lines.peek() {
-                    if !s.is_empty() {
-                        break;
+            if line.is_empty() && lines.peek().is_none() {
+                break;
+            }
+            let mut chars = line.chars();
+            let key = chars.next();
+            let value = match chars.next() {
+                Some('=') => {
+                    let mut value = chars.as_str().to_string();
+                    while let Some(s) = lines.peek() {
+                        if !s.is_empty() {
+                            break;
+                        }
+                        lines.next();
+                        value.push('\n');
+                        value += lines.next().unwrap_or_default();
                     }
-                    lines.next();
-                    value.push('\n');
-                    value += lines.next().unwrap_or_default();
-                }
-
-                if let Some(key) = key.as_bytes().first().and_then(|key| Param::from_u8(*key)) {
-                    inner.insert(key, value);
-                } else {
-                    bail!("Unknown key: {}", key);
+                    value
                 }
+                Some(ESCAPED_SEPARATOR) => unescape(chars.as_str()),
+                _ => bail!("Not a key-value pair: {:?}", line),
+            };
+
+            if let Some(key) = key
+                .filter(char::is_ascii)
+                .and_then(|key| Param::from_u8(key as u8))
+            {
+                inner.insert(key, value);
             } else {
-                bail!("Not a key-value pair: {:?}", line);
+                bail!("Unknown key: {:?}", key);
             }
         }
 
@@ -222,9 +339,10 @@ pub fn set(&mut self, key: Param, value: impl AsRef<str>) -> &mut Self {
     }
 
     /// Removes the given key, if it exists.
-    pub fn remove(&mut self, key: Param) -> &mut Self {
-        self.inner.remove(&key);
-        self
+    ///
+    /// Returns whether the key was set.
+    pub fn remove(&mut self, key: Param) -> bool {
+        self.inner.remove(&key).is_some()
     }
 
     /// Check if there are any values in this.
@@ -242,9 +360,16 @@ pub fn get_int(&self, key: Param) -> Option<i32> {
         self.get(key).and_then(|s| s.parse().ok())
     }
 
+    /// Get the given parameter and parse as `i64`.
+    pub fn get_i64(&self, key: Param) -> Option<i64> {
+        self.get(key).and_then(|s| s.parse().ok())
+    }
+
     /// Get the given parameter and parse as `bool`.
+    ///
+    /// Any integer other than `0` is `true`, see [Params::set_bool].
     pub fn get_bool(&self, key: Param) -> Option<bool> {
-        self.get_int(key).map(|v| v != 0)
+        self.get_i64(key).map(|v| v != 0)
     }
 
     /// Get the parameter behind `Param::Cmd` interpreted as `SystemMessage`.
@@ -260,8 +385,12 @@ pub fn set_cmd(&mut self, value: SystemMessage) {
     }
 
     /// Get the given parameter and parse as `f64`.
-    pub fn get_float(&self, key: Param) -> Option<f64> {
-        self.get(key).and_then(|s| s.parse().ok())
+    ///
+    /// Non-finite values are treated as malformed and result in `None`.
+    pub fn get_f64(&self, key: Param) -> Option<f64> {
+        self.get(key)
+            .and_then(|s| s.parse::<f64>().ok())
+            .filter(|v| v.is_finite())
     }
 
     /// Gets the given parameter and parse as [ParamsFile].
@@ -343,11 +472,43 @@ pub fn set_int(&mut self, key: Param, value: i32) -> &mut Self {
         self
     }
 
+    /// Set the given parameter to the passed in `i64`.
+    pub fn set_i64(&mut self, key: Param, value: i64) -> &mut Self {
+        self.set(key, format!("{}", value));
+        self
+    }
+
+    /// Set the given parameter to the passed in `bool`, stored as `1` or `0`.
+    pub fn set_bool(&mut self, key: Param, value: bool) -> &mut Self {
+        self.set_int(key, value as i32)
+    }
+
     /// Set the given parameter to the passed in `f64` .
-    pub fn set_float(&mut self, key: Param, value: f64) -> &mut Self {
+    pub fn set_f64(&mut self, key: Param, value: f64) -> &mut Self {
         self.set(key, format!("{}", value));
         self
     }
+
+    /// Set the given parameter to a file path.
+    ///
+    /// Paths inside the blob directory are stored as `$BLOBDIR/<name>`, so that
+    /// [Params::get_path] resolves them even if the blob directory is moved.
+    pub fn set_path(
+        &mut self,
+        key: Param,
+        path: impl AsRef<std::path::Path>,
+        context: &Context,
+    ) -> &mut Self {
+        let path = path.as_ref();
+        let blobdir: &std::path::Path = context.get_blobdir().as_ref();
+        let value = match path.strip_prefix(blobdir) {
+            Ok(name) if name.components().count() == 1 => {
+                format!("$BLOBDIR/{}", name.to_string_lossy())
+            }
+            _ => path.to_string_lossy().into_owned(),
+        };
+        self.set(key, value)
+    }
 }
 
 /// The value contained in [Param::File].
@@ -382,6 +543,7 @@ mod tests {
 
     use async_std::fs;
     use async_std::path::Path;
+    use proptest::prelude::*;
 
     use crate::test_utils::TestContext;
 
@@ -400,14 +562,13 @@ fn test_dc_param() {
 
         let mut p1 = Params::new();
 
-        p1.set(Param::Forwarded, "foo")
-            .set_int(Param::File, 2)
-            .remove(Param::GuaranteeE2ee)
-            .set_int(Param::Duration, 4);
+        p1.set(Param::Forwarded, "foo").set_int(Param::File, 2);
+        assert!(!p1.remove(Param::GuaranteeE2ee));
+        p1.set_int(Param::Duration, 4);
 
         assert_eq!(p1.to_string(), "a=foo\nd=4\nf=2");
 
-        p1.remove(Param::File);
+        assert!(p1.remove(Param::File));
 
         assert_eq!(p1.to_string(), "a=foo\nd=4",);
         assert_eq!(p1.len(), 2);
@@ -429,6 +590,51 @@ fn test_roundtrip() {
         assert_eq!(params.to_string().parse::<Params>().unwrap(), params);
     }
 
+    #[test]
+    fn test_escaping() {
+        let mut params = Params::new();
+        params.set(Param::File, "$BLOBDIR/line\nbreak.txt");
+        params.set(Param::Height, "a\r\nb\\n=c");
+        params.set(Param::Width, "C:\\Users\\ünïcödé=1.jpg");
+        params.set(Param::Duration, "\n");
+        params.set(Param::Forwarded, "");
+
+        let serialized = params.to_string();
+        assert_eq!(
+            serialized,
+            "a=\nd:\\n\nf:$BLOBDIR/line\\nbreak.txt\nh:a\\r\\nb\\\\n\\=c\nw=C:\\Users\\ünïcödé=1.jpg"
+        );
+        assert_eq!(serialized.lines().count(), 5);
+        assert_eq!(serialized.parse::<Params>().unwrap(), params);
+    }
+
+    #[test]
+    fn test_parse_legacy() {
+        // Line breaks were written as an empty line followed by the rest of the value.
+        let params: Params = "f=$BLOBDIR/line\n\nbreak.txt\nw=\\n".parse().unwrap();
+        assert_eq!(params.get(Param::File), Some("$BLOBDIR/line\nbreak.txt"));
+        assert_eq!(params.get(Param::Width), Some("\\n"));
+
+        let params: Params = "h=trailing\n\n".parse().unwrap();
+        assert_eq!(params.get(Param::Height), Some("trailing\n"));
+
+        assert!("".parse::<Params>().unwrap().is_empty());
+        assert!("x".parse::<Params>().is_err());
+        assert!("ä=1".parse::<Params>().is_err());
+    }
+
+    proptest! {
+        #[test]
+        fn test_roundtrip_arbitrary(file: String, text: String) {
+            let mut params = Params::new();
+            params.set(Param::File, &file);
+            params.set(Param::Arg, &text);
+            let parsed: Params = params.to_string().parse().unwrap();
+            prop_assert_eq!(parsed.get(Param::File), Some(file.as_str()));
+            prop_assert_eq!(parsed.get(Param::Arg), Some(text.as_str()));
+        }
+    }
+
     #[async_std::test]
     async fn test_params_file_fs_path() {
         let t = TestContext::new().await;
@@ -486,4 +692,65 @@ async fn test_params_get_fileparam() {
         assert!(p.get_path(Param::File, &t).unwrap().is_none());
         assert!(p.get_blob(Param::File, &t, false).await.unwrap().is_none());
     }
+
+    #[test]
+    fn test_typed_roundtrip() {
+        let mut p = Params::new();
+        p.set_int(Param::Width, -7)
+            .set_i64(Param::Duration, 1 << 40)
+            .set_bool(Param::Forwarded, true)
+            .set_bool(Param::GuaranteeE2ee, false)
+            .set_f64(Param::SetLatitude, 51.5);
+        let p: Params = p.to_string().parse().unwrap();
+
+        assert_eq!(p.get_int(Param::Width), Some(-7));
+        assert_eq!(p.get_i64(Param::Width), Some(-7));
+        assert_eq!(p.get_i64(Param::Duration), Some(1 << 40));
+        assert_eq!(p.get_int(Param::Duration), None);
+        assert_eq!(p.get_bool(Param::Forwarded), Some(true));
+        assert_eq!(p.get_bool(Param::GuaranteeE2ee), Some(false));
+        assert_eq!(p.get_f64(Param::SetLatitude), Some(51.5));
+        assert_eq!(p.get_f64(Param::SetLongitude), None);
+    }
+
+    #[test]
+    fn test_typed_malformed() {
+        let p: Params = "w=12.5\nh=abc\nl=NaN\nn=inf\nc=".parse().unwrap();
+        assert_eq!(p.get_int(Param::Width), None);
+        assert_eq!(p.get_i64(Param::Width), None);
+        assert_eq!(p.get_f64(Param::Width), Some(12.5));
+        assert_eq!(p.get_i64(Param::Height), None);
+        assert_eq!(p.get_bool(Param::Height), None);
+        assert_eq!(p.get_f64(Param::Height), None);
+        assert_eq!(p.get_f64(Param::SetLatitude), None);
+        assert_eq!(p.get_f64(Param::SetLongitude), None);
+        assert_eq!(p.get_bool(Param::GuaranteeE2ee), None);
+        assert_eq!(p.get_cmd(), SystemMessage::Unknown);
+    }
+
+    #[async_std::test]
+    async fn test_set_path() {
+        let t = TestContext::new().await;
+        let mut p = Params::new();
+
+        let in_blobdir = t.get_blobdir().join("avatar.png");
+        p.set_path(Param::File, &in_blobdir, &t);
+        assert_eq!(p.get(Param::File), Some("$BLOBDIR/avatar.png"));
+        assert_eq!(
+            p.get_path(Param::File, &t).unwrap(),
+            Some(PathBuf::from(in_blobdir))
+        );
+
+        let outside = t.dir.path().join("avatar.png");
+        p.set_path(Param::File, &outside, &t);
+        assert_eq!(p.get(Param::File), outside.to_str());
+        assert_eq!(
+            p.get_path(Param::File, &t).unwrap(),
+            Some(PathBuf::from(outside))
+        );
+
+        let nested = t.get_blobdir().join("sub").join("avatar.png");
+        p.set_path(Param::File, &nested, &t);
+        assert_eq!(p.get(Param::File), nested.to_str());
+    }
 }
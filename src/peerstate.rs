@@ -1,15 +1,19 @@
 //! # [Autocrypt Peer State](https://autocrypt.org/level1.html#peer-state-management) module
 
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::fmt;
 
 use anyhow::{bail, Result};
+use deltachat_derive::{FromSql, ToSql};
 use num_traits::FromPrimitive;
+use rusqlite::OptionalExtension;
 
 use crate::aheader::{Aheader, EncryptPreference};
-use crate::chat;
-use crate::constants::Blocked;
+use crate::chat::{self, ChatId, ProtectionStatus};
+use crate::constants::{Blocked, DC_CONTACT_ID_SELF};
+use crate::contact::addr_cmp;
 use crate::context::Context;
+use crate::dc_tools::{normalize_addr, time};
 use crate::events::EventType;
 use crate::key::{DcKey, Fingerprint, SignedPublicKey};
 use crate::sql::Sql;
@@ -30,6 +34,7 @@ pub enum PeerstateVerifiedStatus {
 }
 
 /// Peerstate represents the state of an Autocrypt peer.
+#[derive(Clone)]
 pub struct Peerstate {
     pub addr: String,
     pub last_seen: i64,
@@ -96,7 +101,7 @@ pub enum ToSave {
 impl Peerstate {
     pub fn from_header(header: &Aheader, message_time: i64) -> Self {
         Peerstate {
-            addr: header.addr.clone(),
+            addr: normalize_addr(&header.addr),
             last_seen: message_time,
             last_seen_autocrypt: message_time,
             prefer_encrypt: header.prefer_encrypt,
@@ -114,7 +119,7 @@ pub fn from_header(header: &Aheader, message_time: i64) -> Self {
 
     pub fn from_gossip(gossip_header: &Aheader, message_time: i64) -> Self {
         Peerstate {
-            addr: gossip_header.addr.clone(),
+            addr: normalize_addr(&gossip_header.addr),
             last_seen: 0,
             last_seen_autocrypt: 0,
 
@@ -144,7 +149,7 @@ pub async fn from_addr(context: &Context, addr: &str) -> Result<Option<Peerstate
                      verified_key, verified_key_fingerprint \
                      FROM acpeerstates \
                      WHERE addr=? COLLATE NOCASE;";
-        Self::from_stmt(context, query, paramsv![addr]).await
+        Self::from_stmt(context, query, paramsv![normalize_addr(addr)]).await
     }
 
     pub async fn from_fingerprint(
@@ -163,6 +168,44 @@ pub async fn from_fingerprint(
         Self::from_stmt(context, query, paramsv![fp, fp, fp]).await
     }
 
+    /// Loads the peerstates of several addresses at once.
+    ///
+    /// The returned map is keyed by the normalized, lowercased address, see [peerstate_key];
+    /// addresses without a peerstate do not appear in it.
+    pub async fn from_addrs(
+        context: &Context,
+        addrs: &[&str],
+    ) -> Result<HashMap<String, Peerstate>> {
+        let mut addrs: Vec<String> = addrs.iter().map(|addr| peerstate_key(addr)).collect();
+        addrs.sort_unstable();
+        addrs.dedup();
+
+        let mut res = HashMap::with_capacity(addrs.len());
+        for chunk in addrs.chunks(PEERSTATES_PER_QUERY) {
+            let query = format!(
+                "SELECT addr, last_seen, last_seen_autocrypt, prefer_encrypted, public_key, \
+                 gossip_timestamp, gossip_key, public_key_fingerprint, gossip_key_fingerprint, \
+                 verified_key, verified_key_fingerprint \
+                 FROM acpeerstates \
+                 WHERE addr COLLATE NOCASE IN ({});",
+                vec!["?"; chunk.len()].join(",")
+            );
+            let params: Vec<&dyn crate::ToSql> =
+                chunk.iter().map(|addr| addr as &dyn crate::ToSql).collect();
+            let peerstates = context
+                .sql
+                .query_map(query, params, Self::from_row, |rows| {
+                    rows.collect::<std::result::Result<Vec<_>, _>>()
+                        .map_err(Into::into)
+                })
+                .await?;
+            for peerstate in peerstates {
+                res.insert(peerstate_key(&peerstate.addr), peerstate);
+            }
+        }
+        Ok(res)
+    }
+
     async fn from_stmt(
         context: &Context,
         query: &str,
@@ -170,56 +213,58 @@ async fn from_stmt(
     ) -> Result<Option<Peerstate>> {
         let peerstate = context
             .sql
-            .query_row_optional(query, params, |row| {
-                /* all the above queries start with this: SELECT
-                addr, last_seen, last_seen_autocrypt, prefer_encrypted,
-                public_key, gossip_timestamp, gossip_key, public_key_fingerprint,
-                gossip_key_fingerprint, verified_key, verified_key_fingerprint
-                */
-
-                let res = Peerstate {
-                    addr: row.get(0)?,
-                    last_seen: row.get(1)?,
-                    last_seen_autocrypt: row.get(2)?,
-                    prefer_encrypt: EncryptPreference::from_i32(row.get(3)?).unwrap_or_default(),
-                    public_key: row
-                        .get(4)
-                        .ok()
-                        .and_then(|blob: Vec<u8>| SignedPublicKey::from_slice(&blob).ok()),
-                    public_key_fingerprint: row
-                        .get::<_, Option<String>>(7)?
-                        .map(|s| s.parse::<Fingerprint>())
-                        .transpose()
-                        .unwrap_or_default(),
-                    gossip_key: row
-                        .get(6)
-                        .ok()
-                        .and_then(|blob: Vec<u8>| SignedPublicKey::from_slice(&blob).ok()),
-                    gossip_key_fingerprint: row
-                        .get::<_, Option<String>>(8)?
-                        .map(|s| s.parse::<Fingerprint>())
-                        .transpose()
-                        .unwrap_or_default(),
-                    gossip_timestamp: row.get(5)?,
-                    verified_key: row
-                        .get(9)
-                        .ok()
-                        .and_then(|blob: Vec<u8>| SignedPublicKey::from_slice(&blob).ok()),
-                    verified_key_fingerprint: row
-                        .get::<_, Option<String>>(10)?
-                        .map(|s| s.parse::<Fingerprint>())
-                        .transpose()
-                        .unwrap_or_default(),
-                    to_save: None,
-                    fingerprint_changed: false,
-                };
-
-                Ok(res)
-            })
+            .query_row_optional(query, params, Self::from_row)
             .await?;
         Ok(peerstate)
     }
 
+    fn from_row(row: &rusqlite::Row) -> rusqlite::Result<Peerstate> {
+        /* all the above queries start with this: SELECT
+        addr, last_seen, last_seen_autocrypt, prefer_encrypted,
+        public_key, gossip_timestamp, gossip_key, public_key_fingerprint,
+        gossip_key_fingerprint, verified_key, verified_key_fingerprint
+        */
+
+        let res = Peerstate {
+            addr: row.get(0)?,
+            last_seen: row.get(1)?,
+            last_seen_autocrypt: row.get(2)?,
+            prefer_encrypt: EncryptPreference::from_i32(row.get(3)?).unwrap_or_default(),
+            public_key: row
+                .get(4)
+                .ok()
+                .and_then(|blob: Vec<u8>| SignedPublicKey::from_slice(&blob).ok()),
+            public_key_fingerprint: row
+                .get::<_, Option<String>>(7)?
+                .map(|s| s.parse::<Fingerprint>())
+                .transpose()
+                .unwrap_or_default(),
+            gossip_key: row
+                .get(6)
+                .ok()
+                .and_then(|blob: Vec<u8>| SignedPublicKey::from_slice(&blob).ok()),
+            gossip_key_fingerprint: row
+                .get::<_, Option<String>>(8)?
+                .map(|s| s.parse::<Fingerprint>())
+                .transpose()
+                .unwrap_or_default(),
+            gossip_timestamp: row.get(5)?,
+            verified_key: row
+                .get(9)
+                .ok()
+                .and_then(|blob: Vec<u8>| SignedPublicKey::from_slice(&blob).ok()),
+            verified_key_fingerprint: row
+                .get::<_, Option<String>>(10)?
+                .map(|s| s.parse::<Fingerprint>())
+                .transpose()
+                .unwrap_or_default(),
+            to_save: None,
+            fingerprint_changed: false,
+        };
+
+        Ok(res)
+    }
+
     pub fn recalc_fingerprint(&mut self) {
         if let Some(ref public_key) = self.public_key {
             let old_public_fingerprint = self.public_key_fingerprint.take();
@@ -267,7 +312,7 @@ pub(crate) async fn handle_fingerprint_change(&self, context: &Context) -> Resul
             if let Some(contact_id) = context
                 .sql
                 .query_get_value_result(
-                    "SELECT id FROM contacts WHERE addr=?;",
+                    "SELECT id FROM contacts WHERE addr=? COLLATE NOCASE;",
                     paramsv![self.addr],
                 )
                 .await?
@@ -281,6 +326,7 @@ pub(crate) async fn handle_fingerprint_change(&self, context: &Context) -> Resul
 
                 chat::add_info_msg(context, contact_chat_id, msg).await;
                 emit_event!(context, EventType::ChatModified(contact_chat_id));
+                self.audit_protected_chats(context).await?;
             } else {
                 bail!("contact with peerstate.addr {:?} not found", &self.addr);
             }
@@ -288,8 +334,49 @@ pub(crate) async fn handle_fingerprint_change(&self, context: &Context) -> Resul
         Ok(())
     }
 
+    /// Checks the protection status of the protected chats the contact is a member of,
+    /// as its keys changed.
+    pub(crate) async fn audit_protected_chats(&self, context: &Context) -> Result<()> {
+        for chat_id in protected_chats(context, &self.addr).await? {
+            chat_id.audit_protection(context).await?;
+        }
+        Ok(())
+    }
+
+    /// Removes the peerstate of `addr` from the database.
+    ///
+    /// Protected chats the contact is a member of are downgraded to
+    /// unprotected, as the key they relied on is gone.
+    pub async fn forget(context: &Context, addr: &str) -> Result<()> {
+        let chat_ids = protected_chats(context, addr).await?;
+
+        context
+            .sql
+            .execute(
+                "DELETE FROM acpeerstates WHERE addr=? COLLATE NOCASE;",
+                paramsv![addr],
+            )
+            .await?;
+
+        for chat_id in chat_ids {
+            chat_id
+                .inner_set_protection(context, ProtectionStatus::Unprotected)
+                .await?;
+            chat_id
+                .add_protection_msg(
+                    context,
+                    ProtectionStatus::Unprotected,
+                    false,
+                    DC_CONTACT_ID_SELF,
+                )
+                .await?;
+        }
+        info!(context, "Forgot peerstate of {}.", addr);
+        Ok(())
+    }
+
     pub fn apply_header(&mut self, header: &Aheader, message_time: i64) {
-        if self.addr.to_lowercase() != header.addr.to_lowercase() {
+        if !addr_cmp(&self.addr, &header.addr) {
             return;
         }
 
@@ -314,7 +401,7 @@ pub fn apply_header(&mut self, header: &Aheader, message_time: i64) {
     }
 
     pub fn apply_gossip(&mut self, gossip_header: &Aheader, message_time: i64) {
-        if self.addr.to_lowercase() != gossip_header.addr.to_lowercase() {
+        if !addr_cmp(&self.addr, &gossip_header.addr) {
             return;
         }
 
@@ -428,33 +515,49 @@ pub fn set_verified(
 
     pub async fn save_to_db(&self, sql: &Sql, create: bool) -> crate::sql::Result<()> {
         if self.to_save == Some(ToSave::All) || create {
-            sql.execute(
-                if create {
-                    "INSERT INTO acpeerstates (last_seen, last_seen_autocrypt, prefer_encrypted, \
+            let public_key_fingerprint = self.public_key_fingerprint.as_ref().map(|fp| fp.hex());
+            let verified_key_fingerprint =
+                self.verified_key_fingerprint.as_ref().map(|fp| fp.hex());
+
+            sql.transaction(|tx| {
+                if !create {
+                    record_history(
+                        tx,
+                        &self.addr,
+                        public_key_fingerprint.as_deref(),
+                        verified_key_fingerprint.as_deref(),
+                    )?;
+                }
+                tx.execute(
+                    if create {
+                        "INSERT INTO acpeerstates (last_seen, last_seen_autocrypt, prefer_encrypted, \
                  public_key, gossip_timestamp, gossip_key, public_key_fingerprint, gossip_key_fingerprint, \
                  verified_key, verified_key_fingerprint, addr \
                 ) VALUES(?,?,?,?,?,?,?,?,?,?,?)"
-                } else {
-                    "UPDATE acpeerstates \
+                    } else {
+                        "UPDATE acpeerstates \
                  SET last_seen=?, last_seen_autocrypt=?, prefer_encrypted=?, \
                  public_key=?, gossip_timestamp=?, gossip_key=?, public_key_fingerprint=?, gossip_key_fingerprint=?, \
                  verified_key=?, verified_key_fingerprint=? \
                  WHERE addr=?"
-                },
-                paramsv![
-                self.last_seen,
-                self.last_seen_autocrypt,
-                self.prefer_encrypt as i64,
-                self.public_key.as_ref().map(|k| k.to_bytes()),
-                self.gossip_timestamp,
-                self.gossip_key.as_ref().map(|k| k.to_bytes()),
-                self.public_key_fingerprint.as_ref().map(|fp| fp.hex()),
-                self.gossip_key_fingerprint.as_ref().map(|fp| fp.hex()),
-                self.verified_key.as_ref().map(|k| k.to_bytes()),
-                self.verified_key_fingerprint.as_ref().map(|fp| fp.hex()),
-                self.addr,
-                ],
-                ).await?;
+                    },
+                    rusqlite::params![
+                        self.last_seen,
+                        self.last_seen_autocrypt,
+                        self.prefer_encrypt as i64,
+                        self.public_key.as_ref().map(|k| k.to_bytes()),
+                        self.gossip_timestamp,
+                        self.gossip_key.as_ref().map(|k| k.to_bytes()),
+                        public_key_fingerprint,
+                        self.gossip_key_fingerprint.as_ref().map(|fp| fp.hex()),
+                        self.verified_key.as_ref().map(|k| k.to_bytes()),
+                        verified_key_fingerprint,
+                        self.addr,
+                    ],
+                )?;
+                Ok(())
+            })
+            .await?;
         } else if self.to_save == Some(ToSave::Timestamps) {
             sql.execute(
                 "UPDATE acpeerstates SET last_seen=?, last_seen_autocrypt=?, gossip_timestamp=? \
@@ -481,6 +584,241 @@ pub fn has_verified_key(&self, fingerprints: &HashSet<Fingerprint>) -> bool {
     }
 }
 
+/// Returns the key of `addr` in the map returned by [Peerstate::from_addrs].
+pub(crate) fn peerstate_key(addr: &str) -> String {
+    normalize_addr(addr).to_lowercase()
+}
+
+/// Returns the protected chats the contact with the address `addr` is a member of.
+async fn protected_chats(context: &Context, addr: &str) -> Result<Vec<ChatId>> {
+    let chat_ids = context
+        .sql
+        .query_map(
+            "SELECT DISTINCT c.id FROM chats c \
+             INNER JOIN chats_contacts cc ON c.id=cc.chat_id \
+             INNER JOIN contacts ct ON ct.id=cc.contact_id \
+             WHERE ct.addr=? COLLATE NOCASE AND c.protected=?;",
+            paramsv![addr, ProtectionStatus::Protected],
+            |row| row.get::<_, ChatId>(0),
+            |ids| {
+                ids.collect::<std::result::Result<Vec<_>, _>>()
+                    .map_err(Into::into)
+            },
+        )
+        .await?;
+    Ok(chat_ids)
+}
+
+/// Maximum number of addresses bound in a single lookup query,
+/// staying below SQLite's default limit of 999 host parameters.
+const PEERSTATES_PER_QUERY: usize = 500;
+
+/// Number of history entries kept per address by [prune_history].
+const HISTORY_ENTRIES_PER_ADDR: usize = 20;
+
+/// Key whose change is recorded in a [PeerstateChange].
+#[derive(Debug, PartialEq, Eq, Clone, Copy, FromPrimitive, FromSql, ToSql)]
+#[repr(u32)]
+pub enum ChangedKey {
+    PublicKey = 1,
+    VerifiedKey = 2,
+}
+
+impl Default for ChangedKey {
+    fn default() -> Self {
+        ChangedKey::PublicKey
+    }
+}
+
+/// A change of a peer's key, as returned by [get_history].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PeerstateChange {
+    pub timestamp: i64,
+    pub key: ChangedKey,
+    pub old_fingerprint: Option<Fingerprint>,
+    pub new_fingerprint: Option<Fingerprint>,
+}
+
+/// Appends a history entry for each key fingerprint of `addr`
+/// that differs from the one stored in `acpeerstates`.
+fn record_history(
+    tx: &rusqlite::Transaction,
+    addr: &str,
+    public_key_fingerprint: Option<&str>,
+    verified_key_fingerprint: Option<&str>,
+) -> rusqlite::Result<()> {
+    let stored = tx
+        .query_row(
+            "SELECT public_key_fingerprint, verified_key_fingerprint \
+             FROM acpeerstates WHERE addr=? COLLATE NOCASE;",
+            rusqlite::params![addr],
+            |row| {
+                Ok((
+                    row.get::<_, Option<String>>(0)?,
+                    row.get::<_, Option<String>>(1)?,
+                ))
+            },
+        )
+        .optional()?;
+    let (old_public, old_verified) = match stored {
+        Some(stored) => stored,
+        None => return Ok(()),
+    };
+
+    let now = time();
+    for &(key, old, new) in &[
+        (
+            ChangedKey::PublicKey,
+            old_public.as_deref(),
+            public_key_fingerprint,
+        ),
+        (
+            ChangedKey::VerifiedKey,
+            old_verified.as_deref(),
+            verified_key_fingerprint,
+        ),
+    ] {
+        let changed = match (old, new) {
+            (Some(old), Some(new)) => !old.eq_ignore_ascii_case(new),
+            (None, None) => false,
+            _ => true,
+        };
+        if changed {
+            tx.execute(
+                "INSERT INTO peerstate_history (addr, timestamp, key, old_fingerprint, new_fingerprint) \
+                 VALUES (?,?,?,?,?);",
+                rusqlite::params![addr, now, key, old, new],
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// Returns the recorded key changes of `addr`, newest first.
+pub async fn get_history(context: &Context, addr: &str) -> Result<Vec<PeerstateChange>> {
+    let history = context
+        .sql
+        .query_map(
+            "SELECT timestamp, key, old_fingerprint, new_fingerprint \
+             FROM peerstate_history \
+             WHERE addr=? COLLATE NOCASE \
+             ORDER BY timestamp DESC, id DESC;",
+            paramsv![addr],
+            |row| {
+                let fingerprint = |idx| -> rusqlite::Result<Option<Fingerprint>> {
+                    Ok(row
+                        .get::<_, Option<String>>(idx)?
+                        .and_then(|s| s.parse::<Fingerprint>().ok()))
+                };
+                Ok(PeerstateChange {
+                    timestamp: row.get(0)?,
+                    key: row.get(1)?,
+                    old_fingerprint: fingerprint(2)?,
+                    new_fingerprint: fingerprint(3)?,
+                })
+            },
+            |rows| {
+                rows.collect::<std::result::Result<Vec<_>, _>>()
+                    .map_err(Into::into)
+            },
+        )
+        .await?;
+    Ok(history)
+}
+
+/// Deletes all but the newest [HISTORY_ENTRIES_PER_ADDR] history entries of each address.
+pub(crate) async fn prune_history(context: &Context) -> Result<usize> {
+    let deleted = context
+        .sql
+        .execute(
+            "DELETE FROM peerstate_history WHERE id NOT IN \
+             (SELECT h.id FROM peerstate_history h \
+              WHERE h.addr=peerstate_history.addr \
+              ORDER BY h.timestamp DESC, h.id DESC LIMIT ?);",
+            paramsv![HISTORY_ENTRIES_PER_ADDR as i64],
+        )
+        .await?;
+    Ok(deleted)
+}
+
+/// Overview of a stored peerstate, as returned by [list_peerstates].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PeerstateInfo {
+    pub addr: String,
+    pub prefer_encrypt: EncryptPreference,
+    pub last_seen: i64,
+    pub public_key_fingerprint: Option<Fingerprint>,
+    pub gossip_key_fingerprint: Option<Fingerprint>,
+    pub verified_key_fingerprint: Option<Fingerprint>,
+
+    /// The peer replaced its public key by another one during the last
+    /// [KEY_CHANGE_WINDOW] seconds.
+    pub key_changed: bool,
+}
+
+/// Time in seconds for which a key change is reported by [list_peerstates].
+pub const KEY_CHANGE_WINDOW: i64 = 30 * 24 * 60 * 60;
+
+/// Lists stored peerstates ordered by address.
+///
+/// If `query` is given, only peerstates whose address contains it
+/// (case-insensitively) are returned.
+/// `limit` and `offset` page through the result.
+pub async fn list_peerstates(
+    context: &Context,
+    query: Option<&str>,
+    limit: usize,
+    offset: usize,
+) -> Result<Vec<PeerstateInfo>> {
+    let query = query.unwrap_or_default();
+    let list = context
+        .sql
+        .query_map(
+            "SELECT addr, prefer_encrypted, last_seen, public_key_fingerprint, \
+             gossip_key_fingerprint, verified_key_fingerprint, \
+             EXISTS (SELECT 1 FROM peerstate_history h \
+                     WHERE h.addr=acpeerstates.addr AND h.key=? \
+                     AND h.old_fingerprint IS NOT NULL AND h.new_fingerprint IS NOT NULL \
+                     AND h.timestamp>?) \
+             FROM acpeerstates \
+             WHERE instr(lower(addr), lower(?))>0 \
+             ORDER BY addr COLLATE NOCASE \
+             LIMIT ? OFFSET ?;",
+            paramsv![
+                ChangedKey::PublicKey,
+                time() - KEY_CHANGE_WINDOW,
+                query,
+                limit as i64,
+                offset as i64
+            ],
+            |row| {
+                let fingerprint = |idx| -> rusqlite::Result<Option<Fingerprint>> {
+                    Ok(row
+                        .get::<_, Option<String>>(idx)?
+                        .and_then(|s| s.parse::<Fingerprint>().ok()))
+                };
+                let public_key_fingerprint = fingerprint(3)?;
+                let gossip_key_fingerprint = fingerprint(4)?;
+                let verified_key_fingerprint = fingerprint(5)?;
+                Ok(PeerstateInfo {
+                    addr: row.get(0)?,
+                    prefer_encrypt: EncryptPreference::from_i32(row.get(1)?).unwrap_or_default(),
+                    last_seen: row.get(2)?,
+                    public_key_fingerprint,
+                    gossip_key_fingerprint,
+                    verified_key_fingerprint,
+                    key_changed: row.get(6)?,
+                })
+            },
+            |rows| {
+                rows.collect::<std::result::Result<Vec<_>, _>>()
+                    .map_err(Into::into)
+            },
+        )
+        .await?;
+    Ok(list)
+}
+
 impl From<crate::key::FingerprintError> for rusqlite::Error {
     fn from(_source: crate::key::FingerprintError) -> Self {
         Self::InvalidColumnType(0, "Invalid fingerprint".into(), rusqlite::types::Type::Text)
@@ -489,6 +827,8 @@ fn from(_source: crate::key::FingerprintError) -> Self {
 
 #[cfg(test)]
 mod tests {
+    use std::sync::atomic::Ordering;
+
     use super::*;
     use crate::test_utils::alice_keypair;
     use pretty_assertions::assert_eq;
@@ -673,4 +1013,208 @@ async fn test_peerstate_degrade_reordering() {
         peerstate.apply_header(&header, 400);
         assert_eq!(peerstate.prefer_encrypt, EncryptPreference::Mutual);
     }
+
+    fn peerstate_for(addr: &str) -> Peerstate {
+        let pub_key = alice_keypair().public;
+        Peerstate {
+            addr: addr.into(),
+            last_seen: 10,
+            last_seen_autocrypt: 10,
+            prefer_encrypt: EncryptPreference::Mutual,
+            public_key: Some(pub_key.clone()),
+            public_key_fingerprint: Some(pub_key.fingerprint()),
+            gossip_key: None,
+            gossip_timestamp: 0,
+            gossip_key_fingerprint: None,
+            verified_key: Some(pub_key.clone()),
+            verified_key_fingerprint: Some(pub_key.fingerprint()),
+            to_save: Some(ToSave::All),
+            fingerprint_changed: false,
+        }
+    }
+
+    #[async_std::test]
+    async fn test_list_peerstates() {
+        let t = crate::test_utils::TestContext::new().await;
+        assert!(list_peerstates(&t, None, 100, 0).await.unwrap().is_empty());
+        assert!(list_peerstates(&t, Some("foo"), 100, 0)
+            .await
+            .unwrap()
+            .is_empty());
+
+        for addr in &["carol@example.org", "dave@example.net", "Erin@Example.org"] {
+            peerstate_for(addr).save_to_db(&t.sql, true).await.unwrap();
+        }
+
+        let all = list_peerstates(&t, None, 100, 0).await.unwrap();
+        let addrs: Vec<&str> = all.iter().map(|info| info.addr.as_str()).collect();
+        assert_eq!(
+            addrs,
+            vec!["carol@example.org", "dave@example.net", "Erin@Example.org"]
+        );
+        let first = all.first().unwrap();
+        assert_eq!(first.prefer_encrypt, EncryptPreference::Mutual);
+        assert_eq!(
+            first.verified_key_fingerprint,
+            Some(alice_keypair().public.fingerprint())
+        );
+        assert!(!first.key_changed);
+
+        let found = list_peerstates(&t, Some("EXAMPLE.ORG"), 100, 0)
+            .await
+            .unwrap();
+        let addrs: Vec<&str> = found.iter().map(|info| info.addr.as_str()).collect();
+        assert_eq!(addrs, vec!["carol@example.org", "Erin@Example.org"]);
+
+        let page = list_peerstates(&t, None, 1, 1).await.unwrap();
+        assert_eq!(page.len(), 1);
+        assert_eq!(page.first().unwrap().addr, "dave@example.net");
+    }
+
+    #[async_std::test]
+    async fn test_forget_peerstate() {
+        let t = crate::test_utils::TestContext::new_alice().await;
+        let chat = t.create_chat_with_contact("Bob", "bob@example.net").await;
+        peerstate_for("bob@example.net")
+            .save_to_db(&t.sql, true)
+            .await
+            .unwrap();
+        t.sql
+            .execute(
+                "UPDATE chats SET protected=1 WHERE id=?;",
+                paramsv![chat.id],
+            )
+            .await
+            .unwrap();
+
+        Peerstate::forget(&t, "BOB@example.net").await.unwrap();
+
+        assert!(Peerstate::from_addr(&t, "bob@example.net")
+            .await
+            .unwrap()
+            .is_none());
+        let chat = chat::Chat::load_from_db(&t, chat.id).await.unwrap();
+        assert!(!chat.is_protected());
+
+        // Forgetting an unknown address is not an error.
+        Peerstate::forget(&t, "nobody@example.org").await.unwrap();
+    }
+
+    #[async_std::test]
+    async fn test_peerstate_history() {
+        let t = crate::test_utils::TestContext::new().await;
+        let addr = "carol@example.org";
+        let alice_fp = alice_keypair().public.fingerprint();
+        let bob_key = crate::test_utils::bob_keypair().public;
+        let bob_fp = bob_key.fingerprint();
+
+        let mut peerstate = peerstate_for(addr);
+        peerstate.save_to_db(&t.sql, true).await.unwrap();
+        assert!(get_history(&t, addr).await.unwrap().is_empty());
+
+        // Saving unchanged keys does not add history.
+        peerstate.save_to_db(&t.sql, false).await.unwrap();
+        assert!(get_history(&t, addr).await.unwrap().is_empty());
+
+        peerstate.public_key = Some(bob_key);
+        peerstate.public_key_fingerprint = Some(bob_fp.clone());
+        peerstate.save_to_db(&t.sql, false).await.unwrap();
+
+        let mut peerstate = peerstate_for(addr);
+        peerstate.save_to_db(&t.sql, false).await.unwrap();
+
+        let history = get_history(&t, "Carol@Example.org").await.unwrap();
+        assert_eq!(history.len(), 2);
+        let newest = history.first().unwrap();
+        assert_eq!(newest.key, ChangedKey::PublicKey);
+        assert_eq!(newest.old_fingerprint, Some(bob_fp.clone()));
+        assert_eq!(newest.new_fingerprint, Some(alice_fp.clone()));
+        let oldest = history.get(1).unwrap();
+        assert_eq!(oldest.key, ChangedKey::PublicKey);
+        assert_eq!(oldest.old_fingerprint, Some(alice_fp.clone()));
+        assert_eq!(oldest.new_fingerprint, Some(bob_fp.clone()));
+
+        let info = list_peerstates(&t, Some(addr), 1, 0).await.unwrap();
+        assert!(info.first().unwrap().key_changed);
+
+        // Old changes are not reported as recent.
+        t.sql
+            .execute(
+                "UPDATE peerstate_history SET timestamp=?;",
+                paramsv![time() - KEY_CHANGE_WINDOW - 1],
+            )
+            .await
+            .unwrap();
+        let info = list_peerstates(&t, Some(addr), 1, 0).await.unwrap();
+        assert!(!info.first().unwrap().key_changed);
+
+        // Dropping the verified key is recorded as well.
+        peerstate.verified_key = None;
+        peerstate.verified_key_fingerprint = None;
+        peerstate.save_to_db(&t.sql, false).await.unwrap();
+        let history = get_history(&t, addr).await.unwrap();
+        assert_eq!(history.len(), 3);
+        let newest = history.first().unwrap();
+        assert_eq!(newest.key, ChangedKey::VerifiedKey);
+        assert_eq!(newest.old_fingerprint, Some(alice_fp.clone()));
+        assert_eq!(newest.new_fingerprint, None);
+
+        for i in 0..30 {
+            let fp = if i % 2 == 0 { &bob_fp } else { &alice_fp };
+            peerstate.public_key_fingerprint = Some(fp.clone());
+            peerstate.save_to_db(&t.sql, false).await.unwrap();
+        }
+        assert_eq!(get_history(&t, addr).await.unwrap().len(), 33);
+
+        assert_eq!(prune_history(&t).await.unwrap(), 13);
+        let history = get_history(&t, addr).await.unwrap();
+        assert_eq!(history.len(), HISTORY_ENTRIES_PER_ADDR);
+        assert_eq!(
+            history.first().unwrap().new_fingerprint,
+            Some(alice_fp.clone())
+        );
+    }
+
+    #[async_std::test]
+    async fn test_from_addrs() {
+        let t = crate::test_utils::TestContext::new().await;
+        assert!(Peerstate::from_addrs(&t, &[]).await.unwrap().is_empty());
+
+        let addrs: Vec<String> = (0..500).map(|i| format!("peer{}@example.org", i)).collect();
+        for addr in &addrs {
+            peerstate_for(addr).save_to_db(&t.sql, true).await.unwrap();
+        }
+
+        let mut lookup: Vec<&str> = addrs.iter().map(|addr| addr.as_str()).collect();
+        lookup.push("missing@example.org");
+        // The 501 addresses are looked up with one query per chunk of 500 addresses.
+        let queries_before = t.sql.query_map_count.load(Ordering::Relaxed);
+        let peerstates = Peerstate::from_addrs(&t, &lookup).await.unwrap();
+        assert_eq!(
+            t.sql.query_map_count.load(Ordering::Relaxed) - queries_before,
+            2
+        );
+        assert_eq!(peerstates.len(), 500);
+        assert!(!peerstates.contains_key("missing@example.org"));
+        let peerstate = peerstates.get("peer42@example.org").unwrap();
+        assert_eq!(peerstate.addr, "peer42@example.org");
+        assert_eq!(
+            peerstate.public_key_fingerprint,
+            Some(alice_keypair().public.fingerprint())
+        );
+
+        let peerstates = Peerstate::from_addrs(
+            &t,
+            &[
+                "Peer1@Example.org",
+                "peer1@example.org",
+                "PEER2@EXAMPLE.ORG",
+            ],
+        )
+        .await
+        .unwrap();
+        let mut keys: Vec<&String> = peerstates.keys().collect();
+        keys.sort();
+        assert_eq!(keys, vec!["peer1@example.org", "peer2@example.org"]);
+    }
 }
@@ -353,12 +353,19 @@ pub async fn symm_encrypt(passphrase: &str, plain: &[u8]) -> Result<String> {
     .await
 }
 
+/// Error of [symm_decrypt] if the ciphertext is no armored OpenPGP message.
+///
+/// Other errors of [symm_decrypt] are usually caused by a wrong passphrase.
+#[derive(Debug, thiserror::Error)]
+#[error("Invalid armored OpenPGP message: {0}")]
+pub struct InvalidArmoredMessage(#[source] pgp::errors::Error);
+
 /// Symmetric decryption.
 pub async fn symm_decrypt<T: std::io::Read + std::io::Seek>(
     passphrase: &str,
     ctext: T,
 ) -> Result<Vec<u8>> {
-    let (enc_msg, _) = Message::from_armor_single(ctext)?;
+    let (enc_msg, _) = Message::from_armor_single(ctext).map_err(InvalidArmoredMessage)?;
 
     let passphrase = passphrase.to_string();
     async_std::task::spawn_blocking(move || {
@@ -1,11 +1,15 @@
 //! [Provider database](https://providers.delta.chat/) module
 
 mod data;
+pub mod overrides;
 
 use crate::config::Config;
+use crate::context::Context;
 use crate::provider::data::{PROVIDER_DATA, PROVIDER_IDS, PROVIDER_UPDATED};
+use crate::provider::overrides::{get_provider_override, ProviderOverride};
 use async_std_resolver::{config, resolver};
 use chrono::{NaiveDateTime, NaiveTime};
+use serde::Deserialize;
 
 #[derive(Debug, Display, Copy, Clone, PartialEq, FromPrimitive, ToPrimitive)]
 #[repr(u8)]
@@ -15,14 +19,16 @@ pub enum Status {
     BROKEN = 3,
 }
 
-#[derive(Debug, Display, PartialEq, Copy, Clone, FromPrimitive, ToPrimitive)]
+#[derive(Debug, Display, PartialEq, Copy, Clone, FromPrimitive, ToPrimitive, Deserialize)]
+#[serde(rename_all = "lowercase")]
 #[repr(u8)]
 pub enum Protocol {
     SMTP = 1,
     IMAP = 2,
 }
 
-#[derive(Debug, Display, PartialEq, Copy, Clone, FromPrimitive, ToPrimitive)]
+#[derive(Debug, Display, PartialEq, Copy, Clone, FromPrimitive, ToPrimitive, Deserialize)]
+#[serde(rename_all = "lowercase")]
 #[repr(u8)]
 pub enum Socket {
     Automatic = 0,
@@ -37,7 +43,8 @@ fn default() -> Self {
     }
 }
 
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Clone, Deserialize)]
+#[serde(rename_all = "lowercase")]
 #[repr(u8)]
 pub enum UsernamePattern {
     EMAIL = 1,
@@ -81,23 +88,74 @@ pub struct Provider {
     pub oauth2_authorizer: Option<Oauth2Authorizer>,
 }
 
+/// Provider information found for a domain.
+#[derive(Debug, Clone)]
+pub enum ProviderInfo {
+    /// Entry of the compiled-in provider database.
+    Builtin(&'static Provider),
+
+    /// Entry of the provider overrides file.
+    Override(ProviderOverride),
+}
+
+impl ProviderInfo {
+    pub fn status(&self) -> Status {
+        match self {
+            ProviderInfo::Builtin(provider) => provider.status,
+            ProviderInfo::Override(_) => Status::OK,
+        }
+    }
+
+    pub fn before_login_hint(&self) -> &str {
+        match self {
+            ProviderInfo::Builtin(provider) => provider.before_login_hint,
+            ProviderInfo::Override(provider) => &provider.before_login_hint,
+        }
+    }
+
+    pub fn after_login_hint(&self) -> &str {
+        match self {
+            ProviderInfo::Builtin(provider) => provider.after_login_hint,
+            ProviderInfo::Override(provider) => &provider.after_login_hint,
+        }
+    }
+
+    pub fn overview_page(&self) -> &str {
+        match self {
+            ProviderInfo::Builtin(provider) => provider.overview_page,
+            ProviderInfo::Override(provider) => &provider.overview_page,
+        }
+    }
+}
+
 /// Returns provider for the given domain.
 ///
-/// This function looks up domain in offline database first. If not
-/// found, it queries MX record for the domain and looks up offline
-/// database for MX domains.
+/// This function looks up the provider overrides of the context first,
+/// then the offline database. If not found there, it queries MX record
+/// for the domain and looks up offline database for MX domains,
+/// unless `skip_mx` is set.
 ///
 /// For compatibility, email address can be passed to this function
 /// instead of the domain.
-pub async fn get_provider_info(domain: &str) -> Option<&'static Provider> {
+pub async fn get_provider_info(
+    context: &Context,
+    domain: &str,
+    skip_mx: bool,
+) -> Option<ProviderInfo> {
     let domain = domain.rsplitn(2, '@').next()?;
 
+    if let Some(provider_override) = get_provider_override(context, domain).await {
+        return Some(ProviderInfo::Override(provider_override));
+    }
+
     if let Some(provider) = get_provider_by_domain(domain) {
-        return Some(provider);
+        return Some(ProviderInfo::Builtin(provider));
     }
 
-    if let Some(provider) = get_provider_by_mx(domain).await {
-        return Some(provider);
+    if !skip_mx {
+        if let Some(provider) = get_provider_by_mx(domain).await {
+            return Some(ProviderInfo::Builtin(provider));
+        }
     }
 
     None
@@ -170,6 +228,7 @@ mod tests {
 
     use super::*;
     use crate::dc_tools::time;
+    use crate::test_utils::TestContext;
     use chrono::NaiveDate;
 
     #[test]
@@ -219,11 +278,48 @@ fn test_get_provider_by_id() {
 
     #[async_std::test]
     async fn test_get_provider_info() {
-        assert!(get_provider_info("").await.is_none());
-        assert!(get_provider_info("google.com").await.unwrap().id == "gmail");
+        let t = TestContext::new().await;
+        assert!(get_provider_info(&t, "", false).await.is_none());
+        assert!(matches!(
+            get_provider_info(&t, "google.com", false).await,
+            Some(ProviderInfo::Builtin(provider)) if provider.id == "gmail"
+        ));
 
         // get_provider_info() accepts email addresses for backwards compatibility
-        assert!(get_provider_info("example@google.com").await.unwrap().id == "gmail");
+        assert!(matches!(
+            get_provider_info(&t, "example@google.com", false).await,
+            Some(ProviderInfo::Builtin(provider)) if provider.id == "gmail"
+        ));
+    }
+
+    #[async_std::test]
+    async fn test_get_provider_info_override() {
+        let t = TestContext::new().await;
+        let path = t.get_blobdir().join(overrides::OVERRIDES_NAME);
+        async_std::fs::write(
+            &path,
+            "[[provider]]\n\
+             domains = [\"nauta.cu\"]\n\
+             before_login_hint = \"Use your company login.\"\n\
+             overview_page = \"https://example.org/help\"\n",
+        )
+        .await
+        .unwrap();
+        t.sql
+            .set_raw_config(&t, overrides::OVERRIDES_PATH_KEY, path.to_str())
+            .await
+            .unwrap();
+
+        let info = get_provider_info(&t, "alice@nauta.cu", true).await.unwrap();
+        assert!(matches!(info, ProviderInfo::Override(_)));
+        assert_eq!(info.status(), Status::OK);
+        assert_eq!(info.before_login_hint(), "Use your company login.");
+        assert_eq!(info.after_login_hint(), "");
+        assert_eq!(info.overview_page(), "https://example.org/help");
+
+        // Domains without an override still use the compiled-in database.
+        let info = get_provider_info(&t, "googlemail.com", true).await.unwrap();
+        assert_eq!(info.status(), Status::PREPARATION);
     }
 
     #[test]
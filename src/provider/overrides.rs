@@ -0,0 +1,236 @@
+//! # Provider database overrides
+//!
+//! Deployments can ship their own provider information in a `provider-overrides.toml`
+//! without changing the compiled-in provider database:
+//!
+//! ```toml
+//! [[provider]]
+//! domains = ["example.org", "example.net"]
+//! before_login_hint = "Use your company login."
+//!
+//! [[provider.server]]
+//! protocol = "imap"
+//! socket = "ssl"
+//! hostname = "imap.example.org"
+//! port = 993
+//! username_pattern = "email"
+//! ```
+//!
+//! The file is looked up next to `accounts.toml`;
+//! another path can be set per context in the `provider_overrides_file` raw config.
+//! It is read on each configure, so changes take effect without a restart.
+
+use anyhow::{Context as _, Result};
+use async_std::path::{Path, PathBuf};
+use serde::Deserialize;
+
+use crate::accounts::CONFIG_NAME;
+use crate::context::Context;
+use crate::provider::{Protocol, Socket, UsernamePattern};
+
+/// Name of the overrides file placed next to `accounts.toml`.
+pub const OVERRIDES_NAME: &str = "provider-overrides.toml";
+
+/// Raw config key of a custom overrides file path.
+pub(crate) const OVERRIDES_PATH_KEY: &str = "provider_overrides_file";
+
+/// A server of a [ProviderOverride].
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct ServerOverride {
+    pub protocol: Protocol,
+    #[serde(default)]
+    pub socket: Socket,
+    pub hostname: String,
+    #[serde(default)]
+    pub port: u16,
+    #[serde(default = "default_username_pattern")]
+    pub username_pattern: UsernamePattern,
+}
+
+fn default_username_pattern() -> UsernamePattern {
+    UsernamePattern::EMAIL
+}
+
+/// Provider information taking precedence over the compiled-in provider database.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct ProviderOverride {
+    /// Domains the entry applies to.
+    pub domains: Vec<String>,
+    #[serde(default, rename = "server")]
+    pub servers: Vec<ServerOverride>,
+    #[serde(default)]
+    pub before_login_hint: String,
+    #[serde(default)]
+    pub after_login_hint: String,
+    #[serde(default)]
+    pub overview_page: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct OverridesFile {
+    #[serde(default)]
+    provider: Vec<ProviderOverride>,
+}
+
+/// Returns the path of the overrides file used by the context, if any.
+async fn overrides_path(context: &Context) -> Option<PathBuf> {
+    if let Some(path) = context
+        .sql
+        .get_raw_config(context, OVERRIDES_PATH_KEY)
+        .await
+        .filter(|path| !path.is_empty())
+    {
+        return Some(path.into());
+    }
+
+    // Accounts keep their database in a subdirectory of the accounts directory.
+    let accounts_dir = context.get_dbfile().parent()?.parent()?;
+    if accounts_dir.join(CONFIG_NAME).exists().await {
+        Some(accounts_dir.join(OVERRIDES_NAME))
+    } else {
+        None
+    }
+}
+
+async fn load_overrides(path: &Path) -> Result<Vec<ProviderOverride>> {
+    let toml = async_std::fs::read_to_string(path)
+        .await
+        .with_context(|| format!("failed to read {}", path.display()))?;
+    let file: OverridesFile =
+        toml::from_str(&toml).with_context(|| format!("failed to parse {}", path.display()))?;
+    Ok(file.provider)
+}
+
+/// Returns the override entry for the given domain.
+///
+/// A missing overrides file is not an error;
+/// an unreadable or malformed file is ignored with a warning.
+///
+/// For compatibility with [super::get_provider_info],
+/// an email address can be passed instead of the domain.
+pub async fn get_provider_override(context: &Context, domain: &str) -> Option<ProviderOverride> {
+    let domain = domain.rsplitn(2, '@').next()?.to_lowercase();
+    let path = overrides_path(context).await?;
+    if !path.exists().await {
+        return None;
+    }
+
+    match load_overrides(&path).await {
+        Ok(overrides) => overrides
+            .into_iter()
+            .find(|provider| provider.domains.iter().any(|d| d.to_lowercase() == domain)),
+        Err(err) => {
+            warn!(context, "Ignoring provider overrides: {:#}", err);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use async_std::channel;
+
+    use crate::events::{Event, EventType};
+    use crate::test_utils::TestContext;
+
+    async fn set_overrides(t: &TestContext, toml: &str) {
+        let path = t.get_blobdir().join(OVERRIDES_NAME);
+        async_std::fs::write(&path, toml).await.unwrap();
+        t.sql
+            .set_raw_config(&t, OVERRIDES_PATH_KEY, path.to_str())
+            .await
+            .unwrap();
+    }
+
+    #[async_std::test]
+    async fn test_get_provider_override() {
+        let t = TestContext::new().await;
+        set_overrides(
+            &t,
+            r#"
+[[provider]]
+domains = ["example.net"]
+
+[[provider]]
+domains = ["Example.org"]
+after_login_hint = "Welcome!"
+
+[[provider.server]]
+protocol = "imap"
+socket = "ssl"
+hostname = "imap.example.com"
+port = 993
+
+[[provider.server]]
+protocol = "smtp"
+socket = "starttls"
+hostname = "smtp.example.com"
+username_pattern = "emaillocalpart"
+"#,
+        )
+        .await;
+
+        let provider = get_provider_override(&t, "alice@example.ORG")
+            .await
+            .unwrap();
+        assert_eq!(provider.after_login_hint, "Welcome!");
+        assert_eq!(
+            provider.servers,
+            vec![
+                ServerOverride {
+                    protocol: Protocol::IMAP,
+                    socket: Socket::SSL,
+                    hostname: "imap.example.com".to_string(),
+                    port: 993,
+                    username_pattern: UsernamePattern::EMAIL,
+                },
+                ServerOverride {
+                    protocol: Protocol::SMTP,
+                    socket: Socket::STARTTLS,
+                    hostname: "smtp.example.com".to_string(),
+                    port: 0,
+                    username_pattern: UsernamePattern::EMAILLOCALPART,
+                }
+            ]
+        );
+        assert!(get_provider_override(&t, "example.com").await.is_none());
+    }
+
+    #[async_std::test]
+    async fn test_get_provider_override_malformed() {
+        let t = TestContext::new().await;
+        let (warning_tx, warning_rx) = channel::unbounded();
+        t.add_event_sink(move |event: Event| {
+            let warning_tx = warning_tx.clone();
+            async move {
+                if let EventType::Warning(msg) = event.typ {
+                    warning_tx.try_send(msg).unwrap();
+                }
+            }
+        })
+        .await;
+
+        set_overrides(&t, "[[provider]]\ndomains = \"example.org\"\n").await;
+        assert!(get_provider_override(&t, "example.org").await.is_none());
+        while !warning_rx
+            .recv()
+            .await
+            .unwrap()
+            .contains("Ignoring provider overrides")
+        {}
+    }
+
+    #[async_std::test]
+    async fn test_get_provider_override_absent() {
+        let t = TestContext::new().await;
+        assert!(get_provider_override(&t, "example.org").await.is_none());
+
+        t.sql
+            .set_raw_config(&t, OVERRIDES_PATH_KEY, Some("/nonexistent/overrides.toml"))
+            .await
+            .unwrap();
+        assert!(get_provider_override(&t, "example.org").await.is_none());
+    }
+}
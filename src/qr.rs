@@ -1,24 +1,27 @@
 //! # QR code module
 
-use anyhow::{bail, ensure, format_err, Error};
+use anyhow::{bail, Result};
 use once_cell::sync::Lazy;
-use percent_encoding::percent_decode_str;
+use percent_encoding::{percent_decode_str, utf8_percent_encode, NON_ALPHANUMERIC};
 use serde::Deserialize;
 use std::collections::BTreeMap;
 
 use crate::chat;
 use crate::config::Config;
 use crate::constants::Blocked;
-use crate::contact::{addr_normalize, may_be_valid_addr, Contact, Origin};
+use crate::contact::{may_be_valid_addr, Contact, Origin};
 use crate::context::Context;
-use crate::key::Fingerprint;
+use crate::dc_tools::normalize_addr;
+use crate::key::{Fingerprint, FingerprintError};
 use crate::lot::{Lot, LotState};
 use crate::message::Message;
 use crate::peerstate::Peerstate;
+use crate::securejoin::NON_ALPHANUMERIC_WITHOUT_DOT;
 
 const OPENPGP4FPR_SCHEME: &str = "OPENPGP4FPR:"; // yes: uppercase
 const DCACCOUNT_SCHEME: &str = "DCACCOUNT:";
 const DCWEBRTC_SCHEME: &str = "DCWEBRTC:";
+const DCBACKUP_SCHEME: &str = "DCBACKUP:";
 const MAILTO_SCHEME: &str = "mailto:";
 const MATMSG_SCHEME: &str = "MATMSG:";
 const VCARD_SCHEME: &str = "BEGIN:VCARD";
@@ -26,8 +29,96 @@
 const HTTP_SCHEME: &str = "http://";
 const HTTPS_SCHEME: &str = "https://";
 
+/// A scanned QR code, see [parse].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Qr {
+    /// Invitation to verify a contact, see [crate::securejoin::dc_join_securejoin].
+    AskVerifyContact {
+        contact_id: u32,
+        fingerprint: Fingerprint,
+        invitenumber: String,
+        authcode: String,
+    },
+
+    /// Invitation to join a verified group, see [crate::securejoin::dc_join_securejoin].
+    AskVerifyGroup {
+        grpname: String,
+        grpid: String,
+        contact_id: u32,
+        fingerprint: Fingerprint,
+        invitenumber: String,
+        authcode: String,
+    },
+
+    /// The fingerprint matches the key of the contact, which is marked as verified.
+    FprOk {
+        contact_id: u32,
+    },
+
+    /// The fingerprint does not match the key of the contact.
+    ///
+    /// `contact_id` is `None` if there is no contact with the address.
+    FprMismatch {
+        contact_id: Option<u32>,
+    },
+
+    /// Fingerprint without address, there is no peerstate with the fingerprint.
+    FprWithoutAddr {
+        /// The fingerprint formatted for display.
+        fingerprint: String,
+    },
+
+    /// Account creation link, see [set_config_from_qr].
+    Account {
+        domain: String,
+    },
+
+    /// Video chat instance, see [set_config_from_qr].
+    WebrtcInstance {
+        domain: String,
+        instance_pattern: String,
+    },
+
+    /// Backup offered for download from another device, to be imported with
+    /// [crate::imex::imex] after downloading it from `url`.
+    Backup {
+        domain: String,
+        url: String,
+    },
+
+    /// E-mail address from `mailto:`, `SMTP:`, `MATMSG:` or vCard QR codes.
+    Addr {
+        contact_id: u32,
+    },
+
+    Url {
+        url: String,
+    },
+
+    Text {
+        text: String,
+    },
+}
+
+/// Reasons for a QR code to be malformed.
+#[derive(Debug, thiserror::Error)]
+pub enum ParseError {
+    #[error("Failed to parse fingerprint in QR code")]
+    InvalidFingerprint(#[from] FingerprintError),
+    #[error("Bad e-mail address")]
+    InvalidAddress,
+    #[error("Missing address")]
+    MissingAddress,
+    #[error("Invalid name")]
+    InvalidName,
+    #[error("Invalid URL: {0}")]
+    InvalidUrl(String),
+    #[error("Bad URL scheme: {0}")]
+    UnsupportedUrlScheme(String),
+}
+
 // Make it easy to convert errors into the final `Lot`.
-impl Into<Lot> for Error {
+impl Into<Lot> for anyhow::Error {
     fn into(self) -> Lot {
         let mut l = Lot::new();
         l.state = LotState::QrError;
@@ -37,6 +128,71 @@ fn into(self) -> Lot {
     }
 }
 
+impl From<Qr> for Lot {
+    fn from(qr: Qr) -> Self {
+        let mut l = Lot::new();
+        match qr {
+            Qr::AskVerifyContact { contact_id, .. } => {
+                l.state = LotState::QrAskVerifyContact;
+                l.id = contact_id;
+            }
+            Qr::AskVerifyGroup {
+                grpname,
+                grpid,
+                contact_id,
+                ..
+            } => {
+                l.state = LotState::QrAskVerifyGroup;
+                l.id = contact_id;
+                l.text1 = Some(grpname);
+                l.text2 = Some(grpid);
+            }
+            Qr::FprOk { contact_id } => {
+                l.state = LotState::QrFprOk;
+                l.id = contact_id;
+            }
+            Qr::FprMismatch { contact_id } => {
+                l.state = LotState::QrFprMismatch;
+                l.id = contact_id.unwrap_or_default();
+            }
+            Qr::FprWithoutAddr { fingerprint } => {
+                l.state = LotState::QrFprWithoutAddr;
+                l.text1 = Some(fingerprint);
+            }
+            Qr::Account { domain } => {
+                l.state = LotState::QrAccount;
+                l.text1 = Some(domain);
+            }
+            Qr::WebrtcInstance {
+                domain,
+                instance_pattern,
+            } => {
+                l.state = LotState::QrWebrtcInstance;
+                l.text1 = Some(domain);
+                l.text2 = Some(instance_pattern);
+            }
+            Qr::Backup { domain, url } => {
+                l.state = LotState::QrBackup;
+                l.text1 = Some(domain);
+                l.text2 = Some(url);
+            }
+            Qr::Addr { contact_id } => {
+                l.state = LotState::QrAddr;
+                l.id = contact_id;
+            }
+            Qr::Url { url } => {
+                l.state = LotState::QrUrl;
+                l.text1 = Some(url);
+            }
+            Qr::Text { text } => {
+                l.state = LotState::QrText;
+                l.text1 = Some(text);
+            }
+        }
+        l
+    }
+}
+
 fn starts_with_ignore_case(string: &str, pattern: &str) -> bool {
     string.to_lowercase().starts_with(&pattern.to_lowercase())
 }
@@ -44,17 +200,30 @@ fn starts_with_ignore_case(string: &str, pattern: &str) -> bool {
 /// Check a scanned QR code.
 /// The function should be called after a QR code is scanned.
 /// The function takes the raw text scanned and checks what can be done with it.
+///
+/// This is the [Lot] based variant of [parse], errors are returned as [LotState::QrError].
 pub async fn check_qr(context: &Context, qr: impl AsRef<str>) -> Lot {
-    let qr = qr.as_ref();
+    match parse(context, qr.as_ref()).await {
+        Ok(qr) => qr.into(),
+        Err(err) => err.into(),
+    }
+}
 
+/// Parses a scanned QR code.
+///
+/// Contacts for the addresses in the QR code are created.  Malformed QR codes of the
+/// supported kinds result in a [ParseError], anything else is returned as [Qr::Text].
+pub async fn parse(context: &Context, qr: &str) -> Result<Qr> {
     info!(context, "Scanned QR code: {}", qr);
 
     if starts_with_ignore_case(qr, OPENPGP4FPR_SCHEME) {
         decode_openpgp(context, qr).await
     } else if starts_with_ignore_case(qr, DCACCOUNT_SCHEME) {
-        decode_account(context, qr)
+        Ok(decode_account(qr)?)
     } else if starts_with_ignore_case(qr, DCWEBRTC_SCHEME) {
-        decode_webrtc_instance(context, qr)
+        Ok(decode_webrtc_instance(qr)?)
+    } else if starts_with_ignore_case(qr, DCBACKUP_SCHEME) {
+        Ok(decode_backup(qr)?)
     } else if qr.starts_with(MAILTO_SCHEME) {
         decode_mailto(context, qr).await
     } else if qr.starts_with(SMTP_SCHEME) {
@@ -64,17 +233,89 @@ pub async fn check_qr(context: &Context, qr: impl AsRef<str>) -> Lot {
     } else if qr.starts_with(VCARD_SCHEME) {
         decode_vcard(context, qr).await
     } else if qr.starts_with(HTTP_SCHEME) || qr.starts_with(HTTPS_SCHEME) {
-        Lot::from_url(qr)
+        Ok(Qr::Url {
+            url: qr.to_string(),
+        })
     } else {
-        Lot::from_text(qr)
+        Ok(Qr::Text {
+            text: qr.to_string(),
+        })
+    }
+}
+
+/// Returns a QR code inviting to verify the contact `addr`.
+pub fn format_verify_contact(
+    fingerprint: &Fingerprint,
+    addr: &str,
+    name: &str,
+    invitenumber: &str,
+    authcode: &str,
+) -> String {
+    format!(
+        "{}{}#a={}&n={}&i={}&s={}",
+        OPENPGP4FPR_SCHEME,
+        fingerprint.hex(),
+        utf8_percent_encode(addr, NON_ALPHANUMERIC_WITHOUT_DOT),
+        utf8_percent_encode(name, NON_ALPHANUMERIC_WITHOUT_DOT),
+        invitenumber,
+        authcode,
+    )
+}
+
+/// Returns a QR code inviting to join the verified group `grpid`.
+pub fn format_verify_group(
+    fingerprint: &Fingerprint,
+    addr: &str,
+    grpname: &str,
+    grpid: &str,
+    invitenumber: &str,
+    authcode: &str,
+) -> String {
+    format!(
+        "{}{}#a={}&g={}&x={}&i={}&s={}",
+        OPENPGP4FPR_SCHEME,
+        fingerprint.hex(),
+        utf8_percent_encode(addr, NON_ALPHANUMERIC_WITHOUT_DOT),
+        utf8_percent_encode(grpname, NON_ALPHANUMERIC),
+        grpid,
+        invitenumber,
+        authcode,
+    )
+}
+
+/// Returns a QR code to compare the fingerprint of a key, optionally with its address.
+pub fn format_fingerprint(fingerprint: &Fingerprint, addr: Option<&str>) -> String {
+    match addr {
+        Some(addr) => format!(
+            "{}{}#a={}",
+            OPENPGP4FPR_SCHEME,
+            fingerprint.hex(),
+            utf8_percent_encode(addr, NON_ALPHANUMERIC_WITHOUT_DOT)
+        ),
+        None => format!("{}{}", OPENPGP4FPR_SCHEME, fingerprint.hex()),
     }
 }
 
+/// Returns a QR code to create an account using the HTTP(S) `url`.
+pub fn format_account(url: &str) -> String {
+    format!("{}{}", DCACCOUNT_SCHEME, url)
+}
+
+/// Returns a QR code to set the video chat instance, see [Config::WebrtcInstance].
+pub fn format_webrtc_instance(instance: &str) -> String {
+    format!("{}{}", DCWEBRTC_SCHEME, instance)
+}
+
+/// Returns a QR code offering the backup available at the HTTP(S) `url`.
+pub fn format_backup(url: &str) -> String {
+    format!("{}{}", DCBACKUP_SCHEME, url)
+}
+
 /// scheme: `OPENPGP4FPR:FINGERPRINT#a=ADDR&n=NAME&i=INVITENUMBER&s=AUTH`
 ///     or: `OPENPGP4FPR:FINGERPRINT#a=ADDR&g=GROUPNAME&x=GROUPID&i=INVITENUMBER&s=AUTH`
 ///     or: `OPENPGP4FPR:FINGERPRINT#a=ADDR`
 #[allow(clippy::indexing_slicing)]
-async fn decode_openpgp(context: &Context, qr: &str) -> Lot {
+async fn decode_openpgp(context: &Context, qr: &str) -> Result<Qr> {
     let payload = &qr[OPENPGP4FPR_SCHEME.len()..];
 
     let (fingerprint, fragment) = match payload.find('#').map(|offset| {
@@ -85,14 +326,7 @@ async fn decode_openpgp(context: &Context, qr: &str) -> Lot {
         Some(pair) => pair,
         None => (payload, ""),
     };
-    let fingerprint: Fingerprint = match fingerprint.parse() {
-        Ok(fp) => fp,
-        Err(err) => {
-            return Error::new(err)
-                .context("Failed to parse fingerprint in QR code")
-                .into()
-        }
-    };
+    let fingerprint: Fingerprint = fingerprint.parse().map_err(ParseError::from)?;
 
     let param: BTreeMap<&str, &str> = fragment
         .split('&')
@@ -105,152 +339,127 @@ async fn decode_openpgp(context: &Context, qr: &str) -> Lot {
         })
         .collect();
 
-    let addr = if let Some(addr) = param.get("a") {
-        match normalize_address(addr) {
-            Ok(addr) => Some(addr),
-            Err(err) => return err.into(),
-        }
-    } else {
-        None
+    let addr = match param.get("a") {
+        Some(addr) => Some(normalize_address(addr)?),
+        None => None,
     };
 
     // what is up with that param name?
-    let name = if let Some(encoded_name) = param.get("n") {
-        let encoded_name = encoded_name.replace("+", "%20"); // sometimes spaces are encoded as `+`
-        match percent_decode_str(&encoded_name).decode_utf8() {
-            Ok(name) => name.to_string(),
-            Err(err) => return format_err!("Invalid name: {}", err).into(),
-        }
-    } else {
-        "".to_string()
+    let name = match param.get("n") {
+        Some(encoded_name) => decode_name(encoded_name)?,
+        None => "".to_string(),
     };
 
     let invitenumber = param.get("i").map(|s| s.to_string());
     let auth = param.get("s").map(|s| s.to_string());
     let grpid = param.get("x").map(|s| s.to_string());
 
-    let grpname = if grpid.is_some() {
-        if let Some(encoded_name) = param.get("g") {
-            let encoded_name = encoded_name.replace("+", "%20"); // sometimes spaces are encoded as `+`
-            match percent_decode_str(&encoded_name).decode_utf8() {
-                Ok(name) => Some(name.to_string()),
-                Err(err) => return format_err!("Invalid group name: {}", err).into(),
-            }
-        } else {
-            None
-        }
-    } else {
-        None
+    let grpname = match (&grpid, param.get("g")) {
+        (Some(_), Some(encoded_name)) => Some(decode_name(encoded_name)?),
+        _ => None,
     };
 
-    let mut lot = Lot::new();
-
     // retrieve known state for this fingerprint
-    let peerstate = match Peerstate::from_fingerprint(context, &context.sql, &fingerprint).await {
-        Ok(peerstate) => peerstate,
-        Err(err) => return format_err!("Can't load peerstate: {}", err).into(),
-    };
-
-    if invitenumber.is_none() || auth.is_none() {
-        if let Some(peerstate) = peerstate {
-            lot.state = LotState::QrFprOk;
+    let peerstate = Peerstate::from_fingerprint(context, &context.sql, &fingerprint)
+        .await
+        .map_err(|err| anyhow::format_err!("Can't load peerstate: {}", err))?;
+
+    if let (Some(invitenumber), Some(authcode)) = (invitenumber, auth) {
+        let addr = addr.ok_or(ParseError::MissingAddress)?;
+        let (contact_id, _) =
+            Contact::add_or_lookup(context, &name, &addr, Origin::UnhandledQrScan).await?;
+        match (grpid, grpname) {
+            (Some(grpid), Some(grpname)) => Ok(Qr::AskVerifyGroup {
+                grpname,
+                grpid,
+                contact_id,
+                fingerprint,
+                invitenumber,
+                authcode,
+            }),
+            _ => Ok(Qr::AskVerifyContact {
+                contact_id,
+                fingerprint,
+                invitenumber,
+                authcode,
+            }),
+        }
+    } else if let Some(peerstate) = peerstate {
+        let contact_id = Contact::add_or_lookup(
+            context,
+            name,
+            peerstate.addr.clone(),
+            Origin::UnhandledQrScan,
+        )
+        .await
+        .map(|(id, _)| id)
+        .unwrap_or_default();
 
-            lot.id = Contact::add_or_lookup(
-                context,
-                name,
-                peerstate.addr.clone(),
-                Origin::UnhandledQrScan,
-            )
+        let (id, _) = chat::create_or_lookup_by_contact_id(context, contact_id, Blocked::Deaddrop)
             .await
-            .map(|(id, _)| id)
             .unwrap_or_default();
 
-            let (id, _) = chat::create_or_lookup_by_contact_id(context, lot.id, Blocked::Deaddrop)
-                .await
-                .unwrap_or_default();
-
-            chat::add_info_msg(context, id, format!("{} verified.", peerstate.addr)).await;
-        } else if let Some(addr) = addr {
-            lot.state = LotState::QrFprMismatch;
-            lot.id = match Contact::lookup_id_by_addr(context, &addr, Origin::Unknown).await {
-                Ok(contact_id) => contact_id.unwrap_or_default(),
-                Err(err) => {
-                    return format_err!("Error looking up contact {:?}: {}", addr, err).into()
-                }
-            };
-        } else {
-            lot.state = LotState::QrFprWithoutAddr;
-            lot.text1 = Some(fingerprint.to_string());
-        }
+        chat::add_info_msg(context, id, format!("{} verified.", peerstate.addr)).await;
+        Ok(Qr::FprOk { contact_id })
     } else if let Some(addr) = addr {
-        if grpid.is_some() && grpname.is_some() {
-            lot.state = LotState::QrAskVerifyGroup;
-            lot.text1 = grpname;
-            lot.text2 = grpid
-        } else {
-            lot.state = LotState::QrAskVerifyContact;
-        }
-        lot.id = Contact::add_or_lookup(context, &name, &addr, Origin::UnhandledQrScan)
+        let contact_id = Contact::lookup_id_by_addr(context, &addr, Origin::Unknown)
             .await
-            .map(|(id, _)| id)
-            .unwrap_or_default();
-
-        lot.fingerprint = Some(fingerprint);
-        lot.invitenumber = invitenumber;
-        lot.auth = auth;
+            .map_err(|err| anyhow::format_err!("Error looking up contact {:?}: {}", addr, err))?;
+        Ok(Qr::FprMismatch { contact_id })
     } else {
-        return format_err!("Missing address").into();
+        Ok(Qr::FprWithoutAddr {
+            fingerprint: fingerprint.to_string(),
+        })
+    }
+}
+
+/// URL decodes a name, spaces may be encoded as `+`.
+fn decode_name(encoded_name: &str) -> Result<String, ParseError> {
+    let encoded_name = encoded_name.replace("+", "%20"); // sometimes spaces are encoded as `+`
+    match percent_decode_str(&encoded_name).decode_utf8() {
+        Ok(name) => Ok(name.to_string()),
+        Err(_) => Err(ParseError::InvalidName),
     }
+}
 
-    lot
+/// Returns the host of an HTTP(S) URL.
+fn parse_http_url(url: &str) -> Result<String, ParseError> {
+    let parsed = url::Url::parse(url).map_err(|_| ParseError::InvalidUrl(url.to_string()))?;
+    if parsed.scheme() != "http" && parsed.scheme() != "https" {
+        return Err(ParseError::UnsupportedUrlScheme(url.to_string()));
+    }
+    Ok(parsed.host_str().unwrap_or_default().to_string())
 }
 
 /// scheme: `DCACCOUNT:https://example.org/new_email?t=1w_7wDjgjelxeX884x96v3`
 #[allow(clippy::indexing_slicing)]
-fn decode_account(_context: &Context, qr: &str) -> Lot {
+fn decode_account(qr: &str) -> Result<Qr, ParseError> {
     let payload = &qr[DCACCOUNT_SCHEME.len()..];
-
-    let mut lot = Lot::new();
-
-    if let Ok(url) = url::Url::parse(payload) {
-        if url.scheme() == "http" || url.scheme() == "https" {
-            lot.state = LotState::QrAccount;
-            lot.text1 = url.host_str().map(|x| x.to_string());
-        } else {
-            lot.state = LotState::QrError;
-            lot.text1 = Some(format!("Bad scheme for account url: {}", payload));
-        }
-    } else {
-        lot.state = LotState::QrError;
-        lot.text1 = Some(format!("Invalid account url: {}", payload));
-    }
-
-    lot
+    Ok(Qr::Account {
+        domain: parse_http_url(payload)?,
+    })
 }
 
 /// scheme: `DCWEBRTC:https://meet.jit.si/$ROOM`
 #[allow(clippy::indexing_slicing)]
-fn decode_webrtc_instance(_context: &Context, qr: &str) -> Lot {
+fn decode_webrtc_instance(qr: &str) -> Result<Qr, ParseError> {
     let payload = &qr[DCWEBRTC_SCHEME.len()..];
 
-    let mut lot = Lot::new();
-
     let (_type, url) = Message::parse_webrtc_instance(payload);
-    if let Ok(url) = url::Url::parse(&url) {
-        if url.scheme() == "http" || url.scheme() == "https" {
-            lot.state = LotState::QrWebrtcInstance;
-            lot.text1 = url.host_str().map(|x| x.to_string());
-            lot.text2 = Some(payload.to_string())
-        } else {
-            lot.state = LotState::QrError;
-            lot.text1 = Some(format!("Bad scheme for webrtc instance: {}", payload));
-        }
-    } else {
-        lot.state = LotState::QrError;
-        lot.text1 = Some(format!("Invalid webrtc instance: {}", payload));
-    }
+    Ok(Qr::WebrtcInstance {
+        domain: parse_http_url(&url)?,
+        instance_pattern: payload.to_string(),
+    })
+}
 
-    lot
+/// scheme: `DCBACKUP:https://192.168.1.2:8080/backup.tar?t=rTy8s4Dz2qDp`
+#[allow(clippy::indexing_slicing)]
+fn decode_backup(qr: &str) -> Result<Qr, ParseError> {
+    let payload = &qr[DCBACKUP_SCHEME.len()..];
+    Ok(Qr::Backup {
+        domain: parse_http_url(payload)?,
+        url: payload.to_string(),
+    })
 }
 
 #[derive(Debug, Deserialize)]
@@ -263,7 +472,7 @@ struct CreateAccountResponse {
 /// download additional information from the contained url and set the parameters.
 /// on success, a configure::configure() should be able to log in to the account
 #[allow(clippy::indexing_slicing)]
-async fn set_account_from_qr(context: &Context, qr: &str) -> Result<(), Error> {
+async fn set_account_from_qr(context: &Context, qr: &str) -> Result<()> {
     let url_str = &qr[DCACCOUNT_SCHEME.len()..];
 
     let response: Result<CreateAccountResponse, surf::Error> =
@@ -283,13 +492,14 @@ async fn set_account_from_qr(context: &Context, qr: &str) -> Result<(), Error> {
     Ok(())
 }
 
-pub async fn set_config_from_qr(context: &Context, qr: &str) -> Result<(), Error> {
-    match check_qr(context, &qr).await.state {
-        LotState::QrAccount => set_account_from_qr(context, qr).await,
-        LotState::QrWebrtcInstance => {
-            let val = decode_webrtc_instance(context, qr).text2;
+pub async fn set_config_from_qr(context: &Context, qr: &str) -> Result<()> {
+    match parse(context, &qr).await {
+        Ok(Qr::Account { .. }) => set_account_from_qr(context, qr).await,
+        Ok(Qr::WebrtcInstance {
+            instance_pattern, ..
+        }) => {
             context
-                .set_config(Config::WebrtcInstance, val.as_deref())
+                .set_config(Config::WebrtcInstance, Some(&instance_pattern))
                 .await?;
             Ok(())
         }
@@ -301,7 +511,7 @@ pub async fn set_config_from_qr(context: &Context, qr: &str) -> Result<(), Error
 ///
 /// Scheme: `mailto:addr...?subject=...&body=..`
 #[allow(clippy::indexing_slicing)]
-async fn decode_mailto(context: &Context, qr: &str) -> Lot {
+async fn decode_mailto(context: &Context, qr: &str) -> Result<Qr> {
     let payload = &qr[MAILTO_SCHEME.len()..];
 
     let addr = if let Some(query_index) = payload.find('?') {
@@ -310,34 +520,27 @@ async fn decode_mailto(context: &Context, qr: &str) -> Lot {
         payload
     };
 
-    let addr = match normalize_address(addr) {
-        Ok(addr) => addr,
-        Err(err) => return err.into(),
-    };
-
+    let addr = normalize_address(addr)?;
     let name = "".to_string();
-    Lot::from_address(context, name, addr).await
+    decode_address(context, name, addr).await
 }
 
 /// Extract address for the smtp scheme.
 ///
 /// Scheme: `SMTP:addr...:subject...:body...`
 #[allow(clippy::indexing_slicing)]
-async fn decode_smtp(context: &Context, qr: &str) -> Lot {
+async fn decode_smtp(context: &Context, qr: &str) -> Result<Qr> {
     let payload = &qr[SMTP_SCHEME.len()..];
 
     let addr = if let Some(query_index) = payload.find(':') {
         &payload[..query_index]
     } else {
-        return format_err!("Invalid SMTP found").into();
+        return Err(ParseError::MissingAddress.into());
     };
 
-    let addr = match normalize_address(addr) {
-        Ok(addr) => addr,
-        Err(err) => return err.into(),
-    };
+    let addr = normalize_address(addr)?;
     let name = "".to_string();
-    Lot::from_address(context, name, addr).await
+    decode_address(context, name, addr).await
 }
 
 /// Extract address for the matmsg scheme.
@@ -346,7 +549,7 @@ async fn decode_smtp(context: &Context, qr: &str) -> Lot {
 ///
 /// There may or may not be linebreaks after the fields.
 #[allow(clippy::indexing_slicing)]
-async fn decode_matmsg(context: &Context, qr: &str) -> Lot {
+async fn decode_matmsg(context: &Context, qr: &str) -> Result<Qr> {
     // Does not work when the text `TO:` is used in subject/body _and_ TO: is not the first field.
     // we ignore this case.
     let addr = if let Some(to_index) = qr.find("TO:") {
@@ -357,16 +560,12 @@ async fn decode_matmsg(context: &Context, qr: &str) -> Lot {
             addr
         }
     } else {
-        return format_err!("Invalid MATMSG found").into();
-    };
-
-    let addr = match normalize_address(addr) {
-        Ok(addr) => addr,
-        Err(err) => return err.into(),
+        return Err(ParseError::MissingAddress.into());
     };
 
+    let addr = normalize_address(addr)?;
     let name = "".to_string();
-    Lot::from_address(context, name, addr).await
+    decode_address(context, name, addr).await
 }
 
 static VCARD_NAME_RE: Lazy<regex::Regex> =
@@ -378,7 +577,7 @@ async fn decode_matmsg(context: &Context, qr: &str) -> Lot {
 ///
 /// Scheme: `VCARD:BEGIN\nN:last name;first name;...;\nEMAIL;<type>:addr...;`
 #[allow(clippy::indexing_slicing)]
-async fn decode_vcard(context: &Context, qr: &str) -> Lot {
+async fn decode_vcard(context: &Context, qr: &str) -> Result<Qr> {
     let name = VCARD_NAME_RE
         .captures(qr)
         .and_then(|caps| {
@@ -390,55 +589,33 @@ async fn decode_vcard(context: &Context, qr: &str) -> Lot {
         .unwrap_or_default();
 
     let addr = if let Some(caps) = VCARD_EMAIL_RE.captures(qr) {
-        match normalize_address(caps[2].trim()) {
-            Ok(addr) => addr,
-            Err(err) => return err.into(),
-        }
+        normalize_address(caps[2].trim())?
     } else {
-        return format_err!("Bad e-mail address").into();
+        return Err(ParseError::MissingAddress.into());
     };
 
-    Lot::from_address(context, name, addr).await
+    decode_address(context, name, addr).await
 }
 
-impl Lot {
-    pub fn from_text(text: impl AsRef<str>) -> Self {
-        let mut l = Lot::new();
-        l.state = LotState::QrText;
-        l.text1 = Some(text.as_ref().to_string());
-
-        l
-    }
-
-    pub fn from_url(url: impl AsRef<str>) -> Self {
-        let mut l = Lot::new();
-        l.state = LotState::QrUrl;
-        l.text1 = Some(url.as_ref().to_string());
-
-        l
-    }
-
-    pub async fn from_address(context: &Context, name: String, addr: String) -> Self {
-        let mut l = Lot::new();
-        l.state = LotState::QrAddr;
-        l.id = match Contact::add_or_lookup(context, name, addr, Origin::UnhandledQrScan).await {
-            Ok((id, _)) => id,
-            Err(err) => return err.into(),
-        };
-
-        l
-    }
+async fn decode_address(context: &Context, name: String, addr: String) -> Result<Qr> {
+    let (contact_id, _) =
+        Contact::add_or_lookup(context, name, addr, Origin::UnhandledQrScan).await?;
+    Ok(Qr::Addr { contact_id })
 }
 
 /// URL decodes a given address, does basic email validation on the result.
-fn normalize_address(addr: &str) -> Result<String, Error> {
+fn normalize_address(addr: &str) -> Result<String, ParseError> {
     // urldecoding is needed at least for OPENPGP4FPR but should not hurt in the other cases
-    let new_addr = percent_decode_str(addr).decode_utf8()?;
-    let new_addr = addr_normalize(&new_addr);
+    let new_addr = percent_decode_str(addr)
+        .decode_utf8()
+        .map_err(|_| ParseError::InvalidAddress)?;
+    let new_addr = normalize_addr(&new_addr);
 
-    ensure!(may_be_valid_addr(new_addr), "Bad e-mail address");
+    if !may_be_valid_addr(&new_addr) {
+        return Err(ParseError::InvalidAddress);
+    }
 
-    Ok(new_addr.to_string())
+    Ok(new_addr)
 }
 
 #[cfg(test)]
@@ -766,6 +943,27 @@ async fn test_decode_webrtc_instance() {
         assert_eq!(res.get_text2().unwrap(), "https://example.org/");
     }
 
+    #[async_std::test]
+    async fn test_decode_backup() {
+        let ctx = TestContext::new().await;
+
+        let res = check_qr(
+            &ctx.ctx,
+            "DCBACKUP:https://192.168.1.2:8080/backup.tar?t=rTy8s4Dz2qDp",
+        )
+        .await;
+        assert_eq!(res.get_state(), LotState::QrBackup);
+        assert_eq!(res.get_text1().unwrap(), "192.168.1.2");
+        assert_eq!(
+            res.get_text2().unwrap(),
+            "https://192.168.1.2:8080/backup.tar?t=rTy8s4Dz2qDp"
+        );
+
+        let res = check_qr(&ctx.ctx, "dcbackup:http://example.org/backup.tar").await;
+        assert_eq!(res.get_state(), LotState::QrBackup);
+        assert_eq!(res.get_text1().unwrap(), "example.org");
+    }
+
     #[async_std::test]
     async fn test_decode_account_bad_scheme() {
         let ctx = TestContext::new().await;
@@ -816,4 +1014,245 @@ async fn test_set_config_from_qr() {
             "basicwebrtc:https://foo.bar/?$ROOM&test"
         );
     }
+
+    #[async_std::test]
+    async fn test_parse_variants() -> Result<()> {
+        let t = TestContext::new_alice().await;
+
+        let qr = parse(
+            &t,
+            "OPENPGP4FPR:79252762C34C5096AF57958F4FC3D21A81B0F0A7#a=cli%40deltachat.de&n=J%C3%B6rn%20P.+P.&i=TbnwJ6lSvD5&s=0ejvbdFSQxB",
+        )
+        .await?;
+        let cli = Contact::lookup_id_by_addr(&t, "cli@deltachat.de", Origin::Unknown)
+            .await?
+            .unwrap();
+        let fingerprint: Fingerprint = "79252762C34C5096AF57958F4FC3D21A81B0F0A7".parse()?;
+        assert_eq!(
+            qr,
+            Qr::AskVerifyContact {
+                contact_id: cli,
+                fingerprint: fingerprint.clone(),
+                invitenumber: "TbnwJ6lSvD5".to_string(),
+                authcode: "0ejvbdFSQxB".to_string(),
+            }
+        );
+
+        let qr = parse(
+            &t,
+            "OPENPGP4FPR:79252762C34C5096AF57958F4FC3D21A81B0F0A7#a=cli%40deltachat.de&g=test%20%3F+test%20%21&x=h-0oKQf2CDK&i=9JEXlxAqGM0&s=0V7LzL9cxRL",
+        )
+        .await?;
+        assert_eq!(
+            qr,
+            Qr::AskVerifyGroup {
+                grpname: "test ? test !".to_string(),
+                grpid: "h-0oKQf2CDK".to_string(),
+                contact_id: cli,
+                fingerprint,
+                invitenumber: "9JEXlxAqGM0".to_string(),
+                authcode: "0V7LzL9cxRL".to_string(),
+            }
+        );
+
+        let qr = parse(
+            &t,
+            "OPENPGP4FPR:1234567890123456789012345678901234567890#a=cli%40deltachat.de",
+        )
+        .await?;
+        assert_eq!(
+            qr,
+            Qr::FprMismatch {
+                contact_id: Some(cli)
+            }
+        );
+
+        let qr = parse(&t, "OPENPGP4FPR:1234567890123456789012345678901234567890").await?;
+        assert_eq!(
+            qr,
+            Qr::FprWithoutAddr {
+                fingerprint: "1234 5678 9012 3456 7890\n1234 5678 9012 3456 7890".to_string()
+            }
+        );
+
+        let qr = parse(
+            &t,
+            "DCACCOUNT:https://example.org/new_email?t=1w_7wDjgjelxeX884x96v3",
+        )
+        .await?;
+        assert_eq!(
+            qr,
+            Qr::Account {
+                domain: "example.org".to_string()
+            }
+        );
+
+        let qr = parse(&t, "DCWEBRTC:basicwebrtc:https://basicurl.com/$ROOM").await?;
+        assert_eq!(
+            qr,
+            Qr::WebrtcInstance {
+                domain: "basicurl.com".to_string(),
+                instance_pattern: "basicwebrtc:https://basicurl.com/$ROOM".to_string()
+            }
+        );
+
+        let qr = parse(&t, "mailto:stress@test.local?subject=hello&body=world").await?;
+        let stress = Contact::lookup_id_by_addr(&t, "stress@test.local", Origin::Unknown)
+            .await?
+            .unwrap();
+        assert_eq!(qr, Qr::Addr { contact_id: stress });
+        let qr = parse(&t, "SMTP:stress@test.local:subjecthello:bodyworld").await?;
+        assert_eq!(qr, Qr::Addr { contact_id: stress });
+        let qr = parse(&t, "MATMSG:TO:stress@test.local;SUB:Subject;BODY:hello;;").await?;
+        assert_eq!(qr, Qr::Addr { contact_id: stress });
+        let qr = parse(
+            &t,
+            "BEGIN:VCARD\nVERSION:3.0\nN:Last;First\nEMAIL;TYPE=INTERNET:stress@test.local\nEND:VCARD",
+        )
+        .await?;
+        assert_eq!(qr, Qr::Addr { contact_id: stress });
+
+        let qr = parse(&t, "https://delta.chat/").await?;
+        assert_eq!(
+            qr,
+            Qr::Url {
+                url: "https://delta.chat/".to_string()
+            }
+        );
+        let qr = parse(&t, "just some text").await?;
+        assert_eq!(
+            qr,
+            Qr::Text {
+                text: "just some text".to_string()
+            }
+        );
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn test_parse_malformed() {
+        let t = TestContext::new_alice().await;
+        for (qr, expected) in &[
+            (
+                "OPENPGP4FPR:12345678901234567890",
+                "Failed to parse fingerprint in QR code",
+            ),
+            (
+                "OPENPGP4FPR:79252762C34C5096AF57958F4FC3D21A81B0F0A7#a=no-addr",
+                "Bad e-mail address",
+            ),
+            (
+                "OPENPGP4FPR:79252762C34C5096AF57958F4FC3D21A81B0F0A7#n=Name&i=TbnwJ6lSvD5&s=0ejvbdFSQxB",
+                "Missing address",
+            ),
+            (
+                "OPENPGP4FPR:79252762C34C5096AF57958F4FC3D21A81B0F0A7#a=cli%40deltachat.de&n=%FF&i=TbnwJ6lSvD5&s=0ejvbdFSQxB",
+                "Invalid name",
+            ),
+            ("DCACCOUNT:not a url", "Invalid URL: not a url"),
+            (
+                "DCACCOUNT:ftp://example.org/new_email",
+                "Bad URL scheme: ftp://example.org/new_email",
+            ),
+            ("DCWEBRTC:ftp://example.org/", "Bad URL scheme: ftp://example.org/"),
+            ("DCBACKUP:", "Invalid URL: "),
+            (
+                "DCBACKUP:file:///sdcard/backup.tar",
+                "Bad URL scheme: file:///sdcard/backup.tar",
+            ),
+            ("mailto:no-addr", "Bad e-mail address"),
+            ("SMTP:stress@test.local", "Missing address"),
+            ("MATMSG:SUB:Subject;BODY:hello;;", "Missing address"),
+            ("BEGIN:VCARD\nN:Last;First\nEND:VCARD", "Missing address"),
+        ] {
+            let err = parse(&t, qr).await.unwrap_err();
+            assert!(err.downcast_ref::<ParseError>().is_some(), "{}", qr);
+            assert_eq!(err.to_string(), *expected);
+        }
+    }
+
+    #[async_std::test]
+    async fn test_format_roundtrip() -> Result<()> {
+        let t = TestContext::new_alice().await;
+        let fingerprint = alice_keypair().public.fingerprint();
+
+        let qr = format_verify_contact(&fingerprint, "bob@example.net", "Bob B.", "inv", "auth");
+        let bob = match parse(&t, &qr).await? {
+            Qr::AskVerifyContact {
+                contact_id,
+                fingerprint: parsed,
+                invitenumber,
+                authcode,
+            } => {
+                assert_eq!(parsed, fingerprint);
+                assert_eq!(invitenumber, "inv");
+                assert_eq!(authcode, "auth");
+                contact_id
+            }
+            qr => panic!("unexpected {:?}", qr),
+        };
+        let contact = Contact::get_by_id(&t, bob).await?;
+        assert_eq!(contact.get_addr(), "bob@example.net");
+        assert_eq!(contact.get_name(), "Bob B.");
+
+        let qr = format_verify_group(
+            &fingerprint,
+            "bob@example.net",
+            "Group & friends?",
+            "grp-id",
+            "inv",
+            "auth",
+        );
+        assert_eq!(
+            parse(&t, &qr).await?,
+            Qr::AskVerifyGroup {
+                grpname: "Group & friends?".to_string(),
+                grpid: "grp-id".to_string(),
+                contact_id: bob,
+                fingerprint: fingerprint.clone(),
+                invitenumber: "inv".to_string(),
+                authcode: "auth".to_string(),
+            }
+        );
+
+        let qr = format_fingerprint(&fingerprint, Some("bob@example.net"));
+        assert_eq!(
+            parse(&t, &qr).await?,
+            Qr::FprMismatch {
+                contact_id: Some(bob)
+            }
+        );
+        let qr = format_fingerprint(&fingerprint, None);
+        assert_eq!(
+            parse(&t, &qr).await?,
+            Qr::FprWithoutAddr {
+                fingerprint: fingerprint.to_string()
+            }
+        );
+
+        let qr = format_account("https://example.org/new_email?t=1w_7wDjgjelxeX884x96v3");
+        assert_eq!(
+            parse(&t, &qr).await?,
+            Qr::Account {
+                domain: "example.org".to_string()
+            }
+        );
+        let qr = format_webrtc_instance("basicwebrtc:https://example.org/$ROOM");
+        assert_eq!(
+            parse(&t, &qr).await?,
+            Qr::WebrtcInstance {
+                domain: "example.org".to_string(),
+                instance_pattern: "basicwebrtc:https://example.org/$ROOM".to_string()
+            }
+        );
+        let qr = format_backup("https://example.org/backup.tar?t=rTy8s4Dz2qDp");
+        assert_eq!(
+            parse(&t, &qr).await?,
+            Qr::Backup {
+                domain: "example.org".to_string(),
+                url: "https://example.org/backup.tar?t=rTy8s4Dz2qDp".to_string()
+            }
+        );
+        Ok(())
+    }
 }
@@ -0,0 +1,404 @@
+//! # Mailbox quota
+//!
+//! If the IMAP server supports the QUOTA extension defined in
+//! [RFC 2087](https://tools.ietf.org/html/rfc2087), the inbox loop queries the quota of the
+//! inbox from time to time, see [`update`].  The last reported quota is stored in the raw
+//! config, so it is available after a restart, and returned by [`Context::get_quota_info`].
+//!
+//! When the usage crosses [`WARNING_THRESHOLDS`], a warning is emitted,
+//! for each threshold at most once per day.
+
+use anyhow::{Context as _, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::context::Context;
+use crate::dc_tools::time;
+use crate::imap::ImapSession;
+
+/// Seconds between two queries of the quota.
+pub(crate) const UPDATE_INTERVAL: i64 = 60;
+
+/// Usage in percent at which a warning is emitted, in ascending order.
+pub const WARNING_THRESHOLDS: [u64; 2] = [80, 95];
+
+/// Seconds before the warning for a threshold is repeated.
+const WARNING_INTERVAL: i64 = 24 * 60 * 60;
+
+/// Raw config key storing the [`QuotaInfo`].
+const QUOTA_KEY: &str = "quota";
+
+/// Raw config key storing the last [`Warning`].
+const WARNING_KEY: &str = "quota_warning";
+
+/// Usage and limit of a resource of a quota root.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QuotaResource {
+    /// Name of the quota root the resource belongs to, may be empty.
+    pub root: String,
+
+    /// Name of the resource, e.g. `STORAGE` counting units of 1024 octets
+    /// or `MESSAGE` counting messages.
+    pub name: String,
+
+    pub usage: u64,
+    pub limit: u64,
+}
+
+impl QuotaResource {
+    /// Returns the usage in percent of the limit.
+    pub fn usage_percent(&self) -> u64 {
+        if self.limit == 0 {
+            0
+        } else {
+            self.usage.saturating_mul(100) / self.limit
+        }
+    }
+}
+
+/// Quota of the mailbox as last reported by the server.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QuotaInfo {
+    /// The resources of all quota roots of the inbox.
+    pub resources: Vec<QuotaResource>,
+
+    /// Time of the query.
+    pub timestamp: i64,
+}
+
+impl QuotaInfo {
+    /// Returns the highest usage of all resources in percent.
+    pub fn highest_usage_percent(&self) -> u64 {
+        self.resources
+            .iter()
+            .map(QuotaResource::usage_percent)
+            .max()
+            .unwrap_or_default()
+    }
+
+    async fn load(context: &Context) -> Option<Self> {
+        let raw = context.sql.get_raw_config(context, QUOTA_KEY).await?;
+        serde_json::from_str(&raw).ok()
+    }
+
+    async fn save(&self, context: &Context) -> Result<()> {
+        context
+            .sql
+            .set_raw_config(
+                context,
+                QUOTA_KEY,
+                Some(serde_json::to_string(self)?.as_str()),
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn remove(context: &Context) -> Result<()> {
+        context.sql.set_raw_config(context, QUOTA_KEY, None).await?;
+        Ok(())
+    }
+}
+
+/// The last warning emitted.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct Warning {
+    /// The threshold crossed.
+    threshold: u64,
+    timestamp: i64,
+}
+
+impl Warning {
+    async fn load(context: &Context) -> Self {
+        match context.sql.get_raw_config(context, WARNING_KEY).await {
+            Some(raw) => serde_json::from_str(&raw).unwrap_or_default(),
+            None => Warning::default(),
+        }
+    }
+
+    async fn save(&self, context: &Context) -> Result<()> {
+        context
+            .sql
+            .set_raw_config(
+                context,
+                WARNING_KEY,
+                Some(serde_json::to_string(self)?.as_str()),
+            )
+            .await?;
+        Ok(())
+    }
+}
+
+impl Context {
+    /// Returns the quota of the mailbox as last reported by the server.
+    ///
+    /// Returns `None` if the server does not support quotas or was not asked yet.
+    pub async fn get_quota_info(&self) -> Option<QuotaInfo> {
+        QuotaInfo::load(self).await
+    }
+}
+
+/// Queries the quota of `folder` if the last query is older than [`UPDATE_INTERVAL`].
+pub(crate) async fn update(
+    context: &Context,
+    session: &mut impl ImapSession,
+    folder: &str,
+) -> Result<()> {
+    update_at(context, session, folder, time()).await
+}
+
+async fn update_at(
+    context: &Context,
+    session: &mut impl ImapSession,
+    folder: &str,
+    now: i64,
+) -> Result<()> {
+    let old = QuotaInfo::load(context).await;
+    if let Some(old) = &old {
+        if old.timestamp <= now && now < old.timestamp + UPDATE_INTERVAL {
+            return Ok(());
+        }
+    }
+
+    let response = match session.query_quota_root(folder).await? {
+        Some(response) => response,
+        None => {
+            if old.is_some() {
+                QuotaInfo::remove(context).await?;
+            }
+            return Ok(());
+        }
+    };
+    let info = QuotaInfo {
+        resources: parse_quota_root(&response),
+        timestamp: now,
+    };
+    info.save(context)
+        .await
+        .context("cannot store quota info")?;
+    maybe_warn(context, &info, now).await
+}
+
+/// Emits a warning if the usage crossed a threshold and no warning for it or a higher
+/// threshold was emitted during the last day.
+async fn maybe_warn(context: &Context, info: &QuotaInfo, now: i64) -> Result<()> {
+    let usage = info.highest_usage_percent();
+    let threshold = match WARNING_THRESHOLDS.iter().rev().find(|t| usage >= **t) {
+        Some(threshold) => *threshold,
+        None => return Ok(()),
+    };
+
+    let last = Warning::load(context).await;
+    if last.threshold >= threshold
+        && last.timestamp <= now
+        && now < last.timestamp + WARNING_INTERVAL
+    {
+        return Ok(());
+    }
+
+    warn!(
+        context,
+        "Mailbox quota usage is {}%, exceeding {}%", usage, threshold
+    );
+    Warning {
+        threshold,
+        timestamp: now,
+    }
+    .save(context)
+    .await
+}
+
+/// Parses the `QUOTA` responses contained in the response to `GETQUOTAROOT`.
+///
+/// Other responses, e.g. `QUOTAROOT` or unilateral `EXISTS`, are ignored.
+fn parse_quota_root(response: &str) -> Vec<QuotaResource> {
+    let mut resources = Vec::new();
+    for line in response.lines() {
+        let rest = match line.strip_prefix("* QUOTA ") {
+            Some(rest) => rest.trim_start(),
+            None => continue,
+        };
+        let (root, rest) = match parse_astring(rest) {
+            Some(parsed) => parsed,
+            None => continue,
+        };
+        let list = rest
+            .trim()
+            .strip_prefix('(')
+            .and_then(|list| list.strip_suffix(')'));
+        let items: Vec<&str> = match list {
+            Some(list) => list.split_whitespace().collect(),
+            None => continue,
+        };
+        for item in items.chunks_exact(3) {
+            if let [name, usage, limit] = item {
+                if let (Ok(usage), Ok(limit)) = (usage.parse(), limit.parse()) {
+                    resources.push(QuotaResource {
+                        root: root.clone(),
+                        name: name.to_uppercase(),
+                        usage,
+                        limit,
+                    });
+                }
+            }
+        }
+    }
+    resources
+}
+
+/// Parses a quoted string or an atom, returns it and the remaining input.
+fn parse_astring(input: &str) -> Option<(String, &str)> {
+    if let Some(quoted) = input.strip_prefix('"') {
+        let mut value = String::new();
+        let mut chars = quoted.char_indices();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '\\' => value.push(chars.next()?.1),
+                '"' => return Some((value, quoted.get(i + 1..)?)),
+                c => value.push(c),
+            }
+        }
+        None
+    } else {
+        let end = input.find(' ').unwrap_or_else(|| input.len());
+        Some((input.get(..end)?.to_string(), input.get(end..)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use async_std::channel::Receiver;
+
+    use crate::events::{Event, EventType};
+    use crate::test_utils::{MockSession, TestContext};
+
+    /// Returns the quota warnings emitted since the last call.
+    async fn warnings(t: &TestContext, event_rx: &Receiver<Option<String>>) -> Vec<String> {
+        t.emit_event(EventType::Info("checkpoint".to_string()));
+        let mut warnings = Vec::new();
+        while let Some(msg) = event_rx.recv().await.unwrap() {
+            warnings.push(msg);
+        }
+        warnings
+    }
+
+    #[test]
+    fn test_parse_quota_root() {
+        let response = "* QUOTAROOT INBOX \"\" Shared\r\n\
+                        * QUOTA \"\" (STORAGE 10 512)\r\n\
+                        * QUOTA Shared (storage 200 1000 MESSAGE 3 100)\r\n\
+                        * 12 EXISTS\r\n";
+        assert_eq!(
+            parse_quota_root(response),
+            vec![
+                QuotaResource {
+                    root: "".to_string(),
+                    name: "STORAGE".to_string(),
+                    usage: 10,
+                    limit: 512,
+                },
+                QuotaResource {
+                    root: "Shared".to_string(),
+                    name: "STORAGE".to_string(),
+                    usage: 200,
+                    limit: 1000,
+                },
+                QuotaResource {
+                    root: "Shared".to_string(),
+                    name: "MESSAGE".to_string(),
+                    usage: 3,
+                    limit: 100,
+                },
+            ]
+        );
+
+        let resources = parse_quota_root("* QUOTA \"my \\\"quota\\\"\" (STORAGE 5 0)\r\n");
+        assert_eq!(resources.len(), 1);
+        assert_eq!(resources.first().unwrap().root, "my \"quota\"");
+        assert_eq!(resources.first().unwrap().usage_percent(), 0);
+
+        assert!(parse_quota_root("* QUOTAROOT INBOX\r\n").is_empty());
+        assert!(parse_quota_root("* QUOTA \"unterminated (STORAGE 1 2)\r\n").is_empty());
+    }
+
+    #[async_std::test]
+    async fn test_update_quota() {
+        let t = TestContext::new().await;
+        let (event_tx, event_rx) = async_std::channel::unbounded();
+        t.add_event_sink(move |event: Event| {
+            let event_tx = event_tx.clone();
+            async move {
+                match event.typ {
+                    EventType::Warning(msg) if msg.contains("Mailbox quota usage") => {
+                        event_tx.try_send(Some(msg)).unwrap()
+                    }
+                    EventType::Info(msg) if msg == "checkpoint" => event_tx.try_send(None).unwrap(),
+                    _ => {}
+                }
+            }
+        })
+        .await;
+
+        let mut server = MockSession {
+            quota_usage: Some(500),
+            ..Default::default()
+        };
+        let now = time();
+        update_at(&t, &mut server, "INBOX", now).await.unwrap();
+        let info = t.get_quota_info().await.unwrap();
+        assert_eq!(info.timestamp, now);
+        assert_eq!(info.resources.len(), 2);
+        assert_eq!(info.highest_usage_percent(), 50);
+        assert!(warnings(&t, &event_rx).await.is_empty());
+
+        // Queried at most once per interval.
+        server.quota_usage = Some(850);
+        update_at(&t, &mut server, "INBOX", now + 1).await.unwrap();
+        assert_eq!(server.quota_queries, 1);
+        assert_eq!(
+            t.get_quota_info().await.unwrap().highest_usage_percent(),
+            50
+        );
+
+        let now = now + UPDATE_INTERVAL;
+        update_at(&t, &mut server, "INBOX", now).await.unwrap();
+        assert_eq!(server.quota_queries, 2);
+        assert_eq!(
+            t.get_quota_info().await.unwrap().highest_usage_percent(),
+            85
+        );
+        assert_eq!(warnings(&t, &event_rx).await.len(), 1);
+
+        // The same threshold is not warned about again on the same day, a higher one is.
+        let now = now + UPDATE_INTERVAL;
+        update_at(&t, &mut server, "INBOX", now).await.unwrap();
+        assert!(warnings(&t, &event_rx).await.is_empty());
+        server.quota_usage = Some(960);
+        let now = now + UPDATE_INTERVAL;
+        update_at(&t, &mut server, "INBOX", now).await.unwrap();
+        assert_eq!(warnings(&t, &event_rx).await.len(), 1);
+
+        // Quota and warnings are kept across a restart.
+        t.sql.close().await;
+        t.sql.open(&t, &t.get_dbfile(), false).await.unwrap();
+        assert_eq!(
+            t.get_quota_info().await.unwrap().highest_usage_percent(),
+            96
+        );
+        let now = now + UPDATE_INTERVAL;
+        update_at(&t, &mut server, "INBOX", now).await.unwrap();
+        assert!(warnings(&t, &event_rx).await.is_empty());
+
+        // The warning is repeated a day later.
+        let now = now + WARNING_INTERVAL;
+        update_at(&t, &mut server, "INBOX", now).await.unwrap();
+        assert_eq!(warnings(&t, &event_rx).await.len(), 1);
+
+        // Servers without QUOTA yield no info.
+        server.quota_usage = None;
+        update_at(&t, &mut server, "INBOX", now + UPDATE_INTERVAL)
+            .await
+            .unwrap();
+        assert_eq!(t.get_quota_info().await, None);
+    }
+}
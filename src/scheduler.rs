@@ -1,3 +1,6 @@
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
 use async_std::prelude::*;
 use async_std::{
     channel::{self, Receiver, Sender},
@@ -6,11 +9,13 @@
 
 use crate::config::Config;
 use crate::context::Context;
-use crate::dc_tools::maybe_add_time_based_warnings;
+use crate::dc_tools::{maybe_add_time_based_warnings, time};
 use crate::imap::Imap;
 use crate::job::{self, Thread};
 use crate::message::MsgId;
+use crate::quota;
 use crate::smtp::Smtp;
+use crate::state_batch;
 
 pub(crate) struct StopToken;
 
@@ -34,6 +39,7 @@ pub(crate) enum Scheduler {
 impl Context {
     /// Indicate that the network likely has come back.
     pub async fn maybe_network(&self) {
+        crate::ephemeral::check_clock_jump(self).await;
         self.scheduler.read().await.maybe_network().await;
     }
 
@@ -46,10 +52,31 @@ pub(crate) async fn interrupt_smtp(&self, info: InterruptInfo) {
     }
 }
 
+/// Counts a running IO loop in [crate::context::InnerContext::io_loops] while alive.
+struct LoopGuard {
+    context: Context,
+}
+
+impl LoopGuard {
+    fn new(context: &Context) -> Self {
+        context.io_loops.fetch_add(1, Ordering::SeqCst);
+        LoopGuard {
+            context: context.clone(),
+        }
+    }
+}
+
+impl Drop for LoopGuard {
+    fn drop(&mut self) {
+        self.context.io_loops.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
 async fn inbox_loop(ctx: Context, started: Sender<()>, inbox_handlers: ImapConnectionHandlers) {
     use futures::future::FutureExt;
 
     info!(ctx, "starting inbox loop");
+    let _loop_guard = LoopGuard::new(&ctx);
     let ImapConnectionHandlers {
         mut connection,
         stop_receiver,
@@ -122,6 +149,7 @@ async fn inbox_loop(ctx: Context, started: Sender<()>, inbox_handlers: ImapConne
 async fn fetch(ctx: &Context, connection: &mut Imap) {
     match ctx.get_config(Config::ConfiguredInboxFolder).await {
         Some(watch_folder) => {
+            let batch = state_batch::begin(ctx);
             if let Err(err) = connection.connect_configured(ctx).await {
                 error_network!(ctx, "{}", err);
                 return;
@@ -132,6 +160,10 @@ async fn fetch(ctx: &Context, connection: &mut Imap) {
                 connection.trigger_reconnect();
                 warn!(ctx, "{:#}", err);
             }
+
+            if let Err(err) = batch.end().await {
+                warn!(ctx, "Failed to write message states: {}", err);
+            }
         }
         None => {
             warn!(ctx, "Can not fetch inbox folder, not set");
@@ -150,6 +182,7 @@ async fn fetch_idle(ctx: &Context, connection: &mut Imap, folder: Config) -> Int
             }
 
             // fetch
+            let batch = state_batch::begin(ctx);
             if let Err(err) = connection.fetch(ctx, &watch_folder).await {
                 connection.trigger_reconnect();
                 warn!(ctx, "{:#}", err);
@@ -162,6 +195,14 @@ async fn fetch_idle(ctx: &Context, connection: &mut Imap, folder: Config) -> Int
                     // but maybe just one folder can't be selected or something
                     warn!(ctx, "{}", err);
                 }
+
+                if let Err(err) = quota::update(ctx, connection, &watch_folder).await {
+                    warn!(ctx, "Cannot update quota: {:#}", err);
+                }
+            }
+
+            if let Err(err) = batch.end().await {
+                warn!(ctx, "Failed to write message states: {}", err);
             }
 
             // idle
@@ -194,6 +235,7 @@ async fn simple_imap_loop(
     use futures::future::FutureExt;
 
     info!(ctx, "starting simple loop for {}", folder.as_ref());
+    let _loop_guard = LoopGuard::new(&ctx);
     let ImapConnectionHandlers {
         mut connection,
         stop_receiver,
@@ -231,6 +273,7 @@ async fn smtp_loop(ctx: Context, started: Sender<()>, smtp_handlers: SmtpConnect
     use futures::future::FutureExt;
 
     info!(ctx, "starting smtp loop");
+    let _loop_guard = LoopGuard::new(&ctx);
     let SmtpConnectionHandlers {
         mut connection,
         stop_receiver,
@@ -255,9 +298,18 @@ async fn smtp_loop(ctx: Context, started: Sender<()>, smtp_handlers: SmtpConnect
                     interrupt_info = Default::default();
                 }
                 None => {
-                    // Fake Idle
+                    // Fake Idle, but wake up in time for the next scheduled message.
                     info!(ctx, "smtp fake idle - started");
-                    interrupt_info = idle_interrupt_receiver.recv().await.unwrap_or_default();
+                    interrupt_info = match job::next_scheduled_send(&ctx).await {
+                        Some(send_time) => {
+                            let wait = Duration::from_secs((send_time - time()).max(1) as u64);
+                            async_std::future::timeout(wait, idle_interrupt_receiver.recv())
+                                .await
+                                .map(|res| res.unwrap_or_default())
+                                .unwrap_or_default()
+                        }
+                        None => idle_interrupt_receiver.recv().await.unwrap_or_default(),
+                    };
                     info!(ctx, "smtp fake idle - interrupted")
                 }
             }
@@ -612,3 +664,49 @@ pub fn new(probe_network: bool, msg_id: Option<MsgId>) -> Self {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::chat::ChatId;
+    use crate::message::MessageState;
+    use crate::test_utils::TestContext;
+
+    #[async_std::test]
+    async fn test_fetch_ends_state_batch() {
+        let t = TestContext::new().await;
+        t.set_config(Config::ConfiguredInboxFolder, Some("INBOX"))
+            .await
+            .unwrap();
+        let (_interrupt_sender, interrupt_receiver) = channel::bounded(1);
+        let mut connection = Imap::new(interrupt_receiver);
+        fetch(&t, &mut connection).await;
+
+        // State changes after the fetch are written right away.
+        let chat_id = ChatId::new(10);
+        t.sql
+            .execute(
+                "INSERT INTO msgs (chat_id, state) VALUES (?, ?);",
+                paramsv![chat_id, MessageState::OutPending],
+            )
+            .await
+            .unwrap();
+        let msg_id = MsgId::new(
+            t.sql
+                .get_rowid(&t, "msgs", "chat_id", chat_id.to_u32().to_string())
+                .await
+                .unwrap(),
+        );
+        assert!(
+            state_batch::queue(&t, chat_id, msg_id, MessageState::OutDelivered)
+                .await
+                .unwrap()
+        );
+        let state: Option<MessageState> = t
+            .sql
+            .query_get_value(&t, "SELECT state FROM msgs WHERE id=?;", paramsv![msg_id])
+            .await;
+        assert_eq!(state, Some(MessageState::OutDelivered));
+    }
+}
@@ -8,6 +8,8 @@
 //! protocol.  Afterwards it must be stored in a mutex and the [`BobStateHandle`] should be
 //! used to work with the state.
 
+use std::sync::atomic::Ordering;
+
 use anyhow::{Error, Result};
 use async_std::sync::MutexGuard;
 
@@ -20,6 +22,7 @@
 use crate::key::{DcKey, SignedPublicKey};
 use crate::message::Message;
 use crate::mimeparser::{MimeMessage, SystemMessage};
+use crate::ongoing::OperationKind;
 use crate::param::Param;
 
 use super::qrinvite::QrInvite;
@@ -93,11 +96,13 @@ pub async fn handle_message(
         info!(context, "Handling securejoin message for BobStateHandle");
         match self.bobstate.handle_message(context, mime_message).await {
             Ok(Some(stage)) => {
-                if matches!(
-                    stage,
-                    BobHandshakeStage::Completed | BobHandshakeStage::Terminated(_)
-                ) {
-                    self.finish_protocol(context).await;
+                match stage {
+                    BobHandshakeStage::Completed => self.finish_protocol(context).await,
+                    BobHandshakeStage::Terminated(_) => {
+                        context.bob.terminated.store(true, Ordering::Relaxed);
+                        self.finish_protocol(context).await;
+                    }
+                    _ => (),
                 }
                 Some(stage)
             }
@@ -107,6 +112,7 @@ pub async fn handle_message(
                     context,
                     "Error handling handshake message, aborting handshake: {}", err
                 );
+                context.bob.terminated.store(true, Ordering::Relaxed);
                 self.finish_protocol(context).await;
                 None
             }
@@ -121,9 +127,9 @@ pub async fn handle_message(
     /// Note that the state is only cleared on Drop since otherwise the invariant that the
     /// state is always consistent is violated.  However the "ongoing" process is released
     /// here a little bit earlier as this requires access to the Context, which we do not
-    /// have on Drop (Drop can not run asynchronous code).  Stopping the "ongoing" process
-    /// will release [`securejoin`](super::securejoin) which in turn will finally free the
-    /// ongoing process using [`Context::free_ongoing`].
+    /// have on Drop.  Canceling the "ongoing" process will release
+    /// [`securejoin`](super::securejoin) which in turn will finally end the ongoing process
+    /// by dropping its [`OperationGuard`](crate::ongoing::OperationGuard).
     ///
     /// [`InnerContext::bob`]: crate::context::InnerContext::bob
     /// [`Bob`]: super::Bob
@@ -131,7 +137,7 @@ async fn finish_protocol(&mut self, context: &Context) {
         info!(context, "Finishing securejoin handshake protocol for Bob");
         self.clear_state_on_drop = true;
         if let QrInvite::Group { .. } = self.bobstate.invite {
-            context.stop_ongoing().await;
+            context.cancel_operation(OperationKind::SecureJoin);
         }
     }
 }
@@ -261,6 +267,9 @@ async fn handle_message(
             "vg-member-added" | "vc-contact-confirm" => {
                 self.step_contact_confirm(context, mime_message).await
             }
+            "vg-request-rejected" | "vc-request-rejected" => {
+                self.step_request_rejected(context, mime_message).await
+            }
             _ => {
                 warn!(context, "Invalid step for BobState: {}", step);
                 Ok(None)
@@ -311,6 +320,43 @@ async fn step_auth_required(
         Ok(Some(BobHandshakeStage::RequestWithAuthSent))
     }
 
+    /// Handles a *vc-request-rejected* or *vg-request-rejected* message.
+    ///
+    /// # Bob - the joiner's side
+    ///
+    /// The inviter revoked the scanned QR code.  The rejection is only accepted if it is
+    /// signed with the fingerprint from the QR code and echoes one of its tokens, otherwise
+    /// anybody knowing that Bob is joining could abort the handshake.
+    async fn step_request_rejected(
+        &mut self,
+        context: &Context,
+        mime_message: &MimeMessage,
+    ) -> Result<Option<BobHandshakeStage>> {
+        info!(
+            context,
+            "Bob - handling vc-request-rejected/vg-request-rejected message"
+        );
+        if !encrypted_and_signed(context, mime_message, Some(self.invite.fingerprint())) {
+            warn!(
+                context,
+                "Ignoring request rejection without valid signature"
+            );
+            return Ok(None);
+        }
+        let token = mime_message
+            .get(HeaderDef::SecureJoinInvitenumber)
+            .map(|s| s.as_str())
+            .unwrap_or_default();
+        if token.is_empty()
+            || (token != self.invite.invitenumber() && token != self.invite.authcode())
+        {
+            warn!(context, "Ignoring request rejection for another QR code");
+            return Ok(None);
+        }
+        self.next = SecureJoinStep::Terminated;
+        Ok(Some(BobHandshakeStage::Terminated("QR code revoked")))
+    }
+
     /// Handles a *vc-contact-confirm* or *vg-member-added* message.
     ///
     /// # Bob - the joiner's side
@@ -508,10 +554,17 @@ enum SecureJoinStep {
 
 impl SecureJoinStep {
     /// Compares the legacy string representation of a step to a [`SecureJoinStep`] variant.
+    ///
+    /// The inviter may reject the request with a revoked QR code at any step.
     fn matches(&self, context: &Context, step: &str) -> bool {
+        let rejected = step == "vc-request-rejected" || step == "vg-request-rejected";
         match self {
-            Self::AuthRequired => step == "vc-auth-required" || step == "vg-auth-required",
-            Self::ContactConfirm => step == "vc-contact-confirm" || step == "vg-member-added",
+            Self::AuthRequired => {
+                step == "vc-auth-required" || step == "vg-auth-required" || rejected
+            }
+            Self::ContactConfirm => {
+                step == "vc-contact-confirm" || step == "vg-member-added" || rejected
+            }
             SecureJoinStep::Terminated => {
                 warn!(context, "Terminated state for next securejoin step");
                 false
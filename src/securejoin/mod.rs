@@ -1,12 +1,12 @@
 //! Verified contact protocol implementation as [specified by countermitm project](https://countermitm.readthedocs.io/en/stable/new.html#setup-contact-protocol)
 
 use std::convert::TryFrom;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::time::{Duration, Instant};
 
 use anyhow::{bail, Context as _, Error, Result};
-use async_std::channel::Receiver;
 use async_std::sync::Mutex;
-use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use percent_encoding::{AsciiSet, NON_ALPHANUMERIC};
 
 use crate::aheader::EncryptPreference;
 use crate::chat::{self, Chat, ChatId};
@@ -20,9 +20,10 @@
 use crate::key::{self, DcKey, Fingerprint, SignedPublicKey};
 use crate::message::Message;
 use crate::mimeparser::{MimeMessage, SystemMessage};
+use crate::ongoing::{OperationGuard, OperationKind};
 use crate::param::Param;
 use crate::peerstate::{Peerstate, PeerstateKeyType, PeerstateVerifiedStatus, ToSave};
-use crate::qr::check_qr;
+use crate::qr;
 use crate::sql;
 use crate::stock_str;
 use crate::token;
@@ -69,6 +70,8 @@ macro_rules! inviter_progress {
 #[derive(Debug, Default)]
 pub(crate) struct Bob {
     inner: Mutex<Option<BobState>>,
+    /// Whether the last handshake was terminated before completing.
+    terminated: AtomicBool,
 }
 
 /// Return value for [`Bob::start_protocol`].
@@ -80,7 +83,7 @@ enum StartedProtocolVariant {
     SetupContact,
     /// The secure-join protocol, to join a group.
     SecureJoin {
-        ongoing_receiver: Receiver<()>,
+        operation: OperationGuard,
         group_id: String,
     },
 }
@@ -107,34 +110,26 @@ async fn start_protocol(
         if guard.is_some() {
             return Err(JoinError::AlreadyRunning);
         }
+        self.terminated.store(false, Ordering::Relaxed);
         let variant = match invite {
             QrInvite::Group { ref grpid, .. } => {
-                let receiver = context
-                    .alloc_ongoing()
-                    .await
+                let operation = context
+                    .start_operation(OperationKind::SecureJoin, true)
                     .map_err(|_| JoinError::OngoingRunning)?;
                 StartedProtocolVariant::SecureJoin {
-                    ongoing_receiver: receiver,
+                    operation,
                     group_id: grpid.clone(),
                 }
             }
             _ => StartedProtocolVariant::SetupContact,
         };
-        match BobState::start_protocol(context, invite).await {
-            Ok((state, stage)) => {
-                if matches!(stage, BobHandshakeStage::RequestWithAuthSent) {
-                    joiner_progress!(context, state.invite().contact_id(), 400);
-                }
-                *guard = Some(state);
-                Ok(variant)
-            }
-            Err(err) => {
-                if let StartedProtocolVariant::SecureJoin { .. } = variant {
-                    context.free_ongoing().await;
-                }
-                Err(err)
-            }
+        // If starting fails, dropping `variant` also ends the ongoing process.
+        let (state, stage) = BobState::start_protocol(context, invite).await?;
+        if matches!(stage, BobHandshakeStage::RequestWithAuthSent) {
+            joiner_progress!(context, state.invite().contact_id(), 400);
         }
+        *guard = Some(state);
+        Ok(variant)
     }
 
     /// Returns a handle to the [`BobState`] of the handshake.
@@ -192,23 +187,13 @@ pub async fn dc_get_securejoin_qr(context: &Context, group: Option<ChatId>) -> O
         }
     };
 
-    let self_addr_urlencoded =
-        utf8_percent_encode(&self_addr, NON_ALPHANUMERIC_WITHOUT_DOT).to_string();
-    let self_name_urlencoded =
-        utf8_percent_encode(&self_name, NON_ALPHANUMERIC_WITHOUT_DOT).to_string();
-
     let qr = if let Some(group) = group {
         // parameters used: a=g=x=i=s=
         if let Ok(chat) = Chat::load_from_db(context, group).await {
-            let group_name = chat.get_name();
-            let group_name_urlencoded =
-                utf8_percent_encode(group_name, NON_ALPHANUMERIC).to_string();
-
-            Some(format!(
-                "OPENPGP4FPR:{}#a={}&g={}&x={}&i={}&s={}",
-                fingerprint.hex(),
-                self_addr_urlencoded,
-                &group_name_urlencoded,
+            Some(qr::format_verify_group(
+                &fingerprint,
+                &self_addr,
+                chat.get_name(),
                 &chat.grpid,
                 &invitenumber,
                 &auth,
@@ -219,11 +204,10 @@ pub async fn dc_get_securejoin_qr(context: &Context, group: Option<ChatId>) -> O
         }
     } else {
         // parameters used: a=n=i=s=
-        Some(format!(
-            "OPENPGP4FPR:{}#a={}&n={}&i={}&s={}",
-            fingerprint.hex(),
-            self_addr_urlencoded,
-            self_name_urlencoded,
+        Some(qr::format_verify_contact(
+            &fingerprint,
+            &self_addr,
+            &self_name,
             &invitenumber,
             &auth,
         ))
@@ -234,6 +218,25 @@ pub async fn dc_get_securejoin_qr(context: &Context, group: Option<ChatId>) -> O
     qr
 }
 
+/// Revokes the Secure Join QR code of a group or, with `group` set to `None`,
+/// the setup-contact QR code.
+///
+/// Joiners scanning the old QR code are told that it was revoked,
+/// the next call to [dc_get_securejoin_qr] generates a new QR code.
+pub async fn revoke_qr(context: &Context, group: Option<ChatId>) -> Result<()> {
+    token::revoke(context, group).await?;
+    info!(context, "Revoked QR code for chat {:?}", group);
+    Ok(())
+}
+
+/// Returns the number of QR code invitations for the group that can be used to join it.
+///
+/// With `group` set to `None` the setup-contact invitations are counted.
+pub async fn get_active_invite_count(context: &Context, group: Option<ChatId>) -> Result<usize> {
+    let count = token::count(context, token::Namespace::InviteNumber, group).await?;
+    Ok(count)
+}
+
 async fn get_self_fingerprint(context: &Context) -> Option<Fingerprint> {
     match SignedPublicKey::load_self(context).await {
         Ok(key) => Some(key.fingerprint()),
@@ -261,8 +264,8 @@ pub enum JoinError {
     // Note that this can only occur if we failed to create the chat correctly.
     #[error("No Chat found for group (this is a bug)")]
     MissingChat(#[source] sql::Error),
-    #[error("Ongoing sender dropped (this is a bug)")]
-    OngoingSenderDropped,
+    #[error("The secure-join protocol was terminated")]
+    Terminated,
 }
 
 /// Take a scanned QR-code and do the setup-contact/join-group/invite handshake.
@@ -289,7 +292,7 @@ async fn securejoin(context: &Context, qr: &str) -> Result<ChatId, JoinError> {
     ========================================================*/
 
     info!(context, "Requesting secure-join ...",);
-    let qr_scan = check_qr(context, &qr).await;
+    let qr_scan = qr::parse(context, qr).await.map_err(QrError::Parse)?;
     let invite = QrInvite::try_from(qr_scan)?;
 
     match context.bob.start_protocol(context, invite.clone()).await? {
@@ -302,16 +305,16 @@ async fn securejoin(context: &Context, qr: &str) -> Result<ChatId, JoinError> {
             Ok(chat_id)
         }
         StartedProtocolVariant::SecureJoin {
-            ongoing_receiver,
+            operation,
             group_id,
         } => {
             // for a group-join, wait until the protocol is finished and the group is created
-            ongoing_receiver
-                .recv()
-                .await
-                .map_err(|_| JoinError::OngoingSenderDropped)?;
+            operation.canceled().await;
+            if context.bob.terminated.load(Ordering::Relaxed) {
+                return Err(JoinError::Terminated);
+            }
 
-            // handle_securejoin_handshake() calls Context::stop_ongoing before the group
+            // handle_securejoin_handshake() calls Context::cancel_operation before the group
             // chat is created (it is created after handle_securejoin_handshake() returns by
             // dc_receive_imf()).  As a hack we just wait a bit for it to appear.
 
@@ -323,7 +326,6 @@ async fn securejoin(context: &Context, qr: &str) -> Result<ChatId, JoinError> {
                         Ok((chatid, _is_protected, _blocked)) => break chatid,
                         Err(err) => {
                             if start.elapsed() > Duration::from_secs(7) {
-                                context.free_ongoing().await;
                                 return Err(JoinError::MissingChat(err));
                             }
                         }
@@ -331,7 +333,7 @@ async fn securejoin(context: &Context, qr: &str) -> Result<ChatId, JoinError> {
                 }
                 async_std::task::sleep(Duration::from_millis(50)).await;
             };
-            context.free_ongoing().await;
+            drop(operation);
             Ok(chatid)
         }
     }
@@ -381,6 +383,7 @@ async fn send_handshake_msg(
         msg.param.set(Param::Arg4, grpid.as_ref());
     }
     if step == "vg-request" || step == "vc-request" {
+        // The joiner may not have our key yet.
         msg.param.set_int(Param::ForcePlaintext, 1);
     } else {
         msg.param.set_int(Param::GuaranteeE2ee, 1);
@@ -458,7 +461,7 @@ pub(crate) enum HandshakeMessage {
 /// Handle incoming secure-join handshake.
 ///
 /// This function will update the securejoin state in [`InnerContext::bob`] and also
-/// terminate the ongoing process using [`Context::stop_ongoing`] as required by the
+/// terminate the ongoing process using [`Context::cancel_operation`] as required by the
 /// protocol.
 ///
 /// A message which results in [`Err`] will be hidden from the user but not deleted, it may
@@ -524,6 +527,21 @@ pub(crate) async fn handle_securejoin_handshake(
                 }
             };
             if !token::exists(context, token::Namespace::InviteNumber, invitenumber).await {
+                if token::exists(context, token::Namespace::Revoked, invitenumber).await {
+                    warn!(context, "Secure-join denied (revoked invitenumber).");
+                    // Alice -> Bob, encrypted to the key from the Autocrypt header of the
+                    // request; the token tells Bob which QR code was revoked.
+                    send_handshake_msg(
+                        context,
+                        contact_chat_id,
+                        &format!("{}-request-rejected", &step[..2]),
+                        invitenumber,
+                        None,
+                        "",
+                    )
+                    .await?;
+                    return Ok(HandshakeMessage::Done);
+                }
                 warn!(context, "Secure-join denied (bad invitenumber).");
                 return Ok(HandshakeMessage::Ignore);
             }
@@ -618,6 +636,18 @@ pub(crate) async fn handle_securejoin_handshake(
                 }
             };
             if !token::exists(context, token::Namespace::Auth, auth_0).await {
+                if token::exists(context, token::Namespace::Revoked, auth_0).await {
+                    // Alice -> Bob
+                    send_handshake_msg(
+                        context,
+                        contact_chat_id,
+                        &format!("{}-request-rejected", &step[..2]),
+                        auth_0,
+                        None,
+                        "",
+                    )
+                    .await?;
+                }
                 could_not_establish_secure_connection(context, contact_chat_id, "Auth invalid.")
                     .await;
                 return Ok(HandshakeMessage::Ignore);
@@ -678,6 +708,34 @@ pub(crate) async fn handle_securejoin_handshake(
             }
             Ok(HandshakeMessage::Ignore) // "Done" would delete the message and break multi-device (the key from Autocrypt-header is needed)
         }
+        "vg-request-rejected" | "vc-request-rejected" => {
+            /*=======================================================
+            ====             Bob - the joiner's side             ====
+            ====     The inviter revoked the scanned QR code     ====
+            =======================================================*/
+            match context.bob.state(context).await {
+                Some(mut bobstate) => {
+                    if bobstate.invite().contact_id() != contact_id {
+                        warn!(context, "{} not sent by the inviter", step);
+                        return Ok(HandshakeMessage::Ignore);
+                    }
+                    match bobstate.handle_message(context, mime_message).await {
+                        Some(_stage) => {
+                            let addr = Contact::get_by_id(context, contact_id)
+                                .await
+                                .map(|contact| contact.get_addr().to_string())
+                                .unwrap_or_else(|_| "?".to_string());
+                            let msg = stock_str::securejoin_revoked(context, addr).await;
+                            chat::add_info_msg(context, bobstate.chat_id(), msg).await;
+                            joiner_progress!(context, contact_id, 0);
+                            Ok(HandshakeMessage::Done)
+                        }
+                        None => Ok(HandshakeMessage::Ignore),
+                    }
+                }
+                None => Ok(HandshakeMessage::Ignore),
+            }
+        }
         "vg-member-added" | "vc-contact-confirm" => {
             /*=======================================================
             ====             Bob - the joiner's side             ====
@@ -946,6 +1004,8 @@ mod tests {
 
     use crate::chat;
     use crate::chat::ProtectionStatus;
+    use crate::dc_receive_imf::dc_receive_imf;
+    use crate::dc_tools::time;
     use crate::events::Event;
     use crate::peerstate::Peerstate;
     use crate::test_utils::TestContext;
@@ -974,7 +1034,7 @@ async fn test_setup_contact() {
         dc_join_securejoin(&bob.ctx, &qr).await.unwrap();
 
         let sent = bob.pop_sent_msg().await;
-        assert!(!bob.ctx.has_ongoing().await);
+        assert!(!bob.ctx.is_operation_running(OperationKind::SecureJoin));
         assert_eq!(sent.recipient(), "alice@example.com".parse().unwrap());
         let msg = alice.parse_msg(&sent).await;
         assert!(!msg.was_encrypted());
@@ -1205,7 +1265,7 @@ async fn test_setup_contact_bob_knows_alice() {
                 _ => panic!("Wrong event type"),
             }
         }
-        assert!(!bob.ctx.has_ongoing().await);
+        assert!(!bob.ctx.is_operation_running(OperationKind::SecureJoin));
 
         // Check Bob sent the right handshake message.
         let sent = bob.pop_sent_msg().await;
@@ -1321,7 +1381,7 @@ async fn test_secure_join() {
         };
 
         let sent = bob.pop_sent_msg().await;
-        assert!(bob.ctx.has_ongoing().await);
+        assert!(bob.ctx.is_operation_running(OperationKind::SecureJoin));
         assert_eq!(sent.recipient(), "alice@example.com".parse().unwrap());
         let msg = alice.parse_msg(&sent).await;
         assert!(!msg.was_encrypted());
@@ -1440,6 +1500,177 @@ async fn test_secure_join() {
         let bob_chatid = joiner.await;
         let bob_chat = Chat::load_from_db(&bob.ctx, bob_chatid).await.unwrap();
         assert!(bob_chat.is_protected());
-        assert!(!bob.ctx.has_ongoing().await)
+        assert!(!bob.ctx.is_operation_running(OperationKind::SecureJoin))
+    }
+
+    #[async_std::test]
+    async fn test_secure_join_revoked_qr() {
+        let alice = TestContext::new_alice().await;
+        let bob = TestContext::new_bob().await;
+        let chatid = chat::create_group_chat(&alice.ctx, ProtectionStatus::Protected, "the chat")
+            .await
+            .unwrap();
+
+        let revoked_qr = dc_get_securejoin_qr(&alice.ctx, Some(chatid))
+            .await
+            .unwrap();
+        assert_eq!(
+            get_active_invite_count(&alice.ctx, Some(chatid))
+                .await
+                .unwrap(),
+            1
+        );
+        revoke_qr(&alice.ctx, Some(chatid)).await.unwrap();
+        assert_eq!(
+            get_active_invite_count(&alice.ctx, Some(chatid))
+                .await
+                .unwrap(),
+            0
+        );
+
+        // Bob scans the revoked QR-code, Alice rejects the vg-request.
+        let joiner = {
+            let ctx = bob.ctx.clone();
+            async_std::task::spawn(async move { dc_join_securejoin(&ctx, &revoked_qr).await })
+        };
+        let sent = bob.pop_sent_msg().await;
+        alice.recv_msg(&sent).await;
+        let sent = alice.pop_sent_msg().await;
+        let msg = bob.parse_msg(&sent).await;
+        assert!(msg.was_encrypted());
+        let alice_fp = SignedPublicKey::load_self(&alice.ctx)
+            .await
+            .unwrap()
+            .fingerprint();
+        assert!(msg.signatures.contains(&alice_fp));
+        assert_eq!(
+            msg.get(HeaderDef::SecureJoin).unwrap(),
+            "vg-request-rejected"
+        );
+        assert!(msg.get(HeaderDef::SecureJoinInvitenumber).is_some());
+
+        bob.recv_msg(&sent).await;
+        assert!(matches!(joiner.await, Err(JoinError::Terminated)));
+        assert!(!bob.ctx.is_operation_running(OperationKind::SecureJoin));
+        let bob_chat = bob.create_chat(&alice).await;
+        assert_eq!(
+            bob.get_last_msg_in(bob_chat.id).await.get_text().unwrap(),
+            "The invitation by alice@example.com was revoked."
+        );
+
+        // A new QR-code works.
+        let qr = dc_get_securejoin_qr(&alice.ctx, Some(chatid))
+            .await
+            .unwrap();
+        assert_eq!(
+            get_active_invite_count(&alice.ctx, Some(chatid))
+                .await
+                .unwrap(),
+            1
+        );
+        let joiner = {
+            let ctx = bob.ctx.clone();
+            async_std::task::spawn(async move { dc_join_securejoin(&ctx, &qr).await.unwrap() })
+        };
+
+        // Bob got Alice's key with the rejection and takes the shortcut.
+        let sent = bob.pop_sent_msg().await;
+        let msg = alice.parse_msg(&sent).await;
+        assert_eq!(
+            msg.get(HeaderDef::SecureJoin).unwrap(),
+            "vg-request-with-auth"
+        );
+        alice.recv_msg(&sent).await;
+        let sent = alice.pop_sent_msg().await;
+        bob.recv_msg(&sent).await;
+
+        let bob_chatid = joiner.await;
+        let bob_chat = Chat::load_from_db(&bob.ctx, bob_chatid).await.unwrap();
+        assert_eq!(
+            bob_chat.grpid,
+            Chat::load_from_db(&alice.ctx, chatid).await.unwrap().grpid
+        );
+
+        // Revoked tokens expire.
+        assert_eq!(
+            token::count(&alice.ctx, token::Namespace::Revoked, Some(chatid))
+                .await
+                .unwrap(),
+            2
+        );
+        sql::housekeeping(&alice.ctx).await.unwrap();
+        assert_eq!(
+            token::count(&alice.ctx, token::Namespace::Revoked, Some(chatid))
+                .await
+                .unwrap(),
+            2
+        );
+        alice
+            .ctx
+            .sql
+            .execute(
+                "UPDATE tokens SET timestamp=? WHERE namespc=?;",
+                paramsv![
+                    time() - token::REVOKED_TOKEN_LIFETIME - 1,
+                    token::Namespace::Revoked
+                ],
+            )
+            .await
+            .unwrap();
+        sql::housekeeping(&alice.ctx).await.unwrap();
+        assert_eq!(
+            token::count(&alice.ctx, token::Namespace::Revoked, Some(chatid))
+                .await
+                .unwrap(),
+            0
+        );
+    }
+
+    #[async_std::test]
+    async fn test_secure_join_forged_rejection() {
+        let alice = TestContext::new_alice().await;
+        let bob = TestContext::new_bob().await;
+
+        let qr = dc_get_securejoin_qr(&alice.ctx, None).await.unwrap();
+        let joiner = {
+            let ctx = bob.ctx.clone();
+            async_std::task::spawn(async move { dc_join_securejoin(&ctx, &qr).await })
+        };
+        let sent = bob.pop_sent_msg().await;
+        let invitenumber = alice
+            .parse_msg(&sent)
+            .await
+            .get(HeaderDef::SecureJoinInvitenumber)
+            .unwrap()
+            .clone();
+
+        // Anybody can send an unencrypted rejection with the token from the QR code.
+        let forged = format!(
+            "From: alice@example.com\n\
+             To: bob@example.net\n\
+             Subject: Message from alice@example.com\n\
+             Message-ID: <forged-rejection@example.com>\n\
+             Date: Sun, 22 Mar 2020 22:37:57 +0000\n\
+             Chat-Version: 1.0\n\
+             Secure-Join: vc-request-rejected\n\
+             Secure-Join-Invitenumber: {}\n\
+             \n\
+             Secure-Join: vc-request-rejected\n",
+            invitenumber
+        );
+        dc_receive_imf(&bob.ctx, forged.as_bytes(), "INBOX", 100, false)
+            .await
+            .unwrap();
+        assert!(bob.ctx.is_operation_running(OperationKind::SecureJoin));
+
+        // The real handshake still completes.
+        alice.recv_msg(&sent).await;
+        let sent = alice.pop_sent_msg().await;
+        bob.recv_msg(&sent).await;
+        let sent = bob.pop_sent_msg().await;
+        alice.recv_msg(&sent).await;
+        let sent = alice.pop_sent_msg().await;
+        bob.recv_msg(&sent).await;
+        assert!(joiner.await.is_ok());
     }
 }
@@ -2,7 +2,7 @@
 
 pub mod send;
 
-use std::time::{Duration, SystemTime};
+use std::time::SystemTime;
 
 use async_smtp::smtp::client::net::ClientTlsParameters;
 use async_smtp::{error, smtp, EmailAddress};
@@ -11,13 +11,12 @@
 use crate::context::Context;
 use crate::events::EventType;
 use crate::login_param::{dc_build_tls, CertificateChecks, LoginParam, ServerLoginParam};
+use crate::network::NetworkPolicy;
 use crate::oauth2::dc_get_oauth2_access_token;
 use crate::provider::Socket;
+use crate::socks::Socks5Config;
 use crate::stock_str;
 
-/// SMTP write and read timeout in seconds.
-const SMTP_TIMEOUT: u64 = 30;
-
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
     #[error("Bad parameters")]
@@ -41,6 +40,12 @@ pub enum Error {
 
     #[error("TLS error")]
     Tls(#[from] async_native_tls::Error),
+
+    #[error("SMTP: failed to connect through SOCKS5 proxy: {0}")]
+    Socks5(#[source] std::io::Error),
+
+    #[error("SMTP: failed to connect: {0}")]
+    Io(#[from] std::io::Error),
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
@@ -56,6 +61,12 @@ pub(crate) struct Smtp {
     /// (eg connect or send succeeded). On initialization and disconnect
     /// it is set to None.
     last_success: Option<SystemTime>,
+
+    /// SOCKS5 proxy the transport is connected through.
+    socks5_config: Option<Socks5Config>,
+
+    /// Network policy the transport was connected with.
+    network_policy: Option<NetworkPolicy>,
 }
 
 impl Smtp {
@@ -97,7 +108,13 @@ pub async fn is_connected(&self) -> bool {
     /// Connect using configured parameters.
     pub async fn connect_configured(&mut self, context: &Context) -> Result<()> {
         if self.is_connected().await {
-            return Ok(());
+            if self.socks5_config == Socks5Config::from_database(context).await
+                && self.network_policy == Some(NetworkPolicy::from_database(context).await)
+            {
+                return Ok(());
+            }
+            info!(context, "Network settings changed, reconnecting SMTP");
+            self.disconnect().await;
         }
 
         let lp = LoginParam::from_database(context, "configured_").await;
@@ -158,9 +175,6 @@ pub async fn connect(
             CertificateChecks::AcceptInvalidCertificates
             | CertificateChecks::AcceptInvalidCertificates2 => false,
         };
-        let tls_config = dc_build_tls(strict_tls);
-        let tls_parameters = ClientTlsParameters::new(domain.to_string(), tls_config);
-
         let (creds, mechanism) = if oauth2 {
             // oauth2
             let send_pw = &lp.password;
@@ -191,30 +205,48 @@ pub async fn connect(
             )
         };
 
+        let socks5_config = Socks5Config::from_database(context).await;
+        let network_policy = NetworkPolicy::from_database(context).await;
+
+        let tls_config = dc_build_tls(strict_tls);
+        let tls_parameters = ClientTlsParameters::new(domain.to_string(), tls_config);
         let security = match lp.security {
             Socket::Plain => smtp::ClientSecurity::None,
             Socket::STARTTLS => smtp::ClientSecurity::Required(tls_parameters),
             _ => smtp::ClientSecurity::Wrapper(tls_parameters),
         };
 
-        let client = smtp::SmtpClient::with_security((domain.as_str(), port), security)
-            .await
-            .map_err(Error::ConnectionSetupFailure)?;
-
-        let client = client
-            .smtp_utf8(true)
-            .credentials(creds)
-            .authentication_mechanism(mechanism)
-            .connection_reuse(smtp::ConnectionReuseParameters::ReuseUnlimited)
-            .timeout(Some(Duration::from_secs(SMTP_TIMEOUT)));
-
-        let mut trans = client.into_transport();
-        if let Err(err) = trans.connect().await {
-            return Err(Error::ConnectionFailure(err));
-        }
+        let trans = network_policy
+            .connect(async {
+                let client = if let Some(ref socks5_config) = socks5_config {
+                    // The TLS parameters still refer to the domain of the server.
+                    let local_addr = socks5_config
+                        .forward(domain, port)
+                        .await
+                        .map_err(Error::Socks5)?;
+                    smtp::SmtpClient::with_security(local_addr, security).await
+                } else {
+                    smtp::SmtpClient::with_security((domain.as_str(), port), security).await
+                }
+                .map_err(Error::ConnectionSetupFailure)?;
+
+                let client = client
+                    .smtp_utf8(true)
+                    .credentials(creds)
+                    .authentication_mechanism(mechanism)
+                    .connection_reuse(smtp::ConnectionReuseParameters::ReuseUnlimited)
+                    .timeout(Some(network_policy.io_timeout));
+
+                let mut trans = client.into_transport();
+                trans.connect().await.map_err(Error::ConnectionFailure)?;
+                Ok::<_, Error>(trans)
+            })
+            .await?;
 
         self.transport = Some(trans);
         self.last_success = Some(SystemTime::now());
+        self.socks5_config = socks5_config;
+        self.network_policy = Some(network_policy);
 
         context.emit_event(EventType::SmtpConnected(format!(
             "SMTP-LOGIN as {} ok",
@@ -0,0 +1,694 @@
+//! # SOCKS5 proxy support.
+//!
+//! If `socks5_enabled` is set, IMAP and SMTP connections as well as the
+//! autoconfig lookups are established through the configured SOCKS5 proxy
+//! as defined in [RFC 1928](https://tools.ietf.org/html/rfc1928),
+//! optionally using username/password authentication as defined in
+//! [RFC 1929](https://tools.ietf.org/html/rfc1929).
+//!
+//! Host names are always passed to the proxy unresolved,
+//! so that no DNS requests leak past the proxy.
+
+use std::convert::TryFrom;
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::time::Duration;
+
+use anyhow::{bail, format_err, Result};
+use async_std::io::{self, Read, Write};
+use async_std::net::{TcpListener, TcpStream};
+use async_std::prelude::*;
+use async_std::task;
+
+use crate::config::Config;
+use crate::context::Context;
+use crate::login_param::dc_build_tls;
+
+/// Timeout for connecting to the proxy and the handshake
+/// and for the local connection to a forwarded port.
+///
+/// IMAP and SMTP connections are additionally limited by the connect timeout
+/// of [crate::network::NetworkPolicy].
+const SOCKS5_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Default port of SOCKS5 proxies.
+const DEFAULT_SOCKS5_PORT: u16 = 1080;
+
+const SOCKS5_VERSION: u8 = 0x05;
+const AUTH_NONE: u8 = 0x00;
+const AUTH_USER_PASSWORD: u8 = 0x02;
+const AUTH_NO_ACCEPTABLE: u8 = 0xff;
+const CMD_CONNECT: u8 = 0x01;
+const ATYP_IPV4: u8 = 0x01;
+const ATYP_DOMAIN: u8 = 0x03;
+const ATYP_IPV6: u8 = 0x04;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Socks5Config {
+    pub host: String,
+    pub port: u16,
+    pub user_password: Option<(String, String)>,
+}
+
+impl Socks5Config {
+    /// Reads the proxy configuration, returns `None` if no proxy should be used.
+    pub async fn from_database(context: &Context) -> Option<Self> {
+        if !context.get_config_bool(Config::Socks5Enabled).await {
+            return None;
+        }
+
+        let host = context
+            .get_config(Config::Socks5Host)
+            .await
+            .unwrap_or_default();
+        let port = u16::try_from(context.get_config_int(Config::Socks5Port).await)
+            .unwrap_or(DEFAULT_SOCKS5_PORT);
+        let user = context
+            .get_config(Config::Socks5User)
+            .await
+            .unwrap_or_default();
+        let password = context
+            .get_config(Config::Socks5Password)
+            .await
+            .unwrap_or_default();
+        let user_password = if user.is_empty() {
+            None
+        } else {
+            Some((user, password))
+        };
+
+        Some(Socks5Config {
+            host,
+            port,
+            user_password,
+        })
+    }
+
+    /// Connects to `target_host:target_port` through the proxy.
+    pub async fn connect(&self, target_host: &str, target_port: u16) -> io::Result<TcpStream> {
+        if self.host.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "SOCKS5 proxy enabled, but no host set",
+            ));
+        }
+        io::timeout(SOCKS5_TIMEOUT, async {
+            let mut stream = TcpStream::connect((self.host.as_str(), self.port)).await?;
+            self.handshake(&mut stream, target_host, target_port)
+                .await?;
+            Ok(stream)
+        })
+        .await
+    }
+
+    async fn handshake(
+        &self,
+        stream: &mut TcpStream,
+        target_host: &str,
+        target_port: u16,
+    ) -> io::Result<()> {
+        let methods: &[u8] = if self.user_password.is_some() {
+            &[AUTH_NONE, AUTH_USER_PASSWORD]
+        } else {
+            &[AUTH_NONE]
+        };
+        let mut greeting = vec![SOCKS5_VERSION, methods.len() as u8];
+        greeting.extend_from_slice(methods);
+        stream.write_all(&greeting).await?;
+
+        let mut reply = [0u8; 2];
+        stream.read_exact(&mut reply).await?;
+        if reply[0] != SOCKS5_VERSION {
+            return Err(protocol_error("unsupported SOCKS version"));
+        }
+        match reply[1] {
+            AUTH_NONE => {}
+            AUTH_USER_PASSWORD => match &self.user_password {
+                Some((user, password)) => {
+                    let mut auth = vec![0x01];
+                    push_len_prefixed(&mut auth, user)?;
+                    push_len_prefixed(&mut auth, password)?;
+                    stream.write_all(&auth).await?;
+
+                    stream.read_exact(&mut reply).await?;
+                    if reply[1] != 0 {
+                        return Err(io::Error::new(
+                            io::ErrorKind::PermissionDenied,
+                            "SOCKS5 authentication failed",
+                        ));
+                    }
+                }
+                None => return Err(protocol_error("proxy requested unoffered authentication")),
+            },
+            AUTH_NO_ACCEPTABLE => {
+                return Err(io::Error::new(
+                    io::ErrorKind::PermissionDenied,
+                    "SOCKS5 proxy accepts none of the offered authentication methods",
+                ))
+            }
+            _ => return Err(protocol_error("unsupported authentication method")),
+        }
+
+        let mut request = vec![SOCKS5_VERSION, CMD_CONNECT, 0x00];
+        match target_host.parse::<IpAddr>() {
+            Ok(IpAddr::V4(ip)) => {
+                request.push(ATYP_IPV4);
+                request.extend_from_slice(&ip.octets());
+            }
+            Ok(IpAddr::V6(ip)) => {
+                request.push(ATYP_IPV6);
+                request.extend_from_slice(&ip.octets());
+            }
+            Err(_) => {
+                // Let the proxy resolve the name.
+                request.push(ATYP_DOMAIN);
+                push_len_prefixed(&mut request, target_host)?;
+            }
+        }
+        request.extend_from_slice(&target_port.to_be_bytes());
+        stream.write_all(&request).await?;
+
+        let mut header = [0u8; 4];
+        stream.read_exact(&mut header).await?;
+        if header[0] != SOCKS5_VERSION {
+            return Err(protocol_error("unsupported SOCKS version"));
+        }
+        if header[1] != 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::ConnectionRefused,
+                format!(
+                    "SOCKS5 proxy cannot connect to {}:{}: {}",
+                    target_host,
+                    target_port,
+                    reply_error(header[1])
+                ),
+            ));
+        }
+
+        // Skip the bound address, it is not needed.
+        let addr_len = match header[3] {
+            ATYP_IPV4 => 4,
+            ATYP_IPV6 => 16,
+            ATYP_DOMAIN => {
+                let mut len = [0u8; 1];
+                stream.read_exact(&mut len).await?;
+                usize::from(len[0])
+            }
+            _ => return Err(protocol_error("unsupported address type")),
+        };
+        let mut bound_addr = vec![0u8; addr_len + 2];
+        stream.read_exact(&mut bound_addr).await?;
+
+        Ok(())
+    }
+
+    /// Connects to `target_host:target_port` through the proxy and returns a local address
+    /// forwarding a single connection there.
+    ///
+    /// This is used for clients that can only connect to socket addresses themselves,
+    /// they are expected to connect right away.  The port only listens on the loopback
+    /// interface and is closed as soon as the first connection is accepted,
+    /// so the proxied connection cannot be used by anyone connecting later.
+    pub async fn forward(&self, target_host: &str, target_port: u16) -> io::Result<SocketAddr> {
+        let proxy_stream = self.connect(target_host, target_port).await?;
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await?;
+        let local_addr = listener.local_addr()?;
+
+        task::spawn(async move {
+            let accepted = io::timeout(SOCKS5_TIMEOUT, listener.accept()).await;
+            drop(listener);
+            if let Ok((local_stream, peer_addr)) = accepted {
+                if peer_addr.ip().is_loopback() {
+                    pipe(local_stream, proxy_stream).await;
+                }
+            }
+        });
+
+        Ok(local_addr)
+    }
+}
+
+impl fmt::Display for Socks5Config {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "host:{},port:{},user_password:{}",
+            self.host,
+            self.port,
+            if self.user_password.is_some() {
+                "user_password_set"
+            } else {
+                "user_password_unset"
+            }
+        )
+    }
+}
+
+/// Connects to `host:port` through the proxy if one is given, directly otherwise.
+pub(crate) async fn connect_tcp(
+    host: &str,
+    port: u16,
+    socks5_config: Option<&Socks5Config>,
+) -> io::Result<TcpStream> {
+    match socks5_config {
+        Some(socks5_config) => socks5_config.connect(host, port).await,
+        None => TcpStream::connect((host, port)).await,
+    }
+}
+
+/// Requests `url` through the proxy and returns the body of the response.
+///
+/// Only simple GET requests are supported, redirects are followed.
+pub(crate) async fn http_get(socks5_config: &Socks5Config, url: &str) -> Result<String> {
+    let mut url = url::Url::parse(url)?;
+    for _ in 0..10 {
+        let host = url
+            .host_str()
+            .ok_or_else(|| format_err!("URL {} has no host", url))?
+            .to_string();
+        let port = url
+            .port_or_known_default()
+            .ok_or_else(|| format_err!("URL {} has no port", url))?;
+        let mut path = url.path().to_string();
+        if let Some(query) = url.query() {
+            path += "?";
+            path += query;
+        }
+
+        let stream = socks5_config.connect(&host, port).await?;
+        let response = match url.scheme() {
+            "https" => {
+                let tls_stream = dc_build_tls(true).connect(&host, stream).await?;
+                http_request(tls_stream, &host, &path).await?
+            }
+            "http" => http_request(stream, &host, &path).await?,
+            scheme => bail!("unsupported URL scheme {}", scheme),
+        };
+
+        match response {
+            HttpResponse::Body(body) => return Ok(body),
+            HttpResponse::Redirect(location) => url = url.join(&location)?,
+        }
+    }
+    bail!("too many redirects")
+}
+
+enum HttpResponse {
+    Body(String),
+    Redirect(String),
+}
+
+async fn http_request<S: Read + Write + Unpin>(
+    mut stream: S,
+    host: &str,
+    path: &str,
+) -> Result<HttpResponse> {
+    let request = format!(
+        "GET {} HTTP/1.0\r\nHost: {}\r\nConnection: close\r\n\r\n",
+        path, host
+    );
+    stream.write_all(request.as_bytes()).await?;
+    stream.flush().await?;
+
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).await?;
+    let response = String::from_utf8_lossy(&response);
+
+    let mut parts = response.splitn(2, "\r\n\r\n");
+    let head = parts.next().unwrap_or_default();
+    let body = parts.next().unwrap_or_default();
+
+    let mut lines = head.lines();
+    let status = lines
+        .next()
+        .and_then(|status_line| status_line.split_whitespace().nth(1))
+        .and_then(|status| status.parse::<u16>().ok())
+        .ok_or_else(|| format_err!("invalid HTTP response"))?;
+    match status {
+        200..=299 => Ok(HttpResponse::Body(body.to_string())),
+        300..=399 => {
+            let location = lines
+                .find_map(|line| {
+                    let mut header = line.splitn(2, ':');
+                    let name = header.next()?;
+                    let value = header.next()?;
+                    if name.trim().eq_ignore_ascii_case("location") {
+                        Some(value.trim().to_string())
+                    } else {
+                        None
+                    }
+                })
+                .ok_or_else(|| format_err!("HTTP redirect without location"))?;
+            Ok(HttpResponse::Redirect(location))
+        }
+        _ => bail!("HTTP status {}", status),
+    }
+}
+
+/// Copies data between both streams until one of them is closed.
+async fn pipe(a: TcpStream, b: TcpStream) {
+    let (mut a_reader, mut a_writer) = (&a, &a);
+    let (mut b_reader, mut b_writer) = (&b, &b);
+    io::copy(&mut a_reader, &mut b_writer)
+        .race(io::copy(&mut b_reader, &mut a_writer))
+        .await
+        .ok();
+}
+
+fn push_len_prefixed(buf: &mut Vec<u8>, s: &str) -> io::Result<()> {
+    let len = u8::try_from(s.len())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "SOCKS5 field too long"))?;
+    buf.push(len);
+    buf.extend_from_slice(s.as_bytes());
+    Ok(())
+}
+
+fn protocol_error(msg: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("SOCKS5 protocol error: {}", msg),
+    )
+}
+
+fn reply_error(code: u8) -> &'static str {
+    match code {
+        0x01 => "general failure",
+        0x02 => "connection not allowed by ruleset",
+        0x03 => "network unreachable",
+        0x04 => "host unreachable",
+        0x05 => "connection refused",
+        0x06 => "TTL expired",
+        0x07 => "command not supported",
+        0x08 => "address type not supported",
+        _ => "unknown error",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::{Arc, Mutex};
+
+    use crate::login_param::{CertificateChecks, ServerLoginParam};
+    use crate::provider::Socket;
+    use crate::smtp::Smtp;
+    use crate::test_utils::TestContext;
+
+    /// Minimal SOCKS5 proxy recording the requested targets.
+    struct MockProxy {
+        port: u16,
+        requests: Arc<Mutex<Vec<(String, u16)>>>,
+    }
+
+    impl MockProxy {
+        async fn start(user_password: Option<(&'static str, &'static str)>) -> Self {
+            let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+            let port = listener.local_addr().unwrap().port();
+            let requests = Arc::new(Mutex::new(Vec::new()));
+            let recorded = requests.clone();
+            task::spawn(async move {
+                loop {
+                    let (stream, _) = listener.accept().await.unwrap();
+                    let recorded = recorded.clone();
+                    task::spawn(async move {
+                        MockProxy::serve(stream, user_password, recorded).await.ok();
+                    });
+                }
+            });
+            MockProxy { port, requests }
+        }
+
+        async fn serve(
+            mut stream: TcpStream,
+            user_password: Option<(&str, &str)>,
+            recorded: Arc<Mutex<Vec<(String, u16)>>>,
+        ) -> io::Result<()> {
+            let mut buf = [0u8; 2];
+            stream.read_exact(&mut buf).await?;
+            let mut methods = vec![0u8; usize::from(buf[1])];
+            stream.read_exact(&mut methods).await?;
+
+            if let Some((user, password)) = user_password {
+                if !methods.contains(&AUTH_USER_PASSWORD) {
+                    stream
+                        .write_all(&[SOCKS5_VERSION, AUTH_NO_ACCEPTABLE])
+                        .await?;
+                    return Ok(());
+                }
+                stream
+                    .write_all(&[SOCKS5_VERSION, AUTH_USER_PASSWORD])
+                    .await?;
+                let given_user = read_len_prefixed(&mut stream, 1).await?;
+                let given_password = read_len_prefixed(&mut stream, 0).await?;
+                let ok = given_user == user && given_password == password;
+                stream.write_all(&[0x01, if ok { 0 } else { 1 }]).await?;
+                if !ok {
+                    return Ok(());
+                }
+            } else {
+                stream.write_all(&[SOCKS5_VERSION, AUTH_NONE]).await?;
+            }
+
+            let mut header = [0u8; 4];
+            stream.read_exact(&mut header).await?;
+            assert_eq!(header[3], ATYP_DOMAIN);
+            let host = read_len_prefixed(&mut stream, 0).await?;
+            let mut port = [0u8; 2];
+            stream.read_exact(&mut port).await?;
+            let port = u16::from_be_bytes(port);
+            recorded.lock().unwrap().push((host.clone(), port));
+
+            let target = TcpStream::connect((host.as_str(), port)).await?;
+            stream
+                .write_all(&[SOCKS5_VERSION, 0, 0, ATYP_IPV4, 0, 0, 0, 0, 0, 0])
+                .await?;
+            pipe(stream, target).await;
+            Ok(())
+        }
+
+        fn config(&self) -> Socks5Config {
+            Socks5Config {
+                host: "127.0.0.1".to_string(),
+                port: self.port,
+                user_password: None,
+            }
+        }
+
+        fn requests(&self) -> Vec<(String, u16)> {
+            self.requests.lock().unwrap().clone()
+        }
+    }
+
+    /// Reads a length-prefixed string, skipping `skip` bytes before the length.
+    async fn read_len_prefixed(stream: &mut TcpStream, skip: usize) -> io::Result<String> {
+        let mut prefix = vec![0u8; skip + 1];
+        stream.read_exact(&mut prefix).await?;
+        let mut s = vec![0u8; usize::from(prefix.last().copied().unwrap_or_default())];
+        stream.read_exact(&mut s).await?;
+        Ok(String::from_utf8_lossy(&s).to_string())
+    }
+
+    /// Starts a server sending a greeting on every connection, returns its port.
+    async fn start_greeting_server(greeting: &'static str) -> u16 {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        task::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                stream.write_all(greeting.as_bytes()).await.ok();
+            }
+        });
+        port
+    }
+
+    /// Starts a server sending `greeting` on every connection and answering
+    /// each line with `respond`, returns its port.
+    async fn start_line_server(greeting: &'static str, respond: fn(&str) -> String) -> u16 {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        task::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                task::spawn(async move {
+                    let mut writer = &stream;
+                    writer.write_all(greeting.as_bytes()).await.ok();
+                    let mut lines = io::BufReader::new(&stream).lines();
+                    while let Some(Ok(line)) = lines.next().await {
+                        if writer.write_all(respond(&line).as_bytes()).await.is_err() {
+                            break;
+                        }
+                    }
+                });
+            }
+        });
+        port
+    }
+
+    /// Answers every tagged IMAP command with OK.
+    fn imap_respond(line: &str) -> String {
+        let tag = line.split_whitespace().next().unwrap_or("*");
+        format!("{} OK done\r\n", tag)
+    }
+
+    fn smtp_respond(line: &str) -> String {
+        let command = line.to_ascii_uppercase();
+        if command.starts_with("EHLO") {
+            "250-localhost\r\n250-AUTH PLAIN LOGIN\r\n250 8BITMIME\r\n".to_string()
+        } else if command.starts_with("AUTH") {
+            "235 2.7.0 Authentication successful\r\n".to_string()
+        } else if command.starts_with("QUIT") {
+            "221 2.0.0 Bye\r\n".to_string()
+        } else {
+            "250 2.0.0 OK\r\n".to_string()
+        }
+    }
+
+    async fn set_proxy_config(t: &TestContext, proxy: &MockProxy) {
+        t.set_config(Config::Socks5Host, Some("127.0.0.1"))
+            .await
+            .unwrap();
+        t.set_config(Config::Socks5Port, Some(proxy.port.to_string().as_str()))
+            .await
+            .unwrap();
+        t.set_config(Config::Socks5Enabled, Some("1"))
+            .await
+            .unwrap();
+    }
+
+    async fn read_greeting(mut stream: TcpStream) -> String {
+        let mut buf = [0u8; 64];
+        let n = stream.read(&mut buf).await.unwrap();
+        String::from_utf8_lossy(buf.get(..n).unwrap()).to_string()
+    }
+
+    #[async_std::test]
+    async fn test_connect() {
+        let server_port = start_greeting_server("* OK IMAP ready\r\n").await;
+        let proxy = MockProxy::start(None).await;
+
+        let stream = proxy
+            .config()
+            .connect("localhost", server_port)
+            .await
+            .unwrap();
+        assert_eq!(read_greeting(stream).await, "* OK IMAP ready\r\n");
+        assert_eq!(
+            proxy.requests(),
+            vec![("localhost".to_string(), server_port)]
+        );
+    }
+
+    #[async_std::test]
+    async fn test_connect_with_auth() {
+        let server_port = start_greeting_server("* OK IMAP ready\r\n").await;
+        let proxy = MockProxy::start(Some(("alice", "secret"))).await;
+
+        let mut config = proxy.config();
+        assert!(config.connect("localhost", server_port).await.is_err());
+
+        config.user_password = Some(("alice".to_string(), "wrong".to_string()));
+        assert!(config.connect("localhost", server_port).await.is_err());
+
+        config.user_password = Some(("alice".to_string(), "secret".to_string()));
+        let stream = config.connect("localhost", server_port).await.unwrap();
+        assert_eq!(read_greeting(stream).await, "* OK IMAP ready\r\n");
+        assert_eq!(proxy.requests().len(), 1);
+    }
+
+    #[async_std::test]
+    async fn test_forward() {
+        let server_port = start_greeting_server("220 SMTP ready\r\n").await;
+        let proxy = MockProxy::start(None).await;
+
+        let local_addr = proxy
+            .config()
+            .forward("localhost", server_port)
+            .await
+            .unwrap();
+        let stream = TcpStream::connect(local_addr).await.unwrap();
+        assert_eq!(read_greeting(stream).await, "220 SMTP ready\r\n");
+        assert_eq!(
+            proxy.requests(),
+            vec![("localhost".to_string(), server_port)]
+        );
+
+        // The forwarded port accepts only one connection.
+        assert!(TcpStream::connect(local_addr).await.is_err());
+    }
+
+    #[async_std::test]
+    async fn test_imap_through_proxy() {
+        let t = TestContext::new().await;
+        let server_port = start_line_server("* OK IMAP ready\r\n", imap_respond).await;
+        let proxy = MockProxy::start(None).await;
+        set_proxy_config(&t, &proxy).await;
+
+        let socks5_config = Socks5Config::from_database(&t).await;
+        let client =
+            crate::imap::Client::connect_insecure("localhost", server_port, socks5_config.as_ref())
+                .await
+                .unwrap();
+        assert!(client.login("alice", "secret").await.is_ok());
+        assert_eq!(
+            proxy.requests(),
+            vec![("localhost".to_string(), server_port)]
+        );
+    }
+
+    #[async_std::test]
+    async fn test_smtp_through_proxy() {
+        let t = TestContext::new().await;
+        let server_port = start_line_server("220 localhost ESMTP\r\n", smtp_respond).await;
+        let proxy = MockProxy::start(None).await;
+        set_proxy_config(&t, &proxy).await;
+
+        let lp = ServerLoginParam {
+            server: "localhost".to_string(),
+            user: "alice".to_string(),
+            password: "secret".to_string(),
+            port: server_port,
+            security: Socket::Plain,
+            certificate_checks: CertificateChecks::Automatic,
+        };
+        let mut smtp = Smtp::new();
+        smtp.connect(&t, &lp, "alice@example.org", false, false)
+            .await
+            .unwrap();
+        assert!(smtp.is_connected().await);
+        assert_eq!(
+            proxy.requests(),
+            vec![("localhost".to_string(), server_port)]
+        );
+    }
+
+    #[async_std::test]
+    async fn test_enable_disable() {
+        let t = TestContext::new().await;
+        let server_port = start_greeting_server("* OK IMAP ready\r\n").await;
+        let proxy = MockProxy::start(None).await;
+        assert_eq!(Socks5Config::from_database(&t).await, None);
+
+        set_proxy_config(&t, &proxy).await;
+        let socks5_config = Socks5Config::from_database(&t).await;
+        assert_eq!(socks5_config, Some(proxy.config()));
+
+        let stream = connect_tcp("localhost", server_port, socks5_config.as_ref())
+            .await
+            .unwrap();
+        assert_eq!(read_greeting(stream).await, "* OK IMAP ready\r\n");
+        assert_eq!(proxy.requests().len(), 1);
+
+        // Disabling the proxy connects directly again.
+        t.set_config(Config::Socks5Enabled, Some("0"))
+            .await
+            .unwrap();
+        let socks5_config = Socks5Config::from_database(&t).await;
+        assert_eq!(socks5_config, None);
+        let stream = connect_tcp("localhost", server_port, socks5_config.as_ref())
+            .await
+            .unwrap();
+        assert_eq!(read_greeting(stream).await, "* OK IMAP ready\r\n");
+        assert_eq!(proxy.requests().len(), 1);
+    }
+}
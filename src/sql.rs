@@ -1,25 +1,37 @@
 //! # SQLite wrapper
 
 use async_std::prelude::*;
-use async_std::sync::RwLock;
+use async_std::sync::{Arc, RwLock, Weak};
+use async_std::task;
 
-use std::collections::HashSet;
-use std::path::Path;
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::time::Duration;
 
 use anyhow::format_err;
 use anyhow::Context as _;
+use rusqlite::functions::FunctionFlags;
+use rusqlite::types::Value;
 use rusqlite::{Connection, Error as SqlError, OpenFlags};
 
-use crate::chat::{add_device_msg, update_device_icon, update_saved_messages_icon};
-use crate::config::Config;
+use crate::blob::BlobObject;
+use crate::cache::{CacheStats, MidFilterUpdate, SqlCaches};
+use crate::chat::{
+    add_device_msg, update_device_icon, update_saved_messages_icon, DEVICE_ICONS_VERSION,
+    DEVICE_ICONS_VERSION_KEY,
+};
 use crate::config::Config::DeleteServerAfter;
+use crate::config::{is_secret_config_key, Config};
 use crate::constants::{ShowEmails, Viewtype, DC_CHAT_ID_TRASH};
-use crate::context::Context;
+use crate::contact;
+use crate::context::{Context, InnerContext};
 use crate::dc_tools::{dc_delete_file, time, EmailAddress};
 use crate::ephemeral::start_ephemeral_timers;
+use crate::events::EventType;
 use crate::imap;
-use crate::message::Message;
+use crate::message::{Message, MessageState, MsgId};
 use crate::param::{Param, Params};
 use crate::peerstate::Peerstate;
 use crate::provider::get_provider_by_domain;
@@ -35,6 +47,12 @@ macro_rules! paramsv {
     };
 }
 
+/// Database version after all migrations in [`open`] were run.
+///
+/// Must be updated together with the migrations, databases with a higher version were
+/// written by a newer version of this library.
+pub(crate) const DBVERSION: i32 = 96;
+
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
     #[error("Sqlite Error: {0:?}")]
@@ -47,6 +65,20 @@ pub enum Error {
     SqlAlreadyOpen,
     #[error("Sqlite: Failed to open")]
     SqlFailedToOpen,
+    #[error("Sqlite: Database is corrupted")]
+    SqlCorrupt,
+    #[error("Sqlite: Database is opened read-only")]
+    ReadOnly,
+    #[error("Sqlite: Invalid argument: {0}")]
+    InvalidArgument(String),
+    #[error("Invalid value for {key}: {reason}")]
+    InvalidConfig { key: String, reason: String },
+    #[error("Value for {key} has {size} bytes, more than the limit of {limit} bytes")]
+    ConfigValueTooLarge {
+        key: String,
+        size: usize,
+        limit: usize,
+    },
     #[error("{0}")]
     Io(#[from] std::io::Error),
     #[error("{0:?}")]
@@ -57,20 +89,292 @@ pub enum Error {
 
 pub type Result<T> = std::result::Result<T, Error>;
 
+/// Default for the maximum size of config values in bytes, see [`Sql::set_config_value_limit`].
+pub const DEFAULT_CONFIG_VALUE_LIMIT: usize = 64 * 1024;
+
+/// Raw config key storing the limit set by [`Sql::set_config_value_limit`].
+const CONFIG_VALUE_LIMIT_KEY: &str = "config_value_limit";
+
+/// Prefix of config values stored in the blob directory by [`Sql::set_raw_config_blob`],
+/// followed by the name of the file.
+///
+/// [`Sql::set_raw_config`] refuses values with this prefix, so other values, e.g. the
+/// `$BLOBDIR/` path of the avatar, are never taken for a config blob.
+const CONFIG_BLOB_PREFIX: &str = "$CONFIGBLOB/";
+
+/// Number of rows written per transaction by [`Sql::execute_many_bind`].
+const EXECUTE_MANY_CHUNK_SIZE: usize = 500;
+
+/// Number of rows loaded per query by [`Sql::rfc724_mid_may_exist`],
+/// below SQLite's limit of 999 parameters.
+const MIDS_PER_QUERY: usize = 500;
+
 /// A wrapper around the underlying Sqlite3 object.
 #[derive(Debug)]
 pub struct Sql {
     pool: RwLock<Option<r2d2::Pool<r2d2_sqlite::SqliteConnectionManager>>>,
+
+    /// Set when SQLite reported the database file as corrupted.
+    ///
+    /// Once set, all further queries fail with [`Error::SqlCorrupt`] until the database
+    /// is opened again, e.g. after [`Sql::try_recover`] or importing a backup.
+    corrupt: AtomicBool,
+
+    /// Set when the database is opened read-only.
+    readonly: AtomicBool,
+
+    /// Set while a database is attached, see [`Sql::attach`].
+    attached: AtomicBool,
+
+    /// The context owning this database, used to report corruption.
+    context: std::sync::RwLock<Option<Weak<InnerContext>>>,
+
+    /// Recently loaded chats and contacts, invalidated by the connections of the pool.
+    caches: Arc<SqlCaches>,
+
+    /// Maximum size of config values in bytes, see [`Sql::set_config_value_limit`].
+    config_value_limit: AtomicUsize,
+
+    /// Number of queries run by [`Sql::query_map`], to check the number of queries in tests.
+    #[cfg(test)]
+    pub(crate) query_map_count: AtomicUsize,
 }
 
 impl Default for Sql {
     fn default() -> Self {
         Self {
             pool: RwLock::new(None),
+            corrupt: AtomicBool::new(false),
+            readonly: AtomicBool::new(false),
+            attached: AtomicBool::new(false),
+            context: std::sync::RwLock::new(None),
+            caches: Arc::new(SqlCaches::default()),
+            config_value_limit: AtomicUsize::new(DEFAULT_CONFIG_VALUE_LIMIT),
+            #[cfg(test)]
+            query_map_count: AtomicUsize::new(0),
+        }
+    }
+}
+
+/// A database attached to a connection of the pool, see [`Sql::attach`].
+///
+/// The guard keeps the connection out of the pool, so all queries involving the attached
+/// database must go through it.  The database is detached when the guard is dropped.
+#[derive(Debug)]
+pub struct AttachedDb<'a> {
+    sql: &'a Sql,
+    conn: r2d2::PooledConnection<r2d2_sqlite::SqliteConnectionManager>,
+    schema_name: String,
+    detached: bool,
+}
+
+impl AttachedDb<'_> {
+    /// Returns the name the database is attached as.
+    pub fn schema_name(&self) -> &str {
+        &self.schema_name
+    }
+
+    /// Runs `callback` inside a transaction on the connection the database is attached to.
+    ///
+    /// The transaction is committed if `callback` returns `Ok` and rolled back otherwise.
+    pub fn transaction<G, H>(&mut self, callback: G) -> Result<H>
+    where
+        G: FnOnce(&mut rusqlite::Transaction<'_>) -> Result<H>,
+    {
+        let res = self
+            .conn
+            .transaction()
+            .map_err(Error::from)
+            .and_then(|mut tx| {
+                let ret = callback(&mut tx)?;
+                tx.commit()?;
+                Ok(ret)
+            });
+        self.sql.check_corruption(res)
+    }
+
+    /// Copies the rows of `table` matching `where_clause` from the attached database
+    /// into the main database, see [`copy_table`].
+    ///
+    /// Either all rows are copied or, on error, none.
+    pub fn copy_table(&mut self, table: &str, where_clause: &str) -> Result<usize> {
+        let schema_name = self.schema_name.clone();
+        self.transaction(|tx| copy_table(tx, &schema_name, table, where_clause))
+    }
+
+    /// Detaches the database.
+    pub fn detach(mut self) -> Result<()> {
+        self.detached = true;
+        let res = self
+            .conn
+            .execute("DETACH DATABASE ?;", params![self.schema_name])
+            .map(|_| ())
+            .map_err(Error::from);
+        self.sql.check_corruption(res)
+    }
+}
+
+impl Drop for AttachedDb<'_> {
+    fn drop(&mut self) {
+        if !self.detached {
+            self.conn
+                .execute("DETACH DATABASE ?;", params![self.schema_name])
+                .ok();
+        }
+        self.sql.attached.store(false, Ordering::SeqCst);
+    }
+}
+
+/// Returns true if `name` can be used as schema or table name without quoting issues.
+fn is_identifier(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with(|c: char| c.is_ascii_digit())
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Copies the rows of `table` matching `where_clause` from the attached database
+/// `src_schema` into the main database.
+///
+/// Only the columns existing in both databases are copied, so the databases may have
+/// different versions.  Copying the rows with a single `INSERT ... SELECT` is much faster
+/// than reading and writing them one by one.  Returns the number of rows copied.
+pub(crate) fn copy_table(
+    conn: &Connection,
+    src_schema: &str,
+    table: &str,
+    where_clause: &str,
+) -> Result<usize> {
+    if !is_identifier(src_schema) || !is_identifier(table) {
+        return Err(Error::InvalidArgument(format!(
+            "cannot copy {}.{}",
+            src_schema, table
+        )));
+    }
+
+    let columns_of = |schema: &str| {
+        conn.prepare(&format!("PRAGMA {}.table_info({});", schema, table))?
+            .query_map(params![], |row| row.get::<_, String>(1))?
+            .collect::<rusqlite::Result<Vec<_>>>()
+    };
+    let src_columns = columns_of(src_schema)?;
+    let columns = columns_of("main")?
+        .into_iter()
+        .filter(|column| src_columns.contains(column))
+        .map(|column| format!("\"{}\"", column))
+        .collect::<Vec<_>>()
+        .join(",");
+    if columns.is_empty() {
+        return Err(Error::InvalidArgument(format!(
+            "no columns of {} to copy from {}",
+            table, src_schema
+        )));
+    }
+
+    let copied = conn.execute(
+        &format!(
+            "INSERT INTO main.{table} ({columns}) SELECT {columns} FROM {schema}.{table} WHERE {where_clause};",
+            table = table,
+            columns = columns,
+            schema = src_schema,
+            where_clause = where_clause
+        ),
+        params![],
+    )?;
+    Ok(copied)
+}
+
+/// Result of [`Sql::try_recover`].
+#[derive(Debug, Default)]
+pub struct RecoveryReport {
+    /// Output of `PRAGMA integrity_check` on the damaged database.
+    pub integrity_check: Vec<String>,
+    /// Tables copied to the recovered database and the number of rows salvaged for each.
+    pub salvaged: Vec<(String, usize)>,
+    /// Tables which could not be copied completely and the error encountered.
+    pub damaged: Vec<(String, String)>,
+    /// Path the damaged database file was moved to.
+    pub corrupt_dbfile: PathBuf,
+}
+
+/// Non-fatal problems collected while performing an operation.
+///
+/// Used by operations which should complete even if some of their steps fail, so that the
+/// failures are reported to the caller instead of being silently dropped.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Warnings(Vec<String>);
+
+impl Warnings {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records a warning.
+    pub fn push(&mut self, warning: impl Into<String>) {
+        self.0.push(warning.into());
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &String> {
+        self.0.iter()
+    }
+
+    /// Emits each warning as [`EventType::Warning`].
+    pub fn emit(&self, context: &Context) {
+        for warning in &self.0 {
+            context.emit_event(EventType::Warning(warning.clone()));
         }
     }
 }
 
+/// Result of [`housekeeping`].
+#[derive(Debug, Default)]
+pub struct HousekeepingReport {
+    /// Number of unreferenced files deleted from the blob directory.
+    pub deleted_files: usize,
+    /// Unreferenced files were not deleted because the blob directory is read-only.
+    pub skipped_file_deletion: bool,
+    /// Steps which failed without aborting housekeeping.
+    pub warnings: Warnings,
+}
+
+/// Parses a numeric raw config value, an empty value is a cleared number, i.e. zero.
+fn parse_raw_config_number<T>(value: &str) -> Option<T>
+where
+    T: std::str::FromStr + Default,
+{
+    if value.is_empty() {
+        Some(T::default())
+    } else {
+        value.parse().ok()
+    }
+}
+
+/// Returns true if the error is caused by a write to a read-only database.
+fn is_readonly_error(err: &rusqlite::Error) -> bool {
+    match err {
+        SqlError::SqliteFailure(err, _) => err.code == rusqlite::ErrorCode::ReadOnly,
+        _ => false,
+    }
+}
+
+/// Returns true if the error means the database file is damaged or not a database at all.
+fn is_corruption(err: &rusqlite::Error) -> bool {
+    match err {
+        SqlError::SqliteFailure(err, _) => matches!(
+            err.code,
+            rusqlite::ErrorCode::DatabaseCorrupt | rusqlite::ErrorCode::NotADatabase
+        ),
+        _ => false,
+    }
+}
+
 impl Sql {
     pub fn new() -> Sql {
         Self::default()
@@ -83,6 +387,117 @@ pub async fn is_open(&self) -> bool {
     pub async fn close(&self) {
         let _ = self.pool.write().await.take();
         // drop closes the connection
+        self.caches.clear();
+    }
+
+    /// Returns the caches for chats and contacts loaded from this database.
+    pub(crate) fn caches(&self) -> &SqlCaches {
+        &self.caches
+    }
+
+    /// Drops all cached chats and contacts.
+    pub fn clear_caches(&self) {
+        self.caches.clear();
+    }
+
+    /// Applies [`Config::CacheSize`] to the caches.
+    pub(crate) async fn update_cache_size(&self, context: &Context) {
+        let size = context.get_config_int(Config::CacheSize).await;
+        self.caches
+            .set_capacity(usize::try_from(size).unwrap_or_default());
+    }
+
+    /// Returns the statistics of the caches for chats and contacts.
+    pub(crate) fn cache_stats(&self) -> CacheStats {
+        self.caches.stats()
+    }
+
+    /// Returns false if no message with the Message-ID `rfc724_mid` exists,
+    /// true if one may exist and the database has to be queried.
+    ///
+    /// Loads the Message-IDs inserted since the last check into the filter first,
+    /// or all of them on first use.
+    pub(crate) async fn rfc724_mid_may_exist(&self, rfc724_mid: &str) -> Result<bool> {
+        let mids = self.caches.mids();
+        match mids.pending_update() {
+            MidFilterUpdate::None => {}
+            MidFilterUpdate::Build { epoch, finished } => {
+                let rows = self
+                    .query_map(
+                        "SELECT rfc724_mid FROM msgs;",
+                        paramsv![],
+                        |row| row.get::<_, Option<String>>(0),
+                        |rows| {
+                            rows.filter_map(|row| row.transpose())
+                                .collect::<std::result::Result<Vec<_>, _>>()
+                                .map_err(Into::into)
+                        },
+                    )
+                    .await?;
+                mids.build(epoch, &finished, rows);
+            }
+            MidFilterUpdate::Sync { epoch, finished } => {
+                let ids: Vec<u32> = finished.keys().copied().collect();
+                let mut rows = Vec::with_capacity(ids.len());
+                for chunk in ids.chunks(MIDS_PER_QUERY) {
+                    let params: Vec<&dyn crate::ToSql> =
+                        chunk.iter().map(|id| id as &dyn crate::ToSql).collect();
+                    let chunk_rows = self
+                        .query_map(
+                            format!(
+                                "SELECT rfc724_mid FROM msgs WHERE id IN ({});",
+                                vec!["?"; chunk.len()].join(",")
+                            ),
+                            params,
+                            |row| row.get::<_, Option<String>>(0),
+                            |rows| {
+                                rows.filter_map(|row| row.transpose())
+                                    .collect::<std::result::Result<Vec<_>, _>>()
+                                    .map_err(Into::into)
+                            },
+                        )
+                        .await?;
+                    rows.extend(chunk_rows);
+                }
+                mids.sync(epoch, &finished, rows);
+            }
+        }
+        Ok(mids.may_contain(rfc724_mid))
+    }
+
+    /// Adds the Message-ID of the message `msg_id` just inserted to the filter checked by
+    /// [`Sql::rfc724_mid_may_exist`], so that it does not have to be loaded again.
+    pub(crate) fn rfc724_mid_inserted(&self, rfc724_mid: &str, msg_id: MsgId) {
+        self.caches.mids().add(rfc724_mid, msg_id.to_u32());
+    }
+
+    /// Writes a consistent snapshot of the database to `path`, which must not exist yet.
+    ///
+    /// Uses `VACUUM INTO`, so the database stays usable by other connections meanwhile and
+    /// the snapshot is vacuumed.
+    pub async fn backup_to(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref().to_string_lossy().into_owned();
+        self.execute("VACUUM INTO ?;", paramsv![path]).await?;
+        Ok(())
+    }
+
+    /// Copies the content of the write-ahead log into the database file and truncates the log.
+    pub async fn checkpoint(&self) -> Result<()> {
+        self.with_conn(|conn| {
+            conn.query_row("PRAGMA wal_checkpoint(TRUNCATE);", params![], |_row| Ok(()))?;
+            Ok(())
+        })
+        .await
+    }
+
+    /// Returns true if the database is opened read-only.
+    pub fn is_readonly(&self) -> bool {
+        self.readonly.load(Ordering::SeqCst)
+    }
+
+    /// Returns true if the database was detected to be corrupted.
+    pub fn is_corrupt(&self) -> bool {
+        self.corrupt.load(Ordering::SeqCst)
     }
 
     pub async fn open<T: AsRef<Path>>(
@@ -91,16 +506,26 @@ pub async fn open<T: AsRef<Path>>(
         dbfile: T,
         readonly: bool,
     ) -> anyhow::Result<()> {
+        if !self.is_open().await {
+            self.corrupt.store(false, Ordering::SeqCst);
+            self.readonly.store(readonly, Ordering::SeqCst);
+            *self.context.write().unwrap() = Some(Arc::downgrade(&context.inner));
+        }
         let res = open(context, self, &dbfile, readonly).await;
-        if let Err(err) = &res {
-            match err.downcast_ref::<Error>() {
+        match &res {
+            Ok(warnings) => warnings.emit(context),
+            Err(err) => match err.downcast_ref::<Error>() {
                 Some(Error::SqlAlreadyOpen) => {}
                 _ => {
                     self.close().await;
                 }
-            }
+            },
         }
-        res.map_err(|e| {
+        res.map(|_warnings| ()).map_err(|e| {
+            if let Some(Error::SqlCorrupt) = e.downcast_ref::<Error>() {
+                // Returned as is, so that callers can tell a damaged database file apart.
+                return e;
+            }
             format_err!(
                 // We are using Anyhow's .context() and to show the inner error, too, we need the {:#}:
                 "Could not open db file {}: {:#}",
@@ -110,6 +535,47 @@ pub async fn open<T: AsRef<Path>>(
         })
     }
 
+    /// Checks the result of a database operation for corruption errors.
+    ///
+    /// The first corruption error marks the database as corrupted, reports it via
+    /// [`EventType::DatabaseCorrupt`] and stops IO, so that the scheduler does not keep
+    /// running into the same error.
+    ///
+    /// Writes to a database opened read-only are reported as [`Error::ReadOnly`].
+    ///
+    /// As this is called when a statement or transaction is done,
+    /// it also invalidates the cached rows changed by it once more.
+    fn check_corruption<T>(&self, res: Result<T>) -> Result<T> {
+        self.caches.flush_pending();
+        if let Err(Error::Sql(err)) = &res {
+            if is_readonly_error(err) && self.is_readonly() {
+                return Err(Error::ReadOnly);
+            }
+            if is_corruption(err) && !self.corrupt.swap(true, Ordering::SeqCst) {
+                self.report_corruption(err.to_string());
+            }
+        }
+        res
+    }
+
+    fn report_corruption(&self, message: String) {
+        let inner = self
+            .context
+            .read()
+            .unwrap()
+            .as_ref()
+            .and_then(Weak::upgrade);
+        if let Some(inner) = inner {
+            let context = Context { inner };
+            error!(context, "Database is corrupted: {}", message);
+            context.emit_event(EventType::DatabaseCorrupt { message });
+
+            // Stopping IO waits for the IO loops, which may be the ones running into the
+            // corruption right now, so do not wait for it here.
+            task::spawn(async move { context.stop_io().await });
+        }
+    }
+
     pub async fn execute<S: AsRef<str>>(
         &self,
         sql: S,
@@ -120,7 +586,72 @@ pub async fn execute<S: AsRef<str>>(
             conn.execute(sql.as_ref(), params)
         };
 
-        res.map_err(Into::into)
+        self.check_corruption(res.map_err(Into::into))
+    }
+
+    /// Executes the statement once for each row of parameters.
+    ///
+    /// The rows are written in chunks of [`EXECUTE_MANY_CHUNK_SIZE`] rows, each in a
+    /// transaction of its own and with the statement prepared once per chunk.  The
+    /// connection is returned to the pool between the chunks, so other tasks can access
+    /// the database meanwhile.  If a chunk fails, the previous chunks stay written.
+    ///
+    /// Returns the total number of changed rows.
+    pub async fn execute_many_bind(&self, sql: &str, rows: Vec<Vec<Value>>) -> Result<u64> {
+        let (results, _transactions) = self
+            .execute_many_bind_chunked(sql, rows, EXECUTE_MANY_CHUNK_SIZE)
+            .await?;
+        Ok(results.iter().map(|(changes, _)| *changes as u64).sum())
+    }
+
+    /// Executes an `INSERT` statement once for each row of parameters,
+    /// like [`Sql::execute_many_bind`].
+    ///
+    /// Returns the ID of the row inserted for each row of parameters,
+    /// `None` if nothing was inserted.
+    pub async fn insert_many_bind(
+        &self,
+        sql: &str,
+        rows: Vec<Vec<Value>>,
+    ) -> Result<Vec<Option<i64>>> {
+        let (results, _transactions) = self
+            .execute_many_bind_chunked(sql, rows, EXECUTE_MANY_CHUNK_SIZE)
+            .await?;
+        Ok(results
+            .into_iter()
+            .map(|(changes, rowid)| if changes > 0 { Some(rowid) } else { None })
+            .collect())
+    }
+
+    /// Implements [`Sql::execute_many_bind`] and [`Sql::insert_many_bind`].
+    ///
+    /// Returns the number of changed rows and the last inserted row ID for each row of
+    /// parameters, and the number of transactions.
+    async fn execute_many_bind_chunked(
+        &self,
+        sql: &str,
+        rows: Vec<Vec<Value>>,
+        chunk_size: usize,
+    ) -> Result<(Vec<(usize, i64)>, usize)> {
+        let mut results = Vec::with_capacity(rows.len());
+        let mut transactions = 0;
+        for chunk in rows.chunks(chunk_size) {
+            let chunk_results = self
+                .transaction(|tx| {
+                    let mut stmt = tx.prepare_cached(sql)?;
+                    let mut results = Vec::with_capacity(chunk.len());
+                    for row in chunk {
+                        let changes = stmt.execute(row)?;
+                        results.push((changes, tx.last_insert_rowid()));
+                    }
+                    Ok(results)
+                })
+                .await?;
+            results.extend(chunk_results);
+            transactions += 1;
+            task::yield_now().await;
+        }
+        Ok((results, transactions))
     }
 
     /// Prepares and executes the statement and maps a function over the resulting rows.
@@ -138,16 +669,23 @@ pub async fn query_map<T, F, G, H>(
         G: FnMut(rusqlite::MappedRows<F>) -> Result<H>,
     {
         let sql = sql.as_ref();
+        #[cfg(test)]
+        self.query_map_count.fetch_add(1, Ordering::Relaxed);
 
-        let conn = self.get_conn().await?;
-        let mut stmt = conn.prepare(sql)?;
-        let res = stmt.query_map(&params, f)?;
-        g(res)
+        let res = {
+            let conn = self.get_conn().await?;
+            query_map(&conn, sql, params, f, g)
+        };
+
+        self.check_corruption(res)
     }
 
     pub async fn get_conn(
         &self,
     ) -> Result<r2d2::PooledConnection<r2d2_sqlite::SqliteConnectionManager>> {
+        if self.is_corrupt() {
+            return Err(Error::SqlCorrupt);
+        }
         let lock = self.pool.read().await;
         let pool = lock.as_ref().ok_or(Error::SqlNoConnection)?;
         let conn = pool.get()?;
@@ -162,11 +700,10 @@ pub async fn with_conn<G, H>(&self, g: G) -> Result<H>
             + 'static
             + FnOnce(r2d2::PooledConnection<r2d2_sqlite::SqliteConnectionManager>) -> Result<H>,
     {
-        let lock = self.pool.read().await;
-        let pool = lock.as_ref().ok_or(Error::SqlNoConnection)?;
-        let conn = pool.get()?;
+        let conn = self.get_conn().await?;
+        let res = g(conn);
 
-        g(conn)
+        self.check_corruption(res)
     }
 
     pub async fn with_conn_async<G, H, Fut>(&self, mut g: G) -> Result<H>
@@ -174,11 +711,76 @@ pub async fn with_conn_async<G, H, Fut>(&self, mut g: G) -> Result<H>
         G: FnMut(r2d2::PooledConnection<r2d2_sqlite::SqliteConnectionManager>) -> Fut,
         Fut: Future<Output = Result<H>> + Send,
     {
-        let lock = self.pool.read().await;
-        let pool = lock.as_ref().ok_or(Error::SqlNoConnection)?;
+        let conn = self.get_conn().await?;
+        let res = g(conn).await;
 
-        let conn = pool.get()?;
-        g(conn).await
+        self.check_corruption(res)
+    }
+
+    /// Runs `callback` inside a transaction.
+    ///
+    /// The transaction is committed if `callback` returns `Ok` and rolled back otherwise.
+    /// Unlike the closure passed to [`Sql::with_conn`], `callback` is neither required to be
+    /// `'static` nor `Send`, so it can borrow locals instead of cloning them.
+    pub async fn transaction<G, H>(&self, callback: G) -> Result<H>
+    where
+        G: FnOnce(&mut rusqlite::Transaction<'_>) -> Result<H>,
+    {
+        let mut conn = self.get_conn().await?;
+        let res = conn.transaction().map_err(Error::from).and_then(|mut tx| {
+            let ret = callback(&mut tx)?;
+            tx.commit()?;
+            Ok(ret)
+        });
+
+        self.check_corruption(res)
+    }
+
+    /// Attaches the database file at `path` as `schema_name` to a connection of the pool.
+    ///
+    /// Only one database can be attached at a time, attaching another one fails until the
+    /// returned guard is dropped.
+    pub async fn attach(
+        &self,
+        path: impl AsRef<Path>,
+        schema_name: &str,
+    ) -> Result<AttachedDb<'_>> {
+        if !is_identifier(schema_name) || schema_name == "main" || schema_name == "temp" {
+            return Err(Error::InvalidArgument(format!(
+                "invalid schema name {:?}",
+                schema_name
+            )));
+        }
+        if self
+            .attached
+            .compare_exchange(false, true, Ordering::SeqCst, Ordering::SeqCst)
+            .is_err()
+        {
+            return Err(Error::InvalidArgument(
+                "another database is attached already".to_string(),
+            ));
+        }
+
+        let path = path.as_ref().to_string_lossy().into_owned();
+        let res = match self.get_conn().await {
+            Ok(conn) => conn
+                .execute("ATTACH DATABASE ? AS ?;", params![path, schema_name])
+                .map(|_| conn)
+                .map_err(Error::from),
+            Err(err) => Err(err),
+        };
+        match self.check_corruption(res) {
+            Ok(conn) => Ok(AttachedDb {
+                sql: self,
+                conn,
+                schema_name: schema_name.to_string(),
+                detached: false,
+            }),
+            Err(err) => {
+                self.attached.store(false, Ordering::SeqCst);
+                Err(err)
+            }
+        }
     }
 
     /// Return `true` if a query in the SQL statement it executes returns one or more
@@ -190,7 +792,7 @@ pub async fn exists(&self, sql: &str, params: Vec<&dyn crate::ToSql>) -> Result<
             stmt.exists(&params)
         };
 
-        res.map_err(Into::into)
+        self.check_corruption(res.map_err(Into::into))
     }
 
     /// Execute a query which is expected to return one row.
@@ -209,7 +811,7 @@ pub async fn query_row<T, F>(
             conn.query_row(sql, params, f)
         };
 
-        res.map_err(Into::into)
+        self.check_corruption(res.map_err(Into::into))
     }
 
     pub async fn table_exists(&self, name: impl AsRef<str>) -> Result<bool> {
@@ -274,9 +876,33 @@ pub async fn query_row_optional<T, F>(
         }
     }
 
+    /// Executes a query which is expected to return zero or one row with one column.
+    ///
+    /// The outer `Option` tells whether the query returned a row, the inner one whether
+    /// the value is not SQL `NULL`.
+    pub async fn query_get_value_opt<T>(
+        &self,
+        query: &str,
+        params: Vec<&dyn crate::ToSql>,
+    ) -> Result<Option<Option<T>>>
+    where
+        T: rusqlite::types::FromSql,
+    {
+        match self
+            .query_row(query, params, |row| row.get::<_, Option<T>>(0))
+            .await
+        {
+            Ok(value) => Ok(Some(value)),
+            Err(Error::Sql(rusqlite::Error::QueryReturnedNoRows)) => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
     /// Executes a query which is expected to return one row and one
     /// column. If the query does not return a value or returns SQL
     /// `NULL`, returns `Ok(None)`.
+    ///
+    /// Use [`Sql::query_get_value_opt`] to tell both cases apart.
     pub async fn query_get_value_result<T>(
         &self,
         query: &str,
@@ -285,8 +911,8 @@ pub async fn query_get_value_result<T>(
     where
         T: rusqlite::types::FromSql,
     {
-        self.query_row_optional(query, params, |row| row.get::<_, T>(0))
-            .await
+        let value = self.query_get_value_opt(query, params).await?;
+        Ok(value.flatten())
     }
 
     /// Not resultified version of `query_get_value_result`. Returns
@@ -311,44 +937,73 @@ pub async fn query_get_value<T>(
 
     /// Set private configuration options.
     ///
-    /// Setting `None` deletes the value.  On failure an error message
+    /// Setting `None` deletes the value, setting an empty string clears it; see
+    /// [`Sql::get_raw_config`] for how both states are read.  On failure an error message
     /// will already have been logged.
+    ///
+    /// Returns [`Error::SqlNoConnection`] if the database is not open,
+    /// [`Error::InvalidArgument`] if `key` is empty and [`Error::ConfigValueTooLarge`] if
+    /// `value` exceeds [`Sql::config_value_limit`]; use [`Sql::set_raw_config_blob`] for
+    /// large values.
     pub async fn set_raw_config(
         &self,
         context: &Context,
         key: impl AsRef<str>,
         value: Option<&str>,
     ) -> Result<()> {
-        if !self.is_open().await {
-            error!(context, "set_raw_config(): Database not ready.");
-            return Err(Error::SqlNoConnection);
+        let key = key.as_ref();
+        if key.is_empty() {
+            error!(context, "set_raw_config(): Empty key.");
+            return Err(Error::InvalidArgument("empty config key".to_string()));
         }
+        if let Some(value) = value {
+            self.check_config_value(context, key, value)?;
+        }
+        if let Some(value) = value.filter(|_| is_secret_config_key(key)) {
+            context.add_log_secret(value);
+        }
+        self.write_raw_config(context, key, value).await
+    }
 
-        let key = key.as_ref();
-        let res = if let Some(value) = value {
-            let exists = self
-                .exists("SELECT value FROM config WHERE keyname=?;", paramsv![key])
-                .await?;
-            if exists {
-                self.execute(
-                    "UPDATE config SET value=? WHERE keyname=?;",
-                    paramsv![(*value).to_string(), key.to_string()],
-                )
-                .await
-            } else {
-                self.execute(
-                    "INSERT INTO config (keyname, value) VALUES (?, ?);",
-                    paramsv![key.to_string(), (*value).to_string()],
-                )
-                .await
-            }
-        } else {
-            self.execute("DELETE FROM config WHERE keyname=?;", paramsv![key])
-                .await
-        };
+    /// Writes a config value without checking it, see [`Sql::set_raw_config`].
+    async fn write_raw_config(
+        &self,
+        context: &Context,
+        key: &str,
+        value: Option<&str>,
+    ) -> Result<()> {
+        // Whether the database is open is only checked when getting the connection, a
+        // separate check could go stale before the value is written.
+        let res = self
+            .transaction(|tx| {
+                if let Some(value) = value {
+                    let exists = tx
+                        .prepare("SELECT value FROM config WHERE keyname=?;")?
+                        .exists(params![key])?;
+                    if exists {
+                        tx.execute(
+                            "UPDATE config SET value=? WHERE keyname=?;",
+                            params![value, key],
+                        )?;
+                    } else {
+                        tx.execute(
+                            "INSERT INTO config (keyname, value) VALUES (?, ?);",
+                            params![key, value],
+                        )?;
+                    }
+                } else {
+                    tx.execute("DELETE FROM config WHERE keyname=?;", params![key])?;
+                }
+                Ok(())
+            })
+            .await;
 
         match res {
             Ok(_) => Ok(()),
+            Err(Error::SqlNoConnection) => {
+                error!(context, "set_raw_config(): Database not ready.");
+                Err(Error::SqlNoConnection)
+            }
             Err(err) => {
                 error!(context, "set_raw_config(): Cannot change value. {:?}", &err);
                 Err(err)
@@ -356,50 +1011,333 @@ pub async fn set_raw_config(
         }
     }
 
-    /// Get configuration options from the database.
-    pub async fn get_raw_config(&self, context: &Context, key: impl AsRef<str>) -> Option<String> {
-        if !self.is_open().await || key.as_ref().is_empty() {
-            return None;
-        }
-        self.query_get_value(
-            context,
-            "SELECT value FROM config WHERE keyname=?;",
-            paramsv![key.as_ref().to_string()],
-        )
-        .await
-    }
-
-    pub async fn set_raw_config_int(
+    /// Sets several raw config values in a single transaction.
+    ///
+    /// Either all values are written or, on error, none of them.
+    pub async fn set_raw_config_batch(
         &self,
         context: &Context,
-        key: impl AsRef<str>,
-        value: i32,
+        entries: Vec<(String, Option<String>)>,
     ) -> Result<()> {
-        self.set_raw_config(context, key, Some(&format!("{}", value)))
-            .await
-    }
-
-    pub async fn get_raw_config_int(&self, context: &Context, key: impl AsRef<str>) -> Option<i32> {
-        self.get_raw_config(context, key)
+        self.set_raw_config_batch_with(context, entries, |_tx| Ok(()))
             .await
-            .and_then(|s| s.parse().ok())
-    }
-
-    pub async fn get_raw_config_bool(&self, context: &Context, key: impl AsRef<str>) -> bool {
-        // Not the most obvious way to encode bool as string, but it is matter
-        // of backward compatibility.
-        let res = self.get_raw_config_int(context, key).await;
-        res.unwrap_or_default() > 0
     }
 
-    pub async fn set_raw_config_bool<T>(&self, context: &Context, key: T, value: bool) -> Result<()>
+    /// Sets several raw config values like [`Sql::set_raw_config_batch`] and runs `update`
+    /// in the same transaction, so that dependent changes are committed together with the
+    /// values.
+    pub(crate) async fn set_raw_config_batch_with<F>(
+        &self,
+        context: &Context,
+        entries: Vec<(String, Option<String>)>,
+        update: F,
+    ) -> Result<()>
     where
-        T: AsRef<str>,
+        F: FnOnce(&rusqlite::Transaction) -> rusqlite::Result<()> + Send + 'static,
     {
-        let value = if value { Some("1") } else { None };
-        self.set_raw_config(context, key, value).await
-    }
-
+        if entries.iter().any(|(key, _)| key.is_empty()) {
+            error!(context, "set_raw_config_batch(): Empty key.");
+            return Err(Error::InvalidArgument("empty config key".to_string()));
+        }
+        for (key, value) in &entries {
+            if let Some(value) = value {
+                self.check_config_value(context, key, value)?;
+            }
+        }
+        for (key, value) in &entries {
+            if let Some(value) = value.as_deref().filter(|_| is_secret_config_key(key)) {
+                context.add_log_secret(value);
+            }
+        }
+
+        let res = self
+            .with_conn(move |mut conn| {
+                let tx = conn.transaction()?;
+                for (key, value) in entries {
+                    tx.execute("DELETE FROM config WHERE keyname=?;", params![key])?;
+                    if let Some(value) = value {
+                        tx.execute(
+                            "INSERT INTO config (keyname, value) VALUES (?, ?);",
+                            params![key, value],
+                        )?;
+                    }
+                }
+                update(&tx)?;
+                tx.commit()?;
+                Ok(())
+            })
+            .await;
+
+        match &res {
+            Ok(()) => {}
+            Err(Error::SqlNoConnection) => {
+                error!(context, "set_raw_config_batch(): Database not ready.");
+            }
+            Err(err) => {
+                error!(
+                    context,
+                    "set_raw_config_batch(): Cannot change values. {:?}", err
+                );
+            }
+        }
+        res
+    }
+
+    /// Returns the maximum size of config values in bytes.
+    pub fn config_value_limit(&self) -> usize {
+        self.config_value_limit.load(Ordering::Relaxed)
+    }
+
+    /// Sets the maximum size of config values in bytes, [`DEFAULT_CONFIG_VALUE_LIMIT`] by
+    /// default.
+    ///
+    /// Large values slow down every scan of the config table and bloat backups, they
+    /// should be stored with [`Sql::set_raw_config_blob`] instead.  Values stored before
+    /// are not affected.  The limit is stored in the database and applies again after
+    /// the database is opened the next time.
+    pub async fn set_config_value_limit(&self, context: &Context, limit: usize) -> Result<()> {
+        let value = i64::try_from(limit)
+            .map_err(|_| Error::InvalidArgument(format!("config value limit {}", limit)))?;
+        self.set_raw_config_int64(context, CONFIG_VALUE_LIMIT_KEY, value)
+            .await?;
+        self.config_value_limit.store(limit, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Loads the limit stored by [`Sql::set_config_value_limit`] when opening the database.
+    async fn load_config_value_limit(&self, context: &Context) {
+        let limit = self
+            .get_raw_config_int64(context, CONFIG_VALUE_LIMIT_KEY)
+            .await
+            .and_then(|limit| usize::try_from(limit).ok())
+            .unwrap_or(DEFAULT_CONFIG_VALUE_LIMIT);
+        self.config_value_limit.store(limit, Ordering::Relaxed);
+    }
+
+    fn check_config_value(&self, context: &Context, key: &str, value: &str) -> Result<()> {
+        if value.starts_with(CONFIG_BLOB_PREFIX) {
+            error!(
+                context,
+                "set_raw_config(): Value for {} looks like a config blob.", key
+            );
+            return Err(Error::InvalidArgument(format!(
+                "config value starting with {}",
+                CONFIG_BLOB_PREFIX
+            )));
+        }
+        let limit = self.config_value_limit();
+        if value.len() > limit {
+            error!(
+                context,
+                "set_raw_config(): Value for {} too large ({} bytes).",
+                key,
+                value.len()
+            );
+            return Err(Error::ConfigValueTooLarge {
+                key: key.to_string(),
+                size: value.len(),
+                limit,
+            });
+        }
+        Ok(())
+    }
+
+    /// Stores a large config value in a file in the blob directory.
+    ///
+    /// The config value only references the file with [`CONFIG_BLOB_PREFIX`], so the file
+    /// is kept by [housekeeping] as long as the key is set.  A file stored for the key
+    /// before is deleted.  The value is read with [`Sql::get_raw_config_blob`] or, as a
+    /// string, with [`Sql::get_raw_config`].
+    pub async fn set_raw_config_blob(
+        &self,
+        context: &Context,
+        key: impl AsRef<str>,
+        data: &[u8],
+    ) -> Result<()> {
+        let key = key.as_ref();
+        if key.is_empty() {
+            error!(context, "set_raw_config_blob(): Empty key.");
+            return Err(Error::InvalidArgument("empty config key".to_string()));
+        }
+        let old_value = self.query_raw_config(key).await?;
+        let blob = BlobObject::create(context, format!("config-{}", key), data).await?;
+        let value = format!("{}{}", CONFIG_BLOB_PREFIX, blob.as_file_name());
+        if let Err(err) = self.write_raw_config(context, key, Some(&value)).await {
+            dc_delete_file(context, blob.to_abs_path()).await;
+            return Err(err);
+        }
+        if let Some(old_value) = old_value.filter(|old_value| *old_value != value) {
+            if let Some(Ok(old_path)) = self.config_blob_path(&old_value) {
+                dc_delete_file(context, old_path).await;
+            }
+        }
+        Ok(())
+    }
+
+    /// Gets a config value stored with [`Sql::set_raw_config_blob`].
+    ///
+    /// Values which were set with [`Sql::set_raw_config`] are returned as they are, so
+    /// keys can be moved to blob storage without losing their values.
+    pub async fn get_raw_config_blob(&self, key: impl AsRef<str>) -> Result<Option<Vec<u8>>> {
+        let value = match self.query_raw_config(key.as_ref()).await? {
+            Some(value) => value,
+            None => return Ok(None),
+        };
+        match self.config_blob_path(&value) {
+            Some(path) => Ok(Some(async_std::fs::read(path?).await?)),
+            None => Ok(Some(value.into_bytes())),
+        }
+    }
+
+    /// Returns the path of the file referenced by a config blob value,
+    /// or `None` if `value` is a plain value.
+    fn config_blob_path(&self, value: &str) -> Option<Result<PathBuf>> {
+        let name = value.strip_prefix(CONFIG_BLOB_PREFIX)?;
+        let inner = self
+            .context
+            .read()
+            .unwrap()
+            .as_ref()
+            .and_then(Weak::upgrade);
+        let path = match inner {
+            Some(inner) => BlobObject::from_name(&Context { inner }, name.to_string())
+                .map(|blob| blob.to_abs_path())
+                .map_err(Into::into),
+            None => Err(Error::SqlNoConnection),
+        };
+        Some(path)
+    }
+
+    /// Replaces a config blob value by the content of the file it references.
+    ///
+    /// The content is read as UTF-8, invalid sequences are replaced.
+    pub(crate) async fn resolve_config_value(&self, value: String) -> Result<String> {
+        match self.config_blob_path(&value) {
+            Some(path) => {
+                let data = async_std::fs::read(path?).await?;
+                Ok(String::from_utf8_lossy(&data).into_owned())
+            }
+            None => Ok(value),
+        }
+    }
+
+    /// Gets several raw config values with a single query.
+    ///
+    /// Keys that are not set are missing from the returned map, keys which were cleared
+    /// map to an empty string as in [`Sql::get_raw_config`].
+    pub async fn get_raw_config_batch(&self, keys: &[String]) -> Result<HashMap<String, String>> {
+        if keys.is_empty() {
+            return Ok(HashMap::new());
+        }
+        let placeholders = vec!["?"; keys.len()].join(",");
+        let params: Vec<&dyn crate::ToSql> = keys.iter().map(|k| k as &dyn crate::ToSql).collect();
+        let rows = self
+            .query_map(
+                format!(
+                    "SELECT keyname, value FROM config WHERE keyname IN ({});",
+                    placeholders
+                ),
+                params,
+                |row| {
+                    let value: Option<String> = row.get(1)?;
+                    Ok((row.get::<_, String>(0)?, value.unwrap_or_default()))
+                },
+                |rows| {
+                    rows.collect::<std::result::Result<HashMap<_, _>, _>>()
+                        .map_err(Into::into)
+                },
+            )
+            .await?;
+        let mut values = HashMap::with_capacity(rows.len());
+        for (key, value) in rows {
+            values.insert(key, self.resolve_config_value(value).await?);
+        }
+        Ok(values)
+    }
+
+    /// Get configuration options from the database.
+    ///
+    /// Deleting a key and setting it to an empty string are different states: `None` is
+    /// only returned for keys which are not set, so that callers can fall back to a default
+    /// value, while a key set to an empty string or to SQL `NULL` was cleared explicitly and
+    /// returns `Some("")`.
+    ///
+    /// Errors are logged and return `None`, use [`Sql::try_get_raw_config`] to handle them.
+    pub async fn get_raw_config(&self, context: &Context, key: impl AsRef<str>) -> Option<String> {
+        let key = key.as_ref();
+        match self.try_get_raw_config(key).await {
+            Ok(value) => value,
+            // Reading the config of a closed database is not unusual, e.g. while
+            // importing a backup, so it is not worth a warning.
+            Err(Error::SqlNoConnection) => None,
+            Err(err) => {
+                warn!(context, "sql: Failed to get config {:?}: {}", key, err);
+                None
+            }
+        }
+    }
+
+    /// Gets a configuration option like [`Sql::get_raw_config`], but returns errors.
+    ///
+    /// Returns [`Error::SqlNoConnection`] if the database is not open and
+    /// [`Error::InvalidArgument`] if `key` is empty.
+    pub async fn try_get_raw_config(&self, key: impl AsRef<str>) -> Result<Option<String>> {
+        match self.query_raw_config(key.as_ref()).await? {
+            Some(value) => Ok(Some(self.resolve_config_value(value).await?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Gets a configuration option as stored, config blobs are not resolved.
+    async fn query_raw_config(&self, key: &str) -> Result<Option<String>> {
+        if key.is_empty() {
+            return Err(Error::InvalidArgument("empty config key".to_string()));
+        }
+        let value = self
+            .query_get_value_opt::<String>(
+                "SELECT value FROM config WHERE keyname=?;",
+                paramsv![key.to_string()],
+            )
+            .await?;
+        Ok(value.map(Option::unwrap_or_default))
+    }
+
+    pub async fn set_raw_config_int(
+        &self,
+        context: &Context,
+        key: impl AsRef<str>,
+        value: i32,
+    ) -> Result<()> {
+        self.set_raw_config(context, key, Some(&format!("{}", value)))
+            .await
+    }
+
+    /// Gets a numeric configuration option.
+    ///
+    /// As for [`Sql::get_raw_config`], `None` means the key is not set; a cleared key
+    /// reads as `0`.  Values which are not numbers are treated as not set.
+    pub async fn get_raw_config_int(&self, context: &Context, key: impl AsRef<str>) -> Option<i32> {
+        self.get_raw_config(context, key)
+            .await
+            .and_then(|s| parse_raw_config_number(&s))
+    }
+
+    /// Gets a boolean configuration option, keys which are not set or cleared are `false`.
+    pub async fn get_raw_config_bool(&self, context: &Context, key: impl AsRef<str>) -> bool {
+        // Not the most obvious way to encode bool as string, but it is matter
+        // of backward compatibility.
+        let res = self.get_raw_config_int(context, key).await;
+        res.unwrap_or_default() > 0
+    }
+
+    pub async fn set_raw_config_bool<T>(&self, context: &Context, key: T, value: bool) -> Result<()>
+    where
+        T: AsRef<str>,
+    {
+        let value = if value { Some("1") } else { None };
+        self.set_raw_config(context, key, value).await
+    }
+
     pub async fn set_raw_config_int64(
         &self,
         context: &Context,
@@ -417,7 +1355,7 @@ pub async fn get_raw_config_int64(
     ) -> Option<i64> {
         self.get_raw_config(context, key)
             .await
-            .and_then(|r| r.parse().ok())
+            .and_then(|s| parse_raw_config_number(&s))
     }
 
     /// Alternative to sqlite3_last_insert_rowid() which MUST NOT be used due to race conditions, see comment above.
@@ -454,6 +1392,173 @@ pub async fn get_rowid2(
 
         res.map_err(Into::into)
     }
+
+    /// Tries to salvage a corrupted database.
+    ///
+    /// Runs `PRAGMA integrity_check` and copies everything that can still be read into a
+    /// fresh database file, table by table and row by row, stopping at the first unreadable
+    /// row of a table.  The damaged file is kept next to the database with a `.corrupt`
+    /// suffix and the recovered database is opened in its place.
+    pub async fn try_recover(&self, context: &Context) -> anyhow::Result<RecoveryReport> {
+        let dbfile: PathBuf = context.get_dbfile().to_path_buf().into();
+        let mut recovered_dbfile = dbfile.clone().into_os_string();
+        recovered_dbfile.push(".recovered");
+        let recovered_dbfile = PathBuf::from(recovered_dbfile);
+        let mut corrupt_dbfile = dbfile.clone().into_os_string();
+        corrupt_dbfile.push(".corrupt");
+        let corrupt_dbfile = PathBuf::from(corrupt_dbfile);
+
+        info!(context, "Trying to recover database {}", dbfile.display());
+        self.close().await;
+
+        let mut report = RecoveryReport::default();
+        if recovered_dbfile.exists() {
+            std::fs::remove_file(&recovered_dbfile)?;
+        }
+        recover_db(dbfile.as_path(), recovered_dbfile.as_path(), &mut report)
+            .with_context(|| format!("failed to recover {}", dbfile.display()))?;
+
+        std::fs::rename(&dbfile, &corrupt_dbfile)?;
+        for suffix in &["-wal", "-shm"] {
+            let mut sidecar = dbfile.clone().into_os_string();
+            sidecar.push(suffix);
+            let sidecar = PathBuf::from(sidecar);
+            if sidecar.exists() {
+                std::fs::remove_file(&sidecar)?;
+            }
+        }
+        std::fs::rename(&recovered_dbfile, &dbfile)?;
+        report.corrupt_dbfile = corrupt_dbfile;
+
+        self.open(context, &dbfile, false).await?;
+        info!(
+            context,
+            "Recovered database: {} tables salvaged, {} damaged",
+            report.salvaged.len(),
+            report.damaged.len()
+        );
+        Ok(report)
+    }
+}
+
+/// Copies everything readable from the database at `src` into a new database at `dst`.
+fn recover_db(src: &Path, dst: &Path, report: &mut RecoveryReport) -> Result<()> {
+    let src = Connection::open_with_flags(src, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+    let mut dst = Connection::open(dst)?;
+
+    let integrity_check = src.prepare("PRAGMA integrity_check;").and_then(|mut stmt| {
+        let lines = stmt
+            .query_map(params![], |row| row.get::<_, String>(0))?
+            .collect::<rusqlite::Result<Vec<_>>>();
+        lines
+    });
+    report.integrity_check = match integrity_check {
+        Ok(lines) => lines,
+        Err(err) => vec![err.to_string()],
+    };
+
+    // Without the schema there is nothing to salvage.
+    let mut stmt = src.prepare(
+        "SELECT type, name, sql FROM sqlite_master
+         WHERE sql NOT NULL AND name NOT LIKE 'sqlite_%'
+         ORDER BY type='table' DESC, rowid;",
+    )?;
+    let schema = stmt
+        .query_map(params![], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+            ))
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    drop(stmt);
+
+    let tx = dst.transaction()?;
+    for (typ, name, sql) in &schema {
+        if typ != "table" {
+            continue;
+        }
+        tx.execute_batch(sql)?;
+
+        let mut select = match src.prepare(&format!("SELECT * FROM \"{}\";", name)) {
+            Ok(select) => select,
+            Err(err) => {
+                report.damaged.push((name.clone(), err.to_string()));
+                continue;
+            }
+        };
+        let columns = select.column_count();
+        let placeholders = vec!["?"; columns].join(",");
+        let mut insert = tx.prepare(&format!(
+            "INSERT INTO \"{}\" VALUES ({});",
+            name, placeholders
+        ))?;
+
+        let mut copied = 0;
+        let mut rows = select.query(params![])?;
+        loop {
+            match rows.next() {
+                Ok(Some(row)) => {
+                    let values = (0..columns)
+                        .map(|i| row.get::<_, rusqlite::types::Value>(i))
+                        .collect::<rusqlite::Result<Vec<_>>>()?;
+                    insert.execute(&values)?;
+                    copied += 1;
+                }
+                Ok(None) => break,
+                Err(err) => {
+                    report.damaged.push((name.clone(), err.to_string()));
+                    break;
+                }
+            }
+        }
+        report.salvaged.push((name.clone(), copied));
+    }
+
+    // Keep AUTOINCREMENT counters so that IDs of deleted rows are not reused.
+    if let Ok(mut stmt) = src.prepare("SELECT name, seq FROM sqlite_sequence;") {
+        let sequences = stmt
+            .query_map(params![], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?))
+            })
+            .and_then(|rows| rows.collect::<rusqlite::Result<Vec<_>>>())
+            .unwrap_or_default();
+        for (name, seq) in sequences {
+            tx.execute("DELETE FROM sqlite_sequence WHERE name=?;", params![name])?;
+            tx.execute(
+                "INSERT INTO sqlite_sequence (name, seq) VALUES (?, ?);",
+                params![name, seq],
+            )?;
+        }
+    }
+
+    // Indices are created after the data is copied, an index may fail to be created if
+    // salvaged rows violate a unique constraint.
+    for (typ, _name, sql) in &schema {
+        if typ != "table" {
+            tx.execute_batch(sql).ok();
+        }
+    }
+    tx.commit()?;
+
+    Ok(())
+}
+
+fn query_map<T, F, G, H>(
+    conn: &Connection,
+    sql: &str,
+    params: Vec<&dyn crate::ToSql>,
+    f: F,
+    mut g: G,
+) -> Result<H>
+where
+    F: FnMut(&rusqlite::Row) -> rusqlite::Result<T>,
+    G: FnMut(rusqlite::MappedRows<F>) -> Result<H>,
+{
+    let mut stmt = conn.prepare(sql)?;
+    let res = stmt.query_map(&params, f)?;
+    g(res)
 }
 
 pub fn get_rowid(
@@ -496,19 +1601,132 @@ pub fn get_rowid2(
     )
 }
 
-pub async fn housekeeping(context: &Context) -> anyhow::Result<()> {
+/// Deletes expired messages and unreferenced blobs and prunes the database.
+///
+/// Fails if the files in use cannot be determined, as deleting files would not be safe
+/// then.  Failures of the other steps are collected in the returned report.
+pub async fn housekeeping(context: &Context) -> anyhow::Result<HousekeepingReport> {
+    let mut report = HousekeepingReport::default();
+
     if let Err(err) = crate::ephemeral::delete_expired_messages(context).await {
-        warn!(context, "Failed to delete expired messages: {}", err);
+        report
+            .warnings
+            .push(format!("Failed to delete expired messages: {}", err));
+    }
+    if let Err(err) = crate::ephemeral::delete_device_expired_messages(context).await {
+        report
+            .warnings
+            .push(format!("Failed to delete old messages: {}", err));
     }
-
-    let mut files_in_use = HashSet::new();
-    let mut unreferenced_count = 0;
 
     info!(context, "Start housekeeping...");
+    let files_in_use = get_files_in_use(context).await?;
+    info!(context, "{} files in use.", files_in_use.len(),);
+
+    /* go through directory and delete unused files */
+    let p = context.get_blobdir();
+    let blobdir_writable = context.check_blobdir_writable().await;
+    if !blobdir_writable {
+        // Each deletion would fail, see `Context::check_blobdir_writable()`.
+        info!(
+            context,
+            "Housekeeping: Blob directory is read-only, not deleting files."
+        );
+        report.skipped_file_deletion = true;
+    }
+    match async_std::fs::read_dir(p).await {
+        Ok(_) if !blobdir_writable => {}
+        Ok(mut dir_handle) => {
+            let mut unreferenced_count = 0;
+            while let Some(entry) = dir_handle.next().await {
+                if entry.is_err() {
+                    break;
+                }
+                let entry = entry.unwrap();
+                let name_f = entry.file_name();
+                let name_s = name_f.to_string_lossy();
+
+                if delete_blob_if_unreferenced(
+                    context,
+                    &files_in_use,
+                    &entry.path(),
+                    &name_s,
+                    &mut unreferenced_count,
+                )
+                .await
+                {
+                    report.deleted_files += 1;
+                }
+            }
+        }
+        Err(err) => {
+            report.warnings.push(format!(
+                "Housekeeping: Cannot open {}. ({})",
+                context.get_blobdir().display(),
+                err
+            ));
+        }
+    }
+
+    if let Err(err) = start_ephemeral_timers(context).await {
+        report.warnings.push(format!(
+            "Housekeeping: cannot start ephemeral timers: {}",
+            err
+        ));
+    }
+
+    if let Err(err) = crate::peerstate::prune_history(context).await {
+        report.warnings.push(format!(
+            "Housekeeping: Cannot prune peerstate history: {}",
+            err
+        ));
+    }
+
+    if let Err(err) = crate::location::prune(context).await {
+        report
+            .warnings
+            .push(format!("Housekeeping: Cannot prune locations: {}", err));
+    }
+
+    if let Err(err) = crate::token::prune_revoked(context).await {
+        report.warnings.push(format!(
+            "Housekeeping: Cannot prune revoked tokens: {}",
+            err
+        ));
+    }
+
+    if let Err(err) = prune_tombstones(context).await {
+        report.warnings.push(format!(
+            "Housekeeping: Cannot prune message tombstones: {}",
+            err
+        ));
+    }
+
+    if let Err(e) = context
+        .set_config(Config::LastHousekeeping, Some(&time().to_string()))
+        .await
+    {
+        report.warnings.push(format!("Can't set config: {}", e));
+    }
+    info!(
+        context,
+        "Housekeeping done, {} warnings.",
+        report.warnings.len()
+    );
+    Ok(report)
+}
+
+/// Returns the names of the files in the blob directory referenced by the database.
+pub(crate) async fn get_files_in_use(context: &Context) -> anyhow::Result<HashSet<String>> {
+    let mut files_in_use = HashSet::new();
+    // Text messages have no attachment, except for drafts that may carry one.
     maybe_add_from_param(
         context,
         &mut files_in_use,
-        "SELECT param FROM msgs  WHERE chat_id!=3   AND type!=10;",
+        &format!(
+            "SELECT param FROM msgs WHERE (chat_id!=3 AND type!=10) OR state={};",
+            MessageState::OutDraft as i32
+        ),
         Param::File,
     )
     .await?;
@@ -534,10 +1752,11 @@ pub async fn housekeeping(context: &Context) -> anyhow::Result<()> {
     )
     .await?;
 
+    // Parts of attachments that are not yet assembled, see [crate::chunks].
     context
         .sql
         .query_map(
-            "SELECT value FROM config;",
+            "SELECT file FROM msgs_chunks;",
             paramsv![],
             |row| row.get::<_, String>(0),
             |rows| {
@@ -548,95 +1767,79 @@ pub async fn housekeeping(context: &Context) -> anyhow::Result<()> {
             },
         )
         .await
-        .context("housekeeping: failed to SELECT value FROM config")?;
-
-    info!(context, "{} files in use.", files_in_use.len(),);
-    /* go through directory and delete unused files */
-    let p = context.get_blobdir();
-    match async_std::fs::read_dir(p).await {
-        Ok(mut dir_handle) => {
-            /* avoid deletion of files that are just created to build a message object */
-            let diff = std::time::Duration::from_secs(60 * 60);
-            let keep_files_newer_than = std::time::SystemTime::now().checked_sub(diff).unwrap();
+        .context("housekeeping: failed to SELECT file FROM msgs_chunks")?;
 
-            while let Some(entry) = dir_handle.next().await {
-                if entry.is_err() {
-                    break;
+    context
+        .sql
+        .query_map(
+            "SELECT value FROM config;",
+            paramsv![],
+            |row| row.get::<_, String>(0),
+            |rows| {
+                for row in rows {
+                    let value = row?;
+                    match value.strip_prefix(CONFIG_BLOB_PREFIX) {
+                        Some(file) => {
+                            files_in_use.insert(file.to_string());
+                        }
+                        None => maybe_add_file(&mut files_in_use, value),
+                    }
                 }
-                let entry = entry.unwrap();
-                let name_f = entry.file_name();
-                let name_s = name_f.to_string_lossy();
+                Ok(())
+            },
+        )
+        .await
+        .context("housekeeping: failed to SELECT value FROM config")?;
 
-                if is_file_in_use(&files_in_use, None, &name_s)
-                    || is_file_in_use(&files_in_use, Some(".increation"), &name_s)
-                    || is_file_in_use(&files_in_use, Some(".waveform"), &name_s)
-                    || is_file_in_use(&files_in_use, Some("-preview.jpg"), &name_s)
-                {
-                    continue;
-                }
+    Ok(files_in_use)
+}
 
-                unreferenced_count += 1;
-
-                if let Ok(stats) = async_std::fs::metadata(entry.path()).await {
-                    let recently_created =
-                        stats.created().is_ok() && stats.created().unwrap() > keep_files_newer_than;
-                    let recently_modified = stats.modified().is_ok()
-                        && stats.modified().unwrap() > keep_files_newer_than;
-                    let recently_accessed = stats.accessed().is_ok()
-                        && stats.accessed().unwrap() > keep_files_newer_than;
-
-                    if recently_created || recently_modified || recently_accessed {
-                        info!(
-                            context,
-                            "Housekeeping: Keeping new unreferenced file #{}: {:?}",
-                            unreferenced_count,
-                            entry.file_name(),
-                        );
-                        continue;
-                    }
-                }
-                info!(
-                    context,
-                    "Housekeeping: Deleting unreferenced file #{}: {:?}",
-                    unreferenced_count,
-                    entry.file_name()
-                );
-                let path = entry.path();
-                dc_delete_file(context, path).await;
-            }
-        }
-        Err(err) => {
-            warn!(
-                context,
-                "Housekeeping: Cannot open {}. ({})",
-                context.get_blobdir().display(),
-                err
-            );
-        }
+/// Deletes the blob file at `path` named `name` if it is not in `files_in_use`.
+///
+/// Files that were created, modified or accessed during the last hour are kept,
+/// as they may be just created to build a message object.  `unreferenced_count` counts the
+/// unreferenced files for logging.  Returns true if the file was deleted.
+pub(crate) async fn delete_blob_if_unreferenced(
+    context: &Context,
+    files_in_use: &HashSet<String>,
+    path: &async_std::path::Path,
+    name: &str,
+    unreferenced_count: &mut usize,
+) -> bool {
+    if is_file_in_use(files_in_use, None, name)
+        || is_file_in_use(files_in_use, Some(".increation"), name)
+        || is_file_in_use(files_in_use, Some(".waveform"), name)
+        || is_file_in_use(files_in_use, Some("-preview.jpg"), name)
+    {
+        return false;
     }
 
-    if let Err(err) = start_ephemeral_timers(context).await {
-        warn!(
-            context,
-            "Housekeeping: cannot start ephemeral timers: {}", err
-        );
-    }
+    *unreferenced_count += 1;
 
-    if let Err(err) = prune_tombstones(context).await {
-        warn!(
-            context,
-            "Housekeeping: Cannot prune message tombstones: {}", err
-        );
-    }
+    /* avoid deletion of files that are just created to build a message object */
+    let diff = std::time::Duration::from_secs(60 * 60);
+    let keep_files_newer_than = std::time::SystemTime::now().checked_sub(diff).unwrap();
+    if let Ok(stats) = async_std::fs::metadata(path).await {
+        let recently_created =
+            stats.created().is_ok() && stats.created().unwrap() > keep_files_newer_than;
+        let recently_modified =
+            stats.modified().is_ok() && stats.modified().unwrap() > keep_files_newer_than;
+        let recently_accessed =
+            stats.accessed().is_ok() && stats.accessed().unwrap() > keep_files_newer_than;
 
-    if let Err(e) = context
-        .set_config(Config::LastHousekeeping, Some(&time().to_string()))
-        .await
-    {
-        warn!(context, "Can't set config: {}", e);
+        if recently_created || recently_modified || recently_accessed {
+            info!(
+                context,
+                "Housekeeping: Keeping new unreferenced file #{}: {:?}", unreferenced_count, name,
+            );
+            return false;
+        }
     }
-    info!(context, "Housekeeping done.");
-    Ok(())
+    info!(
+        context,
+        "Housekeeping: Deleting unreferenced file #{}: {:?}", unreferenced_count, name
+    );
+    dc_delete_file(context, path).await
 }
 
 #[allow(clippy::indexing_slicing)]
@@ -675,8 +1878,10 @@ async fn maybe_add_from_param(
             |rows| {
                 for row in rows {
                     let param: Params = row?.parse().unwrap_or_default();
-                    if let Some(file) = param.get(param_id) {
-                        maybe_add_file(files_in_use, file);
+                    if let Ok(Some(path)) = param.get_path(param_id, context) {
+                        if let Ok(name) = path.strip_prefix(context.get_blobdir()) {
+                            files_in_use.insert(name.to_string_lossy().into_owned());
+                        }
                     }
                 }
                 Ok(())
@@ -686,13 +1891,154 @@ async fn maybe_add_from_param(
         .context(format!("housekeeping: failed to add_from_param {}", query))
 }
 
+/// Header of SQLite database files.
+const SQLITE_HEADER: &[u8] = b"SQLite format 3\0";
+
+/// Format version in the headers of `-wal` and `-shm` files.
+const WAL_FORMAT_VERSION: u32 = 3_007_000;
+
+/// Returns the path of the `-wal` or `-shm` file belonging to `dbfile`.
+fn sidecar_path(dbfile: &Path, suffix: &str) -> PathBuf {
+    let mut path = dbfile.as_os_str().to_os_string();
+    path.push(suffix);
+    PathBuf::from(path)
+}
+
+/// Reads up to `len` bytes from the start of the file.
+fn read_header(path: &Path, len: u64) -> std::io::Result<Vec<u8>> {
+    use std::io::Read;
+
+    let mut header = Vec::new();
+    std::fs::File::open(path)?
+        .take(len)
+        .read_to_end(&mut header)?;
+    Ok(header)
+}
+
+fn read_u32_be(header: &[u8], offset: usize) -> Option<u32> {
+    match header.get(offset..offset + 4)? {
+        &[a, b, c, d] => Some(u32::from_be_bytes([a, b, c, d])),
+        _ => None,
+    }
+}
+
+/// Returns true if `header` is a write-ahead log header for pages of `page_size` bytes.
+fn is_valid_wal_header(header: &[u8], page_size: u32) -> bool {
+    matches!(
+        read_u32_be(header, 0),
+        Some(0x377f_0682) | Some(0x377f_0683)
+    ) && read_u32_be(header, 4) == Some(WAL_FORMAT_VERSION)
+        && read_u32_be(header, 8) == Some(page_size)
+}
+
+/// Returns true if `header` is a wal-index header.
+///
+/// The wal-index is only shared between processes on the same machine, so it is
+/// written in native byte order.
+fn is_valid_shm_header(header: &[u8]) -> bool {
+    match header.get(0..4) {
+        Some(&[a, b, c, d]) => u32::from_ne_bytes([a, b, c, d]) == WAL_FORMAT_VERSION,
+        _ => false,
+    }
+}
+
+/// Returns why the sidecar at `path` can not be used, if it exists and is not empty.
+fn sidecar_problem(path: &Path, is_valid: impl Fn(&[u8]) -> bool) -> Option<String> {
+    match read_header(path, 32) {
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => None,
+        Err(err) => Some(format!("cannot be read: {}", err)),
+        Ok(header) if header.is_empty() || is_valid(&header) => None,
+        Ok(_) => Some("has an invalid header".to_string()),
+    }
+}
+
+/// Checks the header of `dbfile` and returns its page size.
+///
+/// Returns `None` if the database does not exist yet and [`Error::SqlCorrupt`] if the
+/// file is not an SQLite database.
+fn check_db_header(dbfile: &Path) -> Result<Option<u32>> {
+    let header = match read_header(dbfile, 100) {
+        Ok(header) => header,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err.into()),
+    };
+    if header.is_empty() {
+        // New database, SQLite writes the header with the first table.
+        return Ok(None);
+    }
+    if header.get(..SQLITE_HEADER.len()) != Some(SQLITE_HEADER) {
+        return Err(Error::SqlCorrupt);
+    }
+    match header.get(16..18) {
+        Some(&[1, 0]) => Ok(Some(65536)),
+        Some(&[hi, lo]) => Ok(Some(u32::from(u16::from_be_bytes([hi, lo])))),
+        _ => Err(Error::SqlCorrupt),
+    }
+}
+
+/// Moves `-wal` and `-shm` files aside which SQLite can not use together with `dbfile`.
+///
+/// Restoring a device backup or copying the database between users may leave sidecar
+/// files of another installation, or files owned by another user, next to the database.
+/// SQLite then fails to open the database although the database file itself is fine.
+/// Such sidecars are renamed with a `.stale-<timestamp>` suffix; a `-shm` file is useless
+/// without its `-wal` file, so it is moved aside as well if there is no usable `-wal`.
+///
+/// Returns [`Error::SqlCorrupt`] if `dbfile` exists but is not an SQLite database.
+fn quarantine_stale_sidecars(
+    context: &Context,
+    dbfile: &Path,
+    warnings: &mut Warnings,
+) -> Result<()> {
+    let page_size = match check_db_header(dbfile)? {
+        Some(page_size) => page_size,
+        None => return Ok(()),
+    };
+
+    let wal = sidecar_path(dbfile, "-wal");
+    let shm = sidecar_path(dbfile, "-shm");
+    let wal_problem = sidecar_problem(&wal, |header| is_valid_wal_header(header, page_size));
+    let shm_problem = if wal_problem.is_some() || !wal.exists() {
+        if shm.exists() {
+            Some("has no usable -wal file".to_string())
+        } else {
+            None
+        }
+    } else {
+        sidecar_problem(&shm, is_valid_shm_header)
+    };
+
+    let now = time();
+    for (path, problem) in vec![(wal, wal_problem), (shm, shm_problem)] {
+        if let Some(problem) = problem {
+            let mut stale = path.clone().into_os_string();
+            stale.push(format!(".stale-{}", now));
+            std::fs::rename(&path, &stale)?;
+            let warning = format!(
+                "{} {}, moved it to {}",
+                path.display(),
+                problem,
+                Path::new(&stale).display()
+            );
+            warn!(context, "{}", warning);
+            warnings.push(warning);
+        }
+    }
+    Ok(())
+}
+
+/// Opens the database and migrates it to the current schema.
+///
+/// Failing migrations are fatal and abort opening the database.  Failures of the steps
+/// after the migration, which only refresh derived data, are returned as warnings.
 #[allow(clippy::cognitive_complexity)]
 async fn open(
     context: &Context,
     sql: &Sql,
     dbfile: impl AsRef<Path>,
     readonly: bool,
-) -> anyhow::Result<()> {
+) -> anyhow::Result<Warnings> {
+    let mut warnings = Warnings::new();
     if sql.is_open().await {
         error!(
             context,
@@ -702,6 +2048,13 @@ async fn open(
         return Err(Error::SqlAlreadyOpen.into());
     }
 
+    if readonly {
+        // Opening read-only must not modify any files, so only the header is checked.
+        check_db_header(dbfile.as_ref())?;
+    } else {
+        quarantine_stale_sidecars(context, dbfile.as_ref(), &mut warnings)?;
+    }
+
     let mut open_flags = OpenFlags::SQLITE_OPEN_NO_MUTEX;
     if readonly {
         open_flags.insert(OpenFlags::SQLITE_OPEN_READ_ONLY);
@@ -713,9 +2066,23 @@ async fn open(
     // this actually creates min_idle database handles just now.
     // therefore, with_init() must not try to modify the database as otherwise
     // we easily get busy-errors (eg. table-creation, journal_mode etc. should be done on only one handle)
+    let caches = Arc::clone(&sql.caches);
     let mgr = r2d2_sqlite::SqliteConnectionManager::file(dbfile.as_ref())
         .with_flags(open_flags)
-        .with_init(|c| {
+        .with_init(move |c| {
+            let caches = Arc::clone(&caches);
+            c.update_hook(Some(move |action, _db: &str, table: &str, rowid| {
+                caches.row_changed(action, table, rowid)
+            }));
+            c.create_scalar_function(
+                "search_fold",
+                1,
+                FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC,
+                |ctx| {
+                    let text = ctx.get::<Option<String>>(0)?;
+                    Ok(text.map(|text| crate::message::search_fold(&text)))
+                },
+            )?;
             c.execute_batch(&format!(
                 "PRAGMA secure_delete=on;
                  PRAGMA busy_timeout = {};
@@ -733,7 +2100,13 @@ async fn open(
         .map_err(Error::ConnectionPool)?;
 
     {
-        *sql.pool.write().await = Some(pool);
+        // Checked again while holding the lock, the database may have been opened
+        // concurrently while the pool was built.
+        let mut sql_pool = sql.pool.write().await;
+        if sql_pool.is_some() {
+            return Err(Error::SqlAlreadyOpen.into());
+        }
+        *sql_pool = Some(pool);
     }
 
     if !readonly {
@@ -956,6 +2329,8 @@ async fn open(
                 .await
                 .unwrap_or_default();
         }
+        // Large config values are converted by the migrations with the stored limit.
+        sql.load_config_value_limit(context).await;
 
         // (1) update low-level database structure.
         // this should be done before updates that use high-level objects that
@@ -1513,6 +2888,331 @@ async fn open(
             .await?;
             sql.set_raw_config_int(context, "dbversion", 76).await?;
         }
+        if dbversion < 77 {
+            info!(context, "[migration] v77");
+            sql.execute(
+                "CREATE TABLE peerstate_history (
+                   id INTEGER PRIMARY KEY AUTOINCREMENT,
+                   addr TEXT NOT NULL COLLATE NOCASE,
+                   timestamp INTEGER NOT NULL,
+                   key INTEGER NOT NULL,
+                   old_fingerprint TEXT,
+                   new_fingerprint TEXT);",
+                paramsv![],
+            )
+            .await?;
+            sql.execute(
+                "CREATE INDEX peerstate_history_index1 ON peerstate_history (addr, timestamp);",
+                paramsv![],
+            )
+            .await?;
+            sql.set_raw_config_int(context, "dbversion", 77).await?;
+        }
+        if dbversion < 78 {
+            info!(context, "[migration] v78");
+            sql.execute(
+                "ALTER TABLE contacts ADD COLUMN default_ephemeral_timer INTEGER DEFAULT 0;",
+                paramsv![],
+            )
+            .await?;
+            sql.set_raw_config_int(context, "dbversion", 78).await?;
+        }
+        if dbversion < 79 {
+            info!(context, "[migration] v79");
+            sql.execute(
+                "ALTER TABLE msgs ADD COLUMN quoted_msg_id INTEGER DEFAULT 0;",
+                paramsv![],
+            )
+            .await?;
+            sql.set_raw_config_int(context, "dbversion", 79).await?;
+        }
+        if dbversion < 80 {
+            info!(context, "[migration] v80");
+            // Bookkeeping for incremental backups: the time of the last change of messages,
+            // chats and contacts and the rows deleted since.  Nothing is tracked before the
+            // first backup and a row is only marked by its first change after the last
+            // backup, its time is in the `backup_time` raw config key.
+            sql.execute(
+                "CREATE TABLE backup_tombstones (
+                   tbl TEXT NOT NULL,
+                   row_id INTEGER NOT NULL,
+                   timestamp INTEGER NOT NULL);",
+                paramsv![],
+            )
+            .await?;
+            for table in &["msgs", "chats", "contacts"] {
+                sql.execute(
+                    format!(
+                        "ALTER TABLE {} ADD COLUMN update_timestamp INTEGER DEFAULT 0;",
+                        table
+                    ),
+                    paramsv![],
+                )
+                .await?;
+                sql.execute(
+                    format!(
+                        "CREATE TRIGGER {table}_backup_insert AFTER INSERT ON {table}
+                         WHEN EXISTS (SELECT 1 FROM config WHERE keyname='backup_time')
+                         BEGIN
+                           UPDATE {table} SET update_timestamp=strftime('%s','now') WHERE id=NEW.id;
+                         END;",
+                        table = table
+                    ),
+                    paramsv![],
+                )
+                .await?;
+                sql.execute(
+                    format!(
+                        "CREATE TRIGGER {table}_backup_update AFTER UPDATE ON {table}
+                         WHEN NEW.update_timestamp=OLD.update_timestamp
+                         AND OLD.update_timestamp<(SELECT CAST(value AS INTEGER) FROM config WHERE keyname='backup_time')
+                         BEGIN
+                           UPDATE {table} SET update_timestamp=strftime('%s','now') WHERE id=NEW.id;
+                         END;",
+                        table = table
+                    ),
+                    paramsv![],
+                )
+                .await?;
+                sql.execute(
+                    format!(
+                        "CREATE TRIGGER {table}_backup_delete AFTER DELETE ON {table}
+                         WHEN EXISTS (SELECT 1 FROM config WHERE keyname='backup_time')
+                         BEGIN
+                           INSERT INTO backup_tombstones (tbl, row_id, timestamp)
+                           VALUES ('{table}', OLD.id, strftime('%s','now'));
+                         END;",
+                        table = table
+                    ),
+                    paramsv![],
+                )
+                .await?;
+            }
+            // Changed chat members mark the chat as changed.
+            sql.execute(
+                "CREATE TRIGGER chats_contacts_backup_insert AFTER INSERT ON chats_contacts
+                 WHEN (SELECT update_timestamp FROM chats WHERE id=NEW.chat_id)
+                      <(SELECT CAST(value AS INTEGER) FROM config WHERE keyname='backup_time')
+                 BEGIN
+                   UPDATE chats SET update_timestamp=strftime('%s','now') WHERE id=NEW.chat_id;
+                 END;",
+                paramsv![],
+            )
+            .await?;
+            sql.execute(
+                "CREATE TRIGGER chats_contacts_backup_delete AFTER DELETE ON chats_contacts
+                 WHEN (SELECT update_timestamp FROM chats WHERE id=OLD.chat_id)
+                      <(SELECT CAST(value AS INTEGER) FROM config WHERE keyname='backup_time')
+                 BEGIN
+                   UPDATE chats SET update_timestamp=strftime('%s','now') WHERE id=OLD.chat_id;
+                 END;",
+                paramsv![],
+            )
+            .await?;
+            sql.set_raw_config_int(context, "dbversion", 80).await?;
+        }
+        if dbversion < 81 {
+            info!(context, "[migration] v81");
+            // Serves the correlated subquery selecting the last message of each chat in
+            // `chatlist_query()`, which otherwise sorts all messages of every chat in a
+            // temporary b-tree.  `id` is the rowid and thus part of the index already.
+            // Neither `hidden` nor `DESC` are part of the index: the subquery filters
+            // `hidden` with an OR, so a `hidden` column between `chat_id` and `timestamp`
+            // would prevent using the index for ordering.
+            // There is no index over `chats.muted_until`, all queries reading it look up
+            // chats by id.
+            sql.execute(
+                "CREATE INDEX msgs_index8 ON msgs (chat_id, timestamp);",
+                paramsv![],
+            )
+            .await?;
+            sql.set_raw_config_int(context, "dbversion", 81).await?;
+        }
+        if dbversion < 82 {
+            info!(context, "[migration] v82");
+            sql.execute(
+                "ALTER TABLE msgs ADD COLUMN download_state INTEGER DEFAULT 0;",
+                paramsv![],
+            )
+            .await?;
+            sql.set_raw_config_int(context, "dbversion", 82).await?;
+        }
+        if dbversion < 83 {
+            info!(context, "[migration] v83");
+            sql.execute(
+                "CREATE TABLE msgs_broadcast (
+                   msg_id INTEGER NOT NULL,
+                   contact_id INTEGER NOT NULL,
+                   state INTEGER NOT NULL,
+                   UNIQUE(msg_id, contact_id));",
+                paramsv![],
+            )
+            .await?;
+            sql.set_raw_config_int(context, "dbversion", 83).await?;
+        }
+        if dbversion < 84 {
+            info!(context, "[migration] v84");
+            sql.execute(
+                "CREATE TABLE reactions (
+                   msg_id INTEGER NOT NULL,
+                   contact_id INTEGER NOT NULL,
+                   reaction TEXT NOT NULL,
+                   UNIQUE(msg_id, contact_id));",
+                paramsv![],
+            )
+            .await?;
+            sql.set_raw_config_int(context, "dbversion", 84).await?;
+        }
+        if dbversion < 85 {
+            info!(context, "[migration] v85");
+            sql.execute(
+                "CREATE TABLE ui_config (
+                   id INTEGER PRIMARY KEY,
+                   keyname TEXT NOT NULL UNIQUE,
+                   value TEXT NOT NULL);",
+                paramsv![],
+            )
+            .await?;
+            sql.set_raw_config_int(context, "dbversion", 85).await?;
+        }
+        if dbversion < 86 {
+            info!(context, "[migration] v86");
+            // Existing mailing lists are adopted when the next post arrives,
+            // see `dc_receive_imf::lookup_mailinglist()`.
+            sql.execute(
+                "ALTER TABLE chats ADD COLUMN list_id TEXT DEFAULT '';",
+                paramsv![],
+            )
+            .await?;
+            sql.execute("CREATE INDEX chats_index4 ON chats (list_id);", paramsv![])
+                .await?;
+            sql.set_raw_config_int(context, "dbversion", 86).await?;
+        }
+        if dbversion < 87 {
+            info!(context, "[migration] v87");
+            sql.execute(
+                "CREATE TABLE msgs_status_updates (
+                   id INTEGER PRIMARY KEY AUTOINCREMENT,
+                   msg_id INTEGER NOT NULL,
+                   update_item TEXT NOT NULL);",
+                paramsv![],
+            )
+            .await?;
+            sql.execute(
+                "CREATE INDEX msgs_status_updates_index1 ON msgs_status_updates (msg_id);",
+                paramsv![],
+            )
+            .await?;
+            sql.set_raw_config_int(context, "dbversion", 87).await?;
+        }
+        if dbversion < 88 {
+            info!(context, "[migration] v88");
+            sql.execute(
+                "CREATE TABLE msgs_chunks (
+                   msg_id INTEGER NOT NULL,
+                   part INTEGER NOT NULL,
+                   file TEXT NOT NULL,
+                   UNIQUE(msg_id, part));",
+                paramsv![],
+            )
+            .await?;
+            sql.set_raw_config_int(context, "dbversion", 88).await?;
+        }
+        if dbversion < 89 {
+            info!(context, "[migration] v89");
+            sql.execute(
+                "ALTER TABLE msgs ADD COLUMN deleted_locally INTEGER DEFAULT 0;",
+                paramsv![],
+            )
+            .await?;
+            sql.set_raw_config_int(context, "dbversion", 89).await?;
+        }
+        if dbversion < 90 {
+            info!(context, "[migration] v90");
+            sql.execute(
+                "ALTER TABLE msgs ADD COLUMN deleted_timestamp INTEGER DEFAULT 0;",
+                paramsv![],
+            )
+            .await?;
+            sql.set_raw_config_int(context, "dbversion", 90).await?;
+        }
+        if dbversion < 91 {
+            info!(context, "[migration] v91");
+            // Serves the lookup of the last message received from a contact
+            // in `Contact::last_seen()`.
+            sql.execute(
+                "CREATE INDEX msgs_index9 ON msgs (from_id, timestamp);",
+                paramsv![],
+            )
+            .await?;
+            sql.set_raw_config_int(context, "dbversion", 91).await?;
+        }
+        if dbversion < 92 {
+            info!(context, "[migration] v92");
+            // Only touches tables complete at this version, the version is only recorded
+            // after the addresses are normalized, so a failure is retried on the next open.
+            contact::normalize_stored_addrs(context).await?;
+            sql.set_raw_config_int(context, "dbversion", 92).await?;
+        }
+        if dbversion < 93 {
+            info!(context, "[migration] v93");
+            // Only touches the config table, the version is only recorded after the values
+            // are moved, so a failure is retried on the next open.
+            convert_large_config_values(context).await?;
+            sql.set_raw_config_int(context, "dbversion", 93).await?;
+        }
+        if dbversion < 94 {
+            info!(context, "[migration] v94");
+            // Status updates are ordered by the Date and the Message-ID of the messages
+            // carrying them, older updates keep the order they arrived in.
+            sql.execute(
+                "ALTER TABLE msgs_status_updates ADD COLUMN timestamp INTEGER NOT NULL DEFAULT 0;",
+                paramsv![],
+            )
+            .await?;
+            sql.execute(
+                "ALTER TABLE msgs_status_updates ADD COLUMN uid TEXT NOT NULL DEFAULT '';",
+                paramsv![],
+            )
+            .await?;
+            sql.execute(
+                "CREATE INDEX msgs_status_updates_index2 ON msgs_status_updates (uid);",
+                paramsv![],
+            )
+            .await?;
+            sql.set_raw_config_int(context, "dbversion", 94).await?;
+        }
+        if dbversion < 95 {
+            info!(context, "[migration] v95");
+            // Marks the messages the user deleted to be deleted on the server as well,
+            // other trashed messages stay there.
+            sql.execute(
+                "ALTER TABLE msgs ADD COLUMN delete_on_server INTEGER DEFAULT 0;",
+                paramsv![],
+            )
+            .await?;
+            sql.set_raw_config_int(context, "dbversion", 95).await?;
+        }
+        if dbversion < 96 {
+            info!(context, "[migration] v96");
+            // The folded text is searched instead of folding the text of every message
+            // on each search.
+            sql.execute(
+                "ALTER TABLE msgs ADD COLUMN txt_folded TEXT DEFAULT '';",
+                paramsv![],
+            )
+            .await?;
+            sql.execute(
+                "UPDATE msgs SET txt_folded=search_fold(txt) WHERE txt!='';",
+                paramsv![],
+            )
+            .await?;
+            sql.set_raw_config_int(context, "dbversion", 96).await?;
+        }
+
+        // Rows rewritten by the migrations are not necessarily reported row by row,
+        // e.g. if a table is copied.
+        sql.clear_caches();
 
         // (2) updates that require high-level objects
         // (the structure is complete now and all objects are usable)
@@ -1539,9 +3239,26 @@ async fn open(
                 }
             }
         }
-        if update_icons {
-            update_saved_messages_icon(context).await?;
-            update_device_icon(context).await?;
+        let icons_version = sql
+            .get_raw_config_int(context, DEVICE_ICONS_VERSION_KEY)
+            .await
+            .unwrap_or_default();
+        if update_icons || icons_version < DEVICE_ICONS_VERSION {
+            // Icons are only cosmetic, so failing to update them must not prevent using
+            // the database. The version is only recorded on success to retry on the next open.
+            let mut updated = true;
+            if let Err(err) = update_saved_messages_icon(context).await {
+                warnings.push(format!("Failed to update saved messages icon: {:#}", err));
+                updated = false;
+            }
+            if let Err(err) = update_device_icon(context).await {
+                warnings.push(format!("Failed to update device icon: {:#}", err));
+                updated = false;
+            }
+            if updated {
+                sql.set_raw_config_int(context, DEVICE_ICONS_VERSION_KEY, DEVICE_ICONS_VERSION)
+                    .await?;
+            }
         }
         if disable_server_delete {
             // We now always watch all folders and delete messages there if delete_server is enabled.
@@ -1555,39 +3272,177 @@ async fn open(
         }
     }
 
+    sql.update_cache_size(context).await;
+
     info!(context, "Opened {:?}.", dbfile.as_ref(),);
 
-    Ok(())
+    Ok(warnings)
+}
+
+/// Moves config values larger than [`Sql::config_value_limit`] to the blob directory,
+/// as if they were stored with [`Sql::set_raw_config_blob`].
+///
+/// The values read the same as before, [`Sql::get_raw_config`] resolves config blobs.
+/// Returns the number of converted values.
+pub(crate) async fn convert_large_config_values(context: &Context) -> Result<usize> {
+    let keys = context
+        .sql
+        .query_map(
+            "SELECT keyname FROM config WHERE length(CAST(value AS BLOB))>?;",
+            paramsv![context.sql.config_value_limit() as i64],
+            |row| row.get::<_, String>(0),
+            |rows| {
+                rows.collect::<std::result::Result<Vec<_>, _>>()
+                    .map_err(Into::into)
+            },
+        )
+        .await?;
+    for key in &keys {
+        if let Some(value) = context.sql.query_raw_config(key).await? {
+            info!(
+                context,
+                "Moving config value {} ({} bytes) to the blob directory.",
+                key,
+                value.len()
+            );
+            context
+                .sql
+                .set_raw_config_blob(context, key, value.as_bytes())
+                .await?;
+        }
+    }
+    Ok(keys.len())
 }
 
-/// Removes from the database locally deleted messages that also don't
-/// have a server UID.
+/// Time in seconds the tombstones of messages deleted by `delete_device_after` are kept
+/// if `delete_server_after` is not set, see [prune_tombstones].
+pub(crate) const DELETED_LOCALLY_TOMBSTONE_LIFETIME: i64 = 90 * 24 * 60 * 60;
+
+/// Removes the tombstones of deleted messages that are not needed anymore.
+///
+/// Tombstones of messages not on the server are removed, unless the message was deleted
+/// within [Config::TrashGracePeriod] and can still be restored.
+///
+/// Tombstones of messages deleted by `delete_device_after` keep their server UID,
+/// the message may still be on the server and would be downloaded again without the
+/// tombstone, e.g. after it was moved to another folder.  If `delete_server_after` is set,
+/// they are removed when the message is deleted on the server, otherwise
+/// [DELETED_LOCALLY_TOMBSTONE_LIFETIME] after the deletion.
 async fn prune_tombstones(context: &Context) -> Result<()> {
+    purge_trash(context).await?;
     context
         .sql
         .execute(
             "DELETE FROM msgs \
          WHERE (chat_id = ? OR hidden) \
-         AND server_uid = 0",
-            paramsv![DC_CHAT_ID_TRASH],
+         AND ((server_uid = 0 AND NOT deleted_locally AND deleted_timestamp <= ?) \
+         OR (deleted_locally AND deleted_timestamp <= ?))",
+            paramsv![
+                DC_CHAT_ID_TRASH,
+                trash_grace_cutoff(context).await,
+                deleted_locally_cutoff(context).await
+            ],
         )
         .await?;
     Ok(())
 }
 
-#[cfg(test)]
-mod test {
-    use async_std::fs::File;
+/// Returns the deletion timestamp up to which the tombstones of messages deleted by
+/// `delete_device_after` are removed, see [prune_tombstones].
+async fn deleted_locally_cutoff(context: &Context) -> i64 {
+    match context.get_config_delete_server_after().await {
+        // The tombstones are removed when the messages are deleted on the server.
+        Some(_) => -1,
+        None => time() - DELETED_LOCALLY_TOMBSTONE_LIFETIME,
+    }
+}
 
-    use super::*;
-    use crate::{test_utils::TestContext, Event, EventType};
+/// Returns the deletion timestamp up to which trashed messages are past the grace period,
+/// see [Config::TrashGracePeriod].
+pub(crate) async fn trash_grace_cutoff(context: &Context) -> i64 {
+    time()
+        - context
+            .get_config_i64(Config::TrashGracePeriod)
+            .await
+            .max(0)
+}
 
-    #[test]
-    fn test_maybe_add_file() {
-        let mut files = Default::default();
-        maybe_add_file(&mut files, "$BLOBDIR/hello");
-        maybe_add_file(&mut files, "$BLOBDIR/world.txt");
-        maybe_add_file(&mut files, "world2.txt");
+/// Removes the content of trashed messages whose grace period is over,
+/// they cannot be restored anymore.
+///
+/// Returns the number of purged messages.
+pub(crate) async fn purge_trash(context: &Context) -> Result<usize> {
+    purge_trash_limited(context, usize::MAX).await
+}
+
+/// Like [purge_trash], but purges at most `limit` messages.
+pub(crate) async fn purge_trash_limited(context: &Context, limit: usize) -> Result<usize> {
+    let purged = context
+        .sql
+        .execute(
+            "UPDATE msgs \
+             SET txt='', txt_folded='', subject='', txt_raw='', mime_headers='', from_id=0, to_id=0, param='' \
+             WHERE id IN (\
+             SELECT id FROM msgs \
+             WHERE chat_id=? AND deleted_timestamp>0 AND deleted_timestamp<=? AND param!='' \
+             LIMIT ?)",
+            paramsv![
+                DC_CHAT_ID_TRASH,
+                trash_grace_cutoff(context).await,
+                i64::try_from(limit).unwrap_or(i64::MAX)
+            ],
+        )
+        .await?;
+    if purged > 0 {
+        info!(context, "Purged {} trashed messages.", purged);
+        if context
+            .get_config_bool(Config::DeleteOnServerWhenDeletingLocally)
+            .await
+        {
+            // Deleting these messages on the server was postponed until now.
+            imap::cleanup::schedule_trashed(context).await;
+        }
+    }
+    Ok(purged)
+}
+
+/// Removes at most `limit` tombstones, see [prune_tombstones].
+///
+/// Returns the number of removed tombstones, if it is less than `limit`, none are left.
+pub(crate) async fn prune_tombstones_batch(context: &Context, limit: usize) -> Result<usize> {
+    let deleted = context
+        .sql
+        .execute(
+            "DELETE FROM msgs WHERE id IN (\
+             SELECT id FROM msgs \
+             WHERE (chat_id = ? OR hidden) \
+             AND ((server_uid = 0 AND NOT deleted_locally AND deleted_timestamp <= ?) \
+             OR (deleted_locally AND deleted_timestamp <= ?)) \
+             LIMIT ?)",
+            paramsv![
+                DC_CHAT_ID_TRASH,
+                trash_grace_cutoff(context).await,
+                deleted_locally_cutoff(context).await,
+                limit as i64
+            ],
+        )
+        .await?;
+    Ok(deleted)
+}
+
+#[cfg(test)]
+mod test {
+    use async_std::fs::File;
+
+    use super::*;
+    use crate::{test_utils::TestContext, Event, EventType};
+
+    #[test]
+    fn test_maybe_add_file() {
+        let mut files = Default::default();
+        maybe_add_file(&mut files, "$BLOBDIR/hello");
+        maybe_add_file(&mut files, "$BLOBDIR/world.txt");
+        maybe_add_file(&mut files, "world2.txt");
         maybe_add_file(&mut files, "$BLOBDIR");
 
         assert!(files.contains("hello"));
@@ -1608,6 +3463,283 @@ fn test_is_file_in_use() {
         assert!(is_file_in_use(&files, Some("-suffix"), "world.txt-suffix"));
     }
 
+    #[async_std::test]
+    async fn test_query_get_value_opt() {
+        let t = TestContext::new().await;
+        let query = "SELECT value FROM config WHERE keyname=?;";
+        t.sql
+            .execute(
+                "INSERT INTO config (keyname, value) VALUES ('null', NULL);",
+                paramsv![],
+            )
+            .await
+            .unwrap();
+        t.sql.set_raw_config(&t, "text", Some("x")).await.unwrap();
+
+        for (key, expected) in vec![
+            ("missing", None),
+            ("null", Some(None)),
+            ("text", Some(Some("x".to_string()))),
+        ] {
+            let value = t
+                .sql
+                .query_get_value_opt::<String>(query, paramsv![key])
+                .await
+                .unwrap();
+            assert_eq!(value, expected);
+            let value = t
+                .sql
+                .query_get_value_result::<String>(query, paramsv![key])
+                .await
+                .unwrap();
+            assert_eq!(value, expected.flatten());
+        }
+    }
+
+    #[async_std::test]
+    async fn test_raw_config_missing_vs_cleared() {
+        let t = TestContext::new().await;
+        for (key, value) in &[("str", "foo"), ("int", "42"), ("bool", "1")] {
+            t.sql.set_raw_config(&t, key, Some(*value)).await.unwrap();
+            t.sql
+                .execute(
+                    "INSERT INTO config (keyname, value) VALUES (?, NULL);",
+                    paramsv![format!("{}_null", key)],
+                )
+                .await
+                .unwrap();
+            t.sql
+                .set_raw_config(&t, format!("{}_empty", key), Some(""))
+                .await
+                .unwrap();
+        }
+
+        assert_eq!(t.sql.get_raw_config(&t, "str").await.unwrap(), "foo");
+        assert_eq!(t.sql.get_raw_config(&t, "str_missing").await, None);
+        assert_eq!(t.sql.get_raw_config(&t, "str_null").await.unwrap(), "");
+        assert_eq!(t.sql.get_raw_config(&t, "str_empty").await.unwrap(), "");
+
+        assert_eq!(t.sql.get_raw_config_int(&t, "int").await, Some(42));
+        assert_eq!(t.sql.get_raw_config_int(&t, "int_missing").await, None);
+        assert_eq!(t.sql.get_raw_config_int(&t, "int_null").await, Some(0));
+        assert_eq!(t.sql.get_raw_config_int(&t, "int_empty").await, Some(0));
+        assert_eq!(t.sql.get_raw_config_int64(&t, "int").await, Some(42));
+        assert_eq!(t.sql.get_raw_config_int64(&t, "int_missing").await, None);
+        assert_eq!(t.sql.get_raw_config_int64(&t, "int_null").await, Some(0));
+        assert_eq!(t.sql.get_raw_config_int64(&t, "int_empty").await, Some(0));
+        assert_eq!(t.sql.get_raw_config_int(&t, "str").await, None);
+
+        assert!(t.sql.get_raw_config_bool(&t, "bool").await);
+        assert!(!t.sql.get_raw_config_bool(&t, "bool_missing").await);
+        assert!(!t.sql.get_raw_config_bool(&t, "bool_null").await);
+        assert!(!t.sql.get_raw_config_bool(&t, "bool_empty").await);
+
+        // Deleting the key makes it missing again.
+        t.sql.set_raw_config(&t, "str_empty", None).await.unwrap();
+        assert_eq!(t.sql.get_raw_config(&t, "str_empty").await, None);
+
+        let batch = t
+            .sql
+            .get_raw_config_batch(&["str_null".to_string(), "str_missing".to_string()])
+            .await
+            .unwrap();
+        assert_eq!(batch.get("str_null").unwrap(), "");
+        assert!(!batch.contains_key("str_missing"));
+    }
+
+    #[async_std::test]
+    async fn test_transaction() {
+        let t = TestContext::new().await;
+
+        // The callback may borrow locals.
+        let keys = vec!["a".to_string(), "b".to_string()];
+        let count = t
+            .sql
+            .transaction(|tx| {
+                for key in &keys {
+                    tx.execute(
+                        "INSERT INTO config (keyname, value) VALUES (?, ?);",
+                        params![key, "1"],
+                    )?;
+                }
+                Ok(keys.len())
+            })
+            .await
+            .unwrap();
+        assert_eq!(count, 2);
+        assert_eq!(t.sql.get_raw_config_int(&t, "a").await, Some(1));
+        assert_eq!(t.sql.get_raw_config_int(&t, "b").await, Some(1));
+
+        // Returning an error rolls back all changes.
+        let res: Result<()> = t
+            .sql
+            .transaction(|tx| {
+                tx.execute("DELETE FROM config WHERE keyname=?;", params!["a"])?;
+                tx.execute("UPDATE config SET value=2 WHERE keyname=?;", params!["b"])?;
+                tx.execute("INSERT INTO nonexistent_table VALUES (1);", params![])?;
+                Ok(())
+            })
+            .await;
+        assert!(res.is_err());
+        assert_eq!(t.sql.get_raw_config_int(&t, "a").await, Some(1));
+        assert_eq!(t.sql.get_raw_config_int(&t, "b").await, Some(1));
+    }
+
+    #[async_std::test]
+    async fn test_raw_config_closed_vs_empty_key() {
+        let t = TestContext::new().await;
+        assert!(matches!(
+            t.sql.try_get_raw_config("").await,
+            Err(Error::InvalidArgument(_))
+        ));
+        assert!(matches!(
+            t.sql.set_raw_config(&t, "", Some("value")).await,
+            Err(Error::InvalidArgument(_))
+        ));
+        assert_eq!(t.sql.try_get_raw_config("foo").await.unwrap(), None);
+
+        t.sql.close().await;
+        assert!(matches!(
+            t.sql.try_get_raw_config("foo").await,
+            Err(Error::SqlNoConnection)
+        ));
+        assert!(matches!(
+            t.sql.try_get_raw_config("").await,
+            Err(Error::InvalidArgument(_))
+        ));
+        assert!(matches!(
+            t.sql.set_raw_config(&t, "foo", Some("value")).await,
+            Err(Error::SqlNoConnection)
+        ));
+        assert_eq!(t.sql.get_raw_config(&t, "foo").await, None);
+    }
+
+    #[async_std::test]
+    async fn test_set_raw_config_concurrent_close() {
+        let t = TestContext::new().await;
+        let writes = async {
+            let mut errors = Vec::new();
+            for i in 0..100 {
+                let res = t
+                    .sql
+                    .set_raw_config(&t, format!("key{}", i), Some("value"))
+                    .await;
+                if let Err(err) = res {
+                    errors.push(err);
+                }
+                async_std::task::yield_now().await;
+            }
+            errors
+        };
+        let close = async {
+            for _ in 0..10 {
+                async_std::task::yield_now().await;
+            }
+            t.sql.close().await;
+        };
+        let (errors, ()) = futures::future::join(writes, close).await;
+        assert!(!errors.is_empty());
+        assert!(errors.len() < 100);
+        for err in errors {
+            assert!(matches!(err, Error::SqlNoConnection), "{:?}", err);
+        }
+    }
+
+    #[async_std::test]
+    async fn test_execute_many_bind() {
+        let t = TestContext::new().await;
+        t.sql
+            .execute(
+                "CREATE TABLE bulk (i INTEGER, r REAL, t TEXT, b BLOB);",
+                paramsv![],
+            )
+            .await
+            .unwrap();
+
+        let rows: Vec<Vec<Value>> = (0..10_000)
+            .map(|i: i64| {
+                vec![
+                    i.into(),
+                    (i as f64 / 2.0).into(),
+                    format!("row {}", i).into(),
+                    if i % 2 == 0 {
+                        Value::Blob(i.to_be_bytes().to_vec())
+                    } else {
+                        Value::Null
+                    },
+                ]
+            })
+            .collect();
+        let (results, transactions) = t
+            .sql
+            .execute_many_bind_chunked(
+                "INSERT INTO bulk (i, r, t, b) VALUES (?, ?, ?, ?);",
+                rows,
+                EXECUTE_MANY_CHUNK_SIZE,
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            results.iter().map(|(changes, _)| changes).sum::<usize>(),
+            10_000
+        );
+        assert_eq!(transactions, 20);
+
+        let (count, sum, nulls): (i64, i64, i64) = t
+            .sql
+            .query_row(
+                "SELECT COUNT(*), SUM(i), SUM(b IS NULL) FROM bulk;",
+                paramsv![],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )
+            .await
+            .unwrap();
+        assert_eq!(count, 10_000);
+        assert_eq!(sum, (0..10_000).sum::<i64>());
+        assert_eq!(nulls, 5_000);
+        let (r, text): (f64, String) = t
+            .sql
+            .query_row("SELECT r, t FROM bulk WHERE i=4321;", paramsv![], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })
+            .await
+            .unwrap();
+        assert!((r - 2160.5).abs() < f64::EPSILON);
+        assert_eq!(text, "row 4321");
+
+        let changes = t
+            .sql
+            .execute_many_bind(
+                "UPDATE bulk SET t='' WHERE i<?;",
+                vec![vec![10i64.into()], vec![20i64.into()]],
+            )
+            .await
+            .unwrap();
+        assert_eq!(changes, 30);
+
+        let ids = t
+            .sql
+            .insert_many_bind(
+                "INSERT INTO bulk (i) SELECT ? WHERE ? NOT IN (SELECT i FROM bulk);",
+                vec![
+                    vec![5i64.into(), 5i64.into()],
+                    vec![-1i64.into(), (-1i64).into()],
+                ],
+            )
+            .await
+            .unwrap();
+        assert_eq!(ids.len(), 2);
+        assert_eq!(ids.get(0), Some(&None));
+        let rowid = ids.get(1).unwrap().unwrap();
+        let i: i64 = t
+            .sql
+            .query_get_value_result("SELECT i FROM bulk WHERE rowid=?;", paramsv![rowid])
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(i, -1);
+    }
+
     #[async_std::test]
     async fn test_table_exists() {
         let t = TestContext::new().await;
@@ -1615,6 +3747,40 @@ async fn test_table_exists() {
         assert!(!t.ctx.sql.table_exists("foobar").await.unwrap());
     }
 
+    #[async_std::test]
+    async fn test_backup_to() {
+        let t = TestContext::new().await;
+        t.set_config(Config::Displayname, Some("snapshot"))
+            .await
+            .unwrap();
+        let snapshot = t.dir.path().join("snapshot.sqlite");
+        t.sql.backup_to(&snapshot).await.unwrap();
+        assert!(t.sql.is_open().await);
+
+        let conn =
+            Connection::open_with_flags(&snapshot, OpenFlags::SQLITE_OPEN_READ_ONLY).unwrap();
+        let displayname: String = conn
+            .query_row(
+                "SELECT value FROM config WHERE keyname='displayname';",
+                rusqlite::NO_PARAMS,
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(displayname, "snapshot");
+
+        // The snapshot must not overwrite existing files.
+        assert!(t.sql.backup_to(&snapshot).await.is_err());
+    }
+
+    #[async_std::test]
+    async fn test_dbversion() {
+        let t = TestContext::new().await;
+        assert_eq!(
+            t.sql.get_raw_config_int(&t, "dbversion").await,
+            Some(DBVERSION)
+        );
+    }
+
     #[async_std::test]
     async fn test_col_exists() {
         let t = TestContext::new().await;
@@ -1623,6 +3789,131 @@ async fn test_col_exists() {
         assert!(!t.ctx.sql.col_exists("foobar", "foobar").await.unwrap());
     }
 
+    #[async_std::test]
+    async fn test_config_value_limit() {
+        let t = TestContext::new().await;
+        let large = "x".repeat(DEFAULT_CONFIG_VALUE_LIMIT + 1);
+        assert!(matches!(
+            t.sql.set_raw_config(&t, "foo", Some(&large)).await,
+            Err(Error::ConfigValueTooLarge { size, limit, .. })
+                if size == large.len() && limit == DEFAULT_CONFIG_VALUE_LIMIT
+        ));
+        assert!(matches!(
+            t.sql
+                .set_raw_config_batch(&t, vec![("foo".to_string(), Some(large.clone()))])
+                .await,
+            Err(Error::ConfigValueTooLarge { .. })
+        ));
+        assert_eq!(t.sql.get_raw_config(&t, "foo").await, None);
+
+        t.sql.set_config_value_limit(&t, large.len()).await.unwrap();
+        t.sql.set_raw_config(&t, "foo", Some(&large)).await.unwrap();
+        assert_eq!(t.sql.get_raw_config(&t, "foo").await, Some(large.clone()));
+
+        // The limit is kept when the database is opened again.
+        t.sql.close().await;
+        t.sql.open(&t, t.get_dbfile(), false).await.unwrap();
+        assert_eq!(t.sql.config_value_limit(), large.len());
+        t.sql.set_raw_config(&t, "bar", Some(&large)).await.unwrap();
+    }
+
+    #[async_std::test]
+    async fn test_raw_config_blob() {
+        let t = TestContext::new().await;
+        assert_eq!(t.sql.get_raw_config_blob("foo").await.unwrap(), None);
+
+        let data = vec![b'7'; DEFAULT_CONFIG_VALUE_LIMIT * 2];
+        t.sql.set_raw_config_blob(&t, "foo", &data).await.unwrap();
+        assert_eq!(
+            t.sql.get_raw_config_blob("foo").await.unwrap(),
+            Some(data.clone())
+        );
+        assert_eq!(
+            t.sql
+                .get_raw_config(&t, "foo")
+                .await
+                .map(String::into_bytes),
+            Some(data)
+        );
+        let name = t.sql.query_raw_config("foo").await.unwrap().unwrap();
+        let file = name.strip_prefix(CONFIG_BLOB_PREFIX).unwrap().to_string();
+        assert!(get_files_in_use(&t).await.unwrap().contains(&file));
+
+        // Replacing the value deletes the old file.
+        t.sql.set_raw_config_blob(&t, "foo", b"bar").await.unwrap();
+        assert_eq!(
+            t.sql.get_raw_config_blob("foo").await.unwrap(),
+            Some(b"bar".to_vec())
+        );
+        assert!(!t.get_blobdir().join(&file).exists().await);
+
+        // Plain values are returned as they are.
+        t.sql.set_raw_config(&t, "baz", Some("qux")).await.unwrap();
+        assert_eq!(
+            t.sql.get_raw_config_blob("baz").await.unwrap(),
+            Some(b"qux".to_vec())
+        );
+
+        // Other values referencing blobs are never taken for config blobs.
+        let avatar = BlobObject::create(&t, "avatar.png", b"avatar")
+            .await
+            .unwrap();
+        t.sql
+            .set_raw_config(&t, "selfavatar", Some(avatar.as_name()))
+            .await
+            .unwrap();
+        t.sql
+            .set_raw_config_blob(&t, "selfavatar", b"data")
+            .await
+            .unwrap();
+        assert!(avatar.to_abs_path().exists().await);
+        assert!(matches!(
+            t.sql
+                .set_raw_config(&t, "foo", Some(&format!("{}{}", CONFIG_BLOB_PREFIX, file)))
+                .await,
+            Err(Error::InvalidArgument(_))
+        ));
+    }
+
+    #[async_std::test]
+    async fn test_convert_large_config_values() {
+        let t = TestContext::new().await;
+        let large = "y".repeat(DEFAULT_CONFIG_VALUE_LIMIT + 1);
+        t.sql
+            .execute(
+                "INSERT INTO config (keyname, value) VALUES (?, ?);",
+                paramsv!["theme", large],
+            )
+            .await
+            .unwrap();
+        t.sql
+            .set_raw_config(&t, "small", Some("value"))
+            .await
+            .unwrap();
+
+        assert_eq!(convert_large_config_values(&t).await.unwrap(), 1);
+        let name = t.sql.query_raw_config("theme").await.unwrap().unwrap();
+        assert!(name.starts_with(CONFIG_BLOB_PREFIX));
+        assert_eq!(t.sql.get_raw_config(&t, "theme").await, Some(large.clone()));
+        assert_eq!(
+            t.sql
+                .get_raw_config_batch(&["theme".to_string()])
+                .await
+                .unwrap()
+                .get("theme"),
+            Some(&large)
+        );
+        assert_eq!(
+            t.sql.get_raw_config_blob("theme").await.unwrap(),
+            Some(large.into_bytes())
+        );
+        assert_eq!(
+            t.sql.get_raw_config(&t, "small").await,
+            Some("value".to_string())
+        );
+        assert_eq!(convert_large_config_values(&t).await.unwrap(), 0);
+    }
+
     #[async_std::test]
     async fn test_housekeeping_db_closed() {
         let t = TestContext::new().await;
@@ -1662,4 +3953,441 @@ async fn test_housekeeping_db_closed() {
         let a = t.get_config(Config::Selfavatar).await.unwrap();
         assert_eq!(avatar_bytes, &async_std::fs::read(&a).await.unwrap()[..]);
     }
+
+    #[async_std::test]
+    async fn test_housekeeping_warnings() {
+        let t = TestContext::new().await;
+        let report = housekeeping(&t).await.unwrap();
+        assert!(report.warnings.is_empty());
+
+        // Replace the blobdir with a file, housekeeping should still succeed but report it.
+        let blobdir = t.get_blobdir().to_path_buf();
+        async_std::fs::remove_dir_all(&blobdir).await.unwrap();
+        async_std::fs::write(&blobdir, b"not a directory")
+            .await
+            .unwrap();
+        let report = housekeeping(&t).await.unwrap();
+        assert_eq!(report.warnings.len(), 1);
+        assert!(report
+            .warnings
+            .iter()
+            .any(|warning| warning.contains("Cannot open")));
+        assert!(t.get_config(Config::LastHousekeeping).await.is_some());
+    }
+
+    /// Overwrites the header of the root page of the `msgs` table.
+    async fn corrupt_msgs_table(t: &TestContext) {
+        let root_page: u64 = t
+            .sql
+            .query_get_value_result(
+                "SELECT rootpage FROM sqlite_master WHERE name='msgs';",
+                paramsv![],
+            )
+            .await
+            .unwrap()
+            .unwrap();
+        let page_size: u64 = t
+            .sql
+            .query_get_value_result("PRAGMA page_size;", paramsv![])
+            .await
+            .unwrap()
+            .unwrap();
+        t.sql
+            .query_row("PRAGMA wal_checkpoint(TRUNCATE);", paramsv![], |_| Ok(()))
+            .await
+            .unwrap();
+        t.sql.close().await;
+
+        let dbfile = t.get_dbfile().to_path_buf();
+        let mut bytes = async_std::fs::read(&dbfile).await.unwrap();
+        let offset = ((root_page - 1) * page_size) as usize;
+        for i in offset..offset + 8 {
+            *bytes.get_mut(i).unwrap() = 0xff;
+        }
+        async_std::fs::write(&dbfile, bytes).await.unwrap();
+        t.sql.open(&t, &dbfile, false).await.unwrap();
+    }
+
+    #[async_std::test]
+    async fn test_db_corruption() {
+        let t = TestContext::new_alice().await;
+        let chat = t.create_chat_with_contact("bob", "bob@example.net").await;
+        t.send_text(chat.id, "hello").await;
+        corrupt_msgs_table(&t).await;
+
+        let (event_tx, event_rx) = async_std::channel::bounded(100);
+        t.add_event_sink(move |event: Event| {
+            let event_tx = event_tx.clone();
+            async move {
+                match &event.typ {
+                    EventType::DatabaseCorrupt { .. } => {
+                        event_tx.try_send(event.typ.clone()).unwrap()
+                    }
+                    EventType::Info(msg) if msg == "end of test" => {
+                        event_tx.try_send(event.typ.clone()).unwrap()
+                    }
+                    _ => {}
+                }
+            }
+        })
+        .await;
+
+        let select_msgs = || {
+            t.sql.query_map(
+                "SELECT txt FROM msgs;",
+                paramsv![],
+                |row| row.get::<_, String>(0),
+                |rows| {
+                    rows.collect::<std::result::Result<Vec<_>, _>>()
+                        .map_err(Into::into)
+                },
+            )
+        };
+        assert!(matches!(select_msgs().await, Err(Error::Sql(_))));
+        assert!(t.sql.is_corrupt());
+        assert!(matches!(select_msgs().await, Err(Error::SqlCorrupt)));
+        assert!(matches!(
+            t.sql.execute("DELETE FROM config;", paramsv![]).await,
+            Err(Error::SqlCorrupt)
+        ));
+
+        t.emit_event(EventType::Info("end of test".to_string()));
+        let mut corrupt_events = 0;
+        loop {
+            match event_rx.recv().await.unwrap() {
+                EventType::DatabaseCorrupt { .. } => corrupt_events += 1,
+                _ => break,
+            }
+        }
+        assert_eq!(corrupt_events, 1);
+
+        let report = t.sql.try_recover(&t).await.unwrap();
+        assert!(!report.integrity_check.is_empty());
+        assert!(report.damaged.iter().any(|(table, _)| table == "msgs"));
+        assert!(report.salvaged.iter().any(|(table, _)| table == "config"));
+        assert!(report.corrupt_dbfile.exists());
+
+        assert!(!t.sql.is_corrupt());
+        assert_eq!(
+            t.get_config(Config::ConfiguredAddr).await.unwrap(),
+            "alice@example.com"
+        );
+        assert!(select_msgs().await.is_ok());
+    }
+
+    /// Returns the names of the files next to `dbfile` with a `.stale-` suffix.
+    fn stale_sidecars(dbfile: &Path) -> Vec<String> {
+        std::fs::read_dir(dbfile.parent().unwrap())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .filter(|name| name.contains(".stale-"))
+            .collect()
+    }
+
+    #[async_std::test]
+    async fn test_open_quarantines_stale_shm() {
+        let t = TestContext::new().await;
+        t.set_config(Config::Displayname, Some("Alice"))
+            .await
+            .unwrap();
+        t.sql.close().await;
+
+        let dbfile: PathBuf = t.get_dbfile().to_path_buf().into();
+        std::fs::write(sidecar_path(&dbfile, "-shm"), b"bogus wal-index").unwrap();
+        t.sql.open(&t, &dbfile, false).await.unwrap();
+        assert_eq!(
+            t.get_config(Config::Displayname).await,
+            Some("Alice".to_string())
+        );
+
+        let stale = stale_sidecars(&dbfile);
+        assert_eq!(stale.len(), 1);
+        let name = stale.first().unwrap();
+        assert!(name.contains("-shm.stale-"));
+        assert_eq!(
+            std::fs::read(dbfile.with_file_name(name)).unwrap(),
+            b"bogus wal-index"
+        );
+    }
+
+    #[async_std::test]
+    async fn test_open_quarantines_stale_wal() {
+        let t = TestContext::new().await;
+        t.sql.close().await;
+
+        let dbfile: PathBuf = t.get_dbfile().to_path_buf().into();
+        std::fs::write(sidecar_path(&dbfile, "-wal"), [0u8; 64]).unwrap();
+        std::fs::write(sidecar_path(&dbfile, "-shm"), [0u8; 64]).unwrap();
+        t.sql.open(&t, &dbfile, false).await.unwrap();
+        assert!(t.sql.table_exists("msgs").await.unwrap());
+
+        let mut stale = stale_sidecars(&dbfile);
+        stale.sort();
+        assert_eq!(stale.len(), 2);
+        assert!(stale.first().unwrap().contains("-shm.stale-"));
+        assert!(stale.last().unwrap().contains("-wal.stale-"));
+    }
+
+    #[async_std::test]
+    async fn test_open_keeps_valid_sidecars() {
+        let t = TestContext::new().await;
+        let dir = tempfile::tempdir().unwrap();
+        let dbfile = dir.path().join("db.sqlite");
+
+        // Leak the connection, so that -wal and -shm are left behind as after a crash.
+        let conn = Connection::open(&dbfile).unwrap();
+        conn.execute_batch(
+            "PRAGMA journal_mode=WAL;
+             CREATE TABLE t (x INTEGER);
+             INSERT INTO t VALUES (42);",
+        )
+        .unwrap();
+        std::mem::forget(conn);
+        assert!(sidecar_path(&dbfile, "-wal").exists());
+        assert!(sidecar_path(&dbfile, "-shm").exists());
+
+        let mut warnings = Warnings::new();
+        quarantine_stale_sidecars(&t, &dbfile, &mut warnings).unwrap();
+        assert!(warnings.is_empty());
+        assert!(stale_sidecars(&dbfile).is_empty());
+    }
+
+    #[async_std::test]
+    async fn test_open_not_a_database() {
+        let t = TestContext::new().await;
+        let dir = tempfile::tempdir().unwrap();
+        let dbfile = dir.path().join("db.sqlite");
+        std::fs::write(&dbfile, [0xffu8; 4096]).unwrap();
+
+        let sql = Sql::new();
+        let err = sql.open(&t, &dbfile, false).await.unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<Error>(),
+            Some(Error::SqlCorrupt)
+        ));
+        assert!(!sql.is_open().await);
+        assert!(matches!(check_db_header(&dbfile), Err(Error::SqlCorrupt)));
+    }
+
+    async fn count_msgs(t: &TestContext) -> i64 {
+        t.sql
+            .query_get_value_result::<i64>("SELECT COUNT(*) FROM msgs;", paramsv![])
+            .await
+            .unwrap()
+            .unwrap_or_default()
+    }
+
+    #[async_std::test]
+    async fn test_attach_copy_table() {
+        let alice = TestContext::new_alice().await;
+        let chat = alice
+            .create_chat_with_contact("bob", "bob@example.net")
+            .await;
+        alice.send_text(chat.id, "hello").await;
+        alice.send_text(chat.id, "world").await;
+        let copied = i64::from(chat.id.to_u32());
+
+        let t = TestContext::new().await;
+        let before = count_msgs(&t).await;
+
+        let mut other = t.sql.attach(alice.get_dbfile(), "other").await.unwrap();
+        assert_eq!(other.schema_name(), "other");
+        let where_clause = format!("chat_id={}", copied);
+        assert_eq!(other.copy_table("msgs", &where_clause).unwrap(), 2);
+
+        // Copying the same rows again violates the primary key,
+        // the failed copy must not leave any rows behind.
+        assert!(other.copy_table("msgs", &where_clause).is_err());
+        assert!(other.copy_table("msgs", "no_such_column=1").is_err());
+        assert!(other.copy_table("no-such-table", "1").is_err());
+        other.detach().unwrap();
+
+        assert_eq!(count_msgs(&t).await, before + 2);
+        let texts = t
+            .sql
+            .query_map(
+                "SELECT txt FROM msgs WHERE chat_id=? ORDER BY id;",
+                paramsv![copied],
+                |row| row.get::<_, String>(0),
+                |rows| {
+                    rows.collect::<std::result::Result<Vec<_>, _>>()
+                        .map_err(Into::into)
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(texts, vec!["hello".to_string(), "world".to_string()]);
+    }
+
+    #[async_std::test]
+    async fn test_attach_exclusive() {
+        let alice = TestContext::new_alice().await;
+        let t = TestContext::new().await;
+
+        assert!(t.sql.attach(alice.get_dbfile(), "main").await.is_err());
+        assert!(t.sql.attach(alice.get_dbfile(), "temp").await.is_err());
+        assert!(t.sql.attach(alice.get_dbfile(), "x; DROP").await.is_err());
+
+        let other = t.sql.attach(alice.get_dbfile(), "other").await.unwrap();
+        assert!(t.sql.attach(alice.get_dbfile(), "second").await.is_err());
+        other.detach().unwrap();
+
+        // Dropping the guard detaches as well.
+        let other = t.sql.attach(alice.get_dbfile(), "other").await.unwrap();
+        drop(other);
+        let other = t.sql.attach(alice.get_dbfile(), "other").await.unwrap();
+        other.detach().unwrap();
+    }
+
+    /// Returns the modification times of the files in the blobdir.
+    fn blobdir_mtimes(
+        t: &TestContext,
+    ) -> std::collections::BTreeMap<String, std::time::SystemTime> {
+        let mut mtimes = std::collections::BTreeMap::new();
+        for entry in std::fs::read_dir(t.get_blobdir()).unwrap() {
+            let entry = entry.unwrap();
+            mtimes.insert(
+                entry.file_name().to_string_lossy().into_owned(),
+                entry.metadata().unwrap().modified().unwrap(),
+            );
+        }
+        mtimes
+    }
+
+    #[async_std::test]
+    async fn test_device_icons_not_rewritten() {
+        let t = TestContext::new().await;
+        t.update_device_chats().await.unwrap();
+        assert_eq!(
+            t.sql
+                .get_raw_config_int(&t, DEVICE_ICONS_VERSION_KEY)
+                .await
+                .unwrap(),
+            DEVICE_ICONS_VERSION
+        );
+        let mtimes = blobdir_mtimes(&t);
+        assert!(mtimes.contains_key("icon-device.png"));
+
+        // Force the update on the next open, it must find the icons up to date.
+        t.sql
+            .set_raw_config_int(&t, DEVICE_ICONS_VERSION_KEY, 0)
+            .await
+            .unwrap();
+        t.sql.close().await;
+        t.sql.open(&t, &t.get_dbfile(), false).await.unwrap();
+        assert_eq!(blobdir_mtimes(&t), mtimes);
+        assert_eq!(
+            t.sql
+                .get_raw_config_int(&t, DEVICE_ICONS_VERSION_KEY)
+                .await
+                .unwrap(),
+            DEVICE_ICONS_VERSION
+        );
+
+        t.sql.close().await;
+        t.sql.open(&t, &t.get_dbfile(), false).await.unwrap();
+        assert_eq!(blobdir_mtimes(&t), mtimes);
+    }
+
+    #[async_std::test]
+    async fn test_open_broken_icons_warns() {
+        let t = TestContext::new().await;
+        t.update_device_chats().await.unwrap();
+        t.sql
+            .set_raw_config_int(&t, DEVICE_ICONS_VERSION_KEY, 0)
+            .await
+            .unwrap();
+        t.sql.close().await;
+
+        // The icons cannot be written if the blobdir is replaced by a file.
+        let blobdir = t.get_blobdir().to_path_buf();
+        async_std::fs::remove_dir_all(&blobdir).await.unwrap();
+        async_std::fs::write(&blobdir, b"not a directory")
+            .await
+            .unwrap();
+
+        let (warning_tx, warning_rx) = async_std::channel::unbounded();
+        t.add_event_sink(move |event: Event| {
+            let warning_tx = warning_tx.clone();
+            async move {
+                if let EventType::Warning(msg) = event.typ {
+                    warning_tx.try_send(msg).unwrap();
+                }
+            }
+        })
+        .await;
+
+        // Failing to update the icons is not fatal.
+        t.sql.open(&t, &t.get_dbfile(), false).await.unwrap();
+        assert!(t.sql.is_open().await);
+        loop {
+            let msg = async_std::future::timeout(Duration::from_secs(10), warning_rx.recv())
+                .await
+                .expect("no warning about the device icon")
+                .unwrap();
+            if msg.contains("Failed to update device icon") {
+                break;
+            }
+        }
+        assert_eq!(
+            t.sql
+                .get_raw_config_int(&t, DEVICE_ICONS_VERSION_KEY)
+                .await
+                .unwrap_or_default(),
+            0
+        );
+    }
+
+    #[cfg(unix)]
+    #[async_std::test]
+    async fn test_device_icons_readonly_blobdir() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let t = TestContext::new().await;
+        t.update_device_chats().await.unwrap();
+        for name in &["icon-device.png", "icon-saved-messages.png"] {
+            async_std::fs::remove_file(t.get_blobdir().join(name))
+                .await
+                .unwrap();
+        }
+        t.sql
+            .set_raw_config_int(&t, DEVICE_ICONS_VERSION_KEY, 0)
+            .await
+            .unwrap();
+        t.sql.close().await;
+
+        let set_mode = |mode| {
+            std::fs::set_permissions(t.get_blobdir(), std::fs::Permissions::from_mode(mode))
+                .unwrap()
+        };
+        set_mode(0o555);
+        if std::fs::write(t.get_blobdir().join("probe"), b"").is_ok() {
+            // Permissions are not enforced, e.g. when running as root.
+            set_mode(0o755);
+            return;
+        }
+
+        // The icons cannot be written, but the database is opened anyway.
+        t.sql.open(&t, &t.get_dbfile(), false).await.unwrap();
+        assert_eq!(
+            t.sql
+                .get_raw_config_int(&t, DEVICE_ICONS_VERSION_KEY)
+                .await
+                .unwrap_or_default(),
+            0
+        );
+
+        // Once the blobdir is writable again, the next open restores the icons.
+        set_mode(0o755);
+        t.sql.close().await;
+        t.sql.open(&t, &t.get_dbfile(), false).await.unwrap();
+        assert_eq!(
+            t.sql
+                .get_raw_config_int(&t, DEVICE_ICONS_VERSION_KEY)
+                .await
+                .unwrap(),
+            DEVICE_ICONS_VERSION
+        );
+        assert!(t.get_blobdir().join("icon-device.png").exists().await);
+    }
 }
@@ -0,0 +1,347 @@
+//! # Batched message state updates
+//!
+//! While fetching messages, e.g. during the initial sync, the states of many messages
+//! change in a short time. Instead of writing and announcing every change on its own,
+//! changes are queued using [queue] and written by [flush] in a single transaction
+//! with one `UPDATE` per state.
+//!
+//! Changes are only queued while an IMAP loop is fetching, from [begin] until the returned
+//! [Fetch] is ended or dropped, otherwise they are written right away.
+//! The queue is flushed as soon as it contains [MAX_BATCH_SIZE] changes
+//! or its oldest change is older than [MAX_BATCH_AGE].
+//! As the age is only checked when queueing, the IMAP loops also flush when they finish
+//! fetching and the queue is flushed when IO is stopped.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use async_std::sync::Mutex;
+use async_std::task;
+use itertools::Itertools;
+
+use crate::chat::ChatId;
+use crate::context::Context;
+use crate::events::EventType;
+use crate::message::{MessageState, MsgId};
+
+/// Number of queued changes triggering a flush.
+pub(crate) const MAX_BATCH_SIZE: usize = 100;
+
+/// Age of the oldest queued change triggering a flush.
+pub(crate) const MAX_BATCH_AGE: Duration = Duration::from_millis(500);
+
+/// Message state changes not yet written to the database.
+#[derive(Debug, Default)]
+pub(crate) struct StateBatch {
+    queue: Mutex<Queue>,
+
+    /// Number of running fetches, see [Fetch].
+    fetches: AtomicUsize,
+}
+
+#[derive(Debug, Default)]
+struct Queue {
+    states: BTreeMap<MsgId, (ChatId, MessageState)>,
+    started: Option<Instant>,
+}
+
+impl Queue {
+    fn is_due(&self, fetches: usize) -> bool {
+        fetches == 0
+            || self.states.len() >= MAX_BATCH_SIZE
+            || self
+                .started
+                .map_or(false, |started| started.elapsed() >= MAX_BATCH_AGE)
+    }
+}
+
+/// A running fetch, state changes are queued as long as it exists.
+///
+/// Ending the fetch with [Fetch::end] writes the queued changes.  If it is dropped
+/// without being ended, e.g. on an early return, the changes are written in the background.
+#[derive(Debug)]
+#[must_use]
+pub(crate) struct Fetch {
+    context: Context,
+    flush_on_drop: bool,
+}
+
+impl Fetch {
+    /// Ends the fetch and writes the queued state changes.
+    pub(crate) async fn end(mut self) -> Result<()> {
+        self.flush_on_drop = false;
+        let context = self.context.clone();
+        drop(self);
+        flush(&context).await
+    }
+}
+
+impl Drop for Fetch {
+    fn drop(&mut self) {
+        self.context
+            .state_batch
+            .fetches
+            .fetch_sub(1, Ordering::SeqCst);
+        if self.flush_on_drop {
+            let context = self.context.clone();
+            task::spawn(async move {
+                if let Err(err) = flush(&context).await {
+                    warn!(context, "Failed to write message states: {}", err);
+                }
+            });
+        }
+    }
+}
+
+/// Starts queueing state changes for a fetch until the returned [Fetch] ends.
+pub(crate) fn begin(context: &Context) -> Fetch {
+    context.state_batch.fetches.fetch_add(1, Ordering::SeqCst);
+    Fetch {
+        context: context.clone(),
+        flush_on_drop: true,
+    }
+}
+
+/// Queues setting the state of a message.
+///
+/// A later change of the same message replaces an earlier one.
+/// Outside of a fetch the state is written right away.
+/// Returns true if the queue was flushed.
+pub(crate) async fn queue(
+    context: &Context,
+    chat_id: ChatId,
+    msg_id: MsgId,
+    state: MessageState,
+) -> Result<bool> {
+    let due = {
+        let mut queue = context.state_batch.queue.lock().await;
+        queue.started.get_or_insert_with(Instant::now);
+        queue.states.insert(msg_id, (chat_id, state));
+        queue.is_due(context.state_batch.fetches.load(Ordering::SeqCst))
+    };
+    if due {
+        flush(context).await?;
+    }
+    Ok(due)
+}
+
+/// Writes all queued state changes.
+///
+/// `MsgRead` is emitted for each message that changed to `OutMdnRcvd`,
+/// `MsgsChanged` once for each chat with other changes.
+pub(crate) async fn flush(context: &Context) -> Result<()> {
+    let states = {
+        let mut queue = context.state_batch.queue.lock().await;
+        queue.started = None;
+        std::mem::take(&mut queue.states)
+    };
+    if states.is_empty() {
+        return Ok(());
+    }
+
+    let mut read = Vec::new();
+    let mut changed_chats = BTreeSet::new();
+    for (msg_id, (chat_id, state)) in &states {
+        if *state == MessageState::OutMdnRcvd {
+            read.push((*chat_id, *msg_id));
+        } else {
+            changed_chats.insert(*chat_id);
+        }
+    }
+
+    let mut by_state: Vec<(MessageState, Vec<MsgId>)> = Vec::new();
+    for (msg_id, (_chat_id, state)) in states {
+        match by_state.iter_mut().find(|(s, _)| *s == state) {
+            Some((_, msg_ids)) => msg_ids.push(msg_id),
+            None => by_state.push((state, vec![msg_id])),
+        }
+    }
+
+    context
+        .sql
+        .with_conn(move |mut conn| {
+            let tx = conn.transaction()?;
+            for (state, msg_ids) in by_state {
+                let mut params: Vec<&dyn crate::ToSql> = vec![&state];
+                params.extend(msg_ids.iter().map(|msg_id| msg_id as &dyn crate::ToSql));
+                tx.execute(
+                    &format!(
+                        "UPDATE msgs SET state=? WHERE id IN ({});",
+                        std::iter::repeat("?").take(msg_ids.len()).join(",")
+                    ),
+                    params,
+                )?;
+            }
+            tx.commit()?;
+            Ok(())
+        })
+        .await?;
+
+    for (chat_id, msg_id) in read {
+        context.emit_event(EventType::MsgRead { chat_id, msg_id });
+    }
+    for chat_id in changed_chats {
+        context.emit_event(EventType::MsgsChanged {
+            chat_id,
+            msg_id: MsgId::new(0),
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use async_std::channel;
+
+    use crate::events::Event;
+    use crate::message::Message;
+    use crate::test_utils::TestContext;
+
+    #[async_std::test]
+    async fn test_batched_state_updates() {
+        let t = TestContext::new_alice().await;
+        let bob = t
+            .create_chat_with_contact("bob", "bob@example.net")
+            .await
+            .id;
+        let claire = t
+            .create_chat_with_contact("claire", "claire@example.org")
+            .await
+            .id;
+        let msgs = t
+            .sql
+            .with_conn(move |mut conn| {
+                let tx = conn.transaction()?;
+                let mut msgs = Vec::new();
+                for i in 0..1000 {
+                    let chat_id = if i % 2 == 0 { bob } else { claire };
+                    tx.execute(
+                        "INSERT INTO msgs (chat_id, state) VALUES (?, ?);",
+                        params![chat_id, MessageState::InFresh],
+                    )?;
+                    msgs.push((chat_id, MsgId::new(tx.last_insert_rowid() as u32)));
+                }
+                tx.commit()?;
+                Ok(msgs)
+            })
+            .await
+            .unwrap();
+
+        let (event_tx, event_rx) = channel::bounded(1000);
+        t.add_event_sink(move |event: Event| {
+            let event_tx = event_tx.clone();
+            async move {
+                match &event.typ {
+                    EventType::MsgsChanged { msg_id, .. } if msg_id.is_unset() => {
+                        event_tx.try_send(event.typ.clone()).unwrap()
+                    }
+                    EventType::Info(msg) if msg == "end of test" => {
+                        event_tx.try_send(event.typ.clone()).unwrap()
+                    }
+                    _ => {}
+                }
+            }
+        })
+        .await;
+
+        let fetch = begin(&t);
+        let mut flushes = 0;
+        for (chat_id, msg_id) in &msgs {
+            if queue(&t, *chat_id, *msg_id, MessageState::InSeen)
+                .await
+                .unwrap()
+            {
+                flushes += 1;
+            }
+        }
+        fetch.end().await.unwrap();
+        flushes += 1;
+        assert!(flushes >= msgs.len() / MAX_BATCH_SIZE);
+        assert!(flushes <= msgs.len() / 10);
+
+        let seen: i32 = t
+            .sql
+            .query_get_value(
+                &t,
+                "SELECT COUNT(*) FROM msgs WHERE state=?;",
+                paramsv![MessageState::InSeen],
+            )
+            .await
+            .unwrap_or_default();
+        assert_eq!(seen as usize, msgs.len());
+
+        t.emit_event(EventType::Info("end of test".to_string()));
+        let mut events = 0;
+        loop {
+            match event_rx.recv().await.unwrap() {
+                EventType::Info(_) => break,
+                EventType::MsgsChanged { chat_id, .. } => {
+                    assert!(chat_id == bob || chat_id == claire);
+                    events += 1;
+                }
+                _ => unreachable!(),
+            }
+        }
+        assert!(events <= 2 * flushes);
+    }
+
+    #[async_std::test]
+    async fn test_state_written_outside_fetch() {
+        let t = TestContext::new_alice().await;
+        let chat_id = t
+            .create_chat_with_contact("bob", "bob@example.net")
+            .await
+            .id;
+        let msg_id = t.send_text(chat_id, "hi").await.sender_msg_id;
+
+        assert!(queue(&t, chat_id, msg_id, MessageState::OutDelivered)
+            .await
+            .unwrap());
+        assert_eq!(get_state(&t, msg_id).await, MessageState::OutDelivered);
+
+        let fetch = begin(&t);
+        assert!(!queue(&t, chat_id, msg_id, MessageState::OutMdnRcvd)
+            .await
+            .unwrap());
+        assert_eq!(get_state(&t, msg_id).await, MessageState::OutDelivered);
+        fetch.end().await.unwrap();
+        assert_eq!(get_state(&t, msg_id).await, MessageState::OutMdnRcvd);
+    }
+
+    #[async_std::test]
+    async fn test_dropped_fetch_ends() {
+        let t = TestContext::new_alice().await;
+        let chat_id = t
+            .create_chat_with_contact("bob", "bob@example.net")
+            .await
+            .id;
+        let msg_id = t.send_text(chat_id, "hi").await.sender_msg_id;
+        let later_msg_id = t.send_text(chat_id, "hi again").await.sender_msg_id;
+
+        {
+            let _fetch = begin(&t);
+            assert!(!queue(&t, chat_id, msg_id, MessageState::OutDelivered)
+                .await
+                .unwrap());
+        }
+        assert!(queue(&t, chat_id, later_msg_id, MessageState::OutDelivered)
+            .await
+            .unwrap());
+        assert_eq!(
+            get_state(&t, later_msg_id).await,
+            MessageState::OutDelivered
+        );
+
+        // The change queued during the fetch is written in the background, if not yet.
+        flush(&t).await.unwrap();
+        assert_eq!(get_state(&t, msg_id).await, MessageState::OutDelivered);
+    }
+
+    async fn get_state(t: &TestContext, msg_id: MsgId) -> MessageState {
+        Message::load_from_db(t, msg_id).await.unwrap().state
+    }
+}
@@ -0,0 +1,419 @@
+//! # Account statistics.
+//!
+//! [Context::get_stats] reports the number of messages, chats, contacts and blob files,
+//! e.g. for the debug info screen.  Counting them needs full table scans and reading the
+//! blob directory, which is slow on big accounts, so the counters are cached for [STATS_TTL].
+//!
+//! Adding or removing messages, chats, contacts or blobs adjusts the cached counters where
+//! this is cheap.  Changes not reported to the cache, e.g. bulk deletions by housekeeping
+//! or ephemeral messages, are picked up when the counters are recounted after the TTL expired.
+
+use std::convert::TryFrom;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use async_std::fs;
+use async_std::prelude::*;
+
+use crate::chat::{self, Chat, ChatId};
+use crate::constants::Blocked;
+use crate::contact::Contact;
+use crate::context::Context;
+use crate::dc_tools::time;
+use crate::message;
+
+/// Time the counters are cached before they are counted again.
+pub const STATS_TTL: Duration = Duration::from_secs(60);
+
+/// Number of days covered by [Stats::messages_per_day].
+pub const STATS_DAYS: usize = 30;
+
+/// Counters of an account, see [Context::get_stats].
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Stats {
+    /// Number of messages in chats that are not blocked.
+    pub messages: usize,
+
+    /// Number of messages in contact requests.
+    pub deaddrop_messages: usize,
+
+    /// Number of chats that are not blocked, not counting special chats.
+    pub chats: usize,
+
+    /// Number of contacts, not counting special contacts.
+    pub contacts: usize,
+
+    /// Number of files in the blob directory.
+    pub blobs: usize,
+
+    /// Total size of the files in the blob directory.
+    pub blob_bytes: u64,
+
+    /// Number of messages in chats that are not blocked per day
+    /// for the last [STATS_DAYS] days, oldest first.  The last entry is today.
+    ///
+    /// Days start at midnight UTC.
+    pub messages_per_day: Vec<usize>,
+}
+
+impl Stats {
+    /// Returns the average number of messages per day during the last [STATS_DAYS] days.
+    pub fn average_messages_per_day(&self) -> f64 {
+        let total: usize = self.messages_per_day.iter().sum();
+        total as f64 / STATS_DAYS as f64
+    }
+}
+
+/// A cached counter which can be adjusted, see [StatsCache::adjust].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Counter {
+    Messages,
+    DeaddropMessages,
+    Chats,
+    Contacts,
+}
+
+impl Counter {
+    /// Returns the counter of the messages in a chat with the given blocked state,
+    /// `None` for special chats and blocked chats, which are not counted.
+    pub(crate) fn for_messages_in(chat_id: ChatId, blocked: Blocked) -> Option<Counter> {
+        if chat_id.is_special() {
+            return None;
+        }
+        match blocked {
+            Blocked::Not => Some(Counter::Messages),
+            Blocked::Deaddrop => Some(Counter::DeaddropMessages),
+            Blocked::Manually => None,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+struct Counters {
+    messages: usize,
+    deaddrop_messages: usize,
+    chats: usize,
+    contacts: usize,
+    blobs: usize,
+    blob_bytes: u64,
+}
+
+impl Counters {
+    fn adjust(&mut self, counter: Counter, delta: i64) {
+        let value = match counter {
+            Counter::Messages => &mut self.messages,
+            Counter::DeaddropMessages => &mut self.deaddrop_messages,
+            Counter::Chats => &mut self.chats,
+            Counter::Contacts => &mut self.contacts,
+        };
+        // Changes may have been missed, so the counter must not wrap around.
+        let magnitude = usize::try_from(delta.abs()).unwrap_or(usize::MAX);
+        *value = if delta < 0 {
+            value.saturating_sub(magnitude)
+        } else {
+            value.saturating_add(magnitude)
+        };
+    }
+}
+
+#[derive(Debug, Default)]
+struct CachedCounters {
+    /// The counters and when they were counted.
+    counted: Option<(Instant, Counters)>,
+
+    /// Incremented on every adjustment.
+    ///
+    /// Counters are only cached if no adjustment happened while counting,
+    /// as it is unknown whether it was counted or not.
+    generation: u64,
+}
+
+/// Cache of the counters reported by [Context::get_stats].
+#[derive(Debug, Default)]
+pub(crate) struct StatsCache {
+    inner: Mutex<CachedCounters>,
+}
+
+impl StatsCache {
+    /// Returns the cached counters if they were counted less than [STATS_TTL] before `now`.
+    fn get(&self, now: Instant) -> Option<Counters> {
+        match self.inner.lock().unwrap().counted {
+            Some((counted_at, counters))
+                if now.saturating_duration_since(counted_at) < STATS_TTL =>
+            {
+                Some(counters)
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns the current generation, to be passed to [StatsCache::put] after counting.
+    fn generation(&self) -> u64 {
+        self.inner.lock().unwrap().generation
+    }
+
+    fn put(&self, counters: Counters, counted_at: Instant, generation: u64) {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.generation == generation {
+            inner.counted = Some((counted_at, counters));
+        }
+    }
+
+    /// Adds `delta` to a cached counter.
+    pub(crate) fn adjust(&self, counter: Counter, delta: i64) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.generation += 1;
+        if let Some((_, counters)) = &mut inner.counted {
+            counters.adjust(counter, delta);
+        }
+    }
+
+    /// Adjusts the message counter of a chat with the given blocked state by `delta`.
+    pub(crate) fn adjust_messages(&self, chat_id: ChatId, blocked: Blocked, delta: i64) {
+        if let Some(counter) = Counter::for_messages_in(chat_id, blocked) {
+            self.adjust(counter, delta);
+        }
+    }
+
+    /// Records a new file of `bytes` bytes in the blob directory.
+    pub(crate) fn blob_added(&self, bytes: u64) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.generation += 1;
+        if let Some((_, counters)) = &mut inner.counted {
+            counters.blobs += 1;
+            counters.blob_bytes += bytes;
+        }
+    }
+
+    /// Drops the cached counters, they are counted again on the next request.
+    pub(crate) fn invalidate(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.generation += 1;
+        inner.counted = None;
+    }
+}
+
+/// Adjusts the message counter of the chat `chat_id` by `delta`.
+///
+/// The chat is usually cached, so loading it to find out whether it is blocked is cheap.
+pub(crate) async fn adjust_chat_messages(context: &Context, chat_id: ChatId, delta: i64) {
+    if chat_id.is_special() {
+        return;
+    }
+    match Chat::load_from_db(context, chat_id).await {
+        Ok(chat) => context
+            .stats_cache
+            .adjust_messages(chat_id, chat.blocked, delta),
+        Err(_) => context.stats_cache.invalidate(),
+    }
+}
+
+impl Context {
+    /// Returns the number of messages, chats, contacts and blob files of this account.
+    ///
+    /// The counters are cached for [STATS_TTL], so changes made behind the back
+    /// of the cache, e.g. by housekeeping, may show up only after this time.
+    /// [Stats::messages_per_day] is always up to date.
+    pub async fn get_stats(&self) -> Result<Stats> {
+        get_stats_at(self, Instant::now()).await
+    }
+}
+
+async fn get_stats_at(context: &Context, now: Instant) -> Result<Stats> {
+    let counters = match context.stats_cache.get(now) {
+        Some(counters) => counters,
+        None => {
+            let generation = context.stats_cache.generation();
+            let counters = count(context).await?;
+            context.stats_cache.put(counters, now, generation);
+            counters
+        }
+    };
+    Ok(Stats {
+        messages: counters.messages,
+        deaddrop_messages: counters.deaddrop_messages,
+        chats: counters.chats,
+        contacts: counters.contacts,
+        blobs: counters.blobs,
+        blob_bytes: counters.blob_bytes,
+        messages_per_day: get_messages_per_day(context, time()).await?,
+    })
+}
+
+async fn count(context: &Context) -> Result<Counters> {
+    let mut counters = Counters {
+        messages: message::get_real_msg_cnt(context).await.max(0) as usize,
+        deaddrop_messages: message::get_deaddrop_msg_cnt(context).await,
+        chats: chat::get_chat_cnt(context).await,
+        contacts: Contact::get_real_cnt(context).await,
+        ..Default::default()
+    };
+
+    // The database counters are still useful if the blob directory can not be read.
+    match count_blobs(context).await {
+        Ok((blobs, blob_bytes)) => {
+            counters.blobs = blobs;
+            counters.blob_bytes = blob_bytes;
+        }
+        Err(err) => warn!(context, "Failed to count blobs: {:#}", err),
+    }
+    Ok(counters)
+}
+
+/// Returns the number and the total size of the files in the blob directory.
+async fn count_blobs(context: &Context) -> Result<(usize, u64)> {
+    let mut blobs = 0;
+    let mut blob_bytes = 0;
+    let mut entries = fs::read_dir(context.get_blobdir()).await?;
+    while let Some(entry) = entries.next().await {
+        match entry?.metadata().await {
+            Ok(meta) if meta.is_file() => {
+                blobs += 1;
+                blob_bytes += meta.len();
+            }
+            _ => continue,
+        }
+    }
+    Ok((blobs, blob_bytes))
+}
+
+/// Counts the messages of the last [STATS_DAYS] days before `now` in a single query.
+async fn get_messages_per_day(context: &Context, now: i64) -> Result<Vec<usize>> {
+    const DAY: i64 = 24 * 60 * 60;
+    let first_day = now / DAY - (STATS_DAYS as i64 - 1);
+    let mut per_day = vec![0; STATS_DAYS];
+    context
+        .sql
+        .query_map(
+            "SELECT m.timestamp/? AS day, COUNT(*) \
+             FROM msgs m LEFT JOIN chats c ON c.id=m.chat_id \
+             WHERE m.id>9 AND m.chat_id>9 AND c.blocked=0 AND m.timestamp>=? \
+             GROUP BY day;",
+            paramsv![DAY, first_day * DAY],
+            |row| Ok((row.get::<_, i64>(0)?, row.get::<_, i64>(1)?)),
+            |rows| {
+                for row in rows {
+                    let (day, count) = row?;
+                    // Messages from the future are not counted.
+                    if let Some(entry) = usize::try_from(day - first_day)
+                        .ok()
+                        .and_then(|index| per_day.get_mut(index))
+                    {
+                        *entry = count as usize;
+                    }
+                }
+                Ok(())
+            },
+        )
+        .await?;
+    Ok(per_day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::blob::BlobObject;
+    use crate::chat::{create_group_chat, send_text_msg, ProtectionStatus};
+    use crate::message::{delete_msgs, MsgId};
+    use crate::test_utils::TestContext;
+
+    #[test]
+    fn test_cache_ttl() {
+        let cache = StatsCache::default();
+        let start = Instant::now();
+        assert_eq!(cache.get(start), None);
+
+        let counters = Counters {
+            messages: 5,
+            ..Default::default()
+        };
+        cache.put(counters, start, cache.generation());
+        assert_eq!(cache.get(start), Some(counters));
+        assert_eq!(
+            cache.get(start + STATS_TTL - Duration::from_secs(1)),
+            Some(counters)
+        );
+        assert_eq!(cache.get(start + STATS_TTL), None);
+
+        // Adjustments are applied to the cached counters.
+        cache.adjust(Counter::Messages, 2);
+        cache.adjust(Counter::Messages, -10);
+        cache.adjust(Counter::Chats, 1);
+        cache.blob_added(100);
+        let adjusted = cache.get(start).unwrap();
+        assert_eq!(adjusted.messages, 0);
+        assert_eq!(adjusted.chats, 1);
+        assert_eq!(adjusted.blobs, 1);
+        assert_eq!(adjusted.blob_bytes, 100);
+
+        // Counters are not cached if they were adjusted while counting.
+        cache.invalidate();
+        let generation = cache.generation();
+        cache.adjust(Counter::Contacts, 1);
+        cache.put(counters, start, generation);
+        assert_eq!(cache.get(start), None);
+    }
+
+    #[async_std::test]
+    async fn test_get_stats() -> Result<()> {
+        let t = TestContext::new_alice().await;
+        let stats = t.get_stats().await?;
+        assert_eq!(stats.messages_per_day.len(), STATS_DAYS);
+
+        let chat_id = create_group_chat(&t, ProtectionStatus::Unprotected, "grp").await?;
+        let msg_id = send_text_msg(&t, chat_id, "hi".to_string()).await?;
+        Contact::create(&t, "Bob", "bob@example.net").await?;
+        BlobObject::create(&t, "file.txt", b"hello").await?;
+
+        // The cached counters are adjusted, the new group has a draft besides the sent message.
+        let stats2 = t.get_stats().await?;
+        assert_eq!(stats2.chats, stats.chats + 1);
+        assert_eq!(stats2.messages, stats.messages + 2);
+        assert_eq!(stats2.contacts, stats.contacts + 1);
+        assert_eq!(stats2.blobs, stats.blobs + 1);
+        assert_eq!(stats2.blob_bytes, stats.blob_bytes + 5);
+        assert_eq!(
+            stats2.messages_per_day.iter().sum::<usize>(),
+            stats.messages_per_day.iter().sum::<usize>() + 2
+        );
+
+        // Recounting after the TTL gives the same result.
+        assert_eq!(stats2, get_stats_at(&t, Instant::now() + STATS_TTL).await?);
+
+        // The draft is left.
+        delete_msgs(&t, &[msg_id]).await;
+        assert_eq!(t.get_stats().await?.messages, stats.messages + 1);
+        Ok(())
+    }
+
+    #[async_std::test]
+    async fn test_bulk_delete_recounted_after_ttl() -> Result<()> {
+        let t = TestContext::new_alice().await;
+        let chat_id = create_group_chat(&t, ProtectionStatus::Unprotected, "grp").await?;
+        for i in 0..10 {
+            send_text_msg(&t, chat_id, format!("msg {}", i)).await?;
+        }
+        let stats = t.get_stats().await?;
+        assert!(stats.messages >= 10);
+
+        // Bypass the adjustment hooks.
+        let deleted = t
+            .sql
+            .execute(
+                "DELETE FROM msgs WHERE chat_id=? AND id>?;",
+                paramsv![chat_id, MsgId::new(9)],
+            )
+            .await?;
+        assert!(deleted >= 10);
+
+        // The cached counters are stale until the TTL expires.
+        assert_eq!(t.get_stats().await?.messages, stats.messages);
+        let later = Instant::now() + STATS_TTL;
+        let recounted = get_stats_at(&t, later).await?;
+        assert_eq!(recounted.messages, stats.messages - deleted);
+        assert_eq!(t.get_stats().await?.messages, recounted.messages);
+        Ok(())
+    }
+}
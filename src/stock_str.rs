@@ -11,7 +11,7 @@
 use crate::chat;
 use crate::chat::ProtectionStatus;
 use crate::config::Config;
-use crate::constants::{Viewtype, DC_CONTACT_ID_SELF};
+use crate::constants::{Viewtype, DC_CONTACT_ID_SELF, DC_CONTACT_ID_UNDEFINED};
 use crate::contact::{Contact, Origin};
 use crate::context::Context;
 use crate::message::Message;
@@ -262,6 +262,18 @@ pub enum StockMessage {
 
     #[strum(props(fallback = "Message deletion timer is set to %1$s weeks."))]
     MsgEphemeralTimerWeeks = 96,
+
+    #[strum(props(fallback = "%1$s message"))]
+    PartialDownloadMsgBody = 97,
+
+    #[strum(props(fallback = "New message"))]
+    NewMessageNotification = 98,
+
+    #[strum(props(fallback = "%1$s new messages"))]
+    NewMessagesNotification = 99,
+
+    #[strum(props(fallback = "The invitation by %1$s was revoked."))]
+    SecurejoinRevoked = 100,
 }
 
 impl StockMessage {
@@ -314,6 +326,9 @@ fn replace2(&self, replacement: impl AsRef<str>) -> String {
     /// E.g. this turns `Group image changed.` into `Group image changed by me.` or `Group
     /// image changed by Alice.`.
     ///
+    /// With `contact` set to [`DC_CONTACT_ID_UNDEFINED`] the message is left unchanged,
+    /// this is used for actions not performed by a known user.
+    ///
     /// Note that the original message should end in a `.`.
     fn action_by_contact<'a>(
         self,
@@ -326,6 +341,7 @@ fn action_by_contact<'a>(
         Box::pin(async move {
             let message = self.as_ref().trim_end_matches('.');
             match contact_id {
+                DC_CONTACT_ID_UNDEFINED => self.as_ref().to_string(),
                 DC_CONTACT_ID_SELF => msg_action_by_me(context, message).await,
                 _ => {
                     let displayname = Contact::get_by_id(context, contact_id)
@@ -539,6 +555,13 @@ pub(crate) async fn contact_verified(context: &Context, contact_addr: impl AsRef
         .replace1(contact_addr)
 }
 
+/// Stock string: `The invitation by %1$s was revoked.`.
+pub(crate) async fn securejoin_revoked(context: &Context, contact_addr: impl AsRef<str>) -> String {
+    translated(context, StockMessage::SecurejoinRevoked)
+        .await
+        .replace1(contact_addr)
+}
+
 /// Stock string: `Cannot verify %1$s`.
 pub(crate) async fn contact_not_verified(
     context: &Context,
@@ -856,6 +879,30 @@ pub(crate) async fn msg_ephemeral_timer_weeks(
         .await
 }
 
+/// Stock string: `%1$s message` with placeholder replaced by the human-readable size.
+pub(crate) async fn partial_download_msg_body(context: &Context, org_bytes: u32) -> String {
+    let size = if org_bytes < 1024 * 1024 {
+        format!("{} KiB", (org_bytes + 1023) / 1024)
+    } else {
+        format!("{:.1} MiB", f64::from(org_bytes) / (1024.0 * 1024.0))
+    };
+    translated(context, StockMessage::PartialDownloadMsgBody)
+        .await
+        .replace1(size)
+}
+
+/// Stock string: `New message`.
+pub(crate) async fn new_message_notification(context: &Context) -> String {
+    translated(context, StockMessage::NewMessageNotification).await
+}
+
+/// Stock string: `%1$s new messages`.
+pub(crate) async fn new_messages_notification(context: &Context, cnt: usize) -> String {
+    translated(context, StockMessage::NewMessagesNotification)
+        .await
+        .replace1(cnt.to_string())
+}
+
 impl Context {
     /// Set the stock string for the [StockMessage].
     ///
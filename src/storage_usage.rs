@@ -0,0 +1,298 @@
+//! # Storage usage.
+//!
+//! Breaks down the disk space used by an account into the database,
+//! its write-ahead log and the files in the blob directory.
+
+use std::collections::HashMap;
+
+use anyhow::{format_err, Result};
+use async_std::fs;
+use async_std::prelude::*;
+
+use crate::chat::ChatId;
+use crate::config::Config;
+use crate::constants::{Viewtype, DC_CHAT_ID_TRASH};
+use crate::context::Context;
+use crate::ongoing::OperationKind;
+use crate::param::{Param, Params};
+
+/// Number of chats returned in [StorageUsage::largest_chats].
+pub const STORAGE_USAGE_TOP_CHATS: usize = 10;
+
+/// Disk space used by an account, all sizes in bytes.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct StorageUsage {
+    /// Size of the database file.
+    pub db_bytes: u64,
+
+    /// Size of the database write-ahead log, 0 if there is none.
+    pub wal_bytes: u64,
+
+    /// Total size of all files in the blob directory.
+    pub blobdir_bytes: u64,
+
+    /// Files of image, GIF and sticker messages.
+    pub images_bytes: u64,
+
+    /// Files of video messages.
+    pub video_bytes: u64,
+
+    /// Files of audio and voice messages.
+    pub audio_bytes: u64,
+
+    /// Files of all other messages with attachments.
+    pub files_bytes: u64,
+
+    /// Avatars of chats, contacts and the own avatar.
+    pub avatars_bytes: u64,
+
+    /// Files not referenced by any message or avatar.
+    pub other_bytes: u64,
+
+    /// Chats using the most bytes in the blob directory, largest first,
+    /// at most [STORAGE_USAGE_TOP_CHATS] entries.
+    pub largest_chats: Vec<(ChatId, u64)>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Category {
+    Images,
+    Video,
+    Audio,
+    Files,
+    Avatars,
+}
+
+impl Category {
+    fn from_viewtype(viewtype: Viewtype) -> Category {
+        match viewtype {
+            Viewtype::Image | Viewtype::Gif | Viewtype::Sticker => Category::Images,
+            Viewtype::Video => Category::Video,
+            Viewtype::Audio | Viewtype::Voice => Category::Audio,
+            _ => Category::Files,
+        }
+    }
+}
+
+impl Context {
+    /// Returns the disk space used by this account.
+    ///
+    /// Scanning the blob directory may take a while for large accounts.
+    /// It is an ongoing process and can be canceled by
+    /// [Context::cancel_operation] with [OperationKind::StorageUsage].
+    pub async fn get_storage_usage(&self) -> Result<StorageUsage> {
+        let operation = self.start_operation(OperationKind::StorageUsage, true)?;
+
+        get_storage_usage_inner(self)
+            .race(async {
+                operation.canceled().await;
+                Err(format_err!("canceled"))
+            })
+            .await
+    }
+}
+
+async fn get_storage_usage_inner(context: &Context) -> Result<StorageUsage> {
+    let mut usage = StorageUsage::default();
+
+    let dbfile = context.get_dbfile();
+    usage.db_bytes = fs::metadata(dbfile).await?.len();
+    let mut wal = dbfile.as_os_str().to_owned();
+    wal.push("-wal");
+    usage.wal_bytes = match fs::metadata(&wal).await {
+        Ok(meta) => meta.len(),
+        Err(_) => 0,
+    };
+
+    let referenced = get_referenced_files(context).await?;
+
+    let mut chat_bytes: HashMap<ChatId, u64> = HashMap::new();
+    let mut entries = fs::read_dir(context.get_blobdir()).await?;
+    while let Some(entry) = entries.next().await {
+        let entry = entry?;
+        let meta = match entry.metadata().await {
+            Ok(meta) if meta.is_file() => meta,
+            _ => continue,
+        };
+        let len = meta.len();
+        usage.blobdir_bytes += len;
+
+        let name = entry.file_name();
+        match referenced.get(&*name.to_string_lossy()) {
+            Some((category, chat_id)) => {
+                match category {
+                    Category::Images => usage.images_bytes += len,
+                    Category::Video => usage.video_bytes += len,
+                    Category::Audio => usage.audio_bytes += len,
+                    Category::Files => usage.files_bytes += len,
+                    Category::Avatars => usage.avatars_bytes += len,
+                }
+                if let Some(chat_id) = chat_id {
+                    *chat_bytes.entry(*chat_id).or_default() += len;
+                }
+            }
+            None => usage.other_bytes += len,
+        }
+    }
+
+    let mut largest_chats: Vec<_> = chat_bytes.into_iter().collect();
+    largest_chats.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    largest_chats.truncate(STORAGE_USAGE_TOP_CHATS);
+    usage.largest_chats = largest_chats;
+
+    Ok(usage)
+}
+
+/// Maps the names of referenced blobs to their category and,
+/// for message attachments, to the chat containing the message.
+///
+/// A file referenced several times is attributed to the first reference.
+async fn get_referenced_files(
+    context: &Context,
+) -> Result<HashMap<String, (Category, Option<ChatId>)>> {
+    let mut files = HashMap::new();
+
+    for query in &["SELECT param FROM chats;", "SELECT param FROM contacts;"] {
+        let params: Vec<String> = context
+            .sql
+            .query_map(
+                *query,
+                paramsv![],
+                |row| row.get::<_, String>(0),
+                |rows| {
+                    rows.collect::<std::result::Result<Vec<_>, _>>()
+                        .map_err(Into::into)
+                },
+            )
+            .await?;
+        for param in params {
+            let param: Params = param.parse().unwrap_or_default();
+            if let Some(name) = param.get(Param::ProfileImage).and_then(blob_name) {
+                files
+                    .entry(name.to_string())
+                    .or_insert((Category::Avatars, None));
+            }
+        }
+    }
+    if let Some(selfavatar) = context.sql.try_get_raw_config(Config::Selfavatar).await? {
+        if let Some(name) = blob_name(&selfavatar) {
+            files
+                .entry(name.to_string())
+                .or_insert((Category::Avatars, None));
+        }
+    }
+
+    context
+        .sql
+        .query_map(
+            "SELECT chat_id, type, param FROM msgs WHERE chat_id!=?;",
+            paramsv![DC_CHAT_ID_TRASH],
+            |row| {
+                let chat_id: ChatId = row.get(0)?;
+                let viewtype: Viewtype = row.get(1)?;
+                let param: String = row.get(2)?;
+                Ok((chat_id, viewtype, param))
+            },
+            |rows| {
+                for row in rows {
+                    let (chat_id, viewtype, param) = row?;
+                    let param: Params = param.parse().unwrap_or_default();
+                    if let Some(name) = param.get(Param::File).and_then(blob_name) {
+                        files
+                            .entry(name.to_string())
+                            .or_insert((Category::from_viewtype(viewtype), Some(chat_id)));
+                    }
+                }
+                Ok(())
+            },
+        )
+        .await?;
+
+    Ok(files)
+}
+
+fn blob_name(path: &str) -> Option<&str> {
+    path.strip_prefix("$BLOBDIR/")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::chat;
+    use crate::test_utils::TestContext;
+
+    async fn add_blob(t: &TestContext, name: &str, len: usize) {
+        fs::write(t.get_blobdir().join(name), vec![0u8; len])
+            .await
+            .unwrap();
+    }
+
+    async fn add_file_msg(t: &TestContext, chat_id: ChatId, viewtype: Viewtype, name: &str) {
+        let mut param = Params::new();
+        param.set(Param::File, format!("$BLOBDIR/{}", name));
+        t.sql
+            .execute(
+                "INSERT INTO msgs (chat_id, type, param) VALUES (?,?,?);",
+                paramsv![chat_id, viewtype, param.to_string()],
+            )
+            .await
+            .unwrap();
+    }
+
+    #[async_std::test]
+    async fn test_storage_usage() {
+        let t = TestContext::new_alice().await;
+        let chat1 = t
+            .create_chat_with_contact("bob", "bob@example.net")
+            .await
+            .id;
+        let chat2 = chat::create_group_chat(&t, chat::ProtectionStatus::Unprotected, "grp")
+            .await
+            .unwrap();
+
+        add_blob(&t, "image.jpg", 100).await;
+        add_blob(&t, "video.mp4", 200).await;
+        add_blob(&t, "voice.opus", 300).await;
+        add_blob(&t, "doc.pdf", 450).await;
+        add_blob(&t, "avatar.png", 50).await;
+        add_blob(&t, "orphan.bin", 500).await;
+
+        add_file_msg(&t, chat1, Viewtype::Image, "image.jpg").await;
+        add_file_msg(&t, chat2, Viewtype::Video, "video.mp4").await;
+        add_file_msg(&t, chat2, Viewtype::Voice, "voice.opus").await;
+        add_file_msg(&t, chat1, Viewtype::File, "doc.pdf").await;
+        // References to missing files are ignored.
+        add_file_msg(&t, chat1, Viewtype::File, "missing.pdf").await;
+        t.sql
+            .execute(
+                "UPDATE chats SET param=? WHERE id=?;",
+                paramsv!["i=$BLOBDIR/avatar.png", chat2],
+            )
+            .await
+            .unwrap();
+
+        let usage = t.get_storage_usage().await.unwrap();
+        assert!(usage.db_bytes > 0);
+        assert_eq!(usage.blobdir_bytes, 1600);
+        assert_eq!(usage.images_bytes, 100);
+        assert_eq!(usage.video_bytes, 200);
+        assert_eq!(usage.audio_bytes, 300);
+        assert_eq!(usage.files_bytes, 450);
+        assert_eq!(usage.avatars_bytes, 50);
+        assert_eq!(usage.other_bytes, 500);
+        assert_eq!(usage.largest_chats, vec![(chat1, 550), (chat2, 500)]);
+
+        // The scan is an ongoing process and is released afterwards.
+        assert!(t.get_running_operations().is_empty());
+    }
+
+    #[async_std::test]
+    async fn test_storage_usage_empty() {
+        let t = TestContext::new().await;
+        let usage = t.get_storage_usage().await.unwrap();
+        assert_eq!(usage.blobdir_bytes, 0);
+        assert_eq!(usage.other_bytes, 0);
+        assert!(usage.largest_chats.is_empty());
+    }
+}
@@ -0,0 +1,474 @@
+//! # Synchronizing settings between own devices
+//!
+//! Some settings, e.g. the display name or the avatar, describe the user rather than a
+//! single device. When such a setting is changed, a hidden sync message is sent to the
+//! "saved messages" chat, i.e. to the own address. Other devices using the same account
+//! receive the message like any other self-sent message and apply the settings from it.
+//!
+//! The sync items are transported as a JSON attachment. The avatar itself is not part of
+//! the items, it is attached using the `Chat-User-Avatar` header as for normal messages.
+//!
+//! Each synchronized key has a timestamp of its last change. Received items are only
+//! applied if they are newer than the local change, so the last writer wins.
+//!
+//! Besides config keys, some settings of chats are synchronized, e.g. the notification
+//! profile. Such items name the chat by its group ID or, for 1:1 chats, by the address of
+//! the contact, and are skipped if the chat does not exist on the receiving device.
+//!
+//! Sending sync messages is disabled by default and enabled by the raw config
+//! flag `send_sync_msgs`.
+
+use anyhow::{bail, format_err, Result};
+use num_traits::FromPrimitive;
+use serde::{Deserialize, Serialize};
+
+use crate::chat::{self, Chat, ChatId};
+use crate::config::Config;
+use crate::constants::{
+    Blocked, Chattype, NotifyProfile, Viewtype, DC_CONTACT_ID_LAST_SPECIAL, DC_CONTACT_ID_SELF,
+};
+use crate::contact::{Contact, Origin};
+use crate::context::Context;
+use crate::dc_tools::time;
+use crate::key::{DcKey, SignedPublicKey};
+use crate::message::Message;
+use crate::mimeparser::{MimeMessage, SystemMessage};
+use crate::param::Param;
+
+/// Name of the attachment carrying the sync items.
+pub(crate) const SYNC_ITEMS_FILENAME: &str = "multi-device-sync.json";
+
+/// Raw config flag enabling sending sync messages.
+const SEND_SYNC_MSGS: &str = "send_sync_msgs";
+
+/// Config keys that are synchronized between own devices.
+pub(crate) const SYNCED_KEYS: [Config; 5] = [
+    Config::Displayname,
+    Config::Selfstatus,
+    Config::Selfavatar,
+    Config::MvboxMove,
+    Config::ShowEmails,
+];
+
+/// Key of the sync item carrying the notification profile of a chat.
+const NOTIFY_PROFILE_KEY: &str = "notify_profile";
+
+/// Whether a config change should be synchronized to other devices.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Sync {
+    Nosync,
+    Sync,
+}
+
+/// A chat as identified on all own devices.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum SyncChat {
+    /// Group with the given group ID.
+    Group(String),
+
+    /// 1:1 chat with the given address.
+    Contact(String),
+}
+
+/// A single synchronized config value or chat setting.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct SyncItem {
+    pub key: String,
+    pub value: Option<String>,
+    pub timestamp: i64,
+
+    /// The chat the setting belongs to, `None` for config keys.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chat: Option<SyncChat>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct SyncItems {
+    pub items: Vec<SyncItem>,
+}
+
+impl SyncItems {
+    pub(crate) fn parse(data: &[u8]) -> Result<Self> {
+        Ok(serde_json::from_slice(data)?)
+    }
+}
+
+fn sync_timestamp_key(key: Config) -> String {
+    format!("sync_timestamp.{}", key)
+}
+
+async fn get_sync_timestamp(context: &Context, key: Config) -> i64 {
+    context
+        .sql
+        .get_raw_config_int64(context, sync_timestamp_key(key))
+        .await
+        .unwrap_or_default()
+}
+
+async fn set_sync_timestamp(context: &Context, key: Config, timestamp: i64) -> Result<()> {
+    context
+        .sql
+        .set_raw_config_int64(context, sync_timestamp_key(key), timestamp)
+        .await?;
+    Ok(())
+}
+
+/// Records local changes of config keys and sends them to the other devices.
+///
+/// Keys that are not synchronized are ignored.
+pub(crate) async fn config_changed(context: &Context, keys: &[Config]) -> Result<()> {
+    let keys: Vec<Config> = keys
+        .iter()
+        .filter(|key| SYNCED_KEYS.contains(*key))
+        .copied()
+        .collect();
+    if keys.is_empty() {
+        return Ok(());
+    }
+
+    let timestamp = time();
+    for key in &keys {
+        set_sync_timestamp(context, *key, timestamp).await?;
+    }
+
+    if !sync_msgs_enabled(context).await {
+        return Ok(());
+    }
+
+    let mut items = SyncItems::default();
+    for key in &keys {
+        let value = match key {
+            // The avatar is attached as a file, see `Chat-User-Avatar`.
+            Config::Selfavatar => None,
+            _ => context.sql.get_raw_config(context, key).await,
+        };
+        items.items.push(SyncItem {
+            key: key.to_string(),
+            value,
+            timestamp,
+            chat: None,
+        });
+    }
+    send_sync_items(context, &items, keys.contains(&Config::Selfavatar)).await
+}
+
+async fn sync_msgs_enabled(context: &Context) -> bool {
+    context
+        .sql
+        .get_raw_config_bool(context, SEND_SYNC_MSGS)
+        .await
+        && context.is_configured().await
+}
+
+/// Returns how the chat is identified on other devices, `None` if it is not synchronized.
+async fn get_sync_chat(context: &Context, chat: &Chat) -> Option<SyncChat> {
+    match chat.typ {
+        Chattype::Group if !chat.grpid.is_empty() => Some(SyncChat::Group(chat.grpid.clone())),
+        Chattype::Single => {
+            let contact_id = *chat::get_chat_contacts(context, chat.id).await.first()?;
+            if contact_id <= DC_CONTACT_ID_LAST_SPECIAL {
+                return None;
+            }
+            let contact = Contact::get_by_id(context, contact_id).await.ok()?;
+            Some(SyncChat::Contact(contact.get_addr().to_string()))
+        }
+        _ => None,
+    }
+}
+
+/// Returns the local chat identified by `sync_chat`, if it exists.
+async fn lookup_sync_chat(context: &Context, sync_chat: &SyncChat) -> Result<Option<ChatId>> {
+    let chat_id = match sync_chat {
+        SyncChat::Group(grpid) => chat::get_chat_id_by_grpid(context, grpid)
+            .await
+            .ok()
+            .map(|(chat_id, _, _)| chat_id),
+        SyncChat::Contact(addr) => {
+            match Contact::lookup_id_by_addr(context, addr, Origin::Unknown).await? {
+                Some(contact_id) => chat::lookup_by_contact_id(context, contact_id)
+                    .await
+                    .ok()
+                    .map(|(chat_id, _)| chat_id),
+                None => None,
+            }
+        }
+    };
+    Ok(chat_id.filter(|chat_id| !chat_id.is_special()))
+}
+
+/// Sends a changed notification profile of a chat to the other devices.
+pub(crate) async fn notify_profile_changed(
+    context: &Context,
+    chat: &Chat,
+    profile: NotifyProfile,
+    timestamp: i64,
+) -> Result<()> {
+    if !sync_msgs_enabled(context).await {
+        return Ok(());
+    }
+    let sync_chat = match get_sync_chat(context, chat).await {
+        Some(sync_chat) => sync_chat,
+        None => return Ok(()),
+    };
+
+    let items = SyncItems {
+        items: vec![SyncItem {
+            key: NOTIFY_PROFILE_KEY.to_string(),
+            value: Some((profile as i32).to_string()),
+            timestamp,
+            chat: Some(sync_chat),
+        }],
+    };
+    send_sync_items(context, &items, false).await
+}
+
+/// Applies a sync item of a chat setting.
+async fn receive_chat_item(context: &Context, item: &SyncItem, sync_chat: &SyncChat) -> Result<()> {
+    let chat_id = match lookup_sync_chat(context, sync_chat).await? {
+        Some(chat_id) => chat_id,
+        None => {
+            info!(
+                context,
+                "Ignoring sync item for unknown chat {:?}", sync_chat
+            );
+            return Ok(());
+        }
+    };
+
+    match item.key.as_str() {
+        NOTIFY_PROFILE_KEY => {
+            let chat = Chat::load_from_db(context, chat_id).await?;
+            let local_timestamp = chat
+                .param
+                .get_i64(Param::NotifyProfileTimestamp)
+                .unwrap_or_default();
+            if item.timestamp <= local_timestamp {
+                info!(context, "Ignoring outdated notify profile of {}", chat_id);
+                return Ok(());
+            }
+            let profile = item
+                .value
+                .as_deref()
+                .and_then(|value| value.parse::<i32>().ok())
+                .and_then(NotifyProfile::from_i32)
+                .ok_or_else(|| format_err!("Invalid notify profile {:?}", item.value))?;
+            chat_id
+                .set_notify_profile_ex(context, Sync::Nosync, profile, item.timestamp)
+                .await?;
+        }
+        key => warn!(context, "Ignoring sync item for {:?} of a chat", key),
+    }
+    Ok(())
+}
+
+async fn send_sync_items(context: &Context, items: &SyncItems, with_avatar: bool) -> Result<()> {
+    let (chat_id, _) =
+        chat::create_or_lookup_by_contact_id(context, DC_CONTACT_ID_SELF, Blocked::Not).await?;
+
+    let mut msg = Message::new(Viewtype::Text);
+    msg.hidden = true;
+    msg.param.set_cmd(SystemMessage::ConfigSync);
+    msg.param.set(Param::Arg, serde_json::to_string(items)?);
+    msg.param.set_int(Param::Arg2, with_avatar as i32);
+    msg.param.set_int(Param::GuaranteeE2ee, 1);
+    chat::send_msg(context, chat_id, &mut msg).await?;
+    Ok(())
+}
+
+/// Applies the sync items of a self-sent sync message.
+///
+/// Items older than the local change of the same key are skipped.
+/// If the avatar item is skipped, the attached avatar is dropped from `mime_parser`
+/// so it is not applied later either.
+pub(crate) async fn receive_sync_items(
+    context: &Context,
+    mime_parser: &mut MimeMessage,
+) -> Result<()> {
+    let self_fingerprint = SignedPublicKey::load_self(context).await?.fingerprint();
+    if !mime_parser.signatures.contains(&self_fingerprint) {
+        mime_parser.user_avatar = None;
+        bail!("Sync message is not signed with own key");
+    }
+
+    let items = mime_parser
+        .sync_items
+        .take()
+        .ok_or_else(|| format_err!("Sync message without sync items"))?;
+
+    let mut apply_avatar = false;
+    for item in items.items {
+        if let Some(sync_chat) = &item.chat {
+            if let Err(err) = receive_chat_item(context, &item, sync_chat).await {
+                warn!(
+                    context,
+                    "Cannot apply sync item for {:?}: {}", sync_chat, err
+                );
+            }
+            continue;
+        }
+
+        let key = match item.key.parse::<Config>() {
+            Ok(key) if SYNCED_KEYS.contains(&key) => key,
+            _ => {
+                warn!(context, "Ignoring sync item for {:?}", item.key);
+                continue;
+            }
+        };
+        if item.timestamp <= get_sync_timestamp(context, key).await {
+            info!(context, "Ignoring outdated sync item for {}", key);
+            continue;
+        }
+
+        if key == Config::Selfavatar {
+            apply_avatar = true;
+        } else {
+            context
+                .set_config_ex(Sync::Nosync, key, item.value.as_deref())
+                .await?;
+        }
+        set_sync_timestamp(context, key, item.timestamp).await?;
+    }
+
+    if !apply_avatar {
+        mime_parser.user_avatar = None;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::constants::DC_CHAT_ID_LAST_SPECIAL;
+    use crate::test_utils::TestContext;
+
+    async fn enable_sync(t: &TestContext) {
+        t.sql
+            .set_raw_config_bool(t, SEND_SYNC_MSGS, true)
+            .await
+            .unwrap();
+    }
+
+    #[async_std::test]
+    async fn test_sync_displayname() {
+        let alice1 = TestContext::new_alice().await;
+        let alice2 = TestContext::new_alice().await;
+        enable_sync(&alice1).await;
+
+        alice1
+            .set_config(Config::Displayname, Some("Alice Liddell"))
+            .await
+            .unwrap();
+        let sent = alice1.pop_sent_msg().await;
+        let self_chat = alice1.get_self_chat().await;
+        assert_eq!(
+            chat::get_chat_msgs(&alice1, self_chat.id, 0, None)
+                .await
+                .len(),
+            0
+        );
+
+        alice2.recv_msg(&sent).await;
+        assert_eq!(
+            alice2.get_config(Config::Displayname).await,
+            Some("Alice Liddell".to_string())
+        );
+        // The sync message is not shown anywhere.
+        let visible_msgs: i32 = alice2
+            .sql
+            .query_get_value(
+                &alice2,
+                "SELECT COUNT(*) FROM msgs WHERE hidden=0 AND chat_id>?;",
+                paramsv![DC_CHAT_ID_LAST_SPECIAL],
+            )
+            .await
+            .unwrap_or_default();
+        assert_eq!(visible_msgs, 0);
+    }
+
+    #[async_std::test]
+    async fn test_sync_last_writer_wins() {
+        let alice1 = TestContext::new_alice().await;
+        let alice2 = TestContext::new_alice().await;
+        enable_sync(&alice1).await;
+
+        alice1
+            .set_config(Config::ShowEmails, Some("2"))
+            .await
+            .unwrap();
+        let sent = alice1.pop_sent_msg().await;
+
+        // A later local change on the second device is kept.
+        alice2
+            .set_config(Config::ShowEmails, Some("1"))
+            .await
+            .unwrap();
+        set_sync_timestamp(&alice2, Config::ShowEmails, time() + 10)
+            .await
+            .unwrap();
+        alice2.recv_msg(&sent).await;
+        assert_eq!(
+            alice2.get_config(Config::ShowEmails).await,
+            Some("1".to_string())
+        );
+    }
+
+    #[async_std::test]
+    async fn test_sync_disabled() {
+        let alice = TestContext::new_alice().await;
+        alice
+            .set_config(Config::Displayname, Some("Alice"))
+            .await
+            .unwrap();
+        assert!(get_sync_timestamp(&alice, Config::Displayname).await > 0);
+        let jobs: i32 = alice
+            .sql
+            .query_get_value(&alice, "SELECT COUNT(*) FROM jobs;", paramsv![])
+            .await
+            .unwrap_or_default();
+        assert_eq!(jobs, 0);
+    }
+
+    #[async_std::test]
+    async fn test_sync_notify_profile() {
+        let alice1 = TestContext::new_alice().await;
+        let alice2 = TestContext::new_alice().await;
+        enable_sync(&alice1).await;
+        let chat1 = alice1
+            .create_chat_with_contact("Bob", "bob@example.net")
+            .await;
+        let chat2 = alice2
+            .create_chat_with_contact("Bob", "bob@example.net")
+            .await;
+
+        chat1
+            .id
+            .set_notify_profile(&alice1, NotifyProfile::Silent)
+            .await
+            .unwrap();
+        let sent = alice1.pop_sent_msg().await;
+        alice2.recv_msg(&sent).await;
+        assert_eq!(
+            chat2.id.get_notify_profile(&alice2).await.unwrap(),
+            NotifyProfile::Silent
+        );
+
+        // A newer local change is not overwritten by an older one.
+        chat2
+            .id
+            .set_notify_profile_ex(&alice2, Sync::Nosync, NotifyProfile::Priority, time() + 10)
+            .await
+            .unwrap();
+        chat1
+            .id
+            .set_notify_profile(&alice1, NotifyProfile::Default)
+            .await
+            .unwrap();
+        alice2.recv_msg(&alice1.pop_sent_msg().await).await;
+        assert_eq!(
+            chat2.id.get_notify_profile(&alice2).await.unwrap(),
+            NotifyProfile::Priority
+        );
+    }
+}
@@ -1,6 +1,7 @@
 //! Utilities to help writing tests.
 //!
-//! This private module is only compiled for test runs.
+//! This module is only compiled for test runs and with the `internals` feature,
+//! which makes it available to the tests of other crates.
 
 use std::ops::Deref;
 use std::str::FromStr;
@@ -9,10 +10,12 @@
 use std::{fmt, thread};
 
 use ansi_term::Color;
+use anyhow::{ensure, Context as _, Result};
 use async_std::path::PathBuf;
 use async_std::sync::{Arc, RwLock};
 use async_std::{channel, pin::Pin};
 use async_std::{future::Future, task};
+use async_trait::async_trait;
 use chat::ChatItem;
 use once_cell::sync::Lazy;
 use tempfile::{tempdir, TempDir};
@@ -22,11 +25,12 @@
 use crate::config::Config;
 use crate::constants::Chattype;
 use crate::constants::{Viewtype, DC_CONTACT_ID_SELF, DC_MSG_ID_DAYMARKER, DC_MSG_ID_MARKER1};
-use crate::contact::{Contact, Origin};
+use crate::contact::{addr_cmp, Contact, Origin};
 use crate::context::Context;
 use crate::dc_receive_imf::dc_receive_imf;
 use crate::dc_tools::EmailAddress;
 use crate::events::{Event, EventType};
+use crate::imap::{prefetch_get_message_id, ImapActionResult, ImapSession};
 use crate::job::Action;
 use crate::key::{self, DcKey};
 use crate::message::{update_msg_state, Message, MessageState, MsgId};
@@ -44,7 +48,7 @@
 ///
 /// The temporary directory can be used to store the SQLite database,
 /// see e.g. [test_context] which does this.
-pub(crate) struct TestContext {
+pub struct TestContext {
     pub ctx: Context,
     pub dir: TempDir,
     /// Counter for fake IMAP UIDs in [recv_msg], for private use in that function only.
@@ -249,8 +253,44 @@ pub async fn pop_sent_msg(&self) -> SentMessage {
                 panic!("no sent message found in jobs table");
             }
         };
+        self.take_sent_job(rowid, foreign_id, &raw_params).await
+    }
+
+    /// Retrieves all sent messages from the jobs table in the order they have been sent.
+    ///
+    /// Unlike [TestContext::pop_sent_msg] this does not wait for messages to be scheduled,
+    /// the result is empty if there are none.
+    pub async fn take_sent_msgs(&self) -> Vec<SentMessage> {
+        let jobs = self
+            .ctx
+            .sql
+            .query_map(
+                "SELECT id, foreign_id, param FROM jobs WHERE action=? ORDER BY id;",
+                paramsv![Action::SendMsgToSmtp],
+                |row| {
+                    let id: i64 = row.get(0)?;
+                    let foreign_id: i64 = row.get(1)?;
+                    let param: String = row.get(2)?;
+                    Ok((id, foreign_id, param))
+                },
+                |rows| {
+                    rows.collect::<std::result::Result<Vec<_>, _>>()
+                        .map_err(Into::into)
+                },
+            )
+            .await
+            .unwrap();
+        let mut sent = Vec::with_capacity(jobs.len());
+        for (rowid, foreign_id, raw_params) in jobs {
+            sent.push(self.take_sent_job(rowid, foreign_id, &raw_params).await);
+        }
+        sent
+    }
+
+    /// Removes a job sending a message and marks the message as delivered.
+    async fn take_sent_job(&self, rowid: i64, foreign_id: i64, raw_params: &str) -> SentMessage {
         let id = MsgId::new(foreign_id as u32);
-        let params = Params::from_str(&raw_params).unwrap();
+        let params = Params::from_str(raw_params).unwrap();
         let blob_path = params
             .get_blob(Param::File, &self.ctx, false)
             .await
@@ -511,12 +551,151 @@ pub fn recipient(&self) -> EmailAddress {
         rcpt.parse().expect("failed to parse email address")
     }
 
+    /// All recipients the message was destined for.
+    pub fn recipients(&self) -> Vec<String> {
+        self.params
+            .get(Param::Recipients)
+            .unwrap_or_default()
+            .split(' ')
+            .filter(|addr| !addr.is_empty())
+            .map(|addr| addr.to_string())
+            .collect()
+    }
+
     /// The raw message payload.
     pub fn payload(&self) -> String {
         std::fs::read_to_string(&self.blob_path).unwrap()
     }
 }
 
+/// Delivers the messages sent by [TestContext]s to each other in-process.
+///
+/// Sent messages are taken from the jobs table, as [TestContext::pop_sent_msg] does, and
+/// received by every context whose address is among the recipients.  To test how the
+/// receive pipeline copes with real transports, messages can be delayed, dropped and
+/// reordered.  All of this is deterministic, delays are counted in calls to
+/// [TestTransport::deliver_all] rather than in time.
+#[derive(Debug, Default)]
+pub struct TestTransport {
+    /// Messages sent but not delivered yet.
+    queue: Vec<InFlight>,
+    /// Number of rounds newly sent messages are held back.
+    delay: u32,
+    /// Number of newly sent messages to drop.
+    drop: usize,
+    /// Whether the messages of a round are delivered in reverse order.
+    reverse: bool,
+}
+
+#[derive(Debug)]
+struct InFlight {
+    msg: SentMessage,
+    /// Number of rounds to wait before delivering the message.
+    rounds_left: u32,
+}
+
+impl TestTransport {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Holds back messages sent from now on for `rounds` calls to [TestTransport::deliver_all].
+    pub fn set_delay(&mut self, rounds: u32) {
+        self.delay = rounds;
+    }
+
+    /// Drops the next `count` messages sent.
+    pub fn drop_next(&mut self, count: usize) {
+        self.drop = count;
+    }
+
+    /// Delivers the messages due in a round in reverse order if set.
+    pub fn set_reverse(&mut self, reverse: bool) {
+        self.reverse = reverse;
+    }
+
+    /// Returns the number of messages sent but not delivered yet.
+    pub fn pending(&self) -> usize {
+        self.queue.len()
+    }
+
+    /// Takes the messages sent by `contexts` and queues them for delivery.
+    pub async fn collect(&mut self, contexts: &[&TestContext]) {
+        for t in contexts {
+            for msg in t.take_sent_msgs().await {
+                if self.drop > 0 {
+                    self.drop -= 1;
+                    continue;
+                }
+                self.queue.push(InFlight {
+                    msg,
+                    rounds_left: self.delay,
+                });
+            }
+        }
+    }
+
+    /// Collects the messages sent by `contexts` and delivers all messages that are due.
+    ///
+    /// Returns the number of messages delivered.
+    pub async fn deliver_all(&mut self, contexts: &[&TestContext]) -> usize {
+        self.collect(contexts).await;
+
+        let (mut due, pending): (Vec<_>, Vec<_>) = self
+            .queue
+            .drain(..)
+            .partition(|in_flight| in_flight.rounds_left == 0);
+        self.queue = pending
+            .into_iter()
+            .map(|mut in_flight| {
+                in_flight.rounds_left -= 1;
+                in_flight
+            })
+            .collect();
+        if self.reverse {
+            due.reverse();
+        }
+
+        for in_flight in &due {
+            let recipients = in_flight.msg.recipients();
+            for t in contexts {
+                let addr = t
+                    .get_config(Config::ConfiguredAddr)
+                    .await
+                    .unwrap_or_default();
+                if recipients.iter().any(|rcpt| addr_cmp(rcpt, &addr)) {
+                    t.recv_msg(&in_flight.msg).await;
+                }
+            }
+        }
+        due.len()
+    }
+}
+
+/// Alice and Bob, connected by a [TestTransport].
+#[derive(Debug)]
+pub struct TestContextPair {
+    pub alice: TestContext,
+    pub bob: TestContext,
+    pub transport: TestTransport,
+}
+
+impl TestContextPair {
+    /// Creates the configured contexts of [TestContext::new_alice] and [TestContext::new_bob].
+    pub async fn new() -> Self {
+        Self {
+            alice: TestContext::new_alice().await,
+            bob: TestContext::new_bob().await,
+            transport: TestTransport::new(),
+        }
+    }
+
+    /// Delivers the messages sent by alice and bob, see [TestTransport::deliver_all].
+    pub async fn deliver_all(&mut self) -> usize {
+        self.transport.deliver_all(&[&self.alice, &self.bob]).await
+    }
+}
+
 /// Load a pre-generated keypair for alice@example.com from disk.
 ///
 /// This saves CPU cycles by avoiding having to generate a key.
@@ -557,7 +736,7 @@ pub fn bob_keypair() -> key::KeyPair {
 ///
 /// Panics if the length of the chat is not `asserted_msgs_count` or if the chat item at `index` is not a Message.
 #[allow(clippy::indexing_slicing)]
-pub(crate) async fn get_chat_msg(
+pub async fn get_chat_msg(
     t: &TestContext,
     chat_id: ChatId,
     index: usize,
@@ -573,6 +752,173 @@ pub(crate) async fn get_chat_msg(
     Message::load_from_db(&t.ctx, msg_id).await.unwrap()
 }
 
+/// Receives a chat message from `from` to alice@example.com with the IMAP UID `uid`.
+///
+/// The Message-ID is derived from `uid`, so every UID gives a new message.
+pub async fn receive_chat_msg(context: &Context, from: &str, uid: u32) {
+    let imf = chat_msg(from, &format!("{}@example.org", uid), "hello");
+    dc_receive_imf(context, imf.as_bytes(), "INBOX", uid, false)
+        .await
+        .unwrap();
+}
+
+/// Returns a raw chat message from `from` to alice@example.com with the Message-ID `rfc724_mid`.
+pub fn chat_msg(from: &str, rfc724_mid: &str, text: &str) -> String {
+    format!(
+        "From: {}\n\
+         To: alice@example.com\n\
+         Subject: foo\n\
+         Message-ID: <{}>\n\
+         Chat-Version: 1.0\n\
+         Date: Sun, 22 Mar 2020 22:37:57 +0000\n\
+         \n\
+         {}\n",
+        from, rfc724_mid, text
+    )
+}
+
+/// IMAP server for the tests of background jobs, storing raw messages by folder and UID.
+#[derive(Debug, Default)]
+pub struct MockSession {
+    /// Raw messages by folder and UID.
+    pub folders: BTreeMap<String, BTreeMap<u32, Vec<u8>>>,
+
+    /// UIDs requested with `fetch_message_ids()`.
+    pub fetched_ids: Vec<Vec<u32>>,
+
+    /// UIDs downloaded with `fetch_msgs()` and `fetch_single_msg()`.
+    pub downloaded: Vec<u32>,
+
+    /// Makes `delete_msgs()` fail like a lost connection.
+    pub fail_delete: bool,
+
+    /// Usage of the `STORAGE` resource reported by `GETQUOTAROOT`,
+    /// `None` if the server does not support quotas.
+    pub quota_usage: Option<u64>,
+
+    /// Number of `GETQUOTAROOT` commands received.
+    pub quota_queries: usize,
+}
+
+impl MockSession {
+    /// Stores the raw message `raw` with the UID `uid` in `folder`.
+    pub fn store(&mut self, folder: &str, uid: u32, raw: impl Into<Vec<u8>>) {
+        self.folders
+            .entry(folder.to_string())
+            .or_default()
+            .insert(uid, raw.into());
+    }
+
+    /// Stores the raw message `raw` on the server and receives it as if it was fetched.
+    pub async fn receive(&mut self, context: &Context, folder: &str, uid: u32, raw: &str) {
+        self.store(folder, uid, raw);
+        dc_receive_imf(context, raw.as_bytes(), folder, uid, false)
+            .await
+            .unwrap();
+    }
+
+    /// Returns the raw message with the UID `uid` in `folder`.
+    pub fn get(&self, folder: &str, uid: u32) -> Option<&[u8]> {
+        self.folders.get(folder)?.get(&uid).map(Vec::as_slice)
+    }
+
+    /// Returns the UIDs of the messages in `folder`.
+    pub fn uids(&self, folder: &str) -> Vec<u32> {
+        self.folders
+            .get(folder)
+            .map(|msgs| msgs.keys().copied().collect())
+            .unwrap_or_default()
+    }
+}
+
+#[async_trait]
+impl ImapSession for MockSession {
+    async fn fetch_message_ids(
+        &mut self,
+        _context: &Context,
+        folder: &str,
+        uids: &[u32],
+    ) -> Result<BTreeMap<u32, String>> {
+        self.fetched_ids.push(uids.to_vec());
+        let msgs = self.folders.get(folder).context("no such folder")?;
+        Ok(uids
+            .iter()
+            .filter_map(|uid| Some((*uid, get_message_id(msgs.get(uid)?)?)))
+            .collect())
+    }
+
+    async fn fetch_all_message_ids(
+        &mut self,
+        _context: &Context,
+        folder: &str,
+    ) -> Result<BTreeMap<u32, String>> {
+        Ok(self
+            .folders
+            .get(folder)
+            .map(|msgs| {
+                msgs.iter()
+                    .filter_map(|(uid, raw)| Some((*uid, get_message_id(raw)?)))
+                    .collect()
+            })
+            .unwrap_or_default())
+    }
+
+    async fn fetch_msgs(&mut self, context: &Context, folder: &str, uids: Vec<u32>) -> Result<()> {
+        for uid in uids {
+            let raw = self.get(folder, uid).context("no such message")?;
+            dc_receive_imf(context, raw, folder, uid, false).await?;
+            self.downloaded.push(uid);
+        }
+        Ok(())
+    }
+
+    async fn fetch_single_msg(
+        &mut self,
+        context: &Context,
+        folder: &str,
+        uid: u32,
+    ) -> ImapActionResult {
+        let raw = match self.get(folder, uid) {
+            Some(raw) => raw,
+            None => return ImapActionResult::Failed,
+        };
+        let res = dc_receive_imf(context, raw, folder, uid, false).await;
+        match res {
+            Ok(()) => {
+                self.downloaded.push(uid);
+                ImapActionResult::Success
+            }
+            Err(_) => ImapActionResult::RetryLater,
+        }
+    }
+
+    async fn delete_msgs(&mut self, _context: &Context, folder: &str, uids: &[u32]) -> Result<()> {
+        ensure!(!self.fail_delete, "connection lost");
+        let msgs = self.folders.get_mut(folder).context("no such folder")?;
+        for uid in uids {
+            msgs.remove(uid);
+        }
+        Ok(())
+    }
+
+    async fn query_quota_root(&mut self, folder: &str) -> Result<Option<String>> {
+        self.quota_queries += 1;
+        Ok(self.quota_usage.map(|usage| {
+            format!(
+                "* QUOTAROOT {} \"User quota\"\r\n\
+                 * QUOTA \"User quota\" (STORAGE {} 1000 MESSAGE 5 100)\r\n",
+                folder, usage
+            )
+        }))
+    }
+}
+
+/// Returns the Message-ID of a raw message the same way as it is read from the server.
+fn get_message_id(raw: &[u8]) -> Option<String> {
+    let (headers, _) = mailparse::parse_headers(raw).ok()?;
+    prefetch_get_message_id(&headers).ok()
+}
+
 /// Pretty-print an event to stdout
 ///
 /// Done during tests this is captured by `cargo test` and associated with the test itself.
@@ -696,3 +1042,87 @@ async fn log_msg(context: &Context, prefix: impl AsRef<str>, msg: &Message) {
         statestr,
     );
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message;
+
+    /// Sends texts from alice to bob without delivering them.
+    async fn send_texts(pair: &TestContextPair, texts: &[&str]) -> ChatId {
+        let alice_chat = pair.alice.create_chat(&pair.bob).await;
+        for text in texts {
+            chat::send_text_msg(&pair.alice, alice_chat.id, text.to_string())
+                .await
+                .unwrap();
+        }
+        pair.bob.create_chat(&pair.alice).await.id
+    }
+
+    async fn received_texts(t: &TestContext, chat_id: ChatId) -> Vec<String> {
+        let mut texts = Vec::new();
+        for item in chat::get_chat_msgs(t, chat_id, 0, None).await {
+            if let ChatItem::Message { msg_id } = item {
+                let msg = Message::load_from_db(t, msg_id).await.unwrap();
+                texts.push(msg.get_text().unwrap_or_default());
+            }
+        }
+        texts
+    }
+
+    #[async_std::test]
+    async fn test_transport_reorders() {
+        let mut pair = TestContextPair::new().await;
+        let alice_chat = pair.alice.create_chat(&pair.bob).await;
+        let bob_chat = pair.bob.create_chat(&pair.alice).await;
+        let file = pair.alice.get_blobdir().join("app.json");
+        async_std::fs::write(&file, b"{}").await.unwrap();
+        let mut msg = Message::new(Viewtype::File);
+        msg.set_file(file.to_str().unwrap(), None);
+        let alice_msg_id = chat::send_msg(&pair.alice, alice_chat.id, &mut msg)
+            .await
+            .unwrap();
+        assert_eq!(pair.deliver_all().await, 1);
+        let bob_msg = pair.bob.get_last_msg_in(bob_chat.id).await;
+
+        for update in &[r#"{"move":1}"#, r#"{"move":2}"#, r#"{"move":3}"#] {
+            message::send_status_update(&pair.alice, alice_msg_id, update)
+                .await
+                .unwrap();
+        }
+        pair.transport.set_reverse(true);
+        assert_eq!(pair.deliver_all().await, 3);
+        assert_eq!(pair.transport.pending(), 0);
+
+        // Bob receives the updates in reverse order, but sees them in the order they were sent.
+        let updates = message::get_status_updates(&pair.bob, bob_msg.id, 0)
+            .await
+            .unwrap();
+        let serials: Vec<u32> = updates.iter().map(|update| update.serial).collect();
+        assert!(serials.windows(2).all(|w| w.first() > w.last()));
+        let payloads: Vec<&str> = updates.iter().map(|update| update.json.as_str()).collect();
+        assert_eq!(
+            payloads,
+            vec![r#"{"move":1}"#, r#"{"move":2}"#, r#"{"move":3}"#]
+        );
+    }
+
+    #[async_std::test]
+    async fn test_transport_delays_and_drops() {
+        let mut pair = TestContextPair::new().await;
+        pair.transport.set_delay(1);
+        pair.transport.drop_next(1);
+        let bob_chat_id = send_texts(&pair, &["dropped", "delayed"]).await;
+
+        assert_eq!(pair.deliver_all().await, 0);
+        assert_eq!(pair.transport.pending(), 1);
+        assert!(received_texts(&pair.bob, bob_chat_id).await.is_empty());
+
+        assert_eq!(pair.deliver_all().await, 1);
+        assert_eq!(pair.transport.pending(), 0);
+        assert_eq!(
+            received_texts(&pair.bob, bob_chat_id).await,
+            vec!["delayed"]
+        );
+    }
+}
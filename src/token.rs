@@ -9,6 +9,7 @@
 use crate::chat::ChatId;
 use crate::context::Context;
 use crate::dc_tools::{dc_create_id, time};
+use crate::sql;
 
 /// Token namespace
 #[derive(
@@ -19,8 +20,16 @@ pub enum Namespace {
     Unknown = 0,
     Auth = 110,
     InviteNumber = 100,
+
+    /// Invitenumbers and auths revoked by [crate::securejoin::revoke_qr].
+    ///
+    /// These are kept to tell joiners that the QR code was revoked.
+    Revoked = 120,
 }
 
+/// Seconds revoked tokens are kept to tell joiners that the QR code was revoked.
+pub(crate) const REVOKED_TOKEN_LIFETIME: i64 = 30 * 24 * 60 * 60;
+
 impl Default for Namespace {
     fn default() -> Self {
         Namespace::Unknown
@@ -105,3 +114,70 @@ pub async fn exists(context: &Context, namespace: Namespace, token: &str) -> boo
         .await
         .unwrap_or_default()
 }
+
+/// Returns the number of tokens in the namespace for the chat.
+pub async fn count(
+    context: &Context,
+    namespace: Namespace,
+    chat: Option<ChatId>,
+) -> sql::Result<usize> {
+    // foreign_id is declared as `INTEGER DEFAULT 0` in the schema.
+    let foreign_id = chat.unwrap_or_default();
+    let count: Option<i32> = context
+        .sql
+        .query_get_value_result(
+            "SELECT COUNT(*) FROM tokens WHERE namespc=? AND foreign_id=?;",
+            paramsv![namespace, foreign_id],
+        )
+        .await?;
+    Ok(count.unwrap_or_default() as usize)
+}
+
+/// Deletes the tokens of chats that do not exist anymore.
+///
+/// Returns the number of deleted tokens.
+pub(crate) async fn prune(context: &Context) -> sql::Result<usize> {
+    context
+        .sql
+        .execute(
+            "DELETE FROM tokens WHERE foreign_id!=0 AND foreign_id NOT IN (SELECT id FROM chats);",
+            paramsv![],
+        )
+        .await
+}
+
+/// Deletes the tokens revoked more than [REVOKED_TOKEN_LIFETIME] seconds ago.
+///
+/// Joiners scanning such old QR codes do not get a rejection anymore.
+///
+/// Returns the number of deleted tokens.
+pub(crate) async fn prune_revoked(context: &Context) -> sql::Result<usize> {
+    context
+        .sql
+        .execute(
+            "DELETE FROM tokens WHERE namespc=? AND timestamp<?;",
+            paramsv![Namespace::Revoked, time() - REVOKED_TOKEN_LIFETIME],
+        )
+        .await
+}
+
+/// Moves the invitenumbers and auths of the chat to the [Namespace::Revoked] namespace.
+///
+/// Afterwards [lookup_or_new] creates new tokens for the chat.
+pub async fn revoke(context: &Context, chat: Option<ChatId>) -> sql::Result<()> {
+    let foreign_id = chat.unwrap_or_default();
+    context
+        .sql
+        .execute(
+            "UPDATE tokens SET namespc=?, timestamp=? WHERE namespc IN (?, ?) AND foreign_id=?;",
+            paramsv![
+                Namespace::Revoked,
+                time(),
+                Namespace::InviteNumber,
+                Namespace::Auth,
+                foreign_id
+            ],
+        )
+        .await?;
+    Ok(())
+}
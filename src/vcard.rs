@@ -0,0 +1,489 @@
+//! # vCard support
+//!
+//! Reading and writing contacts as defined in
+//! [RFC 6350](https://tools.ietf.org/html/rfc6350) (vCard 4.0) and
+//! [RFC 2426](https://tools.ietf.org/html/rfc2426) (vCard 3.0).
+//! Cards in the older vCard 2.1 format, as exported by Android, are read as well.
+//!
+//! Only the properties needed for contacts are supported: the name,
+//! the email addresses and the photo. Other properties are ignored.
+
+use anyhow::{bail, ensure, format_err, Result};
+
+/// A contact as contained in a vCard.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub(crate) struct VcardContact {
+    /// The formatted name, empty if the card has none.
+    pub display_name: String,
+    pub addrs: Vec<String>,
+    /// The photo data and a file extension matching its type.
+    pub photo: Option<(Vec<u8>, String)>,
+}
+
+/// A content line, `[group.]name[;param...]:value`.
+#[derive(Debug)]
+struct Property {
+    /// The uppercased name without group.
+    name: String,
+    params: Vec<String>,
+    value: String,
+}
+
+impl Property {
+    fn parse(line: &str) -> Option<Self> {
+        let colon = line.find(':')?;
+        let head = line.get(..colon)?;
+        let value = line.get(colon + 1..)?;
+        let mut parts = head.split(';');
+        let name = parts
+            .next()?
+            .rsplit('.')
+            .next()?
+            .trim()
+            .to_ascii_uppercase();
+        if name.is_empty() {
+            return None;
+        }
+        let params: Vec<String> = parts.map(|param| param.trim().to_string()).collect();
+        let mut property = Property {
+            name,
+            params,
+            value: value.to_string(),
+        };
+        if property.has_param("ENCODING", "QUOTED-PRINTABLE") {
+            property.value = decode_quoted_printable(&property.value);
+        }
+        Some(property)
+    }
+
+    /// Returns true if the parameter `key` has the given value, case-insensitively.
+    ///
+    /// Parameters without key, as `EMAIL;HOME:` or `PHOTO;BASE64:` in vCard 2.1,
+    /// match any key.
+    fn has_param(&self, key: &str, value: &str) -> bool {
+        self.params.iter().any(|param| {
+            let (param_key, values) = match param.find('=') {
+                Some(eq) => (
+                    param.get(..eq).unwrap_or_default(),
+                    param.get(eq + 1..).unwrap_or_default(),
+                ),
+                None => (key, param.as_str()),
+            };
+            param_key.eq_ignore_ascii_case(key)
+                && values
+                    .split(',')
+                    .any(|v| v.trim_matches('"').eq_ignore_ascii_case(value))
+        })
+    }
+
+    /// Returns the value of the `TYPE` parameter of a vCard 3.0 photo,
+    /// or the first parameter without key for vCard 2.1.
+    fn photo_type(&self) -> &str {
+        self.params
+            .iter()
+            .find_map(|param| match param.find('=') {
+                Some(eq) => {
+                    let key = param.get(..eq).unwrap_or_default();
+                    if key.eq_ignore_ascii_case("TYPE") {
+                        param.get(eq + 1..)
+                    } else {
+                        None
+                    }
+                }
+                None if !param.eq_ignore_ascii_case("BASE64") => Some(param.as_str()),
+                None => None,
+            })
+            .unwrap_or_default()
+    }
+}
+
+/// Parses all cards of a vCard file.
+///
+/// The cards are parsed independently, so a malformed card does not affect the others.
+pub(crate) fn parse_vcards(vcard: &str) -> Vec<Result<VcardContact>> {
+    let mut cards = Vec::new();
+    let mut current: Option<Vec<Property>> = None;
+    for line in unfold_lines(vcard) {
+        let property = match Property::parse(&line) {
+            Some(property) => property,
+            None => continue,
+        };
+        match property.name.as_str() {
+            "BEGIN" if property.value.trim().eq_ignore_ascii_case("VCARD") => {
+                if current.is_some() {
+                    cards.push(Err(format_err!("missing END:VCARD")));
+                }
+                current = Some(Vec::new());
+            }
+            "END" if property.value.trim().eq_ignore_ascii_case("VCARD") => {
+                if let Some(properties) = current.take() {
+                    cards.push(parse_card(&properties));
+                }
+            }
+            _ => {
+                if let Some(properties) = current.as_mut() {
+                    properties.push(property);
+                }
+            }
+        }
+    }
+    if current.is_some() {
+        cards.push(Err(format_err!("missing END:VCARD")));
+    }
+    cards
+}
+
+fn parse_card(properties: &[Property]) -> Result<VcardContact> {
+    let mut card = VcardContact::default();
+    let mut structured_name = String::new();
+    for property in properties {
+        match property.name.as_str() {
+            "FN" => card.display_name = unescape_text(&property.value).trim().to_string(),
+            "N" => {
+                // Family; Given; Additional; Prefixes; Suffixes
+                let components = split_components(&property.value);
+                structured_name = components
+                    .iter()
+                    .take(2)
+                    .rev()
+                    .map(|name| name.trim())
+                    .filter(|name| !name.is_empty())
+                    .collect::<Vec<_>>()
+                    .join(" ");
+            }
+            "EMAIL" => {
+                let addr = unescape_text(&property.value).trim().to_string();
+                if !addr.is_empty() && !card.addrs.contains(&addr) {
+                    card.addrs.push(addr);
+                }
+            }
+            "PHOTO" => {
+                if let Some(photo) = parse_photo(property)? {
+                    card.photo = Some(photo);
+                }
+            }
+            _ => {}
+        }
+    }
+    if card.display_name.is_empty() {
+        card.display_name = structured_name;
+    }
+    ensure!(!card.addrs.is_empty(), "no email address");
+    Ok(card)
+}
+
+/// Returns the photo data of a `PHOTO` property.
+///
+/// Photos referenced by URI are not supported and ignored.
+fn parse_photo(property: &Property) -> Result<Option<(Vec<u8>, String)>> {
+    let value = property.value.trim();
+    if let Some(data_url) = value.strip_prefix("data:") {
+        let comma = data_url
+            .find(',')
+            .ok_or_else(|| format_err!("invalid photo data URL"))?;
+        let meta = data_url.get(..comma).unwrap_or_default();
+        let data = data_url.get(comma + 1..).unwrap_or_default();
+        ensure!(
+            meta.to_ascii_lowercase().ends_with(";base64"),
+            "photo data URL is not base64 encoded"
+        );
+        let mime = meta.split(';').next().unwrap_or_default();
+        Ok(Some((decode_base64(data)?, photo_extension(mime))))
+    } else if property.has_param("ENCODING", "B") || property.has_param("ENCODING", "BASE64") {
+        Ok(Some((
+            decode_base64(value)?,
+            photo_extension(property.photo_type()),
+        )))
+    } else {
+        Ok(None)
+    }
+}
+
+fn decode_base64(data: &str) -> Result<Vec<u8>> {
+    let data: String = data.chars().filter(|c| !c.is_whitespace()).collect();
+    match base64::decode(&data) {
+        Ok(data) => Ok(data),
+        Err(err) => bail!("invalid photo: {}", err),
+    }
+}
+
+/// Returns a file extension for a photo type as `JPEG` or `image/jpeg`.
+fn photo_extension(typ: &str) -> String {
+    let typ = typ.trim_matches('"').to_ascii_lowercase();
+    match typ.rsplit('/').next().unwrap_or_default() {
+        "" | "jpeg" | "jpg" | "pjpeg" => "jpg".to_string(),
+        subtype => subtype
+            .chars()
+            .filter(|c| c.is_ascii_alphanumeric())
+            .collect(),
+    }
+}
+
+fn photo_mime_type(extension: &str) -> String {
+    match extension {
+        "" | "jpg" | "jpeg" => "image/jpeg".to_string(),
+        extension => format!("image/{}", extension),
+    }
+}
+
+/// Joins folded lines and quoted-printable soft line breaks.
+fn unfold_lines(vcard: &str) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+    let mut soft_break = false;
+    for line in vcard.lines() {
+        if let Some(last) = lines.last_mut() {
+            if soft_break {
+                last.pop();
+                last.push_str(line);
+                soft_break = is_soft_break(last);
+                continue;
+            }
+            if line.starts_with(' ') || line.starts_with('\t') {
+                last.push_str(line.get(1..).unwrap_or_default());
+                continue;
+            }
+        }
+        lines.push(line.to_string());
+        soft_break = is_soft_break(line);
+    }
+    lines
+}
+
+/// Returns true if `line` is a quoted-printable value continued on the next line.
+fn is_soft_break(line: &str) -> bool {
+    line.ends_with('=')
+        && line
+            .find(':')
+            .and_then(|colon| line.get(..colon))
+            .map_or(false, |head| {
+                head.to_ascii_uppercase().contains("QUOTED-PRINTABLE")
+            })
+}
+
+fn decode_quoted_printable(value: &str) -> String {
+    let mut decoded = Vec::new();
+    let mut bytes = value.bytes();
+    while let Some(byte) = bytes.next() {
+        if byte != b'=' {
+            decoded.push(byte);
+            continue;
+        }
+        let hi = bytes.next();
+        let lo = bytes.next();
+        let digit = |b: Option<u8>| b.and_then(|b| (b as char).to_digit(16));
+        match (digit(hi), digit(lo)) {
+            (Some(hi), Some(lo)) => decoded.push((hi * 16 + lo) as u8),
+            _ => {
+                decoded.push(byte);
+                decoded.extend(hi);
+                decoded.extend(lo);
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+fn unescape_text(value: &str) -> String {
+    let mut unescaped = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            match chars.next() {
+                Some('n') | Some('N') => unescaped.push('\n'),
+                Some(c) => unescaped.push(c),
+                None => unescaped.push('\\'),
+            }
+        } else {
+            unescaped.push(c);
+        }
+    }
+    unescaped
+}
+
+fn escape_text(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace(',', "\\,")
+        .replace(';', "\\;")
+        .replace("\r\n", "\\n")
+        .replace('\n', "\\n")
+}
+
+/// Splits a structured value at unescaped semicolons and unescapes the components.
+fn split_components(value: &str) -> Vec<String> {
+    let mut components = vec![String::new()];
+    let mut escaped = false;
+    for c in value.chars() {
+        if !escaped && c == ';' {
+            components.push(String::new());
+            continue;
+        }
+        escaped = !escaped && c == '\\';
+        if let Some(component) = components.last_mut() {
+            component.push(c);
+        }
+    }
+    components.iter().map(|c| unescape_text(c)).collect()
+}
+
+/// Appends a content line, folded to 75 octets as recommended by RFC 6350.
+fn push_line(vcard: &mut String, line: &str) {
+    let mut len = 0;
+    for c in line.chars() {
+        if len + c.len_utf8() > 75 {
+            vcard.push_str("\r\n ");
+            len = 1;
+        }
+        vcard.push(c);
+        len += c.len_utf8();
+    }
+    vcard.push_str("\r\n");
+}
+
+/// Creates a vCard 4.0 file containing the given contacts.
+pub(crate) fn make_vcard(contacts: &[VcardContact]) -> String {
+    let mut vcard = String::new();
+    for contact in contacts {
+        push_line(&mut vcard, "BEGIN:VCARD");
+        push_line(&mut vcard, "VERSION:4.0");
+        push_line(
+            &mut vcard,
+            &format!("FN:{}", escape_text(&contact.display_name)),
+        );
+        for addr in &contact.addrs {
+            push_line(&mut vcard, &format!("EMAIL:{}", escape_text(addr)));
+        }
+        if let Some((data, extension)) = &contact.photo {
+            push_line(
+                &mut vcard,
+                &format!(
+                    "PHOTO:data:{};base64,{}",
+                    photo_mime_type(extension),
+                    base64::encode(data)
+                ),
+            );
+        }
+        push_line(&mut vcard, "END:VCARD");
+    }
+    vcard
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_android() {
+        let cards = parse_vcards(include_str!("../test-data/vcard/android.vcf"));
+        assert_eq!(cards.len(), 3);
+        let mut cards = cards.into_iter();
+
+        let alice = cards.next().unwrap().unwrap();
+        assert_eq!(alice.display_name, "Alice Liddell");
+        assert_eq!(
+            alice.addrs,
+            vec!["alice@example.org", "alice.liddell@example.com"]
+        );
+        let (data, extension) = alice.photo.unwrap();
+        assert_eq!(
+            data,
+            include_bytes!("../test-data/image/image100x50.gif").to_vec()
+        );
+        assert_eq!(extension, "gif");
+
+        // quoted-printable with a soft line break
+        let juergen = cards.next().unwrap().unwrap();
+        assert_eq!(juergen.display_name, "Jürgen Müller");
+        assert_eq!(juergen.addrs, vec!["juergen@example.net"]);
+        assert_eq!(juergen.photo, None);
+
+        // no email address
+        assert!(cards.next().unwrap().is_err());
+    }
+
+    #[test]
+    fn test_parse_apple() {
+        let cards = parse_vcards(include_str!("../test-data/vcard/apple.vcf"));
+        assert_eq!(cards.len(), 3);
+        let mut cards = cards.into_iter();
+
+        let rabbit = cards.next().unwrap().unwrap();
+        assert_eq!(rabbit.display_name, "White Rabbit");
+        assert_eq!(rabbit.addrs, vec!["rabbit@example.org"]);
+        let (data, extension) = rabbit.photo.unwrap();
+        assert_eq!(
+            data,
+            include_bytes!("../test-data/image/avatar64x64.png").to_vec()
+        );
+        assert_eq!(extension, "png");
+
+        let hatter = cards.next().unwrap().unwrap();
+        assert_eq!(hatter.display_name, "Mad Hatter, Esq.");
+        assert_eq!(hatter.addrs, vec!["hatter@example.com", "not an address"]);
+
+        // invalid photo
+        assert!(cards.next().unwrap().is_err());
+    }
+
+    #[test]
+    fn test_parse_malformed() {
+        let cards = parse_vcards(
+            "BEGIN:VCARD\n\
+             N:Doe;John\n\
+             EMAIL:john@example.org\n\
+             BEGIN:VCARD\n\
+             EMAIL:jane@example.org\n\
+             END:VCARD\n\
+             garbage\n\
+             BEGIN:VCARD\n\
+             FN:Unfinished\n",
+        );
+        assert_eq!(cards.len(), 3);
+        let mut cards = cards.into_iter();
+        assert!(cards.next().unwrap().is_err());
+        let jane = cards.next().unwrap().unwrap();
+        assert_eq!(jane.display_name, "");
+        assert_eq!(jane.addrs, vec!["jane@example.org"]);
+        assert!(cards.next().unwrap().is_err());
+
+        assert!(parse_vcards("not a vcard").is_empty());
+    }
+
+    #[test]
+    fn test_structured_name() {
+        let card = parse_vcards(
+            "BEGIN:VCARD\nVERSION:3.0\nN:O\\;Brien;Conan;;;\nEMAIL:conan@example.org\nEND:VCARD\n",
+        )
+        .into_iter()
+        .next()
+        .unwrap()
+        .unwrap();
+        assert_eq!(card.display_name, "Conan O;Brien");
+    }
+
+    #[test]
+    fn test_make_vcard_roundtrip() {
+        let contacts = vec![
+            VcardContact {
+                display_name: "Smith, John; \\ Jr.\nSecond line".to_string(),
+                addrs: vec!["john@example.org".to_string()],
+                photo: Some((vec![0xff; 300], "jpg".to_string())),
+            },
+            VcardContact {
+                display_name: "Ünïcödé ".repeat(20).trim().to_string(),
+                addrs: vec!["unicode@example.org".to_string()],
+                photo: None,
+            },
+        ];
+        let vcard = make_vcard(&contacts);
+        assert!(vcard.starts_with("BEGIN:VCARD\r\nVERSION:4.0\r\n"));
+        assert!(vcard.contains("PHOTO:data:image/jpeg;base64,"));
+        assert!(vcard.lines().all(|line| line.len() <= 75));
+
+        let parsed: Vec<VcardContact> = parse_vcards(&vcard)
+            .into_iter()
+            .map(|card| card.unwrap())
+            .collect();
+        assert_eq!(parsed, contacts);
+    }
+}
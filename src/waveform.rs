@@ -0,0 +1,261 @@
+//! # Waveforms of voice messages.
+//!
+//! A waveform is a short list of amplitudes drawn in the bubble of a voice message.
+//! It is computed once by the sender, see [crate::message::generate_waveform], and sent
+//! along with the message in the `Chat-Voice-Waveform` header, so receivers do not need to
+//! decode the audio.
+//!
+//! Opus in an Ogg container, as recorded by the apps, is decoded with libopus,
+//! other formats with symphonia.
+
+use std::convert::TryInto;
+
+use anyhow::{bail, ensure, format_err, Result};
+use audiopus::coder::Decoder as OpusDecoder;
+use audiopus::{Channels, SampleRate};
+use ogg::reading::PacketReader;
+use symphonia::core::audio::SampleBuffer;
+use symphonia::core::codecs::DecoderOptions;
+use symphonia::core::errors::Error as DecodeError;
+use symphonia::core::formats::FormatOptions;
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
+
+/// Number of amplitudes in a waveform.
+pub const WAVEFORM_BUCKETS: usize = 100;
+
+/// Maximum number of amplitudes accepted in received waveforms.
+///
+/// Other implementations may use more amplitudes than [WAVEFORM_BUCKETS],
+/// waveforms that are longer are dropped to keep the database small.
+pub(crate) const MAX_RECEIVED_WAVEFORM_LEN: usize = 1000;
+
+/// Number of frames combined into one peak while decoding.
+///
+/// Keeps the memory used for long recordings small.
+const FRAMES_PER_PEAK: usize = 256;
+
+/// Start of the first packet of an Opus stream in an Ogg container, see RFC 7845.
+const OPUS_HEAD: &[u8] = b"OpusHead";
+
+/// Maximum number of frames of an Opus packet, 120 ms at 48 kHz.
+const OPUS_MAX_FRAMES: usize = 5760;
+
+/// Decodes the audio file `data` and returns its waveform.
+///
+/// `extension` is a hint for the file format, the format is guessed from the content
+/// otherwise.  The waveform has [WAVEFORM_BUCKETS] amplitudes, the loudest one is 255.
+///
+/// Decoding is CPU-bound, call this using `spawn_blocking()`.
+pub(crate) fn compute(data: Vec<u8>, extension: Option<&str>) -> Result<Vec<u8>> {
+    if data.starts_with(b"OggS") {
+        compute_ogg_opus(data)
+    } else {
+        compute_symphonia(data, extension)
+    }
+}
+
+/// Decodes the first Opus stream of the Ogg container `data`.
+fn compute_ogg_opus(data: Vec<u8>) -> Result<Vec<u8>> {
+    let mut reader = PacketReader::new(std::io::Cursor::new(data));
+    let head = reader
+        .read_packet()?
+        .ok_or_else(|| format_err!("empty Ogg container"))?;
+    ensure!(head.data.starts_with(OPUS_HEAD), "no Opus stream");
+    let channels = match head.data.get(9) {
+        Some(1) => Channels::Mono,
+        Some(2) => Channels::Stereo,
+        count => bail!("unsupported number of Opus channels: {:?}", count),
+    };
+    let channel_count = channels as usize;
+    // Frames at the start of the stream that are not part of the recording.
+    let mut pre_skip = match head.data.get(10..12) {
+        Some(&[low, high]) => usize::from(u16::from_le_bytes([low, high])),
+        _ => 0,
+    };
+
+    let serial = head.stream_serial();
+    let mut decoder = OpusDecoder::new(SampleRate::Hz48000, channels)?;
+    let mut output = vec![0f32; OPUS_MAX_FRAMES * channel_count];
+    let mut peaks = Peaks::default();
+    let mut tags_skipped = false;
+    while let Some(packet) = reader.read_packet()? {
+        if packet.stream_serial() != serial {
+            continue;
+        }
+        // The second packet holds the comments.
+        if !tags_skipped {
+            tags_skipped = true;
+            continue;
+        }
+        let input = match packet.data.as_slice().try_into() {
+            Ok(input) => input,
+            Err(_) => continue,
+        };
+        let frames =
+            match decoder.decode_float(Some(input), output.as_mut_slice().try_into()?, false) {
+                Ok(frames) => frames,
+                // A corrupt packet is just skipped.
+                Err(_) => continue,
+            };
+        let skipped = frames.min(pre_skip);
+        pre_skip -= skipped;
+        let samples = output
+            .get(skipped * channel_count..frames * channel_count)
+            .unwrap_or_default();
+        peaks.add(samples, channel_count);
+    }
+    peaks.into_waveform()
+}
+
+/// Decodes `data` in one of the formats supported by symphonia.
+fn compute_symphonia(data: Vec<u8>, extension: Option<&str>) -> Result<Vec<u8>> {
+    let mut hint = Hint::new();
+    if let Some(extension) = extension {
+        hint.with_extension(extension);
+    }
+    let source = MediaSourceStream::new(Box::new(std::io::Cursor::new(data)));
+    let probed = symphonia::default::get_probe().format(
+        &hint,
+        source,
+        &FormatOptions::default(),
+        &MetadataOptions::default(),
+    )?;
+    let mut reader = probed.format;
+    let stream = reader
+        .default_stream()
+        .ok_or_else(|| format_err!("no audio stream"))?;
+    let mut decoder =
+        symphonia::default::get_codecs().make(&stream.codec_params, &DecoderOptions::default())?;
+
+    let mut peaks = Peaks::default();
+    loop {
+        let packet = match reader.next_packet() {
+            Ok(packet) => packet,
+            Err(DecodeError::IoError(err)) if err.kind() == std::io::ErrorKind::UnexpectedEof => {
+                break
+            }
+            Err(err) => return Err(err.into()),
+        };
+        let decoded = match decoder.decode(&packet) {
+            Ok(decoded) => decoded,
+            // A corrupt packet is just skipped.
+            Err(DecodeError::DecodeError(_)) => continue,
+            Err(err) => return Err(err.into()),
+        };
+        let spec = *decoded.spec();
+        let channels = spec.channels.count().max(1);
+        let mut samples = SampleBuffer::<f32>::new(decoded.capacity() as u64, spec);
+        samples.copy_interleaved_ref(decoded);
+        peaks.add(samples.samples(), channels);
+    }
+    peaks.into_waveform()
+}
+
+/// Peaks of every [FRAMES_PER_PEAK] decoded frames.
+#[derive(Debug, Default)]
+struct Peaks {
+    peaks: Vec<f32>,
+    peak: f32,
+    frames: usize,
+}
+
+impl Peaks {
+    /// Adds the interleaved `samples` of `channels` channels.
+    fn add(&mut self, samples: &[f32], channels: usize) {
+        for frame in samples.chunks(channels.max(1)) {
+            for sample in frame {
+                self.peak = self.peak.max(sample.abs());
+            }
+            self.frames += 1;
+            if self.frames == FRAMES_PER_PEAK {
+                self.peaks.push(self.peak);
+                self.peak = 0.0;
+                self.frames = 0;
+            }
+        }
+    }
+
+    fn into_waveform(mut self) -> Result<Vec<u8>> {
+        if self.frames > 0 {
+            self.peaks.push(self.peak);
+        }
+        if self.peaks.is_empty() {
+            bail!("no audio samples");
+        }
+        Ok(to_buckets(&self.peaks))
+    }
+}
+
+/// Averages `peaks` into [WAVEFORM_BUCKETS] amplitudes, normalized to the loudest one.
+fn to_buckets(peaks: &[f32]) -> Vec<u8> {
+    let buckets: Vec<f32> = (0..WAVEFORM_BUCKETS)
+        .map(|i| {
+            let start = i * peaks.len() / WAVEFORM_BUCKETS;
+            let end = ((i + 1) * peaks.len() / WAVEFORM_BUCKETS).max(start + 1);
+            let range = peaks.get(start..end).unwrap_or_default();
+            range.iter().sum::<f32>() / range.len().max(1) as f32
+        })
+        .collect();
+    let max = buckets.iter().cloned().fold(0f32, f32::max);
+    buckets
+        .iter()
+        .map(|amplitude| {
+            if max > 0.0 {
+                (amplitude / max * 255.0).round() as u8
+            } else {
+                0
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_buckets() {
+        let buckets = to_buckets(&[0.5]);
+        assert_eq!(buckets, vec![255; WAVEFORM_BUCKETS]);
+
+        let buckets = to_buckets(&[0.0; 3]);
+        assert_eq!(buckets, vec![0; WAVEFORM_BUCKETS]);
+
+        let peaks: Vec<f32> = (0..1000).map(|i| i as f32).collect();
+        let buckets = to_buckets(&peaks);
+        assert_eq!(buckets.len(), WAVEFORM_BUCKETS);
+        assert_eq!(buckets.last(), Some(&255));
+        assert!(buckets.windows(2).all(|w| w.first() <= w.last()));
+    }
+
+    #[test]
+    fn test_compute_invalid() {
+        assert!(compute(b"not audio".to_vec(), Some("opus")).is_err());
+        assert!(compute(b"OggS not audio".to_vec(), Some("opus")).is_err());
+    }
+
+    #[test]
+    fn test_compute_wav() -> Result<()> {
+        // The fixture gets louder over time.
+        let data = include_bytes!("../test-data/message/voice.wav");
+        let waveform = compute(data.to_vec(), Some("wav"))?;
+        assert_eq!(waveform.len(), WAVEFORM_BUCKETS);
+        assert!(waveform.first() < waveform.last());
+        Ok(())
+    }
+
+    #[test]
+    fn test_compute_opus() -> Result<()> {
+        // One second of a loud tone followed by one second of a quiet tone.
+        let data = include_bytes!("../test-data/message/voice.opus");
+        let waveform = compute(data.to_vec(), Some("opus"))?;
+        assert_eq!(waveform.len(), WAVEFORM_BUCKETS);
+        assert_eq!(waveform.iter().max(), Some(&255));
+        let (loud, quiet) = waveform.split_at(WAVEFORM_BUCKETS / 2);
+        assert!(loud.iter().take(45).all(|amplitude| *amplitude > 200));
+        assert!(quiet.iter().skip(5).all(|amplitude| *amplitude < 60));
+        Ok(())
+    }
+}